@@ -0,0 +1,101 @@
+//! Derive macros for `rhizome-trees`, re-exported by it with the `derive`
+//! feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, Index};
+
+/// Implements `Hashable` by writing every field in declaration order, each
+/// prefixed with the length of its encoding as a big-endian `u64` so that
+/// adjacent fields can't be confused. Enums first write the index of the
+/// variant as a big-endian `u32`. Every type parameter must be `Hashable`.
+#[proc_macro_derive(Hashable)]
+pub fn derive_hashable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match hashable(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn hashable(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    for param in input.generics.type_params_mut() {
+        param
+            .bounds
+            .push(parse_quote!(::rhizome_trees::tree::hash::Hashable));
+    }
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, fields) = destructure(&data.fields);
+            quote! {
+                let #name #pattern = self;
+                #(#fields)*
+            }
+        }
+        Data::Enum(data) => {
+            let arms = data.variants.iter().enumerate().map(|(index, variant)| {
+                let variant_name = &variant.ident;
+                let (pattern, fields) = destructure(&variant.fields);
+                let index = index as u32;
+                quote! {
+                    #name::#variant_name #pattern => {
+                        hasher.update(&#index.to_be_bytes());
+                        #(#fields)*
+                    }
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                Span::call_site(),
+                "Hashable can't be derived for unions",
+            ))
+        }
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rhizome_trees::tree::hash::Hashable for #name #ty_generics
+        #where_clause
+        {
+            #[allow(unused_variables)]
+            fn update_hash<H: ::rhizome_trees::tree::hash::Update>(&self, hasher: &mut H) {
+                #body
+            }
+        }
+    })
+}
+
+/// Returns a pattern binding the fields of a struct or variant, and the
+/// statements hashing each binding.
+fn destructure(fields: &Fields) -> (TokenStream2, Vec<TokenStream2>) {
+    let bindings: Vec<_> = (0..fields.len())
+        .map(|i| format_ident!("field_{}", i))
+        .collect();
+    let pattern = match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|field| &field.ident);
+            quote!({ #(#names: #bindings),* })
+        }
+        Fields::Unnamed(_) => {
+            let indices = (0..fields.len()).map(Index::from);
+            quote!({ #(#indices: #bindings),* })
+        }
+        Fields::Unit => quote!(),
+    };
+    let statements = bindings
+        .iter()
+        .map(|binding| {
+            quote! {
+                ::rhizome_trees::tree::hash::update_length_prefixed(hasher, #binding);
+            }
+        })
+        .collect();
+    (pattern, statements)
+}
@@ -0,0 +1,59 @@
+//! Measures AVL proof verification and checks that it doesn't allocate.
+//!
+//! Run with `cargo bench --bench proof_verify`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ENTRIES: u32 = 100_000;
+const ROUNDS: u32 = 100_000;
+
+fn main() -> rhizome_trees::Result<()> {
+    let key = |i: u32| i.to_be_bytes().to_vec();
+    let tree = Tree::from_sorted_iter((0..ENTRIES).map(|i| (key(i), key(i))))?;
+    let root = tree.merkle_hash()?;
+    let proven = key(ENTRIES / 3);
+    let proof = tree.prove(&proven)?.expect("key is present");
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let proof = black_box(&proof).as_ref();
+        assert!(proof.verify(black_box(&root), &proven[..], &proven[..]));
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    println!(
+        "verified a proof of {} steps {} times: {:?} per verification, {} allocations",
+        proof.path.len(),
+        ROUNDS,
+        elapsed / ROUNDS,
+        allocations
+    );
+    assert_eq!(allocations, 0, "proof verification allocated");
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! Walks through the credit registry of the `demo` feature: issuing,
+//! transferring and retiring credits over a few versions, proving a balance
+//! against a published root hash, and listing what each version changed.
+//!
+//! Run with `cargo run --example credit_registry --features demo`.
+
+use std::sync::Arc;
+
+use rhizome_trees::demo::{CreditRegistry, RETIREMENTS};
+use rhizome_trees::tree::avl::diff::Change;
+use rhizome_trees::tree::avl::versioned::{VersionEvent, VersionObserver};
+
+/// Prints the root hash of every committed version, as an operator would
+/// publish it.
+struct Publisher;
+
+impl VersionObserver for Publisher {
+    fn on_version_event(&self, event: &VersionEvent) {
+        if let VersionEvent::Committed(info) = event {
+            println!("published version {}: {}", info.version, hex(&info.hash));
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn main() -> rhizome_trees::Result<()> {
+    let mut registry = CreditRegistry::default();
+    registry.set_observer(Arc::new(Publisher));
+
+    registry.open_account("forest-trust")?;
+    registry.open_account("buyer")?;
+    registry.issue("forest-trust", "C01-2024", 1_000)?;
+    let first = registry.commit()?;
+
+    registry.transfer("forest-trust", "buyer", "C01-2024", 250)?;
+    let retirement = registry.retire("buyer", "C01-2024", 100)?;
+    let second = registry.commit()?;
+
+    // A light client holding only the published root hash checks a balance.
+    let proof = registry
+        .prove_balance("buyer", "C01-2024")?
+        .expect("buyer holds credits");
+    assert_eq!(proof.balance, 150);
+    assert!(proof.verify(&second.hash));
+    assert!(!proof.verify(&first.hash));
+    println!(
+        "proved a balance of {} at version {}",
+        proof.balance, proof.version
+    );
+
+    // A follower replays the changes of each version.
+    for info in [first, second] {
+        for change in registry.changes(info.version)? {
+            println!(
+                "version {}: {} {:?}",
+                info.version,
+                String::from_utf8_lossy(change.tree),
+                change.change
+            );
+        }
+    }
+    let retired = registry.changes(second.version)?.into_iter().any(|change| {
+        change.tree == RETIREMENTS
+            && matches!(&change.change, Change::Create { key, .. } if *key == retirement.to_be_bytes())
+    });
+    assert!(retired);
+
+    assert!(registry.retire("buyer", "C01-2024", 1_000).is_err());
+    Ok(())
+}
@@ -0,0 +1,317 @@
+//! Logical clocks, which order the operations of replicas without trusting
+//! their wall clocks.
+//!
+//! A [`VectorClock`] counts the events of each replica, so comparing two
+//! clocks tells whether one happened before the other or they are
+//! concurrent, at the cost of a counter per replica. An [`Hlc`] (hybrid
+//! logical clock) issues [`HlcTimestamp`]s which stay close to wall clock
+//! time but never go backwards and always exceed the timestamps it
+//! received, so they order events consistently with causality in a fixed
+//! 20 bytes. Timestamps are keys, e.g. for a last-writer-wins value to record
+//! when it was written.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::tree::hash::{Hashable, Update};
+use crate::tree::value::{KeyCodec, ValueCodec};
+use crate::{Error, Result};
+
+/// Counts the events of each replica it has seen. Replicas with no events
+/// aren't stored, so equal clocks are equal as values.
+#[derive(Clone, PartialEq, Eq, Hash, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VectorClock {
+    counters: BTreeMap<u64, u64>,
+}
+
+impl VectorClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of events of `replica` the clock has seen.
+    pub fn get(&self, replica: u64) -> u64 {
+        self.counters.get(&replica).copied().unwrap_or(0)
+    }
+
+    /// Records an event of `replica`, returning its number of events.
+    pub fn increment(&mut self, replica: u64) -> Result<u64> {
+        let counter = self.counters.entry(replica).or_insert(0);
+        let Some(next) = counter.checked_add(1) else {
+            return Err(Error::invalid(format!(
+                "replica {} ran out of events",
+                replica
+            )));
+        };
+        *counter = next;
+        Ok(next)
+    }
+
+    /// Records every event `other` has seen.
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (&replica, &counter) in &other.counters {
+            let local = self.counters.entry(replica).or_insert(0);
+            *local = counter.max(*local);
+        }
+    }
+
+    /// Returns `Less` if every event this clock has seen `other` has seen
+    /// too, `Greater` for the reverse, and `None` if each has seen events
+    /// the other hasn't, i.e. they are concurrent.
+    pub fn compare(&self, other: &VectorClock) -> Option<Ordering> {
+        let replicas = self.counters.keys().chain(other.counters.keys());
+        let mut order = Ordering::Equal;
+        for &replica in replicas {
+            match (order, self.get(replica).cmp(&other.get(replica))) {
+                (_, Ordering::Equal) => {}
+                (Ordering::Equal, replica_order) => order = replica_order,
+                (order, replica_order) if order != replica_order => return None,
+                _ => {}
+            }
+        }
+        Some(order)
+    }
+
+    pub fn happened_before(&self, other: &VectorClock) -> bool {
+        self.compare(other) == Some(Ordering::Less)
+    }
+
+    pub fn is_concurrent(&self, other: &VectorClock) -> bool {
+        self.compare(other).is_none()
+    }
+
+    /// Iterates over the replicas with events and their number of events,
+    /// in replica order.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.counters
+            .iter()
+            .map(|(&replica, &counter)| (replica, counter))
+    }
+}
+
+impl PartialOrd for VectorClock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.compare(other)
+    }
+}
+
+/// Hashes each replica and its number of events as big-endian `u64`s.
+impl Hashable for VectorClock {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        for (replica, counter) in self.iter() {
+            hasher.update(&replica.to_be_bytes());
+            hasher.update(&counter.to_be_bytes());
+        }
+    }
+}
+
+impl ValueCodec for VectorClock {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        for (replica, counter) in self.iter() {
+            buf.extend_from_slice(&replica.to_be_bytes());
+            buf.extend_from_slice(&counter.to_be_bytes());
+        }
+    }
+
+    /// Fails unless the replicas are in increasing order with nonzero
+    /// counters, as [`ValueCodec::encode`] writes them.
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if !bytes.len().is_multiple_of(16) {
+            return Err(Error::codec(format!(
+                "vector clock of {} bytes",
+                bytes.len()
+            )));
+        }
+        let mut counters = BTreeMap::new();
+        let mut previous = None;
+        for entry in bytes.chunks_exact(16) {
+            let replica = u64::from_be_bytes(entry[..8].try_into().expect("8 bytes"));
+            let counter = u64::from_be_bytes(entry[8..].try_into().expect("8 bytes"));
+            if counter == 0 || previous.is_some_and(|previous| previous >= replica) {
+                return Err(Error::codec("non-canonical vector clock"));
+            }
+            previous = Some(replica);
+            counters.insert(replica, counter);
+        }
+        Ok(VectorClock { counters })
+    }
+}
+
+/// A timestamp issued by an [`Hlc`]: the wall clock time in milliseconds it
+/// was issued at or after, a counter of the events issued within that
+/// millisecond, and the replica which issued it, ordered in that order.
+///
+/// Encoded as 20 bytes: the three fields as big-endian `u64`, `u32` and
+/// `u64`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HlcTimestamp {
+    pub millis: u64,
+    pub logical: u32,
+    pub replica: u64,
+}
+
+impl HlcTimestamp {
+    pub fn to_bytes(&self) -> [u8; 20] {
+        let mut bytes = [0u8; 20];
+        bytes[..8].copy_from_slice(&self.millis.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.logical.to_be_bytes());
+        bytes[12..].copy_from_slice(&self.replica.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: [u8; 20]) -> Self {
+        HlcTimestamp {
+            millis: u64::from_be_bytes(bytes[..8].try_into().expect("8 bytes")),
+            logical: u32::from_be_bytes(bytes[8..12].try_into().expect("4 bytes")),
+            replica: u64::from_be_bytes(bytes[12..].try_into().expect("8 bytes")),
+        }
+    }
+}
+
+impl Hashable for HlcTimestamp {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(&self.to_bytes());
+    }
+}
+
+impl ValueCodec for HlcTimestamp {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        match bytes.try_into() {
+            Ok(bytes) => Ok(HlcTimestamp::from_bytes(bytes)),
+            Err(_) => Err(Error::codec(format!(
+                "an HLC timestamp takes 20 bytes, not {}",
+                bytes.len()
+            ))),
+        }
+    }
+}
+
+impl KeyCodec for HlcTimestamp {
+    const FIXED_WIDTH: Option<usize> = Some(20);
+}
+
+/// A hybrid logical clock of one replica.
+#[derive(Clone, Debug)]
+pub struct Hlc {
+    replica: u64,
+    last: HlcTimestamp,
+    max_drift: Option<u64>,
+}
+
+impl Hlc {
+    /// Creates a clock for the replica `replica`, which must be unique among
+    /// the replicas whose timestamps are compared.
+    pub fn new(replica: u64) -> Self {
+        Hlc {
+            replica,
+            last: HlcTimestamp {
+                replica,
+                ..HlcTimestamp::default()
+            },
+            max_drift: None,
+        }
+    }
+
+    /// Makes [`Hlc::update`] reject timestamps more than `millis` ahead of
+    /// the wall clock, so that a replica with a clock far in the future
+    /// can't drag every other clock along. Unlimited by default.
+    pub fn max_drift(self, millis: u64) -> Self {
+        Hlc {
+            max_drift: Some(millis),
+            ..self
+        }
+    }
+
+    /// Resumes from the last timestamp the replica issued, e.g. after a
+    /// restart, so that it never issues it again.
+    pub fn resume(self, last: HlcTimestamp) -> Self {
+        Hlc {
+            last: HlcTimestamp {
+                replica: self.replica,
+                ..last
+            },
+            ..self
+        }
+    }
+
+    /// The last timestamp the clock issued.
+    pub fn last(&self) -> HlcTimestamp {
+        self.last
+    }
+
+    /// Issues a timestamp for a local event, e.g. a write.
+    pub fn now(&mut self) -> Result<HlcTimestamp> {
+        self.now_at(wall_millis())
+    }
+
+    /// Like [`Hlc::now`] with the wall clock reading `wall` milliseconds.
+    pub fn now_at(&mut self, wall: u64) -> Result<HlcTimestamp> {
+        self.advance(wall, None)
+    }
+
+    /// Issues a timestamp for the receipt of an event stamped `remote` by
+    /// another replica, which exceeds both `remote` and every timestamp the
+    /// clock issued.
+    pub fn update(&mut self, remote: HlcTimestamp) -> Result<HlcTimestamp> {
+        self.update_at(remote, wall_millis())
+    }
+
+    /// Like [`Hlc::update`] with the wall clock reading `wall` milliseconds.
+    pub fn update_at(&mut self, remote: HlcTimestamp, wall: u64) -> Result<HlcTimestamp> {
+        if let Some(max_drift) = self.max_drift {
+            if remote.millis > wall.saturating_add(max_drift) {
+                return Err(Error::invalid(format!(
+                    "timestamp of replica {} is {} ms ahead of the wall clock",
+                    remote.replica,
+                    remote.millis - wall
+                )));
+            }
+        }
+        self.advance(wall, Some(remote))
+    }
+
+    fn advance(&mut self, wall: u64, remote: Option<HlcTimestamp>) -> Result<HlcTimestamp> {
+        let last = self.last;
+        let remote_millis = remote.map_or(0, |remote| remote.millis);
+        let millis = wall.max(last.millis).max(remote_millis);
+        // The largest counter already issued within `millis`, if any.
+        let issued = [Some(last), remote]
+            .into_iter()
+            .flatten()
+            .filter(|timestamp| timestamp.millis == millis)
+            .map(|timestamp| timestamp.logical)
+            .max();
+        let logical = match issued {
+            None => 0,
+            Some(logical) => match logical.checked_add(1) {
+                Some(logical) => logical,
+                None => {
+                    return Err(Error::invalid(format!(
+                        "logical counter of {} ms overflowed",
+                        millis
+                    )))
+                }
+            },
+        };
+        self.last = HlcTimestamp {
+            millis,
+            logical,
+            replica: self.replica,
+        };
+        Ok(self.last)
+    }
+}
+
+/// The milliseconds since the unix epoch, 0 for earlier times.
+fn wall_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
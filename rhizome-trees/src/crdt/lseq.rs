@@ -0,0 +1,400 @@
+//! A list which replicas edit concurrently, in the style of Logoot and
+//! LSEQ.
+//!
+//! Every element gets a [`Position`] when it is inserted, a sequence of
+//! atoms which sorts between the positions of its neighbours and never
+//! changes, so positions are the stable IDs of elements and a tree keyed by
+//! position holds the list in order. Finding the element at an index is an
+//! O(log n) [`Tree::nth`], and an insert allocates a position between the
+//! elements at the neighbouring indexes. The last atom of a position holds
+//! the [`Tag`] of the insert, so replicas never allocate the same position,
+//! and elements inserted concurrently at the same index are ordered the
+//! same way on every replica. As with Logoot, runs of elements inserted
+//! concurrently at the same index may interleave.
+//!
+//! Positions get longer when there is no room left between two neighbours.
+//! Like LSEQ, an insert after the last element allocates close to it, and
+//! an insert before the first element close to that, so appending or
+//! prepending runs of elements adds an atom only every 2^16 or so inserts.
+//!
+//! Deleted elements move to a second tree, where they stay as tombstones so
+//! that merges don't bring them back. Merging is commutative, associative
+//! and idempotent, as for an [`ORSet`](super::ORSet), and replicas can find
+//! the elements and tombstones they disagree on with a
+//! [`sync`](super::sync) of the two trees.
+
+use std::sync::Arc;
+
+use crate::tree::avl::node::Manager;
+use crate::tree::avl::Tree;
+use crate::tree::hash::{Hashable, Update};
+use crate::tree::node_manager::Ptr;
+use crate::tree::value::{KeyCodec, ValueCodec};
+
+use super::Tag;
+use crate::{Error, Result};
+
+/// The digits past the neighbouring one within which an insert at either
+/// end of the list allocates, leaving room for the inserts which follow.
+const BOUNDARY: u64 = 1 << 16;
+
+/// The exclusive upper bound of digits.
+const BASE: u64 = 1 << 32;
+
+/// One level of a [`Position`]: a digit, and the tag of the insert which
+/// allocated it, which orders atoms with the same digit.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Atom {
+    pub digit: u32,
+    pub replica: u64,
+    pub counter: u64,
+}
+
+/// The position of a list element, see the [module documentation](self).
+/// Positions are ordered atom by atom, a position before the positions it
+/// is a prefix of.
+///
+/// Encoded as its atoms, each as three big-endian integers of 4, 8 and 8
+/// bytes, which orders encodings as positions.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Position {
+    atoms: Vec<Atom>,
+}
+
+impl Position {
+    pub fn atoms(&self) -> &[Atom] {
+        &self.atoms
+    }
+
+    /// The tag of the insert which allocated the position.
+    pub fn tag(&self) -> Tag {
+        let last = self.atoms.last().expect("positions aren't empty");
+        Tag {
+            replica: last.replica,
+            counter: last.counter,
+        }
+    }
+
+    /// Allocates a position between `left` and `right` for the insert
+    /// `tag`, `None` standing for the start and end of the list. `left`
+    /// must be before `right`.
+    fn between(left: Option<&Position>, right: Option<&Position>, tag: Tag) -> Position {
+        let atom = |digit| Atom {
+            digit,
+            replica: tag.replica,
+            counter: tag.counter,
+        };
+        let (left, right) = (
+            left.map_or(&[][..], |left| &left.atoms),
+            right.map_or(&[][..], |right| &right.atoms),
+        );
+        // Whether the atoms so far equal the prefix of `left` or `right`,
+        // so the atoms to come must keep the position after or before it.
+        let (mut after_left, mut before_right) = (true, !right.is_empty());
+        let mut atoms = Vec::new();
+        for level in 0.. {
+            let low = left.get(level).filter(|_| after_left);
+            let high = right.get(level).filter(|_| before_right);
+            // Allocated digits are at least 1, which leaves 0 to descend
+            // below an atom of digit 1.
+            let start = low.map_or(1, |low| low.digit as u64 + 1);
+            let end = high.map_or(BASE, |high| high.digit as u64);
+            if start < end {
+                let room = end - start;
+                let digit = match (low, high) {
+                    (Some(_), None) => start + room.min(BOUNDARY) / 2,
+                    (None, Some(_)) => end - 1 - room.min(BOUNDARY) / 2,
+                    _ => start + room / 2,
+                };
+                atoms.push(atom(digit as u32));
+                break;
+            }
+            // No room at this level: copy an atom which keeps the position
+            // within its bounds, and allocate at the next one.
+            let next = match (low, high) {
+                (Some(low), _) => *low,
+                (None, Some(high)) if high.digit == 0 => *high,
+                _ => atom(0),
+            };
+            after_left = low == Some(&next);
+            before_right = high == Some(&next);
+            atoms.push(next);
+        }
+        Position { atoms }
+    }
+}
+
+/// Hashes the number of atoms, then the encoding.
+impl Hashable for Position {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(&(self.atoms.len() as u64).to_be_bytes());
+        hasher.update(&self.to_encoded());
+    }
+}
+
+impl ValueCodec for Position {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        for atom in &self.atoms {
+            buf.extend_from_slice(&atom.digit.to_be_bytes());
+            buf.extend_from_slice(&atom.replica.to_be_bytes());
+            buf.extend_from_slice(&atom.counter.to_be_bytes());
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.is_empty() || !bytes.len().is_multiple_of(20) {
+            return Err(Error::codec(format!("position of {} bytes", bytes.len())));
+        }
+        let atoms = bytes
+            .chunks_exact(20)
+            .map(|atom| Atom {
+                digit: u32::from_be_bytes(atom[..4].try_into().expect("4 bytes")),
+                replica: u64::from_be_bytes(atom[4..12].try_into().expect("8 bytes")),
+                counter: u64::from_be_bytes(atom[12..].try_into().expect("8 bytes")),
+            })
+            .collect();
+        Ok(Position { atoms })
+    }
+}
+
+impl KeyCodec for Position {}
+
+/// The node manager of an [`LSeq`].
+pub type LSeqManager<T> = Manager<Position, T>;
+
+/// A saved state of an [`LSeq`], returned by [`LSeq::save`]. Like a saved
+/// tree version, it holds a reference on the roots of both trees until it
+/// is released with [`LSeq::release`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SavedLSeq {
+    pub replica: u64,
+    /// The counter of the next tag the replica creates.
+    pub next_counter: u64,
+    /// The root of the elements, `None` if there are none.
+    pub elements: Option<Ptr>,
+    /// The root of the tombstones, `None` if there are none.
+    pub removed: Option<Ptr>,
+}
+
+/// A replicated list, see the [module documentation](self).
+pub struct LSeq<T> {
+    replica: u64,
+    next_counter: u64,
+    /// The elements by position.
+    elements: Tree<Position, T>,
+    /// The deleted elements by position, which keep their value.
+    removed: Tree<Position, T>,
+}
+
+impl<T> LSeq<T> {
+    /// Creates an empty list for the replica `replica`, which must be unique
+    /// among the replicas whose states are ever merged.
+    pub fn new(manager: Arc<LSeqManager<T>>, replica: u64) -> Self {
+        LSeq {
+            replica,
+            next_counter: 0,
+            elements: Tree::with_manager(manager.clone()),
+            removed: Tree::with_manager(manager),
+        }
+    }
+
+    /// Opens a state saved with [`LSeq::save`] through the manager which
+    /// saved it.
+    pub fn load(manager: Arc<LSeqManager<T>>, saved: &SavedLSeq) -> Self {
+        let tree = |root: Option<Ptr>| match root {
+            Some(root) => Tree::load(manager.clone(), root),
+            None => Tree::with_manager(manager.clone()),
+        };
+        LSeq {
+            replica: saved.replica,
+            next_counter: saved.next_counter,
+            elements: tree(saved.elements),
+            removed: tree(saved.removed),
+        }
+    }
+
+    pub fn replica(&self) -> u64 {
+        self.replica
+    }
+
+    /// The tree of the elements by position.
+    pub fn elements(&self) -> &Tree<Position, T> {
+        &self.elements
+    }
+
+    /// The tree of the deleted elements by position.
+    pub fn removed(&self) -> &Tree<Position, T> {
+        &self.removed
+    }
+
+    pub fn len(&self) -> Result<u64> {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// Releases the references a saved state holds, see
+    /// [`Tree::free_version`]. Returns the number of deleted nodes.
+    pub fn release(manager: &LSeqManager<T>, saved: &SavedLSeq) -> Result<usize>
+    where
+        T: Clone,
+    {
+        let mut deleted = 0;
+        for root in [saved.elements, saved.removed].into_iter().flatten() {
+            deleted += manager.release(&root)?;
+        }
+        Ok(deleted)
+    }
+}
+
+impl<T: Clone> LSeq<T> {
+    /// Returns the element at `index`, `None` if the list is shorter.
+    pub fn get(&self, index: u64) -> Result<Option<T>> {
+        Ok(self.elements.nth(index)?.map(|node| node.value().clone()))
+    }
+
+    /// Returns the position of the element at `index`, `None` if the list
+    /// is shorter.
+    pub fn position(&self, index: u64) -> Result<Option<Position>> {
+        Ok(self.elements.nth(index)?.map(|node| node.key().clone()))
+    }
+
+    /// Returns the index of the element at `position`, `None` if there is
+    /// none, e.g. because it was deleted.
+    pub fn index_of(&self, position: &Position) -> Result<Option<u64>> {
+        if !self.elements.contains_key(position)? {
+            return Ok(None);
+        }
+        Ok(Some(self.elements.rank(position)?))
+    }
+
+    /// Returns the elements in order.
+    pub fn values(&self) -> Result<Vec<T>> {
+        self.elements
+            .iter()?
+            .map(|node| Ok(node?.value().clone()))
+            .collect()
+    }
+
+    /// Returns the elements in order, along with their positions.
+    pub fn entries(&self) -> Result<Vec<(Position, T)>> {
+        self.elements
+            .iter()?
+            .map(|node| {
+                let node = node?;
+                Ok((node.key().clone(), node.value().clone()))
+            })
+            .collect()
+    }
+}
+
+impl<T: Clone + Hashable> LSeq<T> {
+    /// Inserts `value` at `index`, before the element there if any, and
+    /// returns its position. Fails if `index` is past the end of the list.
+    pub fn insert(&mut self, index: u64, value: T) -> Result<Position> {
+        let len = self.len()?;
+        if index > len {
+            return Err(Error::invalid(format!(
+                "index {} is past the end of a list of {}",
+                index, len
+            )));
+        }
+        let Some(next_counter) = self.next_counter.checked_add(1) else {
+            return Err(Error::invalid(format!(
+                "replica {} ran out of tags",
+                self.replica
+            )));
+        };
+        let left = match index {
+            0 => None,
+            index => self.position(index - 1)?,
+        };
+        let right = self.position(index)?;
+        let tag = Tag {
+            replica: self.replica,
+            counter: self.next_counter,
+        };
+        let position = Position::between(left.as_ref(), right.as_ref(), tag);
+        self.elements = self.elements.insert(position.clone(), value)?;
+        self.next_counter = next_counter;
+        Ok(position)
+    }
+
+    /// Appends `value` to the list and returns its position.
+    pub fn push(&mut self, value: T) -> Result<Position> {
+        self.insert(self.len()?, value)
+    }
+
+    /// Deletes the element at `index`, returning it, or `None` if the list
+    /// is shorter.
+    pub fn delete(&mut self, index: u64) -> Result<Option<T>> {
+        let Some(node) = self.elements.nth(index)? else {
+            return Ok(None);
+        };
+        let (position, value) = (node.key().clone(), node.value().clone());
+        self.remove(&position)?;
+        Ok(Some(value))
+    }
+
+    /// Deletes the element at `position`. Returns whether it was present.
+    pub fn remove(&mut self, position: &Position) -> Result<bool> {
+        let Some(value) = self.elements.get(position)? else {
+            return Ok(false);
+        };
+        self.elements = self.elements.delete(position)?;
+        self.removed = self.removed.insert(position.clone(), value)?;
+        Ok(true)
+    }
+
+    /// Merges the state of another replica into this one: the list then
+    /// holds the elements either held which neither deleted.
+    pub fn merge(&mut self, other: &LSeq<T>) -> Result<()> {
+        let entries = |tree: &Tree<Position, T>| -> Result<Vec<_>> {
+            tree.iter()?
+                .map(|node| {
+                    let node = node?;
+                    Ok((node.key().clone(), node.value().clone()))
+                })
+                .collect()
+        };
+        self.merge_entries(entries(&other.removed)?, entries(&other.elements)?)
+    }
+
+    /// Merges entries of another replica, e.g. the deltas a
+    /// [`sync`](super::sync) of the [`LSeq::removed`] and
+    /// [`LSeq::elements`] trees found: the `removed` elements are deleted,
+    /// and the `added` ones are inserted unless they were ever deleted.
+    pub fn merge_entries(
+        &mut self,
+        removed: impl IntoIterator<Item = (Position, T)>,
+        added: impl IntoIterator<Item = (Position, T)>,
+    ) -> Result<()> {
+        for (position, value) in removed {
+            self.elements = self.elements.delete(&position)?;
+            self.removed = self.removed.insert(position, value)?;
+        }
+        for (position, value) in added {
+            if !self.removed.contains_key(&position)? {
+                self.elements = self.elements.insert(position, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves both trees, see [`Tree::save`], and returns the state to
+    /// [`LSeq::load`] it from.
+    pub fn save(&mut self) -> Result<SavedLSeq> {
+        self.elements = self.elements.save()?;
+        self.removed = self.removed.save()?;
+        Ok(SavedLSeq {
+            replica: self.replica,
+            next_counter: self.next_counter,
+            elements: self.elements.root_ptr(),
+            removed: self.removed.root_ptr(),
+        })
+    }
+}
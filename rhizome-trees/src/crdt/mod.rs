@@ -0,0 +1,270 @@
+//! Conflict-free replicated data types kept in AVL trees.
+//!
+//! An [`ORSet`] is an observed-remove set: every add creates a unique
+//! [`Tag`] for the element, and a remove deletes the tags of the element the
+//! replica has observed. An element is present while any of its tags is,
+//! so when one replica adds an element concurrently with another removing
+//! it, the add wins once the two are merged. Merging is commutative,
+//! associative and idempotent, so replicas which have merged the same
+//! states hold the same elements whatever the order.
+//!
+//! The set keeps the tags it holds and the tags it removed in two trees of
+//! a shared node manager, so it is saved and loaded like any other tree, and
+//! replicas can find the tags they disagree on with [`sync`] rather than
+//! sending their whole state. The [`clock`] module provides vector clocks
+//! and hybrid logical clocks for types which order updates by time.
+//!
+//! An [`MVReg`] is a register which keeps the values written concurrently,
+//! and an [`LSeq`] is a list which keeps its elements in a tree ordered by
+//! their positions.
+
+pub mod clock;
+pub mod lseq;
+pub mod mvreg;
+pub mod sync;
+
+pub use lseq::LSeq;
+pub use mvreg::MVReg;
+
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::tree::avl::node::Manager;
+use crate::tree::avl::Tree;
+use crate::tree::hash::{update_length_prefixed, Hashable, Update};
+use crate::tree::node_manager::Ptr;
+use crate::{Error, Result};
+
+/// Identifies one add of an element: the replica which made it and the
+/// number of tags the replica created before.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tag {
+    pub replica: u64,
+    pub counter: u64,
+}
+
+impl Tag {
+    const MIN: Tag = Tag {
+        replica: 0,
+        counter: 0,
+    };
+
+    const MAX: Tag = Tag {
+        replica: u64::MAX,
+        counter: u64::MAX,
+    };
+}
+
+/// The key of an [`ORSet`]'s trees, ordered by element so that the tags of
+/// an element are adjacent.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct TaggedElement<T> {
+    pub element: T,
+    pub tag: Tag,
+}
+
+impl<T: Hashable> Hashable for TaggedElement<T> {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        update_length_prefixed(hasher, &self.element);
+        hasher.update(&self.tag.replica.to_be_bytes());
+        hasher.update(&self.tag.counter.to_be_bytes());
+    }
+}
+
+/// The node manager of an [`ORSet`].
+pub type ORSetManager<T> = Manager<TaggedElement<T>, ()>;
+
+/// A saved state of an [`ORSet`], returned by [`ORSet::save`]. Like a saved
+/// tree version, it holds a reference on the roots of both trees until it
+/// is released with [`ORSet::release`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SavedORSet {
+    pub replica: u64,
+    /// The counter of the next tag the replica creates.
+    pub next_counter: u64,
+    /// The root of the tags held, `None` if there are none.
+    pub added: Option<Ptr>,
+    /// The root of the tags removed, `None` if there are none.
+    pub removed: Option<Ptr>,
+}
+
+/// An observed-remove set, see the [module documentation](self).
+pub struct ORSet<T> {
+    replica: u64,
+    next_counter: u64,
+    /// The tags which were added and not removed.
+    added: Tree<TaggedElement<T>, ()>,
+    /// The tags which were removed, so that merges don't bring them back.
+    removed: Tree<TaggedElement<T>, ()>,
+}
+
+impl<T> ORSet<T> {
+    /// Creates an empty set for the replica `replica`, which must be unique
+    /// among the replicas whose states are ever merged.
+    pub fn new(manager: Arc<ORSetManager<T>>, replica: u64) -> Self {
+        ORSet {
+            replica,
+            next_counter: 0,
+            added: Tree::with_manager(manager.clone()),
+            removed: Tree::with_manager(manager),
+        }
+    }
+
+    /// Opens a state saved with [`ORSet::save`] through the manager which
+    /// saved it.
+    pub fn load(manager: Arc<ORSetManager<T>>, saved: &SavedORSet) -> Self {
+        let tree = |root: Option<Ptr>| match root {
+            Some(root) => Tree::load(manager.clone(), root),
+            None => Tree::with_manager(manager.clone()),
+        };
+        ORSet {
+            replica: saved.replica,
+            next_counter: saved.next_counter,
+            added: tree(saved.added),
+            removed: tree(saved.removed),
+        }
+    }
+
+    pub fn replica(&self) -> u64 {
+        self.replica
+    }
+
+    /// The tree of the tags the set holds.
+    pub fn added(&self) -> &Tree<TaggedElement<T>, ()> {
+        &self.added
+    }
+
+    /// The tree of the tags the set removed.
+    pub fn removed(&self) -> &Tree<TaggedElement<T>, ()> {
+        &self.removed
+    }
+
+    /// Releases the references a saved state holds, see
+    /// [`Tree::free_version`]. Returns the number of deleted nodes.
+    pub fn release(manager: &ORSetManager<T>, saved: &SavedORSet) -> Result<usize>
+    where
+        T: Clone,
+    {
+        let mut deleted = 0;
+        for root in [saved.added, saved.removed].into_iter().flatten() {
+            deleted += manager.release(&root)?;
+        }
+        Ok(deleted)
+    }
+}
+
+impl<T: Ord + Clone> ORSet<T> {
+    fn tags_of(element: &T) -> RangeInclusive<TaggedElement<T>> {
+        let tagged = |tag| TaggedElement {
+            element: element.clone(),
+            tag,
+        };
+        tagged(Tag::MIN)..=tagged(Tag::MAX)
+    }
+
+    pub fn contains(&self, element: &T) -> Result<bool> {
+        match self.added.range(Self::tags_of(element))?.next() {
+            Some(node) => node.map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the elements of the set in order.
+    pub fn elements(&self) -> Result<Vec<T>> {
+        let mut elements: Vec<T> = Vec::new();
+        for node in self.added.iter()? {
+            let node = node?;
+            if elements.last() != Some(&node.key().element) {
+                elements.push(node.key().element.clone());
+            }
+        }
+        Ok(elements)
+    }
+
+    /// Returns the tags of `element` the set holds.
+    pub fn tags(&self, element: &T) -> Result<Vec<Tag>> {
+        self.added
+            .range(Self::tags_of(element))?
+            .map(|node| Ok(node?.key().tag))
+            .collect()
+    }
+}
+
+impl<T: Ord + Clone + Hashable> ORSet<T> {
+    /// Adds `element` under a new tag, which is returned.
+    pub fn add(&mut self, element: T) -> Result<Tag> {
+        let Some(next_counter) = self.next_counter.checked_add(1) else {
+            return Err(Error::invalid(format!(
+                "replica {} ran out of tags",
+                self.replica
+            )));
+        };
+        let tag = Tag {
+            replica: self.replica,
+            counter: self.next_counter,
+        };
+        self.added = self.added.insert(TaggedElement { element, tag }, ())?;
+        self.next_counter = next_counter;
+        Ok(tag)
+    }
+
+    /// Removes `element` by removing every tag of it the set holds. Returns
+    /// whether it was present. Adds of it this replica hasn't merged yet
+    /// survive the removal.
+    pub fn remove(&mut self, element: &T) -> Result<bool> {
+        let tags: Vec<TaggedElement<T>> = self
+            .added
+            .range(Self::tags_of(element))?
+            .map(|node| Ok(node?.key().clone()))
+            .collect::<Result<_>>()?;
+        for tagged in &tags {
+            self.added = self.added.delete(tagged)?;
+            self.removed = self.removed.insert(tagged.clone(), ())?;
+        }
+        Ok(!tags.is_empty())
+    }
+
+    /// Merges the state of another replica into this one: the set then
+    /// holds the tags either held which neither removed.
+    pub fn merge(&mut self, other: &ORSet<T>) -> Result<()> {
+        let keys = |tree: &Tree<TaggedElement<T>, ()>| -> Result<Vec<_>> {
+            tree.iter()?.map(|node| Ok(node?.key().clone())).collect()
+        };
+        self.merge_tags(keys(&other.removed)?, keys(&other.added)?)
+    }
+
+    /// Merges tags of another replica, e.g. the deltas a [`sync`] of the
+    /// [`ORSet::removed`] and [`ORSet::added`] trees found: the `removed`
+    /// tags are removed, and the `added` ones are added unless they were
+    /// ever removed.
+    pub fn merge_tags(
+        &mut self,
+        removed: impl IntoIterator<Item = TaggedElement<T>>,
+        added: impl IntoIterator<Item = TaggedElement<T>>,
+    ) -> Result<()> {
+        for tagged in removed {
+            self.added = self.added.delete(&tagged)?;
+            self.removed = self.removed.insert(tagged, ())?;
+        }
+        for tagged in added {
+            if !self.removed.contains_key(&tagged)? {
+                self.added = self.added.insert(tagged, ())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves both trees, see [`Tree::save`], and returns the state to
+    /// [`ORSet::load`] it from.
+    pub fn save(&mut self) -> Result<SavedORSet> {
+        self.added = self.added.save()?;
+        self.removed = self.removed.save()?;
+        Ok(SavedORSet {
+            replica: self.replica,
+            next_counter: self.next_counter,
+            added: self.added.root_ptr(),
+            removed: self.removed.root_ptr(),
+        })
+    }
+}
@@ -0,0 +1,122 @@
+//! A multi-value register, which keeps every value written concurrently
+//! rather than picking one of them, so no write is silently lost.
+//!
+//! Each write is identified by a dot, the [`Tag`] of the writing replica's
+//! next event, and the register tracks the dots it has seen in a
+//! [`VectorClock`]. A write replaces the values whose dots the writer had
+//! seen, i.e. those in the context it passes, and leaves the others, so
+//! after merging concurrent writes [`MVReg::read`] returns all of them
+//! until a write based on that read replaces them.
+
+use std::collections::BTreeMap;
+
+use crate::tree::hash::{update_length_prefixed, Hashable, Update};
+
+use super::clock::VectorClock;
+use super::Tag;
+use crate::Result;
+
+/// A multi-value register, see the [module documentation](self).
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MVReg<V> {
+    replica: u64,
+    /// The current values by the dot of their write.
+    values: BTreeMap<Tag, V>,
+    /// Every dot the register has seen.
+    context: VectorClock,
+}
+
+impl<V> MVReg<V> {
+    /// Creates an empty register for the replica `replica`, which must be
+    /// unique among the replicas whose states are ever merged.
+    pub fn new(replica: u64) -> Self {
+        MVReg {
+            replica,
+            values: BTreeMap::new(),
+            context: VectorClock::new(),
+        }
+    }
+
+    pub fn replica(&self) -> u64 {
+        self.replica
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The dots of the register's writes, to pass to [`MVReg::write`] along
+    /// with a value derived from [`MVReg::read`].
+    pub fn context(&self) -> &VectorClock {
+        &self.context
+    }
+
+    /// Returns the concurrent values, along with the dots of their writes.
+    pub fn entries(&self) -> impl Iterator<Item = (Tag, &V)> + '_ {
+        self.values.iter().map(|(dot, value)| (*dot, value))
+    }
+
+    /// Writes `value`, replacing the values whose writes `context` covers,
+    /// which is normally the [`MVReg::context`] of the read the value was
+    /// derived from. Returns the dot of the write.
+    pub fn write(&mut self, value: V, context: &VectorClock) -> Result<Tag> {
+        self.values
+            .retain(|dot, _| context.get(dot.replica) < dot.counter);
+        self.context.merge(context);
+        let dot = Tag {
+            replica: self.replica,
+            counter: self.context.increment(self.replica)?,
+        };
+        self.values.insert(dot, value);
+        Ok(dot)
+    }
+
+    /// Writes `value` over every value the register holds.
+    pub fn assign(&mut self, value: V) -> Result<Tag> {
+        let context = self.context.clone();
+        self.write(value, &context)
+    }
+}
+
+impl<V: Clone> MVReg<V> {
+    /// Returns the concurrent values in the order of their dots, none if the
+    /// register was never written.
+    pub fn read(&self) -> Vec<V> {
+        self.values.values().cloned().collect()
+    }
+
+    /// Merges the state of another replica: a value is kept if both hold
+    /// it, or if one holds it and the other hasn't seen its write.
+    pub fn merge(&mut self, other: &MVReg<V>) {
+        let context = &self.context;
+        let unseen = |dot: &Tag, seen: &VectorClock| seen.get(dot.replica) < dot.counter;
+        let mut values: BTreeMap<Tag, V> = self
+            .values
+            .iter()
+            .filter(|(dot, _)| other.values.contains_key(dot) || unseen(dot, &other.context))
+            .map(|(dot, value)| (*dot, value.clone()))
+            .collect();
+        for (dot, value) in &other.values {
+            if !values.contains_key(dot) && unseen(dot, context) {
+                values.insert(*dot, value.clone());
+            }
+        }
+        self.values = values;
+        self.context.merge(&other.context);
+    }
+}
+
+/// Hashes the number of values, then each dot and length-prefixed value,
+/// then the context.
+impl<V: Hashable> Hashable for MVReg<V> {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(&(self.values.len() as u64).to_be_bytes());
+        for (dot, value) in &self.values {
+            hasher.update(&dot.replica.to_be_bytes());
+            hasher.update(&dot.counter.to_be_bytes());
+            update_length_prefixed(hasher, value);
+        }
+        self.context.update_hash(hasher);
+    }
+}
@@ -0,0 +1,344 @@
+//! Anti-entropy: finding the entries two replicas of a tree disagree on by
+//! exchanging merkle hashes of key ranges, so that only the differences
+//! cross the network.
+//!
+//! The hash of an AVL subtree depends on the shape of the tree, and the
+//! shape on the order of the operations which built it, so replicas holding
+//! the same entries rarely share subtree hashes. Replicas hash key ranges
+//! instead, which forms a merkle tree whose shape the ranges decide: a
+//! [`Message::Digest`] carries the hash of the entries of a range, and a
+//! replica whose own digest of the range differs splits it at its own keys
+//! and answers with the digests of the parts. Ranges the replicas agree on
+//! are never descended into, so a sync exchanges O(d log n) digests for `d`
+//! differing entries. Once a range holds few entries, they are sent as they
+//! are, and the other replica answers with the entries of the range it holds
+//! differently.
+//!
+//! A replica computes a digest by reading its range, so a sync reads each
+//! entry once per level of ranges it lies in, about log n times at worst.
+//!
+//! Each [`SyncSession`] collects the entries its peer holds and it lacks or
+//! holds with another value, which [`apply_delta`] merges into its tree.
+
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+use sha2::{Digest as _, Sha256};
+
+use crate::tree::avl::Tree;
+use crate::tree::hash::{update_length_prefixed, Digest, Hashable};
+use crate::{Error, Result};
+
+/// Entries received from a peer which the local tree lacks or holds with
+/// another value.
+pub type Delta<K, V> = Vec<(K, V)>;
+
+/// A range of keys, from `start` included to `end` excluded.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeyRange<K> {
+    /// The first key of the range, `None` if it is unbounded.
+    pub start: Option<K>,
+    /// The key after the range, `None` if it is unbounded.
+    pub end: Option<K>,
+}
+
+impl<K> KeyRange<K> {
+    /// The range of every key.
+    pub fn full() -> Self {
+        KeyRange {
+            start: None,
+            end: None,
+        }
+    }
+
+    fn bounds(&self) -> (Bound<&K>, Bound<&K>) {
+        (
+            self.start
+                .as_ref()
+                .map_or(Bound::Unbounded, Bound::Included),
+            self.end.as_ref().map_or(Bound::Unbounded, Bound::Excluded),
+        )
+    }
+}
+
+impl<K: Ord> KeyRange<K> {
+    pub fn contains(&self, key: &K) -> bool {
+        self.start.as_ref().is_none_or(|start| start <= key)
+            && self.end.as_ref().is_none_or(|end| key < end)
+    }
+}
+
+/// A message exchanged by [`SyncSession`]s.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Message<K, V> {
+    /// The number of entries the sender holds in `range`, and their hash.
+    Digest {
+        range: KeyRange<K>,
+        len: u64,
+        hash: Digest,
+    },
+    /// Every entry the sender holds in `range`, in key order. The receiver
+    /// answers with [`Message::Missing`].
+    Entries {
+        range: KeyRange<K>,
+        entries: Vec<(K, V)>,
+    },
+    /// The entries the receiver of [`Message::Entries`] holds in its range
+    /// and the sender doesn't, or holds with another value.
+    Missing { entries: Vec<(K, V)> },
+}
+
+/// Parameters of a [`SyncSession`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SyncOptions {
+    fanout: usize,
+    leaf_len: u64,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            fanout: 16,
+            leaf_len: 16,
+        }
+    }
+}
+
+impl SyncOptions {
+    /// Sets the number of parts a differing range is split into, 16 by
+    /// default. Values below 2 are raised to 2.
+    pub fn fanout(self, fanout: usize) -> Self {
+        SyncOptions {
+            fanout: fanout.max(2),
+            ..self
+        }
+    }
+
+    /// Sets the number of entries up to which a differing range is sent as
+    /// it is rather than split, 16 by default. Values below 1 are raised
+    /// to 1.
+    pub fn leaf_len(self, leaf_len: u64) -> Self {
+        SyncOptions {
+            leaf_len: leaf_len.max(1),
+            ..self
+        }
+    }
+}
+
+/// One side of a sync with a replica of the same tree. Each side sends the
+/// messages of [`SyncSession::start`] or [`SyncSession::receive`] to the
+/// other, which passes them to its own `receive`, until a side has nothing
+/// left to send. Both sides then hold their [`SyncSession::delta`].
+pub struct SyncSession<'a, K, V> {
+    tree: &'a Tree<K, V>,
+    options: SyncOptions,
+    delta: Delta<K, V>,
+}
+
+impl<'a, K, V> SyncSession<'a, K, V>
+where
+    K: Ord + Clone + Hashable,
+    V: Clone + Hashable + PartialEq,
+{
+    pub fn new(tree: &'a Tree<K, V>, options: SyncOptions) -> Self {
+        SyncSession {
+            tree,
+            options,
+            delta: Vec::new(),
+        }
+    }
+
+    /// Returns the messages opening the sync, the digest of the whole tree.
+    pub fn start(&self) -> Result<Vec<Message<K, V>>> {
+        Ok(vec![self.digest(KeyRange::full())?])
+    }
+
+    /// Handles the messages of the peer, returning the replies to send it.
+    /// The sync is complete when there are none.
+    pub fn receive(&mut self, messages: Vec<Message<K, V>>) -> Result<Vec<Message<K, V>>> {
+        let mut replies = Vec::new();
+        for message in messages {
+            match message {
+                Message::Digest { range, len, hash } => {
+                    let (local_len, local_hash) = self.summarize(&range)?;
+                    if (local_len, local_hash) == (len, hash) {
+                        continue;
+                    }
+                    if local_len.min(len) <= self.options.leaf_len {
+                        let entries = self.entries(&range)?;
+                        replies.push(Message::Entries { range, entries });
+                    } else {
+                        for part in self.split(&range, local_len)? {
+                            replies.push(self.digest(part)?);
+                        }
+                    }
+                }
+                Message::Entries { range, entries } => {
+                    check_entries(&range, &entries)?;
+                    let (missing, received) = difference(self.entries(&range)?, entries);
+                    self.delta.extend(received);
+                    if !missing.is_empty() {
+                        replies.push(Message::Missing { entries: missing });
+                    }
+                }
+                Message::Missing { entries } => self.delta.extend(entries),
+            }
+        }
+        Ok(replies)
+    }
+
+    /// The entries received so far which the local tree lacks or holds with
+    /// another value.
+    pub fn delta(&self) -> &[(K, V)] {
+        &self.delta
+    }
+
+    pub fn into_delta(self) -> Delta<K, V> {
+        self.delta
+    }
+
+    fn digest(&self, range: KeyRange<K>) -> Result<Message<K, V>> {
+        let (len, hash) = self.summarize(&range)?;
+        Ok(Message::Digest { range, len, hash })
+    }
+
+    /// Returns the number of local entries in `range` and their hash.
+    fn summarize(&self, range: &KeyRange<K>) -> Result<(u64, Digest)> {
+        let mut hasher = Sha256::new();
+        let mut len = 0;
+        for node in self.tree.range(range.bounds())? {
+            let node = node?;
+            update_length_prefixed(&mut hasher, node.key());
+            update_length_prefixed(&mut hasher, node.value());
+            len += 1;
+        }
+        Ok((len, hasher.finalize().into()))
+    }
+
+    fn entries(&self, range: &KeyRange<K>) -> Result<Vec<(K, V)>> {
+        self.tree
+            .range(range.bounds())?
+            .map(|node| {
+                let node = node?;
+                Ok((node.key().clone(), node.value().clone()))
+            })
+            .collect()
+    }
+
+    /// Splits `range`, holding `len` local entries, into parts holding about
+    /// as many local entries each.
+    fn split(&self, range: &KeyRange<K>, len: u64) -> Result<Vec<KeyRange<K>>> {
+        let first = match &range.start {
+            Some(start) => self.tree.rank(start)?,
+            None => 0,
+        };
+        let parts = (self.options.fanout as u64).min(len);
+        let mut bounds = vec![range.start.clone()];
+        for i in 1..parts {
+            let Some(node) = self.tree.nth(first + len * i / parts)? else {
+                return Err(Error::invalid(format!(
+                    "range holds fewer than {} entries",
+                    len
+                )));
+            };
+            bounds.push(Some(node.key().clone()));
+        }
+        bounds.push(range.end.clone());
+        Ok(bounds
+            .windows(2)
+            .map(|bounds| KeyRange {
+                start: bounds[0].clone(),
+                end: bounds[1].clone(),
+            })
+            .collect())
+    }
+}
+
+/// Checks that the entries a peer sent for `range` are in it and in order.
+fn check_entries<K: Ord, V>(range: &KeyRange<K>, entries: &[(K, V)]) -> Result<()> {
+    if entries.iter().any(|(key, _)| !range.contains(key)) {
+        return Err(Error::invalid("peer sent entries outside of their range"));
+    }
+    if entries.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+        return Err(Error::invalid("peer sent entries out of order"));
+    }
+    Ok(())
+}
+
+/// Splits two sorted lists of entries into the entries only in `ours` and
+/// those only in `theirs`. A key with different values is in both.
+fn difference<K: Ord, V: PartialEq>(
+    ours: Vec<(K, V)>,
+    theirs: Vec<(K, V)>,
+) -> (Delta<K, V>, Delta<K, V>) {
+    let (mut only_ours, mut only_theirs) = (Vec::new(), Vec::new());
+    let mut ours = ours.into_iter().peekable();
+    let mut theirs = theirs.into_iter().peekable();
+    loop {
+        let order = match (ours.peek(), theirs.peek()) {
+            (Some(a), Some(b)) => a.0.cmp(&b.0),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => break,
+        };
+        match order {
+            Ordering::Less => only_ours.extend(ours.next()),
+            Ordering::Greater => only_theirs.extend(theirs.next()),
+            Ordering::Equal => {
+                let (a, b) = (ours.next().unwrap(), theirs.next().unwrap());
+                if a.1 != b.1 {
+                    only_ours.push(a);
+                    only_theirs.push(b);
+                }
+            }
+        }
+    }
+    (only_ours, only_theirs)
+}
+
+/// Runs a sync between two replicas in the same process, returning the
+/// deltas of `a` and of `b`.
+pub fn sync<K, V>(
+    a: &Tree<K, V>,
+    b: &Tree<K, V>,
+    options: SyncOptions,
+) -> Result<(Delta<K, V>, Delta<K, V>)>
+where
+    K: Ord + Clone + Hashable,
+    V: Clone + Hashable + PartialEq,
+{
+    let (mut a, mut b) = (SyncSession::new(a, options), SyncSession::new(b, options));
+    let mut messages = a.start()?;
+    while !messages.is_empty() {
+        messages = b.receive(messages)?;
+        if messages.is_empty() {
+            break;
+        }
+        messages = a.receive(messages)?;
+    }
+    Ok((a.into_delta(), b.into_delta()))
+}
+
+/// Merges a delta into `tree`: absent keys are inserted, and the values of
+/// present ones are replaced by `merge` of the local and the received value.
+pub fn apply_delta<K, V>(
+    tree: &Tree<K, V>,
+    delta: impl IntoIterator<Item = (K, V)>,
+    mut merge: impl FnMut(&K, V, V) -> V,
+) -> Result<Tree<K, V>>
+where
+    K: Ord + Clone + Hashable,
+    V: Clone + Hashable,
+{
+    let mut tree = tree.clone();
+    for (key, value) in delta {
+        let value = match tree.get(&key)? {
+            Some(local) => merge(&key, local, value),
+            None => value,
+        };
+        tree = tree.insert(key, value)?;
+    }
+    Ok(tree)
+}
@@ -0,0 +1,284 @@
+//! A small verifiable credit registry built from the pieces of the crate,
+//! serving as an end-to-end exercise of the stack and a template for
+//! applications. See `examples/credit_registry.rs` for a walkthrough.
+//!
+//! The registry keeps three trees of byte strings: the open accounts, the
+//! credit balances by account and batch, and the retirements of credits.
+//! They are nested under a versioned root tree as [`RootValue`]s, so the
+//! root hash of a version commits to all three. Every
+//! [`CreditRegistry::commit`] saves a version, balances are proven against
+//! its root hash with [`ChainedProof`]s, and the changes each version made
+//! can be listed for consumers following the registry, who can also be
+//! notified of commits with a [`VersionObserver`].
+
+use std::sync::Arc;
+
+use crate::tree::avl::diff::Change;
+use crate::tree::avl::node::Manager;
+use crate::tree::avl::subtree::{ChainedProof, RootValue};
+use crate::tree::avl::versioned::{Version, VersionInfo, VersionObserver, VersionedTree};
+use crate::tree::avl::Tree;
+use crate::tree::hash::Digest;
+use crate::tree::node_manager::NodeManager;
+use crate::{Error, Result};
+
+type Bytes = Vec<u8>;
+
+/// The key of the accounts tree in the root tree.
+pub const ACCOUNTS: &[u8] = b"accounts";
+/// The key of the balances tree in the root tree.
+pub const CREDITS: &[u8] = b"credits";
+/// The key of the retirements tree in the root tree.
+pub const RETIREMENTS: &[u8] = b"retirements";
+
+const TREES: [&[u8]; 3] = [ACCOUNTS, CREDITS, RETIREMENTS];
+
+/// The key of the balance of `account` in credits of `batch`: the account
+/// name, a zero byte, then the batch name.
+pub fn credit_key(account: &str, batch: &str) -> Bytes {
+    let mut key = account.as_bytes().to_vec();
+    key.push(0);
+    key.extend_from_slice(batch.as_bytes());
+    key
+}
+
+fn amount(value: &[u8]) -> Result<u64> {
+    match value.try_into() {
+        Ok(bytes) => Ok(u64::from_be_bytes(bytes)),
+        Err(_) => Err(Error::codec(format!(
+            "malformed amount of {} bytes",
+            value.len()
+        ))),
+    }
+}
+
+/// A change made by a version, in one of the trees of the registry.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RegistryChange {
+    /// [`ACCOUNTS`], [`CREDITS`] or [`RETIREMENTS`].
+    pub tree: &'static [u8],
+    pub change: Change<Bytes, Bytes>,
+}
+
+/// A proof of a balance as of a committed version.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BalanceProof {
+    pub version: Version,
+    /// The [`credit_key`] of the balance.
+    pub key: Bytes,
+    pub balance: u64,
+    pub proof: ChainedProof,
+}
+
+impl BalanceProof {
+    /// Checks the balance against the root hash of its version, e.g. one
+    /// published by the registry operator.
+    pub fn verify(&self, root: &Digest) -> bool {
+        self.proof.verify(
+            root,
+            &[CREDITS, &self.key[..]],
+            &self.balance.to_be_bytes()[..],
+        )
+    }
+}
+
+/// A registry of accounts holding and retiring batches of credits.
+pub struct CreditRegistry {
+    root: VersionedTree<Bytes, RootValue>,
+    data: Arc<Manager<Bytes, Bytes>>,
+    accounts: Tree<Bytes, Bytes>,
+    credits: Tree<Bytes, Bytes>,
+    retirements: Tree<Bytes, Bytes>,
+}
+
+impl Default for CreditRegistry {
+    fn default() -> Self {
+        CreditRegistry::new(
+            Arc::new(NodeManager::in_memory()),
+            Arc::new(NodeManager::in_memory()),
+        )
+    }
+}
+
+impl CreditRegistry {
+    /// Creates an empty registry whose root tree is saved through `root` and
+    /// whose data trees are saved through `data`.
+    pub fn new(root: Arc<Manager<Bytes, RootValue>>, data: Arc<Manager<Bytes, Bytes>>) -> Self {
+        CreditRegistry {
+            root: VersionedTree::new(root),
+            accounts: Tree::with_manager(data.clone()),
+            credits: Tree::with_manager(data.clone()),
+            retirements: Tree::with_manager(data.clone()),
+            data,
+        }
+    }
+
+    /// Notifies `observer` of every commit.
+    pub fn set_observer(&mut self, observer: Arc<dyn VersionObserver>) {
+        self.root.set_observer(observer);
+    }
+
+    pub fn latest_version(&self) -> Version {
+        self.root.latest_version()
+    }
+
+    pub fn open_account(&mut self, account: &str) -> Result<()> {
+        if account.is_empty() || account.contains('\0') {
+            return Err(Error::invalid(format!(
+                "invalid account name {:?}",
+                account
+            )));
+        }
+        if self.accounts.contains_key(account.as_bytes())? {
+            return Err(Error::invalid(format!(
+                "account {} already exists",
+                account
+            )));
+        }
+        let opened = self.root.next_version()?.get().to_be_bytes().to_vec();
+        self.accounts = self.accounts.insert(account.as_bytes().to_vec(), opened)?;
+        Ok(())
+    }
+
+    fn check_account(&self, account: &str) -> Result<()> {
+        if !self.accounts.contains_key(account.as_bytes())? {
+            return Err(Error::invalid(format!(
+                "account {} does not exist",
+                account
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the uncommitted balance of `account` in credits of `batch`.
+    pub fn balance(&self, account: &str, batch: &str) -> Result<u64> {
+        match self.credits.get(&credit_key(account, batch))? {
+            Some(value) => amount(&value),
+            None => Ok(0),
+        }
+    }
+
+    fn set_balance(&mut self, account: &str, batch: &str, balance: u64) -> Result<()> {
+        let key = credit_key(account, batch);
+        self.credits = match balance {
+            0 => self.credits.delete(&key)?,
+            _ => self.credits.insert(key, balance.to_be_bytes().to_vec())?,
+        };
+        Ok(())
+    }
+
+    /// Issues `amount` new credits of `batch` to `account`.
+    pub fn issue(&mut self, account: &str, batch: &str, amount: u64) -> Result<()> {
+        self.check_account(account)?;
+        let Some(balance) = self.balance(account, batch)?.checked_add(amount) else {
+            return Err(Error::invalid(format!(
+                "balance of {} in {} overflows",
+                account, batch
+            )));
+        };
+        self.set_balance(account, batch, balance)
+    }
+
+    fn withdraw(&mut self, account: &str, batch: &str, amount: u64) -> Result<()> {
+        self.check_account(account)?;
+        let balance = self.balance(account, batch)?;
+        if balance < amount {
+            return Err(Error::invalid(format!(
+                "{} holds {} credits of {}, not {}",
+                account, balance, batch, amount
+            )));
+        }
+        self.set_balance(account, batch, balance - amount)
+    }
+
+    /// Moves `amount` credits of `batch` from one account to another.
+    pub fn transfer(&mut self, from: &str, to: &str, batch: &str, amount: u64) -> Result<()> {
+        self.check_account(to)?;
+        self.withdraw(from, batch, amount)?;
+        self.issue(to, batch, amount)
+    }
+
+    /// Retires `amount` credits of `batch` held by `account`, taking them
+    /// out of circulation, and returns the number of the retirement.
+    pub fn retire(&mut self, account: &str, batch: &str, amount: u64) -> Result<u64> {
+        self.withdraw(account, batch, amount)?;
+        let number = self.retirements.len()?;
+        let mut record = credit_key(account, batch);
+        record.extend_from_slice(&amount.to_be_bytes());
+        self.retirements = self
+            .retirements
+            .insert(number.to_be_bytes().to_vec(), record)?;
+        Ok(number)
+    }
+
+    /// Saves the data trees in a single batch and records their roots as
+    /// the next version.
+    pub fn commit(&mut self) -> Result<VersionInfo> {
+        let saved = Tree::save_all([&self.accounts, &self.credits, &self.retirements])?;
+        [self.accounts, self.credits, self.retirements] =
+            saved.try_into().expect("three trees were saved");
+        let roots = [&self.accounts, &self.credits, &self.retirements].map(RootValue::of);
+        for (name, root) in TREES.into_iter().zip(roots) {
+            self.root.insert(name.to_vec(), root?)?;
+        }
+        let version = self.root.save()?;
+        Ok(*self
+            .root
+            .version(version)
+            .expect("the version was just saved"))
+    }
+
+    /// Opens the tree `name` as of `version`, which is empty before the
+    /// first commit.
+    fn tree_at(&self, name: &[u8], version: Version) -> Result<Tree<Bytes, Bytes>> {
+        Ok(self
+            .root
+            .load_version(version)?
+            .subtree(name, self.data.clone())?
+            .unwrap_or_else(|| Tree::with_manager(self.data.clone())))
+    }
+
+    /// Proves the balance of `account` in credits of `batch` as of the
+    /// latest commit, or returns `None` if it was zero.
+    pub fn prove_balance(&self, account: &str, batch: &str) -> Result<Option<BalanceProof>> {
+        let version = self.latest_version();
+        let key = credit_key(account, batch);
+        let Some((outer, credits)) = self
+            .root
+            .load_version(version)?
+            .prove_subtree(CREDITS, self.data.clone())?
+        else {
+            return Ok(None);
+        };
+        let (Some(inner), Some(balance)) = (credits.prove(&key)?, credits.get(&key)?) else {
+            return Ok(None);
+        };
+        Ok(Some(BalanceProof {
+            version,
+            key,
+            balance: amount(&balance)?,
+            proof: ChainedProof::new(outer).then(inner),
+        }))
+    }
+
+    /// Lists the changes `version` made to each tree, accounts first.
+    pub fn changes(&self, version: Version) -> Result<Vec<RegistryChange>> {
+        let Some(previous) = version.get().checked_sub(1).map(Version::new) else {
+            return Err(Error::invalid(format!(
+                "version {} made no changes",
+                version
+            )));
+        };
+        let mut changes = Vec::new();
+        for name in TREES {
+            let (base, new) = (self.tree_at(name, previous)?, self.tree_at(name, version)?);
+            for change in new.diff(&base) {
+                changes.push(RegistryChange {
+                    tree: name,
+                    change: change?,
+                });
+            }
+        }
+        Ok(changes)
+    }
+}
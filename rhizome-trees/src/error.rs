@@ -0,0 +1,192 @@
+//! The error type every fallible operation of the crate returns.
+//!
+//! [`Error`] tells apart the failures callers handle differently: a node
+//! which isn't in the store, stored data which is corrupt, a store backend
+//! or file which failed, bytes which don't decode, an invalid proof, and
+//! calls which are invalid in the current state. Backend errors are kept as
+//! the [`source`](std::error::Error::source) of the error wrapping them.
+
+use std::fmt;
+use std::io;
+
+use crate::tree::node_manager::Ptr;
+
+/// A [`Result`](std::result::Result) whose error defaults to [`Error`].
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A boxed error of a store backend or other dependency.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// No node is stored under the pointer.
+    #[error("node {0:?} not found")]
+    NotFound(Ptr),
+    /// [`NodeStore::dec_ref_count`](crate::tree::node_manager::NodeStore::dec_ref_count)
+    /// was called on a node whose reference count is already 0.
+    #[error("reference count of node {0:?} is already 0")]
+    RefCountUnderflow(Ptr),
+    /// The stored encoding of the node failed its integrity check or
+    /// doesn't decode; `source` tells why, if the store knows.
+    #[error("node {ptr:?} is corrupt")]
+    Corrupt {
+        ptr: Ptr,
+        #[source]
+        source: Option<Box<Error>>,
+    },
+    /// The stored trees break one of their invariants, e.g. a node's hash
+    /// doesn't match its contents.
+    #[error("{0}")]
+    Corruption(String),
+    /// The backend of a node store failed, e.g. a database transaction.
+    #[error("{context}: {source}")]
+    Storage {
+        context: String,
+        #[source]
+        source: BoxError,
+    },
+    /// Reading or writing a file, stream or process failed.
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    /// A thread panicked while holding a lock, a worker thread panicked, or
+    /// a journal was left unusable by a failed write.
+    #[error("{0}")]
+    Poisoned(String),
+    /// Bytes don't hold a valid encoding, or a value can't be encoded.
+    #[error("{0}")]
+    Codec(String),
+    /// A proof isn't well-formed or doesn't prove what it is checked
+    /// against.
+    #[error("{0}")]
+    ProofInvalid(String),
+    /// A write was made through a read-only node manager.
+    #[error("node manager is read-only")]
+    ReadOnly,
+    /// An empty value was written to a
+    /// [`VersionedTree`](crate::tree::avl::versioned::VersionedTree) while
+    /// [`EmptyValues::Reject`](crate::tree::avl::versioned::EmptyValues::Reject)
+    /// is set.
+    #[error("empty values are not allowed")]
+    EmptyValue,
+    /// The caller cancelled the operation.
+    #[error("{0}")]
+    Cancelled(String),
+    /// An argument or setting is invalid, or the operation isn't possible in
+    /// the current state, e.g. a version which doesn't exist.
+    #[error("{0}")]
+    Invalid(String),
+}
+
+impl Error {
+    /// Whether the error means the stored trees are corrupt, rather than
+    /// e.g. that the store is unavailable. A missing node isn't corruption
+    /// by itself, since it may be the root of a released version.
+    pub fn is_corruption(&self) -> bool {
+        matches!(
+            self,
+            Error::RefCountUnderflow(_) | Error::Corrupt { .. } | Error::Corruption(_)
+        )
+    }
+
+    /// An [`Error::Corrupt`] for the node at `ptr` without a known cause.
+    pub fn corrupt(ptr: Ptr) -> Self {
+        Error::Corrupt { ptr, source: None }
+    }
+
+    pub(crate) fn invalid(message: impl fmt::Display) -> Self {
+        Error::Invalid(message.to_string())
+    }
+
+    pub(crate) fn codec(message: impl fmt::Display) -> Self {
+        Error::Codec(message.to_string())
+    }
+
+    pub(crate) fn corruption(message: impl fmt::Display) -> Self {
+        Error::Corruption(message.to_string())
+    }
+
+    #[cfg(feature = "ics23")]
+    pub(crate) fn proof_invalid(message: impl fmt::Display) -> Self {
+        Error::ProofInvalid(message.to_string())
+    }
+
+    pub(crate) fn poisoned(what: &str) -> Self {
+        Error::Poisoned(format!("{} lock poisoned", what))
+    }
+
+    pub(crate) fn io(context: impl fmt::Display, source: io::Error) -> Self {
+        Error::Io {
+            context: context.to_string(),
+            source,
+        }
+    }
+
+    #[cfg(any(
+        feature = "grpc",
+        feature = "object-store",
+        feature = "redb",
+        feature = "parquet"
+    ))]
+    pub(crate) fn storage(context: impl fmt::Display, source: impl Into<BoxError>) -> Self {
+        Error::Storage {
+            context: context.to_string(),
+            source: source.into(),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Error::io("I/O failed", source)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Self {
+        Error::codec(err)
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<arrow_schema::ArrowError> for Error {
+    fn from(err: arrow_schema::ArrowError) -> Self {
+        Error::codec(err)
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<parquet::errors::ParquetError> for Error {
+    fn from(err: parquet::errors::ParquetError) -> Self {
+        Error::storage("writing Parquet failed", err)
+    }
+}
+
+/// Converts the errors of the redb node store's transactions.
+#[cfg(feature = "redb")]
+macro_rules! from_redb {
+    ($($err:ty),*) => {
+        $(
+            impl From<$err> for Error {
+                fn from(err: $err) -> Self {
+                    Error::storage("node database failed", redb::Error::from(err))
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "redb")]
+from_redb!(
+    redb::Error,
+    redb::DatabaseError,
+    redb::TransactionError,
+    redb::TableError,
+    redb::StorageError,
+    redb::CommitError
+);
@@ -0,0 +1,7 @@
+pub mod crdt;
+#[cfg(feature = "demo")]
+pub mod demo;
+mod error;
+pub mod tree;
+
+pub use error::{BoxError, Error, Result};
@@ -0,0 +1,80 @@
+//! Rendering a tree as a Graphviz graph, for debugging the radix
+//! algorithms.
+//!
+//! Inner nodes show their size, compressed prefix and how many of their
+//! slots are taken, leaves their key, and each edge the key byte it is
+//! taken for. The leaf whose key ends at an inner node hangs off a dashed
+//! edge. Printable ASCII bytes are shown as they are, others as `\xNN`.
+
+use std::fmt::Write;
+use std::sync::Arc;
+
+use super::node::Node;
+use super::Tree;
+use crate::tree::graphviz::{self, escape, Format};
+use crate::Result;
+
+impl<V: Clone> Tree<V> {
+    /// Returns the tree in the Graphviz DOT language, see the
+    /// [module documentation](self).
+    pub fn to_graphviz(&self) -> String {
+        let mut dot = String::from("digraph art {\n    node [fontname=monospace];\n");
+        if let Some(root) = &self.root {
+            let mut next_id = 0;
+            write_node(&mut dot, root, &mut next_id);
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Writes the graph of [`Tree::to_graphviz`] to `writer` in `format`,
+    /// see [`graphviz::render`].
+    pub fn render_graphviz(&self, format: Format, writer: impl std::io::Write) -> Result<()> {
+        graphviz::render(&self.to_graphviz(), format, writer)
+    }
+}
+
+/// Writes `node` and its subtree, returning the ID of `node`.
+fn write_node<V: Clone>(dot: &mut String, node: &Arc<Node<V>>, next_id: &mut usize) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+    let Some(header) = node.header() else {
+        let Node::Leaf(leaf) = &**node else {
+            unreachable!("nodes without a header are leaves");
+        };
+        let label = format!("Leaf\\n{}", escape(leaf.key()));
+        writeln!(dot, "    n{} [shape=box, label=\"{}\"];", id, label).unwrap();
+        return id;
+    };
+    let (kind, capacity) = match &**node {
+        Node::Node4(_) => ("Node4", 4),
+        Node::Node16(_) => ("Node16", 16),
+        Node::Node48(_) => ("Node48", 48),
+        Node::Node256(_) => ("Node256", 256),
+        Node::Leaf(_) => unreachable!("leaves have no header"),
+    };
+    let label = format!(
+        "{}\\nprefix {}\\n{}/{} children",
+        kind,
+        escape(header.prefix()),
+        node.num_children(),
+        capacity
+    );
+    writeln!(dot, "    n{} [label=\"{}\"];", id, label).unwrap();
+    if let Some(leaf) = header.leaf() {
+        let child = write_node(dot, leaf, next_id);
+        writeln!(dot, "    n{} -> n{} [style=dashed];", id, child).unwrap();
+    }
+    for (byte, child) in node.children() {
+        let child_id = write_node(dot, child, next_id);
+        writeln!(
+            dot,
+            "    n{} -> n{} [label=\"{}\"];",
+            id,
+            child_id,
+            escape(&[byte])
+        )
+        .unwrap();
+    }
+    id
+}
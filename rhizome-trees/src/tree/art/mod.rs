@@ -0,0 +1,196 @@
+//! A persistent adaptive radix tree (ART) map over byte string keys.
+
+pub mod graphviz;
+pub mod node;
+pub mod stats;
+
+use std::sync::Arc;
+
+use crate::tree::cardinality::{self, Cardinality, DEFAULT_PROBES};
+use crate::tree::hash::{Digest, Hashable, MerkleTree, EMPTY_HASH};
+use crate::tree::map::{Map, PersistentMap};
+use crate::Result;
+use node::{Node, ResizeStats, Resizer, Resizing};
+
+/// A persistent map from byte strings to values. Like [`crate::tree::avl::Tree`],
+/// cloning is O(1) and modifications share all untouched nodes.
+#[derive(Debug)]
+pub struct Tree<V> {
+    root: Option<Arc<Node<V>>>,
+    resizer: Arc<Resizer>,
+}
+
+impl<V> Clone for Tree<V> {
+    fn clone(&self) -> Self {
+        Tree {
+            root: self.root.clone(),
+            resizer: self.resizer.clone(),
+        }
+    }
+}
+
+impl<V> Default for Tree<V> {
+    fn default() -> Self {
+        Tree {
+            root: None,
+            resizer: Arc::default(),
+        }
+    }
+}
+
+impl<V> Tree<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty tree whose nodes change size at the thresholds of
+    /// `resizing` instead of the defaults. The trees derived from it keep
+    /// the thresholds and share the counts of [`Tree::resize_stats`].
+    pub fn with_resizing(resizing: Resizing) -> Result<Self> {
+        Ok(Tree {
+            root: None,
+            resizer: Arc::new(Resizer::new(resizing)?),
+        })
+    }
+
+    pub fn resizing(&self) -> Resizing {
+        self.resizer.resizing()
+    }
+
+    /// Returns how many nodes this tree and the trees it shares its
+    /// thresholds with converted between sizes.
+    pub fn resize_stats(&self) -> ResizeStats {
+        self.resizer.stats()
+    }
+
+    fn with_root(&self, root: Option<Arc<Node<V>>>) -> Self {
+        Tree {
+            root,
+            resizer: self.resizer.clone(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn root(&self) -> Option<&Arc<Node<V>>> {
+        self.root.as_ref()
+    }
+}
+
+impl<V: Clone> Tree<V> {
+    /// Returns the number of entries, estimated from the fan-out of inner
+    /// nodes with [`DEFAULT_PROBES`] random root-to-leaf descents.
+    pub fn cardinality(&self) -> Result<Cardinality> {
+        self.cardinality_with_probes(DEFAULT_PROBES)
+    }
+
+    /// Like [`Tree::cardinality`] with a configurable number of descents.
+    pub fn cardinality_with_probes(&self, probes: usize) -> Result<Cardinality> {
+        let Some(root) = &self.root else {
+            return Ok(Cardinality::Exact(0));
+        };
+        // Every branch of an inner node leads to at least one entry.
+        let lower =
+            (root.num_children() + root.header().map_or(1, |h| h.leaf().iter().len())) as u64;
+        let upper = if root.header().is_none() { 1 } else { u64::MAX };
+        cardinality::estimate(probes, lower, upper, |rng| {
+            let (mut weight, mut node) = (1.0, root);
+            while let Some(header) = node.header() {
+                let branches = node.num_children() + header.leaf().iter().len();
+                weight *= branches as f64;
+                let choice = rng.below(branches);
+                node = match node.children().nth(choice) {
+                    Some((_, child)) => child,
+                    None => header.leaf().expect("choice is within the branches"),
+                };
+            }
+            Ok(weight)
+        })
+    }
+
+    pub fn get(&self, key: impl AsRef<[u8]>) -> Option<&V> {
+        Node::get(self.root.as_ref()?, key.as_ref())
+    }
+
+    pub fn contains_key(&self, key: impl AsRef<[u8]>) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Counts the entries, which takes O(n) since nodes don't keep the sizes
+    /// of their subtrees; see [`Tree::cardinality`] for an estimate.
+    pub fn len(&self) -> u64 {
+        self.root.as_ref().map_or(0, |root| root.count_entries())
+    }
+
+    /// Returns the entries whose key starts with `prefix`, in key order.
+    pub fn scan_prefix(&self, prefix: impl AsRef<[u8]>) -> Vec<(&[u8], &V)> {
+        let mut out = Vec::new();
+        if let Some(root) = &self.root {
+            Node::scan_prefix(root, prefix.as_ref(), &mut out);
+        }
+        out
+    }
+
+    pub fn insert(&self, key: impl Into<Vec<u8>>, value: V) -> Self {
+        let key = key.into();
+        let root = match &self.root {
+            None => Node::leaf(key, value),
+            Some(root) => Node::insert(root, key, value, 0, &self.resizer),
+        };
+        self.with_root(Some(root))
+    }
+
+    /// Removes `key`. Deleting an absent key copies nothing and returns a
+    /// tree sharing this tree's root.
+    pub fn delete(&self, key: impl AsRef<[u8]>) -> Self {
+        let Some(root) = &self.root else {
+            return self.clone();
+        };
+        match Node::delete(root, key.as_ref(), 0, &self.resizer) {
+            Some(root) => self.with_root(root),
+            None => self.clone(),
+        }
+    }
+}
+
+impl<V: Clone> Map for Tree<V> {
+    type Key = [u8];
+    type Value = V;
+
+    fn get(&self, key: &[u8]) -> Result<Option<V>> {
+        Ok(Tree::get(self, key).cloned())
+    }
+
+    fn len(&self) -> Result<u64> {
+        Ok(Tree::len(self))
+    }
+
+    fn is_empty(&self) -> bool {
+        Tree::is_empty(self)
+    }
+}
+
+/// Commits only compute the root hash, since the tree is kept in memory.
+impl<V: Clone + Hashable> PersistentMap for Tree<V> {
+    fn insert(&mut self, key: &[u8], value: V) -> Result<()> {
+        *self = Tree::insert(self, key, value);
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        *self = Tree::delete(self, key);
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<Digest> {
+        self.merkle_hash()
+    }
+}
+
+impl<V: Clone + Hashable> MerkleTree for Tree<V> {
+    fn merkle_hash(&self) -> Result<Digest> {
+        Ok(self.root.as_ref().map_or(EMPTY_HASH, |root| root.hash()))
+    }
+}
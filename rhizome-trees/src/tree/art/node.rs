@@ -0,0 +1,827 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+
+use crate::tree::hash::{hash_of, hash_parts, Digest, HashCache, Hashable, EMPTY_HASH};
+use crate::{Error, Result};
+
+/// A node of an adaptive radix tree.
+///
+/// Inner nodes come in four sizes which are chosen by the number of children
+/// they hold, and every inner node stores a compressed path (the bytes shared
+/// by all keys below it) instead of a chain of single-child nodes.
+#[derive(Clone, Debug)]
+pub enum Node<V> {
+    Leaf(Leaf<V>),
+    Node4(Node4<V>),
+    Node16(Node16<V>),
+    Node48(Node48<V>),
+    Node256(Node256<V>),
+}
+
+/// An entry, stored with its full key so that a leaf can sit directly below
+/// the first byte in which its key differs from its siblings.
+#[derive(Clone, Debug)]
+pub struct Leaf<V> {
+    pub(crate) key: Vec<u8>,
+    pub(crate) value: V,
+    hash: HashCache,
+}
+
+/// The fields shared by every inner node.
+#[derive(Clone, Debug)]
+pub struct Header<V> {
+    /// The compressed path below the byte which led to this node.
+    pub(crate) prefix: Vec<u8>,
+    /// A [`Node::Leaf`] whose key ends at this node, i.e. which is a prefix of
+    /// every other key below it.
+    pub(crate) leaf: Option<Arc<Node<V>>>,
+    hash: HashCache,
+}
+
+/// An inner node holding up to `N` children in a sorted array of key bytes,
+/// used for the two smallest sizes.
+#[derive(Clone, Debug)]
+pub struct SortedNode<V, const N: usize> {
+    pub(crate) header: Header<V>,
+    len: usize,
+    keys: [u8; N],
+    children: [Option<Arc<Node<V>>>; N],
+}
+
+pub type Node4<V> = SortedNode<V, 4>;
+pub type Node16<V> = SortedNode<V, 16>;
+
+/// An inner node holding up to 48 children, indexed by a 256 entry table of
+/// slot numbers.
+#[derive(Clone, Debug)]
+pub struct Node48<V> {
+    pub(crate) header: Header<V>,
+    len: usize,
+    /// `index[b]` is one more than the slot of the child for byte `b`, or 0.
+    index: Box<[u8; 256]>,
+    children: Box<[Option<Arc<Node<V>>>; 48]>,
+}
+
+/// An inner node with a slot for every possible byte.
+#[derive(Clone, Debug)]
+pub struct Node256<V> {
+    pub(crate) header: Header<V>,
+    len: usize,
+    children: Box<[Option<Arc<Node<V>>>; 256]>,
+}
+
+/// The size transitions of inner nodes, which [`Resizing`] thresholds and
+/// [`ResizeStats`] counts are given for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(usize)]
+pub enum Transition {
+    /// Between [`Node4`] and [`Node16`].
+    Node4To16 = 0,
+    /// Between [`Node16`] and [`Node48`].
+    Node16To48 = 1,
+    /// Between [`Node48`] and [`Node256`].
+    Node48To256 = 2,
+}
+
+impl Transition {
+    const ALL: [Transition; 3] = [
+        Transition::Node4To16,
+        Transition::Node16To48,
+        Transition::Node48To256,
+    ];
+
+    /// The number of children the smaller size holds.
+    fn capacity(self) -> usize {
+        match self {
+            Transition::Node4To16 => 4,
+            Transition::Node16To48 => 16,
+            Transition::Node48To256 => 48,
+        }
+    }
+}
+
+/// When inner nodes change size, set builder-style starting from the
+/// defaults. A node grows when a child is added while it holds the `grow`
+/// threshold of its transition, and shrinks once it holds the `shrink`
+/// threshold or fewer. The shrink threshold must be below the grow
+/// threshold, so alternating inserts and deletes around a boundary don't
+/// convert the node every time.
+///
+/// Growing before a node is full trades memory for faster child lookups,
+/// e.g. for namespaced keys where a few nodes fan out widely.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Resizing {
+    grow: [usize; 3],
+    shrink: [usize; 3],
+}
+
+/// Nodes grow once full and shrink well below the capacity of the smaller
+/// size.
+impl Default for Resizing {
+    fn default() -> Self {
+        Resizing {
+            grow: [4, 16, 48],
+            shrink: [3, 12, 37],
+        }
+    }
+}
+
+impl Resizing {
+    /// Grows nodes of the smaller size of `transition` when a child is added
+    /// while they hold `children`, which is at most their capacity.
+    pub fn grow_at(mut self, transition: Transition, children: usize) -> Self {
+        self.grow[transition as usize] = children;
+        self
+    }
+
+    /// Shrinks nodes of the larger size of `transition` once they hold
+    /// `children` or fewer.
+    pub fn shrink_at(mut self, transition: Transition, children: usize) -> Self {
+        self.shrink[transition as usize] = children;
+        self
+    }
+
+    /// Checks that every grow threshold fits the smaller size and is above
+    /// its shrink threshold.
+    pub fn validate(&self) -> Result<()> {
+        for transition in Transition::ALL {
+            let (grow, shrink) = (
+                self.grow[transition as usize],
+                self.shrink[transition as usize],
+            );
+            if grow == 0 || grow > transition.capacity() {
+                return Err(Error::invalid(format!(
+                    "{:?} grow threshold {} is out of range",
+                    transition, grow
+                )));
+            }
+            if shrink >= grow {
+                return Err(Error::invalid(format!(
+                    "{:?} shrink threshold {} is not below the grow threshold {}",
+                    transition, shrink, grow
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How many nodes a tree converted, by [`Transition`].
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct ResizeStats {
+    pub grown: [u64; 3],
+    pub shrunk: [u64; 3],
+}
+
+/// The validated [`Resizing`] of a tree along with its conversion counts,
+/// shared by the versions of the tree.
+#[derive(Debug, Default)]
+pub(crate) struct Resizer {
+    resizing: Resizing,
+    grown: [AtomicU64; 3],
+    shrunk: [AtomicU64; 3],
+}
+
+impl Resizer {
+    pub(crate) fn new(resizing: Resizing) -> Result<Self> {
+        resizing.validate()?;
+        Ok(Resizer {
+            resizing,
+            ..Resizer::default()
+        })
+    }
+
+    pub(crate) fn resizing(&self) -> Resizing {
+        self.resizing
+    }
+
+    pub(crate) fn stats(&self) -> ResizeStats {
+        ResizeStats {
+            grown: self.grown.each_ref().map(|count| count.load(Relaxed)),
+            shrunk: self.shrunk.each_ref().map(|count| count.load(Relaxed)),
+        }
+    }
+
+    fn grows(&self, transition: Transition, children: usize) -> bool {
+        let grows = children >= self.resizing.grow[transition as usize];
+        if grows {
+            self.grown[transition as usize].fetch_add(1, Relaxed);
+        }
+        grows
+    }
+
+    fn shrinks(&self, transition: Transition, children: usize) -> bool {
+        let shrinks = children <= self.resizing.shrink[transition as usize];
+        if shrinks {
+            self.shrunk[transition as usize].fetch_add(1, Relaxed);
+        }
+        shrinks
+    }
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+impl<V> Header<V> {
+    pub(crate) fn new(prefix: Vec<u8>) -> Self {
+        Header {
+            prefix,
+            leaf: None,
+            hash: HashCache::default(),
+        }
+    }
+
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    pub fn leaf(&self) -> Option<&Arc<Node<V>>> {
+        self.leaf.as_ref()
+    }
+}
+
+impl<V> Leaf<V> {
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+}
+
+impl<V: Clone> Leaf<V> {
+    /// Inserts an entry next to the leaf `this` at `depth`. If the keys differ,
+    /// the leaf is replaced by a [`Node4`] whose prefix is the part of both
+    /// keys they share after `depth`.
+    pub(crate) fn insert(
+        this: &Arc<Node<V>>,
+        key: Vec<u8>,
+        value: V,
+        depth: usize,
+    ) -> Arc<Node<V>> {
+        let Node::Leaf(leaf) = &**this else {
+            unreachable!("Leaf::insert called on an inner node")
+        };
+        if leaf.key == key {
+            return Node::leaf(key, value);
+        }
+        let shared = common_prefix(&leaf.key[depth..], &key[depth..]);
+        let depth = depth + shared;
+        let mut node = Node4::new(Header::new(key[depth - shared..depth].to_vec()));
+        node.place_leaf(this.clone(), depth);
+        node.place_leaf(Node::leaf(key, value), depth);
+        Arc::new(Node::Node4(node))
+    }
+}
+
+impl<V, const N: usize> SortedNode<V, N> {
+    pub(crate) fn new(header: Header<V>) -> Self {
+        SortedNode {
+            header,
+            len: 0,
+            keys: [0; N],
+            children: std::array::from_fn(|_| None),
+        }
+    }
+
+    fn position(&self, byte: u8) -> Result<usize, usize> {
+        self.keys[..self.len].binary_search(&byte)
+    }
+
+    pub(crate) fn find(&self, byte: u8) -> Option<&Arc<Node<V>>> {
+        self.position(byte)
+            .ok()
+            .and_then(|i| self.children[i].as_ref())
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (u8, &Arc<Node<V>>)> {
+        self.keys[..self.len]
+            .iter()
+            .copied()
+            .zip(self.children[..self.len].iter().flatten())
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Sets the child for `byte`, which must either already be present or the
+    /// node must not be full.
+    pub(crate) fn set(&mut self, byte: u8, child: Arc<Node<V>>) {
+        match self.position(byte) {
+            Ok(i) => self.children[i] = Some(child),
+            Err(i) => {
+                assert!(!self.is_full(), "SortedNode::set on a full node");
+                self.keys.copy_within(i..self.len, i + 1);
+                self.children[i..=self.len].rotate_right(1);
+                self.keys[i] = byte;
+                self.children[i] = Some(child);
+                self.len += 1;
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, byte: u8) {
+        if let Ok(i) = self.position(byte) {
+            self.keys.copy_within(i + 1..self.len, i);
+            self.children[i..self.len].rotate_left(1);
+            self.children[self.len - 1] = None;
+            self.len -= 1;
+        }
+    }
+
+    /// Places a leaf below this node, where `depth` is the length of the key
+    /// consumed by this node and its prefix.
+    fn place_leaf(&mut self, leaf: Arc<Node<V>>, depth: usize) {
+        let Node::Leaf(l) = &*leaf else {
+            unreachable!("place_leaf called with an inner node")
+        };
+        match l.key.get(depth) {
+            None => self.header.leaf = Some(leaf),
+            Some(&byte) => self.set(byte, leaf),
+        }
+    }
+}
+
+impl<V> Node48<V> {
+    fn new(header: Header<V>) -> Self {
+        Node48 {
+            header,
+            len: 0,
+            index: Box::new([0; 256]),
+            children: Box::new(std::array::from_fn(|_| None)),
+        }
+    }
+
+    pub(crate) fn find(&self, byte: u8) -> Option<&Arc<Node<V>>> {
+        match self.index[byte as usize] {
+            0 => None,
+            slot => self.children[slot as usize - 1].as_ref(),
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (u8, &Arc<Node<V>>)> {
+        (0..=255u8).filter_map(move |byte| self.find(byte).map(|child| (byte, child)))
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == 48
+    }
+
+    pub(crate) fn set(&mut self, byte: u8, child: Arc<Node<V>>) {
+        match self.index[byte as usize] {
+            0 => {
+                assert!(!self.is_full(), "Node48::set on a full node");
+                let slot = self
+                    .children
+                    .iter()
+                    .position(Option::is_none)
+                    .expect("a non-full Node48 has a free slot");
+                self.children[slot] = Some(child);
+                self.index[byte as usize] = slot as u8 + 1;
+                self.len += 1;
+            }
+            slot => self.children[slot as usize - 1] = Some(child),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, byte: u8) {
+        let slot = std::mem::take(&mut self.index[byte as usize]);
+        if slot != 0 {
+            self.children[slot as usize - 1] = None;
+            self.len -= 1;
+        }
+    }
+}
+
+impl<V> Node256<V> {
+    fn new(header: Header<V>) -> Self {
+        Node256 {
+            header,
+            len: 0,
+            children: Box::new(std::array::from_fn(|_| None)),
+        }
+    }
+
+    pub(crate) fn find(&self, byte: u8) -> Option<&Arc<Node<V>>> {
+        self.children[byte as usize].as_ref()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (u8, &Arc<Node<V>>)> {
+        (0..=255u8).filter_map(move |byte| self.find(byte).map(|child| (byte, child)))
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn set(&mut self, byte: u8, child: Arc<Node<V>>) {
+        if self.children[byte as usize].replace(child).is_none() {
+            self.len += 1;
+        }
+    }
+
+    pub(crate) fn remove(&mut self, byte: u8) {
+        if self.children[byte as usize].take().is_some() {
+            self.len -= 1;
+        }
+    }
+}
+
+impl<V> Node<V> {
+    pub(crate) fn leaf(key: Vec<u8>, value: V) -> Arc<Self> {
+        Arc::new(Node::Leaf(Leaf {
+            key,
+            value,
+            hash: HashCache::default(),
+        }))
+    }
+}
+
+const LEAF_TAG: u8 = 0;
+const INNER_TAG: u8 = 1;
+
+impl<V: Clone + Hashable> Node<V> {
+    /// Returns the merkle hash of the subtree rooted at this node.
+    ///
+    /// A leaf hashes as `SHA-256(0 || H(key) || H(value))`. An inner node
+    /// hashes as `SHA-256(1 || len(prefix) || prefix || leaf || children)`
+    /// where `len(prefix)` is a big-endian `u32`, `leaf` is the hash of the
+    /// entry ending at the node or [`EMPTY_HASH`], and `children` is each
+    /// child's key byte followed by its hash, in byte order. The node size
+    /// isn't part of the hash, so equal maps have equal hashes regardless of
+    /// the order of the operations which built them.
+    pub fn hash(&self) -> Digest {
+        match self {
+            Node::Leaf(leaf) => leaf.hash.get_or_init(|| {
+                hash_parts(&[
+                    &[LEAF_TAG],
+                    &hash_of(leaf.key.as_slice()),
+                    &hash_of(&leaf.value),
+                ])
+            }),
+            inner => {
+                let header = inner.header().expect("inner nodes have a header");
+                header.hash.get_or_init(|| {
+                    let mut buf = Vec::with_capacity(
+                        1 + 4 + header.prefix.len() + 32 + 33 * inner.num_children(),
+                    );
+                    buf.push(INNER_TAG);
+                    buf.extend_from_slice(&(header.prefix.len() as u32).to_be_bytes());
+                    buf.extend_from_slice(&header.prefix);
+                    buf.extend_from_slice(
+                        &header.leaf.as_ref().map_or(EMPTY_HASH, |leaf| leaf.hash()),
+                    );
+                    for (byte, child) in inner.children() {
+                        buf.push(byte);
+                        buf.extend_from_slice(&child.hash());
+                    }
+                    hash_parts(&[&buf])
+                })
+            }
+        }
+    }
+}
+
+impl<V: Clone> Node<V> {
+    pub fn header(&self) -> Option<&Header<V>> {
+        match self {
+            Node::Leaf(_) => None,
+            Node::Node4(n) => Some(&n.header),
+            Node::Node16(n) => Some(&n.header),
+            Node::Node48(n) => Some(&n.header),
+            Node::Node256(n) => Some(&n.header),
+        }
+    }
+
+    fn header_mut(&mut self) -> Option<&mut Header<V>> {
+        match self {
+            Node::Leaf(_) => None,
+            Node::Node4(n) => Some(&mut n.header),
+            Node::Node16(n) => Some(&mut n.header),
+            Node::Node48(n) => Some(&mut n.header),
+            Node::Node256(n) => Some(&mut n.header),
+        }
+    }
+
+    pub fn find_child(&self, byte: u8) -> Option<&Arc<Node<V>>> {
+        match self {
+            Node::Leaf(_) => None,
+            Node::Node4(n) => n.find(byte),
+            Node::Node16(n) => n.find(byte),
+            Node::Node48(n) => n.find(byte),
+            Node::Node256(n) => n.find(byte),
+        }
+    }
+
+    pub fn num_children(&self) -> usize {
+        match self {
+            Node::Leaf(_) => 0,
+            Node::Node4(n) => n.len(),
+            Node::Node16(n) => n.len(),
+            Node::Node48(n) => n.len(),
+            Node::Node256(n) => n.len(),
+        }
+    }
+
+    /// Counts the entries of the subtree rooted at this node.
+    pub(crate) fn count_entries(&self) -> u64 {
+        match self.header() {
+            None => 1,
+            Some(header) => {
+                header.leaf().map_or(0, |leaf| leaf.count_entries())
+                    + self
+                        .children()
+                        .map(|(_, child)| child.count_entries())
+                        .sum::<u64>()
+            }
+        }
+    }
+
+    /// Returns the children of an inner node in key byte order.
+    pub fn children(&self) -> Box<dyn Iterator<Item = (u8, &Arc<Node<V>>)> + '_> {
+        match self {
+            Node::Leaf(_) => Box::new(std::iter::empty()),
+            Node::Node4(n) => Box::new(n.iter()),
+            Node::Node16(n) => Box::new(n.iter()),
+            Node::Node48(n) => Box::new(n.iter()),
+            Node::Node256(n) => Box::new(n.iter()),
+        }
+    }
+
+    /// Sets the child for `byte` in an inner node, promoting the node to the
+    /// next larger size if it reached its grow threshold.
+    fn set_child(&mut self, byte: u8, child: Arc<Node<V>>, r: &Resizer) {
+        if self.find_child(byte).is_none() {
+            self.grow_if_crowded(r);
+        }
+        match self {
+            Node::Leaf(_) => unreachable!("set_child called on a leaf"),
+            Node::Node4(n) => n.set(byte, child),
+            Node::Node16(n) => n.set(byte, child),
+            Node::Node48(n) => n.set(byte, child),
+            Node::Node256(n) => n.set(byte, child),
+        }
+    }
+
+    fn grow_if_crowded(&mut self, r: &Resizer) {
+        let grown = match self {
+            Node::Node4(n) if r.grows(Transition::Node4To16, n.len()) => {
+                let mut grown = Node16::new(n.header.clone());
+                n.iter()
+                    .for_each(|(byte, child)| grown.set(byte, child.clone()));
+                Node::Node16(grown)
+            }
+            Node::Node16(n) if r.grows(Transition::Node16To48, n.len()) => {
+                let mut grown = Node48::new(n.header.clone());
+                n.iter()
+                    .for_each(|(byte, child)| grown.set(byte, child.clone()));
+                Node::Node48(grown)
+            }
+            Node::Node48(n) if r.grows(Transition::Node48To256, n.len()) => {
+                let mut grown = Node256::new(n.header.clone());
+                n.iter()
+                    .for_each(|(byte, child)| grown.set(byte, child.clone()));
+                Node::Node256(grown)
+            }
+            _ => return,
+        };
+        *self = grown;
+    }
+
+    fn remove_child(&mut self, byte: u8) {
+        match self {
+            Node::Leaf(_) => unreachable!("remove_child called on a leaf"),
+            Node::Node4(n) => n.remove(byte),
+            Node::Node16(n) => n.remove(byte),
+            Node::Node48(n) => n.remove(byte),
+            Node::Node256(n) => n.remove(byte),
+        }
+    }
+
+    fn shrink_if_sparse(&mut self, r: &Resizer) {
+        let shrunk = match self {
+            Node::Node16(n) if r.shrinks(Transition::Node4To16, n.len()) => {
+                let mut shrunk = Node4::new(n.header.clone());
+                n.iter()
+                    .for_each(|(byte, child)| shrunk.set(byte, child.clone()));
+                Node::Node4(shrunk)
+            }
+            Node::Node48(n) if r.shrinks(Transition::Node16To48, n.len()) => {
+                let mut shrunk = Node16::new(n.header.clone());
+                n.iter()
+                    .for_each(|(byte, child)| shrunk.set(byte, child.clone()));
+                Node::Node16(shrunk)
+            }
+            Node::Node256(n) if r.shrinks(Transition::Node48To256, n.len()) => {
+                let mut shrunk = Node48::new(n.header.clone());
+                n.iter()
+                    .for_each(|(byte, child)| shrunk.set(byte, child.clone()));
+                Node::Node48(shrunk)
+            }
+            _ => return,
+        };
+        *self = shrunk;
+    }
+
+    /// Restores the invariants of an inner node after one of its branches was
+    /// removed. A node left with a single branch is collapsed into it: a leaf
+    /// takes the node's place directly, while an inner child absorbs the
+    /// node's prefix and the byte leading to it into its own prefix.
+    fn collapse(mut self, r: &Resizer) -> Option<Arc<Node<V>>> {
+        let header = self.header().expect("inner nodes have a header");
+        match (self.num_children(), &header.leaf) {
+            (0, leaf) => leaf.clone(),
+            (1, None) => {
+                let (byte, child) = self.children().next().expect("one child");
+                if let Node::Leaf(_) = &**child {
+                    return Some(child.clone());
+                }
+                let mut prefix = header.prefix.clone();
+                prefix.push(byte);
+                let mut merged = (**child).clone();
+                let child_header = merged.header_mut().expect("inner nodes have a header");
+                prefix.extend_from_slice(&child_header.prefix);
+                child_header.prefix = prefix;
+                Some(Arc::new(merged))
+            }
+            _ => {
+                self.shrink_if_sparse(r);
+                Some(Arc::new(self))
+            }
+        }
+    }
+
+    pub(crate) fn get<'a>(mut node: &'a Arc<Node<V>>, key: &[u8]) -> Option<&'a V> {
+        let mut depth = 0;
+        loop {
+            let header = match &**node {
+                Node::Leaf(leaf) => return (leaf.key == key).then_some(&leaf.value),
+                inner => inner.header().expect("inner nodes have a header"),
+            };
+            if !key[depth..].starts_with(&header.prefix) {
+                return None;
+            }
+            depth += header.prefix.len();
+            match key.get(depth) {
+                None => {
+                    return match header.leaf.as_deref() {
+                        Some(Node::Leaf(leaf)) => Some(&leaf.value),
+                        _ => None,
+                    }
+                }
+                Some(&byte) => node = node.find_child(byte)?,
+            }
+            depth += 1;
+        }
+    }
+
+    /// Appends the entries whose key starts with `prefix` to `out`, in key
+    /// order.
+    pub(crate) fn scan_prefix<'a>(
+        mut node: &'a Arc<Node<V>>,
+        prefix: &[u8],
+        out: &mut Vec<(&'a [u8], &'a V)>,
+    ) {
+        let mut depth = 0;
+        loop {
+            let header = match &**node {
+                Node::Leaf(leaf) => {
+                    if leaf.key.starts_with(prefix) {
+                        out.push((&leaf.key, &leaf.value));
+                    }
+                    return;
+                }
+                inner => inner.header().expect("inner nodes have a header"),
+            };
+            let rest = &prefix[depth..];
+            if rest.len() <= header.prefix.len() {
+                if header.prefix.starts_with(rest) {
+                    node.collect(out);
+                }
+                return;
+            }
+            if !rest.starts_with(&header.prefix) {
+                return;
+            }
+            depth += header.prefix.len();
+            match node.find_child(prefix[depth]) {
+                Some(child) => node = child,
+                None => return,
+            }
+            depth += 1;
+        }
+    }
+
+    /// Appends every entry of the subtree to `out`, in key order.
+    fn collect<'a>(&'a self, out: &mut Vec<(&'a [u8], &'a V)>) {
+        match self {
+            Node::Leaf(leaf) => out.push((&leaf.key, &leaf.value)),
+            inner => {
+                // An entry ending at this node is a prefix of every key below.
+                if let Some(leaf) = inner.header().and_then(Header::leaf) {
+                    leaf.collect(out);
+                }
+                for (_, child) in inner.children() {
+                    child.collect(out);
+                }
+            }
+        }
+    }
+
+    /// Inserts an entry into the subtree `this`, whose parents have consumed
+    /// the first `depth` bytes of `key`.
+    pub(crate) fn insert(
+        this: &Arc<Node<V>>,
+        key: Vec<u8>,
+        value: V,
+        depth: usize,
+        r: &Resizer,
+    ) -> Arc<Node<V>> {
+        let header = match &**this {
+            Node::Leaf(_) => return Leaf::insert(this, key, value, depth),
+            inner => inner.header().expect("inner nodes have a header"),
+        };
+        let shared = common_prefix(&header.prefix, &key[depth..]);
+        if shared < header.prefix.len() {
+            // The key leaves the compressed path part way: split the path at
+            // the first differing byte.
+            let mut split = Node4::new(Header::new(header.prefix[..shared].to_vec()));
+            let mut rest = (**this).clone();
+            rest.header_mut().expect("inner nodes have a header").prefix =
+                header.prefix[shared + 1..].to_vec();
+            split.set(header.prefix[shared], Arc::new(rest));
+            split.place_leaf(Node::leaf(key, value), depth + shared);
+            return Arc::new(Node::Node4(split));
+        }
+        let depth = depth + shared;
+        let mut node = (**this).clone();
+        match key.get(depth) {
+            None => {
+                node.header_mut().expect("inner nodes have a header").leaf =
+                    Some(Node::leaf(key, value))
+            }
+            Some(&byte) => {
+                let child = match this.find_child(byte) {
+                    Some(child) => Node::insert(child, key, value, depth + 1, r),
+                    None => Node::leaf(key, value),
+                };
+                node.set_child(byte, child, r);
+            }
+        }
+        Arc::new(node)
+    }
+
+    /// Removes `key` from the subtree `this`, whose parents have consumed the
+    /// first `depth` bytes of it. Returns `None` if the key is not present,
+    /// and otherwise the new subtree, which is `None` if it became empty.
+    pub(crate) fn delete(
+        this: &Arc<Node<V>>,
+        key: &[u8],
+        depth: usize,
+        r: &Resizer,
+    ) -> Option<Option<Arc<Node<V>>>> {
+        let header = match &**this {
+            Node::Leaf(leaf) => return (leaf.key == key).then_some(None),
+            inner => inner.header().expect("inner nodes have a header"),
+        };
+        if !key[depth..].starts_with(&header.prefix) {
+            return None;
+        }
+        let depth = depth + header.prefix.len();
+        // Only copy the node once the key is known to be present below it.
+        match key.get(depth) {
+            None => {
+                header.leaf.as_ref()?;
+                let mut node = (**this).clone();
+                node.header_mut().expect("inner nodes have a header").leaf = None;
+                Some(node.collapse(r))
+            }
+            Some(&byte) => {
+                let child = Node::delete(this.find_child(byte)?, key, depth + 1, r)?;
+                let mut node = (**this).clone();
+                match child {
+                    Some(child) => {
+                        node.set_child(byte, child, r);
+                        Some(Some(Arc::new(node)))
+                    }
+                    None => {
+                        node.remove_child(byte);
+                        Some(node.collapse(r))
+                    }
+                }
+            }
+        }
+    }
+}
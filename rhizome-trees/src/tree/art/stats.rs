@@ -0,0 +1,109 @@
+//! A report of the shape of a tree, for catching key sets which the radix
+//! algorithms handle poorly, such as long chains of sparse nodes.
+//!
+//! The nodes of these trees are only held in memory, so unlike the
+//! [AVL report](crate::tree::avl::stats) there are no stored nodes or
+//! encoded sizes to count.
+
+use std::sync::Arc;
+
+use super::node::Node;
+use super::Tree;
+
+/// How full the inner nodes of one size are.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NodeTypeStats {
+    /// The number of children a node of this size holds at most.
+    pub capacity: u64,
+    pub nodes: u64,
+    /// The children of all nodes of this size, not counting the leaves
+    /// whose key ends at a node.
+    pub children: u64,
+}
+
+impl NodeTypeStats {
+    fn new(capacity: u64) -> Self {
+        NodeTypeStats {
+            capacity,
+            nodes: 0,
+            children: 0,
+        }
+    }
+
+    /// Returns the share of the slots of these nodes that hold a child, or
+    /// 0 if there are none.
+    pub fn fill_factor(&self) -> f64 {
+        if self.nodes == 0 {
+            return 0.0;
+        }
+        self.children as f64 / (self.nodes * self.capacity) as f64
+    }
+}
+
+/// The shape of a tree, see [`Tree::stats`].
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TreeStats {
+    /// The number of leaves, one per entry.
+    pub entries: u64,
+    /// The number of nodes, inner nodes and leaves.
+    pub nodes: u64,
+    /// The number of nodes on the longest path from the root to a leaf, or
+    /// 0 for an empty tree.
+    pub height: u32,
+    /// The mean number of nodes above a leaf, which is how many nodes a
+    /// lookup passes before reaching it.
+    pub average_depth: f64,
+    pub node4: NodeTypeStats,
+    pub node16: NodeTypeStats,
+    pub node48: NodeTypeStats,
+    pub node256: NodeTypeStats,
+}
+
+impl Default for TreeStats {
+    fn default() -> Self {
+        TreeStats {
+            entries: 0,
+            nodes: 0,
+            height: 0,
+            average_depth: 0.0,
+            node4: NodeTypeStats::new(4),
+            node16: NodeTypeStats::new(16),
+            node48: NodeTypeStats::new(48),
+            node256: NodeTypeStats::new(256),
+        }
+    }
+}
+
+impl<V: Clone> Tree<V> {
+    /// Returns the shape of the tree, visiting every node.
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats::default();
+        let mut total_depth = 0;
+        let mut pending: Vec<(&Arc<Node<V>>, u32)> =
+            Vec::from_iter(self.root.iter().map(|root| (root, 0)));
+        while let Some((node, depth)) = pending.pop() {
+            stats.nodes += 1;
+            stats.height = stats.height.max(depth + 1);
+            let Some(header) = node.header() else {
+                stats.entries += 1;
+                total_depth += depth as u64;
+                continue;
+            };
+            let kind = match &**node {
+                Node::Node4(_) => &mut stats.node4,
+                Node::Node16(_) => &mut stats.node16,
+                Node::Node48(_) => &mut stats.node48,
+                Node::Node256(_) => &mut stats.node256,
+                Node::Leaf(_) => unreachable!("leaves have no header"),
+            };
+            kind.nodes += 1;
+            kind.children += node.num_children() as u64;
+            pending.extend(header.leaf().map(|leaf| (leaf, depth + 1)));
+            pending.extend(node.children().map(|(_, child)| (child, depth + 1)));
+        }
+        if stats.entries > 0 {
+            stats.average_depth = total_depth as f64 / stats.entries as f64;
+        }
+        stats
+    }
+}
@@ -0,0 +1,259 @@
+//! Exporting a tree version to Arrow record batches and Parquet files, so
+//! that committed state can be queried with standard data tooling.
+//!
+//! Every entry becomes a row, in key order, with the columns
+//!
+//! ```text
+//! key         binary, the key's KeyCodec encoding
+//! value       binary, the value's ValueCodec encoding
+//! entry_hash  fixed size binary, if ExportOptions::entry_hashes is set
+//! ```
+//!
+//! The entry hash is the part of the node hash which commits to the entry:
+//! the 32 byte leaf hash with [`HashVersion::V1`], and the key hash followed
+//! by the value hash, 64 bytes, with [`HashVersion::V0`]. The schema's
+//! metadata records the hash version as `rhizome.hash_version` and the root
+//! hash of the exported version, in hex, as `rhizome.root_hash`.
+//!
+//! Batches are built as the entries are read, so exporting a large stored
+//! tree only holds one batch at a time.
+
+use std::collections::HashMap;
+#[cfg(feature = "parquet")]
+use std::io::Write;
+use std::sync::Arc;
+
+use arrow_array::builder::{BinaryBuilder, FixedSizeBinaryBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+
+use super::node::{EntryHash, Manager};
+use super::range::Range;
+use super::Tree;
+use crate::tree::hash::{HashVersion, Hashable, MerkleTree};
+use crate::tree::value::{KeyCodec, ValueCodec};
+use crate::{Error, Result};
+
+/// The key and value bytes after which a batch is cut short of
+/// [`ExportOptions::batch_rows`], well below the 2 GiB a binary column holds.
+const BATCH_BYTES: usize = 64 << 20;
+
+/// Parameters of [`Tree::record_batches`] and [`Tree::export_parquet`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ExportOptions {
+    batch_rows: usize,
+    entry_hashes: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            batch_rows: 8192,
+            entry_hashes: false,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// Sets the number of rows per batch, 8192 by default. Batches whose
+    /// keys and values take more than 64 MiB are cut short. Values below 1
+    /// are raised to 1.
+    pub fn batch_rows(self, batch_rows: usize) -> Self {
+        ExportOptions {
+            batch_rows: batch_rows.max(1),
+            ..self
+        }
+    }
+
+    /// Sets whether to add the `entry_hash` column, off by default.
+    pub fn entry_hashes(self, entry_hashes: bool) -> Self {
+        ExportOptions {
+            entry_hashes,
+            ..self
+        }
+    }
+}
+
+impl<K: KeyCodec + Clone + Hashable, V: ValueCodec + Hashable> Tree<K, V> {
+    /// Returns the schema of the batches [`Tree::record_batches`] yields,
+    /// see the [module documentation](self).
+    pub fn arrow_schema(&self, options: &ExportOptions) -> Result<SchemaRef> {
+        let hash_version = self.export_hash_version()?;
+        let mut fields = vec![
+            Field::new("key", DataType::Binary, false),
+            Field::new("value", DataType::Binary, false),
+        ];
+        if options.entry_hashes {
+            fields.push(Field::new(
+                "entry_hash",
+                DataType::FixedSizeBinary(entry_hash_width(hash_version)),
+                false,
+            ));
+        }
+        let root_hash: String = self
+            .merkle_hash()?
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let metadata = HashMap::from([
+            (
+                "rhizome.hash_version".to_string(),
+                (hash_version as u8).to_string(),
+            ),
+            ("rhizome.root_hash".to_string(), root_hash),
+        ]);
+        Ok(Arc::new(Schema::new_with_metadata(fields, metadata)))
+    }
+
+    /// Returns the entries of this version as Arrow record batches in key
+    /// order, see the [module documentation](self). An empty tree yields no
+    /// batches.
+    pub fn record_batches(&self, options: ExportOptions) -> Result<RecordBatches<'_, K, V>> {
+        let hash_width = match options.entry_hashes {
+            true => Some(entry_hash_width(self.export_hash_version()?)),
+            false => None,
+        };
+        Ok(RecordBatches {
+            manager: &self.manager,
+            entries: self.iter()?,
+            schema: self.arrow_schema(&options)?,
+            batch_rows: options.batch_rows,
+            hash_width,
+            failed: false,
+        })
+    }
+
+    /// Writes the entries of this version to `writer` as a Parquet file with
+    /// a row group per batch of [`Tree::record_batches`], and the metadata of
+    /// the schema as the file's key-value metadata. Returns the number of
+    /// rows written.
+    #[cfg(feature = "parquet")]
+    pub fn export_parquet(&self, writer: impl Write + Send, options: ExportOptions) -> Result<u64> {
+        use parquet::arrow::ArrowWriter;
+        use parquet::file::metadata::KeyValue;
+        use parquet::file::properties::WriterProperties;
+
+        let batches = self.record_batches(options)?;
+        let schema = batches.schema();
+        // Copied to the file's own metadata too, for readers ignoring the
+        // Arrow schema.
+        let metadata = schema
+            .metadata()
+            .iter()
+            .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+            .collect();
+        let properties = WriterProperties::builder()
+            .set_key_value_metadata(Some(metadata))
+            .build();
+        let mut writer = ArrowWriter::try_new(writer, schema, Some(properties))?;
+        let mut rows = 0;
+        for batch in batches {
+            let batch = batch?;
+            writer.write(&batch)?;
+            writer.flush()?;
+            rows += batch.num_rows() as u64;
+        }
+        writer.close()?;
+        Ok(rows)
+    }
+
+    /// The hash version of the nodes of this version, that of the manager
+    /// for an empty tree.
+    fn export_hash_version(&self) -> Result<HashVersion> {
+        Ok(match &self.root {
+            None => self.manager.hash_version(),
+            Some(root) => self.manager.read(root)?.hash_version,
+        })
+    }
+}
+
+fn entry_hash_width(hash_version: HashVersion) -> i32 {
+    match hash_version {
+        HashVersion::V0 => 64,
+        HashVersion::V1 => 32,
+    }
+}
+
+/// An iterator over the entries of a tree version as Arrow record batches,
+/// created by [`Tree::record_batches`].
+///
+/// An error reading a node or building a batch is yielded once, after which
+/// the iterator ends.
+pub struct RecordBatches<'a, K, V> {
+    manager: &'a Manager<K, V>,
+    entries: Range<'a, K, V>,
+    schema: SchemaRef,
+    batch_rows: usize,
+    /// The width of the entry hash column, if there is one.
+    hash_width: Option<i32>,
+    failed: bool,
+}
+
+impl<K, V> RecordBatches<'_, K, V> {
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+impl<K: KeyCodec + Hashable, V: ValueCodec + Hashable> RecordBatches<'_, K, V> {
+    /// Builds the next batch, `None` once every entry is exported.
+    fn next_batch(&mut self) -> Result<Option<RecordBatch>> {
+        let mut keys = BinaryBuilder::new();
+        let mut values = BinaryBuilder::new();
+        let mut hashes = self
+            .hash_width
+            .map(|width| FixedSizeBinaryBuilder::with_capacity(self.batch_rows, width));
+        let (mut rows, mut bytes) = (0, 0);
+        let mut buf = Vec::new();
+        while rows < self.batch_rows && bytes < BATCH_BYTES {
+            let Some(node) = self.entries.next() else {
+                break;
+            };
+            let node = node?;
+            buf.clear();
+            node.key().encode(&mut buf);
+            bytes += buf.len();
+            keys.append_value(&buf);
+            buf.clear();
+            node.value().encode(&mut buf);
+            if buf.len() > i32::MAX as usize {
+                return Err(Error::codec(format!(
+                    "a value of {} bytes doesn't fit a binary column",
+                    buf.len()
+                )));
+            }
+            bytes += buf.len();
+            values.append_value(&buf);
+            if let Some(hashes) = &mut hashes {
+                match node.entry_hash_in(self.manager) {
+                    EntryHash::Leaf(leaf) => hashes.append_value(leaf)?,
+                    EntryHash::Split { key, value } => {
+                        hashes.append_value([key, value].concat())?
+                    }
+                }
+            }
+            rows += 1;
+        }
+        if rows == 0 {
+            return Ok(None);
+        }
+        let mut columns: Vec<ArrayRef> = vec![Arc::new(keys.finish()), Arc::new(values.finish())];
+        if let Some(mut hashes) = hashes {
+            columns.push(Arc::new(hashes.finish()));
+        }
+        Ok(Some(RecordBatch::try_new(self.schema.clone(), columns)?))
+    }
+}
+
+impl<K: KeyCodec + Hashable, V: ValueCodec + Hashable> Iterator for RecordBatches<'_, K, V> {
+    type Item = Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let batch = self.next_batch();
+        self.failed = batch.is_err();
+        batch.transpose()
+    }
+}
@@ -0,0 +1,66 @@
+//! Checks that the encodings of a tree's keys sort like the keys.
+//!
+//! Trees order keys by their [`Ord`] implementation, while byte-ordered
+//! stores, snapshots and proofs order them by their [`KeyCodec`] encodings.
+//! A codec which doesn't preserve the order, such as one writing integers
+//! little-endian, goes unnoticed until range scans over the encodings skip
+//! or repeat entries. [`Tree::audit_key_order`] compares the encodings of
+//! sampled neighboring keys of a tree to catch that.
+
+use super::Tree;
+use crate::tree::value::KeyCodec;
+use crate::Result;
+
+/// Two keys of a tree whose encodings don't sort like the keys: `lower`
+/// sorts before `higher`, but its encoding doesn't sort before theirs.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MisorderedKeys<K> {
+    pub lower: K,
+    pub higher: K,
+}
+
+/// The result of [`Tree::audit_key_order`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct KeyOrderReport<K> {
+    /// The number of pairs of neighboring keys compared.
+    pub pairs: u64,
+    pub misordered: Vec<MisorderedKeys<K>>,
+}
+
+impl<K> KeyOrderReport<K> {
+    /// Returns whether every compared pair was in order.
+    pub fn is_consistent(&self) -> bool {
+        self.misordered.is_empty()
+    }
+}
+
+impl<K: KeyCodec + Clone, V> Tree<K, V> {
+    /// Compares the encodings of up to `samples` pairs of neighboring keys,
+    /// spread evenly over the tree, with [`KeyCodec::compare`], and reports
+    /// the pairs it finds out of order. Equal encodings of different keys
+    /// count as out of order. Comparing neighbors catches a codec which
+    /// misorders the keys of a tree wherever the samples fall, and a sample
+    /// per entry checks the whole tree.
+    pub fn audit_key_order(&self, samples: u64) -> Result<KeyOrderReport<K>> {
+        let pairs = self.len()?.saturating_sub(1);
+        let mut report = KeyOrderReport {
+            pairs: pairs.min(samples),
+            misordered: Vec::new(),
+        };
+        for i in 0..report.pairs {
+            // Evenly spaced, and every pair once there are as many samples.
+            let index = (i as u128 * pairs as u128 / report.pairs as u128) as u64;
+            let (Some(lower), Some(higher)) = (self.nth(index)?, self.nth(index + 1)?) else {
+                break;
+            };
+            if K::compare(&lower.key().to_encoded(), &higher.key().to_encoded()).is_lt() {
+                continue;
+            }
+            report.misordered.push(MisorderedKeys {
+                lower: lower.key().clone(),
+                higher: higher.key().clone(),
+            });
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,122 @@
+//! A [Borsh](https://borsh.io) encoding of stored nodes.
+//!
+//! [`BorshCodec`] writes a node with Borsh's rules: little-endian
+//! integers, a `u8` tag of 0 or 1 before an optional field, and a `u32`
+//! length before a byte string. The layout is stable:
+//!
+//! ```text
+//! key           K in Borsh
+//! value         V in Borsh
+//! height        u8
+//! size          u64
+//! left          Option<Vec<u8>>, the bytes of the pointer to the left child
+//! right         Option<Vec<u8>>, the same for the right child
+//! hash_version  u8
+//! hash          [u8; 32], the merkle hash of the subtree
+//! ```
+//!
+//! Every field is required and decoding rejects trailing bytes, invalid tags
+//! and unknown hash versions, so each node has exactly one encoding as long
+//! as the encodings of its keys and values are canonical too.
+
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use std::sync::OnceLock;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use super::node::{Link, Node};
+use crate::tree::hash::{Digest, HashVersion};
+use crate::tree::node_manager::{NodeCodec, NodeRef, Ptr};
+use crate::{Error, Result};
+
+/// Encodes AVL nodes in the layout of the [module documentation](self).
+pub struct BorshCodec<K, V>(PhantomData<fn() -> (K, V)>);
+
+impl<K, V> BorshCodec<K, V> {
+    pub fn new() -> Self {
+        BorshCodec(PhantomData)
+    }
+}
+
+impl<K, V> Default for BorshCodec<K, V> {
+    fn default() -> Self {
+        BorshCodec::new()
+    }
+}
+
+impl<K, V> Clone for BorshCodec<K, V> {
+    fn clone(&self) -> Self {
+        BorshCodec::new()
+    }
+}
+
+impl<K, V> NodeCodec<Node<K, V>> for BorshCodec<K, V>
+where
+    K: BorshSerialize + BorshDeserialize,
+    V: BorshSerialize + BorshDeserialize,
+{
+    fn encode(&self, node: &Node<K, V>, buf: &mut Vec<u8>) -> Result<()> {
+        let hash = node
+            .hash
+            .get()
+            .ok_or_else(|| Error::codec("can't encode a node before it is hashed"))?;
+        node.key.serialize(buf)?;
+        node.value.serialize(buf)?;
+        node.height.serialize(buf)?;
+        node.size.serialize(buf)?;
+        write_link(&node.left, buf)?;
+        write_link(&node.right, buf)?;
+        (node.hash_version as u8).serialize(buf)?;
+        hash.serialize(buf)?;
+        Ok(())
+    }
+
+    fn decode(&self, mut bytes: &[u8]) -> Result<Node<K, V>> {
+        let reader = &mut bytes;
+        let key = K::deserialize_reader(reader).map_err(Error::codec)?;
+        let value = V::deserialize_reader(reader).map_err(Error::codec)?;
+        let height = u8::deserialize_reader(reader).map_err(Error::codec)?;
+        let size = u64::deserialize_reader(reader).map_err(Error::codec)?;
+        let left = read_link(reader)?;
+        let right = read_link(reader)?;
+        let version = u8::deserialize_reader(reader).map_err(Error::codec)?;
+        let hash_version = HashVersion::from_u8(version)
+            .ok_or_else(|| Error::codec(format!("unknown hash version {}", version)))?;
+        let hash = Digest::deserialize_reader(reader).map_err(Error::codec)?;
+        if !reader.is_empty() {
+            return Err(Error::codec(format!(
+                "{} trailing bytes after the node",
+                reader.len()
+            )));
+        }
+        Ok(Node {
+            key,
+            value,
+            height,
+            size,
+            left,
+            right,
+            hash: OnceLock::from(hash),
+            hash_version,
+        })
+    }
+}
+
+fn write_link<K, V>(link: &Link<K, V>, writer: &mut impl Write) -> Result<()> {
+    let ptr = match link {
+        None => None,
+        Some(NodeRef::Stored(ptr)) => Some(ptr.as_bytes().to_vec()),
+        Some(NodeRef::Mem(_)) => {
+            return Err(Error::codec("can't encode a node with an unsaved child"))
+        }
+    };
+    Ok(ptr.serialize(writer)?)
+}
+
+fn read_link<K, V>(reader: &mut impl Read) -> Result<Link<K, V>> {
+    match Option::<Vec<u8>>::deserialize_reader(reader).map_err(Error::codec)? {
+        None => Ok(None),
+        Some(bytes) => Ok(Some(NodeRef::Stored(Ptr::new(&bytes)?))),
+    }
+}
@@ -0,0 +1,205 @@
+//! Computing the changes between two versions of a tree.
+//!
+//! Subtrees shared by both versions are skipped without being visited, so
+//! diffing a version against its parent costs time proportional to the number
+//! of modified nodes rather than the size of the tree.
+
+use crate::tree::node_manager::{NodeHandle, NodeRef};
+
+use super::node::{Link, Manager, Node};
+use crate::Result;
+
+/// A single entry-level change between a base and a new version of a tree.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Change<K, V> {
+    /// `key` is absent in the base version.
+    Create { key: K, value: V },
+    /// `key` is present in both versions with different values.
+    Update { key: K, old: V, new: V },
+    /// `key` is absent in the new version.
+    Delete { key: K, old: V },
+}
+
+impl<K, V> Change<K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            Change::Create { key, .. }
+            | Change::Update { key, .. }
+            | Change::Delete { key, .. } => key,
+        }
+    }
+}
+
+/// How a key whose value differs between the two versions is reported.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OverwriteEvents {
+    /// A single [`Change::Update`] carrying both values.
+    #[default]
+    Update,
+    /// A [`Change::Delete`] of the old value followed by a [`Change::Create`]
+    /// of the new one, for consumers which only understand inserts and
+    /// removals.
+    DeleteThenCreate,
+}
+
+enum Item<K, V> {
+    Subtree(NodeRef<Node<K, V>>),
+    Entry(NodeHandle<Node<K, V>>),
+}
+
+/// An iterator over the [`Change`]s between two trees in key order, created by
+/// [`Tree::diff`](super::Tree::diff).
+pub struct Diff<'a, K, V> {
+    base_manager: &'a Manager<K, V>,
+    new_manager: &'a Manager<K, V>,
+    base: Vec<Item<K, V>>,
+    new: Vec<Item<K, V>>,
+    overwrite: OverwriteEvents,
+    pending: Option<Change<K, V>>,
+}
+
+impl<'a, K, V> Diff<'a, K, V> {
+    pub(crate) fn new(
+        (base_manager, base): (&'a Manager<K, V>, &Link<K, V>),
+        (new_manager, new): (&'a Manager<K, V>, &Link<K, V>),
+        overwrite: OverwriteEvents,
+    ) -> Self {
+        Diff {
+            base_manager,
+            new_manager,
+            base: base.iter().cloned().map(Item::Subtree).collect(),
+            new: new.iter().cloned().map(Item::Subtree).collect(),
+            overwrite,
+            pending: None,
+        }
+    }
+
+    /// Whether the subtrees on top of both stacks are the same node. Stored
+    /// nodes can only be compared if both trees use the same node manager.
+    fn same_subtree(&self) -> bool {
+        match (self.base.last(), self.new.last()) {
+            (Some(Item::Subtree(a)), Some(Item::Subtree(b))) => match (a, b) {
+                (NodeRef::Stored(_), NodeRef::Stored(_)) => {
+                    std::ptr::eq(self.base_manager, self.new_manager) && a.same_node(b)
+                }
+                _ => a.same_node(b),
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Replaces the subtree on top of `stack` with its left subtree, its entry and
+/// its right subtree, keeping the smallest item on top.
+fn expand<K, V>(m: &Manager<K, V>, stack: &mut Vec<Item<K, V>>) -> Result<()> {
+    if let Some(Item::Subtree(node)) = stack.pop() {
+        let node = m.read(&node)?;
+        stack.extend(node.right.iter().cloned().map(Item::Subtree));
+        let left = node.left.clone();
+        stack.push(Item::Entry(node));
+        stack.extend(left.into_iter().map(Item::Subtree));
+    }
+    Ok(())
+}
+
+fn subtree_height<K, V>(m: &Manager<K, V>, stack: &[Item<K, V>]) -> Result<u8> {
+    match stack.last() {
+        Some(Item::Subtree(node)) => Ok(m.read(node)?.height),
+        _ => Ok(0),
+    }
+}
+
+impl<K: Ord + Clone, V: Clone + PartialEq> Diff<'_, K, V> {
+    fn next_change(&mut self) -> Result<Option<Change<K, V>>> {
+        if let Some(change) = self.pending.take() {
+            return Ok(Some(change));
+        }
+        loop {
+            if self.same_subtree() {
+                self.base.pop();
+                self.new.pop();
+                continue;
+            }
+            match (self.base.last(), self.new.last()) {
+                (None, None) => return Ok(None),
+                (Some(Item::Subtree(_)), Some(Item::Subtree(_))) => {
+                    if subtree_height(self.base_manager, &self.base)?
+                        >= subtree_height(self.new_manager, &self.new)?
+                    {
+                        expand(self.base_manager, &mut self.base)?
+                    } else {
+                        expand(self.new_manager, &mut self.new)?
+                    }
+                }
+                (Some(Item::Subtree(_)), _) => expand(self.base_manager, &mut self.base)?,
+                (_, Some(Item::Subtree(_))) => expand(self.new_manager, &mut self.new)?,
+                (Some(Item::Entry(a)), Some(Item::Entry(b))) => match a.key.cmp(&b.key) {
+                    std::cmp::Ordering::Less => {
+                        let change = deleted(a);
+                        self.base.pop();
+                        return Ok(Some(change));
+                    }
+                    std::cmp::Ordering::Greater => {
+                        let change = created(b);
+                        self.new.pop();
+                        return Ok(Some(change));
+                    }
+                    std::cmp::Ordering::Equal => {
+                        let change = if a.value == b.value {
+                            None
+                        } else {
+                            Some(match self.overwrite {
+                                OverwriteEvents::Update => Change::Update {
+                                    key: b.key.clone(),
+                                    old: a.value.clone(),
+                                    new: b.value.clone(),
+                                },
+                                OverwriteEvents::DeleteThenCreate => {
+                                    self.pending = Some(created(b));
+                                    deleted(a)
+                                }
+                            })
+                        };
+                        self.base.pop();
+                        self.new.pop();
+                        if change.is_some() {
+                            return Ok(change);
+                        }
+                    }
+                },
+                (Some(Item::Entry(a)), None) => {
+                    let change = deleted(a);
+                    self.base.pop();
+                    return Ok(Some(change));
+                }
+                (None, Some(Item::Entry(b))) => {
+                    let change = created(b);
+                    self.new.pop();
+                    return Ok(Some(change));
+                }
+            }
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone + PartialEq> Iterator for Diff<'_, K, V> {
+    type Item = Result<Change<K, V>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_change().transpose()
+    }
+}
+
+fn created<K: Clone, V: Clone>(node: &Node<K, V>) -> Change<K, V> {
+    Change::Create {
+        key: node.key.clone(),
+        value: node.value.clone(),
+    }
+}
+
+fn deleted<K: Clone, V: Clone>(node: &Node<K, V>) -> Change<K, V> {
+    Change::Delete {
+        key: node.key.clone(),
+        old: node.value.clone(),
+    }
+}
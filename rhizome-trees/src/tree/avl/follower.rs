@@ -0,0 +1,223 @@
+//! Read replicas of a [`VersionedTree`](super::versioned::VersionedTree).
+//!
+//! A [`Follower`] applies the [`StoreEvent`]s and [`VersionEvent`]s of a
+//! primary, in the order the primary reported them, to its own store. Every
+//! replicated node is checked against the merkle hash it claims and every
+//! committed root against the version's recorded hash. A version only
+//! becomes visible to readers once its commit is applied, and stops being
+//! visible before its nodes are deleted.
+//!
+//! The follower's store must assign the same pointers as the primary's,
+//! since replicated nodes refer to their children by pointer. A
+//! [`ContentAddressedStore`](crate::tree::node_manager::ContentAddressedStore)
+//! on both sides always does; other stores only do if the follower starts
+//! from the same state as the primary.
+
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use super::node::{node_hash, Link, Manager, Node};
+use super::versioned::{Version, VersionEvent, VersionInfo};
+use super::Tree;
+use crate::tree::hash::{Digest, Hashable, EMPTY_HASH};
+use crate::tree::node_manager::{NodeRef, Ptr, StoreEvent};
+use crate::{Error, Result};
+
+/// A replica which applies a primary's events and serves the replicated
+/// versions to readers.
+///
+/// Events must be applied by one thread at a time, in order; readers may
+/// load versions concurrently. Trees loaded from a follower must not be
+/// saved, as that would write to the replicated store.
+pub struct Follower<K, V> {
+    manager: Arc<Manager<K, V>>,
+    versions: RwLock<Vec<VersionInfo>>,
+}
+
+impl<K, V> Follower<K, V> {
+    /// Creates a follower which replicates into the store of `manager`.
+    pub fn new(manager: Arc<Manager<K, V>>) -> Self {
+        Follower {
+            manager,
+            versions: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn manager(&self) -> &Arc<Manager<K, V>> {
+        &self.manager
+    }
+
+    /// Returns the versions visible to readers, oldest first.
+    pub fn versions(&self) -> Result<Vec<VersionInfo>> {
+        Ok(self.versions.read().map_err(poisoned)?.clone())
+    }
+
+    /// Returns the latest visible version, or [`Version::ZERO`] if none is.
+    pub fn latest_version(&self) -> Result<Version> {
+        let versions = self.versions.read().map_err(poisoned)?;
+        Ok(versions.last().map_or(Version::ZERO, |info| info.version))
+    }
+
+    /// Opens the latest visible version.
+    pub fn latest(&self) -> Result<Tree<K, V>> {
+        let versions = self.versions.read().map_err(poisoned)?;
+        Ok(self.open(versions.last().and_then(|info| info.root)))
+    }
+
+    /// Opens a visible version.
+    pub fn load_version(&self, version: Version) -> Result<Tree<K, V>> {
+        let versions = self.versions.read().map_err(poisoned)?;
+        let Some(info) = versions.iter().find(|info| info.version == version) else {
+            return Err(Error::invalid(format!(
+                "version {} is not replicated",
+                version
+            )));
+        };
+        Ok(self.open(info.root))
+    }
+
+    fn open(&self, root: Option<Ptr>) -> Tree<K, V> {
+        match root {
+            None => Tree::with_manager(self.manager.clone()),
+            Some(root) => Tree::load(self.manager.clone(), root),
+        }
+    }
+
+    /// Applies a change to the primary's history.
+    pub fn apply_version_event(&self, event: &VersionEvent) -> Result<()>
+    where
+        K: Hashable,
+        V: Hashable,
+    {
+        match event {
+            VersionEvent::Committed(info) => {
+                if info.version <= self.latest_version()? {
+                    return Err(Error::invalid(format!(
+                        "replicated version {} is not after the latest one",
+                        info.version
+                    )));
+                }
+                let hash = match info.root {
+                    None => EMPTY_HASH,
+                    Some(root) => self
+                        .manager
+                        .read(&NodeRef::Stored(root))?
+                        .hash(&self.manager)?,
+                };
+                if hash != info.hash {
+                    return Err(Error::corruption(format!(
+                        "replicated version {} doesn't match its root hash",
+                        info.version
+                    )));
+                }
+                self.versions.write().map_err(poisoned)?.push(*info);
+            }
+            VersionEvent::RolledBack { to } => {
+                let mut versions = self.versions.write().map_err(poisoned)?;
+                versions.retain(|info| info.version <= *to);
+            }
+            VersionEvent::Pruned { versions: pruned } => {
+                let mut versions = self.versions.write().map_err(poisoned)?;
+                versions.retain(|info| !pruned.contains(&info.version));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K: Clone + Hashable, V: Clone + Hashable> Follower<K, V> {
+    /// Applies a write to the primary's store, after checking that
+    /// replicated nodes hash to the hashes they carry.
+    pub fn apply_store_event(&self, event: &StoreEvent<Node<K, V>>) -> Result<()> {
+        let m = &*self.manager;
+        match event {
+            StoreEvent::Inserted { ptr, node } => {
+                let node = checked(m, node, &HashMap::new())?;
+                let inserted = m.insert(node)?;
+                if inserted != *ptr {
+                    m.release(&inserted)?;
+                    return Err(Error::invalid(format!(
+                        "follower stored replicated node {:?} at {:?}; its store must assign \
+                         the primary's pointers",
+                        ptr, inserted
+                    )));
+                }
+            }
+            StoreEvent::Updated { nodes } => {
+                let pending: HashMap<Ptr, &Node<K, V>> =
+                    nodes.iter().map(|(ptr, node)| (*ptr, &**node)).collect();
+                let nodes = nodes
+                    .iter()
+                    .map(|(ptr, node)| Ok((*ptr, checked(m, node, &pending)?)))
+                    .collect::<Result<Vec<_>>>()?;
+                if !m.try_update(nodes)? {
+                    return Err(Error::invalid(
+                        "follower store rejected a replicated in-place update",
+                    ));
+                }
+            }
+            StoreEvent::RefCountIncremented { ptr } => {
+                m.inc_ref_count(ptr)?;
+            }
+            StoreEvent::RefCountDecremented { ptr } => {
+                m.dec_ref_count(ptr)?;
+            }
+            StoreEvent::Deleted { ptr } => {
+                m.delete(ptr)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns a copy of a replicated node after recomputing its hash from its
+/// entry and children and comparing it with the hash it carries. Children
+/// are looked up in `pending`, the other nodes of the same update, before
+/// the store.
+fn checked<K: Clone + Hashable, V: Clone + Hashable>(
+    m: &Manager<K, V>,
+    node: &Node<K, V>,
+    pending: &HashMap<Ptr, &Node<K, V>>,
+) -> Result<Node<K, V>> {
+    let hash = expected_hash(m, node, pending)?;
+    Ok(Node {
+        key: node.key.clone(),
+        value: node.value.clone(),
+        height: node.height,
+        size: node.size,
+        left: node.left.clone(),
+        right: node.right.clone(),
+        hash: OnceLock::from(hash),
+        hash_version: node.hash_version,
+    })
+}
+
+fn expected_hash<K: Hashable, V: Hashable>(
+    m: &Manager<K, V>,
+    node: &Node<K, V>,
+    pending: &HashMap<Ptr, &Node<K, V>>,
+) -> Result<Digest> {
+    let child_hash = |child: &Link<K, V>| match child {
+        None => Ok(EMPTY_HASH),
+        Some(NodeRef::Stored(ptr)) => match pending.get(ptr) {
+            Some(child) => expected_hash(m, child, pending),
+            // Nodes in the store were checked when they were replicated.
+            None => m.read(&NodeRef::Stored(*ptr))?.hash(m),
+        },
+        Some(NodeRef::Mem(_)) => Err(Error::corruption("replicated node has an unsaved child")),
+    };
+    let hash = node_hash(
+        &child_hash(&node.left)?,
+        &node.entry_hash(),
+        &child_hash(&node.right)?,
+    );
+    match node.hash.get() {
+        Some(claimed) if *claimed == hash => Ok(hash),
+        Some(_) => Err(Error::corruption("replicated node doesn't match its hash")),
+        None => Err(Error::corruption("replicated node carries no hash")),
+    }
+}
+
+fn poisoned<T>(_: T) -> Error {
+    Error::poisoned("follower versions")
+}
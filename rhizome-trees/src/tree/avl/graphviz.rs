@@ -0,0 +1,178 @@
+//! Rendering a tree as a Graphviz graph, for auditing which of its nodes
+//! are held in memory and which are stored.
+//!
+//! Each node shows its key and size. Nodes which haven't been saved are
+//! filled yellow, stored nodes blue and annotated with their pointer, and
+//! stored nodes which aren't in the manager's cache, so that reaching them
+//! reads the store, have a dashed outline. Rendering doesn't change which
+//! nodes are cached. Keys are shown by their encoding, with printable ASCII
+//! bytes as they are and others as `\xNN`, and edges are labeled `L` or `R`
+//! for the side of the child.
+//!
+//! [`Tree::to_graphviz_with`] picks the annotations with
+//! [`GraphvizOptions`]: it can also label stored nodes with their
+//! reference counts, which tell the nodes versions share, and add a legend
+//! explaining the colors and labels.
+
+use std::fmt::Write;
+
+use super::node::Link;
+use super::Tree;
+use crate::tree::graphviz::{self, escape, Format};
+use crate::tree::node_manager::NodeRef;
+use crate::tree::value::ValueCodec;
+use crate::Result;
+
+/// The annotations of the graph [`Tree::to_graphviz_with`] renders. The
+/// default shows subtree sizes and cache residency, as
+/// [`Tree::to_graphviz`] does.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct GraphvizOptions {
+    sizes: bool,
+    ref_counts: bool,
+    cache_residency: bool,
+    legend: bool,
+}
+
+impl Default for GraphvizOptions {
+    fn default() -> Self {
+        GraphvizOptions {
+            sizes: true,
+            ref_counts: false,
+            cache_residency: true,
+            legend: false,
+        }
+    }
+}
+
+impl GraphvizOptions {
+    /// Sets whether nodes show the number of entries in their subtree.
+    pub fn sizes(self, sizes: bool) -> Self {
+        GraphvizOptions { sizes, ..self }
+    }
+
+    /// Sets whether stored nodes show their reference count, if the store
+    /// can tell it, see
+    /// [`NodeStore::ref_count`](crate::tree::node_manager::NodeStore::ref_count).
+    pub fn ref_counts(self, ref_counts: bool) -> Self {
+        GraphvizOptions { ref_counts, ..self }
+    }
+
+    /// Sets whether stored nodes which aren't cached have a dashed outline.
+    pub fn cache_residency(self, cache_residency: bool) -> Self {
+        GraphvizOptions {
+            cache_residency,
+            ..self
+        }
+    }
+
+    /// Sets whether the graph has a legend, a `cluster_legend` subgraph
+    /// showing the node styles and the lines of node labels.
+    pub fn legend(self, legend: bool) -> Self {
+        GraphvizOptions { legend, ..self }
+    }
+}
+
+impl<K: ValueCodec, V> Tree<K, V> {
+    /// Returns the tree in the Graphviz DOT language, see the
+    /// [module documentation](self). Stored nodes which aren't cached are
+    /// read from the store.
+    pub fn to_graphviz(&self) -> Result<String> {
+        self.to_graphviz_with(GraphvizOptions::default())
+    }
+
+    /// Like [`Tree::to_graphviz`], with the annotations of `options`.
+    pub fn to_graphviz_with(&self, options: GraphvizOptions) -> Result<String> {
+        let mut dot = String::from("digraph avl {\n    node [fontname=monospace, style=filled];\n");
+        if options.legend {
+            write_legend(&mut dot, options);
+        }
+        let mut next_id = 0;
+        self.write_link(&mut dot, &self.root, options, &mut next_id)?;
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+
+    /// Writes the graph of [`Tree::to_graphviz`] to `writer` in `format`,
+    /// see [`graphviz::render`].
+    pub fn render_graphviz(&self, format: Format, writer: impl std::io::Write) -> Result<()> {
+        graphviz::render(&self.to_graphviz()?, format, writer)
+    }
+
+    /// Writes the subtree at `link`, returning the ID of its root.
+    fn write_link(
+        &self,
+        dot: &mut String,
+        link: &Link<K, V>,
+        options: GraphvizOptions,
+        next_id: &mut usize,
+    ) -> Result<Option<usize>> {
+        let Some(link) = link else {
+            return Ok(None);
+        };
+        let id = *next_id;
+        *next_id += 1;
+        let (node, attrs) = match link {
+            NodeRef::Mem(_) => (
+                self.manager.read(link)?,
+                String::from("fillcolor=lightyellow"),
+            ),
+            NodeRef::Stored(ptr) => {
+                let (node, cached) = self.manager.peek(ptr)?;
+                let style = if cached || !options.cache_residency {
+                    ""
+                } else {
+                    ", style=\"filled,dashed\""
+                };
+                (node, format!("fillcolor=lightblue{}", style))
+            }
+        };
+        let mut label = escape(&node.key.to_encoded());
+        if options.sizes {
+            write!(label, "\\nsize {}", node.size).unwrap();
+        }
+        if let NodeRef::Stored(ptr) = link {
+            write!(label, "\\n{:?}", ptr).unwrap();
+            if options.ref_counts {
+                if let Some(count) = self.manager.ref_count(ptr)? {
+                    write!(label, "\\nrefs {}", count).unwrap();
+                }
+            }
+        }
+        writeln!(dot, "    n{} [{}, label=\"{}\"];", id, attrs, label).unwrap();
+        for (side, child) in [("L", &node.left), ("R", &node.right)] {
+            if let Some(child) = self.write_link(dot, child, options, next_id)? {
+                writeln!(dot, "    n{} -> n{} [label=\"{}\"];", id, child, side).unwrap();
+            }
+        }
+        Ok(Some(id))
+    }
+}
+
+/// Writes the legend of a graph with the annotations of `options`.
+fn write_legend(dot: &mut String, options: GraphvizOptions) {
+    dot.push_str("    subgraph cluster_legend {\n        label=\"legend\";\n");
+    dot.push_str("        legend_unsaved [fillcolor=lightyellow, label=\"unsaved\"];\n");
+    dot.push_str("        legend_stored [fillcolor=lightblue, label=\"stored\"];\n");
+    if options.cache_residency {
+        dot.push_str(
+            "        legend_uncached [fillcolor=lightblue, style=\"filled,dashed\", \
+             label=\"stored, not cached\"];\n",
+        );
+    }
+    let mut label = String::from("key");
+    if options.sizes {
+        label.push_str("\\nsize: entries in subtree");
+    }
+    label.push_str("\\npointer, if stored");
+    if options.ref_counts {
+        label.push_str("\\nrefs: reference count");
+    }
+    writeln!(
+        dot,
+        "        legend_label [fillcolor=white, shape=box, label=\"{}\"];",
+        label
+    )
+    .unwrap();
+    dot.push_str("    }\n");
+}
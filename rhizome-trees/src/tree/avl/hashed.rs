@@ -0,0 +1,229 @@
+//! Trees ordered by the hashes of their keys.
+//!
+//! Applications with long composite keys pay for them on every comparison
+//! and in every proof step. A [`HashedKeyTree`] orders its entries by a
+//! fixed-size [`KeyHash`] of the key instead, and stores the full key next to
+//! the value in a [`KeyedValue`]. Lookups compare the stored key with the
+//! requested one, so a hash collision can never return the value of another
+//! key, and since the value hash covers the full key, a proof still commits
+//! to it: a proof of `key` is checked with
+//! `proof.verify(root, &KeyHash::of(key), &KeyedValue { key, value })`.
+//!
+//! The price is that entries are no longer in key order, so ranges over the
+//! original keys can't be iterated.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::sync::Arc;
+
+use super::node::Manager;
+use super::proof::Proof;
+use super::Tree;
+use crate::tree::hash::{hash_of, Digest, Hashable, MerkleTree, Update};
+use crate::{Error, Result};
+
+/// The SHA-256 hash of the encoding of a key, which orders the entries of a
+/// [`HashedKeyTree`].
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct KeyHash(pub Digest);
+
+impl KeyHash {
+    pub fn of<K: Hashable + ?Sized>(key: &K) -> Self {
+        KeyHash(hash_of(key))
+    }
+}
+
+impl Hashable for KeyHash {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(&self.0);
+    }
+}
+
+/// An entry of a [`HashedKeyTree`]: the full key along with its value.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct KeyedValue<K, V> {
+    pub key: K,
+    pub value: V,
+}
+
+/// Hashes the hashes of the key and the value, so keys and values of any
+/// encoding stay unambiguous.
+impl<K: Hashable, V: Hashable> Hashable for KeyedValue<K, V> {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(&hash_of(&self.key));
+        hasher.update(&hash_of(&self.value));
+    }
+}
+
+/// A persistent map stored as a tree keyed by [`KeyHash`]es, see the
+/// [module documentation](self).
+pub struct HashedKeyTree<K, V> {
+    tree: Tree<KeyHash, KeyedValue<K, V>>,
+}
+
+impl<K, V> Clone for HashedKeyTree<K, V> {
+    fn clone(&self) -> Self {
+        HashedKeyTree {
+            tree: self.tree.clone(),
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for HashedKeyTree<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HashedKeyTree")
+            .field("root", &self.tree.root)
+            .finish()
+    }
+}
+
+impl<K, V> Default for HashedKeyTree<K, V>
+where
+    K: Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        HashedKeyTree {
+            tree: Tree::default(),
+        }
+    }
+}
+
+impl<K, V> HashedKeyTree<K, V>
+where
+    K: Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<K, V> HashedKeyTree<K, V> {
+    pub fn with_manager(manager: Arc<Manager<KeyHash, KeyedValue<K, V>>>) -> Self {
+        HashedKeyTree {
+            tree: Tree::with_manager(manager),
+        }
+    }
+
+    pub fn from_tree(tree: Tree<KeyHash, KeyedValue<K, V>>) -> Self {
+        HashedKeyTree { tree }
+    }
+
+    pub fn tree(&self) -> &Tree<KeyHash, KeyedValue<K, V>> {
+        &self.tree
+    }
+
+    pub fn into_tree(self) -> Tree<KeyHash, KeyedValue<K, V>> {
+        self.tree
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn len(&self) -> Result<u64> {
+        self.tree.len()
+    }
+}
+
+impl<K: Clone, V: Clone> HashedKeyTree<K, V> {
+    /// Returns the value of `key`, or an error if another key with the same
+    /// hash is stored in its place.
+    pub fn get<Q>(&self, key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hashable + PartialEq + ?Sized,
+    {
+        let found = self.tree.get_with(&KeyHash::of(key), |entry| {
+            (entry.key.borrow() == key).then(|| entry.value.clone())
+        })?;
+        match found {
+            Some(None) => Err(Error::invalid(
+                "another key is stored under the hash of the requested key",
+            )),
+            Some(value) => Ok(value),
+            None => Ok(None),
+        }
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+        Q: Hashable + PartialEq + ?Sized,
+    {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Inserts `key`, failing if another key with the same hash is stored.
+    pub fn insert(&self, key: K, value: V) -> Result<Self>
+    where
+        K: Hashable + PartialEq,
+    {
+        let hash = KeyHash::of(&key);
+        if self.tree.get_with(&hash, |entry| entry.key != key)? == Some(true) {
+            return Err(Error::invalid(
+                "another key is stored under the hash of the inserted key",
+            ));
+        }
+        Ok(HashedKeyTree {
+            tree: self.tree.insert(hash, KeyedValue { key, value })?,
+        })
+    }
+
+    /// Removes `key`. Deleting an absent key returns a tree sharing this
+    /// tree's root, including when another key has the same hash.
+    pub fn delete<Q>(&self, key: &Q) -> Result<Self>
+    where
+        K: Borrow<Q>,
+        Q: Hashable + PartialEq + ?Sized,
+    {
+        let hash = KeyHash::of(key);
+        if self
+            .tree
+            .get_with(&hash, |entry| entry.key.borrow() == key)?
+            != Some(true)
+        {
+            return Ok(self.clone());
+        }
+        Ok(HashedKeyTree {
+            tree: self.tree.delete(&hash)?,
+        })
+    }
+}
+
+impl<K: Clone + Hashable, V: Clone + Hashable> HashedKeyTree<K, V> {
+    /// Persists all unsaved nodes, see [`Tree::save`].
+    pub fn save(&self) -> Result<Self> {
+        Ok(HashedKeyTree {
+            tree: self.tree.save()?,
+        })
+    }
+}
+
+impl<K: Hashable, V: Hashable> HashedKeyTree<K, V> {
+    /// Returns a proof that `key` is in the tree, or `None` if it isn't. The
+    /// proof is checked against the [`KeyHash`] of the key and its
+    /// [`KeyedValue`].
+    pub fn prove<Q>(&self, key: &Q) -> Result<Option<Proof>>
+    where
+        K: Borrow<Q>,
+        Q: Hashable + PartialEq + ?Sized,
+    {
+        let hash = KeyHash::of(key);
+        if self
+            .tree
+            .get_with(&hash, |entry| entry.key.borrow() == key)?
+            != Some(true)
+        {
+            return Ok(None);
+        }
+        self.tree.prove(&hash)
+    }
+}
+
+impl<K: Hashable, V: Hashable> MerkleTree for HashedKeyTree<K, V> {
+    fn merkle_hash(&self) -> Result<Digest> {
+        self.tree.merkle_hash()
+    }
+}
@@ -0,0 +1,258 @@
+//! Per-key change histories.
+//!
+//! A [`KeyHistory`] records every change of every key along with the
+//! [`Version`] which made it, so a key can be read as of any retained
+//! version. Histories grow with every change, so [`KeyHistory::prune`] trims
+//! them according to a [`Retention`] set per key, falling back to a default
+//! for keys without one. High-churn keys can keep a short history while
+//! slow-changing entries, e.g. of a registry, keep all of theirs.
+
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+
+use super::node::Link;
+use super::versioned::Version;
+use super::Tree;
+use crate::tree::hash::{hash_of, Hashable, Update};
+use crate::{Error, Result};
+
+/// How much of a key's history [`KeyHistory::prune`] keeps. The latest
+/// change is always kept.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Retention {
+    #[default]
+    KeepAll,
+    /// Keeps the latest `n` changes.
+    KeepLast(usize),
+    /// Keeps the changes made after a version along with the one in effect
+    /// at it, so the key can still be read as of that version.
+    KeepSince(Version),
+}
+
+/// The retained changes of a single key, oldest first. A `None` value is a
+/// deletion.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct History<V> {
+    since: Version,
+    changes: Vec<(Version, Option<V>)>,
+}
+
+impl<V> History<V> {
+    /// Returns the earliest version the history can be read at. It is
+    /// [`Version::ZERO`] until older changes are pruned.
+    pub fn since(&self) -> Version {
+        self.since
+    }
+
+    pub fn changes(&self) -> &[(Version, Option<V>)] {
+        &self.changes
+    }
+
+    /// Returns the value as of `version`, or an error if the changes needed
+    /// to tell were pruned.
+    pub fn get_at(&self, version: Version) -> Result<Option<&V>> {
+        if version < self.since {
+            return Err(Error::invalid(format!(
+                "history before version {} was pruned",
+                self.since
+            )));
+        }
+        let after = self.changes.partition_point(|(v, _)| *v <= version);
+        Ok(after
+            .checked_sub(1)
+            .and_then(|i| self.changes[i].1.as_ref()))
+    }
+
+    /// Drops the changes `retention` doesn't keep and returns how many.
+    fn prune(&mut self, retention: Retention) -> usize {
+        let keep_from = match retention {
+            Retention::KeepAll => 0,
+            Retention::KeepLast(n) => self.changes.len().saturating_sub(n.max(1)),
+            Retention::KeepSince(version) => self
+                .changes
+                .partition_point(|(v, _)| *v <= version)
+                .saturating_sub(1),
+        };
+        if keep_from > 0 {
+            self.changes.drain(..keep_from);
+            self.since = self.changes[0].0;
+        }
+        keep_from
+    }
+}
+
+/// Hashes the retained versions and the hashes of the retained values, so
+/// values of any encoding stay unambiguous.
+impl<V: Hashable> Hashable for History<V> {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(&self.since.get().to_be_bytes());
+        hasher.update(&(self.changes.len() as u64).to_be_bytes());
+        for (version, value) in &self.changes {
+            hasher.update(&version.get().to_be_bytes());
+            match value {
+                None => hasher.update(&[0]),
+                Some(value) => {
+                    hasher.update(&[1]);
+                    hasher.update(&hash_of(value));
+                }
+            }
+        }
+    }
+}
+
+/// The histories of a set of keys, stored in a [`Tree`] which can be saved
+/// and proven like any other.
+pub struct KeyHistory<K, V> {
+    tree: Tree<K, History<V>>,
+    default_retention: Retention,
+    retention: BTreeMap<K, Retention>,
+}
+
+impl<K, V> KeyHistory<K, V> {
+    /// Records changes into `tree`, keeping every change until retention
+    /// policies are set.
+    pub fn new(tree: Tree<K, History<V>>) -> Self {
+        KeyHistory {
+            tree,
+            default_retention: Retention::default(),
+            retention: BTreeMap::new(),
+        }
+    }
+
+    pub fn tree(&self) -> &Tree<K, History<V>> {
+        &self.tree
+    }
+
+    pub fn set_tree(&mut self, tree: Tree<K, History<V>>) {
+        self.tree = tree;
+    }
+
+    /// Sets the retention of keys without one of their own.
+    pub fn set_default_retention(&mut self, retention: Retention) {
+        self.default_retention = retention;
+    }
+}
+
+impl<K: Ord, V> KeyHistory<K, V> {
+    /// Sets the retention of `key`, overriding the default.
+    pub fn set_retention(&mut self, key: K, retention: Retention) {
+        self.retention.insert(key, retention);
+    }
+
+    /// Makes `key` use the default retention again.
+    pub fn clear_retention<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.retention.remove(key);
+    }
+
+    pub fn retention<Q>(&self, key: &Q) -> Retention
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.retention
+            .get(key)
+            .copied()
+            .unwrap_or(self.default_retention)
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> KeyHistory<K, V> {
+    /// Returns the retained history of `key`, or `None` if it never changed.
+    pub fn history<Q>(&self, key: &Q) -> Result<Option<History<V>>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.tree.get(key)
+    }
+
+    /// Returns the latest value of `key`.
+    pub fn get<Q>(&self, key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Ok(self
+            .history(key)?
+            .and_then(|history| history.changes.last()?.1.clone()))
+    }
+
+    /// Returns the value of `key` as of `version`, or an error if that part
+    /// of its history was pruned.
+    pub fn get_at<Q>(&self, key: &Q, version: Version) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match self.history(key)? {
+            None => Ok(None),
+            Some(history) => Ok(history.get_at(version)?.cloned()),
+        }
+    }
+
+    /// Records that `version` set `key` to `value`. Versions must increase
+    /// with every change of a key.
+    pub fn insert(&mut self, version: Version, key: K, value: V) -> Result<()> {
+        self.record(version, key, Some(value))
+    }
+
+    /// Records that `version` deleted `key`.
+    pub fn delete(&mut self, version: Version, key: K) -> Result<()> {
+        self.record(version, key, None)
+    }
+
+    fn record(&mut self, version: Version, key: K, value: Option<V>) -> Result<()> {
+        let mut history = self.tree.get(&key)?.unwrap_or(History {
+            since: Version::ZERO,
+            changes: Vec::new(),
+        });
+        if let Some((last, _)) = history.changes.last() {
+            if version <= *last {
+                return Err(Error::invalid(format!(
+                    "change at version {} is not after the key's last change at {}",
+                    version, last
+                )));
+            }
+        }
+        history.changes.push((version, value));
+        self.tree = self.tree.insert(key, history)?;
+        Ok(())
+    }
+
+    /// Trims every history according to its key's retention and returns the
+    /// number of dropped changes.
+    pub fn prune(&mut self) -> Result<usize> {
+        let mut pruned = Vec::new();
+        self.collect_pruned(self.tree.root().cloned(), &mut pruned)?;
+        let mut dropped = 0;
+        for (key, history, count) in pruned {
+            self.tree = self.tree.insert(key, history)?;
+            dropped += count;
+        }
+        Ok(dropped)
+    }
+
+    /// Collects the pruned histories of the subtree `link` which lost any
+    /// changes.
+    fn collect_pruned(
+        &self,
+        link: Link<K, History<V>>,
+        pruned: &mut Vec<(K, History<V>, usize)>,
+    ) -> Result<()> {
+        let Some(link) = link else {
+            return Ok(());
+        };
+        let node = self.tree.read(&link)?;
+        self.collect_pruned(node.left().cloned(), pruned)?;
+        let mut history = node.value().clone();
+        let count = history.prune(self.retention(node.key()));
+        if count > 0 {
+            pruned.push((node.key().clone(), history, count));
+        }
+        self.collect_pruned(node.right().cloned(), pruned)
+    }
+}
@@ -0,0 +1,355 @@
+//! Bulk import of unsorted entries.
+//!
+//! [`Tree::import`] builds a saved tree from a stream of entries in any
+//! order, in three stages:
+//!
+//! 1. Entries are buffered up to [`ImportOptions::run_bytes`], sorted and
+//!    spilled to a run file in [`ImportOptions::spill_dir`], so memory use
+//!    doesn't depend on the size of the input.
+//! 2. The runs are merged into one sorted stream. A key imported more than
+//!    once keeps its last value.
+//! 3. The stream is cut into chunks of [`ImportOptions::chunk_entries`],
+//!    which worker threads build into balanced subtrees and save in
+//!    parallel. Chunks are handed over through a bounded queue, so reading
+//!    stalls while the workers are behind. Finally the subtrees are joined
+//!    along their edges, which only copies the O(log n) nodes on the seams.
+//!
+//! Inputs that fit in a single run never touch the disk.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::node::{Link, Manager, Node};
+use super::Tree;
+use crate::tree::node_manager::{NodeRef, Ptr};
+use crate::{Error, Result};
+
+type Entry = (Vec<u8>, Vec<u8>);
+
+/// Settings of [`Tree::import`].
+#[derive(Clone, Debug)]
+pub struct ImportOptions {
+    /// The approximate number of key and value bytes buffered before a
+    /// sorted run is spilled to disk.
+    pub run_bytes: usize,
+    /// Where run files are created. They are removed when the import ends.
+    pub spill_dir: PathBuf,
+    /// The number of entries in each subtree built by a worker.
+    pub chunk_entries: usize,
+    /// The number of worker threads building subtrees.
+    pub threads: usize,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            run_bytes: 64 << 20,
+            spill_dir: std::env::temp_dir(),
+            chunk_entries: 1 << 16,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+/// The per-entry memory overhead counted against [`ImportOptions::run_bytes`].
+const ENTRY_OVERHEAD: usize = 2 * std::mem::size_of::<Vec<u8>>();
+
+impl Tree<Vec<u8>, Vec<u8>> {
+    /// Builds and saves a tree holding `entries`, which may come in any
+    /// order, see the [module documentation](self). If a key occurs more
+    /// than once, the last value wins.
+    pub fn import(
+        manager: Arc<Manager<Vec<u8>, Vec<u8>>>,
+        entries: impl IntoIterator<Item = Entry>,
+        options: &ImportOptions,
+    ) -> Result<Self> {
+        if options.chunk_entries == 0 || options.threads == 0 {
+            return Err(Error::invalid("import chunks and threads must not be zero"));
+        }
+        let mut runs = Vec::new();
+        let mut buffer = Vec::new();
+        let mut buffered = 0;
+        for (key, value) in entries {
+            buffered += key.len() + value.len() + ENTRY_OVERHEAD;
+            buffer.push((key, value));
+            if buffered >= options.run_bytes {
+                runs.push(Run::spill(options, sort_run(buffer))?);
+                buffer = Vec::new();
+                buffered = 0;
+            }
+        }
+        let buffer = sort_run(buffer);
+        let sorted: Box<dyn Iterator<Item = Result<Entry>>> = if runs.is_empty() {
+            Box::new(buffer.into_iter().map(Ok))
+        } else {
+            runs.push(Run::spill(options, buffer)?);
+            Box::new(Merge::new(runs)?)
+        };
+        build(manager, sorted, options)
+    }
+}
+
+/// Sorts a run by key, keeping the last value of duplicate keys.
+fn sort_run(mut entries: Vec<Entry>) -> Vec<Entry> {
+    // The sort is stable, so the last duplicate is the latest.
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut deduped: Vec<Entry> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        match deduped.last_mut() {
+            Some(last) if last.0 == entry.0 => *last = entry,
+            _ => deduped.push(entry),
+        }
+    }
+    deduped
+}
+
+/// A sorted run spilled to a temporary file, which is removed on drop.
+struct Run {
+    path: PathBuf,
+    reader: BufReader<File>,
+}
+
+static RUN_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl Run {
+    fn spill(options: &ImportOptions, entries: Vec<Entry>) -> Result<Self> {
+        let path = options.spill_dir.join(format!(
+            "rhizome-import-{}-{}.run",
+            std::process::id(),
+            RUN_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|err| Error::io(format!("creating import run {}", path.display()), err))?;
+        let mut run = Run {
+            path,
+            reader: BufReader::new(file),
+        };
+        // Written through the run, so the file is removed on failure.
+        let mut writer = BufWriter::new(run.reader.get_mut());
+        for (key, value) in &entries {
+            for bytes in [key, value] {
+                writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+                writer.write_all(bytes)?;
+            }
+        }
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .rewind()?;
+        Ok(run)
+    }
+
+    fn next(&mut self) -> Result<Option<Entry>> {
+        let Some(key) = self.read_bytes(true)? else {
+            return Ok(None);
+        };
+        let value = self
+            .read_bytes(false)?
+            .ok_or_else(|| Error::codec("truncated import run"))?;
+        Ok(Some((key, value)))
+    }
+
+    fn read_bytes(&mut self, eof_ok: bool) -> Result<Option<Vec<u8>>> {
+        let mut len = [0u8; 4];
+        match self.reader.read_exact(&mut len) {
+            Err(err) if eof_ok && err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let mut bytes = vec![0; u32::from_le_bytes(len) as usize];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(Some(bytes))
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Merges sorted runs into one sorted stream, keeping the value of the
+/// latest run for keys present in several.
+struct Merge {
+    runs: Vec<Run>,
+    heads: Vec<Option<Vec<u8>>>,
+    /// The smallest key first, and among equal keys the latest run.
+    heap: BinaryHeap<(Reverse<Vec<u8>>, usize)>,
+}
+
+impl Merge {
+    fn new(runs: Vec<Run>) -> Result<Self> {
+        let mut merge = Merge {
+            heads: vec![None; runs.len()],
+            runs,
+            heap: BinaryHeap::new(),
+        };
+        for i in 0..merge.runs.len() {
+            merge.advance(i)?;
+        }
+        Ok(merge)
+    }
+
+    /// Reads the next entry of run `i` into the heap.
+    fn advance(&mut self, i: usize) -> Result<()> {
+        if let Some((key, value)) = self.runs[i].next()? {
+            self.heads[i] = Some(value);
+            self.heap.push((Reverse(key), i));
+        }
+        Ok(())
+    }
+
+    fn next_entry(&mut self) -> Result<Option<Entry>> {
+        let Some((Reverse(key), i)) = self.heap.pop() else {
+            return Ok(None);
+        };
+        let value = self.heads[i].take().expect("heap entry without a value");
+        self.advance(i)?;
+        while let Some((Reverse(next), _)) = self.heap.peek() {
+            if *next != key {
+                break;
+            }
+            let (_, stale) = self.heap.pop().expect("peeked entry");
+            self.heads[stale] = None;
+            self.advance(stale)?;
+        }
+        Ok(Some((key, value)))
+    }
+}
+
+impl Iterator for Merge {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+/// A chunk of the sorted stream. Every chunk but the first holds back its
+/// first entry, which joins it to the subtree of the chunks before it.
+struct Chunk {
+    index: usize,
+    separator: Option<Entry>,
+    entries: Vec<Entry>,
+}
+
+/// A chunk built into a saved subtree.
+struct Built {
+    index: usize,
+    separator: Option<Entry>,
+    root: Option<Ptr>,
+}
+
+fn build(
+    manager: Arc<Manager<Vec<u8>, Vec<u8>>>,
+    mut sorted: impl Iterator<Item = Result<Entry>>,
+    options: &ImportOptions,
+) -> Result<Tree<Vec<u8>, Vec<u8>>> {
+    let m = &*manager;
+    let mut built = thread::scope(|scope| -> Result<Vec<Built>> {
+        let (sender, receiver) = mpsc::sync_channel::<Chunk>(options.threads);
+        // Shared by the workers only, so sending fails rather than blocks
+        // once they have all stopped.
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers: Vec<_> = (0..options.threads)
+            .map(|_| {
+                let receiver = receiver.clone();
+                scope.spawn(move || work(m, &receiver))
+            })
+            .collect();
+        drop(receiver);
+        let read = (|| {
+            let mut index = 0;
+            while let Some(chunk) = next_chunk(&mut sorted, index, options.chunk_entries)? {
+                if sender.send(chunk).is_err() {
+                    break;
+                }
+                index += 1;
+            }
+            Ok(())
+        })();
+        drop(sender);
+        let mut built = Vec::new();
+        for worker in workers {
+            built.extend(worker.join().expect("import worker panicked")?);
+        }
+        read.map(|()| built)
+    })?;
+    built.sort_by_key(|chunk| chunk.index);
+
+    let mut root: Link<Vec<u8>, Vec<u8>> = None;
+    for chunk in &built {
+        let subtree = chunk.root.map(NodeRef::Stored);
+        root = match chunk.separator.clone() {
+            None => subtree,
+            Some((key, value)) => Node::join(m, root, key, value, subtree)?,
+        };
+    }
+    let tree = Tree {
+        root,
+        manager: manager.clone(),
+    }
+    .save()?;
+    // The saved tree holds its own references on the subtrees it kept.
+    for ptr in built.iter().filter_map(|chunk| chunk.root) {
+        manager.release(&ptr)?;
+    }
+    Ok(tree)
+}
+
+fn next_chunk(
+    sorted: &mut impl Iterator<Item = Result<Entry>>,
+    index: usize,
+    len: usize,
+) -> Result<Option<Chunk>> {
+    let separator = match (index, sorted.next()) {
+        (_, None) => return Ok(None),
+        (0, Some(first)) => {
+            let entries = std::iter::once(first)
+                .chain(sorted.take(len - 1))
+                .collect::<Result<_>>()?;
+            return Ok(Some(Chunk {
+                index,
+                separator: None,
+                entries,
+            }));
+        }
+        (_, Some(separator)) => Some(separator?),
+    };
+    Ok(Some(Chunk {
+        index,
+        separator,
+        entries: sorted.take(len).collect::<Result<_>>()?,
+    }))
+}
+
+fn work(m: &Manager<Vec<u8>, Vec<u8>>, chunks: &Mutex<Receiver<Chunk>>) -> Result<Vec<Built>> {
+    let mut built = Vec::new();
+    loop {
+        let chunk = match chunks.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return Err(Error::poisoned("import queue")),
+        };
+        let Ok(chunk) = chunk else {
+            return Ok(built);
+        };
+        let len = chunk.entries.len();
+        let subtree = Node::build(m, &mut chunk.entries.into_iter(), len)?;
+        let root = subtree.map(|subtree| Node::save(m, &subtree)).transpose()?;
+        built.push(Built {
+            index: chunk.index,
+            separator: chunk.separator,
+            root,
+        });
+    }
+}
@@ -0,0 +1,765 @@
+//! A persistent AVL tree map.
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod audit;
+#[cfg(feature = "borsh")]
+pub mod borsh;
+pub mod diff;
+pub mod follower;
+pub mod graphviz;
+pub mod hashed;
+pub mod history;
+pub mod import;
+pub mod node;
+pub mod overlay;
+pub mod proof;
+pub mod range;
+#[cfg(feature = "rkyv")]
+pub mod rkyv;
+pub mod scrub;
+pub mod set;
+pub mod snapshot;
+pub mod stats;
+#[cfg(feature = "proptest")]
+pub mod strategy;
+#[cfg(feature = "stream")]
+pub mod stream;
+pub mod subtree;
+pub mod versioned;
+
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::{ControlFlow, RangeBounds};
+use std::sync::Arc;
+
+use crate::tree::cardinality::{self, Cardinality, DEFAULT_PROBES};
+use crate::tree::hash::{hash_of, Digest, HashProgress, Hashable, MerkleTree, EMPTY_HASH};
+use crate::tree::map::{Map, PersistentMap};
+use crate::tree::node_manager::{Batch, NodeHandle, NodeManager, NodeMeta, NodeRef, Ptr};
+use crate::{Error, Result};
+use diff::{Diff, OverwriteEvents};
+use node::{Link, Manager, Node, ValueHandle};
+use proof::{PathNode, Proof};
+use range::{ChunkedRange, ProvenRange, Range};
+
+/// A persistent sorted map. Cloning is O(1) and modifications return a new
+/// tree sharing all unmodified nodes with the original.
+///
+/// Nodes are read and saved through the tree's [`NodeManager`]. Trees created
+/// with [`Tree::new`] save their nodes in memory; use [`Tree::with_manager`]
+/// for a durable store.
+pub struct Tree<K, V> {
+    root: Link<K, V>,
+    manager: Arc<Manager<K, V>>,
+}
+
+/// A write applied by [`Tree::apply_batch`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum BatchOp<K, V> {
+    Insert(K, V),
+    Delete(K),
+}
+
+impl<K, V> Clone for Tree<K, V> {
+    fn clone(&self) -> Self {
+        Tree {
+            root: self.root.clone(),
+            manager: self.manager.clone(),
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for Tree<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tree").field("root", &self.root).finish()
+    }
+}
+
+/// An empty tree whose saved nodes are kept in memory, see
+/// [`NodeManager::in_memory`].
+impl<K, V> Default for Tree<K, V>
+where
+    K: Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Tree::with_manager(Arc::new(NodeManager::in_memory()))
+    }
+}
+
+impl<K, V> Tree<K, V>
+where
+    K: Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a tree from entries sorted by key without duplicates, see
+    /// [`Tree::from_sorted_iter_with`].
+    pub fn from_sorted_iter(entries: impl IntoIterator<Item = (K, V)>) -> Result<Self>
+    where
+        K: Ord,
+    {
+        Tree::from_sorted_iter_with(Arc::new(NodeManager::in_memory()), entries)
+    }
+}
+
+impl<K, V> Tree<K, V> {
+    /// Creates an empty tree which reads and saves nodes through `manager`.
+    pub fn with_manager(manager: Arc<Manager<K, V>>) -> Self {
+        Tree {
+            root: None,
+            manager,
+        }
+    }
+
+    /// Opens the version of a tree saved with root `ptr`.
+    pub fn load(manager: Arc<Manager<K, V>>, ptr: Ptr) -> Self {
+        Tree {
+            root: Some(NodeRef::Stored(ptr)),
+            manager,
+        }
+    }
+
+    pub fn manager(&self) -> &Arc<Manager<K, V>> {
+        &self.manager
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Returns the number of entries, which every node keeps for its
+    /// subtree, so only the root is read.
+    pub fn len(&self) -> Result<u64> {
+        Ok(match &self.root {
+            None => 0,
+            Some(root) => self.manager.read(root)?.size,
+        })
+    }
+
+    pub fn root(&self) -> Option<&NodeRef<Node<K, V>>> {
+        self.root.as_ref()
+    }
+
+    /// Returns the pointer to the root if the tree is saved, i.e. the tree is
+    /// neither empty nor has unsaved modifications.
+    pub fn root_ptr(&self) -> Option<Ptr> {
+        self.root.as_ref().and_then(NodeRef::ptr)
+    }
+
+    /// Releases this saved version, deleting the nodes no other saved version
+    /// shares, see [`NodeManager::release`]. Other handles to the version
+    /// must not be used afterwards. Returns the number of deleted nodes.
+    pub fn free_version(&self) -> Result<usize> {
+        match &self.root {
+            None => Ok(0),
+            Some(NodeRef::Stored(ptr)) => self.manager.release(ptr),
+            Some(NodeRef::Mem(_)) => Err(Error::invalid(
+                "a tree with unsaved changes is not a saved version",
+            )),
+        }
+    }
+
+    /// Loads the node `node` of this tree.
+    pub fn read(&self, node: &NodeRef<Node<K, V>>) -> Result<NodeHandle<Node<K, V>>> {
+        self.manager.read(node)
+    }
+
+    /// Returns the metadata the store recorded for the node `node` of this
+    /// tree, or `None` if it is unsaved or the store records none.
+    pub fn node_meta(&self, node: &NodeRef<Node<K, V>>) -> Result<Option<NodeMeta>> {
+        match node {
+            NodeRef::Stored(ptr) => self.manager.meta(ptr),
+            NodeRef::Mem(_) => Ok(None),
+        }
+    }
+
+    /// Returns the number of entries, estimated from node heights with
+    /// [`DEFAULT_PROBES`] random root-to-leaf descents.
+    pub fn cardinality(&self) -> Result<Cardinality> {
+        self.cardinality_with_probes(DEFAULT_PROBES)
+    }
+
+    /// Like [`Tree::cardinality`] with a configurable number of descents.
+    pub fn cardinality_with_probes(&self, probes: usize) -> Result<Cardinality> {
+        let Some(root) = &self.root else {
+            return Ok(Cardinality::Exact(0));
+        };
+        let root = self.manager.read(root)?;
+        // The sparsest AVL tree of height h has one entry more than the
+        // sparsest trees of heights h - 1 and h - 2 together.
+        let (mut lower, mut sparsest) = (1u64, 0u64);
+        for _ in 1..root.height {
+            (lower, sparsest) = (lower.saturating_add(sparsest).saturating_add(1), lower);
+        }
+        let upper = 1u64
+            .checked_shl(root.height as u32)
+            .map_or(u64::MAX, |full| full - 1);
+        cardinality::estimate(probes, lower, upper, |rng| {
+            let (mut count, mut weight, mut node) = (0.0, 1.0, root.clone());
+            loop {
+                count += weight;
+                let next = match (&node.left, &node.right) {
+                    (None, None) => return Ok(count),
+                    (Some(child), None) | (None, Some(child)) => child,
+                    (Some(left), Some(right)) => {
+                        weight *= 2.0;
+                        if rng.below(2) == 0 {
+                            left
+                        } else {
+                            right
+                        }
+                    }
+                };
+                node = self.manager.read_child(next)?;
+            }
+        })
+    }
+}
+
+impl<K: Ord, V> Tree<K, V> {
+    /// Returns a handle to the value of `key` which borrows it from the
+    /// loaded node rather than cloning it, so large values can be read in
+    /// place even when the node came from the store.
+    pub fn get_ref<Q>(&self, key: &Q) -> Result<Option<ValueHandle<K, V>>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Ok(Node::find(&self.manager, &self.root, key)?.map(|node| node.map(Node::value)))
+    }
+
+    /// Returns the entry with the smallest key, or `None` if the tree is
+    /// empty.
+    pub fn first(&self) -> Result<Option<NodeHandle<Node<K, V>>>> {
+        Node::edge(&self.manager, &self.root, false)
+    }
+
+    /// Returns the entry with the largest key, or `None` if the tree is
+    /// empty.
+    pub fn last(&self) -> Result<Option<NodeHandle<Node<K, V>>>> {
+        Node::edge(&self.manager, &self.root, true)
+    }
+
+    /// Returns the entry with the smallest key greater than `key`, whether
+    /// or not `key` is present.
+    pub fn successor<Q>(&self, key: &Q) -> Result<Option<NodeHandle<Node<K, V>>>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Node::neighbor(&self.manager, &self.root, key, false)
+    }
+
+    /// Returns the entry with the largest key less than `key`, whether or
+    /// not `key` is present.
+    pub fn predecessor<Q>(&self, key: &Q) -> Result<Option<NodeHandle<Node<K, V>>>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Node::neighbor(&self.manager, &self.root, key, true)
+    }
+
+    /// Returns the entry at `index` in key order, i.e. the entry with
+    /// `index` smaller keys, or `None` if the tree has at most `index`
+    /// entries. Takes O(log n) using the subtree sizes kept in the nodes.
+    pub fn nth(&self, index: u64) -> Result<Option<NodeHandle<Node<K, V>>>> {
+        Node::nth(&self.manager, &self.root, index)
+    }
+
+    /// Counts the keys less than `key`, whether or not `key` is present, in
+    /// O(log n). For a present key this is its index for [`Tree::nth`].
+    pub fn rank<Q>(&self, key: &Q) -> Result<u64>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Node::rank(&self.manager, &self.root, key)
+    }
+
+    /// Calls `f` with a reference to the value of `key` and returns its
+    /// result, or `None` if the key is absent.
+    pub fn get_with<Q, R>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Result<Option<R>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Ok(Node::find(&self.manager, &self.root, key)?.map(|node| f(node.value())))
+    }
+}
+
+impl<K: Ord + Clone, V> Tree<K, V> {
+    /// Builds a perfectly balanced tree bottom-up from entries sorted by key
+    /// without duplicates, in O(n) and without rebalancing, e.g. to load a
+    /// genesis state. Fails if the keys aren't strictly increasing. The tree
+    /// isn't saved; see [`Tree::import`] for unsorted input or datasets
+    /// which don't fit in memory.
+    pub fn from_sorted_iter_with(
+        manager: Arc<Manager<K, V>>,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self> {
+        let mut sorted: Vec<(K, V)> = Vec::new();
+        for (key, value) in entries {
+            if sorted.last().is_some_and(|(last, _)| *last >= key) {
+                return Err(Error::invalid(
+                    "entries are not sorted by key without duplicates",
+                ));
+            }
+            sorted.push((key, value));
+        }
+        let len = sorted.len();
+        let root = Node::build(&manager, &mut sorted.into_iter(), len)?;
+        Ok(Tree { root, manager })
+    }
+
+    /// Iterates over the entries whose keys are in `range`, in key order.
+    pub fn range(&self, range: impl RangeBounds<K>) -> Result<Range<'_, K, V>> {
+        Range::new(&self.manager, &self.root, range)
+    }
+
+    /// Iterates over all entries in key order.
+    pub fn iter(&self) -> Result<Range<'_, K, V>> {
+        self.range(..)
+    }
+
+    /// Iterates over the entries whose keys are in `range` in chunks of up
+    /// to `chunk_size` entries, reading the nodes of a chunk in batches to
+    /// save round trips to remote stores, see [`ChunkedRange`]. A
+    /// `chunk_size` of 0 is raised to 1.
+    pub fn range_chunked(
+        &self,
+        range: impl RangeBounds<K>,
+        chunk_size: usize,
+    ) -> ChunkedRange<'_, K, V> {
+        ChunkedRange::new(&self.manager, &self.root, range, chunk_size)
+    }
+
+    /// Streams the entries whose keys are in `range` in key order, reading
+    /// them on a worker thread at most `prefetch` entries ahead of the
+    /// consumer, see [`stream::RangeStream`].
+    #[cfg(feature = "stream")]
+    pub fn range_stream(
+        &self,
+        range: impl RangeBounds<K>,
+        prefetch: usize,
+    ) -> stream::RangeStream<K, V>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        stream::RangeStream::new(self.clone(), bounds, prefetch)
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Tree<K, V> {
+    pub fn get<Q>(&self, key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Node::get(&self.manager, &self.root, key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Ok(self.get(key)?.is_some())
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Result<Self> {
+        let root = Node::insert(&self.manager, &self.root, key, value)?;
+        Ok(self.with_root(Some(NodeRef::Mem(Arc::new(root)))))
+    }
+
+    /// Removes `key`. Deleting an absent key copies nothing and returns a
+    /// tree sharing this tree's root, so its cached hashes stay valid.
+    pub fn delete<Q>(&self, key: &Q) -> Result<Self>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Ok(match Node::delete(&self.manager, &self.root, key)? {
+            Some(root) => self.with_root(root),
+            None => self.clone(),
+        })
+    }
+
+    /// Like [`Tree::get`], but remembers the keys found absent from a saved
+    /// version in the manager's negative lookup cache, if it has one, so
+    /// that looking them up again skips the traversal. See
+    /// [`NodeManagerBuilder::negative_lookup_cache`](crate::tree::node_manager::NodeManagerBuilder::negative_lookup_cache).
+    pub fn get_cached<Q>(&self, key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + Hashable + ?Sized,
+    {
+        let Some(NodeRef::Stored(root)) = &self.root else {
+            return self.get(key);
+        };
+        if !self.manager.caches_absent_keys() {
+            return self.get(key);
+        }
+        let key_hash = hash_of(key);
+        if self.manager.is_known_absent(root, &key_hash)? {
+            return Ok(None);
+        }
+        let value = self.get(key)?;
+        if value.is_none() {
+            self.manager.record_absent(*root, key_hash)?;
+        }
+        Ok(value)
+    }
+
+    pub fn contains_key_cached<Q>(&self, key: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+        Q: Ord + Hashable + ?Sized,
+    {
+        Ok(self.get_cached(key)?.is_some())
+    }
+
+    /// Removes every key in `range` by splitting the tree at its bounds and
+    /// joining the outer parts, in O(log n) however many keys are removed,
+    /// so expiring a large contiguous keyspace is cheap. Like
+    /// [`Tree::delete`], a range without keys leaves the tree unchanged.
+    pub fn delete_range(&self, range: impl RangeBounds<K>) -> Result<Self> {
+        if self
+            .range((range.start_bound(), range.end_bound()))?
+            .next()
+            .is_none()
+        {
+            return Ok(self.clone());
+        }
+        Ok(self.with_root(Node::delete_range(&self.manager, &self.root, &range)?))
+    }
+
+    /// Applies many inserts and deletes in a single pass, where later writes
+    /// of a key override earlier ones. The writes are sorted first, so each
+    /// node on a path to a written key is copied and rebalanced once instead
+    /// of once per write.
+    pub fn apply_batch(&self, ops: impl IntoIterator<Item = BatchOp<K, V>>) -> Result<Self> {
+        let mut writes = BTreeMap::new();
+        for op in ops {
+            match op {
+                BatchOp::Insert(key, value) => writes.insert(key, Some(value)),
+                BatchOp::Delete(key) => writes.insert(key, None),
+            };
+        }
+        self.apply_writes(writes)
+    }
+
+    /// Inserts many entries in a single pass, see [`Tree::apply_batch`].
+    pub fn insert_batch(&self, entries: impl IntoIterator<Item = (K, V)>) -> Result<Self> {
+        self.apply_batch(
+            entries
+                .into_iter()
+                .map(|(key, value)| BatchOp::Insert(key, value)),
+        )
+    }
+
+    /// Applies writes by key, where `None` deletes.
+    pub(crate) fn apply_writes(&self, writes: BTreeMap<K, Option<V>>) -> Result<Self> {
+        let mut writes = writes.into_iter().peekable();
+        let root = Node::apply(&self.manager, &self.root, &mut writes, None)?;
+        Ok(self.with_root(root))
+    }
+
+    /// Returns the changes which turn `base` into this tree, in key order.
+    /// Overwritten values are reported as [`diff::Change::Update`].
+    pub fn diff<'a>(&'a self, base: &'a Tree<K, V>) -> Diff<'a, K, V> {
+        self.diff_with(base, OverwriteEvents::Update)
+    }
+
+    /// Like [`Tree::diff`] but with a configurable representation of
+    /// overwritten values.
+    pub fn diff_with<'a>(
+        &'a self,
+        base: &'a Tree<K, V>,
+        overwrite: OverwriteEvents,
+    ) -> Diff<'a, K, V> {
+        Diff::new(
+            (&base.manager, &base.root),
+            (&self.manager, &self.root),
+            overwrite,
+        )
+    }
+
+    fn with_root(&self, root: Link<K, V>) -> Self {
+        Tree {
+            root,
+            manager: self.manager.clone(),
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Map for Tree<K, V> {
+    type Key = K;
+    type Value = V;
+
+    fn get(&self, key: &K) -> Result<Option<V>> {
+        Tree::get(self, key)
+    }
+
+    fn len(&self) -> Result<u64> {
+        Tree::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Tree::is_empty(self)
+    }
+}
+
+impl<K: Ord + Clone + Hashable, V: Clone + Hashable> PersistentMap for Tree<K, V> {
+    fn insert(&mut self, key: &K, value: V) -> Result<()> {
+        *self = Tree::insert(self, key.clone(), value)?;
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &K) -> Result<()> {
+        *self = Tree::delete(self, key)?;
+        Ok(())
+    }
+
+    /// Saves the tree, see [`Tree::save`], unless its root is stored
+    /// already, so that committing without changes takes no further
+    /// reference. Each commit of changes holds a reference on its root,
+    /// which [`Tree::free_version`] releases. Fails for trees without a
+    /// store.
+    fn commit(&mut self) -> Result<Digest> {
+        if !matches!(self.root, Some(NodeRef::Stored(_))) {
+            *self = self.save()?;
+        }
+        self.merkle_hash()
+    }
+}
+
+impl<K: Clone + Hashable, V: Clone + Hashable> Tree<K, V> {
+    /// Persists all unsaved nodes and returns the tree with a stored root.
+    ///
+    /// Every call takes a new reference on the root node, including when the
+    /// tree had no unsaved modifications, so each saved version holds exactly
+    /// one reference to its root. The new version is written to the store
+    /// atomically, see
+    /// [`NodeStore::begin_batch`](crate::tree::node_manager::NodeStore::begin_batch).
+    pub fn save(&self) -> Result<Self> {
+        self.save_with(None)
+    }
+
+    /// Like [`Tree::save`], but records `version` as the version which
+    /// created the new nodes, in stores which keep [`NodeMeta`].
+    pub fn save_at(&self, version: u64) -> Result<Self> {
+        self.save_with(Some(version))
+    }
+
+    fn save_with(&self, version: Option<u64>) -> Result<Self> {
+        let mut batch = self.manager.batch()?;
+        if let Some(version) = version {
+            batch.set_version(version);
+        }
+        let saved = self.save_in(&mut batch)?;
+        batch.set_root(saved.root_ptr());
+        batch.commit()?;
+        Ok(saved)
+    }
+
+    /// Saves several trees of the same node manager like [`Tree::save`], but
+    /// in a single batch, so the store writes and syncs them once and either
+    /// all of them are saved or none are. Returns the saved trees in order.
+    ///
+    /// The batch records no root, since there are several, so stores which
+    /// keep track of one such as [`WalStore`](crate::tree::node_manager::WalStore)
+    /// keep the previous one.
+    pub fn save_all<'a>(trees: impl IntoIterator<Item = &'a Self>) -> Result<Vec<Self>>
+    where
+        K: 'a,
+        V: 'a,
+    {
+        let mut trees = trees.into_iter().peekable();
+        let Some(first) = trees.peek() else {
+            return Ok(Vec::new());
+        };
+        let manager = first.manager.clone();
+        let mut batch = manager.batch()?;
+        let mut saved = Vec::new();
+        for tree in trees {
+            if !Arc::ptr_eq(&tree.manager, &manager) {
+                return Err(Error::invalid(
+                    "trees saved together must share a node manager",
+                ));
+            }
+            saved.push(tree.save_in(&mut batch)?);
+        }
+        batch.commit()?;
+        Ok(saved)
+    }
+
+    /// Stages the writes saving the tree in `batch`, returning the tree
+    /// they store once it is committed.
+    fn save_in(&self, batch: &mut Batch<'_, Node<K, V>>) -> Result<Self> {
+        let root = match &self.root {
+            None => None,
+            Some(NodeRef::Stored(ptr)) => {
+                batch.inc_ref_count(ptr)?;
+                Some(NodeRef::Stored(*ptr))
+            }
+            Some(root) => Some(NodeRef::Stored(Node::save_in(batch, root)?)),
+        };
+        Ok(Tree {
+            root,
+            manager: self.manager.clone(),
+        })
+    }
+}
+
+impl<K: Ord + Clone + Hashable, V: Clone + Hashable> Tree<K, V> {
+    /// Sets the value of an existing `key` in place, for transient trees
+    /// which are saved after every edit. The stored nodes on the path to the
+    /// key are rewritten under their current pointers instead of being
+    /// copied, so the root pointer stays the same and every handle to this
+    /// tree sees the new value.
+    ///
+    /// Returns `false` without changing anything if the key is absent, the
+    /// root belongs to a version recorded by a
+    /// [`VersionedTree`](versioned::VersionedTree) or is referenced more than
+    /// once, the path has unsaved or shared nodes, or the store can't rewrite
+    /// nodes, in which case [`Tree::insert`] and [`Tree::save`] do the
+    /// update. Recorded versions thus keep the hashes they recorded.
+    pub fn try_update<Q>(&self, key: &Q, value: V) -> Result<bool>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Node::try_update(&self.manager, &self.root, key, value)
+    }
+}
+
+impl<K: Hashable, V: Hashable> Tree<K, V> {
+    /// Computes the root hash like [`MerkleTree::merkle_hash`], calling
+    /// `progress` after each node hashed. Returning [`ControlFlow::Break`]
+    /// from `progress` cancels the computation with an error. The hashes of
+    /// the subtrees completed before cancellation stay cached in the nodes,
+    /// so a later call resumes roughly where the cancelled one stopped.
+    pub fn merkle_hash_with_progress(
+        &self,
+        mut progress: impl FnMut(HashProgress) -> ControlFlow<()>,
+    ) -> Result<Digest> {
+        let Some(root) = &self.root else {
+            return Ok(EMPTY_HASH);
+        };
+        let root = self.manager.read(root)?;
+        if let Some(hash) = root.hash.get() {
+            return Ok(*hash);
+        }
+        let estimated_total = self.cardinality()?.value();
+        let mut hashed = 0;
+        root.hash_with(&self.manager, &mut || {
+            hashed += 1;
+            match progress(HashProgress {
+                hashed,
+                estimated_total,
+            }) {
+                ControlFlow::Continue(()) => Ok(()),
+                ControlFlow::Break(()) => Err(Error::Cancelled("merkle hash cancelled".into())),
+            }
+        })
+    }
+}
+
+impl<K: Hashable, V: Hashable> MerkleTree for Tree<K, V> {
+    fn merkle_hash(&self) -> Result<Digest> {
+        match &self.root {
+            None => Ok(EMPTY_HASH),
+            Some(root) => self.manager.read(root)?.hash(&self.manager),
+        }
+    }
+}
+
+impl<K: Ord + Clone + Hashable, V: Hashable> Tree<K, V> {
+    /// Checks the integrity of the tree by reloading every node and
+    /// recomputing its hash, height and key order, without relying on cached
+    /// hashes. Returns the recomputed root hash. Broken invariants are
+    /// handled according to the manager's
+    /// [`CorruptionPolicy`](crate::tree::node_manager::CorruptionPolicy).
+    pub fn verify(&self) -> Result<Digest> {
+        match &self.root {
+            None => Ok(EMPTY_HASH),
+            Some(root) => Ok(Node::verify(&self.manager, root, &mut HashMap::new())?.hash),
+        }
+    }
+}
+
+impl<K: Ord + Clone + Hashable, V: Hashable> Tree<K, V> {
+    /// Iterates over the entries whose keys are in `range` in key order,
+    /// each with a proof of its inclusion. Cheaper than proving the entries
+    /// one by one since the proofs share the traversal, see
+    /// [`ProvenRange`].
+    pub fn range_with_proofs(&self, range: impl RangeBounds<K>) -> Result<ProvenRange<'_, K, V>> {
+        ProvenRange::new(&self.manager, &self.root, range)
+    }
+}
+
+impl<K: Ord + Hashable, V: Hashable> Tree<K, V> {
+    /// Returns a proof that `key` is in the tree, or `None` if it isn't.
+    pub fn prove<Q>(&self, key: &Q) -> Result<Option<Proof>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        proof::prove(&self.manager, &self.root, key)
+    }
+
+    /// Returns the nodes on the path from the root towards `key`, nearest to
+    /// the key first, with the hashes each node's hash is computed from. The
+    /// first node holds the key if it is present. Meant for tracking down
+    /// where two implementations start to disagree on a root hash.
+    pub fn hash_path<Q>(&self, key: &Q) -> Result<Vec<PathNode>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        proof::hash_path(&self.manager, &self.root, key)
+    }
+
+    /// Proves each of `keys`, returning the proofs in the same order.
+    pub fn prove_batch<Q>(&self, keys: &[Q]) -> Result<Vec<Option<Proof>>>
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+    {
+        keys.iter().map(|key| self.prove(key)).collect()
+    }
+
+    /// Like [`Tree::prove_batch`] but generates the proofs in parallel on
+    /// `pool`. The workers share the tree's node cache and the hashes cached
+    /// in the nodes, so each node is loaded and hashed only once no matter
+    /// how many proofs pass through it. Requires the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn prove_batch_in<Q>(
+        &self,
+        pool: &rayon::ThreadPool,
+        keys: &[Q],
+    ) -> Result<Vec<Option<Proof>>>
+    where
+        K: Borrow<Q> + Send + Sync,
+        V: Send + Sync,
+        Q: Ord + Sync,
+    {
+        use rayon::prelude::*;
+
+        pool.install(|| {
+            // Hashing the root first fills the cache top-down in one pass
+            // instead of having the workers race to hash the same subtrees.
+            self.merkle_hash()?;
+            keys.par_iter().map(|key| self.prove(key)).collect()
+        })
+    }
+}
@@ -0,0 +1,1169 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, OnceLock};
+
+use crate::tree::hash::{hash_of, hash_parts, Digest, HashVersion, Hashable, Update, EMPTY_HASH};
+use crate::tree::node_manager::{
+    Batch, MappedNodeHandle, NodeHandle, NodeManager, NodeRef, Ptr, TreeNode, WriteOrder,
+};
+use crate::{Error, Result};
+
+/// A possibly empty subtree.
+pub type Link<K, V> = Option<NodeRef<Node<K, V>>>;
+
+/// The node manager of an AVL tree.
+pub type Manager<K, V> = NodeManager<Node<K, V>>;
+
+/// A value borrowed from a loaded node, returned by
+/// [`Tree::get_ref`](super::Tree::get_ref).
+pub type ValueHandle<K, V> = MappedNodeHandle<Node<K, V>, V>;
+
+/// An immutable AVL tree node. Every node holds an entry; modifications copy
+/// the path from the root to the modified node and share everything else.
+///
+/// With the `serde` feature, nodes whose keys and values implement serde's
+/// traits do as well, so they can be kept in byte-oriented stores through a
+/// [`NodeCodec`](crate::tree::node_manager::NodeCodec). Only nodes whose
+/// children are saved can be serialized.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Node<K, V> {
+    pub(crate) key: K,
+    pub(crate) value: V,
+    pub(crate) height: u8,
+    /// The number of entries in this subtree.
+    pub(crate) size: u64,
+    pub(crate) left: Link<K, V>,
+    pub(crate) right: Link<K, V>,
+    /// The merkle hash of this subtree, computed on first use. Nodes are
+    /// immutable so it never has to be invalidated.
+    #[cfg_attr(feature = "serde", serde(with = "memoized_hash"))]
+    pub(crate) hash: OnceLock<Digest>,
+    /// The layout of `hash`, taken from the manager which created the node.
+    pub(crate) hash_version: HashVersion,
+}
+
+/// Serializes the hash of a node if it was computed, so that it needn't be
+/// recomputed from the children when the node is read back.
+#[cfg(feature = "serde")]
+mod memoized_hash {
+    use std::sync::OnceLock;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::tree::hash::Digest;
+
+    pub fn serialize<S: Serializer>(
+        hash: &OnceLock<Digest>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        hash.get().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<OnceLock<Digest>, D::Error> {
+        Ok(Option::<Digest>::deserialize(deserializer)?.map_or_else(OnceLock::new, OnceLock::from))
+    }
+}
+
+/// The height and number of entries of a possibly empty subtree.
+#[derive(Clone, Copy, Default, Debug)]
+pub(crate) struct Shape {
+    pub(crate) height: u8,
+    pub(crate) size: u64,
+}
+
+impl Shape {
+    /// The shape of a subtree with an entry above subtrees of shapes `left`
+    /// and `right`.
+    pub(crate) fn join(left: Shape, right: Shape) -> Shape {
+        Shape {
+            height: 1 + left.height.max(right.height),
+            size: left.size + right.size + 1,
+        }
+    }
+}
+
+pub(crate) fn shape<K, V>(m: &Manager<K, V>, link: &Link<K, V>) -> Result<Shape> {
+    Ok(match link {
+        None => Shape::default(),
+        Some(node) => m.read(node)?.shape(),
+    })
+}
+
+pub(crate) fn same_link<K, V>(a: &Link<K, V>, b: &Link<K, V>) -> bool {
+    match (a, b) {
+        (None, None) => true,
+        (Some(a), Some(b)) => a.same_node(b),
+        _ => false,
+    }
+}
+
+/// Reads the root of a subtree, if any.
+fn read_link<K, V>(m: &Manager<K, V>, link: &Link<K, V>) -> Result<Option<NodeHandle<Node<K, V>>>> {
+    link.as_ref().map(|node| m.read(node)).transpose()
+}
+
+/// Reads the node `depth` levels down a path from the root of a subtree.
+/// Only a missing child, below the root, is reported as corruption.
+pub(crate) fn read_on_path<K, V>(
+    m: &Manager<K, V>,
+    node: &NodeRef<Node<K, V>>,
+    depth: usize,
+) -> Result<NodeHandle<Node<K, V>>> {
+    match depth {
+        0 => m.read(node),
+        _ => m.read_child(node),
+    }
+}
+
+/// Like [`NodeManager::in_child`] for a link, which may be empty.
+fn in_child<K, V, T>(m: &Manager<K, V>, child: &Link<K, V>, result: Result<T>) -> Result<T> {
+    match child {
+        Some(child) => m.in_child(child, result),
+        None => result,
+    }
+}
+
+fn shape_of<K, V>(node: &Option<NodeHandle<Node<K, V>>>) -> Shape {
+    node.as_ref().map_or(Shape::default(), |node| node.shape())
+}
+
+fn mem<K, V>(node: Node<K, V>) -> Link<K, V> {
+    Some(NodeRef::Mem(Arc::new(node)))
+}
+
+impl<K, V> Node<K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    pub fn height(&self) -> u8 {
+        self.height
+    }
+
+    /// Returns the number of entries in the subtree rooted at this node.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub(crate) fn shape(&self) -> Shape {
+        Shape {
+            height: self.height,
+            size: self.size,
+        }
+    }
+
+    pub fn left(&self) -> Option<&NodeRef<Node<K, V>>> {
+        self.left.as_ref()
+    }
+
+    pub fn right(&self) -> Option<&NodeRef<Node<K, V>>> {
+        self.right.as_ref()
+    }
+
+    fn new(
+        key: K,
+        value: V,
+        left: Link<K, V>,
+        right: Link<K, V>,
+        m: &Manager<K, V>,
+    ) -> Result<Self> {
+        let (ls, rs) = (shape(m, &left)?, shape(m, &right)?);
+        Ok(Node::with_shapes(key, value, (left, ls), (right, rs), m))
+    }
+
+    /// Like [`Node::new`] with the shapes of the children already known,
+    /// which saves reading them.
+    fn with_shapes(
+        key: K,
+        value: V,
+        (left, ls): (Link<K, V>, Shape),
+        (right, rs): (Link<K, V>, Shape),
+        m: &Manager<K, V>,
+    ) -> Self {
+        Node::with_shape(key, value, left, right, Shape::join(ls, rs), m)
+    }
+
+    fn with_shape(
+        key: K,
+        value: V,
+        left: Link<K, V>,
+        right: Link<K, V>,
+        shape: Shape,
+        m: &Manager<K, V>,
+    ) -> Self {
+        Node {
+            key,
+            value,
+            height: shape.height,
+            size: shape.size,
+            left,
+            right,
+            hash: OnceLock::new(),
+            hash_version: m.hash_version(),
+        }
+    }
+
+    /// Builds a perfectly balanced subtree from the next `len` entries of
+    /// `entries`, which must be sorted by key without duplicates.
+    pub(crate) fn build(
+        m: &Manager<K, V>,
+        entries: &mut impl Iterator<Item = (K, V)>,
+        len: usize,
+    ) -> Result<Link<K, V>> {
+        if len == 0 {
+            return Ok(None);
+        }
+        let left = Node::build(m, entries, len / 2)?;
+        let (key, value) = entries.next().expect("fewer entries than announced");
+        let right = Node::build(m, entries, len - len / 2 - 1)?;
+        Ok(mem(Node::new(key, value, left, right, m)?))
+    }
+}
+
+impl<K: Ord, V> Node<K, V> {
+    /// Loads the node holding `key` in the subtree `link`.
+    pub(crate) fn find<Q>(
+        m: &Manager<K, V>,
+        link: &Link<K, V>,
+        key: &Q,
+    ) -> Result<Option<NodeHandle<Node<K, V>>>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let (mut link, mut depth) = (link.clone(), 0);
+        while let Some(node) = link {
+            let node = read_on_path(m, &node, depth)?;
+            depth += 1;
+            link = match key.cmp(node.key.borrow()) {
+                Ordering::Less => node.left.clone(),
+                Ordering::Greater => node.right.clone(),
+                Ordering::Equal => return Ok(Some(node)),
+            };
+        }
+        Ok(None)
+    }
+
+    /// Loads the node holding the entry at `index` in key order in the
+    /// subtree `link`, descending by subtree sizes.
+    pub(crate) fn nth(
+        m: &Manager<K, V>,
+        link: &Link<K, V>,
+        mut index: u64,
+    ) -> Result<Option<NodeHandle<Node<K, V>>>> {
+        let (mut link, mut depth) = (link.clone(), 0);
+        while let Some(node) = link {
+            let node = read_on_path(m, &node, depth)?;
+            depth += 1;
+            let left = shape(m, &node.left)?.size;
+            link = match index.cmp(&left) {
+                Ordering::Less => node.left.clone(),
+                Ordering::Equal => return Ok(Some(node)),
+                Ordering::Greater => {
+                    index -= left + 1;
+                    node.right.clone()
+                }
+            };
+        }
+        Ok(None)
+    }
+
+    /// Loads the node holding the smallest key of the subtree `link`, or
+    /// the largest one if `last`.
+    pub(crate) fn edge(
+        m: &Manager<K, V>,
+        link: &Link<K, V>,
+        last: bool,
+    ) -> Result<Option<NodeHandle<Node<K, V>>>> {
+        let Some(node) = link else {
+            return Ok(None);
+        };
+        let mut node = m.read(node)?;
+        while let Some(child) = if last { &node.right } else { &node.left } {
+            node = m.read_child(child)?;
+        }
+        Ok(Some(node))
+    }
+
+    /// Loads the node holding the smallest key greater than `key` in the
+    /// subtree `link`, or the largest key less than `key` if `before`.
+    pub(crate) fn neighbor<Q>(
+        m: &Manager<K, V>,
+        link: &Link<K, V>,
+        key: &Q,
+        before: bool,
+    ) -> Result<Option<NodeHandle<Node<K, V>>>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let toward = if before {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        };
+        let mut found = None;
+        let (mut link, mut depth) = (link.clone(), 0);
+        while let Some(node) = link {
+            let node = read_on_path(m, &node, depth)?;
+            depth += 1;
+            // Keys on the far side of `key` are candidates, the nearest of
+            // which is the last one passed on the way down.
+            link = if node.key.borrow().cmp(key) == toward {
+                let next = if before {
+                    node.right.clone()
+                } else {
+                    node.left.clone()
+                };
+                found = Some(node);
+                next
+            } else if before {
+                node.left.clone()
+            } else {
+                node.right.clone()
+            };
+        }
+        Ok(found)
+    }
+
+    /// Counts the keys less than `key` in the subtree `link`.
+    pub(crate) fn rank<Q>(m: &Manager<K, V>, link: &Link<K, V>, key: &Q) -> Result<u64>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut rank = 0;
+        let (mut link, mut depth) = (link.clone(), 0);
+        while let Some(node) = link {
+            let node = read_on_path(m, &node, depth)?;
+            depth += 1;
+            link = match key.cmp(node.key.borrow()) {
+                Ordering::Less => node.left.clone(),
+                Ordering::Equal => return Ok(rank + shape(m, &node.left)?.size),
+                Ordering::Greater => {
+                    rank += shape(m, &node.left)?.size + 1;
+                    node.right.clone()
+                }
+            };
+        }
+        Ok(rank)
+    }
+}
+
+impl<K, V> TreeNode for Node<K, V> {
+    fn stored_children(&self) -> Vec<Ptr> {
+        [&self.left, &self.right]
+            .into_iter()
+            .filter_map(|child| child.as_ref()?.ptr())
+            .collect()
+    }
+}
+
+const LEAF_TAG: u8 = 0;
+const INNER_TAG: u8 = 1;
+
+/// The part of a node's hash which commits to its entry.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EntryHash {
+    /// The hashes of the key and value, hashed into the node as they are
+    /// with [`HashVersion::V0`].
+    Split { key: Digest, value: Digest },
+    /// The hash of the entry as a leaf, with [`HashVersion::V1`].
+    Leaf(Digest),
+}
+
+/// Hashes the entry of a node.
+///
+/// With [`HashVersion::V1`] the entry hashes as the leaf
+/// `SHA-256(0x00 || 0x20 || H(key) || 0x20 || H(value))`, i.e. the key and
+/// value hashes each prefixed with their length as a protobuf varint.
+pub fn entry_hash<K: Hashable + ?Sized, V: Hashable + ?Sized>(
+    version: HashVersion,
+    key: &K,
+    value: &V,
+) -> EntryHash {
+    entry_hash_of(version, hash_of(key), hash_of(value))
+}
+
+/// Hashes an entry from the hashes of its key and value.
+fn entry_hash_of(version: HashVersion, key: Digest, value: Digest) -> EntryHash {
+    match version {
+        HashVersion::V0 => EntryHash::Split { key, value },
+        HashVersion::V1 => EntryHash::Leaf(hash_parts(&[&[LEAF_TAG, 32], &key, &[32], &value])),
+    }
+}
+
+/// Computes the hash of a node from the hashes of its children and entry,
+/// where missing children are represented by [`EMPTY_HASH`].
+///
+/// With [`HashVersion::V1`] the hash is `SHA-256(0x01 || left || leaf ||
+/// right)`, so no node hash is the hash of a leaf as well. With
+/// [`HashVersion::V0`] it is `SHA-256(left || H(key) || H(value) || right)`.
+pub fn node_hash(left: &Digest, entry: &EntryHash, right: &Digest) -> Digest {
+    match entry {
+        EntryHash::Split { key, value } => hash_parts(&[left, key, value, right]),
+        EntryHash::Leaf(leaf) => hash_parts(&[&[INNER_TAG], left, leaf, right]),
+    }
+}
+
+pub(crate) fn link_hash<K: Hashable, V: Hashable>(
+    m: &Manager<K, V>,
+    link: &Link<K, V>,
+) -> Result<Digest> {
+    link_hash_with(m, link, &mut || Ok(()))
+}
+
+fn link_hash_with<K: Hashable, V: Hashable>(
+    m: &Manager<K, V>,
+    link: &Link<K, V>,
+    on_hashed: &mut dyn FnMut() -> Result<()>,
+) -> Result<Digest> {
+    match link {
+        None => Ok(EMPTY_HASH),
+        Some(node) => m.read_child(node)?.hash_with(m, on_hashed),
+    }
+}
+
+impl<K: Hashable, V: Hashable> Node<K, V> {
+    pub fn entry_hash(&self) -> EntryHash {
+        entry_hash(self.hash_version, &self.key, &self.value)
+    }
+
+    /// Like [`Node::entry_hash`], hashing the value through `m` so that a
+    /// memoized value hash is reused.
+    pub(crate) fn entry_hash_in(&self, m: &Manager<K, V>) -> EntryHash {
+        entry_hash_of(
+            self.hash_version,
+            hash_of(&self.key),
+            m.value_hash(&self.value),
+        )
+    }
+
+    /// Returns the merkle hash of the subtree rooted at this node.
+    pub fn hash(&self, m: &Manager<K, V>) -> Result<Digest> {
+        self.hash_with(m, &mut || Ok(()))
+    }
+
+    /// Like [`Node::hash`], calling `on_hashed` after each node whose hash
+    /// wasn't cached yet. An error from `on_hashed` aborts the computation,
+    /// keeping the hashes of the subtrees completed so far.
+    pub(crate) fn hash_with(
+        &self,
+        m: &Manager<K, V>,
+        on_hashed: &mut dyn FnMut() -> Result<()>,
+    ) -> Result<Digest> {
+        if let Some(hash) = self.hash.get() {
+            return Ok(*hash);
+        }
+        let hash = node_hash(
+            &link_hash_with(m, &self.left, on_hashed)?,
+            &self.entry_hash_in(m),
+            &link_hash_with(m, &self.right, on_hashed)?,
+        );
+        let hash = *self.hash.get_or_init(|| hash);
+        on_hashed()?;
+        Ok(hash)
+    }
+}
+
+/// Hashes a node as stored, laid out as in [`node_hash`] with its
+/// children's pointers in place of their hashes. When pointers are the
+/// children's hashes, as in a
+/// [`ContentAddressedStore`](crate::tree::node_manager::ContentAddressedStore),
+/// this is the node's merkle hash.
+///
+/// In-memory children contribute their merkle hash, which must have been
+/// computed with [`Node::hash`] beforehand.
+impl<K: Hashable, V: Hashable> Hashable for Node<K, V> {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        let update_child = |hasher: &mut H, child: &Link<K, V>| match child {
+            None => hasher.update(&EMPTY_HASH),
+            Some(NodeRef::Stored(ptr)) => hasher.update(ptr.as_bytes()),
+            Some(NodeRef::Mem(node)) => hasher.update(
+                node.hash
+                    .get()
+                    .expect("in-memory children are hashed before their parent"),
+            ),
+        };
+        match self.entry_hash() {
+            EntryHash::Split { key, value } => {
+                update_child(hasher, &self.left);
+                hasher.update(&key);
+                hasher.update(&value);
+            }
+            EntryHash::Leaf(leaf) => {
+                hasher.update(&[INNER_TAG]);
+                update_child(hasher, &self.left);
+                hasher.update(&leaf);
+            }
+        }
+        update_child(hasher, &self.right);
+    }
+}
+
+/// A subtree checked by [`Node::verify`].
+#[derive(Clone)]
+pub(crate) struct Verified<K> {
+    pub(crate) hash: Digest,
+    shape: Shape,
+    min: K,
+    max: K,
+}
+
+impl<K: Ord + Clone + Hashable, V: Hashable> Node<K, V> {
+    /// Checks the subtree `link` and returns its recomputed hash. Every
+    /// node's hash is recomputed from its entry and children, ignoring and
+    /// checking cached hashes, along with its height, size, balance and key
+    /// order.
+    ///
+    /// Stored subtrees are recorded in `verified`, so subtrees shared between
+    /// several checked versions are only checked once.
+    pub(crate) fn verify(
+        m: &Manager<K, V>,
+        link: &NodeRef<Node<K, V>>,
+        verified: &mut HashMap<Ptr, Verified<K>>,
+    ) -> Result<Verified<K>> {
+        if let Some(done) = link.ptr().and_then(|ptr| verified.get(&ptr)) {
+            return Ok(done.clone());
+        }
+        let node = m.read(link)?;
+        let mut child = |child: &Link<K, V>| {
+            child
+                .as_ref()
+                .map(|c| m.in_child(c, Node::verify(m, c, verified)))
+                .transpose()
+        };
+        let (left, right) = (child(&node.left)?, child(&node.right)?);
+        let shape =
+            |child: &Option<Verified<K>>| child.as_ref().map_or(Shape::default(), |c| c.shape);
+        let (ls, rs) = (shape(&left), shape(&right));
+        if node.height != 1 + ls.height.max(rs.height) || ls.height.abs_diff(rs.height) > 1 {
+            return Err(m.corrupted(Error::corruption(format!(
+                "node {:?} has height {} over children of height {} and {}",
+                link.ptr(),
+                node.height,
+                ls.height,
+                rs.height
+            ))));
+        }
+        if node.size != ls.size + rs.size + 1 {
+            return Err(m.corrupted(Error::corruption(format!(
+                "node {:?} has size {} over children of size {} and {}",
+                link.ptr(),
+                node.size,
+                ls.size,
+                rs.size
+            ))));
+        }
+        if left.as_ref().is_some_and(|l| l.max >= node.key)
+            || right.as_ref().is_some_and(|r| r.min <= node.key)
+        {
+            return Err(m.corrupted(Error::corruption(format!(
+                "keys under node {:?} are out of order",
+                link.ptr()
+            ))));
+        }
+        let child_hash =
+            |child: &Option<Verified<K>>| child.as_ref().map_or(EMPTY_HASH, |c| c.hash);
+        let hash = node_hash(
+            &child_hash(&left),
+            &node.entry_hash_in(m),
+            &child_hash(&right),
+        );
+        if node.hash.get().is_some_and(|cached| *cached != hash) {
+            return Err(m.corrupted(Error::corruption(format!(
+                "node {:?} has a stale cached hash",
+                link.ptr()
+            ))));
+        }
+        let result = Verified {
+            hash,
+            shape: node.shape(),
+            min: left.map_or_else(|| node.key.clone(), |l| l.min),
+            max: right.map_or_else(|| node.key.clone(), |r| r.max),
+        };
+        if let Some(ptr) = link.ptr() {
+            verified.insert(ptr, result.clone());
+        }
+        Ok(result)
+    }
+}
+
+impl<K: Clone + Hashable, V: Clone + Hashable> Node<K, V> {
+    /// Persists every in-memory node of the subtree `link`, children first,
+    /// and returns the pointer to its root. New nodes start with a reference
+    /// count of 1 for their parent, and already stored children gain a
+    /// reference from each new parent. A stored `link` itself is returned
+    /// as-is, leaving its reference count to the caller. The nodes are
+    /// written in a single [`Batch`], so either all of them are stored or
+    /// none are.
+    pub(crate) fn save(m: &Manager<K, V>, link: &NodeRef<Node<K, V>>) -> Result<Ptr> {
+        let mut batch = m.batch()?;
+        let ptr = Node::save_in(&mut batch, link)?;
+        batch.commit()?;
+        Ok(ptr)
+    }
+
+    /// Stages the nodes [`Node::save`] writes in `batch`, in the manager's
+    /// [`WriteOrder`].
+    pub(crate) fn save_in(
+        batch: &mut Batch<'_, Node<K, V>>,
+        link: &NodeRef<Node<K, V>>,
+    ) -> Result<Ptr> {
+        let node = match link {
+            NodeRef::Stored(ptr) => return Ok(*ptr),
+            NodeRef::Mem(node) => node,
+        };
+        // Hash before the children are replaced by pointers, so the hashes of
+        // in-memory children don't have to be reloaded from the store.
+        node.hash(batch.manager())?;
+        match batch.manager().write_order() {
+            WriteOrder::PostOrder => Node::save_post_order(batch, node),
+            WriteOrder::LevelOrder => Node::save_level_order(batch, node),
+        }
+    }
+
+    fn save_post_order(batch: &mut Batch<'_, Node<K, V>>, node: &Arc<Node<K, V>>) -> Result<Ptr> {
+        let mut save_child = |child: &Link<K, V>| -> Result<Link<K, V>> {
+            Ok(match child {
+                None => None,
+                Some(NodeRef::Stored(ptr)) => {
+                    batch.inc_ref_count(ptr)?;
+                    Some(NodeRef::Stored(*ptr))
+                }
+                Some(NodeRef::Mem(child)) => {
+                    Some(NodeRef::Stored(Node::save_post_order(batch, child)?))
+                }
+            })
+        };
+        let left = save_child(&node.left)?;
+        let right = save_child(&node.right)?;
+        batch.insert(node.stored(left, right))
+    }
+
+    fn save_level_order(batch: &mut Batch<'_, Node<K, V>>, root: &Arc<Node<K, V>>) -> Result<Ptr> {
+        let mut levels = vec![vec![root.clone()]];
+        loop {
+            let next: Vec<_> = levels[levels.len() - 1]
+                .iter()
+                .flat_map(|node| [&node.left, &node.right])
+                .filter_map(|child| match child {
+                    Some(NodeRef::Mem(child)) => Some(child.clone()),
+                    _ => None,
+                })
+                .collect();
+            if next.is_empty() {
+                break;
+            }
+            levels.push(next);
+        }
+        // The pointers of the staged nodes, by their address in memory.
+        let mut ptrs: HashMap<*const Node<K, V>, Ptr> = HashMap::new();
+        for level in levels.iter().rev() {
+            for node in level {
+                let mut save_child = |child: &Link<K, V>| -> Result<Link<K, V>> {
+                    Ok(match child {
+                        None => None,
+                        Some(NodeRef::Stored(ptr)) => {
+                            batch.inc_ref_count(ptr)?;
+                            Some(NodeRef::Stored(*ptr))
+                        }
+                        Some(NodeRef::Mem(child)) => {
+                            Some(NodeRef::Stored(ptrs[&Arc::as_ptr(child)]))
+                        }
+                    })
+                };
+                let left = save_child(&node.left)?;
+                let right = save_child(&node.right)?;
+                let ptr = batch.insert(node.stored(left, right))?;
+                ptrs.insert(Arc::as_ptr(node), ptr);
+            }
+        }
+        Ok(ptrs[&Arc::as_ptr(root)])
+    }
+
+    /// Returns a copy of the node with its children replaced by `left` and
+    /// `right`, to be stored.
+    fn stored(&self, left: Link<K, V>, right: Link<K, V>) -> Node<K, V> {
+        Node {
+            key: self.key.clone(),
+            value: self.value.clone(),
+            height: self.height,
+            size: self.size,
+            left,
+            right,
+            hash: self.hash.clone(),
+            hash_version: self.hash_version,
+        }
+    }
+}
+
+impl<K: Ord + Clone + Hashable, V: Clone + Hashable> Node<K, V> {
+    /// Sets the value of `key` by rewriting the stored nodes on the path to
+    /// it in place, keeping their pointers. Returns `false` without changing
+    /// anything if the key is absent, the root is the root of a recorded
+    /// version or doesn't have a single reference, any node on the path isn't
+    /// stored or is shared with another version, or the store can't rewrite
+    /// nodes.
+    pub(crate) fn try_update<Q>(
+        m: &Manager<K, V>,
+        root: &Link<K, V>,
+        key: &Q,
+        value: V,
+    ) -> Result<bool>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        // Rewriting a recorded version would change the hash it recorded.
+        if let Some(NodeRef::Stored(root)) = root {
+            if m.is_version_root(root)? || m.ref_count(root)? != Some(1) {
+                return Ok(false);
+            }
+        }
+        let mut path = Vec::new();
+        let mut link = root.clone();
+        while let Some(node_ref) = link {
+            let NodeRef::Stored(ptr) = node_ref else {
+                return Ok(false);
+            };
+            let node = read_on_path(m, &node_ref, path.len())?;
+            let order = key.cmp(node.key.borrow());
+            link = match order {
+                Ordering::Less => node.left.clone(),
+                Ordering::Greater => node.right.clone(),
+                Ordering::Equal => None,
+            };
+            path.push((ptr, node, order));
+        }
+        if !matches!(path.last(), Some((_, _, Ordering::Equal))) {
+            return Ok(false);
+        }
+        // Rehash bottom-up. Children off the path keep their hashes, while
+        // the child on the path has the hash computed in the previous step.
+        let mut value = Some(value);
+        let mut child_hash = EMPTY_HASH;
+        let mut updates = Vec::with_capacity(path.len());
+        for (ptr, node, order) in path.into_iter().rev() {
+            let mut node = node.clone_inner();
+            if order == Ordering::Equal {
+                node.value = value.take().expect("only the last node holds the key");
+            }
+            let left = match order {
+                Ordering::Less => child_hash,
+                _ => link_hash(m, &node.left)?,
+            };
+            let right = match order {
+                Ordering::Greater => child_hash,
+                _ => link_hash(m, &node.right)?,
+            };
+            child_hash = node_hash(&left, &node.entry_hash_in(m), &right);
+            node.hash = OnceLock::from(child_hash);
+            updates.push((ptr, node));
+        }
+        m.try_update(updates)
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Node<K, V> {
+    /// Builds a node from an entry and two subtrees whose heights differ by
+    /// at most two, rotating as needed to restore the AVL invariant. Every
+    /// node involved is read once, with shapes threaded through from there.
+    fn balance(
+        m: &Manager<K, V>,
+        key: K,
+        value: V,
+        left: Link<K, V>,
+        right: Link<K, V>,
+    ) -> Result<Self> {
+        let (l, r) = (read_link(m, &left)?, read_link(m, &right)?);
+        let (ls, rs) = (shape_of(&l), shape_of(&r));
+        if ls.height > rs.height + 1 {
+            let l = l.expect("left subtree is taller than right");
+            let (ll, lr) = (read_link(m, &l.left)?, read_link(m, &l.right)?);
+            let (lls, lrs) = (shape_of(&ll), shape_of(&lr));
+            if lls.height >= lrs.height {
+                let right = Node::with_shapes(key, value, (l.right.clone(), lrs), (right, rs), m);
+                let rs = right.shape();
+                Ok(Node::with_shapes(
+                    l.key.clone(),
+                    l.value.clone(),
+                    (l.left.clone(), lls),
+                    (mem(right), rs),
+                    m,
+                ))
+            } else {
+                let lr = lr.expect("left-right subtree is taller");
+                let (lrl, lrr) = (shape(m, &lr.left)?, shape(m, &lr.right)?);
+                let left = Node::with_shapes(
+                    l.key.clone(),
+                    l.value.clone(),
+                    (l.left.clone(), lls),
+                    (lr.left.clone(), lrl),
+                    m,
+                );
+                let right = Node::with_shapes(key, value, (lr.right.clone(), lrr), (right, rs), m);
+                let (ls, rs) = (left.shape(), right.shape());
+                Ok(Node::with_shapes(
+                    lr.key.clone(),
+                    lr.value.clone(),
+                    (mem(left), ls),
+                    (mem(right), rs),
+                    m,
+                ))
+            }
+        } else if rs.height > ls.height + 1 {
+            let r = r.expect("right subtree is taller than left");
+            let (rl, rr) = (read_link(m, &r.left)?, read_link(m, &r.right)?);
+            let (rls, rrs) = (shape_of(&rl), shape_of(&rr));
+            if rrs.height >= rls.height {
+                let left = Node::with_shapes(key, value, (left, ls), (r.left.clone(), rls), m);
+                let ls = left.shape();
+                Ok(Node::with_shapes(
+                    r.key.clone(),
+                    r.value.clone(),
+                    (mem(left), ls),
+                    (r.right.clone(), rrs),
+                    m,
+                ))
+            } else {
+                let rl = rl.expect("right-left subtree is taller");
+                let (rll, rlr) = (shape(m, &rl.left)?, shape(m, &rl.right)?);
+                let left = Node::with_shapes(key, value, (left, ls), (rl.left.clone(), rll), m);
+                let right = Node::with_shapes(
+                    r.key.clone(),
+                    r.value.clone(),
+                    (rl.right.clone(), rlr),
+                    (r.right.clone(), rrs),
+                    m,
+                );
+                let (ls, rs) = (left.shape(), right.shape());
+                Ok(Node::with_shapes(
+                    rl.key.clone(),
+                    rl.value.clone(),
+                    (mem(left), ls),
+                    (mem(right), rs),
+                    m,
+                ))
+            }
+        } else {
+            Ok(Node::with_shapes(key, value, (left, ls), (right, rs), m))
+        }
+    }
+
+    pub(crate) fn get<Q>(m: &Manager<K, V>, link: &Link<K, V>, key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Ok(Node::find(m, link, key)?.map(|node| node.value.clone()))
+    }
+
+    pub(crate) fn insert(m: &Manager<K, V>, link: &Link<K, V>, key: K, value: V) -> Result<Self> {
+        let Some(node) = link else {
+            return Node::new(key, value, None, None, m);
+        };
+        let node = m.read(node)?;
+        match key.cmp(&node.key) {
+            Ordering::Less => {
+                let left = Node::insert(m, &node.left, key, value);
+                let left = mem(in_child(m, &node.left, left)?);
+                Node::balance(
+                    m,
+                    node.key.clone(),
+                    node.value.clone(),
+                    left,
+                    node.right.clone(),
+                )
+            }
+            Ordering::Greater => {
+                let right = Node::insert(m, &node.right, key, value);
+                let right = mem(in_child(m, &node.right, right)?);
+                Node::balance(
+                    m,
+                    node.key.clone(),
+                    node.value.clone(),
+                    node.left.clone(),
+                    right,
+                )
+            }
+            Ordering::Equal => Ok(Node::with_shape(
+                key,
+                value,
+                node.left.clone(),
+                node.right.clone(),
+                node.shape(),
+                m,
+            )),
+        }
+    }
+
+    /// Returns the subtree with `key` removed, or `None` if `key` was not
+    /// present (in which case nothing was copied).
+    pub(crate) fn delete<Q>(
+        m: &Manager<K, V>,
+        link: &Link<K, V>,
+        key: &Q,
+    ) -> Result<Option<Link<K, V>>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let Some(node) = link else {
+            return Ok(None);
+        };
+        let node = m.read(node)?;
+        Ok(match key.cmp(node.key.borrow()) {
+            Ordering::Less => match in_child(m, &node.left, Node::delete(m, &node.left, key))? {
+                None => None,
+                Some(left) => Some(mem(Node::balance(
+                    m,
+                    node.key.clone(),
+                    node.value.clone(),
+                    left,
+                    node.right.clone(),
+                )?)),
+            },
+            Ordering::Greater => match in_child(m, &node.right, Node::delete(m, &node.right, key))?
+            {
+                None => None,
+                Some(right) => Some(mem(Node::balance(
+                    m,
+                    node.key.clone(),
+                    node.value.clone(),
+                    node.left.clone(),
+                    right,
+                )?)),
+            },
+            Ordering::Equal => Some(match (&node.left, &node.right) {
+                (None, right) => right.clone(),
+                (left, None) => left.clone(),
+                (left, Some(right)) => {
+                    let (key, value, right) = m.in_child(right, Node::pop_min(m, right))?;
+                    mem(Node::balance(m, key, value, left.clone(), right)?)
+                }
+            }),
+        })
+    }
+
+    /// Removes the smallest entry of a non-empty subtree, returning it along
+    /// with the remaining subtree.
+    fn pop_min(m: &Manager<K, V>, node: &NodeRef<Node<K, V>>) -> Result<(K, V, Link<K, V>)> {
+        let node = m.read(node)?;
+        match &node.left {
+            None => Ok((node.key.clone(), node.value.clone(), node.right.clone())),
+            Some(left) => {
+                let (key, value, left) = m.in_child(left, Node::pop_min(m, left))?;
+                let rest = Node::balance(
+                    m,
+                    node.key.clone(),
+                    node.value.clone(),
+                    left,
+                    node.right.clone(),
+                )?;
+                Ok((key, value, mem(rest)))
+            }
+        }
+    }
+
+    /// Applies writes sorted by key without duplicates, where `None`
+    /// deletes, to the subtree `link` holding the keys below `upper`. Takes
+    /// the writes below `upper` from `writes`. Each node on a path to a
+    /// written key is rebuilt and rebalanced once, and subtrees without
+    /// writes are shared.
+    pub(crate) fn apply(
+        m: &Manager<K, V>,
+        link: &Link<K, V>,
+        writes: &mut Peekable<impl Iterator<Item = (K, Option<V>)>>,
+        upper: Option<&K>,
+    ) -> Result<Link<K, V>> {
+        let below = |(key, _): &(K, Option<V>)| upper.is_none_or(|upper| key < upper);
+        if !writes.peek().is_some_and(below) {
+            return Ok(link.clone());
+        }
+        let Some(node_ref) = link else {
+            let mut entries = Vec::new();
+            while let Some((key, value)) = writes.next_if(below) {
+                entries.extend(value.map(|value| (key, value)));
+            }
+            let len = entries.len();
+            return Node::build(m, &mut entries.into_iter(), len);
+        };
+        let node = m.read(node_ref)?;
+        let left = Node::apply(m, &node.left, writes, Some(&node.key));
+        let left = in_child(m, &node.left, left)?;
+        let own = writes.next_if(|(key, _)| *key == node.key);
+        let right = Node::apply(m, &node.right, writes, upper);
+        let right = in_child(m, &node.right, right)?;
+        match own {
+            Some((key, Some(value))) => Node::join(m, left, key, value, right),
+            Some((_, None)) => Node::join2(m, left, right),
+            None if same_link(&left, &node.left) && same_link(&right, &node.right) => {
+                Ok(link.clone())
+            }
+            None => Node::join(m, left, node.key.clone(), node.value.clone(), right),
+        }
+    }
+
+    /// Joins two subtrees with an entry ordered between them into a balanced
+    /// subtree, descending the taller side until the heights match. Takes
+    /// O(|height difference|).
+    pub(crate) fn join(
+        m: &Manager<K, V>,
+        left: Link<K, V>,
+        key: K,
+        value: V,
+        right: Link<K, V>,
+    ) -> Result<Link<K, V>> {
+        let (l, r) = (read_link(m, &left)?, read_link(m, &right)?);
+        let (ls, rs) = (shape_of(&l), shape_of(&r));
+        if ls.height > rs.height + 1 {
+            let l = l.expect("left subtree is taller");
+            let joined = Node::join(m, l.right.clone(), key, value, right)?;
+            Ok(mem(Node::balance(
+                m,
+                l.key.clone(),
+                l.value.clone(),
+                l.left.clone(),
+                joined,
+            )?))
+        } else if rs.height > ls.height + 1 {
+            let r = r.expect("right subtree is taller");
+            let joined = Node::join(m, left, key, value, r.left.clone())?;
+            Ok(mem(Node::balance(
+                m,
+                r.key.clone(),
+                r.value.clone(),
+                joined,
+                r.right.clone(),
+            )?))
+        } else {
+            Ok(mem(Node::with_shapes(
+                key,
+                value,
+                (left, ls),
+                (right, rs),
+                m,
+            )))
+        }
+    }
+
+    /// Joins two subtrees where every key of `left` is less than every key of
+    /// `right`.
+    pub(crate) fn join2(
+        m: &Manager<K, V>,
+        left: Link<K, V>,
+        right: Link<K, V>,
+    ) -> Result<Link<K, V>> {
+        match right {
+            None => Ok(left),
+            Some(right) => {
+                let (key, value, right) = Node::pop_min(m, &right)?;
+                Node::join(m, left, key, value, right)
+            }
+        }
+    }
+
+    /// Joins two subtrees with an optional entry ordered between them.
+    fn join_opt(
+        m: &Manager<K, V>,
+        left: Link<K, V>,
+        entry: Option<(K, V)>,
+        right: Link<K, V>,
+    ) -> Result<Link<K, V>> {
+        match entry {
+            Some((key, value)) => Node::join(m, left, key, value, right),
+            None => Node::join2(m, left, right),
+        }
+    }
+
+    /// Removes the entries of a subtree whose keys are in `range` by
+    /// splitting it at both bounds and joining the outer parts, which takes
+    /// O(log n) however many entries are removed.
+    pub(crate) fn delete_range(
+        m: &Manager<K, V>,
+        link: &Link<K, V>,
+        range: &impl RangeBounds<K>,
+    ) -> Result<Link<K, V>> {
+        let (below, rest) = match range.start_bound() {
+            Bound::Unbounded => (None, link.clone()),
+            Bound::Included(start) => {
+                let (less, found, greater) = Node::split(m, link, start)?;
+                (less, Node::join_opt(m, None, found, greater)?)
+            }
+            Bound::Excluded(start) => {
+                let (less, found, greater) = Node::split(m, link, start)?;
+                (Node::join_opt(m, less, found, None)?, greater)
+            }
+        };
+        let above = match range.end_bound() {
+            Bound::Unbounded => None,
+            Bound::Included(end) => Node::split(m, &rest, end)?.2,
+            Bound::Excluded(end) => {
+                let (_, found, greater) = Node::split(m, &rest, end)?;
+                Node::join_opt(m, None, found, greater)?
+            }
+        };
+        Node::join2(m, below, above)
+    }
+
+    /// Splits a subtree into the entries less than `key`, the entry for `key`
+    /// if present, and the entries greater than `key`. Subtrees entirely on
+    /// one side are shared rather than copied.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn split<Q>(
+        m: &Manager<K, V>,
+        link: &Link<K, V>,
+        key: &Q,
+    ) -> Result<(Link<K, V>, Option<(K, V)>, Link<K, V>)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let Some(node) = link else {
+            return Ok((None, None, None));
+        };
+        let node = m.read(node)?;
+        Ok(match key.cmp(node.key.borrow()) {
+            Ordering::Equal => (
+                node.left.clone(),
+                Some((node.key.clone(), node.value.clone())),
+                node.right.clone(),
+            ),
+            Ordering::Less => {
+                let (less, found, greater) =
+                    in_child(m, &node.left, Node::split(m, &node.left, key))?;
+                let greater = Node::join(
+                    m,
+                    greater,
+                    node.key.clone(),
+                    node.value.clone(),
+                    node.right.clone(),
+                )?;
+                (less, found, greater)
+            }
+            Ordering::Greater => {
+                let (less, found, greater) =
+                    in_child(m, &node.right, Node::split(m, &node.right, key))?;
+                let less = Node::join(
+                    m,
+                    node.left.clone(),
+                    node.key.clone(),
+                    node.value.clone(),
+                    less,
+                )?;
+                (less, found, greater)
+            }
+        })
+    }
+}
@@ -0,0 +1,148 @@
+//! Uncommitted modifications layered over a tree.
+//!
+//! An [`OverlayTree`] buffers writes in memory and reads through to the tree
+//! (or overlay) beneath it for keys it hasn't written. Overlays nest as
+//! scopes: [`OverlayTree::begin`] opens a scope over the current one, which is
+//! later either committed into it or reverted, discarding exactly the writes
+//! made in the scope. [`OverlayTree::flatten`] applies every layer to the
+//! base tree and saves the result as a new version.
+
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+
+use super::Tree;
+use crate::tree::hash::Hashable;
+use crate::{Error, Result};
+
+/// What an overlay reads through to.
+#[derive(Clone, Debug)]
+enum Base<K, V> {
+    Tree(Tree<K, V>),
+    Overlay(Box<OverlayTree<K, V>>),
+}
+
+/// A layer of in-memory modifications over a tree or another overlay.
+#[derive(Clone, Debug)]
+pub struct OverlayTree<K, V> {
+    base: Base<K, V>,
+    /// Written values by key, where `None` marks a deletion.
+    writes: BTreeMap<K, Option<V>>,
+}
+
+impl<K, V> OverlayTree<K, V> {
+    /// Creates an empty overlay over `base`.
+    pub fn new(base: Tree<K, V>) -> Self {
+        OverlayTree {
+            base: Base::Tree(base),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Opens a nested scope, returning an empty overlay over this one. Reads
+    /// see the writes of every layer below.
+    pub fn begin(self) -> Self {
+        OverlayTree {
+            base: Base::Overlay(Box::new(self)),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the number of overlays below this one.
+    pub fn depth(&self) -> usize {
+        match &self.base {
+            Base::Tree(_) => 0,
+            Base::Overlay(parent) => parent.depth() + 1,
+        }
+    }
+
+    /// Returns the tree at the bottom of the stack of overlays.
+    pub fn base_tree(&self) -> &Tree<K, V> {
+        match &self.base {
+            Base::Tree(tree) => tree,
+            Base::Overlay(parent) => parent.base_tree(),
+        }
+    }
+
+    /// Whether this layer has any writes.
+    pub fn is_modified(&self) -> bool {
+        !self.writes.is_empty()
+    }
+
+    /// Closes this scope, discarding its writes, and returns the enclosing
+    /// overlay unchanged.
+    pub fn revert(self) -> Result<Self> {
+        match self.base {
+            Base::Tree(_) => Err(Error::invalid(
+                "the outermost overlay has no enclosing scope",
+            )),
+            Base::Overlay(parent) => Ok(*parent),
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> OverlayTree<K, V> {
+    pub fn get<Q>(&self, key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        if let Some(value) = self.writes.get(key) {
+            return Ok(value.clone());
+        }
+        match &self.base {
+            Base::Tree(tree) => tree.get(key),
+            Base::Overlay(parent) => parent.get(key),
+        }
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Ok(self.get(key)?.is_some())
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.writes.insert(key, Some(value));
+    }
+
+    /// Deletes `key`. The deletion is recorded even if the key doesn't exist
+    /// below, which is cheaper than looking it up first.
+    pub fn delete(&mut self, key: K) {
+        self.writes.insert(key, None);
+    }
+
+    /// Closes this scope, moving its writes into the enclosing overlay, which
+    /// is returned. The outermost overlay is committed with
+    /// [`OverlayTree::flatten`] instead.
+    pub fn commit(self) -> Result<Self> {
+        match self.base {
+            Base::Tree(_) => Err(Error::invalid(
+                "the outermost overlay has no enclosing scope",
+            )),
+            Base::Overlay(parent) => {
+                let mut parent = *parent;
+                parent.writes.extend(self.writes);
+                Ok(parent)
+            }
+        }
+    }
+
+    /// Applies the writes of every layer to the base tree, without saving it.
+    fn apply(self) -> Result<Tree<K, V>> {
+        let tree = match self.base {
+            Base::Tree(tree) => tree,
+            Base::Overlay(parent) => parent.apply()?,
+        };
+        tree.apply_writes(self.writes)
+    }
+}
+
+impl<K: Ord + Clone + Hashable, V: Clone + Hashable> OverlayTree<K, V> {
+    /// Applies the writes of every layer to the base tree and saves the
+    /// result as a new version.
+    pub fn flatten(self) -> Result<Tree<K, V>> {
+        self.apply()?.save()
+    }
+}
@@ -0,0 +1,236 @@
+//! Inclusion proofs for entries of an AVL tree.
+//!
+//! A proof holds the hashes needed to recompute the root hash from a single
+//! entry: the child hashes of the node holding the entry, and for every
+//! ancestor its entry hash and the hash of the child not on the path.
+//!
+//! Verification never allocates: [`ProofRef`] borrows the hashes of a proof
+//! from wherever they are kept, and the root is recomputed by folding the
+//! path into a single running digest, so it fits constrained verifiers such
+//! as wasm contracts.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::tree::hash::{Digest, HashVersion, Hashable};
+
+use super::node::{entry_hash, link_hash, node_hash, read_on_path, EntryHash, Link, Manager};
+use crate::Result;
+
+/// Which child of a node a proof path descends into.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// An ancestor of the proven node.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ProofStep {
+    /// The child of this ancestor which leads to the proven node.
+    pub side: Side,
+    pub entry: EntryHash,
+    /// The hash of the other child.
+    pub sibling: Digest,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Proof {
+    /// The layout of the hashes of the tree the proof was created from.
+    pub hash_version: HashVersion,
+    /// The hash of the left child of the proven node.
+    pub left: Digest,
+    /// The hash of the right child of the proven node.
+    pub right: Digest,
+    /// The ancestors of the proven node, nearest first.
+    pub path: Vec<ProofStep>,
+}
+
+impl Proof {
+    /// Borrows the hashes of the proof.
+    pub fn as_ref(&self) -> ProofRef<'_> {
+        ProofRef {
+            hash_version: self.hash_version,
+            left: &self.left,
+            right: &self.right,
+            path: &self.path,
+        }
+    }
+
+    /// Computes the root hash of the tree this proof was created from,
+    /// assuming it contains `key` with `value`.
+    pub fn root_hash<K: Hashable + ?Sized, V: Hashable + ?Sized>(
+        &self,
+        key: &K,
+        value: &V,
+    ) -> Digest {
+        self.as_ref().root_hash(key, value)
+    }
+
+    /// Checks that `key` maps to `value` in the tree with root hash `root`.
+    pub fn verify<K: Hashable + ?Sized, V: Hashable + ?Sized>(
+        &self,
+        root: &Digest,
+        key: &K,
+        value: &V,
+    ) -> bool {
+        self.as_ref().verify(root, key, value)
+    }
+}
+
+/// A [`Proof`] whose hashes are borrowed, e.g. from a decoded message, which
+/// is verified without allocating.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ProofRef<'a> {
+    pub hash_version: HashVersion,
+    pub left: &'a Digest,
+    pub right: &'a Digest,
+    /// The ancestors of the proven node, nearest first.
+    pub path: &'a [ProofStep],
+}
+
+impl ProofRef<'_> {
+    /// Computes the root hash like [`Proof::root_hash`].
+    pub fn root_hash<K: Hashable + ?Sized, V: Hashable + ?Sized>(
+        &self,
+        key: &K,
+        value: &V,
+    ) -> Digest {
+        let entry = entry_hash(self.hash_version, key, value);
+        let node = node_hash(self.left, &entry, self.right);
+        self.path.iter().fold(node, |child, step| match step.side {
+            Side::Left => node_hash(&child, &step.entry, &step.sibling),
+            Side::Right => node_hash(&step.sibling, &step.entry, &child),
+        })
+    }
+
+    /// Checks that `key` maps to `value` in the tree with root hash `root`.
+    pub fn verify<K: Hashable + ?Sized, V: Hashable + ?Sized>(
+        &self,
+        root: &Digest,
+        key: &K,
+        value: &V,
+    ) -> bool {
+        &self.root_hash(key, value) == root
+    }
+}
+
+pub(crate) fn prove<K, V, Q>(m: &Manager<K, V>, link: &Link<K, V>, key: &Q) -> Result<Option<Proof>>
+where
+    K: Borrow<Q> + Hashable,
+    V: Hashable,
+    Q: Ord + ?Sized,
+{
+    let mut path = Vec::new();
+    let mut link = link.clone();
+    while let Some(node) = link {
+        let node = read_on_path(m, &node, path.len())?;
+        let (side, next, sibling) = match key.cmp(node.key.borrow()) {
+            Ordering::Less => (Side::Left, &node.left, &node.right),
+            Ordering::Greater => (Side::Right, &node.right, &node.left),
+            Ordering::Equal => {
+                path.reverse();
+                return Ok(Some(Proof {
+                    hash_version: node.hash_version,
+                    left: link_hash(m, &node.left)?,
+                    right: link_hash(m, &node.right)?,
+                    path,
+                }));
+            }
+        };
+        path.push(ProofStep {
+            side,
+            entry: node.entry_hash(),
+            sibling: link_hash(m, sibling)?,
+        });
+        link = next.clone();
+    }
+    Ok(None)
+}
+
+/// A node on the path from the root towards a key, as reported by
+/// [`Tree::hash_path`](super::Tree::hash_path).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PathNode {
+    /// The distance from the root.
+    pub depth: usize,
+    pub height: u8,
+    /// The child the path continues into, or `None` for the node holding the
+    /// key.
+    pub side: Option<Side>,
+    pub left: Digest,
+    pub entry: EntryHash,
+    pub right: Digest,
+    /// The hash of the node, computed from the three hashes above.
+    pub hash: Digest,
+}
+
+fn hex(digest: &Digest) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl fmt::Display for PathNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = match self.side {
+            None => "found",
+            Some(Side::Left) => "left",
+            Some(Side::Right) => "right",
+        };
+        writeln!(
+            f,
+            "depth {} height {} ({}): {}",
+            self.depth,
+            self.height,
+            side,
+            hex(&self.hash)
+        )?;
+        writeln!(f, "  left  {}", hex(&self.left))?;
+        match &self.entry {
+            EntryHash::Split { key, value } => {
+                writeln!(f, "  key   {}", hex(key))?;
+                writeln!(f, "  value {}", hex(value))?;
+            }
+            EntryHash::Leaf(leaf) => writeln!(f, "  leaf  {}", hex(leaf))?,
+        }
+        write!(f, "  right {}", hex(&self.right))
+    }
+}
+
+pub(crate) fn hash_path<K, V, Q>(
+    m: &Manager<K, V>,
+    link: &Link<K, V>,
+    key: &Q,
+) -> Result<Vec<PathNode>>
+where
+    K: Borrow<Q> + Hashable,
+    V: Hashable,
+    Q: Ord + ?Sized,
+{
+    let mut path = Vec::new();
+    let mut link = link.clone();
+    while let Some(node) = link {
+        let node = read_on_path(m, &node, path.len())?;
+        let side = match key.cmp(node.key.borrow()) {
+            Ordering::Less => Some(Side::Left),
+            Ordering::Greater => Some(Side::Right),
+            Ordering::Equal => None,
+        };
+        path.push(PathNode {
+            depth: path.len(),
+            height: node.height,
+            side,
+            left: link_hash(m, &node.left)?,
+            entry: node.entry_hash(),
+            right: link_hash(m, &node.right)?,
+            hash: node.hash(m)?,
+        });
+        link = match side {
+            None => break,
+            Some(Side::Left) => node.left.clone(),
+            Some(Side::Right) => node.right.clone(),
+        };
+    }
+    path.reverse();
+    Ok(path)
+}
@@ -0,0 +1,339 @@
+//! Iterating over the entries of a tree in key order.
+
+use std::ops::{Bound, RangeBounds};
+
+use crate::tree::hash::Hashable;
+use crate::tree::node_manager::{NodeHandle, NodeRef};
+
+use super::node::{link_hash, Link, Manager, Node};
+use super::proof::{Proof, ProofStep, Side};
+use crate::Result;
+
+/// An iterator over the entries of a tree whose keys are in a range, in key
+/// order, created by [`Tree::range`](super::Tree::range). Only the nodes on
+/// the way to the next entry are held, so iterating a large stored tree
+/// loads nodes as it goes rather than up front.
+///
+/// An error reading a node is yielded once, after which the iterator ends.
+pub struct Range<'a, K, V> {
+    manager: &'a Manager<K, V>,
+    /// The nodes whose entry and right subtree are still to be visited,
+    /// innermost last.
+    stack: Vec<NodeHandle<Node<K, V>>>,
+    lower: Bound<K>,
+    upper: Bound<K>,
+}
+
+impl<'a, K: Ord + Clone, V> Range<'a, K, V> {
+    pub(crate) fn new(
+        manager: &'a Manager<K, V>,
+        root: &Link<K, V>,
+        range: impl RangeBounds<K>,
+    ) -> Result<Self> {
+        let mut range = Range {
+            manager,
+            stack: Vec::new(),
+            lower: range.start_bound().cloned(),
+            upper: range.end_bound().cloned(),
+        };
+        range.descend(root)?;
+        Ok(range)
+    }
+}
+
+impl<K: Ord, V> Range<'_, K, V> {
+    /// Pushes the path from `link` to its smallest key within the lower
+    /// bound, skipping the nodes below it.
+    fn descend(&mut self, link: &Link<K, V>) -> Result<()> {
+        let mut link = link.clone();
+        while let Some(node) = link {
+            let node = self.manager.read(&node)?;
+            if above_lower(&self.lower, &node.key) {
+                link = node.left.clone();
+                self.stack.push(node);
+            } else {
+                link = node.right.clone();
+            }
+        }
+        Ok(())
+    }
+}
+
+fn above_lower<K: Ord>(lower: &Bound<K>, key: &K) -> bool {
+    match lower {
+        Bound::Included(lower) => key >= lower,
+        Bound::Excluded(lower) => key > lower,
+        Bound::Unbounded => true,
+    }
+}
+
+fn below_upper<K: Ord>(upper: &Bound<K>, key: &K) -> bool {
+    match upper {
+        Bound::Included(upper) => key <= upper,
+        Bound::Excluded(upper) => key < upper,
+        Bound::Unbounded => true,
+    }
+}
+
+impl<K: Ord, V> Iterator for Range<'_, K, V> {
+    type Item = Result<NodeHandle<Node<K, V>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        if !below_upper(&self.upper, &node.key) {
+            self.stack.clear();
+            return None;
+        }
+        if let Err(err) = self.descend(&node.right) {
+            self.stack.clear();
+            return Some(Err(err));
+        }
+        Some(Ok(node))
+    }
+}
+
+/// An iterator over the entries of a tree whose keys are in a range, in
+/// chunks of up to `chunk_size` entries in key order, created by
+/// [`Tree::range_chunked`](super::Tree::range_chunked).
+///
+/// To fill a chunk the iterator reads the roots of the next subtrees it
+/// needs, as many as the chunk has entries left to fill, with one
+/// [`NodeManager::read_many`](crate::tree::node_manager::NodeManager::read_many),
+/// and repeats with their children. A chunk thus costs about one batched
+/// read per level of the tree rather than one read per node, which saves
+/// round trips to stores whose
+/// [`NodeStore::read_many`](crate::tree::node_manager::NodeStore::read_many)
+/// answers a batch at once.
+///
+/// An error reading a node is yielded once, after which the iterator ends.
+pub struct ChunkedRange<'a, K, V> {
+    manager: &'a Manager<K, V>,
+    /// The entries and subtrees still to be visited, next last.
+    pending: Vec<Pending<K, V>>,
+    chunk_size: usize,
+    lower: Bound<K>,
+    upper: Bound<K>,
+}
+
+enum Pending<K, V> {
+    Entry(NodeHandle<Node<K, V>>),
+    Subtree(NodeRef<Node<K, V>>),
+}
+
+impl<'a, K: Ord + Clone, V> ChunkedRange<'a, K, V> {
+    pub(crate) fn new(
+        manager: &'a Manager<K, V>,
+        root: &Link<K, V>,
+        range: impl RangeBounds<K>,
+        chunk_size: usize,
+    ) -> Self {
+        ChunkedRange {
+            manager,
+            pending: root.iter().cloned().map(Pending::Subtree).collect(),
+            chunk_size: chunk_size.max(1),
+            lower: range.start_bound().cloned(),
+            upper: range.end_bound().cloned(),
+        }
+    }
+}
+
+impl<K: Ord, V> ChunkedRange<'_, K, V> {
+    fn next_chunk(&mut self) -> Result<Vec<NodeHandle<Node<K, V>>>> {
+        let mut chunk = Vec::with_capacity(self.chunk_size);
+        while chunk.len() < self.chunk_size {
+            match self.pending.pop() {
+                None => break,
+                Some(Pending::Entry(node)) => {
+                    if !below_upper(&self.upper, &node.key) {
+                        self.pending.clear();
+                        break;
+                    }
+                    chunk.push(node);
+                }
+                Some(subtree) => {
+                    self.pending.push(subtree);
+                    self.expand(self.chunk_size - chunk.len())?;
+                }
+            }
+        }
+        Ok(chunk)
+    }
+
+    /// Reads the roots of the next subtrees, stopping once `wanted` entries
+    /// and subtrees are ahead, and replaces each with its entry and the
+    /// children within the range.
+    fn expand(&mut self, wanted: usize) -> Result<()> {
+        let start = self.pending.len().saturating_sub(wanted);
+        let ahead = self.pending.split_off(start);
+        let links: Vec<_> = ahead
+            .iter()
+            .filter_map(|pending| match pending {
+                Pending::Subtree(link) => Some(link.clone()),
+                Pending::Entry(_) => None,
+            })
+            .collect();
+        let mut nodes = self.manager.read_many(&links)?.into_iter();
+        for pending in ahead {
+            let node = match pending {
+                Pending::Entry(node) => {
+                    self.pending.push(Pending::Entry(node));
+                    continue;
+                }
+                Pending::Subtree(_) => nodes.next().expect("a node per subtree"),
+            };
+            let above = above_lower(&self.lower, &node.key);
+            if below_upper(&self.upper, &node.key) {
+                self.pending
+                    .extend(node.right.clone().map(Pending::Subtree));
+            }
+            let left = node.left.clone().filter(|_| above);
+            if above {
+                self.pending.push(Pending::Entry(node));
+            }
+            self.pending.extend(left.map(Pending::Subtree));
+        }
+        Ok(())
+    }
+}
+
+impl<K: Ord, V> Iterator for ChunkedRange<'_, K, V> {
+    type Item = Result<Vec<NodeHandle<Node<K, V>>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_chunk() {
+            Ok(chunk) if chunk.is_empty() => None,
+            Ok(chunk) => Some(Ok(chunk)),
+            Err(err) => {
+                self.pending.clear();
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// An iterator over the entries of a tree whose keys are in a range, each
+/// with a proof of its inclusion, created by
+/// [`Tree::range_with_proofs`](super::Tree::range_with_proofs).
+///
+/// The iterator keeps the whole path from the root to the next entry along
+/// with the proof step of each ancestor, so consecutive entries share the
+/// work of hashing their common ancestors and only the nodes entering the
+/// path are read. A client syncing a range from an untrusted server can
+/// check each entry against the root hash as it arrives.
+///
+/// An error reading a node is yielded once, after which the iterator ends.
+pub struct ProvenRange<'a, K, V> {
+    manager: &'a Manager<K, V>,
+    /// The ancestors of the next entry, root first, each with the step of
+    /// its proof leading towards the entry.
+    path: Vec<(NodeHandle<Node<K, V>>, ProofStep)>,
+    /// The next entry.
+    next: Option<NodeHandle<Node<K, V>>>,
+    upper: Bound<K>,
+}
+
+impl<'a, K: Ord + Clone + Hashable, V: Hashable> ProvenRange<'a, K, V> {
+    pub(crate) fn new(
+        manager: &'a Manager<K, V>,
+        root: &Link<K, V>,
+        range: impl RangeBounds<K>,
+    ) -> Result<Self> {
+        let mut proven = ProvenRange {
+            manager,
+            path: Vec::new(),
+            next: None,
+            upper: range.end_bound().cloned(),
+        };
+        proven.descend(root, &range.start_bound().cloned())?;
+        Ok(proven)
+    }
+}
+
+impl<K: Ord + Hashable, V: Hashable> ProvenRange<'_, K, V> {
+    /// Pushes the path from `link` to its smallest key within `lower`, then
+    /// moves to that entry.
+    fn descend(&mut self, link: &Link<K, V>, lower: &Bound<K>) -> Result<()> {
+        let mut link = link.clone();
+        while let Some(node) = link {
+            let node = self.manager.read(&node)?;
+            let side = match above_lower(lower, &node.key) {
+                true => Side::Left,
+                false => Side::Right,
+            };
+            link = self.push(node, side)?;
+        }
+        self.settle();
+        Ok(())
+    }
+
+    /// Makes `node` an ancestor of the next entry on its `side`, returning
+    /// the child on that side.
+    fn push(&mut self, node: NodeHandle<Node<K, V>>, side: Side) -> Result<Link<K, V>> {
+        let (next, sibling) = match side {
+            Side::Left => (&node.left, &node.right),
+            Side::Right => (&node.right, &node.left),
+        };
+        let step = ProofStep {
+            side,
+            entry: node.entry_hash(),
+            sibling: link_hash(self.manager, sibling)?,
+        };
+        let next = next.clone();
+        self.path.push((node, step));
+        Ok(next)
+    }
+
+    /// Moves to the nearest ancestor whose entry is still to be visited:
+    /// the entries of the ancestors entered on the right come first.
+    fn settle(&mut self) {
+        while let Some((_, step)) = self.path.last() {
+            if step.side == Side::Left {
+                break;
+            }
+            self.path.pop();
+        }
+        self.next = self.path.pop().map(|(node, _)| node);
+    }
+
+    fn advance(&mut self, node: NodeHandle<Node<K, V>>) -> Result<()> {
+        let right = self.push(node, Side::Right)?;
+        self.descend(&right, &Bound::Unbounded)
+    }
+
+    fn prove(&self, node: &Node<K, V>) -> Result<Proof> {
+        Ok(Proof {
+            hash_version: node.hash_version,
+            left: link_hash(self.manager, &node.left)?,
+            right: link_hash(self.manager, &node.right)?,
+            path: self
+                .path
+                .iter()
+                .rev()
+                .map(|(_, step)| step.clone())
+                .collect(),
+        })
+    }
+}
+
+impl<K: Ord + Hashable, V: Hashable> Iterator for ProvenRange<'_, K, V> {
+    type Item = Result<(NodeHandle<Node<K, V>>, Proof)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.next.take()?;
+        if !below_upper(&self.upper, &node.key) {
+            self.path.clear();
+            return None;
+        }
+        let result = self
+            .prove(&node)
+            .and_then(|proof| self.advance(node.clone()).map(|()| proof));
+        match result {
+            Ok(proof) => Some(Ok((node, proof))),
+            Err(err) => {
+                self.path.clear();
+                self.next = None;
+                Some(Err(err))
+            }
+        }
+    }
+}
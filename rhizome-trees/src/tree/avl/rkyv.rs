@@ -0,0 +1,259 @@
+//! An [rkyv](https://rkyv.org) encoding of stored nodes, which can be read in
+//! place.
+//!
+//! [`RkyvCodec`] archives a node with its key and value as rkyv archives
+//! them, its height, size, hash version and hash, and the bytes of the
+//! pointers to its children. Decoding checks the archive before reading it,
+//! so a damaged node fails to decode rather than being misread.
+//!
+//! Trees work on owned nodes, so reading one through the codec still
+//! deserializes its key and value. Readers which only look at a few fields
+//! of each node, such as a lookup walking down from the root, can instead
+//! read [`NodeView`]s from the store of bytes with a [`ViewReader`]: a view
+//! checks the archive once and then borrows every field from it, so only
+//! the value finally returned is deserialized. Archives must be aligned to
+//! be read, so bytes which aren't are copied into an aligned buffer first.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::marker::PhantomData;
+use std::sync::OnceLock;
+
+use rkyv::api::high::{HighDeserializer, HighSerializer, HighValidator};
+use rkyv::bytecheck::CheckBytes;
+use rkyv::rancor;
+use rkyv::ser::allocator::ArenaHandle;
+use rkyv::util::AlignedVec;
+use rkyv::{Archive, Archived, Deserialize, Serialize};
+
+use super::node::{Link, Node};
+use crate::tree::hash::{Digest, HashVersion};
+use crate::tree::node_manager::{NodeCodec, NodeRef, NodeStore, Ptr};
+use crate::{Error, Result};
+
+/// The alignment of archives, that of [`AlignedVec`].
+const ALIGNMENT: usize = 16;
+
+/// Keys and values which rkyv can archive, check and deserialize again.
+pub trait Archivable:
+    Sized
+    + Archive<
+        Archived: for<'a> CheckBytes<HighValidator<'a, rancor::Error>>
+                      + Deserialize<Self, HighDeserializer<rancor::Error>>,
+    > + for<'a> Serialize<HighSerializer<AlignedVec, ArenaHandle<'a>, rancor::Error>>
+{
+}
+
+impl<T> Archivable for T where
+    T: Archive<
+            Archived: for<'a> CheckBytes<HighValidator<'a, rancor::Error>>
+                          + Deserialize<T, HighDeserializer<rancor::Error>>,
+        > + for<'a> Serialize<HighSerializer<AlignedVec, ArenaHandle<'a>, rancor::Error>>
+{
+}
+
+/// The fields of a node as they are archived.
+#[derive(Archive, Serialize)]
+struct StoredNode<K, V> {
+    key: K,
+    value: V,
+    height: u8,
+    size: u64,
+    left: Option<Vec<u8>>,
+    right: Option<Vec<u8>>,
+    hash_version: u8,
+    hash: Digest,
+}
+
+/// Encodes AVL nodes as rkyv archives, see the [module documentation](self).
+pub struct RkyvCodec<K, V>(PhantomData<fn() -> (K, V)>);
+
+impl<K, V> RkyvCodec<K, V> {
+    pub fn new() -> Self {
+        RkyvCodec(PhantomData)
+    }
+}
+
+impl<K, V> Default for RkyvCodec<K, V> {
+    fn default() -> Self {
+        RkyvCodec::new()
+    }
+}
+
+impl<K, V> Clone for RkyvCodec<K, V> {
+    fn clone(&self) -> Self {
+        RkyvCodec::new()
+    }
+}
+
+impl<K, V> NodeCodec<Node<K, V>> for RkyvCodec<K, V>
+where
+    K: Archivable + Clone,
+    V: Archivable + Clone,
+{
+    fn encode(&self, node: &Node<K, V>, buf: &mut Vec<u8>) -> Result<()> {
+        let hash = node
+            .hash
+            .get()
+            .ok_or_else(|| Error::codec("can't encode a node before it is hashed"))?;
+        let stored = StoredNode {
+            key: node.key.clone(),
+            value: node.value.clone(),
+            height: node.height,
+            size: node.size,
+            left: link_bytes(&node.left)?,
+            right: link_bytes(&node.right)?,
+            hash_version: node.hash_version as u8,
+            hash: *hash,
+        };
+        let bytes = rkyv::to_bytes::<rancor::Error>(&stored).map_err(Error::codec)?;
+        buf.extend_from_slice(&bytes);
+        Ok(())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Node<K, V>> {
+        let mut buf = AlignedVec::new();
+        let view = NodeView::<K, V>::new(aligned(bytes, &mut buf))?;
+        Ok(Node {
+            key: view.to_key()?,
+            value: view.to_value()?,
+            height: view.height(),
+            size: view.size(),
+            left: view.left()?.map(NodeRef::Stored),
+            right: view.right()?.map(NodeRef::Stored),
+            hash: OnceLock::from(*view.hash()),
+            hash_version: view.hash_version()?,
+        })
+    }
+}
+
+/// A stored node borrowed from its checked archive, without deserializing
+/// it.
+pub struct NodeView<'a, K: Archive, V: Archive>(&'a ArchivedStoredNode<K, V>);
+
+impl<'a, K: Archivable, V: Archivable> NodeView<'a, K, V> {
+    /// Checks the archive of a node in `bytes`, which must be aligned to 16
+    /// bytes.
+    pub fn new(bytes: &'a [u8]) -> Result<Self> {
+        rkyv::access::<ArchivedStoredNode<K, V>, rancor::Error>(bytes)
+            .map(NodeView)
+            .map_err(Error::codec)
+    }
+
+    pub fn key(&self) -> &'a Archived<K> {
+        &self.0.key
+    }
+
+    pub fn value(&self) -> &'a Archived<V> {
+        &self.0.value
+    }
+
+    pub fn to_key(&self) -> Result<K> {
+        rkyv::deserialize::<K, rancor::Error>(self.key()).map_err(Error::codec)
+    }
+
+    pub fn to_value(&self) -> Result<V> {
+        rkyv::deserialize::<V, rancor::Error>(self.value()).map_err(Error::codec)
+    }
+
+    pub fn height(&self) -> u8 {
+        self.0.height
+    }
+
+    /// Returns the number of entries in the subtree of the node.
+    pub fn size(&self) -> u64 {
+        self.0.size.to_native()
+    }
+
+    pub fn left(&self) -> Result<Option<Ptr>> {
+        self.0
+            .left
+            .as_ref()
+            .map(|bytes| Ptr::new(bytes))
+            .transpose()
+    }
+
+    pub fn right(&self) -> Result<Option<Ptr>> {
+        self.0
+            .right
+            .as_ref()
+            .map(|bytes| Ptr::new(bytes))
+            .transpose()
+    }
+
+    /// Returns the merkle hash of the subtree of the node.
+    pub fn hash(&self) -> &'a Digest {
+        &self.0.hash
+    }
+
+    pub fn hash_version(&self) -> Result<HashVersion> {
+        HashVersion::from_u8(self.0.hash_version)
+            .ok_or_else(|| Error::codec(format!("unknown hash version {}", self.0.hash_version)))
+    }
+}
+
+/// Reads [`NodeView`]s from a store of the bytes [`RkyvCodec`] encoded,
+/// reusing one aligned buffer for them.
+pub struct ViewReader<'s, S: ?Sized> {
+    store: &'s S,
+    buf: AlignedVec,
+}
+
+impl<'s, S: NodeStore<Vec<u8>> + ?Sized> ViewReader<'s, S> {
+    pub fn new(store: &'s S) -> Self {
+        ViewReader {
+            store,
+            buf: AlignedVec::new(),
+        }
+    }
+
+    /// Reads the node at `ptr`, which stays borrowed until the next read.
+    pub fn read<K: Archivable, V: Archivable>(&mut self, ptr: &Ptr) -> Result<NodeView<'_, K, V>> {
+        let bytes = self.store.read(ptr)?;
+        self.buf.clear();
+        self.buf.extend_from_slice(&bytes);
+        NodeView::new(&self.buf).map_err(|err| Error::Corrupt {
+            ptr: *ptr,
+            source: Some(Box::new(err)),
+        })
+    }
+
+    /// Looks up `key` in the saved tree whose root is at `root`, comparing
+    /// it with the archived keys and deserializing only the value found.
+    pub fn get<K, V, Q>(&mut self, root: &Ptr, key: &Q) -> Result<Option<V>>
+    where
+        K: Archivable,
+        V: Archivable,
+        Archived<K>: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut next = Some(*root);
+        while let Some(ptr) = next {
+            let view = self.read::<K, V>(&ptr)?;
+            next = match key.cmp(view.key().borrow()) {
+                Ordering::Less => view.left()?,
+                Ordering::Greater => view.right()?,
+                Ordering::Equal => return view.to_value().map(Some),
+            };
+        }
+        Ok(None)
+    }
+}
+
+/// Returns `bytes` if they are aligned for reading an archive, or else a
+/// copy of them in `buf`.
+fn aligned<'a>(bytes: &'a [u8], buf: &'a mut AlignedVec) -> &'a [u8] {
+    if (bytes.as_ptr() as usize).is_multiple_of(ALIGNMENT) {
+        return bytes;
+    }
+    buf.extend_from_slice(bytes);
+    buf
+}
+
+fn link_bytes<K, V>(link: &Link<K, V>) -> Result<Option<Vec<u8>>> {
+    match link {
+        None => Ok(None),
+        Some(NodeRef::Stored(ptr)) => Ok(Some(ptr.as_bytes().to_vec())),
+        Some(NodeRef::Mem(_)) => Err(Error::codec("can't encode a node with an unsaved child")),
+    }
+}
@@ -0,0 +1,373 @@
+//! Continuous scrubbing: re-hashing random subtrees of saved versions in the
+//! background to catch bit rot in long-lived stores before a read trips
+//! over it.
+//!
+//! A [`Scrubber`] tracks the versions of a
+//! [`VersionedTree`](super::versioned::VersionedTree) as its
+//! [`VersionObserver`], and [`Scrubber::spawn`] starts a thread which
+//! repeatedly picks a tracked version, descends from its root along a
+//! random path to a subtree of at most [`ScrubOptions::subtree_nodes`]
+//! entries, and re-hashes it. Every node is read from the store itself
+//! rather than the manager's cache, so the check covers the stored bytes,
+//! and its recomputed hash is compared with the hash stored along with it.
+//! A subtree which is a whole version is also compared with the recorded
+//! root hash, which catches damage to stores that keep nodes without their
+//! hashes. The thread pauses between subtrees to stay within
+//! [`ScrubOptions::nodes_per_second`].
+//!
+//! Each scrubbed subtree is reported to the [`ScrubObserver`] and counted in
+//! [`Scrubber::stats`]. A mismatch also goes through the manager's
+//! [`CorruptionPolicy`](crate::tree::node_manager::CorruptionPolicy).
+//!
+//! A version is dropped from scrubbing when it is pruned or rolled back.
+//! Both are reported before the version's nodes are deleted, and wait for a
+//! subtree being scrubbed to be finished, so the scrubber never reads
+//! deleted nodes.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::node::{node_hash, Manager, Node};
+use super::versioned::{Version, VersionEvent, VersionInfo, VersionObserver};
+use crate::tree::cardinality::ProbeRng;
+use crate::tree::hash::{Digest, Hashable, EMPTY_HASH};
+use crate::tree::node_manager::{NodeRef, Ptr};
+use crate::{Error, Result};
+
+/// Parameters of a [`Scrubber`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ScrubOptions {
+    nodes_per_second: u32,
+    subtree_nodes: u64,
+}
+
+impl Default for ScrubOptions {
+    fn default() -> Self {
+        ScrubOptions {
+            nodes_per_second: 1000,
+            subtree_nodes: 256,
+        }
+    }
+}
+
+impl ScrubOptions {
+    /// Sets the number of nodes the background thread reads per second on
+    /// average, 1000 by default. Values below 1 are raised to 1.
+    pub fn nodes_per_second(self, nodes_per_second: u32) -> Self {
+        ScrubOptions {
+            nodes_per_second: nodes_per_second.max(1),
+            ..self
+        }
+    }
+
+    /// Sets the number of entries up to which a subtree is re-hashed at
+    /// once, 256 by default. Larger subtrees are descended into. Values
+    /// below 1 are raised to 1.
+    pub fn subtree_nodes(self, subtree_nodes: u64) -> Self {
+        ScrubOptions {
+            subtree_nodes: subtree_nodes.max(1),
+            ..self
+        }
+    }
+}
+
+/// The outcome of scrubbing one subtree.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ScrubEvent {
+    /// Every node of the subtree matched its stored hash.
+    Verified {
+        version: Version,
+        root: Ptr,
+        /// The nodes read, including those on the path to the subtree.
+        nodes: u64,
+    },
+    /// A node of the subtree didn't match its stored hash or couldn't be
+    /// read, or the version didn't match its recorded root hash. `error`
+    /// describes the first such problem, and `root` is the version root if
+    /// it was found before a subtree was picked.
+    Mismatch {
+        version: Version,
+        root: Ptr,
+        error: String,
+    },
+}
+
+/// Receives the [`ScrubEvent`]s of a [`Scrubber`], set with
+/// [`Scrubber::observer`]. Events are delivered on the scrubbing thread.
+pub trait ScrubObserver: Send + Sync {
+    fn on_scrub_event(&self, event: &ScrubEvent);
+}
+
+/// Counts of the work a [`Scrubber`] did.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct ScrubStats {
+    pub subtrees: u64,
+    pub nodes: u64,
+    pub mismatches: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    subtrees: AtomicU64,
+    nodes: AtomicU64,
+    mismatches: AtomicU64,
+}
+
+/// Re-hashes random subtrees of saved versions, see the
+/// [module documentation](self).
+pub struct Scrubber<K, V> {
+    manager: Arc<Manager<K, V>>,
+    options: ScrubOptions,
+    observer: Option<Arc<dyn ScrubObserver>>,
+    /// The versions to scrub, locked while a subtree is scrubbed.
+    versions: Mutex<BTreeMap<Version, VersionInfo>>,
+    rng: Mutex<ProbeRng>,
+    counters: Counters,
+}
+
+impl<K, V> Scrubber<K, V> {
+    /// Creates a scrubber of versions saved through `manager`, which tracks
+    /// none until it is told of them by [`Scrubber::track`] or as an
+    /// observer.
+    pub fn new(manager: Arc<Manager<K, V>>, options: ScrubOptions) -> Self {
+        Scrubber {
+            manager,
+            options,
+            observer: None,
+            versions: Mutex::new(BTreeMap::new()),
+            rng: Mutex::new(ProbeRng::new()),
+            counters: Counters::default(),
+        }
+    }
+
+    /// Reports every scrubbed subtree to `observer`.
+    pub fn observer(self, observer: Arc<dyn ScrubObserver>) -> Self {
+        Scrubber {
+            observer: Some(observer),
+            ..self
+        }
+    }
+
+    /// Starts scrubbing `versions`, e.g. the
+    /// [`versions`](super::versioned::VersionedTree::versions) of a tree
+    /// saved before the scrubber observed it.
+    pub fn track(&self, versions: impl IntoIterator<Item = VersionInfo>) -> Result<()> {
+        let mut tracked = lock(&self.versions)?;
+        for info in versions {
+            tracked.insert(info.version, info);
+        }
+        Ok(())
+    }
+
+    /// Returns the versions being scrubbed, oldest first.
+    pub fn tracked(&self) -> Result<Vec<Version>> {
+        Ok(lock(&self.versions)?.keys().copied().collect())
+    }
+
+    pub fn stats(&self) -> ScrubStats {
+        ScrubStats {
+            subtrees: self.counters.subtrees.load(Relaxed),
+            nodes: self.counters.nodes.load(Relaxed),
+            mismatches: self.counters.mismatches.load(Relaxed),
+        }
+    }
+}
+
+impl<K: Hashable, V: Hashable> Scrubber<K, V> {
+    /// Scrubs one random subtree of a tracked version, returning the
+    /// outcome, or `None` if no version with nodes is tracked.
+    pub fn scrub_once(&self) -> Result<Option<ScrubEvent>> {
+        let versions = lock(&self.versions)?;
+        let roots: Vec<&VersionInfo> = versions.values().filter(|v| v.root.is_some()).collect();
+        if roots.is_empty() {
+            return Ok(None);
+        }
+        let mut rng = lock(&self.rng)?;
+        let info = roots[rng.below(roots.len())];
+        let root = info.root.expect("versions without nodes are skipped");
+        let mut nodes = 0;
+        let outcome = match self.pick_subtree(root, &mut rng, &mut nodes) {
+            Err(err) => Err((root, err)),
+            Ok((subtree, node)) => match self.rehash_node(subtree, node, &mut nodes) {
+                Err(err) => Err((subtree, err)),
+                Ok(hash) if subtree == root && hash != info.hash => Err((
+                    root,
+                    self.manager.corrupted(Error::corruption(format!(
+                        "version {} doesn't have its recorded root hash",
+                        info.version
+                    ))),
+                )),
+                Ok(_) => Ok(subtree),
+            },
+        };
+        let event = match outcome {
+            Ok(subtree) => ScrubEvent::Verified {
+                version: info.version,
+                root: subtree,
+                nodes,
+            },
+            Err((subtree, err)) => {
+                self.counters.mismatches.fetch_add(1, Relaxed);
+                ScrubEvent::Mismatch {
+                    version: info.version,
+                    root: subtree,
+                    error: format!("{:#}", err),
+                }
+            }
+        };
+        drop(versions);
+        self.counters.subtrees.fetch_add(1, Relaxed);
+        self.counters.nodes.fetch_add(nodes, Relaxed);
+        if let Some(observer) = &self.observer {
+            observer.on_scrub_event(&event);
+        }
+        Ok(Some(event))
+    }
+
+    /// Descends from `root` to a random subtree of at most `subtree_nodes`
+    /// entries, returning its root along with the node read there.
+    fn pick_subtree(
+        &self,
+        root: Ptr,
+        rng: &mut ProbeRng,
+        nodes: &mut u64,
+    ) -> Result<(Ptr, Node<K, V>)> {
+        let mut ptr = root;
+        loop {
+            let node = self.read(&ptr, nodes)?;
+            if node.size <= self.options.subtree_nodes {
+                return Ok((ptr, node));
+            }
+            let children: Vec<Ptr> = [&node.left, &node.right]
+                .into_iter()
+                .flatten()
+                .filter_map(NodeRef::ptr)
+                .collect();
+            if children.is_empty() {
+                return Ok((ptr, node));
+            }
+            ptr = children[rng.below(children.len())];
+        }
+    }
+
+    /// Recomputes the hash of the subtree at `ptr` from the store, checking
+    /// it against the stored hash of every node which has one.
+    fn rehash(&self, ptr: Ptr, nodes: &mut u64) -> Result<Digest> {
+        let node = self.read(&ptr, nodes)?;
+        self.rehash_node(ptr, node, nodes)
+    }
+
+    /// Like [`Scrubber::rehash`], for the node already read from `ptr`.
+    fn rehash_node(&self, ptr: Ptr, node: Node<K, V>, nodes: &mut u64) -> Result<Digest> {
+        let mut child = |child: &Option<NodeRef<Node<K, V>>>| match child {
+            None => Ok(EMPTY_HASH),
+            Some(child) => match child.ptr() {
+                Some(child) => self.rehash(child, nodes),
+                None => Err(Error::corruption(format!(
+                    "stored node {:?} has an unsaved child",
+                    ptr
+                ))),
+            },
+        };
+        let (left, right) = (child(&node.left)?, child(&node.right)?);
+        let hash = node_hash(&left, &node.entry_hash_in(&self.manager), &right);
+        if node.hash.get().is_some_and(|stored| *stored != hash) {
+            return Err(self.manager.corrupted(Error::corruption(format!(
+                "node {:?} doesn't match its stored hash",
+                ptr
+            ))));
+        }
+        Ok(hash)
+    }
+
+    /// Reads a node from the store, bypassing the cache. Scrubbed versions
+    /// can't be released meanwhile, so even a missing root is corruption.
+    fn read(&self, ptr: &Ptr, nodes: &mut u64) -> Result<Node<K, V>> {
+        *nodes += 1;
+        self.manager.store().read(ptr).map_err(|err| {
+            if err.is_corruption() || matches!(err, Error::NotFound(_)) {
+                self.manager.corrupted(err)
+            } else {
+                err
+            }
+        })
+    }
+}
+
+impl<K, V> Scrubber<K, V>
+where
+    K: Hashable + Send + Sync + 'static,
+    V: Hashable + Send + Sync + 'static,
+{
+    /// Starts scrubbing on a background thread until the returned handle is
+    /// stopped or dropped.
+    pub fn spawn(self: &Arc<Self>) -> ScrubHandle {
+        let (stop, stopped) = mpsc::channel();
+        let scrubber = self.clone();
+        let per_node = Duration::from_secs(1) / scrubber.options.nodes_per_second;
+        let thread = thread::spawn(move || loop {
+            let nodes = match scrubber.scrub_once()? {
+                Some(ScrubEvent::Verified { nodes, .. }) => nodes,
+                // Pace mismatches and idle rounds like a full subtree.
+                _ => scrubber.options.subtree_nodes,
+            };
+            let pause = per_node.saturating_mul(nodes.try_into().unwrap_or(u32::MAX));
+            match stopped.recv_timeout(pause) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return Ok(()),
+            }
+        });
+        ScrubHandle { stop, thread }
+    }
+}
+
+/// Receives [`VersionEvent`]s to track the versions to scrub.
+impl<K: Send + Sync, V: Send + Sync> VersionObserver for Scrubber<K, V> {
+    fn on_version_event(&self, event: &VersionEvent) {
+        // A poisoned lock only means a scrub panicked, the map is intact.
+        let mut versions = self
+            .versions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match event {
+            VersionEvent::Committed(info) => {
+                versions.insert(info.version, *info);
+            }
+            VersionEvent::RolledBack { to } => versions.retain(|version, _| version <= to),
+            VersionEvent::Pruned { versions: pruned } => {
+                for version in pruned {
+                    versions.remove(version);
+                }
+            }
+        }
+    }
+}
+
+/// The background thread of [`Scrubber::spawn`]. Dropping the handle stops
+/// the thread without waiting for it.
+pub struct ScrubHandle {
+    stop: Sender<()>,
+    thread: JoinHandle<Result<()>>,
+}
+
+impl ScrubHandle {
+    /// Stops the thread and waits for it, returning the error which stopped
+    /// it early, if any.
+    pub fn stop(self) -> Result<()> {
+        // The thread is gone if sending fails, which joining reports.
+        let _ = self.stop.send(());
+        match self.thread.join() {
+            Ok(result) => result,
+            Err(_) => Err(Error::Poisoned("scrubber thread panicked".into())),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>> {
+    mutex.lock().map_err(|_| Error::poisoned("scrubber"))
+}
@@ -0,0 +1,239 @@
+//! A persistent sorted set with set algebra.
+//!
+//! Union, intersection and difference divide and conquer on the entries of
+//! one set by splitting the other, then join the results back together.
+//! Subtrees which end up entirely on one side are shared with the inputs
+//! rather than copied, so combining a small set with a large one takes
+//! O(m log(n / m + 1)) work.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::sync::Arc;
+
+use super::node::{same_link, Link, Manager, Node};
+use super::Tree;
+use crate::tree::hash::{Digest, Hashable, MerkleTree};
+use crate::tree::node_manager::NodeRef;
+use crate::Result;
+
+/// A persistent sorted set, stored as a tree with `()` values.
+pub struct PersistentSet<K> {
+    tree: Tree<K, ()>,
+}
+
+impl<K> Clone for PersistentSet<K> {
+    fn clone(&self) -> Self {
+        PersistentSet {
+            tree: self.tree.clone(),
+        }
+    }
+}
+
+impl<K> fmt::Debug for PersistentSet<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PersistentSet")
+            .field("root", &self.tree.root)
+            .finish()
+    }
+}
+
+impl<K: Clone + Send + Sync + 'static> Default for PersistentSet<K> {
+    fn default() -> Self {
+        PersistentSet {
+            tree: Tree::default(),
+        }
+    }
+}
+
+impl<K: Clone + Send + Sync + 'static> PersistentSet<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<K> PersistentSet<K> {
+    pub fn with_manager(manager: Arc<Manager<K, ()>>) -> Self {
+        PersistentSet {
+            tree: Tree::with_manager(manager),
+        }
+    }
+
+    pub fn from_tree(tree: Tree<K, ()>) -> Self {
+        PersistentSet { tree }
+    }
+
+    pub fn tree(&self) -> &Tree<K, ()> {
+        &self.tree
+    }
+
+    pub fn into_tree(self) -> Tree<K, ()> {
+        self.tree
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+}
+
+impl<K: Ord + Clone> PersistentSet<K> {
+    pub fn contains<Q>(&self, key: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.tree.contains_key(key)
+    }
+
+    pub fn insert(&self, key: K) -> Result<Self> {
+        Ok(PersistentSet {
+            tree: self.tree.insert(key, ())?,
+        })
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Result<Self>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Ok(PersistentSet {
+            tree: self.tree.delete(key)?,
+        })
+    }
+
+    /// Returns the keys in either set.
+    pub fn union(&self, other: &Self) -> Result<Self> {
+        let m = &self.tree.manager;
+        let other = self.import(other)?;
+        Ok(self.with_root(union(m, &self.tree.root, &other)?))
+    }
+
+    /// Returns the keys in both sets.
+    pub fn intersection(&self, other: &Self) -> Result<Self> {
+        let m = &self.tree.manager;
+        let other = self.import(other)?;
+        Ok(self.with_root(intersection(m, &self.tree.root, &other)?))
+    }
+
+    /// Returns the keys in this set but not in `other`.
+    pub fn difference(&self, other: &Self) -> Result<Self> {
+        let m = &self.tree.manager;
+        let other = self.import(other)?;
+        Ok(self.with_root(difference(m, &self.tree.root, &other)?))
+    }
+
+    /// Returns the root of `other` in a form readable through this set's
+    /// manager. Sets with different managers have the stored nodes of
+    /// `other` copied into memory.
+    fn import(&self, other: &Self) -> Result<Link<K, ()>> {
+        if Arc::ptr_eq(&self.tree.manager, &other.tree.manager) {
+            Ok(other.tree.root.clone())
+        } else {
+            detach(&other.tree.manager, &other.tree.root)
+        }
+    }
+
+    fn with_root(&self, root: Link<K, ()>) -> Self {
+        PersistentSet {
+            tree: self.tree.with_root(root),
+        }
+    }
+}
+
+impl<K: Clone + Hashable> PersistentSet<K> {
+    /// Persists the set, see [`Tree::save`].
+    pub fn save(&self) -> Result<Self> {
+        Ok(PersistentSet {
+            tree: self.tree.save()?,
+        })
+    }
+}
+
+impl<K: Hashable> MerkleTree for PersistentSet<K> {
+    fn merkle_hash(&self) -> Result<Digest> {
+        self.tree.merkle_hash()
+    }
+}
+
+/// Copies the stored nodes of a subtree into memory, sharing in-memory
+/// subtrees which have no stored descendants.
+fn detach<K: Clone, V: Clone>(m: &Manager<K, V>, link: &Link<K, V>) -> Result<Link<K, V>> {
+    let Some(node_ref) = link else {
+        return Ok(None);
+    };
+    let node = m.read(node_ref)?;
+    let left = detach(m, &node.left)?;
+    let right = detach(m, &node.right)?;
+    if matches!(node_ref, NodeRef::Mem(_))
+        && same_link(&left, &node.left)
+        && same_link(&right, &node.right)
+    {
+        return Ok(link.clone());
+    }
+    Ok(Some(NodeRef::Mem(Arc::new(Node {
+        key: node.key.clone(),
+        value: node.value.clone(),
+        height: node.height,
+        size: node.size,
+        left,
+        right,
+        hash: node.hash.clone(),
+        hash_version: node.hash_version,
+    }))))
+}
+
+fn union<K: Ord + Clone, V: Clone>(
+    m: &Manager<K, V>,
+    a: &Link<K, V>,
+    b: &Link<K, V>,
+) -> Result<Link<K, V>> {
+    let Some(b_ref) = b else {
+        return Ok(a.clone());
+    };
+    if a.is_none() || same_link(a, b) {
+        return Ok(b.clone());
+    }
+    let b = m.read(b_ref)?;
+    let (less, _, greater) = Node::split(m, a, &b.key)?;
+    let less = union(m, &less, &b.left)?;
+    let greater = union(m, &greater, &b.right)?;
+    Node::join(m, less, b.key.clone(), b.value.clone(), greater)
+}
+
+fn intersection<K: Ord + Clone, V: Clone>(
+    m: &Manager<K, V>,
+    a: &Link<K, V>,
+    b: &Link<K, V>,
+) -> Result<Link<K, V>> {
+    let Some(b_ref) = b else {
+        return Ok(None);
+    };
+    if a.is_none() || same_link(a, b) {
+        return Ok(a.clone());
+    }
+    let b = m.read(b_ref)?;
+    let (less, found, greater) = Node::split(m, a, &b.key)?;
+    let less = intersection(m, &less, &b.left)?;
+    let greater = intersection(m, &greater, &b.right)?;
+    match found {
+        Some((key, value)) => Node::join(m, less, key, value, greater),
+        None => Node::join2(m, less, greater),
+    }
+}
+
+fn difference<K: Ord + Clone, V: Clone>(
+    m: &Manager<K, V>,
+    a: &Link<K, V>,
+    b: &Link<K, V>,
+) -> Result<Link<K, V>> {
+    let Some(b_ref) = b else {
+        return Ok(a.clone());
+    };
+    if a.is_none() || same_link(a, b) {
+        return Ok(None);
+    }
+    let b = m.read(b_ref)?;
+    let (less, _, greater) = Node::split(m, a, &b.key)?;
+    let less = difference(m, &less, &b.left)?;
+    let greater = difference(m, &greater, &b.right)?;
+    Node::join2(m, less, greater)
+}
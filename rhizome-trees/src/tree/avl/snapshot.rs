@@ -0,0 +1,314 @@
+//! Snapshots of a single tree version.
+//!
+//! A snapshot holds every node reachable from a root, so a new node can be
+//! bootstrapped from it without the history which led to that version. The
+//! format is deterministic, the same version always produces the same bytes:
+//!
+//! ```text
+//! magic     b"RHZSNAP" followed by the format version 2
+//! hashing   the tree's hash version, a single byte
+//! root      the 32 byte root hash
+//! chunk*    node count: u32 LE | payload length: u32 LE | payload
+//! end       a chunk with no nodes and an empty payload
+//! ```
+//!
+//! The payloads hold the nodes in pre-order, each encoded as a flags byte
+//! (bit 0: has a left child, bit 1: has a right child) followed by the key
+//! and value in their [`KeyCodec`] and [`ValueCodec`] encodings, each
+//! prefixed with its u32 LE length. Importing rebuilds the exact tree shape
+//! and checks the recomputed root hash against the header.
+
+use std::io::{self, Read, Write};
+use std::sync::{Arc, OnceLock};
+
+use super::node::{entry_hash, node_hash, Manager, Node, Shape};
+use super::Tree;
+use crate::tree::hash::{Digest, HashVersion, Hashable, MerkleTree, EMPTY_HASH};
+use crate::tree::node_manager::{NodeRef, Ptr};
+use crate::tree::value::{KeyCodec, ValueCodec};
+use crate::{Error, Result};
+
+const MAGIC: &[u8; 8] = b"RHZSNAP\x02";
+
+/// The payload size after which a chunk is written out.
+const CHUNK_BYTES: usize = 1 << 20;
+
+const HAS_LEFT: u8 = 1;
+const HAS_RIGHT: u8 = 2;
+
+impl<K: KeyCodec + Hashable, V: ValueCodec + Hashable> Tree<K, V> {
+    /// Writes a snapshot of this version to `writer`, see the
+    /// [module documentation](self). Returns the number of nodes written.
+    pub fn export_snapshot(&self, mut writer: impl Write) -> Result<u64> {
+        let hash_version = match &self.root {
+            None => self.manager.hash_version(),
+            Some(root) => self.manager.read(root)?.hash_version,
+        };
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[hash_version as u8])?;
+        writer.write_all(&self.merkle_hash()?)?;
+        let mut chunk = ChunkWriter {
+            writer,
+            payload: Vec::new(),
+            nodes: 0,
+            total: 0,
+        };
+        let mut stack: Vec<NodeRef<_>> = self.root.iter().cloned().collect();
+        while let Some(link) = stack.pop() {
+            let node = self.manager.read(&link)?;
+            let flags = node.left.as_ref().map_or(0, |_| HAS_LEFT)
+                | node.right.as_ref().map_or(0, |_| HAS_RIGHT);
+            chunk.push(flags, &node.key, &node.value)?;
+            stack.extend(node.right.iter().cloned());
+            stack.extend(node.left.iter().cloned());
+        }
+        chunk.finish()
+    }
+
+    /// Reads a snapshot written by [`Tree::export_snapshot`] and saves its
+    /// nodes through `manager`, returning the saved version. Fails without
+    /// leaving any nodes behind if the snapshot is malformed, its nodes
+    /// don't hash to the root hash it declares, or its hash version isn't
+    /// the one `manager` creates nodes with.
+    pub fn import_snapshot(manager: Arc<Manager<K, V>>, mut reader: impl Read) -> Result<Self> {
+        let mut magic = [0; 8];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|err| Error::io("reading snapshot header", err))?;
+        if &magic != MAGIC {
+            return Err(Error::codec("not a snapshot of format version 2"));
+        }
+        let mut hash_version = [0];
+        reader.read_exact(&mut hash_version)?;
+        match HashVersion::from_u8(hash_version[0]) {
+            Some(version) if version == manager.hash_version() => {}
+            Some(version) => {
+                return Err(Error::invalid(format!(
+                    "snapshot uses hash version {:?} but the manager uses {:?}",
+                    version,
+                    manager.hash_version()
+                )))
+            }
+            None => {
+                return Err(Error::codec(format!(
+                    "unknown snapshot hash version {}",
+                    hash_version[0]
+                )))
+            }
+        }
+        let mut root_hash = Digest::default();
+        reader.read_exact(&mut root_hash)?;
+        let mut chunks = ChunkReader {
+            reader,
+            payload: io::Cursor::new(Vec::new()),
+            remaining: 0,
+            ended: false,
+        };
+        let root = match chunks.next_node()? {
+            None => None,
+            Some(node) => Some(import_subtree(&manager, &mut chunks, node)?),
+        };
+        let tree = Tree {
+            root: root.map(|(ptr, _, _)| NodeRef::Stored(ptr)),
+            manager,
+        };
+        let hash = root.map_or(EMPTY_HASH, |(_, hash, _)| hash);
+        let check = (|| {
+            if chunks.next_node()?.is_some() {
+                return Err(Error::codec("snapshot has nodes after the end of the tree"));
+            }
+            if hash != root_hash {
+                return Err(Error::corruption(
+                    "snapshot nodes don't match its root hash",
+                ));
+            }
+            Ok(())
+        })();
+        if let Err(err) = check {
+            tree.free_version()?;
+            return Err(err);
+        }
+        Ok(tree)
+    }
+}
+
+type Entry = (u8, Vec<u8>, Vec<u8>);
+
+/// Saves the subtree whose root was just read as `entry`, reading its
+/// descendants from `chunks`. Returns the saved root with its hash and
+/// shape, or releases everything saved so far on failure.
+fn import_subtree<K: KeyCodec + Hashable, V: ValueCodec + Hashable>(
+    m: &Manager<K, V>,
+    chunks: &mut ChunkReader<impl Read>,
+    (flags, key, value): Entry,
+) -> Result<(Ptr, Digest, Shape)> {
+    let mut children = Vec::new();
+    let result = (|| {
+        for flag in [HAS_LEFT, HAS_RIGHT] {
+            children.push(if flags & flag == 0 {
+                None
+            } else {
+                let entry = chunks
+                    .next_node()?
+                    .ok_or_else(|| Error::codec("snapshot ends inside the tree"))?;
+                Some(import_subtree(m, chunks, entry)?)
+            });
+        }
+        let (left, right) = (children[0], children[1]);
+        let key = K::decode(&key)
+            .map_err(|err| Error::codec(format!("decoding a snapshot key: {}", err)))?;
+        let value = V::decode(&value)
+            .map_err(|err| Error::codec(format!("decoding a snapshot value: {}", err)))?;
+        let hash = |child: Option<(Ptr, Digest, Shape)>| child.map_or(EMPTY_HASH, |c| c.1);
+        let shape = |child: Option<(Ptr, Digest, Shape)>| child.map_or(Shape::default(), |c| c.2);
+        let version = m.hash_version();
+        let node_hash = node_hash(
+            &hash(left),
+            &entry_hash(version, &key, &value),
+            &hash(right),
+        );
+        let node_shape = Shape::join(shape(left), shape(right));
+        let node = Node {
+            key,
+            value,
+            height: node_shape.height,
+            size: node_shape.size,
+            left: left.map(|c| NodeRef::Stored(c.0)),
+            right: right.map(|c| NodeRef::Stored(c.0)),
+            hash: OnceLock::from(node_hash),
+            hash_version: version,
+        };
+        Ok((m.insert(node)?, node_hash, node_shape))
+    })();
+    if result.is_err() {
+        for (ptr, _, _) in children.into_iter().flatten() {
+            m.release(&ptr)?;
+        }
+    }
+    result
+}
+
+struct ChunkWriter<W> {
+    writer: W,
+    payload: Vec<u8>,
+    nodes: u32,
+    total: u64,
+}
+
+impl<W: Write> ChunkWriter<W> {
+    fn push(&mut self, flags: u8, key: &impl ValueCodec, value: &impl ValueCodec) -> Result<()> {
+        self.payload.push(flags);
+        self.push_encoded(key)?;
+        self.push_encoded(value)?;
+        self.nodes += 1;
+        if self.payload.len() >= CHUNK_BYTES {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Appends the encoding of `item` prefixed with its length.
+    fn push_encoded(&mut self, item: &impl ValueCodec) -> Result<()> {
+        let start = self.payload.len();
+        self.payload.extend_from_slice(&[0; 4]);
+        item.encode(&mut self.payload);
+        let len = self.payload.len() - start - 4;
+        let len =
+            u32::try_from(len).map_err(|_| Error::invalid("entry too large for a snapshot"))?;
+        self.payload[start..start + 4].copy_from_slice(&len.to_le_bytes());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let len = u32::try_from(self.payload.len())
+            .map_err(|_| Error::invalid("snapshot chunk too large"))?;
+        self.writer.write_all(&self.nodes.to_le_bytes())?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(&self.payload)?;
+        self.total += u64::from(self.nodes);
+        self.payload.clear();
+        self.nodes = 0;
+        Ok(())
+    }
+
+    /// Writes the last chunk and the end marker.
+    fn finish(mut self) -> Result<u64> {
+        if self.nodes > 0 {
+            self.flush()?;
+        }
+        self.flush()?;
+        self.writer.flush()?;
+        Ok(self.total)
+    }
+}
+
+struct ChunkReader<R> {
+    reader: R,
+    payload: io::Cursor<Vec<u8>>,
+    /// The nodes left in the current chunk.
+    remaining: u32,
+    ended: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Reads the next node, or returns `None` at the end marker.
+    fn next_node(&mut self) -> Result<Option<Entry>> {
+        if self.ended {
+            return Ok(None);
+        }
+        if self.remaining == 0 {
+            if self.payload.position() != self.payload.get_ref().len() as u64 {
+                return Err(Error::codec("snapshot chunk has trailing bytes"));
+            }
+            let mut header = [0; 8];
+            self.reader
+                .read_exact(&mut header)
+                .map_err(|err| Error::io("snapshot ends without an end marker", err))?;
+            let nodes = u32::from_le_bytes(header[..4].try_into().expect("4 bytes"));
+            let len = u32::from_le_bytes(header[4..].try_into().expect("4 bytes"));
+            if nodes == 0 {
+                if len != 0 {
+                    return Err(Error::codec("snapshot end marker has a payload"));
+                }
+                self.ended = true;
+                return Ok(None);
+            }
+            let mut payload = Vec::new();
+            (&mut self.reader)
+                .take(len.into())
+                .read_to_end(&mut payload)?;
+            if payload.len() != len as usize {
+                return Err(Error::codec("snapshot ends inside a chunk"));
+            }
+            self.payload = io::Cursor::new(payload);
+            self.remaining = nodes;
+        }
+        self.remaining -= 1;
+        let mut flags = [0];
+        self.payload
+            .read_exact(&mut flags)
+            .map_err(|err| Error::io("snapshot chunk holds fewer nodes than declared", err))?;
+        if flags[0] & !(HAS_LEFT | HAS_RIGHT) != 0 {
+            return Err(Error::codec(format!(
+                "invalid snapshot node flags {:#x}",
+                flags[0]
+            )));
+        }
+        let key = self.read_bytes()?;
+        let value = self.read_bytes()?;
+        Ok(Some((flags[0], key, value)))
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        let mut len = [0; 4];
+        self.payload.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+        let available = self.payload.get_ref().len() - self.payload.position() as usize;
+        if len > available {
+            return Err(Error::codec("snapshot entry exceeds its chunk"));
+        }
+        let mut bytes = vec![0; len];
+        self.payload.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
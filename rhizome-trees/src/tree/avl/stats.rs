@@ -0,0 +1,98 @@
+//! A report of the shape and footprint of a tree, for capacity planning.
+//!
+//! [`Tree::stats`] visits every node, reading stored nodes which aren't
+//! cached from the store without caching them. The encoded size of a stored
+//! node is taken from the store when it reports one, see
+//! [`NodeMeta::size`](crate::tree::node_manager::NodeMeta::size). Other
+//! nodes are estimated from the encodings of their key and value plus the
+//! fixed fields of a node, laid out like the Borsh encoding of the `borsh`
+//! feature.
+
+use super::node::Link;
+use super::Tree;
+use crate::tree::node_manager::NodeRef;
+use crate::tree::value::ValueCodec;
+use crate::Result;
+
+/// The bytes of a node besides its key, value and children: the height,
+/// size, hash version and hash.
+const FIXED_SIZE: u64 = 1 + 8 + 1 + 32;
+
+/// The bytes of a child pointer besides the pointer itself: an option tag
+/// and a length.
+const LINK_OVERHEAD: u64 = 1 + 4;
+
+/// The pointer length assumed for children which aren't stored yet.
+const UNSAVED_PTR_SIZE: u64 = 8;
+
+/// The shape of a tree and where its nodes are, see [`Tree::stats`].
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct TreeStats {
+    /// The number of nodes, one per entry.
+    pub nodes: u64,
+    /// The number of nodes on the longest path from the root to a leaf, or
+    /// 0 for an empty tree.
+    pub height: u32,
+    /// The mean number of nodes above an entry, 0 for the root, which is how
+    /// many nodes a lookup reads before reaching it.
+    pub average_depth: f64,
+    /// The nodes which haven't been saved.
+    pub in_memory: u64,
+    /// The nodes which are in the store.
+    pub stored: u64,
+    /// The total size of the nodes once encoded, measured by the store where
+    /// it reports sizes and estimated otherwise.
+    pub encoded_size: u64,
+}
+
+impl<K: ValueCodec, V: ValueCodec> Tree<K, V> {
+    /// Returns the shape of the tree, see the [module documentation](self).
+    /// Takes O(n) node reads.
+    pub fn stats(&self) -> Result<TreeStats> {
+        let mut stats = TreeStats::default();
+        let mut total_depth = 0;
+        let mut pending = Vec::from_iter(self.root.clone().map(|root| (root, 0)));
+        while let Some((link, depth)) = pending.pop() {
+            let node = match &link {
+                NodeRef::Mem(_) => {
+                    stats.in_memory += 1;
+                    self.manager.read(&link)?
+                }
+                NodeRef::Stored(ptr) => {
+                    stats.stored += 1;
+                    self.manager.peek(ptr)?.0
+                }
+            };
+            stats.nodes += 1;
+            stats.height = stats.height.max(depth + 1);
+            total_depth += depth as u64;
+            let measured = match &link {
+                NodeRef::Stored(ptr) => self.manager.meta(ptr)?.and_then(|meta| meta.size),
+                NodeRef::Mem(_) => None,
+            };
+            stats.encoded_size += match measured {
+                Some(size) => size,
+                None => {
+                    let entry = node.key.to_encoded().len() + node.value.to_encoded().len();
+                    entry as u64 + FIXED_SIZE + link_size(&node.left) + link_size(&node.right)
+                }
+            };
+            for child in [&node.left, &node.right].into_iter().flatten() {
+                pending.push((child.clone(), depth + 1));
+            }
+        }
+        if stats.nodes > 0 {
+            stats.average_depth = total_depth as f64 / stats.nodes as f64;
+        }
+        Ok(stats)
+    }
+}
+
+/// Estimates the encoded size of a child pointer.
+fn link_size<K, V>(link: &Link<K, V>) -> u64 {
+    match link {
+        None => 1,
+        Some(NodeRef::Stored(ptr)) => LINK_OVERHEAD + ptr.as_bytes().len() as u64,
+        Some(NodeRef::Mem(_)) => LINK_OVERHEAD + UNSAVED_PTR_SIZE,
+    }
+}
@@ -0,0 +1,215 @@
+//! Proptest strategies generating arbitrary trees, so that code consuming
+//! trees can be property-tested without building them by hand.
+//!
+//! [`trees`] applies an arbitrary sequence of [`Op`]s to an empty tree, so
+//! it generates the shapes which real insertions and deletions produce
+//! rather than only the balanced ones of [`Tree::from_sorted_iter`].
+//! [`Persistence`] picks whether a tree is in memory, saved or reloaded
+//! from its store, since code reading trees often takes different paths for
+//! stored and in-memory nodes. Every tree gets its own in-memory node
+//! manager, which can also save the changes made to it.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use proptest::collection::{vec, SizeRange};
+use proptest::prelude::*;
+use proptest::sample::Index;
+
+use crate::tree::hash::Hashable;
+use crate::tree::node_manager::NodeManager;
+
+use super::Tree;
+use crate::Result;
+
+/// An operation applied to a generated tree.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Op<K, V> {
+    Insert(K, V),
+    Delete(K),
+}
+
+impl<K: Ord + Clone + Hashable, V: Clone + Hashable> Op<K, V> {
+    pub fn apply(&self, tree: &Tree<K, V>) -> Result<Tree<K, V>> {
+        match self {
+            Op::Insert(key, value) => tree.insert(key.clone(), value.clone()),
+            Op::Delete(key) => tree.delete(key),
+        }
+    }
+
+    /// Applies the operation to a map holding the same entries as a tree.
+    pub fn apply_to_map(&self, map: &mut BTreeMap<K, V>) {
+        match self {
+            Op::Insert(key, value) => map.insert(key.clone(), value.clone()),
+            Op::Delete(key) => map.remove(key),
+        };
+    }
+}
+
+/// How the nodes of a generated tree are stored.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub enum Persistence {
+    /// The tree is never saved, so every node is in memory.
+    InMemory,
+    /// The tree is saved once every operation is applied.
+    Saved,
+    /// The tree is saved, then opened again from its root pointer, so every
+    /// node is read from the store.
+    Reloaded,
+    /// The tree is saved after some of the operations, and the rest are
+    /// left unsaved, so in-memory nodes mix with stored ones.
+    Partial,
+    /// Any of the others, picked for each tree.
+    #[default]
+    Any,
+}
+
+/// Parameters of [`trees`].
+#[derive(Clone, Debug)]
+pub struct TreeOptions {
+    ops: SizeRange,
+    persistence: Persistence,
+}
+
+impl Default for TreeOptions {
+    fn default() -> Self {
+        TreeOptions {
+            ops: (0..=64).into(),
+            persistence: Persistence::Any,
+        }
+    }
+}
+
+impl TreeOptions {
+    /// Sets the range of the number of operations applied, 0 to 64 by
+    /// default. Deletions and repeated keys make trees smaller.
+    pub fn ops(mut self, ops: impl Into<SizeRange>) -> Self {
+        self.ops = ops.into();
+        self
+    }
+
+    /// Sets how the trees are stored, [`Persistence::Any`] by default.
+    pub fn persistence(mut self, persistence: Persistence) -> Self {
+        self.persistence = persistence;
+        self
+    }
+}
+
+/// A step of [`ops`] before deletions are resolved to inserted keys.
+#[derive(Debug)]
+enum Step<K, V> {
+    Insert(K, V),
+    Delete(Index),
+}
+
+/// Generates sequences of operations in which about one in four is the
+/// deletion of a key inserted earlier in the sequence. Deletions before any
+/// insertion are dropped, so sequences may be shorter than `size`.
+pub fn ops<K, V>(
+    keys: impl Strategy<Value = K>,
+    values: impl Strategy<Value = V>,
+    size: impl Into<SizeRange>,
+) -> impl Strategy<Value = Vec<Op<K, V>>>
+where
+    K: Clone + Debug,
+    V: Debug,
+{
+    let step = prop_oneof![
+        3 => (keys, values).prop_map(|(key, value)| Step::Insert(key, value)),
+        1 => any::<Index>().prop_map(Step::Delete),
+    ];
+    vec(step, size).prop_map(|steps| {
+        let mut inserted: Vec<K> = Vec::new();
+        steps
+            .into_iter()
+            .filter_map(|step| match step {
+                Step::Insert(key, value) => {
+                    inserted.push(key.clone());
+                    Some(Op::Insert(key, value))
+                }
+                Step::Delete(_) if inserted.is_empty() => None,
+                Step::Delete(index) => Some(Op::Delete(index.get(&inserted).clone())),
+            })
+            .collect()
+    })
+}
+
+/// Generates trees by applying [`ops`] of `keys` and `values` to an empty
+/// tree.
+pub fn trees<K, V>(
+    keys: impl Strategy<Value = K>,
+    values: impl Strategy<Value = V>,
+    options: TreeOptions,
+) -> impl Strategy<Value = Tree<K, V>>
+where
+    K: Ord + Clone + Hashable + Debug + Send + Sync + 'static,
+    V: Clone + Hashable + Debug + Send + Sync + 'static,
+{
+    trees_with_entries(keys, values, options).prop_map(|(tree, _)| tree)
+}
+
+/// Like [`trees`], but also returns the entries of each tree, to check the
+/// code consuming it against.
+pub fn trees_with_entries<K, V>(
+    keys: impl Strategy<Value = K>,
+    values: impl Strategy<Value = V>,
+    options: TreeOptions,
+) -> impl Strategy<Value = (Tree<K, V>, BTreeMap<K, V>)>
+where
+    K: Ord + Clone + Hashable + Debug + Send + Sync + 'static,
+    V: Clone + Hashable + Debug + Send + Sync + 'static,
+{
+    let persistence = match options.persistence {
+        Persistence::Any => prop_oneof![
+            Just(Persistence::InMemory),
+            Just(Persistence::Saved),
+            Just(Persistence::Reloaded),
+            Just(Persistence::Partial),
+        ]
+        .boxed(),
+        persistence => Just(persistence).boxed(),
+    };
+    (ops(keys, values, options.ops), persistence, any::<Index>()).prop_map(
+        |(ops, persistence, split)| {
+            build(&ops, persistence, split).expect("in-memory stores don't fail")
+        },
+    )
+}
+
+fn build<K, V>(
+    ops: &[Op<K, V>],
+    persistence: Persistence,
+    split: Index,
+) -> Result<(Tree<K, V>, BTreeMap<K, V>)>
+where
+    K: Ord + Clone + Hashable + Send + Sync + 'static,
+    V: Clone + Hashable + Send + Sync + 'static,
+{
+    let manager = Arc::new(NodeManager::in_memory());
+    let mut tree = Tree::with_manager(manager.clone());
+    let mut entries = BTreeMap::new();
+    let split = match persistence {
+        Persistence::Partial => split.index(ops.len() + 1),
+        _ => ops.len(),
+    };
+    let (before, after) = ops.split_at(split);
+    for op in before {
+        tree = op.apply(&tree)?;
+        op.apply_to_map(&mut entries);
+    }
+    if persistence != Persistence::InMemory {
+        tree = tree.save()?;
+    }
+    if persistence == Persistence::Reloaded {
+        tree = match tree.root_ptr() {
+            Some(root) => Tree::load(manager, root),
+            None => Tree::with_manager(manager),
+        };
+    }
+    for op in after {
+        tree = op.apply(&tree)?;
+        op.apply_to_map(&mut entries);
+    }
+    Ok((tree, entries))
+}
@@ -0,0 +1,136 @@
+//! Ranges of a tree as a [`Stream`], for async consumers.
+//!
+//! Node stores are synchronous, so [`RangeStream`] walks the range on a
+//! worker thread and hands the entries over through a bounded queue. Polling
+//! never waits for the store, and the worker stops reading once the queue
+//! holds `prefetch` entries, so paginating a huge range neither blocks the
+//! executor nor loads more than a bounded number of entries ahead.
+
+use std::collections::VecDeque;
+use std::ops::Bound;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use futures_core::Stream;
+
+use crate::tree::node_manager::NodeHandle;
+
+use super::node::Node;
+use super::Tree;
+use crate::Result;
+
+type Item<K, V> = Result<NodeHandle<Node<K, V>>>;
+
+/// A stream over the entries of a tree whose keys are in a range, in key
+/// order, created by [`Tree::range_stream`]. Dropping the stream stops the
+/// worker thread.
+pub struct RangeStream<K, V> {
+    shared: Arc<Shared<K, V>>,
+}
+
+struct Shared<K, V> {
+    state: Mutex<State<K, V>>,
+    /// Signalled when the consumer takes an entry or drops the stream.
+    space: Condvar,
+    prefetch: usize,
+}
+
+struct State<K, V> {
+    queue: VecDeque<Item<K, V>>,
+    /// Whether the worker reached the end of the range.
+    done: bool,
+    /// Whether the stream was dropped.
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl<K, V> Shared<K, V> {
+    /// Locks the state, which stays consistent even if a thread panicked
+    /// while holding the lock since every update is a single assignment or
+    /// queue operation.
+    fn lock(&self) -> MutexGuard<'_, State<K, V>> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Marks the range as done after queueing `last`.
+    fn finish(&self, last: Option<Item<K, V>>) {
+        let mut state = self.lock();
+        state.queue.extend(last);
+        state.done = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<K, V> RangeStream<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    pub(crate) fn new(tree: Tree<K, V>, range: (Bound<K>, Bound<K>), prefetch: usize) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                done: false,
+                closed: false,
+                waker: None,
+            }),
+            space: Condvar::new(),
+            prefetch: prefetch.max(1),
+        });
+        let worker = shared.clone();
+        thread::spawn(move || {
+            let entries = match tree.range(range) {
+                Ok(entries) => entries,
+                Err(err) => return worker.finish(Some(Err(err))),
+            };
+            for entry in entries {
+                let mut state = worker.lock();
+                while state.queue.len() >= worker.prefetch && !state.closed {
+                    state = worker
+                        .space
+                        .wait(state)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                }
+                if state.closed {
+                    return;
+                }
+                state.queue.push_back(entry);
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            }
+            worker.finish(None);
+        });
+        RangeStream { shared }
+    }
+}
+
+impl<K, V> Stream for RangeStream<K, V> {
+    type Item = Item<K, V>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.shared.lock();
+        if let Some(entry) = state.queue.pop_front() {
+            self.shared.space.notify_one();
+            return Poll::Ready(Some(entry));
+        }
+        if state.done {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<K, V> Drop for RangeStream<K, V> {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.space.notify_one();
+    }
+}
@@ -0,0 +1,168 @@
+//! Trees whose values are the roots of other trees.
+//!
+//! Storing a [`RootValue`] under a key nests a saved tree inside another, as
+//! in layouts like account → storage. The value commits to the subtree's
+//! merkle root, so a proof for the outer key followed by a proof inside the
+//! subtree forms a [`ChainedProof`] of an entry against the outermost root.
+
+use std::borrow::Borrow;
+use std::sync::Arc;
+
+use super::node::Manager;
+use super::proof::Proof;
+use super::Tree;
+use crate::tree::hash::{Digest, Hashable, MerkleTree, Update, EMPTY_HASH};
+use crate::tree::node_manager::Ptr;
+use crate::{Error, Result};
+
+/// A reference to a saved tree, used as a value of another tree.
+///
+/// Only the merkle root is hashed; the pointer just locates the subtree in
+/// its store.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RootValue {
+    hash: Digest,
+    ptr: Option<Ptr>,
+}
+
+impl RootValue {
+    /// The value of an empty subtree.
+    pub const EMPTY: RootValue = RootValue {
+        hash: EMPTY_HASH,
+        ptr: None,
+    };
+
+    /// Refers to the current version of `tree`, which must be saved.
+    pub fn of<K: Hashable, V: Hashable>(tree: &Tree<K, V>) -> Result<Self> {
+        if tree.is_empty() {
+            return Ok(RootValue::EMPTY);
+        }
+        let Some(ptr) = tree.root_ptr() else {
+            return Err(Error::invalid(
+                "a subtree must be saved before it is stored as a value",
+            ));
+        };
+        Ok(RootValue {
+            hash: tree.merkle_hash()?,
+            ptr: Some(ptr),
+        })
+    }
+
+    /// Returns the merkle root of the subtree.
+    pub fn hash(&self) -> &Digest {
+        &self.hash
+    }
+
+    pub fn ptr(&self) -> Option<Ptr> {
+        self.ptr
+    }
+
+    /// Opens the subtree, whose nodes are read through `manager` as they are
+    /// accessed.
+    pub fn open<K, V>(&self, manager: Arc<Manager<K, V>>) -> Tree<K, V> {
+        match self.ptr {
+            None => Tree::with_manager(manager),
+            Some(ptr) => Tree::load(manager, ptr),
+        }
+    }
+}
+
+impl Hashable for RootValue {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(&self.hash);
+    }
+}
+
+impl<K: Ord + Clone> Tree<K, RootValue> {
+    /// Opens the subtree stored under `key`, if any.
+    pub fn subtree<Q, K2, V2>(
+        &self,
+        key: &Q,
+        manager: Arc<Manager<K2, V2>>,
+    ) -> Result<Option<Tree<K2, V2>>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Ok(self.get(key)?.map(|root| root.open(manager)))
+    }
+}
+
+impl<K: Ord + Clone + Hashable> Tree<K, RootValue> {
+    /// Proves that a subtree is stored under `key`, returning the proof along
+    /// with the opened subtree so the proof can be extended into it.
+    pub fn prove_subtree<Q, K2, V2>(
+        &self,
+        key: &Q,
+        manager: Arc<Manager<K2, V2>>,
+    ) -> Result<Option<(Proof, Tree<K2, V2>)>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let Some(root) = self.get(key)? else {
+            return Ok(None);
+        };
+        let Some(proof) = self.prove(key)? else {
+            return Ok(None);
+        };
+        Ok(Some((proof, root.open(manager))))
+    }
+}
+
+/// Proofs through nested trees, outermost first. Every proof but the last
+/// proves a [`RootValue`] whose subtree the next proof is for.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ChainedProof {
+    pub levels: Vec<Proof>,
+}
+
+impl ChainedProof {
+    pub fn new(outer: Proof) -> Self {
+        ChainedProof {
+            levels: vec![outer],
+        }
+    }
+
+    /// Extends the chain with a proof inside the subtree proven last.
+    pub fn then(mut self, inner: Proof) -> Self {
+        self.levels.push(inner);
+        self
+    }
+
+    /// Computes the outermost root hash, assuming the innermost tree contains
+    /// `value` and `keys` are the keys at each level, outermost first.
+    /// Returns `None` if there isn't one key per level.
+    pub fn root_hash<K: Hashable + ?Sized, V: Hashable + ?Sized>(
+        &self,
+        keys: &[&K],
+        value: &V,
+    ) -> Option<Digest> {
+        if keys.len() != self.levels.len() {
+            return None;
+        }
+        let mut levels = self.levels.iter().zip(keys).rev();
+        let (inner, key) = levels.next()?;
+        let root = inner.root_hash(*key, value);
+        Some(levels.fold(root, |root, (proof, key)| {
+            proof.root_hash(
+                *key,
+                &RootValue {
+                    hash: root,
+                    ptr: None,
+                },
+            )
+        }))
+    }
+
+    /// Checks that the entry at `keys` holds `value` in the tree with root
+    /// hash `root`.
+    pub fn verify<K: Hashable + ?Sized, V: Hashable + ?Sized>(
+        &self,
+        root: &Digest,
+        keys: &[&K],
+        value: &V,
+    ) -> bool {
+        self.root_hash(keys, value).as_ref() == Some(root)
+    }
+}
@@ -0,0 +1,837 @@
+//! A tree with numbered saved versions.
+//!
+//! [`VersionedTree`] keeps a working tree which is modified in place and
+//! committed by [`VersionedTree::save`], which assigns the next [`Version`].
+//! Versions increase by exactly one per save, starting from a configurable
+//! initial version. The roots of all versions are recorded, so any version can be
+//! read back, and the history can be rolled back to an earlier version,
+//! freeing the nodes only the discarded versions used. Old versions can also
+//! be pruned according to a [`PruningPolicy`]. Changes to the history are
+//! reported to a [`VersionObserver`], e.g. to replicate committed roots along
+//! with the node writes reported by the manager's
+//! [`StoreObserver`](crate::tree::node_manager::StoreObserver). Before a save
+//! is committed, its changes are checked by the registered
+//! [`CommitValidator`]s, any of which can reject it. Changesets applied with
+//! [`VersionedTree::apply_changeset`] carry an id recorded with their
+//! version, so retrying one is applied exactly once. Writes of empty values
+//! are handled according to the tree's [`EmptyValues`] setting.
+//!
+//! Readers on other threads can hold on to a version with
+//! [`VersionedTree::pin`] or [`VersionedTree::pin_current`]. While a
+//! [`VersionPin`] of a version exists, pruning keeps the version and
+//! rollbacks which would discard it fail, so a traversal of the pinned tree
+//! never finds its nodes deleted. The version is pruned by the first prune
+//! after its last pin is dropped.
+//!
+//! [`VersionedTree::prune_deferred`] drops the pruned versions right away but
+//! leaves deleting their nodes to a [`DeletionQueue`], which a background
+//! thread drains at a limited rate.
+
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Bound::{Excluded, Unbounded};
+use std::ops::RangeBounds;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use super::diff::Change;
+use super::node::{Manager, Node};
+use super::{BatchOp, Tree};
+use crate::tree::hash::{is_empty_encoding, Digest, Hashable, MerkleTree, EMPTY_HASH};
+use crate::tree::map::{Map, PersistentMap};
+use crate::tree::node_manager::{DeletionQueue, NodeRef, Ptr};
+use crate::{Error, Result};
+
+/// A version number of a [`VersionedTree`].
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
+pub struct Version(u64);
+
+impl Version {
+    /// The empty tree before the first save. It is never saved itself.
+    pub const ZERO: Version = Version(0);
+
+    /// The version created by the first save unless configured otherwise.
+    pub const INITIAL: Version = Version(1);
+
+    pub const fn new(n: u64) -> Self {
+        Version(n)
+    }
+
+    pub const fn get(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the version following this one.
+    pub fn next(self) -> Result<Version> {
+        match self.0.checked_add(1) {
+            Some(n) => Ok(Version(n)),
+            None => Err(Error::invalid(format!("version {} has no successor", self))),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<Version> for u64 {
+    fn from(version: Version) -> u64 {
+        version.0
+    }
+}
+
+/// Identifies a changeset applied with [`VersionedTree::apply_changeset`],
+/// e.g. a random token or the hash of the changeset, chosen by the client.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ChangesetId(pub [u8; 32]);
+
+/// A saved version of a [`VersionedTree`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VersionInfo {
+    pub version: Version,
+    /// The root of the version, or `None` if it is empty.
+    pub root: Option<Ptr>,
+    pub hash: Digest,
+    /// The changeset the version was saved by, if it was saved by
+    /// [`VersionedTree::apply_changeset`].
+    pub changeset: Option<ChangesetId>,
+}
+
+/// A change to the history of a [`VersionedTree`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum VersionEvent {
+    /// A version was saved, after all of its nodes were written.
+    Committed(VersionInfo),
+    /// The versions after `to` were discarded. Reported before their nodes
+    /// are deleted.
+    RolledBack { to: Version },
+    /// Versions were dropped by pruning. Reported before their nodes are
+    /// deleted.
+    Pruned { versions: Vec<Version> },
+}
+
+/// Receives the [`VersionEvent`]s of a [`VersionedTree`], set with
+/// [`VersionedTree::set_observer`]. Events are delivered on the thread which
+/// made the change, before the call making it returns.
+pub trait VersionObserver: Send + Sync {
+    fn on_version_event(&self, event: &VersionEvent);
+}
+
+/// A save about to be committed, passed to [`CommitValidator`]s.
+pub struct PendingCommit<'a, K, V> {
+    /// The version the save creates.
+    pub version: Version,
+    /// The latest saved version, empty before the first save.
+    pub base: &'a Tree<K, V>,
+    /// The tree being saved.
+    pub working: &'a Tree<K, V>,
+    /// The changes from `base` to `working` in key order.
+    pub changes: &'a [Change<K, V>],
+}
+
+/// Checks a save before it is committed, registered with
+/// [`VersionedTree::add_validator`], e.g. to enforce invariants such as
+/// non-negative balances next to the data. Returning an error rejects the
+/// save, which then fails without writing anything and leaves the working
+/// tree as it was.
+pub trait CommitValidator<K, V>: Send + Sync {
+    fn validate(&self, commit: &PendingCommit<'_, K, V>) -> Result<()>;
+}
+
+impl<K, V, F> CommitValidator<K, V> for F
+where
+    F: Fn(&PendingCommit<'_, K, V>) -> Result<()> + Send + Sync,
+{
+    fn validate(&self, commit: &PendingCommit<'_, K, V>) -> Result<()> {
+        self(commit)
+    }
+}
+
+/// Which versions [`VersionedTree::prune`] keeps. The default keeps every
+/// version; setting either rule prunes the versions matched by neither. The
+/// latest version is always kept.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct PruningPolicy {
+    keep_recent: Option<u64>,
+    keep_every: Option<u64>,
+}
+
+impl PruningPolicy {
+    /// Keeps the latest `n` versions.
+    pub fn keep_recent(self, n: u64) -> Self {
+        PruningPolicy {
+            keep_recent: Some(n),
+            ..self
+        }
+    }
+
+    /// Keeps every version which is a multiple of `k`, e.g. as snapshots.
+    /// Zero keeps none of them.
+    pub fn keep_every(self, k: u64) -> Self {
+        PruningPolicy {
+            keep_every: Some(k),
+            ..self
+        }
+    }
+
+    /// Returns whether `version` is kept while `latest` is the latest
+    /// version.
+    pub fn retains(&self, version: Version, latest: Version) -> bool {
+        if version == latest || (self.keep_recent.is_none() && self.keep_every.is_none()) {
+            return true;
+        }
+        let recent = self.keep_recent.is_some_and(|n| latest.0 - version.0 < n);
+        let every = self.keep_every.is_some_and(|k| version.0.is_multiple_of(k));
+        recent || every
+    }
+}
+
+/// How a [`VersionedTree`] handles writes of values with an empty encoding,
+/// e.g. empty byte strings. Ecosystems disagree: some store them like any
+/// other value, Cosmos chains reject them, and others read an empty value as
+/// the absence of the key.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum EmptyValues {
+    /// Stores empty values like any other.
+    #[default]
+    Allow,
+    /// Fails the write with [`Error::EmptyValue`].
+    Reject,
+    /// Deletes the key instead.
+    Delete,
+}
+
+impl EmptyValues {
+    /// Returns the write `op` turns into under this setting.
+    pub fn apply<K, V: Hashable>(self, op: BatchOp<K, V>) -> Result<BatchOp<K, V>> {
+        match op {
+            BatchOp::Insert(key, value) if is_empty_encoding(&value) => match self {
+                EmptyValues::Allow => Ok(BatchOp::Insert(key, value)),
+                EmptyValues::Reject => Err(Error::EmptyValue),
+                EmptyValues::Delete => Ok(BatchOp::Delete(key)),
+            },
+            op => Ok(op),
+        }
+    }
+}
+
+/// The number of live [`VersionPin`]s of each pinned version.
+type PinCounts = Arc<Mutex<BTreeMap<Version, usize>>>;
+
+fn lock_pins(pins: &Mutex<BTreeMap<Version, usize>>) -> MutexGuard<'_, BTreeMap<Version, usize>> {
+    // The counts are consistent after every update, so a panic elsewhere
+    // while the lock was held leaves nothing to recover.
+    pins.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Keeps a version of a [`VersionedTree`] from being pruned or rolled back
+/// while it is held, see the [module documentation](self). Clones pin the
+/// version too, and the pin can be moved to other threads along with its
+/// tree.
+pub struct VersionPin<K, V> {
+    version: Version,
+    tree: Tree<K, V>,
+    pins: PinCounts,
+}
+
+impl<K, V> VersionPin<K, V> {
+    fn new(version: Version, tree: Tree<K, V>, pins: PinCounts) -> Self {
+        *lock_pins(&pins).entry(version).or_insert(0) += 1;
+        VersionPin {
+            version,
+            tree,
+            pins,
+        }
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Returns the pinned version, which can be read until the pin is
+    /// dropped.
+    pub fn tree(&self) -> &Tree<K, V> {
+        &self.tree
+    }
+}
+
+impl<K, V> Clone for VersionPin<K, V> {
+    fn clone(&self) -> Self {
+        VersionPin::new(self.version, self.tree.clone(), self.pins.clone())
+    }
+}
+
+impl<K, V> Drop for VersionPin<K, V> {
+    fn drop(&mut self) {
+        let mut pins = lock_pins(&self.pins);
+        if let Some(count) = pins.get_mut(&self.version) {
+            *count -= 1;
+            if *count == 0 {
+                pins.remove(&self.version);
+            }
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for VersionPin<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VersionPin")
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
+/// A tree whose saves are numbered versions which stay readable until they
+/// are rolled back or pruned.
+pub struct VersionedTree<K, V> {
+    working: Tree<K, V>,
+    versions: Vec<VersionInfo>,
+    pins: PinCounts,
+    /// The version the first save creates.
+    initial: Version,
+    pruning: PruningPolicy,
+    observer: Option<Arc<dyn VersionObserver>>,
+    validators: Vec<Arc<dyn CommitValidator<K, V>>>,
+    /// The version each recorded changeset was saved as.
+    changesets: HashMap<ChangesetId, Version>,
+    empty_values: EmptyValues,
+}
+
+impl<K, V> VersionedTree<K, V> {
+    /// Creates an empty tree which saves its versions through `manager`,
+    /// starting with [`Version::INITIAL`].
+    pub fn new(manager: Arc<Manager<K, V>>) -> Self {
+        VersionedTree {
+            working: Tree::with_manager(manager),
+            versions: Vec::new(),
+            pins: PinCounts::default(),
+            initial: Version::INITIAL,
+            pruning: PruningPolicy::default(),
+            observer: None,
+            validators: Vec::new(),
+            changesets: HashMap::new(),
+            empty_values: EmptyValues::default(),
+        }
+    }
+
+    /// Like [`VersionedTree::new`], but the first save creates `initial`,
+    /// e.g. for a chain which starts at a later height.
+    pub fn with_initial_version(manager: Arc<Manager<K, V>>, initial: Version) -> Result<Self> {
+        if initial == Version::ZERO {
+            return Err(Error::invalid("the initial version must not be zero"));
+        }
+        Ok(VersionedTree {
+            initial,
+            ..VersionedTree::new(manager)
+        })
+    }
+
+    /// Reopens a tree from the versions it previously recorded, see
+    /// [`VersionedTree::versions`], which must be in increasing order. The
+    /// working tree starts at the latest version. Versions may be missing
+    /// where they were pruned; the first one is taken as the initial version.
+    pub fn open(
+        manager: Arc<Manager<K, V>>,
+        versions: impl IntoIterator<Item = VersionInfo>,
+    ) -> Result<Self> {
+        let versions: Vec<VersionInfo> = versions.into_iter().collect();
+        let mut last = Version::ZERO;
+        for info in &versions {
+            if info.version <= last {
+                return Err(Error::invalid(format!(
+                    "version {} doesn't follow version {}",
+                    info.version, last
+                )));
+            }
+            last = info.version;
+        }
+        let initial = versions
+            .first()
+            .map_or(Version::INITIAL, |info| info.version);
+        for root in versions.iter().filter_map(|info| info.root) {
+            manager.record_version_root(root)?;
+        }
+        let working = match versions.last().and_then(|info| info.root) {
+            Some(root) => Tree::load(manager, root),
+            None => Tree::with_manager(manager),
+        };
+        let changesets = versions
+            .iter()
+            .filter_map(|info| Some((info.changeset?, info.version)))
+            .collect();
+        Ok(VersionedTree {
+            working,
+            versions,
+            pins: PinCounts::default(),
+            initial,
+            pruning: PruningPolicy::default(),
+            observer: None,
+            validators: Vec::new(),
+            changesets,
+            empty_values: EmptyValues::default(),
+        })
+    }
+
+    /// Returns the tree with the changes made since the last save.
+    pub fn working(&self) -> &Tree<K, V> {
+        &self.working
+    }
+
+    /// Replaces the working tree, which must use the same manager.
+    pub fn set_working(&mut self, tree: Tree<K, V>) -> Result<()> {
+        if !Arc::ptr_eq(tree.manager(), self.working.manager()) {
+            return Err(Error::invalid(
+                "the working tree must use the versioned tree's node manager",
+            ));
+        }
+        self.working = tree;
+        Ok(())
+    }
+
+    pub fn pruning(&self) -> PruningPolicy {
+        self.pruning
+    }
+
+    /// Sets the policy applied by [`VersionedTree::prune`].
+    pub fn set_pruning(&mut self, policy: PruningPolicy) {
+        self.pruning = policy;
+    }
+
+    /// Reports every change to the history to `observer`.
+    pub fn set_observer(&mut self, observer: Arc<dyn VersionObserver>) {
+        self.observer = Some(observer);
+    }
+
+    /// Checks every later save with `validator`, after the validators
+    /// added before it.
+    pub fn add_validator(&mut self, validator: Arc<dyn CommitValidator<K, V>>) {
+        self.validators.push(validator);
+    }
+
+    fn notify(&self, event: impl FnOnce() -> VersionEvent) {
+        if let Some(observer) = &self.observer {
+            observer.on_version_event(&event());
+        }
+    }
+
+    /// Returns the recorded versions, oldest first.
+    pub fn versions(&self) -> &[VersionInfo] {
+        &self.versions
+    }
+
+    /// Returns the latest saved version, or [`Version::ZERO`] if nothing was
+    /// saved.
+    pub fn latest_version(&self) -> Version {
+        self.versions
+            .last()
+            .map_or(Version::ZERO, |info| info.version)
+    }
+
+    /// Returns the version the next save creates.
+    pub fn next_version(&self) -> Result<Version> {
+        match self.versions.last() {
+            None => Ok(self.initial),
+            Some(info) => info.version.next(),
+        }
+    }
+
+    /// Returns the record of `version`, if it exists.
+    pub fn version(&self, version: Version) -> Option<&VersionInfo> {
+        self.versions
+            .binary_search_by_key(&version, |info| info.version)
+            .ok()
+            .map(|i| &self.versions[i])
+    }
+
+    /// Returns the record of the version saved by the changeset `id`, if it
+    /// is still recorded.
+    pub fn changeset_version(&self, id: &ChangesetId) -> Option<&VersionInfo> {
+        self.version(*self.changesets.get(id)?)
+    }
+
+    /// Opens a saved version for reading. [`Version::ZERO`] is the empty
+    /// tree.
+    pub fn load_version(&self, version: Version) -> Result<Tree<K, V>> {
+        let manager = self.working.manager().clone();
+        if version == Version::ZERO {
+            return Ok(Tree::with_manager(manager));
+        }
+        let Some(info) = self.version(version) else {
+            return Err(Error::invalid(format!(
+                "version {} does not exist",
+                version
+            )));
+        };
+        Ok(match info.root {
+            Some(root) => Tree::load(manager, root),
+            None => Tree::with_manager(manager),
+        })
+    }
+
+    /// Pins a saved version for reading, see [`VersionPin`].
+    /// [`Version::ZERO`] pins the empty tree, which holds no nodes.
+    pub fn pin(&self, version: Version) -> Result<VersionPin<K, V>> {
+        let tree = self.load_version(version)?;
+        Ok(VersionPin::new(version, tree, self.pins.clone()))
+    }
+
+    /// Pins the latest saved version.
+    pub fn pin_current(&self) -> Result<VersionPin<K, V>> {
+        self.pin(self.latest_version())
+    }
+
+    /// Returns whether any [`VersionPin`] of `version` is held.
+    pub fn is_pinned(&self, version: Version) -> bool {
+        lock_pins(&self.pins).contains_key(&version)
+    }
+
+    /// Discards every version after `version` along with the unsaved
+    /// changes, and resets the working tree to `version`. [`Version::ZERO`]
+    /// discards the whole history. Fails if any of the discarded versions is
+    /// pinned. Returns the number of deleted nodes.
+    pub fn rollback_to(&mut self, version: Version) -> Result<usize> {
+        if version != Version::ZERO && self.version(version).is_none() {
+            return Err(Error::invalid(format!(
+                "version {} does not exist",
+                version
+            )));
+        }
+        let pins = lock_pins(&self.pins);
+        if let Some((pinned, _)) = pins.range((Excluded(version), Unbounded)).next() {
+            return Err(Error::invalid(format!(
+                "can't roll back past version {}, which is pinned",
+                pinned
+            )));
+        }
+        drop(pins);
+        let keep = self
+            .versions
+            .partition_point(|info| info.version <= version);
+        let discarded = self.versions.split_off(keep);
+        for id in discarded.iter().filter_map(|info| info.changeset) {
+            self.changesets.remove(&id);
+        }
+        self.working = self.load_version(version)?;
+        // Reported before the nodes go away, so followers stop serving the
+        // discarded versions first.
+        self.notify(|| VersionEvent::RolledBack { to: version });
+        let mut deleted = 0;
+        for root in discarded.iter().rev().filter_map(|info| info.root) {
+            self.working.manager().forget_version_root(&root)?;
+            deleted += self.working.manager().release(&root)?;
+        }
+        Ok(deleted)
+    }
+
+    /// Removes the records of the versions the pruning policy doesn't keep
+    /// and which aren't pinned, and returns them as trees, which still hold
+    /// their nodes.
+    fn take_pruned(&mut self) -> Result<Vec<Tree<K, V>>> {
+        let latest = self.latest_version();
+        let manager = self.working.manager();
+        let pins = lock_pins(&self.pins);
+        let mut pruned = Vec::new();
+        let mut versions = Vec::new();
+        self.versions.retain(|info| {
+            let keep =
+                self.pruning.retains(info.version, latest) || pins.contains_key(&info.version);
+            if !keep {
+                if let Some(id) = &info.changeset {
+                    self.changesets.remove(id);
+                }
+                versions.push(info.version);
+                pruned.extend(info.root.map(|root| Tree::load(manager.clone(), root)));
+            }
+            keep
+        });
+        drop(pins);
+        if !versions.is_empty() {
+            self.notify(|| VersionEvent::Pruned { versions });
+        }
+        for root in pruned.iter().filter_map(Tree::root_ptr) {
+            manager.forget_version_root(&root)?;
+        }
+        Ok(pruned)
+    }
+
+    /// Drops the versions the pruning policy doesn't keep, other than pinned
+    /// ones, and deletes the nodes no remaining version uses. Returns the
+    /// number of deleted nodes.
+    pub fn prune(&mut self) -> Result<usize> {
+        self.take_pruned()?
+            .iter()
+            .map(Tree::free_version)
+            .sum::<Result<usize>>()
+    }
+
+    /// Like [`VersionedTree::prune`], but deletes the nodes on a background
+    /// thread. The pruned versions are dropped from the history before this
+    /// returns, so they can't be loaded while their nodes are being deleted.
+    pub fn prune_in_background(&mut self) -> JoinHandle<Result<usize>>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let pruned = self.take_pruned();
+        thread::spawn(move || pruned?.iter().map(Tree::free_version).sum())
+    }
+
+    /// Like [`VersionedTree::prune`], but queues the roots of the pruned
+    /// versions on `queue` instead of deleting their nodes, which happens as
+    /// the queue is drained. Returns the number of queued roots.
+    pub fn prune_deferred(&mut self, queue: &DeletionQueue<Node<K, V>>) -> Result<usize> {
+        if !Arc::ptr_eq(queue.manager(), self.working.manager()) {
+            return Err(Error::invalid(
+                "deletion queue belongs to another node manager",
+            ));
+        }
+        let roots: Vec<Ptr> = self
+            .take_pruned()?
+            .iter()
+            .filter_map(Tree::root_ptr)
+            .collect();
+        for root in &roots {
+            queue.defer(*root)?;
+        }
+        Ok(roots.len())
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> VersionedTree<K, V> {
+    /// Looks up `key` in the working tree.
+    pub fn get<Q>(&self, key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.working.get(key)
+    }
+
+    pub fn delete<Q>(&mut self, key: &Q) -> Result<()>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.working = self.working.delete(key)?;
+        Ok(())
+    }
+
+    /// Removes every key in `range` from the working tree, see
+    /// [`Tree::delete_range`].
+    pub fn delete_range(&mut self, range: impl RangeBounds<K>) -> Result<()> {
+        self.working = self.working.delete_range(range)?;
+        Ok(())
+    }
+}
+
+impl<K: Ord + Clone, V: Clone + Hashable> VersionedTree<K, V> {
+    /// Sets how writes of empty values are handled. Defaults to
+    /// [`EmptyValues::Allow`].
+    pub fn set_empty_values(&mut self, empty_values: EmptyValues) {
+        self.empty_values = empty_values;
+    }
+
+    pub fn empty_values(&self) -> EmptyValues {
+        self.empty_values
+    }
+
+    /// Sets `key` to `value`, or handles an empty value as set with
+    /// [`VersionedTree::set_empty_values`].
+    pub fn insert(&mut self, key: K, value: V) -> Result<()> {
+        self.working = match self.empty_values.apply(BatchOp::Insert(key, value))? {
+            BatchOp::Insert(key, value) => self.working.insert(key, value)?,
+            BatchOp::Delete(key) => self.working.delete(&key)?,
+        };
+        Ok(())
+    }
+
+    /// Applies many writes to the working tree in a single pass, see
+    /// [`Tree::apply_batch`]. If any of them is rejected, none is applied.
+    pub fn apply_batch(&mut self, ops: impl IntoIterator<Item = BatchOp<K, V>>) -> Result<()> {
+        let ops = self.checked(ops)?;
+        self.working = self.working.apply_batch(ops)?;
+        Ok(())
+    }
+
+    fn checked(&self, ops: impl IntoIterator<Item = BatchOp<K, V>>) -> Result<Vec<BatchOp<K, V>>> {
+        ops.into_iter()
+            .map(|op| self.empty_values.apply(op))
+            .collect()
+    }
+}
+
+impl<K: Ord + Clone + Hashable, V: Clone + PartialEq + Hashable> VersionedTree<K, V> {
+    /// Saves the working tree as the next version and returns it.
+    pub fn save(&mut self) -> Result<Version> {
+        let version = self.next_version()?;
+        self.save_version(version)?;
+        Ok(version)
+    }
+
+    /// Saves the working tree as `version`, which must be the next version.
+    /// Fails without saving if it would skip versions or go back to an
+    /// existing one, e.g. when replaying blocks out of order.
+    pub fn save_version(&mut self, version: Version) -> Result<()> {
+        self.commit(version, None).map(drop)
+    }
+
+    /// Applies the changes of the changeset `id` to the latest version and
+    /// saves the result as the next version, recording `id` with it. If a
+    /// recorded version was already saved by `id`, nothing is applied and
+    /// that version is returned, so a client which doesn't know whether its
+    /// changeset went through can retry it safely. Ids are forgotten along
+    /// with their versions when these are pruned or rolled back.
+    ///
+    /// Fails if the working tree has unsaved changes, which would otherwise
+    /// be committed along with the changeset.
+    pub fn apply_changeset(
+        &mut self,
+        id: ChangesetId,
+        changes: impl IntoIterator<Item = Change<K, V>>,
+    ) -> Result<VersionInfo> {
+        if let Some(info) = self.changeset_version(&id) {
+            return Ok(*info);
+        }
+        let latest = self.versions.last().and_then(|info| info.root);
+        if self.working.root_ptr() != latest || (latest.is_none() && !self.working.is_empty()) {
+            return Err(Error::invalid(
+                "can't apply a changeset over unsaved changes",
+            ));
+        }
+        let ops = self.checked(changes.into_iter().map(|change| match change {
+            Change::Create { key, value }
+            | Change::Update {
+                key, new: value, ..
+            } => BatchOp::Insert(key, value),
+            Change::Delete { key, .. } => BatchOp::Delete(key),
+        }))?;
+        let base = self.working.clone();
+        self.working = base.apply_batch(ops)?;
+        let version = self.next_version()?;
+        self.commit(version, Some(id))
+            .inspect_err(|_| self.working = base)
+    }
+
+    fn commit(&mut self, version: Version, changeset: Option<ChangesetId>) -> Result<VersionInfo> {
+        let next = self.next_version()?;
+        if version < next {
+            return Err(Error::invalid(format!(
+                "version {} is not after the latest version {}",
+                version,
+                self.latest_version()
+            )));
+        }
+        if version > next {
+            return Err(Error::invalid(format!(
+                "saving version {} would skip version {}",
+                version, next
+            )));
+        }
+        self.validate(version)?;
+        let saved = self.working.save_at(version.get())?;
+        let info = VersionInfo {
+            version,
+            root: saved.root_ptr(),
+            hash: saved.merkle_hash()?,
+            changeset,
+        };
+        if let Some(root) = info.root {
+            self.working.manager().record_version_root(root)?;
+        }
+        self.versions.push(info);
+        if let Some(id) = changeset {
+            self.changesets.insert(id, version);
+        }
+        self.working = saved;
+        self.notify(|| VersionEvent::Committed(info));
+        Ok(info)
+    }
+
+    /// Runs the validators on the changes since the latest version.
+    fn validate(&self, version: Version) -> Result<()> {
+        if self.validators.is_empty() {
+            return Ok(());
+        }
+        let base = self.load_version(self.latest_version())?;
+        let changes = self.working.diff(&base).collect::<Result<Vec<_>>>()?;
+        let commit = PendingCommit {
+            version,
+            base: &base,
+            working: &self.working,
+            changes: &changes,
+        };
+        for validator in &self.validators {
+            validator.validate(&commit)?;
+        }
+        Ok(())
+    }
+}
+
+impl<K: Ord + Clone + Hashable, V: Hashable> VersionedTree<K, V> {
+    /// Checks the latest `n` versions like [`Tree::verify`], and that each
+    /// has the recorded root hash. Nodes shared between the versions are
+    /// checked once.
+    pub fn verify_recent(&self, n: usize) -> Result<()> {
+        let m = self.working.manager();
+        let mut verified = HashMap::new();
+        for info in self.versions.iter().rev().take(n) {
+            let hash = match info.root {
+                None => EMPTY_HASH,
+                Some(root) => Node::verify(m, &NodeRef::Stored(root), &mut verified)?.hash,
+            };
+            if hash != info.hash {
+                return Err(Error::corruption(format!(
+                    "version {} doesn't have its recorded root hash",
+                    info.version
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the working tree.
+impl<K: Ord + Clone, V: Clone> Map for VersionedTree<K, V> {
+    type Key = K;
+    type Value = V;
+
+    fn get(&self, key: &K) -> Result<Option<V>> {
+        VersionedTree::get(self, key)
+    }
+
+    fn len(&self) -> Result<u64> {
+        self.working.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.working.is_empty()
+    }
+}
+
+/// Commits save the next version.
+impl<K: Ord + Clone + Hashable, V: Clone + PartialEq + Hashable> PersistentMap
+    for VersionedTree<K, V>
+{
+    fn insert(&mut self, key: &K, value: V) -> Result<()> {
+        VersionedTree::insert(self, key.clone(), value)
+    }
+
+    fn delete(&mut self, key: &K) -> Result<()> {
+        VersionedTree::delete(self, key)
+    }
+
+    fn commit(&mut self) -> Result<Digest> {
+        self.save()?;
+        self.merkle_hash()
+    }
+}
+
+impl<K: Hashable, V: Hashable> MerkleTree for VersionedTree<K, V> {
+    /// Returns the root hash of the working tree.
+    fn merkle_hash(&self) -> Result<Digest> {
+        self.working.merkle_hash()
+    }
+}
@@ -0,0 +1,81 @@
+//! Approximate entry counts which don't require visiting every node.
+//!
+//! Without per-subtree sizes, counts are estimated with Knuth's random probe
+//! estimator: each probe descends a single random path from the root, and the
+//! product of the branching factors along the path is an unbiased estimate of
+//! the number of entries. Probing reads O(depth) nodes, so it stays cheap even
+//! when nodes have to be loaded from disk.
+
+use crate::Result;
+
+/// The number of entries in a tree.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Cardinality {
+    Exact(u64),
+    Estimated {
+        estimate: u64,
+        /// The smallest count consistent with the tree's shape.
+        lower: u64,
+        /// The largest count consistent with the tree's shape, or `u64::MAX`
+        /// if the shape doesn't bound it.
+        upper: u64,
+    },
+}
+
+impl Cardinality {
+    /// The exact count if known, otherwise the estimate.
+    pub fn value(&self) -> u64 {
+        match *self {
+            Cardinality::Exact(n) => n,
+            Cardinality::Estimated { estimate, .. } => estimate,
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        matches!(self, Cardinality::Exact(_))
+    }
+}
+
+/// The number of random paths averaged by the `cardinality` methods.
+pub const DEFAULT_PROBES: usize = 32;
+
+/// Averages `probes` runs of `probe` and clamps the result to the bounds.
+pub(crate) fn estimate(
+    probes: usize,
+    lower: u64,
+    upper: u64,
+    mut probe: impl FnMut(&mut ProbeRng) -> Result<f64>,
+) -> Result<Cardinality> {
+    if lower == upper {
+        return Ok(Cardinality::Exact(lower));
+    }
+    let mut rng = ProbeRng::new();
+    let mut total = 0.0;
+    for _ in 0..probes.max(1) {
+        total += probe(&mut rng)?;
+    }
+    let estimate = (total / probes.max(1) as f64).round() as u64;
+    Ok(Cardinality::Estimated {
+        estimate: estimate.clamp(lower, upper),
+        lower,
+        upper,
+    })
+}
+
+/// A small deterministic xorshift generator used to choose probe paths, so
+/// that repeated estimates of the same tree agree.
+pub(crate) struct ProbeRng(u64);
+
+impl ProbeRng {
+    pub(crate) fn new() -> Self {
+        ProbeRng(0x9e37_79b9_7f4a_7c15)
+    }
+
+    /// Returns a number in `0..n`, which must be non-zero.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
@@ -0,0 +1,247 @@
+//! IPLD content identifiers for merkle hashes.
+//!
+//! Every node hash is the SHA-256 of a plain byte string, the concatenation
+//! of the hashes the node commits to, so it is the CIDv1 of that string as a
+//! `raw` block. Roots and proofs can be exchanged as [`Cid`]s with tooling
+//! which doesn't know about these trees, and a node's children can be looked
+//! up by CID in a [`ContentAddressedStore`](crate::tree::node_manager::ContentAddressedStore),
+//! whose pointers are the node hashes.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::tree::avl::proof::Proof;
+use crate::tree::avl::subtree::{ChainedProof, RootValue};
+use crate::tree::avl::versioned::VersionInfo;
+use crate::tree::avl::Tree;
+use crate::tree::hash::{Digest, Hashable, MerkleTree};
+use crate::tree::node_manager::Ptr;
+use crate::{Error, Result};
+
+/// The multicodec of raw binary blocks.
+pub const RAW_CODEC: u64 = 0x55;
+
+/// The multihash code of SHA-256.
+pub const SHA2_256: u64 = 0x12;
+
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// A CIDv1 of a SHA-256 digest.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cid {
+    codec: u64,
+    digest: Digest,
+}
+
+impl Cid {
+    /// Identifies a `raw` block by its hash.
+    pub fn new(digest: Digest) -> Self {
+        Cid::with_codec(RAW_CODEC, digest)
+    }
+
+    pub fn with_codec(codec: u64, digest: Digest) -> Self {
+        Cid { codec, digest }
+    }
+
+    /// Converts a pointer into a content-addressed store, which is the hash
+    /// of the node it points to.
+    pub fn from_ptr(ptr: &Ptr) -> Result<Self> {
+        let digest = ptr
+            .as_bytes()
+            .try_into()
+            .map_err(|_| Error::invalid(format!("pointer {:?} is not a SHA-256 digest", ptr)))?;
+        Ok(Cid::new(digest))
+    }
+
+    pub fn codec(&self) -> u64 {
+        self.codec
+    }
+
+    pub fn digest(&self) -> &Digest {
+        &self.digest
+    }
+
+    /// Returns the pointer of the identified node in a content-addressed
+    /// store.
+    pub fn to_ptr(&self) -> Result<Ptr> {
+        Ptr::new(&self.digest)
+    }
+
+    /// Returns the binary form: the version, the codec and the multihash.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(36);
+        write_varint(1, &mut bytes);
+        write_varint(self.codec, &mut bytes);
+        write_varint(SHA2_256, &mut bytes);
+        write_varint(self.digest.len() as u64, &mut bytes);
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+        let version = read_varint(&mut bytes)?;
+        if version != 1 {
+            return Err(Error::codec(format!("unsupported CID version {}", version)));
+        }
+        let codec = read_varint(&mut bytes)?;
+        let hash = read_varint(&mut bytes)?;
+        if hash != SHA2_256 {
+            return Err(Error::codec(format!(
+                "unsupported multihash code {:#x}",
+                hash
+            )));
+        }
+        let len = read_varint(&mut bytes)?;
+        let digest = bytes
+            .try_into()
+            .ok()
+            .filter(|_| len == 32)
+            .ok_or_else(|| Error::codec("SHA-256 multihash has the wrong length"))?;
+        Ok(Cid { codec, digest })
+    }
+}
+
+fn write_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..63).step_by(7) {
+        let Some((&byte, rest)) = bytes.split_first() else {
+            return Err(Error::codec("truncated varint"));
+        };
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::codec("varint is too long"))
+}
+
+/// Formats the CID as multibase base32, the default string form of CIDv1.
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.to_bytes();
+        let mut out = String::with_capacity(1 + bytes.len().div_ceil(5) * 8);
+        out.push('b');
+        for chunk in bytes.chunks(5) {
+            let mut buf = [0u8; 5];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            let bits = u64::from_be_bytes([0, 0, 0, buf[0], buf[1], buf[2], buf[3], buf[4]]);
+            for i in 0..(chunk.len() * 8).div_ceil(5) {
+                out.push(BASE32_ALPHABET[(bits >> (35 - 5 * i) & 0x1f) as usize] as char);
+            }
+        }
+        f.write_str(&out)
+    }
+}
+
+impl fmt::Debug for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cid({})", self)
+    }
+}
+
+impl FromStr for Cid {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some(encoded) = s.strip_prefix('b') else {
+            return Err(Error::codec("only base32 CIDs are supported"));
+        };
+        let mut bytes = Vec::with_capacity(encoded.len() * 5 / 8);
+        let (mut bits, mut len) = (0u64, 0);
+        for c in encoded.bytes() {
+            let Some(digit) = BASE32_ALPHABET.iter().position(|&a| a == c) else {
+                return Err(Error::codec(format!(
+                    "invalid base32 character {:?}",
+                    c as char
+                )));
+            };
+            bits = bits << 5 | digit as u64;
+            len += 5;
+            if len >= 8 {
+                len -= 8;
+                bytes.push((bits >> len) as u8);
+            }
+        }
+        Cid::from_bytes(&bytes)
+    }
+}
+
+impl<K: Hashable, V: Hashable> Tree<K, V> {
+    /// Returns the CID of the root hash.
+    pub fn root_cid(&self) -> Result<Cid> {
+        Ok(Cid::new(self.merkle_hash()?))
+    }
+}
+
+impl RootValue {
+    /// Returns the CID of the subtree's root hash.
+    pub fn cid(&self) -> Cid {
+        Cid::new(*self.hash())
+    }
+}
+
+impl VersionInfo {
+    /// Returns the CID of the version's root hash.
+    pub fn cid(&self) -> Cid {
+        Cid::new(self.hash)
+    }
+}
+
+impl Proof {
+    /// Like [`Proof::root_hash`], returning the CID of the root.
+    pub fn root_cid<K: Hashable + ?Sized, V: Hashable + ?Sized>(&self, key: &K, value: &V) -> Cid {
+        Cid::new(self.root_hash(key, value))
+    }
+
+    /// Checks that `key` maps to `value` in the tree with root `root`, which
+    /// must identify a SHA-256 hash of a `raw` block.
+    pub fn verify_cid<K: Hashable + ?Sized, V: Hashable + ?Sized>(
+        &self,
+        root: &Cid,
+        key: &K,
+        value: &V,
+    ) -> bool {
+        root.codec == RAW_CODEC && self.verify(&root.digest, key, value)
+    }
+
+    /// Returns the CIDs of the subtrees the proof commits to, i.e. the
+    /// children of the proven node followed by the sibling of each ancestor,
+    /// nearest first.
+    pub fn sibling_cids(&self) -> Vec<Cid> {
+        [self.left, self.right]
+            .into_iter()
+            .chain(self.path.iter().map(|step| step.sibling))
+            .map(Cid::new)
+            .collect()
+    }
+}
+
+impl ChainedProof {
+    /// Like [`ChainedProof::root_hash`], returning the CID of the outermost
+    /// root.
+    pub fn root_cid<K: Hashable + ?Sized, V: Hashable + ?Sized>(
+        &self,
+        keys: &[&K],
+        value: &V,
+    ) -> Option<Cid> {
+        self.root_hash(keys, value).map(Cid::new)
+    }
+
+    pub fn verify_cid<K: Hashable + ?Sized, V: Hashable + ?Sized>(
+        &self,
+        root: &Cid,
+        keys: &[&K],
+        value: &V,
+    ) -> bool {
+        root.codec == RAW_CODEC && self.verify(&root.digest, keys, value)
+    }
+}
@@ -0,0 +1,98 @@
+//! Output of the Graphviz graphs the trees render for debugging, see
+//! [`art::Tree::to_graphviz`](super::art::Tree::to_graphviz) and
+//! [`avl::Tree::to_graphviz`](super::avl::Tree::to_graphviz).
+//!
+//! [`render`] writes a graph to any [`Write`], as DOT text or laid out as
+//! an image by the `dot` command of a Graphviz installation, which has to
+//! be on the `PATH`.
+
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::{Error, Result};
+
+/// The formats [`render`] writes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    /// The DOT text itself, written without running Graphviz.
+    Dot,
+    Svg,
+    Png,
+}
+
+impl Format {
+    /// Returns the name of the format for `dot -T`.
+    fn name(self) -> &'static str {
+        match self {
+            Format::Dot => "dot",
+            Format::Svg => "svg",
+            Format::Png => "png",
+        }
+    }
+}
+
+/// Writes the DOT graph `dot` to `writer` in `format`. Formats other than
+/// [`Format::Dot`] run the Graphviz `dot` command, and fail if it can't be
+/// run or reports an error.
+pub fn render(dot: &str, format: Format, mut writer: impl Write) -> Result<()> {
+    if format == Format::Dot {
+        writer.write_all(dot.as_bytes())?;
+        return Ok(writer.flush()?);
+    }
+    let mut child = Command::new("dot")
+        .arg(format!("-T{}", format.name()))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| Error::io("running the Graphviz dot command", err))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    // Feed and drain dot concurrently so that neither pipe fills up.
+    let input = dot.to_owned();
+    let feeder = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let errors = thread::spawn(move || {
+        let mut errors = String::new();
+        stderr.read_to_string(&mut errors).map(|_| errors)
+    });
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let copied = std::io::copy(&mut stdout, &mut writer);
+    let status = child.wait()?;
+    let fed = feeder.join().expect("writing to dot doesn't panic");
+    let errors = errors.join().expect("reading from dot doesn't panic")?;
+    if !status.success() {
+        let mut message = format!("Graphviz dot failed with {}", status);
+        if !errors.trim().is_empty() {
+            write!(message, ": {}", errors.trim()).unwrap();
+        }
+        return Err(Error::io(
+            "running the Graphviz dot command",
+            std::io::Error::other(message),
+        ));
+    }
+    fed.map_err(|err| Error::io("writing the graph to Graphviz dot", err))?;
+    copied?;
+    Ok(writer.flush()?)
+}
+
+/// Shows `bytes` within a quoted DOT string, `""` for none. Printable ASCII
+/// bytes are shown as they are, others as `\xNN`.
+pub(crate) fn escape(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "\\\"\\\"".to_string();
+    }
+    let mut out = String::new();
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            b' '..=b'~' => out.push(byte as char),
+            _ => write!(out, "\\\\x{:02x}", byte).unwrap(),
+        }
+    }
+    out
+}
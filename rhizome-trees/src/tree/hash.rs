@@ -0,0 +1,227 @@
+use std::num::NonZeroUsize;
+use std::sync::{Mutex, OnceLock};
+
+use lru::LruCache;
+use sha2::{Digest as _, Sha256};
+
+pub use sha2::digest::Update;
+
+/// The output of hashing a value or a tree node.
+pub type Digest = [u8; 32];
+
+/// A value which can be fed into a hasher using a canonical byte encoding.
+///
+/// Implementations must write an encoding which is unambiguous, i.e. two
+/// different values never produce the same sequence of updates.
+pub trait Hashable {
+    fn update_hash<H: Update>(&self, hasher: &mut H);
+}
+
+/// Implements [`Hashable`] for structs and enums, writing each field with
+/// [`update_length_prefixed`].
+#[cfg(feature = "derive")]
+pub use rhizome_trees_derive::Hashable;
+
+/// Writes the encoding of `value` prefixed with its length in bytes as a
+/// big-endian `u64`, so that it can be followed by other values without
+/// becoming ambiguous. Used for the fields of `#[derive(Hashable)]` types.
+pub fn update_length_prefixed<T: Hashable + ?Sized, H: Update>(hasher: &mut H, value: &T) {
+    let mut len = Length(0);
+    value.update_hash(&mut len);
+    hasher.update(&len.0.to_be_bytes());
+    value.update_hash(hasher);
+}
+
+struct Length(u64);
+
+impl Update for Length {
+    fn update(&mut self, data: &[u8]) {
+        self.0 += data.len() as u64;
+    }
+}
+
+/// The hash of an empty tree or subtree.
+pub const EMPTY_HASH: Digest = [0; 32];
+
+/// The layout a tree uses to hash its nodes. Every node records the version
+/// it was created with, so that its hash can be reproduced wherever it goes.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+#[repr(u8)]
+pub enum HashVersion {
+    /// The original layout, which hashes each node along with its entry
+    /// untagged. Only needed to reproduce the hashes of existing trees.
+    V0 = 0,
+    /// Entries and nodes are hashed with distinct tags, with length-prefixed
+    /// key and value hashes, so no preimage of one can be mistaken for the
+    /// other. The layout can be checked by ICS-23 verifiers.
+    #[default]
+    V1 = 1,
+}
+
+impl HashVersion {
+    pub fn from_u8(version: u8) -> Option<Self> {
+        match version {
+            0 => Some(HashVersion::V0),
+            1 => Some(HashVersion::V1),
+            _ => None,
+        }
+    }
+}
+
+/// Serializes as the version number.
+#[cfg(feature = "serde")]
+impl serde::Serialize for HashVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(*self as u8)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for HashVersion {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let version = u8::deserialize(deserializer)?;
+        HashVersion::from_u8(version).ok_or_else(|| {
+            serde::de::Error::custom(format_args!("unknown hash version {}", version))
+        })
+    }
+}
+
+/// A tree which commits to its entire contents with a single root hash.
+pub trait MerkleTree {
+    /// Returns the root hash, which is [`EMPTY_HASH`] for an empty tree.
+    fn merkle_hash(&self) -> crate::Result<Digest>;
+}
+
+/// Progress of a long running root hash computation.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct HashProgress {
+    /// The number of nodes hashed so far.
+    pub hashed: u64,
+    /// The estimated number of nodes in the tree. Subtrees whose hash was
+    /// already cached are skipped, so `hashed` may never reach it.
+    pub estimated_total: u64,
+}
+
+/// A lazily computed node hash. Cloning yields an empty cache, since nodes of
+/// persistent trees are only ever cloned in order to modify the copy.
+#[derive(Debug, Default)]
+pub(crate) struct HashCache(OnceLock<Digest>);
+
+impl HashCache {
+    pub(crate) fn get_or_init(&self, f: impl FnOnce() -> Digest) -> Digest {
+        *self.0.get_or_init(f)
+    }
+}
+
+impl Clone for HashCache {
+    fn clone(&self) -> Self {
+        HashCache::default()
+    }
+}
+
+/// Hashes `value` with SHA-256.
+pub fn hash_of<T: Hashable + ?Sized>(value: &T) -> Digest {
+    let mut hasher = Sha256::new();
+    value.update_hash(&mut hasher);
+    hasher.finalize().into()
+}
+
+/// Remembers the hashes of recently hashed large values, so that many
+/// entries sharing one large value hash it once. Values are looked up by
+/// their encoding, which is compared in full, so equal values share a hash
+/// wherever they come from and distinct ones never do.
+#[derive(Debug)]
+pub struct ValueHashMemo {
+    min_len: usize,
+    hashes: Mutex<LruCache<Vec<u8>, Digest>>,
+}
+
+impl ValueHashMemo {
+    /// Remembers the hashes of up to `capacity` distinct values whose
+    /// encoding is at least `min_len` bytes long. Shorter values are cheaper
+    /// to hash than to look up.
+    pub fn new(capacity: NonZeroUsize, min_len: usize) -> Self {
+        ValueHashMemo {
+            min_len,
+            hashes: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Returns [`hash_of`] `value`, computing it only if it isn't
+    /// remembered.
+    pub fn hash_of<T: Hashable + ?Sized>(&self, value: &T) -> Digest {
+        let encoding = encoding(value);
+        if encoding.len() < self.min_len {
+            return hash_parts(&[&encoding]);
+        }
+        // A poisoned lock only means another thread panicked mid-update of
+        // the cache, which is safe to skip.
+        let Ok(mut hashes) = self.hashes.lock() else {
+            return hash_parts(&[&encoding]);
+        };
+        if let Some(hash) = hashes.get(&encoding) {
+            return *hash;
+        }
+        let hash = hash_parts(&[&encoding]);
+        hashes.put(encoding, hash);
+        hash
+    }
+}
+
+/// Returns the canonical encoding `value` feeds into a hasher.
+pub fn encoding<T: Hashable + ?Sized>(value: &T) -> Vec<u8> {
+    let mut encoding = Encoding(Vec::new());
+    value.update_hash(&mut encoding);
+    encoding.0
+}
+
+/// Whether the canonical encoding of `value` is empty, without collecting
+/// it.
+pub fn is_empty_encoding<T: Hashable + ?Sized>(value: &T) -> bool {
+    let mut written = Written(false);
+    value.update_hash(&mut written);
+    !written.0
+}
+
+struct Written(bool);
+
+impl Update for Written {
+    fn update(&mut self, data: &[u8]) {
+        self.0 |= !data.is_empty();
+    }
+}
+
+struct Encoding(Vec<u8>);
+
+impl Update for Encoding {
+    fn update(&mut self, data: &[u8]) {
+        self.0.extend_from_slice(data);
+    }
+}
+
+/// Hashes the concatenation of `parts` with SHA-256.
+pub fn hash_parts(parts: &[&[u8]]) -> Digest {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        sha2::Digest::update(&mut hasher, part);
+    }
+    hasher.finalize().into()
+}
+
+impl Hashable for [u8] {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(self);
+    }
+}
+
+impl Hashable for Vec<u8> {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(self);
+    }
+}
+
+/// The unit value hashes as no bytes at all, so a tree of `()` values
+/// commits to its keys only.
+impl Hashable for () {
+    fn update_hash<H: Update>(&self, _hasher: &mut H) {}
+}
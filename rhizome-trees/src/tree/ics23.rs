@@ -0,0 +1,544 @@
+//! AVL proofs in the [ICS-23] wire format, so IBC verifiers can check them.
+//!
+//! [`Proof::encode`] writes a proof as an ics23 `CommitmentProof` holding an
+//! `ExistenceProof`, which verifies against [`proof_spec`]. The spec
+//! describes the [`HashVersion::V1`] layout: an entry is the leaf
+//! `SHA-256(0x00 || 0x20 || H(key) || 0x20 || H(value))` and a node the
+//! inner op `SHA-256(0x01 || left || leaf || right)`, i.e. a node with three
+//! 32 byte children, the entry in the middle. Keys and values appear in
+//! proofs as their [`Hashable`] encoding. Trees hashed with
+//! [`HashVersion::V0`] can't be expressed in ICS-23.
+//!
+//! [ICS-23]: https://github.com/cosmos/ics23
+
+use crate::tree::avl::node::EntryHash;
+use crate::tree::avl::proof::{Proof, ProofStep, Side};
+use crate::tree::hash::{encoding, Digest, HashVersion, Hashable, EMPTY_HASH};
+use crate::{Error, Result};
+
+/// The ics23 `HashOp` enum.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HashOp {
+    NoHash = 0,
+    Sha256 = 1,
+    Sha512 = 2,
+    Keccak256 = 3,
+    Ripemd160 = 4,
+    Bitcoin = 5,
+    Sha512_256 = 6,
+    Blake2b512 = 7,
+    Blake2s256 = 8,
+    Blake3 = 9,
+}
+
+/// The ics23 `LengthOp` enum.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LengthOp {
+    NoPrefix = 0,
+    VarProto = 1,
+    VarRlp = 2,
+    Fixed32Big = 3,
+    Fixed32Little = 4,
+    Fixed64Big = 5,
+    Fixed64Little = 6,
+    Require32Bytes = 7,
+    Require64Bytes = 8,
+}
+
+/// The ics23 `LeafOp` message: how an entry is hashed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LeafOp {
+    pub hash: HashOp,
+    pub prehash_key: HashOp,
+    pub prehash_value: HashOp,
+    pub length: LengthOp,
+    pub prefix: Vec<u8>,
+}
+
+/// The ics23 `InnerOp` message: hashes a child between a prefix and a
+/// suffix.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InnerOp {
+    pub hash: HashOp,
+    pub prefix: Vec<u8>,
+    pub suffix: Vec<u8>,
+}
+
+/// The ics23 `InnerSpec` message: the shape of inner nodes.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InnerSpec {
+    pub child_order: Vec<i32>,
+    pub child_size: i32,
+    pub min_prefix_length: i32,
+    pub max_prefix_length: i32,
+    pub empty_child: Vec<u8>,
+    pub hash: HashOp,
+}
+
+/// The ics23 `ProofSpec` message, which verifiers check proofs against.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ProofSpec {
+    pub leaf_spec: LeafOp,
+    pub inner_spec: InnerSpec,
+    /// The maximum number of inner ops, or 0 for no limit.
+    pub max_depth: i32,
+    pub min_depth: i32,
+    pub prehash_key_before_comparison: bool,
+}
+
+/// A child of an inner node, see [`SpecDescriptor::child_order`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Child {
+    /// The hash of the left subtree.
+    Left,
+    /// The leaf hash of the node's own entry.
+    Entry,
+    /// The hash of the right subtree.
+    Right,
+}
+
+/// The hashing scheme of a [`ProofSpec`] spelled out for verifiers outside
+/// of ICS-23, which can configure themselves from its serialized form.
+///
+/// A leaf is `hash(leaf_prefix || length(prehash_key(key)) ||
+/// prehash_key(key) || length(prehash_value(value)) ||
+/// prehash_value(value))`, where keys and values are their [`Hashable`]
+/// encodings, and an inner node is `hash(inner_prefix || children)` with
+/// the children in `child_order`, each `child_size` bytes long and
+/// `empty_child` if absent.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpecDescriptor {
+    pub hash_version: HashVersion,
+    /// The hash of leaves and inner nodes.
+    pub hash: HashOp,
+    pub prehash_key: HashOp,
+    pub prehash_value: HashOp,
+    /// The length prefix of the prehashed key and value in a leaf.
+    pub length: LengthOp,
+    pub leaf_prefix: Vec<u8>,
+    pub inner_prefix: Vec<u8>,
+    pub child_order: Vec<Child>,
+    pub child_size: u32,
+    pub empty_child: Vec<u8>,
+}
+
+const LEAF_PREFIX: u8 = 0;
+const INNER_PREFIX: u8 = 1;
+
+fn leaf_op() -> LeafOp {
+    LeafOp {
+        hash: HashOp::Sha256,
+        prehash_key: HashOp::Sha256,
+        prehash_value: HashOp::Sha256,
+        length: LengthOp::VarProto,
+        prefix: vec![LEAF_PREFIX],
+    }
+}
+
+/// Returns the spec of AVL trees hashed with [`HashVersion::V1`].
+pub fn proof_spec() -> ProofSpec {
+    ProofSpec {
+        leaf_spec: leaf_op(),
+        inner_spec: InnerSpec {
+            child_order: vec![0, 1, 2],
+            child_size: 32,
+            min_prefix_length: 1,
+            max_prefix_length: 1,
+            empty_child: EMPTY_HASH.to_vec(),
+            hash: HashOp::Sha256,
+        },
+        max_depth: 0,
+        min_depth: 0,
+        prehash_key_before_comparison: false,
+    }
+}
+
+impl ProofSpec {
+    /// Describes the hashing scheme of the spec, which must be the
+    /// [`proof_spec`] of [`HashVersion::V1`] trees.
+    pub fn describe(&self) -> Result<SpecDescriptor> {
+        if *self != proof_spec() {
+            return Err(Error::invalid(
+                "only the spec of hash version V1 trees can be described",
+            ));
+        }
+        let children = [Child::Left, Child::Entry, Child::Right];
+        Ok(SpecDescriptor {
+            hash_version: HashVersion::V1,
+            hash: self.inner_spec.hash,
+            prehash_key: self.leaf_spec.prehash_key,
+            prehash_value: self.leaf_spec.prehash_value,
+            length: self.leaf_spec.length,
+            leaf_prefix: self.leaf_spec.prefix.clone(),
+            inner_prefix: vec![INNER_PREFIX],
+            child_order: self
+                .inner_spec
+                .child_order
+                .iter()
+                .map(|&child| children[child as usize])
+                .collect(),
+            child_size: self.inner_spec.child_size as u32,
+            empty_child: self.inner_spec.empty_child.clone(),
+        })
+    }
+
+    /// Encodes the spec as an ics23 `ProofSpec` message.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut inner = Vec::new();
+        put_packed(&mut inner, 1, &self.inner_spec.child_order);
+        put_int(&mut inner, 2, self.inner_spec.child_size.into());
+        put_int(&mut inner, 3, self.inner_spec.min_prefix_length.into());
+        put_int(&mut inner, 4, self.inner_spec.max_prefix_length.into());
+        put_bytes(&mut inner, 5, &self.inner_spec.empty_child);
+        put_int(&mut inner, 6, self.inner_spec.hash as i64);
+        let mut spec = Vec::new();
+        put_message(&mut spec, 1, &self.leaf_spec.encode());
+        put_message(&mut spec, 2, &inner);
+        put_int(&mut spec, 3, self.max_depth.into());
+        put_int(&mut spec, 4, self.min_depth.into());
+        put_int(&mut spec, 5, self.prehash_key_before_comparison.into());
+        spec
+    }
+}
+
+impl LeafOp {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_int(&mut buf, 1, self.hash as i64);
+        put_int(&mut buf, 2, self.prehash_key as i64);
+        put_int(&mut buf, 3, self.prehash_value as i64);
+        put_int(&mut buf, 4, self.length as i64);
+        put_bytes(&mut buf, 5, &self.prefix);
+        buf
+    }
+}
+
+impl InnerOp {
+    fn new(prefix: &[&[u8]], suffix: &[&[u8]]) -> Self {
+        InnerOp {
+            hash: HashOp::Sha256,
+            prefix: prefix.concat(),
+            suffix: suffix.concat(),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_int(&mut buf, 1, self.hash as i64);
+        put_bytes(&mut buf, 2, &self.prefix);
+        put_bytes(&mut buf, 3, &self.suffix);
+        buf
+    }
+}
+
+impl Proof {
+    /// Returns the inner ops leading from the proven entry's leaf to the
+    /// root, nearest first.
+    pub fn inner_ops(&self) -> Result<Vec<InnerOp>> {
+        if self.hash_version != HashVersion::V1 {
+            return Err(Error::invalid(format!(
+                "proofs of hash version {:?} have no ICS-23 form",
+                self.hash_version
+            )));
+        }
+        let tag: &[u8] = &[INNER_PREFIX];
+        let mut ops = vec![InnerOp::new(&[tag, &self.left], &[&self.right])];
+        for step in &self.path {
+            let EntryHash::Leaf(leaf) = &step.entry else {
+                return Err(Error::proof_invalid("proof step doesn't hold a leaf hash"));
+            };
+            ops.push(match step.side {
+                Side::Left => InnerOp::new(&[tag], &[leaf, &step.sibling]),
+                Side::Right => InnerOp::new(&[tag, &step.sibling, leaf], &[]),
+            });
+        }
+        Ok(ops)
+    }
+
+    /// Encodes the proof that `key` maps to `value` as an ics23
+    /// `CommitmentProof`. Fails for proofs of [`HashVersion::V0`] trees.
+    pub fn encode<K: Hashable + ?Sized, V: Hashable + ?Sized>(
+        &self,
+        key: &K,
+        value: &V,
+    ) -> Result<Vec<u8>> {
+        let mut exist = Vec::new();
+        put_bytes(&mut exist, 1, &encoding(key));
+        put_bytes(&mut exist, 2, &encoding(value));
+        put_message(&mut exist, 3, &leaf_op().encode());
+        for op in self.inner_ops()? {
+            put_message(&mut exist, 4, &op.encode());
+        }
+        let mut proof = Vec::new();
+        put_message(&mut proof, 1, &exist);
+        Ok(proof)
+    }
+
+    /// Decodes an ics23 `CommitmentProof` holding an existence proof in the
+    /// layout of [`proof_spec`]. Returns the proof along with the encoded
+    /// key and value it proves.
+    pub fn decode(bytes: &[u8]) -> Result<(Proof, Vec<u8>, Vec<u8>)> {
+        let mut exist = None;
+        for field in Fields(bytes) {
+            match field? {
+                (1, Value::Bytes(bytes)) => exist = Some(bytes),
+                (2..=4, _) => {
+                    return Err(Error::proof_invalid("only existence proofs are supported"))
+                }
+                _ => {}
+            }
+        }
+        let exist = exist.ok_or_else(|| Error::proof_invalid("commitment proof holds no proof"))?;
+        let (mut key, mut value, mut leaf, mut ops) = (Vec::new(), Vec::new(), None, Vec::new());
+        for field in Fields(exist) {
+            match field? {
+                (1, Value::Bytes(bytes)) => key = bytes.to_vec(),
+                (2, Value::Bytes(bytes)) => value = bytes.to_vec(),
+                (3, Value::Bytes(bytes)) => leaf = Some(decode_leaf_op(bytes)?),
+                (4, Value::Bytes(bytes)) => ops.push(decode_inner_op(bytes)?),
+                _ => {}
+            }
+        }
+        if leaf != Some(leaf_op()) {
+            return Err(Error::proof_invalid(
+                "existence proof doesn't hash its leaf as the spec does",
+            ));
+        }
+        let mut ops = ops.into_iter();
+        let first = ops
+            .next()
+            .ok_or_else(|| Error::proof_invalid("existence proof has no inner ops"))?;
+        let (left, right) = match (&first.prefix[..], &first.suffix[..]) {
+            ([INNER_PREFIX, left @ ..], right) if left.len() == 32 && right.len() == 32 => {
+                (digest(left), digest(right))
+            }
+            _ => {
+                return Err(Error::proof_invalid(
+                    "first inner op doesn't surround an entry",
+                ))
+            }
+        };
+        let path = ops
+            .map(|op| {
+                Ok(match (&op.prefix[..], &op.suffix[..]) {
+                    ([INNER_PREFIX], suffix) if suffix.len() == 64 => ProofStep {
+                        side: Side::Left,
+                        entry: EntryHash::Leaf(digest(&suffix[..32])),
+                        sibling: digest(&suffix[32..]),
+                    },
+                    ([INNER_PREFIX, prefix @ ..], []) if prefix.len() == 64 => ProofStep {
+                        side: Side::Right,
+                        entry: EntryHash::Leaf(digest(&prefix[32..])),
+                        sibling: digest(&prefix[..32]),
+                    },
+                    _ => return Err(Error::proof_invalid("inner op doesn't match the spec")),
+                })
+            })
+            .collect::<Result<_>>()?;
+        let proof = Proof {
+            hash_version: HashVersion::V1,
+            left,
+            right,
+            path,
+        };
+        Ok((proof, key, value))
+    }
+}
+
+fn digest(bytes: &[u8]) -> Digest {
+    bytes.try_into().expect("32 bytes")
+}
+
+fn hash_op(value: u64) -> Result<HashOp> {
+    Ok(match value {
+        0 => HashOp::NoHash,
+        1 => HashOp::Sha256,
+        2 => HashOp::Sha512,
+        3 => HashOp::Keccak256,
+        4 => HashOp::Ripemd160,
+        5 => HashOp::Bitcoin,
+        6 => HashOp::Sha512_256,
+        7 => HashOp::Blake2b512,
+        8 => HashOp::Blake2s256,
+        9 => HashOp::Blake3,
+        _ => return Err(Error::codec(format!("unknown ics23 hash op {}", value))),
+    })
+}
+
+fn length_op(value: u64) -> Result<LengthOp> {
+    Ok(match value {
+        0 => LengthOp::NoPrefix,
+        1 => LengthOp::VarProto,
+        2 => LengthOp::VarRlp,
+        3 => LengthOp::Fixed32Big,
+        4 => LengthOp::Fixed32Little,
+        5 => LengthOp::Fixed64Big,
+        6 => LengthOp::Fixed64Little,
+        7 => LengthOp::Require32Bytes,
+        8 => LengthOp::Require64Bytes,
+        _ => return Err(Error::codec(format!("unknown ics23 length op {}", value))),
+    })
+}
+
+fn decode_leaf_op(bytes: &[u8]) -> Result<LeafOp> {
+    let mut op = LeafOp {
+        hash: HashOp::NoHash,
+        prehash_key: HashOp::NoHash,
+        prehash_value: HashOp::NoHash,
+        length: LengthOp::NoPrefix,
+        prefix: Vec::new(),
+    };
+    for field in Fields(bytes) {
+        match field? {
+            (1, Value::Varint(v)) => op.hash = hash_op(v)?,
+            (2, Value::Varint(v)) => op.prehash_key = hash_op(v)?,
+            (3, Value::Varint(v)) => op.prehash_value = hash_op(v)?,
+            (4, Value::Varint(v)) => op.length = length_op(v)?,
+            (5, Value::Bytes(prefix)) => op.prefix = prefix.to_vec(),
+            _ => {}
+        }
+    }
+    Ok(op)
+}
+
+fn decode_inner_op(bytes: &[u8]) -> Result<InnerOp> {
+    let mut op = InnerOp {
+        hash: HashOp::NoHash,
+        prefix: Vec::new(),
+        suffix: Vec::new(),
+    };
+    for field in Fields(bytes) {
+        match field? {
+            (1, Value::Varint(v)) => op.hash = hash_op(v)?,
+            (2, Value::Bytes(prefix)) => op.prefix = prefix.to_vec(),
+            (3, Value::Bytes(suffix)) => op.suffix = suffix.to_vec(),
+            _ => {}
+        }
+    }
+    if op.hash != HashOp::Sha256 {
+        return Err(Error::proof_invalid(format!(
+            "inner op uses {:?} rather than SHA-256",
+            op.hash
+        )));
+    }
+    Ok(op)
+}
+
+// A minimal protobuf codec: proto3 omits fields holding their default value
+// and readers skip fields they don't know.
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn put_int(buf: &mut Vec<u8>, field: u32, value: i64) {
+    if value != 0 {
+        put_varint(buf, u64::from(field) << 3);
+        put_varint(buf, value as u64);
+    }
+}
+
+fn put_bytes(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    if !bytes.is_empty() {
+        put_message(buf, field, bytes);
+    }
+}
+
+fn put_message(buf: &mut Vec<u8>, field: u32, message: &[u8]) {
+    put_varint(buf, u64::from(field) << 3 | 2);
+    put_varint(buf, message.len() as u64);
+    buf.extend_from_slice(message);
+}
+
+fn put_packed(buf: &mut Vec<u8>, field: u32, values: &[i32]) {
+    let mut packed = Vec::new();
+    for value in values {
+        put_varint(&mut packed, i64::from(*value) as u64);
+    }
+    put_bytes(buf, field, &packed);
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Iterates over the fields of a protobuf message.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self
+                .0
+                .split_first()
+                .ok_or_else(|| Error::codec("truncated protobuf varint"))?;
+            self.0 = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err(Error::codec("protobuf varint too long"))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.0.len() {
+            return Err(Error::codec("truncated protobuf field"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u64, Value<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let mut field = || {
+            let key = self.varint()?;
+            let value = match key & 7 {
+                0 => Value::Varint(self.varint()?),
+                1 => {
+                    self.take(8)?;
+                    Value::Fixed
+                }
+                2 => {
+                    let len = usize::try_from(self.varint()?)
+                        .map_err(|_| Error::codec("protobuf field length exceeds usize"))?;
+                    Value::Bytes(self.take(len)?)
+                }
+                5 => {
+                    self.take(4)?;
+                    Value::Fixed
+                }
+                wire => {
+                    return Err(Error::codec(format!(
+                        "unsupported protobuf wire type {}",
+                        wire
+                    )))
+                }
+            };
+            Ok((key >> 3, value))
+        };
+        let field = field();
+        if field.is_err() {
+            self.0 = &[];
+        }
+        Some(field)
+    }
+}
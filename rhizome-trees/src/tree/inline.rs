@@ -0,0 +1,124 @@
+//! Byte strings stored inline in tree nodes.
+//!
+//! Most keys and many values are only a few bytes long, yet a `Vec<u8>`
+//! stores them behind a pointer, so every comparison on a lookup path loads
+//! another cache line. [`InlineBytes`] keeps short contents in the node
+//! itself and is a drop-in replacement for `Vec<u8>` keys and values.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+
+use arrayvec::ArrayVec;
+
+use crate::tree::hash::{Hashable, Update};
+
+/// A byte string which holds up to `N` bytes inline and longer ones on the
+/// heap. Tree nodes keyed by small byte strings then carry the bytes in the
+/// node itself rather than behind another pointer.
+///
+/// Compares, hashes and borrows like the `[u8]` it holds, so it can stand in
+/// for `Vec<u8>` without changing tree hashes or lookups.
+#[derive(Clone)]
+pub enum InlineBytes<const N: usize> {
+    Inline(ArrayVec<u8, N>),
+    Heap(Box<[u8]>),
+}
+
+/// Inline bytes sized for keys.
+pub type InlineKey = InlineBytes<16>;
+
+/// Inline bytes sized for values.
+pub type InlineValue = InlineBytes<32>;
+
+impl<const N: usize> InlineBytes<N> {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            InlineBytes::Inline(bytes) => bytes,
+            InlineBytes::Heap(bytes) => bytes,
+        }
+    }
+
+    pub fn is_inline(&self) -> bool {
+        matches!(self, InlineBytes::Inline(_))
+    }
+}
+
+impl<const N: usize> Default for InlineBytes<N> {
+    fn default() -> Self {
+        InlineBytes::Inline(ArrayVec::new())
+    }
+}
+
+impl<const N: usize> From<&[u8]> for InlineBytes<N> {
+    fn from(value: &[u8]) -> Self {
+        match ArrayVec::try_from(value) {
+            Ok(bytes) => InlineBytes::Inline(bytes),
+            Err(_) => InlineBytes::Heap(value.into()),
+        }
+    }
+}
+
+impl<const N: usize> From<Vec<u8>> for InlineBytes<N> {
+    fn from(value: Vec<u8>) -> Self {
+        if value.len() <= N {
+            InlineBytes::from(value.as_slice())
+        } else {
+            InlineBytes::Heap(value.into_boxed_slice())
+        }
+    }
+}
+
+impl<const N: usize> Deref for InlineBytes<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl<const N: usize> Borrow<[u8]> for InlineBytes<N> {
+    fn borrow(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl<const N: usize> PartialEq for InlineBytes<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<const N: usize> Eq for InlineBytes<N> {}
+
+impl<const N: usize> PartialOrd for InlineBytes<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<const N: usize> Ord for InlineBytes<N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+impl<const N: usize> Hash for InlineBytes<N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state);
+    }
+}
+
+impl<const N: usize> fmt::Debug for InlineBytes<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_bytes().fmt(f)
+    }
+}
+
+impl<const N: usize> Hashable for InlineBytes<N> {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(self.as_bytes());
+    }
+}
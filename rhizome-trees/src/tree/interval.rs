@@ -0,0 +1,354 @@
+//! A persistent interval map.
+//!
+//! Entries are keyed by closed intervals and kept in an AVL tree ordered by
+//! start, then end. Each node is augmented with the largest end point in its
+//! subtree, so stabbing and overlap queries skip every subtree which ends
+//! before the query, taking O(log n + k) for k results.
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::tree::hash::{hash_of, hash_parts, Digest, HashCache, Hashable, MerkleTree, EMPTY_HASH};
+use crate::{Error, Result};
+
+/// A closed interval `[start, end]`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Interval<T> {
+    pub start: T,
+    pub end: T,
+}
+
+impl<T: Ord> Interval<T> {
+    pub fn new(start: T, end: T) -> Result<Self> {
+        if start > end {
+            return Err(Error::invalid("interval starts after it ends"));
+        }
+        Ok(Interval { start, end })
+    }
+
+    pub fn contains(&self, point: &T) -> bool {
+        &self.start <= point && point <= &self.end
+    }
+
+    pub fn overlaps(&self, other: &Interval<T>) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+}
+
+type Link<T, V> = Option<Arc<Node<T, V>>>;
+
+#[derive(Clone, Debug)]
+pub struct Node<T, V> {
+    interval: Interval<T>,
+    value: V,
+    /// The largest end point of any interval in this subtree.
+    max_end: T,
+    height: u8,
+    left: Link<T, V>,
+    right: Link<T, V>,
+    hash: HashCache,
+}
+
+fn height<T, V>(link: &Link<T, V>) -> u8 {
+    link.as_ref().map_or(0, |node| node.height)
+}
+
+impl<T, V> Node<T, V> {
+    pub fn interval(&self) -> &Interval<T> {
+        &self.interval
+    }
+
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    pub fn max_end(&self) -> &T {
+        &self.max_end
+    }
+
+    pub fn left(&self) -> Option<&Arc<Node<T, V>>> {
+        self.left.as_ref()
+    }
+
+    pub fn right(&self) -> Option<&Arc<Node<T, V>>> {
+        self.right.as_ref()
+    }
+}
+
+impl<T: Ord + Clone, V: Clone> Node<T, V> {
+    fn new(interval: Interval<T>, value: V, left: Link<T, V>, right: Link<T, V>) -> Arc<Self> {
+        let mut max_end = interval.end.clone();
+        for child in left.iter().chain(right.iter()) {
+            if child.max_end > max_end {
+                max_end = child.max_end.clone();
+            }
+        }
+        Arc::new(Node {
+            interval,
+            value,
+            max_end,
+            height: 1 + height(&left).max(height(&right)),
+            left,
+            right,
+            hash: HashCache::default(),
+        })
+    }
+
+    /// Builds a node from an entry and two subtrees whose heights differ by
+    /// at most two, rotating as needed to restore the AVL invariant.
+    fn balance(interval: Interval<T>, value: V, left: Link<T, V>, right: Link<T, V>) -> Arc<Self> {
+        let (lh, rh) = (height(&left), height(&right));
+        if lh > rh + 1 {
+            let l = left.expect("left subtree is taller than right");
+            if height(&l.left) >= height(&l.right) {
+                let right = Node::new(interval, value, l.right.clone(), right);
+                Node::new(
+                    l.interval.clone(),
+                    l.value.clone(),
+                    l.left.clone(),
+                    Some(right),
+                )
+            } else {
+                let lr = l.right.as_ref().expect("left-right subtree is taller");
+                let left = Node::new(
+                    l.interval.clone(),
+                    l.value.clone(),
+                    l.left.clone(),
+                    lr.left.clone(),
+                );
+                let right = Node::new(interval, value, lr.right.clone(), right);
+                Node::new(
+                    lr.interval.clone(),
+                    lr.value.clone(),
+                    Some(left),
+                    Some(right),
+                )
+            }
+        } else if rh > lh + 1 {
+            let r = right.expect("right subtree is taller than left");
+            if height(&r.right) >= height(&r.left) {
+                let left = Node::new(interval, value, left, r.left.clone());
+                Node::new(
+                    r.interval.clone(),
+                    r.value.clone(),
+                    Some(left),
+                    r.right.clone(),
+                )
+            } else {
+                let rl = r.left.as_ref().expect("right-left subtree is taller");
+                let left = Node::new(interval, value, left, rl.left.clone());
+                let right = Node::new(
+                    r.interval.clone(),
+                    r.value.clone(),
+                    rl.right.clone(),
+                    r.right.clone(),
+                );
+                Node::new(
+                    rl.interval.clone(),
+                    rl.value.clone(),
+                    Some(left),
+                    Some(right),
+                )
+            }
+        } else {
+            Node::new(interval, value, left, right)
+        }
+    }
+
+    fn insert(link: &Link<T, V>, interval: Interval<T>, value: V) -> Arc<Self> {
+        let Some(node) = link else {
+            return Node::new(interval, value, None, None);
+        };
+        match interval.cmp(&node.interval) {
+            Ordering::Less => Node::balance(
+                node.interval.clone(),
+                node.value.clone(),
+                Some(Node::insert(&node.left, interval, value)),
+                node.right.clone(),
+            ),
+            Ordering::Greater => Node::balance(
+                node.interval.clone(),
+                node.value.clone(),
+                node.left.clone(),
+                Some(Node::insert(&node.right, interval, value)),
+            ),
+            Ordering::Equal => Node::new(interval, value, node.left.clone(), node.right.clone()),
+        }
+    }
+
+    /// Returns the subtree with `interval` removed, or `None` if it was not
+    /// present.
+    fn remove(link: &Link<T, V>, interval: &Interval<T>) -> Option<Link<T, V>> {
+        let node = link.as_ref()?;
+        Some(match interval.cmp(&node.interval) {
+            Ordering::Less => Some(Node::balance(
+                node.interval.clone(),
+                node.value.clone(),
+                Node::remove(&node.left, interval)?,
+                node.right.clone(),
+            )),
+            Ordering::Greater => Some(Node::balance(
+                node.interval.clone(),
+                node.value.clone(),
+                node.left.clone(),
+                Node::remove(&node.right, interval)?,
+            )),
+            Ordering::Equal => match (&node.left, &node.right) {
+                (None, right) => right.clone(),
+                (left, None) => left.clone(),
+                (left, Some(right)) => {
+                    let (interval, value, right) = Node::pop_min(right);
+                    Some(Node::balance(interval, value, left.clone(), right))
+                }
+            },
+        })
+    }
+
+    fn pop_min(node: &Arc<Self>) -> (Interval<T>, V, Link<T, V>) {
+        match &node.left {
+            None => (
+                node.interval.clone(),
+                node.value.clone(),
+                node.right.clone(),
+            ),
+            Some(left) => {
+                let (interval, value, left) = Node::pop_min(left);
+                let rest = Node::balance(
+                    node.interval.clone(),
+                    node.value.clone(),
+                    left,
+                    node.right.clone(),
+                );
+                (interval, value, Some(rest))
+            }
+        }
+    }
+
+    /// Collects the entries whose interval overlaps `query`, in order.
+    fn overlapping<'a>(
+        link: &'a Link<T, V>,
+        query: &Interval<T>,
+        out: &mut Vec<(&'a Interval<T>, &'a V)>,
+    ) {
+        let Some(node) = link else {
+            return;
+        };
+        if node.max_end < query.start {
+            return;
+        }
+        Node::overlapping(&node.left, query, out);
+        // Everything to the right starts after this node does.
+        if node.interval.start <= query.end {
+            if node.interval.end >= query.start {
+                out.push((&node.interval, &node.value));
+            }
+            Node::overlapping(&node.right, query, out);
+        }
+    }
+}
+
+impl<T: Hashable, V: Hashable> Node<T, V> {
+    /// Returns the merkle hash of the subtree rooted at this node:
+    /// `SHA-256(left || H(start) || H(end) || H(value) || right)` where
+    /// missing children are represented by [`EMPTY_HASH`]. The augmented
+    /// end point is derived from the entries, so it isn't hashed.
+    pub fn hash(&self) -> Digest {
+        self.hash.get_or_init(|| {
+            let link_hash =
+                |link: &Link<T, V>| link.as_ref().map_or(EMPTY_HASH, |node| node.hash());
+            hash_parts(&[
+                &link_hash(&self.left),
+                &hash_of(&self.interval.start),
+                &hash_of(&self.interval.end),
+                &hash_of(&self.value),
+                &link_hash(&self.right),
+            ])
+        })
+    }
+}
+
+/// A persistent map from closed intervals to values. Cloning is O(1) and
+/// modifications share all untouched nodes.
+#[derive(Debug)]
+pub struct IntervalTree<T, V> {
+    root: Link<T, V>,
+}
+
+impl<T, V> Clone for IntervalTree<T, V> {
+    fn clone(&self) -> Self {
+        IntervalTree {
+            root: self.root.clone(),
+        }
+    }
+}
+
+impl<T, V> Default for IntervalTree<T, V> {
+    fn default() -> Self {
+        IntervalTree { root: None }
+    }
+}
+
+impl<T, V> IntervalTree<T, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn root(&self) -> Option<&Arc<Node<T, V>>> {
+        self.root.as_ref()
+    }
+}
+
+impl<T: Ord + Clone, V: Clone> IntervalTree<T, V> {
+    pub fn get(&self, interval: &Interval<T>) -> Option<&V> {
+        let mut link = &self.root;
+        while let Some(node) = link {
+            link = match interval.cmp(&node.interval) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => &node.right,
+                Ordering::Equal => return Some(&node.value),
+            };
+        }
+        None
+    }
+
+    pub fn insert(&self, interval: Interval<T>, value: V) -> Self {
+        IntervalTree {
+            root: Some(Node::insert(&self.root, interval, value)),
+        }
+    }
+
+    pub fn remove(&self, interval: &Interval<T>) -> Self {
+        match Node::remove(&self.root, interval) {
+            Some(root) => IntervalTree { root },
+            None => self.clone(),
+        }
+    }
+
+    /// Returns the entries whose interval contains `point`, ordered by
+    /// interval.
+    pub fn intervals_containing(&self, point: &T) -> Vec<(&Interval<T>, &V)> {
+        let query = Interval {
+            start: point.clone(),
+            end: point.clone(),
+        };
+        self.overlapping(&query)
+    }
+
+    /// Returns the entries whose interval overlaps `query`, ordered by
+    /// interval.
+    pub fn overlapping(&self, query: &Interval<T>) -> Vec<(&Interval<T>, &V)> {
+        let mut out = Vec::new();
+        Node::overlapping(&self.root, query, &mut out);
+        out
+    }
+}
+
+impl<T: Hashable, V: Hashable> MerkleTree for IntervalTree<T, V> {
+    fn merkle_hash(&self) -> Result<Digest> {
+        Ok(self.root.as_ref().map_or(EMPTY_HASH, |root| root.hash()))
+    }
+}
@@ -0,0 +1,47 @@
+//! Operations shared by the map implementations, so code can be generic
+//! over them.
+//!
+//! Both traits are object-safe, so application code which chooses its
+//! backend at runtime can hold a `Box<dyn PersistentMap<Key = K, Value = V>>`
+//! instead of being generic over the tree type.
+
+use crate::tree::hash::Digest;
+use crate::Result;
+
+/// A map from keys to values, implemented by the
+/// [AVL](crate::tree::avl::Tree) and [ART](crate::tree::art::Tree) trees.
+///
+/// Reads return `Result`s since a tree may have to load nodes from its
+/// store, and values are returned by clone since a loaded node isn't
+/// necessarily kept alive by the tree.
+pub trait Map {
+    type Key: ?Sized;
+    type Value;
+
+    fn get(&self, key: &Self::Key) -> Result<Option<Self::Value>>;
+
+    fn contains_key(&self, key: &Self::Key) -> Result<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Returns the number of entries.
+    fn len(&self) -> Result<u64>;
+
+    fn is_empty(&self) -> bool;
+}
+
+/// A [`Map`] which is modified in place and whose changes are persisted by
+/// [`PersistentMap::commit`], implemented by the AVL and ART trees and by
+/// [`VersionedTree`](crate::tree::avl::versioned::VersionedTree).
+///
+/// The trees themselves return a new tree from every write; their
+/// implementations replace the tree with the new one.
+pub trait PersistentMap: Map {
+    fn insert(&mut self, key: &Self::Key, value: Self::Value) -> Result<()>;
+
+    fn delete(&mut self, key: &Self::Key) -> Result<()>;
+
+    /// Persists the changes made since the last commit and returns the new
+    /// root hash.
+    fn commit(&mut self) -> Result<Digest>;
+}
@@ -0,0 +1,16 @@
+pub mod art;
+pub mod avl;
+pub mod cardinality;
+#[cfg(feature = "cid")]
+pub mod cid;
+pub mod graphviz;
+pub mod hash;
+#[cfg(feature = "ics23")]
+pub mod ics23;
+#[cfg(feature = "inline")]
+pub mod inline;
+pub mod interval;
+pub mod map;
+pub mod node_manager;
+pub mod spatial;
+pub mod value;
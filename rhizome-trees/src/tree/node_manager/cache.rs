@@ -0,0 +1,203 @@
+//! Caches of stored nodes.
+//!
+//! Every [`NodeManager`](super::NodeManager) caches the nodes it reads in
+//! an LRU cache of its own by default, so a store holding many trees of
+//! different node types splits its memory into one budget per tree. A
+//! [`SharedCache`] is a single LRU cache which any number of managers can
+//! use instead, whatever their node types, so that recently used nodes of
+//! busy trees push out those of idle ones.
+//!
+//! A manager can also remember the keys recently found absent from saved
+//! versions, see
+//! [`NodeManagerBuilder::negative_lookup_cache`](super::NodeManagerBuilder::negative_lookup_cache).
+
+use std::any::Any;
+use std::fmt;
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use lru::LruCache;
+
+use super::Ptr;
+use crate::tree::hash::Digest;
+use crate::{Error, Result};
+
+type AnyNode = Arc<dyn Any + Send + Sync>;
+
+/// An LRU cache of nodes of any type, shared by the managers built with
+/// [`NodeManagerBuilder::shared_cache`](super::NodeManagerBuilder::shared_cache).
+/// Clones refer to the same cache.
+#[derive(Clone)]
+pub struct SharedCache {
+    entries: Arc<Mutex<LruCache<(u64, Ptr), AnyNode>>>,
+    /// The namespace of the next manager, which keeps apart equal pointers
+    /// into different stores.
+    next_namespace: Arc<AtomicU64>,
+}
+
+impl fmt::Debug for SharedCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedCache")
+            .field("capacity", &self.capacity())
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl SharedCache {
+    /// Creates a cache of up to `capacity` nodes in total.
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        SharedCache {
+            entries: Arc::new(Mutex::new(LruCache::new(capacity))),
+            next_namespace: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.lock().map_or(0, |entries| entries.cap().get())
+    }
+
+    /// Returns the number of cached nodes, of all managers.
+    pub fn len(&self) -> usize {
+        self.lock().map_or(0, |entries| entries.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, LruCache<(u64, Ptr), AnyNode>>> {
+        self.entries
+            .lock()
+            .map_err(|_| Error::poisoned("node cache"))
+    }
+}
+
+/// The cache of a manager.
+pub(crate) enum NodeCache<N> {
+    Own(Mutex<LruCache<Ptr, Arc<N>>>),
+    Shared {
+        cache: SharedCache,
+        namespace: u64,
+        /// Recovers the node type, which the manager's methods can't name
+        /// as [`Any`] since they don't require it to be `'static`.
+        downcast: fn(AnyNode) -> Option<Arc<N>>,
+        upcast: fn(Arc<N>) -> AnyNode,
+    },
+}
+
+impl<N> NodeCache<N> {
+    pub(crate) fn own(capacity: NonZeroUsize) -> Self {
+        NodeCache::Own(Mutex::new(LruCache::new(capacity)))
+    }
+
+    pub(crate) fn shared(cache: SharedCache) -> Self
+    where
+        N: Send + Sync + 'static,
+    {
+        NodeCache::Shared {
+            namespace: cache.next_namespace.fetch_add(1, Relaxed),
+            cache,
+            downcast: |node| node.downcast().ok(),
+            upcast: |node| node,
+        }
+    }
+
+    pub(crate) fn get(&self, ptr: &Ptr) -> Result<Option<Arc<N>>> {
+        match self {
+            NodeCache::Own(cache) => Ok(lock(cache)?.get(ptr).cloned()),
+            NodeCache::Shared {
+                cache,
+                namespace,
+                downcast,
+                ..
+            } => Ok(cache
+                .lock()?
+                .get(&(*namespace, *ptr))
+                .cloned()
+                .and_then(downcast)),
+        }
+    }
+
+    /// Like [`NodeCache::get`], without making the node the most recently
+    /// used.
+    pub(crate) fn peek(&self, ptr: &Ptr) -> Result<Option<Arc<N>>> {
+        match self {
+            NodeCache::Own(cache) => Ok(lock(cache)?.peek(ptr).cloned()),
+            NodeCache::Shared {
+                cache,
+                namespace,
+                downcast,
+                ..
+            } => Ok(cache
+                .lock()?
+                .peek(&(*namespace, *ptr))
+                .cloned()
+                .and_then(downcast)),
+        }
+    }
+
+    pub(crate) fn put(&self, ptr: Ptr, node: Arc<N>) -> Result<()> {
+        match self {
+            NodeCache::Own(cache) => {
+                lock(cache)?.put(ptr, node);
+            }
+            NodeCache::Shared {
+                cache,
+                namespace,
+                upcast,
+                ..
+            } => {
+                cache.lock()?.put((*namespace, ptr), upcast(node));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn pop(&self, ptr: &Ptr) -> Result<Option<Arc<N>>> {
+        match self {
+            NodeCache::Own(cache) => Ok(lock(cache)?.pop(ptr)),
+            NodeCache::Shared {
+                cache,
+                namespace,
+                downcast,
+                ..
+            } => Ok(cache.lock()?.pop(&(*namespace, *ptr)).and_then(downcast)),
+        }
+    }
+}
+
+/// The hashes of keys found absent from saved versions, by the root of the
+/// version. Saved versions don't change, so an entry stays true until the
+/// root is deleted; the manager clears them all whenever it writes.
+pub(crate) struct AbsentKeys(Mutex<LruCache<(Ptr, Digest), ()>>);
+
+impl AbsentKeys {
+    pub(crate) fn new(capacity: NonZeroUsize) -> Self {
+        AbsentKeys(Mutex::new(LruCache::new(capacity)))
+    }
+
+    pub(crate) fn contains(&self, root: &Ptr, key_hash: &Digest) -> Result<bool> {
+        Ok(lock(&self.0)?.get(&(*root, *key_hash)).is_some())
+    }
+
+    pub(crate) fn insert(&self, root: Ptr, key_hash: Digest) -> Result<()> {
+        lock(&self.0)?.put((root, key_hash), ());
+        Ok(())
+    }
+
+    pub(crate) fn clear(&self) -> Result<()> {
+        let mut keys = lock(&self.0)?;
+        if !keys.is_empty() {
+            keys.clear();
+        }
+        Ok(())
+    }
+}
+
+fn lock<K: Hash + Eq, V>(cache: &Mutex<LruCache<K, V>>) -> Result<MutexGuard<'_, LruCache<K, V>>> {
+    cache.lock().map_err(|_| Error::poisoned("node cache"))
+}
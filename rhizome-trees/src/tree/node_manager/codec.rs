@@ -0,0 +1,191 @@
+//! Turning nodes into bytes, for stores which keep bytes.
+//!
+//! Byte-oriented stores such as [`FileNodeStore`](super::FileNodeStore)
+//! implement [`NodeStore<Vec<u8>>`]. An [`EncodedStore`] adapts one of them
+//! to trees of any node type with a [`NodeCodec`], and since
+//! [`NodeStore`] is implemented for `Arc`s of stores, several encoded stores
+//! of different node types can share one byte store. With the `serde`
+//! feature, [`BincodeCodec`] encodes any node type which implements serde's
+//! traits, as the AVL tree nodes do when their keys and values do.
+//!
+//! A node is encoded only once its children are saved, so its children are
+//! written as their pointers.
+
+#[cfg(feature = "serde")]
+use bincode::Options;
+
+use super::store::{HealthReport, NodeMeta, NodeStore, Ptr, WriteBatch};
+use crate::{Error, Result};
+
+/// Encodes nodes of type `N` to bytes and decodes them back.
+pub trait NodeCodec<N>: Send + Sync {
+    /// Appends the encoding of `node` to `buf`.
+    fn encode(&self, node: &N, buf: &mut Vec<u8>) -> Result<()>;
+
+    /// Reads a node from the whole of `bytes`.
+    fn decode(&self, bytes: &[u8]) -> Result<N>;
+
+    fn to_bytes(&self, node: &N) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.encode(node, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Encodes nodes with [bincode](https://docs.rs/bincode/1) through their
+/// serde implementations, with fixed-width little-endian integers.
+#[cfg(feature = "serde")]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "serde")]
+impl BincodeCodec {
+    fn options() -> impl bincode::Options {
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .reject_trailing_bytes()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<N: serde::Serialize + serde::de::DeserializeOwned> NodeCodec<N> for BincodeCodec {
+    fn encode(&self, node: &N, buf: &mut Vec<u8>) -> Result<()> {
+        Ok(BincodeCodec::options().serialize_into(buf, node)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<N> {
+        Ok(BincodeCodec::options().deserialize(bytes)?)
+    }
+}
+
+/// A store of nodes of type `N` which keeps their encodings in a store of
+/// bytes, see the [module documentation](self).
+pub struct EncodedStore<S, C> {
+    inner: S,
+    codec: C,
+}
+
+impl<S, C> EncodedStore<S, C> {
+    pub fn new(inner: S, codec: C) -> Self {
+        EncodedStore { inner, codec }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+}
+
+impl<N, S: NodeStore<Vec<u8>>, C: NodeCodec<N>> NodeStore<N> for EncodedStore<S, C> {
+    /// Reads and decodes a node. A node which doesn't decode is reported as
+    /// [`Error::Corrupt`].
+    fn read(&self, ptr: &Ptr) -> Result<N> {
+        let bytes = self.inner.read(ptr)?;
+        self.codec.decode(&bytes).map_err(|err| Error::Corrupt {
+            ptr: *ptr,
+            source: Some(Box::new(err)),
+        })
+    }
+
+    /// Reads the encodings of the nodes in one call to the inner store.
+    fn read_many(&self, ptrs: &[Ptr]) -> Result<Vec<N>> {
+        let encoded = self.inner.read_many(ptrs)?;
+        ptrs.iter()
+            .zip(encoded)
+            .map(|(ptr, bytes)| {
+                self.codec.decode(&bytes).map_err(|err| Error::Corrupt {
+                    ptr: *ptr,
+                    source: Some(Box::new(err)),
+                })
+            })
+            .collect()
+    }
+
+    fn insert(&self, node: &N) -> Result<Ptr> {
+        self.inner.insert(&self.codec.to_bytes(node)?)
+    }
+
+    fn inc_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        self.inner.inc_ref_count(ptr)
+    }
+
+    fn dec_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        self.inner.dec_ref_count(ptr)
+    }
+
+    fn delete(&self, ptr: &Ptr) -> Result<()> {
+        self.inner.delete(ptr)
+    }
+
+    fn try_update(&self, nodes: &[(Ptr, N)]) -> Result<bool> {
+        let encoded = nodes
+            .iter()
+            .map(|(ptr, node)| Ok((*ptr, self.codec.to_bytes(node)?)))
+            .collect::<Result<Vec<_>>>()?;
+        self.inner.try_update(&encoded)
+    }
+
+    fn begin_batch<'a>(&'a self) -> Result<Box<dyn WriteBatch<N> + 'a>>
+    where
+        N: 'a,
+    {
+        Ok(Box::new(EncodedBatch {
+            inner: self.inner.begin_batch()?,
+            codec: &self.codec,
+        }))
+    }
+
+    fn header(&self) -> Result<Option<Vec<u8>>> {
+        self.inner.header()
+    }
+
+    fn set_header(&self, header: &[u8]) -> Result<()> {
+        self.inner.set_header(header)
+    }
+
+    fn health_check(&self, probe: &N) -> Result<HealthReport> {
+        self.inner.health_check(&self.codec.to_bytes(probe)?)
+    }
+
+    /// Returns the metadata of the inner store, whose sizes are those of
+    /// the encoded nodes.
+    fn meta(&self, ptr: &Ptr) -> Result<Option<NodeMeta>> {
+        self.inner.meta(ptr)
+    }
+
+    fn ref_count(&self, ptr: &Ptr) -> Result<Option<u64>> {
+        self.inner.ref_count(ptr)
+    }
+}
+
+/// Encodes the nodes staged in a batch of the inner store.
+struct EncodedBatch<'a, C> {
+    inner: Box<dyn WriteBatch<Vec<u8>> + 'a>,
+    codec: &'a C,
+}
+
+impl<N, C: NodeCodec<N>> WriteBatch<N> for EncodedBatch<'_, C> {
+    fn insert(&mut self, node: &N) -> Result<Ptr> {
+        let bytes = self.codec.to_bytes(node)?;
+        self.inner.insert(&bytes)
+    }
+
+    fn inc_ref_count(&mut self, ptr: &Ptr) -> Result<()> {
+        self.inner.inc_ref_count(ptr)
+    }
+
+    fn set_root(&mut self, root: Option<Ptr>) {
+        self.inner.set_root(root);
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.inner.set_version(version);
+    }
+
+    fn commit(self: Box<Self>) -> Result<()> {
+        self.inner.commit()
+    }
+}
@@ -0,0 +1,232 @@
+//! The settings a [`NodeManager`](super::NodeManager) is created with.
+//!
+//! A [`TreeConfig`] gathers every setting which affects how the nodes of a
+//! store are hashed, proven and cached. It is encoded into the header of the
+//! store by [`NodeManagerBuilder::open`](super::NodeManagerBuilder::open),
+//! so a store is always reopened with the settings its trees were written
+//! with. The encoding is
+//!
+//! ```text
+//! magic: "RZTC" | format: u8 | hash_version: u8 | proof_format: u8 |
+//! metrics: u8 | cache: u8 [capacity: u64 LE] |
+//! value_hashes: u8 [capacity: u64 LE | min_len: u64 LE]
+//! ```
+//!
+//! where `cache` and `value_hashes` are 0 when disabled and 1 followed by
+//! their parameters otherwise.
+
+use std::num::NonZeroUsize;
+
+use super::store::NodeStore;
+use super::CachePolicy;
+use crate::tree::hash::HashVersion;
+use crate::{Error, Result};
+
+const MAGIC: &[u8; 4] = b"RZTC";
+const FORMAT: u8 = 1;
+
+/// The proof encoding trees are expected to serve.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+#[repr(u8)]
+pub enum ProofFormat {
+    /// [`Proof`](crate::tree::avl::proof::Proof)s as they are.
+    #[default]
+    Native = 0,
+    /// ICS-23 `CommitmentProof`s, which require [`HashVersion::V1`].
+    Ics23 = 1,
+}
+
+impl ProofFormat {
+    pub fn from_u8(format: u8) -> Option<Self> {
+        match format {
+            0 => Some(ProofFormat::Native),
+            1 => Some(ProofFormat::Ics23),
+            _ => None,
+        }
+    }
+}
+
+/// The settings of a [`NodeManager`](super::NodeManager), set builder-style
+/// starting from the defaults.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct TreeConfig {
+    pub(super) hash_version: HashVersion,
+    pub(super) proof_format: ProofFormat,
+    pub(super) cache_policy: CachePolicy,
+    pub(super) metrics: bool,
+    pub(super) value_hashes: Option<(NonZeroUsize, usize)>,
+}
+
+impl TreeConfig {
+    /// Sets the layout nodes are hashed with. Defaults to the latest
+    /// [`HashVersion`].
+    pub fn hash_version(self, hash_version: HashVersion) -> Self {
+        TreeConfig {
+            hash_version,
+            ..self
+        }
+    }
+
+    /// Sets the proof encoding trees serve. Defaults to
+    /// [`ProofFormat::Native`].
+    pub fn proof_format(self, proof_format: ProofFormat) -> Self {
+        TreeConfig {
+            proof_format,
+            ..self
+        }
+    }
+
+    /// Sets how stored nodes are cached. Defaults to an LRU cache of 10,000
+    /// nodes.
+    pub fn cache_policy(self, cache_policy: CachePolicy) -> Self {
+        TreeConfig {
+            cache_policy,
+            ..self
+        }
+    }
+
+    /// Enables counting operations, see
+    /// [`NodeManager::stats`](super::NodeManager::stats).
+    pub fn metrics(self, metrics: bool) -> Self {
+        TreeConfig { metrics, ..self }
+    }
+
+    /// Remembers the hashes of up to `capacity` distinct values encoded in
+    /// at least `min_len` bytes, see
+    /// [`ValueHashMemo`](crate::tree::hash::ValueHashMemo).
+    pub fn memoize_value_hashes(self, capacity: NonZeroUsize, min_len: usize) -> Self {
+        TreeConfig {
+            value_hashes: Some((capacity, min_len)),
+            ..self
+        }
+    }
+
+    /// Checks that the settings can be used together.
+    pub fn validate(&self) -> Result<()> {
+        if self.proof_format == ProofFormat::Ics23 && self.hash_version != HashVersion::V1 {
+            return Err(Error::invalid(format!(
+                "ICS-23 proofs require hash version V1, not {:?}",
+                self.hash_version
+            )));
+        }
+        Ok(())
+    }
+
+    /// Encodes the settings as laid out in the [module
+    /// documentation](self).
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[
+            FORMAT,
+            self.hash_version as u8,
+            self.proof_format as u8,
+            self.metrics as u8,
+        ]);
+        match self.cache_policy {
+            CachePolicy::Disabled => bytes.push(0),
+            CachePolicy::Lru { capacity } => {
+                bytes.push(1);
+                bytes.extend_from_slice(&(capacity.get() as u64).to_le_bytes());
+            }
+        }
+        match self.value_hashes {
+            None => bytes.push(0),
+            Some((capacity, min_len)) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&(capacity.get() as u64).to_le_bytes());
+                bytes.extend_from_slice(&(min_len as u64).to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Decodes and validates settings written by [`TreeConfig::encode`].
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(Error::codec("not a tree config"));
+        }
+        let format = reader.u8()?;
+        if format != FORMAT {
+            return Err(Error::codec(format!(
+                "unknown tree config format {}",
+                format
+            )));
+        }
+        let hash_version = reader.u8()?;
+        let hash_version = HashVersion::from_u8(hash_version)
+            .ok_or_else(|| Error::codec(format!("unknown hash version {}", hash_version)))?;
+        let proof_format = reader.u8()?;
+        let proof_format = ProofFormat::from_u8(proof_format)
+            .ok_or_else(|| Error::codec(format!("unknown proof format {}", proof_format)))?;
+        let metrics = reader.flag()?;
+        let cache_policy = match reader.flag()? {
+            false => CachePolicy::Disabled,
+            true => CachePolicy::Lru {
+                capacity: reader.capacity()?,
+            },
+        };
+        let value_hashes = match reader.flag()? {
+            false => None,
+            true => Some((reader.capacity()?, reader.usize()?)),
+        };
+        if !reader.0.is_empty() {
+            return Err(Error::codec("trailing bytes after tree config"));
+        }
+        let config = TreeConfig {
+            hash_version,
+            proof_format,
+            cache_policy,
+            metrics,
+            value_hashes,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads the settings persisted in the header of `store`, if any.
+    pub fn load<N>(store: &dyn NodeStore<N>) -> Result<Option<Self>> {
+        store
+            .header()?
+            .map(|header| TreeConfig::decode(&header))
+            .transpose()
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(Error::codec("truncated tree config"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn flag(&mut self) -> Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            flag => Err(Error::codec(format!(
+                "invalid flag {} in tree config",
+                flag
+            ))),
+        }
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        let bytes = self.take(8)?.try_into().expect("took 8 bytes");
+        usize::try_from(u64::from_le_bytes(bytes))
+            .map_err(|_| Error::codec("tree config value exceeds usize"))
+    }
+
+    fn capacity(&mut self) -> Result<NonZeroUsize> {
+        NonZeroUsize::new(self.usize()?).ok_or_else(|| Error::codec("zero capacity in tree config"))
+    }
+}
@@ -0,0 +1,175 @@
+//! A store which addresses nodes by their hash.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
+use super::store::{decrement, not_found, poisoned, NodeStore, Ptr, WriteBatch};
+use super::TreeNode;
+use crate::tree::hash::{hash_of, Digest, Hashable};
+use crate::Result;
+
+/// A store whose pointers are the hashes of the nodes they point to.
+///
+/// Inserting a node equal to one already stored returns the existing pointer
+/// and takes another reference on it, so identical subtrees are stored once
+/// no matter how many tree versions contain them. The references the
+/// duplicate took on its children are released again.
+///
+/// Nodes are hashed through their [`Hashable`] implementation. For tree
+/// nodes, which hash their children's pointers, this makes each pointer the
+/// merkle hash of the subtree below it.
+pub struct ContentAddressedStore<N> {
+    nodes: RwLock<HashMap<Digest, (N, u64)>>,
+    header: RwLock<Option<Vec<u8>>>,
+}
+
+impl<N> Default for ContentAddressedStore<N> {
+    fn default() -> Self {
+        ContentAddressedStore {
+            nodes: RwLock::new(HashMap::new()),
+            header: RwLock::new(None),
+        }
+    }
+}
+
+impl<N> ContentAddressedStore<N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of distinct stored nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.read().map_or(0, |nodes| nodes.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn digest(ptr: &Ptr) -> Result<Digest> {
+    ptr.as_bytes().try_into().map_err(|_| not_found(ptr))
+}
+
+fn insert<N: Clone + TreeNode>(nodes: &mut HashMap<Digest, (N, u64)>, hash: Digest, node: &N) {
+    if let Some((_, count)) = nodes.get_mut(&hash) {
+        *count += 1;
+        // The stored copy already holds references on the children, so
+        // the ones taken for the duplicate are returned.
+        for child in node.stored_children() {
+            if let Some((_, count)) = nodes.get_mut(child.as_bytes()) {
+                *count = count.saturating_sub(1);
+            }
+        }
+    } else {
+        nodes.insert(hash, (node.clone(), 1));
+    }
+}
+
+impl<N: Clone + Hashable + TreeNode + Send + Sync> NodeStore<N> for ContentAddressedStore<N> {
+    fn read(&self, ptr: &Ptr) -> Result<N> {
+        let nodes = self.nodes.read().map_err(poisoned)?;
+        let (node, _) = nodes.get(&digest(ptr)?).ok_or_else(|| not_found(ptr))?;
+        Ok(node.clone())
+    }
+
+    fn read_many(&self, ptrs: &[Ptr]) -> Result<Vec<N>> {
+        let nodes = self.nodes.read().map_err(poisoned)?;
+        ptrs.iter()
+            .map(|ptr| {
+                let (node, _) = nodes.get(&digest(ptr)?).ok_or_else(|| not_found(ptr))?;
+                Ok(node.clone())
+            })
+            .collect()
+    }
+
+    fn insert(&self, node: &N) -> Result<Ptr> {
+        let hash = hash_of(node);
+        let mut nodes = self.nodes.write().map_err(poisoned)?;
+        insert(&mut nodes, hash, node);
+        Ptr::new(&hash)
+    }
+
+    fn inc_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        let mut nodes = self.nodes.write().map_err(poisoned)?;
+        let (_, count) = nodes.get_mut(&digest(ptr)?).ok_or_else(|| not_found(ptr))?;
+        *count += 1;
+        Ok(*count)
+    }
+
+    fn dec_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        let mut nodes = self.nodes.write().map_err(poisoned)?;
+        let (_, count) = nodes.get_mut(&digest(ptr)?).ok_or_else(|| not_found(ptr))?;
+        decrement(ptr, count)
+    }
+
+    fn delete(&self, ptr: &Ptr) -> Result<()> {
+        let mut nodes = self.nodes.write().map_err(poisoned)?;
+        nodes.remove(&digest(ptr)?).ok_or_else(|| not_found(ptr))?;
+        Ok(())
+    }
+
+    fn header(&self) -> Result<Option<Vec<u8>>> {
+        Ok(self.header.read().map_err(poisoned)?.clone())
+    }
+
+    fn set_header(&self, header: &[u8]) -> Result<()> {
+        *self.header.write().map_err(poisoned)? = Some(header.to_vec());
+        Ok(())
+    }
+
+    fn begin_batch<'a>(&'a self) -> Result<Box<dyn WriteBatch<N> + 'a>>
+    where
+        N: 'a,
+    {
+        Ok(Box::new(ContentBatch {
+            store: self,
+            nodes: Vec::new(),
+            increments: Vec::new(),
+        }))
+    }
+
+    fn ref_count(&self, ptr: &Ptr) -> Result<Option<u64>> {
+        let nodes = self.nodes.read().map_err(poisoned)?;
+        let (_, count) = nodes.get(&digest(ptr)?).ok_or_else(|| not_found(ptr))?;
+        Ok(Some(*count))
+    }
+}
+
+struct ContentBatch<'a, N> {
+    store: &'a ContentAddressedStore<N>,
+    nodes: Vec<(Digest, N)>,
+    increments: Vec<Digest>,
+}
+
+impl<N: Clone + Hashable + TreeNode> WriteBatch<N> for ContentBatch<'_, N> {
+    fn insert(&mut self, node: &N) -> Result<Ptr> {
+        let hash = hash_of(node);
+        self.nodes.push((hash, node.clone()));
+        Ptr::new(&hash)
+    }
+
+    fn inc_ref_count(&mut self, ptr: &Ptr) -> Result<()> {
+        self.increments.push(digest(ptr)?);
+        Ok(())
+    }
+
+    fn commit(self: Box<Self>) -> Result<()> {
+        let mut nodes = self.store.nodes.write().map_err(poisoned)?;
+        let staged: HashSet<_> = self.nodes.iter().map(|(hash, _)| *hash).collect();
+        for hash in &self.increments {
+            if !nodes.contains_key(hash) && !staged.contains(hash) {
+                return Err(not_found(&Ptr::new(hash)?));
+            }
+        }
+        for (hash, node) in &self.nodes {
+            insert(&mut nodes, *hash, node);
+        }
+        for hash in &self.increments {
+            if let Some((_, count)) = nodes.get_mut(hash) {
+                *count += 1;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+//! Deferred deletion: releasing the nodes of dropped versions on a
+//! background thread rather than while pruning, so that a prune freeing a
+//! large version doesn't stall the commits waiting behind it.
+//!
+//! A [`DeletionQueue`] holds the pointers whose reference the trees gave up
+//! but which haven't been released in the store yet, e.g. the roots of the
+//! versions dropped by
+//! [`VersionedTree::prune_deferred`](crate::tree::avl::versioned::VersionedTree::prune_deferred).
+//! Draining a pointer decrements its reference count and, if that drops to
+//! 0, deletes the node and queues its stored children in turn, so a large
+//! version is released a node at a time. Until then the stored reference
+//! counts still include the queued references, so the nodes stay readable
+//! and a new version can share them safely.
+//!
+//! [`DeletionQueue::spawn`] starts a thread which drains the queue in
+//! chunks of [`DrainOptions::chunk_nodes`] pointers, pausing between chunks
+//! to stay within [`DrainOptions::nodes_per_second`]. The queued pointers are
+//! listed by [`DeletionQueue::pending`] and the queue depth is reported by
+//! [`DeletionQueue::stats`]. The queue is kept in memory: pointers still
+//! queued when the process exits keep their nodes in the store.
+
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::{NodeManager, Ptr, TreeNode};
+use crate::{Error, Result};
+
+/// Parameters of the thread started by [`DeletionQueue::spawn`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DrainOptions {
+    nodes_per_second: u32,
+    chunk_nodes: usize,
+}
+
+impl Default for DrainOptions {
+    fn default() -> Self {
+        DrainOptions {
+            nodes_per_second: 1000,
+            chunk_nodes: 100,
+        }
+    }
+}
+
+impl DrainOptions {
+    /// Sets the number of pointers the background thread releases per
+    /// second on average, 1000 by default. Values below 1 are raised to 1.
+    pub fn nodes_per_second(self, nodes_per_second: u32) -> Self {
+        DrainOptions {
+            nodes_per_second: nodes_per_second.max(1),
+            ..self
+        }
+    }
+
+    /// Sets the number of pointers released at once before pausing, 100 by
+    /// default. Values below 1 are raised to 1.
+    pub fn chunk_nodes(self, chunk_nodes: usize) -> Self {
+        DrainOptions {
+            chunk_nodes: chunk_nodes.max(1),
+            ..self
+        }
+    }
+}
+
+/// The depth of a [`DeletionQueue`] and the work it did.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct DeletionStats {
+    /// The pointers waiting to be released.
+    pub queued: usize,
+    /// The references released, each decrementing a reference count.
+    pub released: u64,
+    /// The nodes deleted because their last reference was released.
+    pub deleted: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    released: AtomicU64,
+    deleted: AtomicU64,
+}
+
+/// Pointers whose references wait to be released, see the
+/// [module documentation](self).
+pub struct DeletionQueue<N> {
+    manager: Arc<NodeManager<N>>,
+    options: DrainOptions,
+    pending: Mutex<VecDeque<Ptr>>,
+    counters: Counters,
+}
+
+impl<N> DeletionQueue<N> {
+    /// Creates an empty queue of pointers stored through `manager`.
+    pub fn new(manager: Arc<NodeManager<N>>, options: DrainOptions) -> Self {
+        DeletionQueue {
+            manager,
+            options,
+            pending: Mutex::new(VecDeque::new()),
+            counters: Counters::default(),
+        }
+    }
+
+    pub fn manager(&self) -> &Arc<NodeManager<N>> {
+        &self.manager
+    }
+
+    /// Queues the release of a reference on `ptr`, e.g. the root of a
+    /// dropped version, which must not be used afterwards.
+    pub fn defer(&self, ptr: Ptr) -> Result<()> {
+        lock(&self.pending)?.push_back(ptr);
+        Ok(())
+    }
+
+    /// Returns the queued pointers, in the order they are released. A
+    /// pointer is listed once per queued reference.
+    pub fn pending(&self) -> Result<Vec<Ptr>> {
+        Ok(lock(&self.pending)?.iter().copied().collect())
+    }
+
+    /// Returns the number of queued pointers.
+    pub fn len(&self) -> usize {
+        self.pending.lock().map_or(0, |pending| pending.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> DeletionStats {
+        DeletionStats {
+            queued: self.len(),
+            released: self.counters.released.load(Relaxed),
+            deleted: self.counters.deleted.load(Relaxed),
+        }
+    }
+}
+
+impl<N: TreeNode> DeletionQueue<N> {
+    /// Releases up to `max` queued pointers, returning the number of nodes
+    /// deleted. A pointer whose release fails is queued again, at the
+    /// front, before the error is returned.
+    pub fn drain(&self, max: usize) -> Result<usize> {
+        let mut deleted = 0;
+        for _ in 0..max {
+            // Held while releasing, so the children of a deleted node are
+            // queued before the queue can be seen empty.
+            let mut pending = lock(&self.pending)?;
+            let Some(ptr) = pending.pop_front() else {
+                break;
+            };
+            match self.release(&ptr) {
+                Ok(children) => {
+                    deleted += usize::from(children.is_some());
+                    pending.extend(children.into_iter().flatten());
+                }
+                Err(err) => {
+                    pending.push_front(ptr);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Releases every queued pointer, returning the number of nodes
+    /// deleted.
+    pub fn drain_all(&self) -> Result<usize> {
+        let mut deleted = 0;
+        while !self.is_empty() {
+            deleted += self.drain(self.options.chunk_nodes)?;
+        }
+        Ok(deleted)
+    }
+
+    /// Releases a reference on `ptr`, returning the stored children of the
+    /// node if it was deleted.
+    fn release(&self, ptr: &Ptr) -> Result<Option<Vec<Ptr>>> {
+        let count = self.manager.dec_ref_count(ptr)?;
+        self.counters.released.fetch_add(1, Relaxed);
+        if count > 0 {
+            return Ok(None);
+        }
+        let node = self.manager.delete(ptr)?;
+        self.counters.deleted.fetch_add(1, Relaxed);
+        Ok(Some(node.stored_children()))
+    }
+}
+
+impl<N: TreeNode + Send + Sync + 'static> DeletionQueue<N> {
+    /// Starts draining the queue on a background thread until the returned
+    /// handle is stopped or dropped.
+    pub fn spawn(self: &Arc<Self>) -> DrainHandle {
+        let (stop, stopped) = mpsc::channel();
+        let queue = self.clone();
+        let per_node = Duration::from_secs(1) / queue.options.nodes_per_second;
+        let chunk = queue.options.chunk_nodes;
+        let thread = thread::spawn(move || loop {
+            let before = queue.counters.released.load(Relaxed);
+            queue.drain(chunk)?;
+            let released = queue.counters.released.load(Relaxed) - before;
+            // Pace idle rounds like a full chunk.
+            let paced = if released == 0 {
+                chunk as u64
+            } else {
+                released
+            };
+            let pause = per_node.saturating_mul(paced.try_into().unwrap_or(u32::MAX));
+            match stopped.recv_timeout(pause) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return Ok(()),
+            }
+        });
+        DrainHandle { stop, thread }
+    }
+}
+
+/// The background thread of [`DeletionQueue::spawn`]. Dropping the handle
+/// stops the thread without waiting for it.
+pub struct DrainHandle {
+    stop: Sender<()>,
+    thread: JoinHandle<Result<()>>,
+}
+
+impl DrainHandle {
+    /// Stops the thread and waits for it, returning the error which stopped
+    /// it early, if any. Pointers still queued stay queued.
+    pub fn stop(self) -> Result<()> {
+        // The thread is gone if sending fails, which joining reports.
+        let _ = self.stop.send(());
+        match self.thread.join() {
+            Ok(result) => result,
+            Err(_) => Err(Error::Poisoned("deletion thread panicked".into())),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>> {
+    mutex.lock().map_err(|_| Error::poisoned("deletion queue"))
+}
@@ -0,0 +1,522 @@
+//! An append-only log file of encoded nodes.
+//!
+//! The file is a sequence of records, each laid out as
+//!
+//! ```text
+//! tag: u8 | len: u32 LE | crc: u32 LE | payload: [u8; len]
+//! ```
+//!
+//! where `crc` is the CRC-32 of the tag, length and payload. A node record
+//! holds the encoded node and its pointer is the record's offset in the file.
+//! Reference count changes are records whose payload is the offset of the
+//! node they apply to, as are deletions, so nothing is ever overwritten and
+//! the space of deleted nodes isn't reclaimed. A header record replaces the
+//! store's header, see [`NodeStore::set_header`]. On open the log is
+//! replayed to rebuild the reference counts, and a torn record at the end,
+//! left by a crash during a write, is truncated. A damaged record before the
+//! end can't have been left by a crash, so it fails the open and the file is
+//! left as it is.
+//!
+//! A committed [`WriteBatch`] is written as a single batch record whose
+//! payload holds the records of its writes, laid out as above. The pointer
+//! of a node in a batch is the offset of its record inside the batch record,
+//! and since the batch record is checksummed as a whole, a crash keeps all
+//! of its writes or none. A batch with a version, see
+//! [`WriteBatch::set_version`], ends with a version record whose payload is
+//! the version as a `u64` LE, which applies to every node of the batch. The
+//! versions and the sizes of the node records are the store's [`NodeMeta`].
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use super::store::{decrement, not_found, poisoned, NodeMeta, NodeStore, Ptr, WriteBatch};
+use crate::{Error, Result};
+
+const NODE: u8 = 0;
+const INC_REF: u8 = 1;
+const DEC_REF: u8 = 2;
+const DELETE: u8 = 3;
+const BATCH: u8 = 4;
+const HEADER: u8 = 5;
+const VERSION: u8 = 6;
+
+const HEADER_LEN: usize = 9;
+
+/// A durable store which appends nodes, already encoded as bytes, to a log
+/// file. Pointers are `u64` file offsets.
+///
+/// Writes go to the OS without being synced; call [`FileNodeStore::sync`] to
+/// make them durable. While a batch is open, other writes wait until it is
+/// committed or dropped.
+pub struct FileNodeStore {
+    file: File,
+    /// Held by every write, and by an open batch so that the offsets it
+    /// assigned stay where its record will be written.
+    writer: Mutex<()>,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// The offset at which the next record is written.
+    end: u64,
+    /// The reference count of each node record by offset.
+    ref_counts: HashMap<u64, u64>,
+    /// The version of each node record written by a batch with one.
+    versions: HashMap<u64, u64>,
+    /// The payload of the last header record.
+    header: Option<Vec<u8>>,
+}
+
+impl FileNodeStore {
+    /// Opens the log at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|err| Error::io(format!("opening node log {}", path.display()), err))?;
+        let state = replay(&file)?;
+        let file_len = file.metadata()?.len();
+        if state.end < file_len {
+            file.set_len(state.end)?;
+            file.sync_data()?;
+        }
+        Ok(FileNodeStore {
+            file,
+            writer: Mutex::new(()),
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Flushes all writes to disk.
+    pub fn sync(&self) -> Result<()> {
+        Ok(self.file.sync_data()?)
+    }
+
+    /// Returns the number of node records in the log.
+    pub fn len(&self) -> usize {
+        self.state.lock().map_or(0, |state| state.ref_counts.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn append(&self, state: &mut State, tag: u8, payload: &[u8]) -> Result<u64> {
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+        encode_record(&mut record, tag, payload)?;
+        let offset = state.end;
+        write_all_at(&self.file, &record, offset)?;
+        state.end += record.len() as u64;
+        Ok(offset)
+    }
+
+    fn update_ref_count(&self, ptr: &Ptr, tag: u8) -> Result<u64> {
+        let _writer = self.writer.lock().map_err(poisoned)?;
+        let mut state = self.state.lock().map_err(poisoned)?;
+        let offset = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        let mut count = *state
+            .ref_counts
+            .get(&offset)
+            .ok_or_else(|| not_found(ptr))?;
+        match tag {
+            INC_REF => count += 1,
+            _ => {
+                decrement(ptr, &mut count)?;
+            }
+        }
+        self.append(&mut state, tag, &offset.to_le_bytes())?;
+        state.ref_counts.insert(offset, count);
+        Ok(count)
+    }
+}
+
+impl NodeStore<Vec<u8>> for FileNodeStore {
+    fn read(&self, ptr: &Ptr) -> Result<Vec<u8>> {
+        let offset = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        if !self
+            .state
+            .lock()
+            .map_err(poisoned)?
+            .ref_counts
+            .contains_key(&offset)
+        {
+            return Err(not_found(ptr));
+        }
+        let mut header = [0; HEADER_LEN];
+        read_exact_at(&self.file, &mut header, offset)?;
+        let (tag, len, crc) = parse_header(&header);
+        let mut payload = vec![0; len as usize];
+        read_exact_at(&self.file, &mut payload, offset + HEADER_LEN as u64)?;
+        if tag != NODE || checksum(tag, &payload) != crc {
+            return Err(Error::corrupt(*ptr));
+        }
+        Ok(payload)
+    }
+
+    fn insert(&self, node: &Vec<u8>) -> Result<Ptr> {
+        let _writer = self.writer.lock().map_err(poisoned)?;
+        let mut state = self.state.lock().map_err(poisoned)?;
+        let offset = self.append(&mut state, NODE, node)?;
+        state.ref_counts.insert(offset, 1);
+        Ok(Ptr::from_u64(offset))
+    }
+
+    fn inc_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        self.update_ref_count(ptr, INC_REF)
+    }
+
+    fn dec_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        self.update_ref_count(ptr, DEC_REF)
+    }
+
+    fn delete(&self, ptr: &Ptr) -> Result<()> {
+        let _writer = self.writer.lock().map_err(poisoned)?;
+        let mut state = self.state.lock().map_err(poisoned)?;
+        let offset = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        if !state.ref_counts.contains_key(&offset) {
+            return Err(not_found(ptr));
+        }
+        self.append(&mut state, DELETE, &offset.to_le_bytes())?;
+        state.ref_counts.remove(&offset);
+        state.versions.remove(&offset);
+        Ok(())
+    }
+
+    fn header(&self) -> Result<Option<Vec<u8>>> {
+        Ok(self.state.lock().map_err(poisoned)?.header.clone())
+    }
+
+    fn set_header(&self, header: &[u8]) -> Result<()> {
+        let _writer = self.writer.lock().map_err(poisoned)?;
+        let mut state = self.state.lock().map_err(poisoned)?;
+        self.append(&mut state, HEADER, header)?;
+        state.header = Some(header.to_vec());
+        Ok(())
+    }
+
+    fn begin_batch<'a>(&'a self) -> Result<Box<dyn WriteBatch<Vec<u8>> + 'a>>
+    where
+        Vec<u8>: 'a,
+    {
+        let writer = self.writer.lock().map_err(poisoned)?;
+        let base = self.state.lock().map_err(poisoned)?.end;
+        Ok(Box::new(FileBatch {
+            store: self,
+            _writer: writer,
+            base,
+            payload: Vec::new(),
+            nodes: Vec::new(),
+            increments: Vec::new(),
+            version: None,
+        }))
+    }
+
+    /// Returns the version of the node, if it was written by a batch with
+    /// one, and the length of its record's payload.
+    fn meta(&self, ptr: &Ptr) -> Result<Option<NodeMeta>> {
+        let offset = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        let version = {
+            let state = self.state.lock().map_err(poisoned)?;
+            if !state.ref_counts.contains_key(&offset) {
+                return Err(not_found(ptr));
+            }
+            state.versions.get(&offset).copied()
+        };
+        let mut header = [0; HEADER_LEN];
+        read_exact_at(&self.file, &mut header, offset)?;
+        let (_, len, _) = parse_header(&header);
+        Ok(Some(NodeMeta {
+            version,
+            size: Some(len as u64),
+        }))
+    }
+
+    fn ref_count(&self, ptr: &Ptr) -> Result<Option<u64>> {
+        let offset = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        let state = self.state.lock().map_err(poisoned)?;
+        let count = state
+            .ref_counts
+            .get(&offset)
+            .ok_or_else(|| not_found(ptr))?;
+        Ok(Some(*count))
+    }
+}
+
+struct FileBatch<'a> {
+    store: &'a FileNodeStore,
+    _writer: MutexGuard<'a, ()>,
+    /// The offset the batch record will be written at.
+    base: u64,
+    /// The records of the staged writes.
+    payload: Vec<u8>,
+    nodes: Vec<u64>,
+    increments: Vec<u64>,
+    version: Option<u64>,
+}
+
+impl WriteBatch<Vec<u8>> for FileBatch<'_> {
+    fn insert(&mut self, node: &Vec<u8>) -> Result<Ptr> {
+        let offset = self.base + (HEADER_LEN + self.payload.len()) as u64;
+        encode_record(&mut self.payload, NODE, node)?;
+        self.nodes.push(offset);
+        Ok(Ptr::from_u64(offset))
+    }
+
+    fn inc_ref_count(&mut self, ptr: &Ptr) -> Result<()> {
+        let offset = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        encode_record(&mut self.payload, INC_REF, &offset.to_le_bytes())?;
+        self.increments.push(offset);
+        Ok(())
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.version = Some(version);
+    }
+
+    fn commit(mut self: Box<Self>) -> Result<()> {
+        if self.payload.is_empty() {
+            return Ok(());
+        }
+        if let Some(version) = self.version.filter(|_| !self.nodes.is_empty()) {
+            encode_record(&mut self.payload, VERSION, &version.to_le_bytes())?;
+        }
+        let mut state = self.store.state.lock().map_err(poisoned)?;
+        for offset in &self.increments {
+            if !state.ref_counts.contains_key(offset) && !self.nodes.contains(offset) {
+                return Err(not_found(&Ptr::from_u64(*offset)));
+            }
+        }
+        let offset = self.store.append(&mut state, BATCH, &self.payload)?;
+        debug_assert_eq!(offset, self.base);
+        for &offset in &self.nodes {
+            state.ref_counts.insert(offset, 1);
+            if let Some(version) = self.version {
+                state.versions.insert(offset, version);
+            }
+        }
+        for offset in self.increments {
+            *state.ref_counts.get_mut(&offset).expect("checked above") += 1;
+        }
+        Ok(())
+    }
+}
+
+fn encode_record(buf: &mut Vec<u8>, tag: u8, payload: &[u8]) -> Result<()> {
+    let len =
+        u32::try_from(payload.len()).map_err(|_| Error::invalid("record too large for the log"))?;
+    buf.push(tag);
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf.extend_from_slice(&checksum(tag, payload).to_le_bytes());
+    buf.extend_from_slice(payload);
+    Ok(())
+}
+
+/// Reads every complete record of the log, stopping at a torn record at its
+/// end: one which is cut short, or which fails its checksum and runs to the
+/// end of the file. A record failing its checksum before the end fails the
+/// replay.
+fn replay(file: &File) -> Result<State> {
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut state = State {
+        end: 0,
+        ref_counts: HashMap::new(),
+        versions: HashMap::new(),
+        header: None,
+    };
+    let mut header = [0; HEADER_LEN];
+    loop {
+        if !read_record_part(&mut reader, &mut header)? {
+            break;
+        }
+        let (tag, len, crc) = parse_header(&header);
+        // A torn header may claim any length, so check it before allocating.
+        if state.end + (HEADER_LEN as u64) + len as u64 > file_len {
+            break;
+        }
+        let mut payload = vec![0; len as usize];
+        if !read_record_part(&mut reader, &mut payload)? {
+            break;
+        }
+        if checksum(tag, &payload) != crc {
+            if state.end + (HEADER_LEN + payload.len()) as u64 == file_len {
+                break;
+            }
+            return Err(Error::corruption(format!(
+                "record at offset {} of the node log fails its checksum",
+                state.end
+            )));
+        }
+        if tag == BATCH {
+            let base = state.end;
+            let malformed =
+                || Error::corruption(format!("malformed batch record at offset {}", base));
+            let mut nodes = Vec::new();
+            let mut version = None;
+            let mut pos = 0;
+            while pos < payload.len() {
+                let header = payload
+                    .get(pos..pos + HEADER_LEN)
+                    .and_then(|header| <&[u8; HEADER_LEN]>::try_from(header).ok())
+                    .ok_or_else(malformed)?;
+                let (tag, len, crc) = parse_header(header);
+                let start = pos + HEADER_LEN;
+                let inner = payload
+                    .get(start..start + len as usize)
+                    .filter(|inner| tag != BATCH && checksum(tag, inner) == crc)
+                    .ok_or_else(malformed)?;
+                let offset = base + (HEADER_LEN + pos) as u64;
+                if tag == VERSION {
+                    version = Some(parse_u64(inner).ok_or_else(malformed)?);
+                } else {
+                    if tag == NODE {
+                        nodes.push(offset);
+                    }
+                    apply_record(&mut state, offset, tag, inner)?;
+                }
+                pos = start + len as usize;
+            }
+            if let Some(version) = version {
+                state
+                    .versions
+                    .extend(nodes.into_iter().map(|node| (node, version)));
+            }
+        } else {
+            let offset = state.end;
+            apply_record(&mut state, offset, tag, &payload)?;
+        }
+        state.end += (HEADER_LEN + payload.len()) as u64;
+    }
+    Ok(state)
+}
+
+/// Applies the record at `offset` to the reference counts.
+fn apply_record(state: &mut State, offset: u64, tag: u8, payload: &[u8]) -> Result<()> {
+    match tag {
+        NODE => {
+            state.ref_counts.insert(offset, 1);
+        }
+        HEADER => state.header = Some(payload.to_vec()),
+        INC_REF | DEC_REF | DELETE => {
+            let target = parse_u64(payload)
+                .ok_or_else(|| Error::corruption("malformed node update record"))?;
+            let count = state.ref_counts.get_mut(&target).ok_or_else(|| {
+                Error::corruption(format!("update record for unknown node {}", target))
+            })?;
+            match tag {
+                INC_REF => *count += 1,
+                DEC_REF => *count = count.saturating_sub(1),
+                _ => {
+                    state.ref_counts.remove(&target);
+                    state.versions.remove(&target);
+                }
+            }
+        }
+        _ => {
+            return Err(Error::corruption(format!(
+                "unknown record tag {} at offset {}",
+                tag, offset
+            )))
+        }
+    }
+    Ok(())
+}
+
+fn parse_u64(payload: &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(payload.try_into().ok()?))
+}
+
+/// Fills `buf`, returning `false` if the log ends first.
+fn read_record_part(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn parse_header(header: &[u8; HEADER_LEN]) -> (u8, u32, u32) {
+    let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]);
+    let crc = u32::from_le_bytes([header[5], header[6], header[7], header[8]]);
+    (header[0], len, crc)
+}
+
+fn checksum(tag: u8, payload: &[u8]) -> u32 {
+    let len = (payload.len() as u32).to_le_bytes();
+    let mut crc = Crc32::default();
+    crc.update(&[tag]);
+    crc.update(&len);
+    crc.update(payload);
+    crc.finish()
+}
+
+/// CRC-32 with the IEEE polynomial, as used by zlib and Ethernet.
+pub(super) struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32(!0)
+    }
+}
+
+impl Crc32 {
+    pub(super) fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u32;
+            for _ in 0..8 {
+                self.0 = (self.0 >> 1) ^ (0xedb8_8320 & (self.0 & 1).wrapping_neg());
+            }
+        }
+    }
+
+    pub(super) fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset)? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            n => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
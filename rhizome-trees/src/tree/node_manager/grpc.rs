@@ -0,0 +1,669 @@
+//! A node store served over gRPC, so several processes share one storage
+//! tier.
+//!
+//! [`NodeStoreServer`] is a [tonic](https://docs.rs/tonic) service exposing
+//! any store of encoded nodes, and [`RemoteNodeStore`] is the store which
+//! talks to it. Wrapping the remote store in an
+//! [`EncodedStore`](super::EncodedStore) gives trees of any node type access
+//! to the shared nodes. The service is `rhizome.NodeStore`, with unary
+//! methods equivalent to this protobuf definition:
+//!
+//! ```proto
+//! service NodeStore {
+//!   rpc Read(Ptrs) returns (Nodes);
+//!   rpc Insert(Node) returns (Ptr);
+//!   rpc IncRefCount(Ptr) returns (Count);
+//!   rpc DecRefCount(Ptr) returns (Count);
+//!   rpc Delete(Ptr) returns (Empty);
+//!   rpc GetHeader(Empty) returns (Header);
+//!   rpc SetHeader(Header) returns (Empty);
+//! }
+//!
+//! message Ptrs { repeated bytes ptrs = 1; }
+//! message Nodes { repeated bytes nodes = 1; }
+//! message Node { bytes node = 1; }
+//! message Ptr { bytes ptr = 1; }
+//! message Count { uint64 count = 1; }
+//! message Header { optional bytes header = 1; }
+//! message Empty {}
+//! ```
+//!
+//! Failures travel as gRPC status codes, `NOT_FOUND`, `FAILED_PRECONDITION`
+//! for a reference count underflow and `DATA_LOSS` for corrupt nodes, with
+//! the pointer of the node in the status details, so the remote store
+//! reports them as the [`Error`] the served store returned. Failures of the
+//! served store's backend or I/O are `UNAVAILABLE`, since trying again may
+//! succeed.
+//!
+//! The remote store retries calls failing on such transient errors, and on
+//! the server being unreachable, according to its [`RetryPolicy`]. Calls
+//! which change the store are retried only if they weren't sent, since the
+//! server may have applied them before failing. A deadline set with
+//! [`RemoteNodeStoreBuilder::timeout`] bounds each call with its retries,
+//! and [`ReadConsistency`] sets whether reads may be answered without a
+//! call.
+//!
+//! Batches and in-place updates aren't part of the service: the remote
+//! store keeps the [`NodeStore`] defaults, so a save is a call per written
+//! node rather than a single atomic commit.
+
+use std::convert::Infallible;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+
+use tokio::runtime::Runtime;
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::codegen::{http, Body, BoxFuture, Bytes, Context, Poll, Service, StdError};
+use tonic::server::{Grpc, NamedService, UnaryService};
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Request, Response, Status};
+
+use super::store::{NodeStore, Ptr};
+use crate::{Error, Result};
+
+const SERVICE: &str = "rhizome.NodeStore";
+
+const READ: &str = "/rhizome.NodeStore/Read";
+const INSERT: &str = "/rhizome.NodeStore/Insert";
+const INC_REF_COUNT: &str = "/rhizome.NodeStore/IncRefCount";
+const DEC_REF_COUNT: &str = "/rhizome.NodeStore/DecRefCount";
+const DELETE: &str = "/rhizome.NodeStore/Delete";
+const GET_HEADER: &str = "/rhizome.NodeStore/GetHeader";
+const SET_HEADER: &str = "/rhizome.NodeStore/SetHeader";
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct PtrsMessage {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    ptrs: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct NodesMessage {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    nodes: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct NodeMessage {
+    #[prost(bytes = "vec", tag = "1")]
+    node: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct PtrMessage {
+    #[prost(bytes = "vec", tag = "1")]
+    ptr: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct CountMessage {
+    #[prost(uint64, tag = "1")]
+    count: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct HeaderMessage {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    header: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct EmptyMessage {}
+
+/// How a [`RemoteNodeStore`] retries calls failing on transient errors,
+/// waiting between attempts for a backoff which doubles from
+/// `initial_backoff` up to `max_backoff`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy making a single attempt at each call.
+    pub fn never() -> Self {
+        RetryPolicy::default().max_attempts(1)
+    }
+
+    /// Sets the number of attempts at a call, including the first, 3 by
+    /// default. Values below 1 are raised to 1.
+    pub fn max_attempts(self, max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            ..self
+        }
+    }
+
+    /// Sets the backoff before the first retry and the most it grows to,
+    /// 50 ms and 2 s by default. A `max` below `initial` is raised to it.
+    pub fn backoff(self, initial: Duration, max: Duration) -> Self {
+        RetryPolicy {
+            initial_backoff: initial,
+            max_backoff: max.max(initial),
+            ..self
+        }
+    }
+
+    /// The backoff after the `attempt`th failed attempt, counting from 1.
+    fn backoff_after(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt - 1).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Which answers a [`RemoteNodeStore`] accepts for reads.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum ReadConsistency {
+    /// Every read is answered by the server.
+    #[default]
+    Strong,
+    /// Reads may be answered by the remote store itself with the nodes and
+    /// header it read or wrote less than the given time ago, saving a round
+    /// trip. Stored nodes don't change, so a stale read can only return a
+    /// node which was deleted since, or a header which was replaced, by
+    /// another client. The store's own writes are always seen.
+    MaxStaleness(Duration),
+}
+
+/// The number of nodes a [`RemoteNodeStore`] keeps for stale reads.
+const RECENT_NODES: usize = 10_000;
+
+/// The nodes and header a [`RemoteNodeStore`] may answer stale reads with,
+/// along with when they were read or written.
+struct Recent {
+    max_staleness: Duration,
+    nodes: LruCache<Ptr, (Instant, Vec<u8>)>,
+    header: Option<(Instant, Option<Vec<u8>>)>,
+}
+
+impl Recent {
+    fn node(&mut self, ptr: &Ptr) -> Option<Vec<u8>> {
+        match self.nodes.get(ptr) {
+            Some((at, node)) if at.elapsed() <= self.max_staleness => Some(node.clone()),
+            _ => None,
+        }
+    }
+
+    fn header(&self) -> Option<Option<Vec<u8>>> {
+        self.header
+            .as_ref()
+            .filter(|(at, _)| at.elapsed() <= self.max_staleness)
+            .map(|(_, header)| header.clone())
+    }
+}
+
+impl From<&Ptr> for PtrMessage {
+    fn from(ptr: &Ptr) -> Self {
+        PtrMessage {
+            ptr: ptr.as_bytes().to_vec(),
+        }
+    }
+}
+
+/// A store whose nodes live in a [`NodeStoreServer`], reached over a gRPC
+/// channel.
+///
+/// The store runs its calls on a runtime of its own and blocks the calling
+/// thread until they complete, like the other stores do on I/O. It must not
+/// be called from within an async task, which should use
+/// `tokio::task::spawn_blocking` instead.
+pub struct RemoteNodeStore {
+    runtime: Runtime,
+    channel: Channel,
+    retry: RetryPolicy,
+    timeout: Option<Duration>,
+    recent: Option<Mutex<Recent>>,
+}
+
+/// Configures a [`RemoteNodeStore`], created by [`RemoteNodeStore::builder`].
+pub struct RemoteNodeStoreBuilder {
+    endpoint: String,
+    retry: RetryPolicy,
+    timeout: Option<Duration>,
+    read_consistency: ReadConsistency,
+}
+
+impl RemoteNodeStoreBuilder {
+    /// Sets how calls failing on transient errors are retried. Defaults to
+    /// [`RetryPolicy::default`].
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sets the deadline of each call, including its retries and the
+    /// backoffs between them. Calls have no deadline by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets which answers reads accept. Defaults to
+    /// [`ReadConsistency::Strong`].
+    pub fn read_consistency(mut self, read_consistency: ReadConsistency) -> Self {
+        self.read_consistency = read_consistency;
+        self
+    }
+
+    /// Connects to the server.
+    pub fn connect(self) -> Result<RemoteNodeStore> {
+        let endpoint = self.endpoint;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("rhizome-remote-store")
+            .enable_all()
+            .build()
+            .map_err(|err| Error::io("starting the remote node store runtime", err))?;
+        let channel = runtime
+            .block_on(async { Endpoint::from_shared(endpoint.clone())?.connect().await })
+            .map_err(|err| Error::storage(format!("connecting to node store {}", endpoint), err))?;
+        let recent = match self.read_consistency {
+            ReadConsistency::Strong => None,
+            ReadConsistency::MaxStaleness(max_staleness) => Some(Mutex::new(Recent {
+                max_staleness,
+                nodes: LruCache::new(NonZeroUsize::new(RECENT_NODES).expect("non-zero")),
+                header: None,
+            })),
+        };
+        Ok(RemoteNodeStore {
+            runtime,
+            channel,
+            retry: self.retry,
+            timeout: self.timeout,
+            recent,
+        })
+    }
+}
+
+impl RemoteNodeStore {
+    /// Connects to the server at `endpoint`, e.g. `http://127.0.0.1:50051`,
+    /// with the default settings.
+    pub fn connect(endpoint: impl Into<String>) -> Result<Self> {
+        RemoteNodeStore::builder(endpoint).connect()
+    }
+
+    pub fn builder(endpoint: impl Into<String>) -> RemoteNodeStoreBuilder {
+        RemoteNodeStoreBuilder {
+            endpoint: endpoint.into(),
+            retry: RetryPolicy::default(),
+            timeout: None,
+            read_consistency: ReadConsistency::default(),
+        }
+    }
+
+    /// Makes a unary call of `method` on the server, retrying it according
+    /// to the [`RetryPolicy`] within the deadline.
+    fn call<Req, Resp>(&self, method: &'static str, request: Req) -> Result<Resp>
+    where
+        Req: prost::Message + Clone + Send + Sync + 'static,
+        Resp: prost::Message + Default + Send + Sync + 'static,
+    {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        // The other methods change the store, maybe before failing.
+        let idempotent = matches!(method, READ | GET_HEADER | SET_HEADER);
+        self.runtime.block_on(async {
+            let mut attempts = 0;
+            loop {
+                let left =
+                    deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                let mut client = tonic::client::Grpc::new(self.channel.clone());
+                let mut request = Request::new(request.clone());
+                if let Some(left) = left {
+                    request.set_timeout(left);
+                }
+                let attempt = async {
+                    client
+                        .ready()
+                        .await
+                        .map_err(|err| (Status::unavailable(err.to_string()), false))?;
+                    let path = PathAndQuery::from_static(method);
+                    let codec = ProstCodec::<Req, Resp>::default();
+                    client
+                        .unary(request, path, codec)
+                        .await
+                        .map_err(|status| (status, true))
+                };
+                let outcome = match left {
+                    None => attempt.await,
+                    Some(left) => match tokio::time::timeout(left, attempt).await {
+                        Ok(outcome) => outcome,
+                        Err(_) => {
+                            let message = format!("{} missed its deadline", method);
+                            Err((Status::deadline_exceeded(message), true))
+                        }
+                    },
+                };
+                let (status, sent) = match outcome {
+                    Ok(response) => return Ok(response.into_inner()),
+                    Err(failure) => failure,
+                };
+                attempts += 1;
+                let backoff = self.retry.backoff_after(attempts);
+                let retry = attempts < self.retry.max_attempts
+                    && transient(status.code())
+                    && (idempotent || !sent)
+                    && deadline.is_none_or(|deadline| Instant::now() + backoff < deadline);
+                if !retry {
+                    return Err(error(status));
+                }
+                tokio::time::sleep(backoff).await;
+            }
+        })
+    }
+
+    /// Returns the state kept for stale reads, if they are allowed.
+    fn recent(&self) -> Result<Option<MutexGuard<'_, Recent>>> {
+        self.recent
+            .as_ref()
+            .map(|recent| {
+                recent
+                    .lock()
+                    .map_err(|_| Error::poisoned("remote node store"))
+            })
+            .transpose()
+    }
+}
+
+impl NodeStore<Vec<u8>> for RemoteNodeStore {
+    fn read(&self, ptr: &Ptr) -> Result<Vec<u8>> {
+        let mut nodes = self.read_many(std::slice::from_ref(ptr))?;
+        Ok(nodes.remove(0))
+    }
+
+    /// Reads the nodes which can't be answered from the recent ones in a
+    /// single call.
+    fn read_many(&self, ptrs: &[Ptr]) -> Result<Vec<Vec<u8>>> {
+        let mut nodes: Vec<Option<Vec<u8>>> = match self.recent()? {
+            Some(mut recent) => ptrs.iter().map(|ptr| recent.node(ptr)).collect(),
+            None => vec![None; ptrs.len()],
+        };
+        let missing: Vec<&Ptr> = ptrs
+            .iter()
+            .zip(&nodes)
+            .filter(|(_, node)| node.is_none())
+            .map(|(ptr, _)| ptr)
+            .collect();
+        if !missing.is_empty() {
+            let request = PtrsMessage {
+                ptrs: missing.iter().map(|ptr| ptr.as_bytes().to_vec()).collect(),
+            };
+            let read = self.call::<_, NodesMessage>(READ, request)?.nodes;
+            if read.len() != missing.len() {
+                return Err(Error::corruption(format!(
+                    "node server returned {} of {} nodes",
+                    read.len(),
+                    missing.len()
+                )));
+            }
+            if let Some(mut recent) = self.recent()? {
+                for (ptr, node) in missing.iter().zip(&read) {
+                    recent.nodes.put(**ptr, (Instant::now(), node.clone()));
+                }
+            }
+            let mut read = read.into_iter();
+            for node in nodes.iter_mut().filter(|node| node.is_none()) {
+                *node = read.next();
+            }
+        }
+        Ok(nodes
+            .into_iter()
+            .map(|node| node.expect("every node is read"))
+            .collect())
+    }
+
+    fn insert(&self, node: &Vec<u8>) -> Result<Ptr> {
+        let request = NodeMessage { node: node.clone() };
+        let response: PtrMessage = self.call(INSERT, request)?;
+        let ptr = Ptr::new(&response.ptr)?;
+        if let Some(mut recent) = self.recent()? {
+            recent.nodes.put(ptr, (Instant::now(), node.clone()));
+        }
+        Ok(ptr)
+    }
+
+    fn inc_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        let response: CountMessage = self.call(INC_REF_COUNT, PtrMessage::from(ptr))?;
+        Ok(response.count)
+    }
+
+    fn dec_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        let response: CountMessage = self.call(DEC_REF_COUNT, PtrMessage::from(ptr))?;
+        Ok(response.count)
+    }
+
+    fn delete(&self, ptr: &Ptr) -> Result<()> {
+        if let Some(mut recent) = self.recent()? {
+            recent.nodes.pop(ptr);
+        }
+        self.call::<_, EmptyMessage>(DELETE, PtrMessage::from(ptr))?;
+        Ok(())
+    }
+
+    fn header(&self) -> Result<Option<Vec<u8>>> {
+        if let Some(header) = self.recent()?.and_then(|recent| recent.header()) {
+            return Ok(header);
+        }
+        let response: HeaderMessage = self.call(GET_HEADER, EmptyMessage {})?;
+        if let Some(mut recent) = self.recent()? {
+            recent.header = Some((Instant::now(), response.header.clone()));
+        }
+        Ok(response.header)
+    }
+
+    fn set_header(&self, header: &[u8]) -> Result<()> {
+        let request = HeaderMessage {
+            header: Some(header.to_vec()),
+        };
+        if let Some(mut recent) = self.recent()? {
+            recent.header = None;
+        }
+        self.call::<_, EmptyMessage>(SET_HEADER, request)?;
+        if let Some(mut recent) = self.recent()? {
+            recent.header = Some((Instant::now(), Some(header.to_vec())));
+        }
+        Ok(())
+    }
+}
+
+/// Serves a store of encoded nodes as the `rhizome.NodeStore` gRPC service,
+/// e.g. with `tonic::transport::Server::builder().add_service(server)`.
+///
+/// The store is called on tokio's blocking threads, so stores which wait
+/// on disk don't hold up the server's other requests.
+pub struct NodeStoreServer<S> {
+    store: Arc<S>,
+}
+
+impl<S: NodeStore<Vec<u8>> + 'static> NodeStoreServer<S> {
+    pub fn new(store: S) -> Self {
+        NodeStoreServer::from_arc(Arc::new(store))
+    }
+
+    /// Serves a store which is also used locally.
+    pub fn from_arc(store: Arc<S>) -> Self {
+        NodeStoreServer { store }
+    }
+
+    /// Decodes the request of a call, runs `handler` with it on a blocking
+    /// thread and encodes its response.
+    fn unary<B, Req, Resp>(
+        &self,
+        request: http::Request<B>,
+        handler: fn(&S, Req) -> Result<Resp>,
+    ) -> BoxFuture<http::Response<BoxBody>, Infallible>
+    where
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+        Req: prost::Message + Default + Send + 'static,
+        Resp: prost::Message + Send + 'static,
+    {
+        let method = Handler {
+            store: self.store.clone(),
+            handler,
+        };
+        Box::pin(async move {
+            let mut grpc = Grpc::new(ProstCodec::<Resp, Req>::default());
+            Ok(grpc.unary(method, request).await)
+        })
+    }
+}
+
+impl<S> Clone for NodeStoreServer<S> {
+    fn clone(&self) -> Self {
+        NodeStoreServer {
+            store: self.store.clone(),
+        }
+    }
+}
+
+impl<S> NamedService for NodeStoreServer<S> {
+    const NAME: &'static str = SERVICE;
+}
+
+impl<S, B> Service<http::Request<B>> for NodeStoreServer<S>
+where
+    S: NodeStore<Vec<u8>> + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        match request.uri().path() {
+            READ => self.unary(request, |store, request: PtrsMessage| {
+                let ptrs = request
+                    .ptrs
+                    .iter()
+                    .map(|ptr| Ptr::new(ptr))
+                    .collect::<Result<Vec<_>>>()?;
+                let nodes = store.read_many(&ptrs)?;
+                Ok(NodesMessage { nodes })
+            }),
+            INSERT => self.unary(request, |store, request: NodeMessage| {
+                Ok(PtrMessage::from(&store.insert(&request.node)?))
+            }),
+            INC_REF_COUNT => self.unary(request, |store, request: PtrMessage| {
+                let count = store.inc_ref_count(&Ptr::new(&request.ptr)?)?;
+                Ok(CountMessage { count })
+            }),
+            DEC_REF_COUNT => self.unary(request, |store, request: PtrMessage| {
+                let count = store.dec_ref_count(&Ptr::new(&request.ptr)?)?;
+                Ok(CountMessage { count })
+            }),
+            DELETE => self.unary(request, |store, request: PtrMessage| {
+                store.delete(&Ptr::new(&request.ptr)?)?;
+                Ok(EmptyMessage {})
+            }),
+            GET_HEADER => self.unary(request, |store, _: EmptyMessage| {
+                let header = store.header()?;
+                Ok(HeaderMessage { header })
+            }),
+            SET_HEADER => self.unary(request, |store, request: HeaderMessage| {
+                let header = request
+                    .header
+                    .ok_or_else(|| Error::invalid("no header to set"))?;
+                store.set_header(&header)?;
+                Ok(EmptyMessage {})
+            }),
+            _ => Box::pin(async move {
+                let status = Status::unimplemented(format!("{} has no such method", SERVICE));
+                Ok(status.into_http())
+            }),
+        }
+    }
+}
+
+/// One method of a [`NodeStoreServer`].
+struct Handler<S, Req, Resp> {
+    store: Arc<S>,
+    handler: fn(&S, Req) -> Result<Resp>,
+}
+
+impl<S, Req, Resp> UnaryService<Req> for Handler<S, Req, Resp>
+where
+    S: NodeStore<Vec<u8>> + 'static,
+    Req: Send + 'static,
+    Resp: Send + 'static,
+{
+    type Response = Resp;
+    type Future = BoxFuture<Response<Resp>, Status>;
+
+    fn call(&mut self, request: Request<Req>) -> Self::Future {
+        let (store, handler) = (self.store.clone(), self.handler);
+        Box::pin(async move {
+            let request = request.into_inner();
+            match tokio::task::spawn_blocking(move || handler(&store, request)).await {
+                Ok(Ok(response)) => Ok(Response::new(response)),
+                Ok(Err(err)) => Err(status(err)),
+                Err(_) => Err(Status::internal("node store call panicked")),
+            }
+        })
+    }
+}
+
+/// Converts a failure of the served store into the status the remote store
+/// converts back with [`error`].
+fn status(err: Error) -> Status {
+    let (code, ptr) = match &err {
+        Error::NotFound(ptr) => (Code::NotFound, Some(ptr)),
+        Error::RefCountUnderflow(ptr) => (Code::FailedPrecondition, Some(ptr)),
+        Error::Corrupt { ptr, .. } => (Code::DataLoss, Some(ptr)),
+        Error::Corruption(_) => (Code::DataLoss, None),
+        Error::Invalid(_) | Error::Codec(_) => (Code::InvalidArgument, None),
+        Error::ReadOnly => (Code::PermissionDenied, None),
+        Error::Storage { .. } | Error::Io { .. } => (Code::Unavailable, None),
+        _ => (Code::Internal, None),
+    };
+    let details = ptr.map_or_else(Bytes::new, |ptr| Bytes::copy_from_slice(ptr.as_bytes()));
+    Status::with_details(code, err.to_string(), details)
+}
+
+/// Whether a call failing with `code` may succeed if tried again.
+fn transient(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Unavailable | Code::DeadlineExceeded | Code::ResourceExhausted | Code::Aborted
+    )
+}
+
+fn error(status: Status) -> Error {
+    let ptr = Some(status.details())
+        .filter(|details| !details.is_empty())
+        .and_then(|details| Ptr::new(details).ok());
+    match (status.code(), ptr) {
+        (Code::NotFound, Some(ptr)) => Error::NotFound(ptr),
+        (Code::FailedPrecondition, Some(ptr)) => Error::RefCountUnderflow(ptr),
+        (Code::DataLoss, Some(ptr)) => Error::Corrupt {
+            ptr,
+            source: Some(Box::new(Error::corruption(status.message()))),
+        },
+        (Code::DataLoss, None) => Error::corruption(status.message()),
+        (Code::InvalidArgument, _) => Error::invalid(status.message()),
+        (Code::PermissionDenied, _) => Error::ReadOnly,
+        _ => Error::storage("remote node store failed", status),
+    }
+}
@@ -0,0 +1,43 @@
+//! Hooks for monitoring how a [`NodeManager`](super::NodeManager) uses its
+//! store and cache in production, e.g. by exporting them as Prometheus
+//! counters and histograms.
+
+use std::time::Duration;
+
+/// Receives the reads, cache lookups and writes of a
+/// [`NodeManager`](super::NodeManager), set with
+/// [`NodeManagerBuilder::metrics_hooks`](super::NodeManagerBuilder::metrics_hooks).
+///
+/// Every method does nothing by default, so implementations only override
+/// the ones they export. They are called on the reading or writing thread
+/// and should return quickly, e.g. by updating atomic counters.
+pub trait NodeManagerMetrics: Send + Sync {
+    /// A stored node was found in the cache.
+    fn cache_hit(&self) {}
+
+    /// A stored node wasn't cached and is read from the store.
+    fn cache_miss(&self) {}
+
+    /// `nodes` nodes were read from the store at once, taking `latency`.
+    fn store_read(&self, nodes: usize, latency: Duration) {
+        let _ = (nodes, latency);
+    }
+
+    /// `nodes` nodes were written to the store. `bytes` is the size of
+    /// their encodings as reported by [`NodeStore::meta`](super::NodeStore::meta),
+    /// which is 0 for stores that don't encode their nodes.
+    fn nodes_written(&self, nodes: usize, bytes: u64) {
+        let _ = (nodes, bytes);
+    }
+
+    /// A reference count was incremented (`true`) or decremented (`false`).
+    fn ref_count_updated(&self, incremented: bool) {
+        let _ = incremented;
+    }
+
+    /// A write, either a single operation or a whole batch such as a save,
+    /// was applied to the store, taking `latency`.
+    fn store_write(&self, latency: Duration) {
+        let _ = latency;
+    }
+}
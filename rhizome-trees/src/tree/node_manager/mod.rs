@@ -0,0 +1,1040 @@
+//! Access to tree nodes which may live in memory or in a [`NodeStore`].
+//!
+//! Trees refer to their children through [`NodeRef`]s. Nodes created by
+//! modifying a tree are held in memory until the tree is saved, after which
+//! they are referred to by their [`Ptr`] and loaded on demand through the
+//! [`NodeManager`], which keeps recently used nodes in a cache.
+
+pub mod cache;
+pub mod codec;
+pub mod config;
+pub mod content;
+pub mod deferred;
+pub mod file;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod metrics;
+#[cfg(feature = "object-store")]
+pub mod object;
+pub mod observer;
+#[cfg(feature = "redb")]
+pub mod redb;
+pub mod store;
+pub mod wal;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::ops::Deref;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use self::cache::{AbsentKeys, NodeCache};
+use crate::tree::hash::{hash_of, Digest, HashVersion, Hashable, ValueHashMemo};
+use crate::{Error, Result};
+
+#[cfg(feature = "redb")]
+pub use self::redb::RedbNodeStore;
+pub use cache::SharedCache;
+#[cfg(feature = "serde")]
+pub use codec::BincodeCodec;
+pub use codec::{EncodedStore, NodeCodec};
+pub use config::{ProofFormat, TreeConfig};
+pub use content::ContentAddressedStore;
+pub use deferred::{DeletionQueue, DeletionStats, DrainHandle, DrainOptions};
+pub use file::FileNodeStore;
+#[cfg(feature = "grpc")]
+pub use grpc::{
+    NodeStoreServer, ReadConsistency, RemoteNodeStore, RemoteNodeStoreBuilder, RetryPolicy,
+};
+pub use metrics::NodeManagerMetrics;
+#[cfg(feature = "object-store")]
+pub use object::{ObjectNodeStore, ObjectNodeStoreBuilder};
+pub use observer::{StoreEvent, StoreObserver};
+pub use store::{HealthReport, MemNodeStore, NodeMeta, NodeStore, NullNodeStore, Ptr, WriteBatch};
+pub use wal::WalStore;
+
+/// A node which may refer to other stored nodes, which lets a
+/// [`NodeManager`] walk the nodes of a saved version.
+pub trait TreeNode {
+    /// Returns the pointers to the stored children of the node.
+    fn stored_children(&self) -> Vec<Ptr>;
+}
+
+/// A reference from a tree to one of its nodes.
+pub enum NodeRef<N> {
+    /// A node which hasn't been saved yet.
+    Mem(Arc<N>),
+    /// A node persisted in the tree's store.
+    Stored(Ptr),
+}
+
+impl<N> Clone for NodeRef<N> {
+    fn clone(&self) -> Self {
+        match self {
+            NodeRef::Mem(node) => NodeRef::Mem(node.clone()),
+            NodeRef::Stored(ptr) => NodeRef::Stored(*ptr),
+        }
+    }
+}
+
+impl<N> fmt::Debug for NodeRef<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeRef::Mem(node) => write!(f, "Mem({:p})", Arc::as_ptr(node)),
+            NodeRef::Stored(ptr) => write!(f, "Stored({:?})", ptr),
+        }
+    }
+}
+
+/// Serializes as the pointer of a stored node. Nodes are only serialized
+/// once their children are saved, so serializing a reference to an unsaved
+/// node fails.
+#[cfg(feature = "serde")]
+impl<N> serde::Serialize for NodeRef<N> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            NodeRef::Mem(_) => Err(serde::ser::Error::custom(
+                "can't serialize a reference to an unsaved node",
+            )),
+            NodeRef::Stored(ptr) => ptr.serialize(serializer),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, N> serde::Deserialize<'de> for NodeRef<N> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(NodeRef::Stored(Ptr::deserialize(deserializer)?))
+    }
+}
+
+impl<N> NodeRef<N> {
+    pub fn ptr(&self) -> Option<Ptr> {
+        match self {
+            NodeRef::Mem(_) => None,
+            NodeRef::Stored(ptr) => Some(*ptr),
+        }
+    }
+
+    /// Whether both references point at the same node. Nodes are immutable,
+    /// so this implies that the subtrees below them are equal.
+    pub fn same_node(&self, other: &NodeRef<N>) -> bool {
+        match (self, other) {
+            (NodeRef::Mem(a), NodeRef::Mem(b)) => Arc::ptr_eq(a, b),
+            (NodeRef::Stored(a), NodeRef::Stored(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// A loaded node. Handles are reference counted rather than borrowed from
+/// the cache, so they are `Send` and `'static` whenever the node is and can be
+/// held across `.await` points and passed between threads.
+pub struct NodeHandle<N>(Arc<N>);
+
+impl<N> Clone for NodeHandle<N> {
+    fn clone(&self) -> Self {
+        NodeHandle(self.0.clone())
+    }
+}
+
+impl<N> Deref for NodeHandle<N> {
+    type Target = N;
+
+    fn deref(&self) -> &N {
+        &self.0
+    }
+}
+
+impl<N: fmt::Debug> fmt::Debug for NodeHandle<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<N> NodeHandle<N> {
+    /// Returns a new reference to the shared node.
+    pub fn to_arc(&self) -> Arc<N> {
+        self.0.clone()
+    }
+
+    pub fn into_arc(self) -> Arc<N> {
+        self.0
+    }
+
+    /// Returns the node, cloning it only if it is shared.
+    pub fn into_owned(self) -> N
+    where
+        N: Clone,
+    {
+        Arc::unwrap_or_clone(self.0)
+    }
+
+    /// Returns a clone of the node.
+    pub fn clone_inner(&self) -> N
+    where
+        N: Clone,
+    {
+        (*self.0).clone()
+    }
+
+    /// Narrows the handle to a part of the node, such as its key or value,
+    /// while keeping the node alive.
+    pub fn map<T: ?Sized>(self, project: fn(&N) -> &T) -> MappedNodeHandle<N, T> {
+        MappedNodeHandle {
+            node: self.0,
+            project,
+        }
+    }
+}
+
+/// A handle to a part of a loaded node, created by [`NodeHandle::map`].
+pub struct MappedNodeHandle<N, T: ?Sized> {
+    node: Arc<N>,
+    project: fn(&N) -> &T,
+}
+
+impl<N, T: ?Sized> Deref for MappedNodeHandle<N, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        (self.project)(&self.node)
+    }
+}
+
+impl<N, T: ?Sized> Clone for MappedNodeHandle<N, T> {
+    fn clone(&self) -> Self {
+        MappedNodeHandle {
+            node: self.node.clone(),
+            project: self.project,
+        }
+    }
+}
+
+impl<N, T: ?Sized> MappedNodeHandle<N, T> {
+    /// Returns the node this handle points into.
+    pub fn node(&self) -> NodeHandle<N> {
+        NodeHandle(self.node.clone())
+    }
+}
+
+const DEFAULT_CACHE_CAPACITY: usize = 10_000;
+
+/// How a [`NodeManager`] caches stored nodes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CachePolicy {
+    /// Keep up to `capacity` of the most recently used nodes.
+    Lru { capacity: NonZeroUsize },
+    /// Read every stored node from the store.
+    Disabled,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        CachePolicy::Lru {
+            capacity: NonZeroUsize::new(DEFAULT_CACHE_CAPACITY).expect("capacity is non-zero"),
+        }
+    }
+}
+
+/// What a [`NodeManager`] does when it finds the invariants of its trees
+/// broken, e.g. a missing child, a node of the wrong height or a hash which
+/// doesn't match. Consensus-critical services may prefer halting over
+/// serving corrupt data. Either way the error is first passed to the hook
+/// set by [`NodeManagerBuilder::on_corruption`], if any.
+///
+/// A missing root isn't corruption, since it may belong to a version which
+/// was released, so it is returned as [`Error::NotFound`] like any other
+/// error.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum CorruptionPolicy {
+    /// Returns the error like any other.
+    #[default]
+    Error,
+    /// Aborts the process.
+    Abort,
+}
+
+/// Called with the errors reporting corrupt state, see
+/// [`NodeManagerBuilder::on_corruption`].
+type CorruptionHook = Arc<dyn Fn(&Error) + Send + Sync>;
+
+/// The order in which saving a tree writes its new nodes to the store, and
+/// thus lays them out in stores which append, such as
+/// [`FileNodeStore`]. A node's pointer is part of its parent, so children
+/// are always written before their parents.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum WriteOrder {
+    /// Each subtree is written depth-first, left before right, so the
+    /// nodes of a subtree are contiguous and a range scan reads mostly
+    /// adjacent nodes.
+    #[default]
+    PostOrder,
+    /// The nodes are written one level at a time, the deepest first and
+    /// left to right within a level, so the upper levels, which every
+    /// lookup reads, end up together at the end of the write.
+    LevelOrder,
+}
+
+/// Counts of the operations a [`NodeManager`] performed, if it was built
+/// with [`NodeManagerBuilder::metrics`].
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct NodeManagerStats {
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub inserts: u64,
+    pub ref_count_updates: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    inserts: AtomicU64,
+    ref_count_updates: AtomicU64,
+}
+
+/// Reads and writes the nodes of trees through a [`NodeStore`], caching the
+/// most recently used stored nodes.
+pub struct NodeManager<N> {
+    store: Box<dyn NodeStore<N>>,
+    config: TreeConfig,
+    cache: Option<NodeCache<N>>,
+    counters: Option<Counters>,
+    read_only: bool,
+    observer: Option<Arc<dyn StoreObserver<N>>>,
+    metrics: Option<Arc<dyn NodeManagerMetrics>>,
+    value_hashes: Option<ValueHashMemo>,
+    corruption_policy: CorruptionPolicy,
+    on_corruption: Option<CorruptionHook>,
+    absent_keys: Option<AbsentKeys>,
+    write_order: WriteOrder,
+    /// The roots of recorded versions, with the number of versions recording
+    /// each, which in-place updates must leave alone.
+    version_roots: Mutex<HashMap<Ptr, usize>>,
+}
+
+impl<N> fmt::Debug for NodeManager<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NodeManager")
+            .field("config", &self.config)
+            .field("read_only", &self.read_only)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Configures a [`NodeManager`], created by [`NodeManager::builder`].
+pub struct NodeManagerBuilder<N> {
+    store: Box<dyn NodeStore<N>>,
+    config: TreeConfig,
+    read_only: bool,
+    observer: Option<Arc<dyn StoreObserver<N>>>,
+    metrics: Option<Arc<dyn NodeManagerMetrics>>,
+    corruption_policy: CorruptionPolicy,
+    on_corruption: Option<CorruptionHook>,
+    shared_cache: Option<NodeCache<N>>,
+    absent_keys: Option<NonZeroUsize>,
+    write_order: WriteOrder,
+}
+
+impl<N> NodeManagerBuilder<N> {
+    /// Replaces every setting of [`TreeConfig`] at once.
+    pub fn config(mut self, config: TreeConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets how stored nodes are cached. Defaults to an LRU cache of 10,000
+    /// nodes.
+    pub fn cache_policy(mut self, cache_policy: CachePolicy) -> Self {
+        self.config = self.config.cache_policy(cache_policy);
+        self
+    }
+
+    /// Caches stored nodes in `cache`, which other managers may share,
+    /// instead of a cache of the manager's own. Overrides the cache policy.
+    pub fn shared_cache(mut self, cache: SharedCache) -> Self
+    where
+        N: Send + Sync + 'static,
+    {
+        self.shared_cache = Some(NodeCache::shared(cache));
+        self
+    }
+
+    /// Enables counting operations, reported by [`NodeManager::stats`].
+    pub fn metrics(mut self, metrics: bool) -> Self {
+        self.config = self.config.metrics(metrics);
+        self
+    }
+
+    /// Rejects every write to the store, for serving saved versions.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Reports every write to the store to `observer`.
+    pub fn observer(mut self, observer: Arc<dyn StoreObserver<N>>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Reports reads, cache lookups and writes to `metrics`, e.g. to export
+    /// them to a monitoring system. Unlike [`NodeManagerBuilder::metrics`],
+    /// the manager keeps no counts of its own for them.
+    pub fn metrics_hooks(mut self, metrics: Arc<dyn NodeManagerMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Sets what happens when corrupt state is found. Defaults to
+    /// [`CorruptionPolicy::Error`].
+    pub fn corruption_policy(mut self, corruption_policy: CorruptionPolicy) -> Self {
+        self.corruption_policy = corruption_policy;
+        self
+    }
+
+    /// Calls `hook` with every error reporting corrupt state before the
+    /// [`CorruptionPolicy`] is applied, e.g. to log it or raise an alert.
+    pub fn on_corruption(mut self, hook: impl Fn(&Error) + Send + Sync + 'static) -> Self {
+        self.on_corruption = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets the order in which saves write new nodes. Defaults to
+    /// [`WriteOrder::PostOrder`]. The order doesn't change the trees or
+    /// their hashes, so it can differ between managers of one store.
+    pub fn write_order(mut self, write_order: WriteOrder) -> Self {
+        self.write_order = write_order;
+        self
+    }
+
+    /// Sets the layout used to hash the nodes created through the manager.
+    /// Defaults to the latest [`HashVersion`]; it must match the version
+    /// the stored nodes were created with for trees to keep a consistent
+    /// hash.
+    pub fn hash_version(mut self, hash_version: HashVersion) -> Self {
+        self.config = self.config.hash_version(hash_version);
+        self
+    }
+
+    /// Sets the proof encoding trees serve, see [`ProofFormat`].
+    pub fn proof_format(mut self, proof_format: ProofFormat) -> Self {
+        self.config = self.config.proof_format(proof_format);
+        self
+    }
+
+    /// Remembers the hashes of up to `capacity` distinct values encoded in
+    /// at least `min_len` bytes, see [`ValueHashMemo`]. Worth enabling when
+    /// many keys share a few large values. Disabled by default.
+    pub fn memoize_value_hashes(mut self, capacity: NonZeroUsize, min_len: usize) -> Self {
+        self.config = self.config.memoize_value_hashes(capacity, min_len);
+        self
+    }
+
+    /// Remembers up to `capacity` keys which lookups through
+    /// [`Tree::get_cached`](crate::tree::avl::Tree::get_cached) found absent
+    /// from saved versions, so that looking them up again skips the
+    /// traversal, e.g. for deduplication checks of keys which are mostly
+    /// new. Every write through the manager forgets them. Disabled by
+    /// default.
+    pub fn negative_lookup_cache(mut self, capacity: NonZeroUsize) -> Self {
+        self.absent_keys = Some(capacity);
+        self
+    }
+
+    /// Creates the manager without checking the settings against the
+    /// store, see [`NodeManagerBuilder::open`].
+    pub fn build(self) -> NodeManager<N> {
+        let config = self.config;
+        NodeManager {
+            store: self.store,
+            config,
+            cache: match (self.shared_cache, config.cache_policy) {
+                (Some(cache), _) => Some(cache),
+                (None, CachePolicy::Lru { capacity }) => Some(NodeCache::own(capacity)),
+                (None, CachePolicy::Disabled) => None,
+            },
+            counters: config.metrics.then(Counters::default),
+            read_only: self.read_only,
+            observer: self.observer,
+            metrics: self.metrics,
+            value_hashes: config
+                .value_hashes
+                .map(|(capacity, min_len)| ValueHashMemo::new(capacity, min_len)),
+            corruption_policy: self.corruption_policy,
+            on_corruption: self.on_corruption,
+            absent_keys: self.absent_keys.map(AbsentKeys::new),
+            write_order: self.write_order,
+            version_roots: Mutex::default(),
+        }
+    }
+
+    /// Validates the settings and creates the manager, persisting the
+    /// settings in the header of a store which has none yet. A store whose
+    /// header holds different settings is rejected, so its trees are never
+    /// read with settings they weren't written with; use
+    /// [`NodeManager::reopen`] to take the settings from the store instead.
+    pub fn open(self) -> Result<NodeManager<N>> {
+        self.config.validate()?;
+        match TreeConfig::load(&*self.store)? {
+            Some(stored) if stored != self.config => {
+                return Err(Error::invalid(format!(
+                    "store was written with {:?}, not {:?}",
+                    stored, self.config
+                )))
+            }
+            Some(_) => {}
+            // Read-only managers never write, so the settings are persisted
+            // by the first writable one.
+            None if self.read_only => {}
+            None => self.store.set_header(&self.config.encode())?,
+        }
+        Ok(self.build())
+    }
+}
+
+impl<N> NodeManager<N> {
+    /// Creates a writable manager with the default cache policy.
+    pub fn new(store: impl NodeStore<N> + 'static) -> Self {
+        NodeManager::builder(store).build()
+    }
+
+    pub fn builder(store: impl NodeStore<N> + 'static) -> NodeManagerBuilder<N> {
+        NodeManagerBuilder {
+            store: Box::new(store),
+            config: TreeConfig::default(),
+            read_only: false,
+            observer: None,
+            metrics: None,
+            corruption_policy: CorruptionPolicy::default(),
+            on_corruption: None,
+            shared_cache: None,
+            absent_keys: None,
+            write_order: WriteOrder::default(),
+        }
+    }
+
+    /// Starts configuring a manager with the settings persisted in the
+    /// header of `store` by [`NodeManagerBuilder::open`]. Fails if the store
+    /// has none.
+    pub fn reopen(store: impl NodeStore<N> + 'static) -> Result<NodeManagerBuilder<N>> {
+        let Some(config) = TreeConfig::load(&store)? else {
+            return Err(Error::invalid("store has no tree config"));
+        };
+        Ok(NodeManager::builder(store).config(config))
+    }
+
+    /// A manager whose saved nodes live in a [`MemNodeStore`], so trees
+    /// can be saved and versioned without a durable store.
+    pub fn in_memory() -> Self
+    where
+        N: Clone + Send + Sync + 'static,
+    {
+        NodeManager::new(MemNodeStore::new())
+    }
+
+    /// A manager for trees which are never saved. Saving or loading a tree
+    /// through it fails.
+    pub fn memory_only() -> Self {
+        NodeManager::new(NullNodeStore)
+    }
+
+    pub fn store(&self) -> &dyn NodeStore<N> {
+        &*self.store
+    }
+
+    pub fn config(&self) -> &TreeConfig {
+        &self.config
+    }
+
+    pub fn hash_version(&self) -> HashVersion {
+        self.config.hash_version
+    }
+
+    pub fn proof_format(&self) -> ProofFormat {
+        self.config.proof_format
+    }
+
+    /// Hashes a value of a node, through the memo set with
+    /// [`NodeManagerBuilder::memoize_value_hashes`] if there is one.
+    pub fn value_hash<T: Hashable + ?Sized>(&self, value: &T) -> Digest {
+        match &self.value_hashes {
+            Some(memo) => memo.hash_of(value),
+            None => hash_of(value),
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn corruption_policy(&self) -> CorruptionPolicy {
+        self.corruption_policy
+    }
+
+    pub fn write_order(&self) -> WriteOrder {
+        self.write_order
+    }
+
+    /// Passes `err`, which reports corrupt state, to the
+    /// [`NodeManagerBuilder::on_corruption`] hook and handles it according
+    /// to the [`CorruptionPolicy`], returning it.
+    pub fn corrupted(&self, err: Error) -> Error {
+        if let Some(hook) = &self.on_corruption {
+            hook(&err);
+        }
+        if self.corruption_policy == CorruptionPolicy::Abort {
+            std::process::abort();
+        }
+        err
+    }
+
+    /// Reads a node linked from another node. Unlike a missing root, a
+    /// missing child means the stored trees are corrupt, so it is handled
+    /// through [`NodeManager::corrupted`].
+    pub fn read_child(&self, child: &NodeRef<N>) -> Result<NodeHandle<N>> {
+        self.in_child(child, self.read(child))
+    }
+
+    /// Handles `result`, of an operation descending into `child`, failing
+    /// because `child` is missing like [`NodeManager::read_child`].
+    pub(crate) fn in_child<T>(&self, child: &NodeRef<N>, result: Result<T>) -> Result<T> {
+        match result {
+            Err(Error::NotFound(ptr)) if child.ptr() == Some(ptr) => Err(self.corrupted(
+                Error::corruption(format!("child node {:?} is missing", ptr)),
+            )),
+            result => result,
+        }
+    }
+
+    /// Passes store errors which report corruption through
+    /// [`NodeManager::corrupted`].
+    fn checked<T>(&self, result: Result<T>) -> Result<T> {
+        result.map_err(|err| {
+            if err.is_corruption() {
+                self.corrupted(err)
+            } else {
+                err
+            }
+        })
+    }
+
+    /// Returns the operation counts, or `None` if metrics are disabled.
+    pub fn stats(&self) -> Option<NodeManagerStats> {
+        self.counters.as_ref().map(|counters| NodeManagerStats {
+            cache_hits: counters.cache_hits.load(Relaxed),
+            cache_misses: counters.cache_misses.load(Relaxed),
+            inserts: counters.inserts.load(Relaxed),
+            ref_count_updates: counters.ref_count_updates.load(Relaxed),
+        })
+    }
+
+    pub fn read(&self, node: &NodeRef<N>) -> Result<NodeHandle<N>> {
+        let ptr = match node {
+            NodeRef::Mem(node) => return Ok(NodeHandle(node.clone())),
+            NodeRef::Stored(ptr) => ptr,
+        };
+        if let Some(cache) = &self.cache {
+            if let Some(node) = cache.get(ptr)? {
+                self.count(|counters| &counters.cache_hits);
+                self.report(|metrics| metrics.cache_hit());
+                return Ok(NodeHandle(node));
+            }
+        }
+        self.count(|counters| &counters.cache_misses);
+        self.report(|metrics| metrics.cache_miss());
+        let node = Arc::new(self.checked(self.read_store(1, || self.store.read(ptr)))?);
+        if let Some(cache) = &self.cache {
+            cache.put(*ptr, node.clone())?;
+        }
+        Ok(NodeHandle(node))
+    }
+
+    /// Reads several nodes like [`NodeManager::read`], fetching those which
+    /// aren't cached with a single [`NodeStore::read_many`].
+    pub fn read_many(&self, nodes: &[NodeRef<N>]) -> Result<Vec<NodeHandle<N>>> {
+        let mut handles = Vec::with_capacity(nodes.len());
+        let mut missing = Vec::new();
+        for (i, node) in nodes.iter().enumerate() {
+            let ptr = match node {
+                NodeRef::Mem(node) => {
+                    handles.push(Some(NodeHandle(node.clone())));
+                    continue;
+                }
+                NodeRef::Stored(ptr) => ptr,
+            };
+            if let Some(cache) = &self.cache {
+                if let Some(node) = cache.get(ptr)? {
+                    self.count(|counters| &counters.cache_hits);
+                    self.report(|metrics| metrics.cache_hit());
+                    handles.push(Some(NodeHandle(node)));
+                    continue;
+                }
+            }
+            self.count(|counters| &counters.cache_misses);
+            self.report(|metrics| metrics.cache_miss());
+            handles.push(None);
+            missing.push((i, *ptr));
+        }
+        if !missing.is_empty() {
+            let ptrs: Vec<Ptr> = missing.iter().map(|(_, ptr)| *ptr).collect();
+            let read = self.checked(self.read_store(ptrs.len(), || self.store.read_many(&ptrs)))?;
+            if read.len() != ptrs.len() {
+                return Err(Error::corruption(format!(
+                    "node store returned {} of {} nodes",
+                    read.len(),
+                    ptrs.len()
+                )));
+            }
+            for ((i, ptr), node) in missing.into_iter().zip(read) {
+                let node = Arc::new(node);
+                if let Some(cache) = &self.cache {
+                    cache.put(ptr, node.clone())?;
+                }
+                handles[i] = Some(NodeHandle(node));
+            }
+        }
+        Ok(handles
+            .into_iter()
+            .map(|handle| handle.expect("every node is read"))
+            .collect())
+    }
+
+    /// Reads a stored node without changing the cache or the statistics,
+    /// along with whether the node is cached, e.g. to inspect a tree
+    /// without disturbing which of its nodes stay cached.
+    pub(crate) fn peek(&self, ptr: &Ptr) -> Result<(NodeHandle<N>, bool)> {
+        if let Some(cache) = &self.cache {
+            if let Some(node) = cache.peek(ptr)? {
+                return Ok((NodeHandle(node), true));
+            }
+        }
+        let node = Arc::new(self.checked(self.store.read(ptr))?);
+        Ok((NodeHandle(node), false))
+    }
+
+    /// Persists a node, returning its pointer. The node is cached since it is
+    /// likely to be read again soon.
+    pub fn insert(&self, node: N) -> Result<Ptr> {
+        self.check_writable()?;
+        let ptr = self.write_store(|| self.store.insert(&node))?;
+        self.report_written(&[ptr]);
+        self.forget_absent_keys()?;
+        self.count(|counters| &counters.inserts);
+        let node = Arc::new(node);
+        if let Some(cache) = &self.cache {
+            cache.put(ptr, node.clone())?;
+        }
+        self.notify(|| StoreEvent::Inserted { ptr, node });
+        Ok(ptr)
+    }
+
+    /// Takes another reference on a stored node.
+    pub fn inc_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        self.check_writable()?;
+        self.count(|counters| &counters.ref_count_updates);
+        self.report(|metrics| metrics.ref_count_updated(true));
+        let count = self.write_store(|| self.store.inc_ref_count(ptr))?;
+        self.notify(|| StoreEvent::RefCountIncremented { ptr: *ptr });
+        Ok(count)
+    }
+
+    /// Releases a reference on a stored node.
+    pub fn dec_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        self.check_writable()?;
+        self.count(|counters| &counters.ref_count_updates);
+        self.report(|metrics| metrics.ref_count_updated(false));
+        let count = self.checked(self.write_store(|| self.store.dec_ref_count(ptr)))?;
+        self.notify(|| StoreEvent::RefCountDecremented { ptr: *ptr });
+        Ok(count)
+    }
+
+    /// Replaces stored nodes in place if the store supports it and no other
+    /// version references them, see [`NodeStore::try_update`].
+    pub fn try_update(&self, nodes: Vec<(Ptr, N)>) -> Result<bool> {
+        self.check_writable()?;
+        if !self.write_store(|| self.store.try_update(&nodes))? {
+            return Ok(false);
+        }
+        let ptrs: Vec<Ptr> = nodes.iter().map(|(ptr, _)| *ptr).collect();
+        self.report_written(&ptrs);
+        self.forget_absent_keys()?;
+        let nodes: Vec<_> = nodes
+            .into_iter()
+            .map(|(ptr, node)| (ptr, Arc::new(node)))
+            .collect();
+        if let Some(cache) = &self.cache {
+            for (ptr, node) in &nodes {
+                cache.put(*ptr, node.clone())?;
+            }
+        }
+        self.notify(|| StoreEvent::Updated { nodes });
+        Ok(true)
+    }
+
+    /// Removes a node whose reference count dropped to 0, returning it.
+    /// [`NodeManager::release`] does this for every node it frees.
+    pub fn delete(&self, ptr: &Ptr) -> Result<Arc<N>> {
+        self.check_writable()?;
+        let cached = match &self.cache {
+            Some(cache) => cache.pop(ptr)?,
+            None => None,
+        };
+        let node = match cached {
+            Some(node) => node,
+            None => Arc::new(self.store.read(ptr)?),
+        };
+        self.write_store(|| self.store.delete(ptr))?;
+        self.forget_absent_keys()?;
+        self.notify(|| StoreEvent::Deleted { ptr: *ptr });
+        Ok(node)
+    }
+
+    /// Returns the metadata the store recorded for a stored node, see
+    /// [`NodeStore::meta`].
+    pub fn meta(&self, ptr: &Ptr) -> Result<Option<NodeMeta>> {
+        self.store.meta(ptr)
+    }
+
+    /// Returns the reference count of a stored node, if the store can tell
+    /// it, see [`NodeStore::ref_count`].
+    pub fn ref_count(&self, ptr: &Ptr) -> Result<Option<u64>> {
+        self.store.ref_count(ptr)
+    }
+
+    /// Whether a key hashing to `key_hash` was found absent from the saved
+    /// version with root `root` since the last write, see
+    /// [`NodeManagerBuilder::negative_lookup_cache`].
+    pub(crate) fn is_known_absent(&self, root: &Ptr, key_hash: &Digest) -> Result<bool> {
+        match &self.absent_keys {
+            Some(absent_keys) => absent_keys.contains(root, key_hash),
+            None => Ok(false),
+        }
+    }
+
+    /// Remembers that a key hashing to `key_hash` is absent from the saved
+    /// version with root `root`, if the manager has a negative lookup cache.
+    pub(crate) fn record_absent(&self, root: Ptr, key_hash: Digest) -> Result<()> {
+        match &self.absent_keys {
+            Some(absent_keys) => absent_keys.insert(root, key_hash),
+            None => Ok(()),
+        }
+    }
+
+    /// Records that a version with root `root` was recorded, so that its
+    /// nodes aren't rewritten in place until
+    /// [`NodeManager::forget_version_root`] is called as many times.
+    pub(crate) fn record_version_root(&self, root: Ptr) -> Result<()> {
+        *self.lock_version_roots()?.entry(root).or_default() += 1;
+        Ok(())
+    }
+
+    /// Undoes one [`NodeManager::record_version_root`] of `root`, once the
+    /// version is discarded.
+    pub(crate) fn forget_version_root(&self, root: &Ptr) -> Result<()> {
+        let mut roots = self.lock_version_roots()?;
+        if let Some(count) = roots.get_mut(root) {
+            *count -= 1;
+            if *count == 0 {
+                roots.remove(root);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `root` is the root of a recorded version.
+    pub(crate) fn is_version_root(&self, root: &Ptr) -> Result<bool> {
+        Ok(self.lock_version_roots()?.contains_key(root))
+    }
+
+    fn lock_version_roots(&self) -> Result<MutexGuard<'_, HashMap<Ptr, usize>>> {
+        self.version_roots
+            .lock()
+            .map_err(|_| Error::poisoned("version roots"))
+    }
+
+    pub(crate) fn caches_absent_keys(&self) -> bool {
+        self.absent_keys.is_some()
+    }
+
+    fn forget_absent_keys(&self) -> Result<()> {
+        match &self.absent_keys {
+            Some(absent_keys) => absent_keys.clear(),
+            None => Ok(()),
+        }
+    }
+
+    /// Starts a batch of writes which reach the store together when it is
+    /// committed, see [`NodeStore::begin_batch`].
+    pub fn batch(&self) -> Result<Batch<'_, N>> {
+        self.check_writable()?;
+        Ok(Batch {
+            manager: self,
+            batch: self.store.begin_batch()?,
+            events: Vec::new(),
+        })
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        Ok(())
+    }
+
+    fn notify(&self, event: impl FnOnce() -> StoreEvent<N>) {
+        if let Some(observer) = &self.observer {
+            observer.on_store_event(&event());
+        }
+    }
+
+    fn count(&self, counter: impl FnOnce(&Counters) -> &AtomicU64) {
+        if let Some(counters) = &self.counters {
+            counter(counters).fetch_add(1, Relaxed);
+        }
+    }
+
+    fn report(&self, hook: impl FnOnce(&dyn NodeManagerMetrics)) {
+        if let Some(metrics) = &self.metrics {
+            hook(&**metrics);
+        }
+    }
+
+    /// Runs `read`, which reads `nodes` nodes from the store, timing it for
+    /// the metrics hooks.
+    fn read_store<T>(&self, nodes: usize, read: impl FnOnce() -> Result<T>) -> Result<T> {
+        let (result, latency) = self.timed(read);
+        self.report(|metrics| metrics.store_read(nodes, latency));
+        result
+    }
+
+    /// Runs `write`, which writes to the store, timing it for the metrics
+    /// hooks.
+    fn write_store<T>(&self, write: impl FnOnce() -> Result<T>) -> Result<T> {
+        let (result, latency) = self.timed(write);
+        if result.is_ok() {
+            self.report(|metrics| metrics.store_write(latency));
+        }
+        result
+    }
+
+    /// Runs `f`, measuring how long it takes only if there are metrics
+    /// hooks to report it to.
+    fn timed<T>(&self, f: impl FnOnce() -> T) -> (T, Duration) {
+        if self.metrics.is_none() {
+            return (f(), Duration::ZERO);
+        }
+        let started = Instant::now();
+        let result = f();
+        (result, started.elapsed())
+    }
+
+    /// Reports the nodes at `ptrs` as written, with the sizes the store
+    /// records for them. The write already succeeded, so sizes which can't
+    /// be read are left out rather than failing it.
+    fn report_written(&self, ptrs: &[Ptr]) {
+        self.report(|metrics| {
+            let bytes = ptrs
+                .iter()
+                .filter_map(|ptr| self.store.meta(ptr).ok().flatten()?.size)
+                .sum();
+            metrics.nodes_written(ptrs.len(), bytes);
+        });
+    }
+}
+
+/// Writes through a [`NodeManager`] which are applied atomically, created by
+/// [`NodeManager::batch`]. Staged nodes are cached and reported to the
+/// observer only once the batch is committed.
+pub struct Batch<'a, N> {
+    manager: &'a NodeManager<N>,
+    batch: Box<dyn WriteBatch<N> + 'a>,
+    events: Vec<StoreEvent<N>>,
+}
+
+impl<N> Batch<'_, N> {
+    pub fn manager(&self) -> &NodeManager<N> {
+        self.manager
+    }
+
+    /// Stages a node, returning the pointer it will be stored under.
+    pub fn insert(&mut self, node: N) -> Result<Ptr> {
+        let ptr = self.batch.insert(&node)?;
+        self.events.push(StoreEvent::Inserted {
+            ptr,
+            node: Arc::new(node),
+        });
+        Ok(ptr)
+    }
+
+    /// Stages taking another reference on a stored node.
+    pub fn inc_ref_count(&mut self, ptr: &Ptr) -> Result<()> {
+        self.batch.inc_ref_count(ptr)?;
+        self.events
+            .push(StoreEvent::RefCountIncremented { ptr: *ptr });
+        Ok(())
+    }
+
+    /// Records the root of the version the batch saves, see
+    /// [`WriteBatch::set_root`].
+    pub fn set_root(&mut self, root: Option<Ptr>) {
+        self.batch.set_root(root);
+    }
+
+    /// Records the version which creates the nodes of the batch, see
+    /// [`WriteBatch::set_version`].
+    pub fn set_version(&mut self, version: u64) {
+        self.batch.set_version(version);
+    }
+
+    /// Applies every staged write to the store.
+    pub fn commit(self) -> Result<()> {
+        let manager = self.manager;
+        manager.write_store(|| self.batch.commit())?;
+        manager.forget_absent_keys()?;
+        let mut written = Vec::new();
+        for event in self.events {
+            match &event {
+                StoreEvent::Inserted { ptr, node } => {
+                    manager.count(|counters| &counters.inserts);
+                    written.push(*ptr);
+                    if let Some(cache) = &manager.cache {
+                        cache.put(*ptr, node.clone())?;
+                    }
+                }
+                _ => {
+                    manager.count(|counters| &counters.ref_count_updates);
+                    manager.report(|metrics| metrics.ref_count_updated(true));
+                }
+            }
+            manager.notify(|| event);
+        }
+        if !written.is_empty() {
+            manager.report_written(&written);
+        }
+        Ok(())
+    }
+}
+
+impl<N: TreeNode> NodeManager<N> {
+    /// Releases the reference a saved version holds on its root `root`. Nodes
+    /// whose reference count drops to 0 are deleted, releasing their own
+    /// references on their children in turn, so nodes shared with other
+    /// versions are kept. Returns the number of deleted nodes.
+    pub fn release(&self, root: &Ptr) -> Result<usize> {
+        let mut deleted = 0;
+        let mut pending = vec![*root];
+        while let Some(ptr) = pending.pop() {
+            if self.dec_ref_count(&ptr)? > 0 {
+                continue;
+            }
+            pending.extend(self.delete(&ptr)?.stored_children());
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+}
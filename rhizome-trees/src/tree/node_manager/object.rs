@@ -0,0 +1,556 @@
+//! A store keeping nodes in object storage such as S3 or GCS, so large
+//! trees can live in cheap cloud storage.
+//!
+//! [`ObjectNodeStore`] stores encoded nodes through the
+//! [object_store](https://docs.rs/object_store) crate, whose backends are
+//! enabled with its own features, e.g. `aws` or `gcp`. Wrapping it in an
+//! [`EncodedStore`](super::EncodedStore) gives trees of any node type access
+//! to the stored nodes. Under the store's prefix it writes:
+//!
+//! - `nodes/<hash>`, the encoding of each node, named after its SHA-256
+//!   hash, which is also its pointer,
+//! - `refs/<hash>`, the reference count of each node together with its
+//!   [`NodeMeta`],
+//! - `header`, the header of the store.
+//!
+//! As objects are named after their contents, nodes are never overwritten
+//! and reads check the bytes they get against the pointer, which lets a
+//! local directory cache them without ever going stale.
+//!
+//! Reference counts are updated by reading and rewriting their objects, so
+//! the store must have a single writer at a time. Any number of processes
+//! may read from it.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use object_store::path::Path;
+use object_store::{ObjectStore, PutPayload, WriteMultipart};
+use sha2::{Digest as _, Sha256};
+use tokio::runtime::Runtime;
+
+use super::store::{decrement, not_found, NodeMeta, NodeStore, Ptr, WriteBatch};
+use crate::tree::hash::Digest;
+use crate::{Error, Result};
+
+/// The smallest part size S3 accepts for multipart uploads.
+const DEFAULT_PART_SIZE: usize = 5 * 1024 * 1024;
+
+const DEFAULT_CONCURRENCY: usize = 16;
+
+/// Names the temporary files the cache writes before renaming them.
+static CACHE_WRITES: AtomicU64 = AtomicU64::new(0);
+
+/// A store whose nodes are objects in an [`ObjectStore`], named after the
+/// hashes of their encodings.
+///
+/// Inserting a node equal to one already stored takes another reference on
+/// the stored object. Unlike
+/// [`ContentAddressedStore`](super::ContentAddressedStore), the store can't
+/// decode the node to return the references the duplicate took on its
+/// children, so those children outlive their last tree version rather than
+/// being deleted with it.
+///
+/// [`NodeStore::begin_batch`] stages the nodes a save writes and uploads
+/// them concurrently on commit, with encodings larger than the part size
+/// sent as multipart uploads. Nodes are uploaded before any reference count
+/// is written, so a commit which fails while uploading leaves the reference
+/// counts as they were, but one which fails while writing them may leave
+/// some of them updated.
+///
+/// The store runs its requests on a runtime of its own and blocks the
+/// calling thread until they complete, like the other stores do on I/O. It
+/// must not be called from within an async task, which should use
+/// `tokio::task::spawn_blocking` instead.
+pub struct ObjectNodeStore {
+    runtime: Runtime,
+    objects: Arc<dyn ObjectStore>,
+    prefix: Path,
+    cache_dir: Option<PathBuf>,
+    concurrency: usize,
+    part_size: usize,
+}
+
+/// Configures an [`ObjectNodeStore`], created by [`ObjectNodeStore::builder`].
+pub struct ObjectNodeStoreBuilder {
+    objects: Arc<dyn ObjectStore>,
+    prefix: Path,
+    cache_dir: Option<PathBuf>,
+    concurrency: usize,
+    part_size: usize,
+}
+
+impl ObjectNodeStoreBuilder {
+    /// Keeps the objects of the store under `prefix`, so several stores can
+    /// share a bucket. Defaults to the root of the object store.
+    pub fn prefix(mut self, prefix: impl Into<Path>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Caches the nodes read from or written to the object store as files
+    /// in `dir`, which is created if it doesn't exist. Nodes aren't cached
+    /// locally by default.
+    pub fn cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets how many requests reads and commits make at once, 16 by default.
+    pub fn concurrency(mut self, requests: usize) -> Self {
+        self.concurrency = requests;
+        self
+    }
+
+    /// Sets the size of the parts of multipart uploads, 5 MiB by default.
+    /// Encodings no larger than a part are uploaded in a single request.
+    pub fn part_size(mut self, bytes: usize) -> Self {
+        self.part_size = bytes;
+        self
+    }
+
+    pub fn build(self) -> Result<ObjectNodeStore> {
+        if self.concurrency == 0 || self.part_size == 0 {
+            return Err(Error::invalid(
+                "object store concurrency and part size must be positive",
+            ));
+        }
+        if let Some(dir) = &self.cache_dir {
+            fs::create_dir_all(dir)
+                .map_err(|err| Error::io(format!("creating node cache {}", dir.display()), err))?;
+        }
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("rhizome-object-store")
+            .enable_all()
+            .build()
+            .map_err(|err| Error::io("starting the object node store runtime", err))?;
+        Ok(ObjectNodeStore {
+            runtime,
+            objects: self.objects,
+            prefix: self.prefix,
+            cache_dir: self.cache_dir,
+            concurrency: self.concurrency,
+            part_size: self.part_size,
+        })
+    }
+}
+
+/// The reference count and metadata of a node, stored as its `refs` object.
+#[derive(Clone, Copy)]
+struct Entry {
+    count: u64,
+    meta: NodeMeta,
+}
+
+impl Entry {
+    fn new(size: usize, version: Option<u64>) -> Self {
+        Entry {
+            count: 1,
+            meta: NodeMeta {
+                version,
+                size: Some(size as u64),
+            },
+        }
+    }
+
+    /// Encodes the count, the size and the version if there is one, as
+    /// little-endian `u64`s.
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(24);
+        bytes.extend_from_slice(&self.count.to_le_bytes());
+        bytes.extend_from_slice(&self.meta.size.unwrap_or_default().to_le_bytes());
+        if let Some(version) = self.meta.version {
+            bytes.extend_from_slice(&version.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn from_bytes(ptr: &Ptr, bytes: &[u8]) -> Result<Self> {
+        let word = |i: usize| -> Option<u64> {
+            Some(u64::from_le_bytes(
+                bytes.get(i * 8..i * 8 + 8)?.try_into().ok()?,
+            ))
+        };
+        let (Some(count), Some(size)) = (word(0), word(1)) else {
+            return Err(Error::corrupt(*ptr));
+        };
+        let version = match bytes.len() {
+            16 => None,
+            24 => word(2),
+            _ => return Err(Error::corrupt(*ptr)),
+        };
+        Ok(Entry {
+            count,
+            meta: NodeMeta {
+                version,
+                size: Some(size),
+            },
+        })
+    }
+}
+
+fn digest(ptr: &Ptr) -> Result<Digest> {
+    ptr.as_bytes().try_into().map_err(|_| not_found(ptr))
+}
+
+fn sha256(bytes: &[u8]) -> Digest {
+    Sha256::digest(bytes).into()
+}
+
+fn hex(digest: &Digest) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Converts a failed request about the node at `ptr`.
+fn object_error(ptr: &Ptr, path: &Path, err: object_store::Error) -> Error {
+    match err {
+        object_store::Error::NotFound { .. } => not_found(ptr),
+        err => Error::storage(format!("object store request for {} failed", path), err),
+    }
+}
+
+impl ObjectNodeStore {
+    /// Stores nodes at the root of `objects`, without a local cache.
+    pub fn new(objects: Arc<dyn ObjectStore>) -> Result<Self> {
+        ObjectNodeStore::builder(objects).build()
+    }
+
+    pub fn builder(objects: Arc<dyn ObjectStore>) -> ObjectNodeStoreBuilder {
+        ObjectNodeStoreBuilder {
+            objects,
+            prefix: Path::default(),
+            cache_dir: None,
+            concurrency: DEFAULT_CONCURRENCY,
+            part_size: DEFAULT_PART_SIZE,
+        }
+    }
+
+    fn node_path(&self, digest: &Digest) -> Path {
+        self.prefix.child("nodes").child(hex(digest))
+    }
+
+    fn entry_path(&self, digest: &Digest) -> Path {
+        self.prefix.child("refs").child(hex(digest))
+    }
+
+    fn header_path(&self) -> Path {
+        self.prefix.child("header")
+    }
+
+    /// Returns the cached node, if it is cached and its bytes hash to
+    /// `digest`. A cached file which doesn't is fetched again.
+    fn cached(&self, digest: &Digest) -> Result<Option<Vec<u8>>> {
+        let Some(dir) = &self.cache_dir else {
+            return Ok(None);
+        };
+        let path = dir.join(hex(digest));
+        match fs::read(&path) {
+            Ok(bytes) if sha256(&bytes) == *digest => Ok(Some(bytes)),
+            Ok(_) => Ok(None),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::io(
+                format!("reading cached node {}", path.display()),
+                err,
+            )),
+        }
+    }
+
+    /// Writes a node to the cache, through a temporary file so concurrent
+    /// readers never see part of it.
+    fn cache(&self, digest: &Digest, bytes: &[u8]) -> Result<()> {
+        let Some(dir) = &self.cache_dir else {
+            return Ok(());
+        };
+        let path = dir.join(hex(digest));
+        let temp = dir.join(format!(
+            "{}.{}.tmp",
+            hex(digest),
+            CACHE_WRITES.fetch_add(1, Relaxed)
+        ));
+        fs::write(&temp, bytes)
+            .and_then(|()| fs::rename(&temp, &path))
+            .map_err(|err| Error::io(format!("caching node {}", path.display()), err))
+    }
+
+    fn uncache(&self, digest: &Digest) -> Result<()> {
+        let Some(dir) = &self.cache_dir else {
+            return Ok(());
+        };
+        let path = dir.join(hex(digest));
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(Error::io(
+                format!("removing cached node {}", path.display()),
+                err,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fetches a node and checks that it hashes to its pointer.
+    async fn fetch(&self, ptr: &Ptr) -> Result<Vec<u8>> {
+        let digest = digest(ptr)?;
+        let path = self.node_path(&digest);
+        let fetched = async { self.objects.get(&path).await?.bytes().await }.await;
+        let bytes = fetched.map_err(|err| object_error(ptr, &path, err))?;
+        if sha256(&bytes) != digest {
+            return Err(Error::corrupt(*ptr));
+        }
+        Ok(bytes.to_vec())
+    }
+
+    /// Uploads a node, in parts if it is larger than a part.
+    async fn upload(&self, ptr: &Ptr, bytes: &[u8]) -> Result<()> {
+        let path = self.node_path(&digest(ptr)?);
+        let uploaded = async {
+            if bytes.len() <= self.part_size {
+                self.objects
+                    .put(&path, PutPayload::from(bytes.to_vec()))
+                    .await?;
+            } else {
+                let upload = self.objects.put_multipart(&path).await?;
+                let mut writer = WriteMultipart::new_with_chunk_size(upload, self.part_size);
+                writer.write(bytes);
+                writer.finish().await?;
+            }
+            Ok(())
+        };
+        uploaded.await.map_err(|err| object_error(ptr, &path, err))
+    }
+
+    /// Fetches the entry of a node, `None` if the node isn't stored.
+    async fn entry(&self, ptr: &Ptr) -> Result<Option<Entry>> {
+        let path = self.entry_path(&digest(ptr)?);
+        match async { self.objects.get(&path).await?.bytes().await }.await {
+            Ok(bytes) => Entry::from_bytes(ptr, &bytes).map(Some),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(err) => Err(object_error(ptr, &path, err)),
+        }
+    }
+
+    async fn put_entry(&self, ptr: &Ptr, entry: Entry) -> Result<()> {
+        let path = self.entry_path(&digest(ptr)?);
+        self.objects
+            .put(&path, PutPayload::from(entry.to_bytes()))
+            .await
+            .map(drop)
+            .map_err(|err| object_error(ptr, &path, err))
+    }
+
+    /// Applies `update` to the reference count of a stored node.
+    fn update_ref_count(
+        &self,
+        ptr: &Ptr,
+        update: impl FnOnce(&mut u64) -> Result<u64>,
+    ) -> Result<u64> {
+        self.runtime.block_on(async {
+            let mut entry = self.entry(ptr).await?.ok_or_else(|| not_found(ptr))?;
+            let count = update(&mut entry.count)?;
+            self.put_entry(ptr, entry).await?;
+            Ok(count)
+        })
+    }
+}
+
+impl NodeStore<Vec<u8>> for ObjectNodeStore {
+    fn read(&self, ptr: &Ptr) -> Result<Vec<u8>> {
+        let mut nodes = self.read_many(std::slice::from_ref(ptr))?;
+        Ok(nodes.remove(0))
+    }
+
+    /// Reads the nodes which aren't cached concurrently.
+    fn read_many(&self, ptrs: &[Ptr]) -> Result<Vec<Vec<u8>>> {
+        let mut nodes = Vec::with_capacity(ptrs.len());
+        let mut missing = Vec::new();
+        for (i, ptr) in ptrs.iter().enumerate() {
+            match self.cached(&digest(ptr)?)? {
+                Some(bytes) => nodes.push(bytes),
+                None => {
+                    nodes.push(Vec::new());
+                    missing.push(i);
+                }
+            }
+        }
+        let fetched: Vec<Vec<u8>> = self.runtime.block_on(
+            stream::iter(&missing)
+                .map(|&i| self.fetch(&ptrs[i]))
+                .buffered(self.concurrency)
+                .try_collect(),
+        )?;
+        for (i, bytes) in missing.into_iter().zip(fetched) {
+            self.cache(&digest(&ptrs[i])?, &bytes)?;
+            nodes[i] = bytes;
+        }
+        Ok(nodes)
+    }
+
+    fn insert(&self, node: &Vec<u8>) -> Result<Ptr> {
+        let mut batch = self.begin_batch()?;
+        let ptr = batch.insert(node)?;
+        batch.commit()?;
+        Ok(ptr)
+    }
+
+    fn inc_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        self.update_ref_count(ptr, |count| {
+            *count += 1;
+            Ok(*count)
+        })
+    }
+
+    fn dec_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        self.update_ref_count(ptr, |count| decrement(ptr, count))
+    }
+
+    /// Deletes the node's objects and cached file.
+    fn delete(&self, ptr: &Ptr) -> Result<()> {
+        let digest = digest(ptr)?;
+        self.runtime.block_on(async {
+            for path in [self.node_path(&digest), self.entry_path(&digest)] {
+                match self.objects.delete(&path).await {
+                    Ok(()) | Err(object_store::Error::NotFound { .. }) => {}
+                    Err(err) => return Err(object_error(ptr, &path, err)),
+                }
+            }
+            Ok(())
+        })?;
+        self.uncache(&digest)
+    }
+
+    fn begin_batch<'a>(&'a self) -> Result<Box<dyn WriteBatch<Vec<u8>> + 'a>>
+    where
+        Vec<u8>: 'a,
+    {
+        Ok(Box::new(ObjectBatch {
+            store: self,
+            nodes: HashMap::new(),
+            increments: HashMap::new(),
+            version: None,
+        }))
+    }
+
+    fn header(&self) -> Result<Option<Vec<u8>>> {
+        let path = self.header_path();
+        self.runtime.block_on(async {
+            match async { self.objects.get(&path).await?.bytes().await }.await {
+                Ok(bytes) => Ok(Some(bytes.to_vec())),
+                Err(object_store::Error::NotFound { .. }) => Ok(None),
+                Err(err) => Err(Error::storage(format!("reading header {}", path), err)),
+            }
+        })
+    }
+
+    fn set_header(&self, header: &[u8]) -> Result<()> {
+        let path = self.header_path();
+        self.runtime
+            .block_on(self.objects.put(&path, PutPayload::from(header.to_vec())))
+            .map(drop)
+            .map_err(|err| Error::storage(format!("writing header {}", path), err))
+    }
+
+    fn meta(&self, ptr: &Ptr) -> Result<Option<NodeMeta>> {
+        let entry = self.runtime.block_on(self.entry(ptr))?;
+        Ok(Some(entry.ok_or_else(|| not_found(ptr))?.meta))
+    }
+
+    fn ref_count(&self, ptr: &Ptr) -> Result<Option<u64>> {
+        let entry = self.runtime.block_on(self.entry(ptr))?;
+        Ok(Some(entry.ok_or_else(|| not_found(ptr))?.count))
+    }
+}
+
+/// The nodes and increments staged for an [`ObjectNodeStore`], uploaded on
+/// commit.
+struct ObjectBatch<'a> {
+    store: &'a ObjectNodeStore,
+    /// The encoding of each staged node and the references taken on it.
+    nodes: HashMap<Digest, (Vec<u8>, u64)>,
+    /// The references taken on stored nodes.
+    increments: HashMap<Digest, u64>,
+    version: Option<u64>,
+}
+
+impl WriteBatch<Vec<u8>> for ObjectBatch<'_> {
+    fn insert(&mut self, node: &Vec<u8>) -> Result<Ptr> {
+        let digest = sha256(node);
+        self.nodes
+            .entry(digest)
+            .and_modify(|(_, count)| *count += 1)
+            .or_insert_with(|| (node.clone(), 1));
+        Ptr::new(&digest)
+    }
+
+    fn inc_ref_count(&mut self, ptr: &Ptr) -> Result<()> {
+        let digest = digest(ptr)?;
+        match self.nodes.get_mut(&digest) {
+            Some((_, count)) => *count += 1,
+            None => *self.increments.entry(digest).or_default() += 1,
+        }
+        Ok(())
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.version = Some(version);
+    }
+
+    /// Fetches the entries of every node the batch touches, uploads the
+    /// nodes which aren't stored yet and then writes the updated entries.
+    fn commit(self: Box<Self>) -> Result<()> {
+        let store = self.store;
+        let digests: Vec<&Digest> = self.nodes.keys().chain(self.increments.keys()).collect();
+        let ptrs = digests
+            .iter()
+            .map(|digest| Ptr::new(*digest))
+            .collect::<Result<Vec<_>>>()?;
+        store.runtime.block_on(async {
+            let entries: Vec<Option<Entry>> = stream::iter(&ptrs)
+                .map(|ptr| store.entry(ptr))
+                .buffered(store.concurrency)
+                .try_collect()
+                .await?;
+            let mut uploads = Vec::new();
+            let mut updated = Vec::with_capacity(digests.len());
+            for ((digest, ptr), entry) in digests.iter().zip(&ptrs).zip(entries) {
+                let entry = match (self.nodes.get(*digest), entry) {
+                    (Some((bytes, count)), None) => {
+                        uploads.push((ptr, bytes));
+                        Entry {
+                            count: *count,
+                            ..Entry::new(bytes.len(), self.version)
+                        }
+                    }
+                    (Some((_, count)), Some(entry)) => Entry {
+                        count: entry.count + count,
+                        ..entry
+                    },
+                    (None, Some(entry)) => Entry {
+                        count: entry.count + self.increments[*digest],
+                        ..entry
+                    },
+                    (None, None) => return Err(not_found(ptr)),
+                };
+                updated.push((ptr, entry));
+            }
+            stream::iter(&uploads)
+                .map(|(ptr, bytes)| store.upload(ptr, bytes))
+                .buffer_unordered(store.concurrency)
+                .try_collect::<()>()
+                .await?;
+            stream::iter(updated)
+                .map(|(ptr, entry)| store.put_entry(ptr, entry))
+                .buffer_unordered(store.concurrency)
+                .try_collect::<()>()
+                .await
+        })?;
+        for (digest, (bytes, _)) in &self.nodes {
+            store.cache(digest, bytes)?;
+        }
+        Ok(())
+    }
+}
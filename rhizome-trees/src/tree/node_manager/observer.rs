@@ -0,0 +1,62 @@
+//! Hooks for observing the writes a [`NodeManager`](super::NodeManager)
+//! makes to its store, e.g. to replicate them to follower stores.
+
+use std::sync::Arc;
+
+use super::store::Ptr;
+
+/// A write applied to a node store.
+#[derive(Debug)]
+pub enum StoreEvent<N> {
+    /// A new node was stored with a reference count of 1.
+    Inserted {
+        ptr: Ptr,
+        node: Arc<N>,
+    },
+    /// Nodes were rewritten in place, all at once, by
+    /// [`NodeStore::try_update`](super::NodeStore::try_update).
+    Updated {
+        nodes: Vec<(Ptr, Arc<N>)>,
+    },
+    RefCountIncremented {
+        ptr: Ptr,
+    },
+    RefCountDecremented {
+        ptr: Ptr,
+    },
+    /// A node was removed after its reference count dropped to 0.
+    Deleted {
+        ptr: Ptr,
+    },
+}
+
+impl<N> Clone for StoreEvent<N> {
+    fn clone(&self) -> Self {
+        match self {
+            StoreEvent::Inserted { ptr, node } => StoreEvent::Inserted {
+                ptr: *ptr,
+                node: node.clone(),
+            },
+            StoreEvent::Updated { nodes } => StoreEvent::Updated {
+                nodes: nodes.clone(),
+            },
+            StoreEvent::RefCountIncremented { ptr } => {
+                StoreEvent::RefCountIncremented { ptr: *ptr }
+            }
+            StoreEvent::RefCountDecremented { ptr } => {
+                StoreEvent::RefCountDecremented { ptr: *ptr }
+            }
+            StoreEvent::Deleted { ptr } => StoreEvent::Deleted { ptr: *ptr },
+        }
+    }
+}
+
+/// Receives every write a [`NodeManager`](super::NodeManager) makes, set with
+/// [`NodeManagerBuilder::observer`](super::NodeManagerBuilder::observer).
+///
+/// Events are delivered on the writing thread right after the store applied
+/// the write. A version's nodes are inserted children first, so a follower
+/// applying the events in order never sees a node before its children.
+pub trait StoreObserver<N>: Send + Sync {
+    fn on_store_event(&self, event: &StoreEvent<N>);
+}
@@ -0,0 +1,323 @@
+//! A node store in a [redb](https://docs.rs/redb) database, a pure-Rust
+//! embedded key-value store.
+//!
+//! Encoded nodes are kept in a table by `u64` id, which is the node's
+//! pointer, and their reference counts and the versions of the batches
+//! which wrote them in tables of their own. A metadata table holds the
+//! store's header and the next id to assign, so ids are never reused.
+//!
+//! Every write is a redb transaction, committed durably before the call
+//! returns, and a [`WriteBatch`] is a single transaction. redb allows one
+//! writer at a time, so writes wait while a batch is open, while reads see
+//! the last commit without waiting for writers.
+
+use std::path::Path;
+
+use redb::{
+    Database, ReadableTable, ReadableTableMetadata, StorageError, TableDefinition, WriteTransaction,
+};
+
+use super::store::{decrement, not_found, NodeMeta, NodeStore, Ptr, WriteBatch};
+use crate::{Error, Result};
+
+const NODES: TableDefinition<u64, &[u8]> = TableDefinition::new("nodes");
+const REF_COUNTS: TableDefinition<u64, u64> = TableDefinition::new("ref_counts");
+const VERSIONS: TableDefinition<u64, u64> = TableDefinition::new("versions");
+const META: TableDefinition<&str, &[u8]> = TableDefinition::new("meta");
+
+const HEADER: &str = "header";
+const NEXT_ID: &str = "next_id";
+
+/// A durable store which keeps nodes, already encoded as bytes, in a redb
+/// database. Pointers are `u64` ids.
+pub struct RedbNodeStore {
+    db: Database,
+}
+
+impl RedbNodeStore {
+    /// Opens the database at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let db = Database::create(path).map_err(|err| {
+            Error::storage(format!("opening node database {}", path.display()), err)
+        })?;
+        Self::from_database(db)
+    }
+
+    /// Keeps the store's tables in `db`, e.g. one opened through a
+    /// [`redb::Builder`] with a custom cache size.
+    pub fn from_database(db: Database) -> Result<Self> {
+        let txn = db.begin_write()?;
+        txn.open_table(NODES)?;
+        txn.open_table(REF_COUNTS)?;
+        txn.open_table(VERSIONS)?;
+        txn.open_table(META)?;
+        txn.commit()?;
+        Ok(RedbNodeStore { db })
+    }
+
+    /// Returns the number of stored nodes.
+    pub fn len(&self) -> usize {
+        self.db
+            .begin_read()
+            .ok()
+            .and_then(|txn| txn.open_table(REF_COUNTS).ok()?.len().ok())
+            .map_or(0, |len| len as usize)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Runs `f` in a write transaction, committing it if `f` succeeds.
+    fn write<T>(&self, f: impl FnOnce(&WriteTransaction) -> Result<T>) -> Result<T> {
+        let txn = self.db.begin_write()?;
+        let result = f(&txn)?;
+        txn.commit()?;
+        Ok(result)
+    }
+
+    fn update_ref_count(
+        &self,
+        ptr: &Ptr,
+        update: fn(&Ptr, &mut u64) -> Result<u64>,
+    ) -> Result<u64> {
+        let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        self.write(|txn| {
+            let mut ref_counts = txn.open_table(REF_COUNTS)?;
+            let mut count = ref_counts.get(id)?.ok_or_else(|| not_found(ptr))?.value();
+            update(ptr, &mut count)?;
+            ref_counts.insert(id, count)?;
+            Ok(count)
+        })
+    }
+}
+
+fn increment(_: &Ptr, count: &mut u64) -> Result<u64> {
+    *count += 1;
+    Ok(*count)
+}
+
+/// Reports damage redb detected in the pages holding a node as
+/// [`Error::Corrupt`].
+fn read_error(ptr: &Ptr, err: StorageError) -> Error {
+    match err {
+        StorageError::Corrupted(_) => Error::Corrupt {
+            ptr: *ptr,
+            source: Some(Box::new(err.into())),
+        },
+        err => err.into(),
+    }
+}
+
+/// Returns the id the next inserted node gets.
+fn next_id(txn: &WriteTransaction) -> Result<u64> {
+    let meta = txn.open_table(META)?;
+    let next = meta.get(NEXT_ID)?;
+    match next {
+        Some(bytes) => {
+            Ok(u64::from_le_bytes(bytes.value().try_into().map_err(
+                |_| Error::corruption("malformed next id in node database"),
+            )?))
+        }
+        None => Ok(0),
+    }
+}
+
+/// Assigns the next `count` ids, returning the first.
+fn allocate_ids(txn: &WriteTransaction, count: u64) -> Result<u64> {
+    let next = next_id(txn)?;
+    txn.open_table(META)?
+        .insert(NEXT_ID, (next + count).to_le_bytes().as_slice())?;
+    Ok(next)
+}
+
+impl NodeStore<Vec<u8>> for RedbNodeStore {
+    fn read(&self, ptr: &Ptr) -> Result<Vec<u8>> {
+        let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        let txn = self.db.begin_read()?;
+        let nodes = txn.open_table(NODES)?;
+        let node = nodes.get(id).map_err(|err| read_error(ptr, err))?;
+        Ok(node.ok_or_else(|| not_found(ptr))?.value().to_vec())
+    }
+
+    /// Reads the nodes in a single read transaction.
+    fn read_many(&self, ptrs: &[Ptr]) -> Result<Vec<Vec<u8>>> {
+        let txn = self.db.begin_read()?;
+        let nodes = txn.open_table(NODES)?;
+        ptrs.iter()
+            .map(|ptr| {
+                let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+                let node = nodes.get(id).map_err(|err| read_error(ptr, err))?;
+                Ok(node.ok_or_else(|| not_found(ptr))?.value().to_vec())
+            })
+            .collect()
+    }
+
+    fn insert(&self, node: &Vec<u8>) -> Result<Ptr> {
+        self.write(|txn| {
+            let id = allocate_ids(txn, 1)?;
+            txn.open_table(NODES)?.insert(id, node.as_slice())?;
+            txn.open_table(REF_COUNTS)?.insert(id, 1)?;
+            Ok(Ptr::from_u64(id))
+        })
+    }
+
+    fn inc_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        self.update_ref_count(ptr, increment)
+    }
+
+    fn dec_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        self.update_ref_count(ptr, decrement)
+    }
+
+    fn delete(&self, ptr: &Ptr) -> Result<()> {
+        let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        self.write(|txn| {
+            txn.open_table(REF_COUNTS)?
+                .remove(id)?
+                .ok_or_else(|| not_found(ptr))?;
+            txn.open_table(NODES)?.remove(id)?;
+            txn.open_table(VERSIONS)?.remove(id)?;
+            Ok(())
+        })
+    }
+
+    fn try_update(&self, nodes: &[(Ptr, Vec<u8>)]) -> Result<bool> {
+        let txn = self.db.begin_write()?;
+        {
+            let ref_counts = txn.open_table(REF_COUNTS)?;
+            let mut table = txn.open_table(NODES)?;
+            let mut ids = Vec::with_capacity(nodes.len());
+            for (ptr, _) in nodes {
+                let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+                let count = ref_counts.get(id)?.ok_or_else(|| not_found(ptr))?;
+                if count.value() != 1 {
+                    // Dropping the transaction aborts it.
+                    return Ok(false);
+                }
+                ids.push(id);
+            }
+            for (id, (_, node)) in ids.into_iter().zip(nodes) {
+                table.insert(id, node.as_slice())?;
+            }
+        }
+        txn.commit()?;
+        Ok(true)
+    }
+
+    fn header(&self) -> Result<Option<Vec<u8>>> {
+        let txn = self.db.begin_read()?;
+        let meta = txn.open_table(META)?;
+        let header = meta.get(HEADER)?;
+        Ok(header.map(|header| header.value().to_vec()))
+    }
+
+    fn set_header(&self, header: &[u8]) -> Result<()> {
+        self.write(|txn| {
+            txn.open_table(META)?.insert(HEADER, header)?;
+            Ok(())
+        })
+    }
+
+    fn begin_batch<'a>(&'a self) -> Result<Box<dyn WriteBatch<Vec<u8>> + 'a>>
+    where
+        Vec<u8>: 'a,
+    {
+        let txn = self.db.begin_write()?;
+        let next = next_id(&txn)?;
+        Ok(Box::new(RedbBatch {
+            txn,
+            next,
+            nodes: Vec::new(),
+            increments: Vec::new(),
+            version: None,
+        }))
+    }
+
+    /// Returns the version of the node, if it was written by a batch with
+    /// one, and the length of its encoding.
+    fn meta(&self, ptr: &Ptr) -> Result<Option<NodeMeta>> {
+        let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        let txn = self.db.begin_read()?;
+        let node = txn.open_table(NODES)?;
+        let node = node.get(id).map_err(|err| read_error(ptr, err))?;
+        let size = node.ok_or_else(|| not_found(ptr))?.value().len();
+        let version = txn.open_table(VERSIONS)?.get(id)?;
+        Ok(Some(NodeMeta {
+            version: version.map(|version| version.value()),
+            size: Some(size as u64),
+        }))
+    }
+
+    fn ref_count(&self, ptr: &Ptr) -> Result<Option<u64>> {
+        let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        let txn = self.db.begin_read()?;
+        let count = txn.open_table(REF_COUNTS)?.get(id)?;
+        Ok(Some(count.ok_or_else(|| not_found(ptr))?.value()))
+    }
+}
+
+/// A write transaction held open while the batch is staged, which keeps
+/// other writers out so the ids it assigns stay unused.
+struct RedbBatch {
+    txn: WriteTransaction,
+    next: u64,
+    nodes: Vec<(u64, Vec<u8>)>,
+    increments: Vec<u64>,
+    version: Option<u64>,
+}
+
+impl WriteBatch<Vec<u8>> for RedbBatch {
+    fn insert(&mut self, node: &Vec<u8>) -> Result<Ptr> {
+        let id = self.next + self.nodes.len() as u64;
+        self.nodes.push((id, node.clone()));
+        Ok(Ptr::from_u64(id))
+    }
+
+    fn inc_ref_count(&mut self, ptr: &Ptr) -> Result<()> {
+        self.increments
+            .push(ptr.to_u64().ok_or_else(|| not_found(ptr))?);
+        Ok(())
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.version = Some(version);
+    }
+
+    fn commit(self: Box<Self>) -> Result<()> {
+        let RedbBatch {
+            txn,
+            nodes,
+            increments,
+            version,
+            ..
+        } = *self;
+        if nodes.is_empty() && increments.is_empty() {
+            return Ok(());
+        }
+        allocate_ids(&txn, nodes.len() as u64)?;
+        {
+            let mut table = txn.open_table(NODES)?;
+            let mut ref_counts = txn.open_table(REF_COUNTS)?;
+            let mut versions = txn.open_table(VERSIONS)?;
+            for (id, node) in &nodes {
+                table.insert(id, node.as_slice())?;
+                ref_counts.insert(id, 1)?;
+                if let Some(version) = version {
+                    versions.insert(id, version)?;
+                }
+            }
+            // A failure drops the transaction, which aborts it.
+            for id in increments {
+                let count = ref_counts
+                    .get(id)?
+                    .ok_or_else(|| not_found(&Ptr::from_u64(id)))?
+                    .value();
+                ref_counts.insert(id, count + 1)?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+}
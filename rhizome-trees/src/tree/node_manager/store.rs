@@ -0,0 +1,581 @@
+//! Backends which persist tree nodes.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::{Error, Result};
+
+/// Identifies a node within a [`NodeStore`]. What it contains (a counter, a
+/// file offset, a content hash, ...) is up to the store, as long as it fits
+/// in [`Ptr::MAX_LEN`] bytes.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Ptr {
+    len: u8,
+    bytes: [u8; Ptr::MAX_LEN],
+}
+
+impl Ptr {
+    pub const MAX_LEN: usize = 32;
+
+    pub fn new(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() > Ptr::MAX_LEN {
+            return Err(Error::invalid(format!(
+                "pointer of {} bytes exceeds {} bytes",
+                bytes.len(),
+                Ptr::MAX_LEN
+            )));
+        }
+        let mut ptr = Ptr {
+            len: bytes.len() as u8,
+            bytes: [0; Ptr::MAX_LEN],
+        };
+        ptr.bytes[..bytes.len()].copy_from_slice(bytes);
+        Ok(ptr)
+    }
+
+    pub fn from_u64(n: u64) -> Self {
+        Ptr::new(&n.to_be_bytes()).expect("8 bytes fit in a pointer")
+    }
+
+    /// Returns the number stored by [`Ptr::from_u64`].
+    pub fn to_u64(&self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.as_bytes().try_into().ok()?))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+/// Serializes as the bytes of the pointer.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Ptr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.as_bytes())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Ptr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = <Vec<u8>>::deserialize(deserializer)?;
+        Ptr::new(&bytes).map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Debug for Ptr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Ptr(")?;
+        for byte in self.as_bytes() {
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// A backend which persists nodes of type `N`.
+///
+/// Nodes are immutable once inserted and are shared between tree versions, so
+/// stores keep a reference count per node which the tree maintains as
+/// versions are saved.
+pub trait NodeStore<N>: Send + Sync {
+    fn read(&self, ptr: &Ptr) -> Result<N>;
+
+    /// Reads several nodes at once, in the order of `ptrs`, failing if any
+    /// of them can't be read.
+    ///
+    /// Stores which answer a batch of reads in one round trip, such as
+    /// network-backed ones, override this default, which reads the nodes
+    /// one by one.
+    fn read_many(&self, ptrs: &[Ptr]) -> Result<Vec<N>> {
+        ptrs.iter().map(|ptr| self.read(ptr)).collect()
+    }
+
+    /// Persists a new node with a reference count of 1.
+    fn insert(&self, node: &N) -> Result<Ptr>;
+
+    /// Increments the reference count of a node, returning the new count.
+    fn inc_ref_count(&self, ptr: &Ptr) -> Result<u64>;
+
+    /// Decrements the reference count of a node, returning the new count.
+    ///
+    /// Decrementing a count which is already 0 means a reference was
+    /// released twice, so stores fail with [`Error::RefCountUnderflow`]
+    /// and leave the count at 0 rather than hiding the bug.
+    fn dec_ref_count(&self, ptr: &Ptr) -> Result<u64>;
+
+    /// Removes a node, normally once its reference count dropped to 0.
+    fn delete(&self, ptr: &Ptr) -> Result<()>;
+
+    /// Replaces the nodes at the given pointers in place if every one of
+    /// them has a reference count of 1, returning whether it did. Either all
+    /// nodes are replaced or none are.
+    ///
+    /// Stores which can't rewrite nodes, such as append-only or
+    /// content-addressed ones, keep this default, which never updates.
+    fn try_update(&self, nodes: &[(Ptr, N)]) -> Result<bool> {
+        let _ = nodes;
+        Ok(false)
+    }
+
+    /// Starts a batch of writes which [`WriteBatch::commit`] applies
+    /// atomically, so a crash leaves either all of them or none in the store.
+    ///
+    /// Stores without transactions keep this default, whose batch applies
+    /// each write as it is staged and whose commit does nothing.
+    fn begin_batch<'a>(&'a self) -> Result<Box<dyn WriteBatch<N> + 'a>>
+    where
+        N: 'a,
+    {
+        Ok(Box::new(Unbatched(self)))
+    }
+
+    /// Returns the header last written with [`NodeStore::set_header`], such
+    /// as the encoded [`TreeConfig`](super::TreeConfig) of the store's
+    /// trees.
+    ///
+    /// Stores which can't keep a header keep this default, which has none.
+    fn header(&self) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// Replaces the header of the store.
+    ///
+    /// Stores which can't keep a header keep this default, which fails.
+    fn set_header(&self, header: &[u8]) -> Result<()> {
+        let _ = header;
+        Err(Error::invalid("node store can't keep a header"))
+    }
+
+    /// Checks that the store works by inserting `probe`, reading it back and
+    /// releasing it again, timing each step. The probe is only deleted if
+    /// its reference count drops to 0, so a content-addressed store holding
+    /// an equal node keeps it.
+    fn health_check(&self, probe: &N) -> Result<HealthReport> {
+        let start = Instant::now();
+        let ptr = self.insert(probe)?;
+        let written = Instant::now();
+        self.read(&ptr)?;
+        let read = Instant::now();
+        if self.dec_ref_count(&ptr)? == 0 {
+            self.delete(&ptr)?;
+        }
+        Ok(HealthReport {
+            write: written - start,
+            read: read - written,
+            delete: read.elapsed(),
+        })
+    }
+
+    /// Returns the [`NodeMeta`] recorded for a node.
+    ///
+    /// Stores which don't record any keep this default, which returns
+    /// `None` for every node.
+    fn meta(&self, ptr: &Ptr) -> Result<Option<NodeMeta>> {
+        let _ = ptr;
+        Ok(None)
+    }
+
+    /// Returns the reference count of a stored node, e.g. to show which
+    /// nodes versions share.
+    ///
+    /// Stores which can't tell it without changing it keep this default,
+    /// which returns `None` for every node.
+    fn ref_count(&self, ptr: &Ptr) -> Result<Option<u64>> {
+        let _ = ptr;
+        Ok(None)
+    }
+}
+
+/// Writes staged by [`NodeStore::begin_batch`]. Pointers are assigned as
+/// nodes are staged, so a node can refer to children inserted earlier in the
+/// same batch. Dropping a batch without committing it discards its writes.
+pub trait WriteBatch<N> {
+    /// Stages a new node with a reference count of 1.
+    fn insert(&mut self, node: &N) -> Result<Ptr>;
+
+    /// Stages an increment of the reference count of a node, which may be
+    /// stored or staged in this batch.
+    fn inc_ref_count(&mut self, ptr: &Ptr) -> Result<()>;
+
+    /// Records the root of the tree version the batch saves, `None` for an
+    /// empty tree, for stores which keep track of it such as
+    /// [`WalStore`](super::WalStore). Other stores ignore it.
+    fn set_root(&mut self, root: Option<Ptr>) {
+        let _ = root;
+    }
+
+    /// Records `version` as the version which created every node the batch
+    /// inserts, for stores which keep [`NodeMeta`]. Other stores ignore it.
+    fn set_version(&mut self, version: u64) {
+        let _ = version;
+    }
+
+    /// Applies every staged write, or none of them if it fails.
+    fn commit(self: Box<Self>) -> Result<()>;
+}
+
+/// The batch of stores which don't support them, writing through to the
+/// store right away.
+struct Unbatched<'a, S: ?Sized>(&'a S);
+
+impl<N, S: NodeStore<N> + ?Sized> WriteBatch<N> for Unbatched<'_, S> {
+    fn insert(&mut self, node: &N) -> Result<Ptr> {
+        self.0.insert(node)
+    }
+
+    fn inc_ref_count(&mut self, ptr: &Ptr) -> Result<()> {
+        self.0.inc_ref_count(ptr).map(drop)
+    }
+
+    fn commit(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The latencies measured by [`NodeStore::health_check`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct HealthReport {
+    pub write: Duration,
+    pub read: Duration,
+    /// The time taken to release and delete the probe.
+    pub delete: Duration,
+}
+
+impl HealthReport {
+    pub fn total(&self) -> Duration {
+        self.write + self.read + self.delete
+    }
+}
+
+/// Secondary metadata a store records about a node, returned by
+/// [`NodeStore::meta`], e.g. to find the nodes written by old versions or
+/// to measure how many bytes a traversal reads.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct NodeMeta {
+    /// The version whose save wrote the node, or `None` if it was written
+    /// by a batch without one, see [`WriteBatch::set_version`].
+    pub version: Option<u64>,
+    /// The size of the stored encoding of the node in bytes, or `None` if
+    /// the store doesn't encode its nodes.
+    pub size: Option<u64>,
+}
+
+/// A store which keeps nodes in a hash map. It records the version of each
+/// node as [`NodeMeta`].
+pub struct MemNodeStore<N> {
+    inner: RwLock<MemInner<N>>,
+}
+
+struct MemInner<N> {
+    next: u64,
+    nodes: HashMap<u64, (N, u64)>,
+    /// The version of each node inserted by a batch with one.
+    versions: HashMap<u64, u64>,
+    header: Option<Vec<u8>>,
+}
+
+impl<N> Default for MemNodeStore<N> {
+    fn default() -> Self {
+        MemNodeStore {
+            inner: RwLock::new(MemInner {
+                next: 0,
+                nodes: HashMap::new(),
+                versions: HashMap::new(),
+                header: None,
+            }),
+        }
+    }
+}
+
+impl<N> MemNodeStore<N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of stored nodes.
+    pub fn len(&self) -> usize {
+        self.inner.read().map_or(0, |inner| inner.nodes.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+pub(super) fn poisoned<T>(_: T) -> Error {
+    Error::poisoned("node store")
+}
+
+pub(super) fn not_found(ptr: &Ptr) -> Error {
+    Error::NotFound(*ptr)
+}
+
+/// Decrements `count`, failing for a count of 0.
+pub(super) fn decrement(ptr: &Ptr, count: &mut u64) -> Result<u64> {
+    *count = count.checked_sub(1).ok_or(Error::RefCountUnderflow(*ptr))?;
+    Ok(*count)
+}
+
+impl<N: Clone + Send + Sync> NodeStore<N> for MemNodeStore<N> {
+    fn read(&self, ptr: &Ptr) -> Result<N> {
+        let inner = self.inner.read().map_err(poisoned)?;
+        let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        let (node, _) = inner.nodes.get(&id).ok_or_else(|| not_found(ptr))?;
+        Ok(node.clone())
+    }
+
+    fn read_many(&self, ptrs: &[Ptr]) -> Result<Vec<N>> {
+        let inner = self.inner.read().map_err(poisoned)?;
+        ptrs.iter()
+            .map(|ptr| {
+                let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+                let (node, _) = inner.nodes.get(&id).ok_or_else(|| not_found(ptr))?;
+                Ok(node.clone())
+            })
+            .collect()
+    }
+
+    fn insert(&self, node: &N) -> Result<Ptr> {
+        let mut inner = self.inner.write().map_err(poisoned)?;
+        let id = inner.next;
+        inner.next += 1;
+        inner.nodes.insert(id, (node.clone(), 1));
+        Ok(Ptr::from_u64(id))
+    }
+
+    fn inc_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        let mut inner = self.inner.write().map_err(poisoned)?;
+        let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        let (_, count) = inner.nodes.get_mut(&id).ok_or_else(|| not_found(ptr))?;
+        *count += 1;
+        Ok(*count)
+    }
+
+    fn dec_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        let mut inner = self.inner.write().map_err(poisoned)?;
+        let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        let (_, count) = inner.nodes.get_mut(&id).ok_or_else(|| not_found(ptr))?;
+        decrement(ptr, count)
+    }
+
+    fn delete(&self, ptr: &Ptr) -> Result<()> {
+        let mut inner = self.inner.write().map_err(poisoned)?;
+        let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        inner.nodes.remove(&id).ok_or_else(|| not_found(ptr))?;
+        inner.versions.remove(&id);
+        Ok(())
+    }
+
+    fn try_update(&self, nodes: &[(Ptr, N)]) -> Result<bool> {
+        let mut inner = self.inner.write().map_err(poisoned)?;
+        let mut ids = Vec::with_capacity(nodes.len());
+        for (ptr, _) in nodes {
+            let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+            let (_, count) = inner.nodes.get(&id).ok_or_else(|| not_found(ptr))?;
+            if *count != 1 {
+                return Ok(false);
+            }
+            ids.push(id);
+        }
+        for (id, (_, node)) in ids.into_iter().zip(nodes) {
+            inner.nodes.insert(id, (node.clone(), 1));
+        }
+        Ok(true)
+    }
+
+    fn header(&self) -> Result<Option<Vec<u8>>> {
+        Ok(self.inner.read().map_err(poisoned)?.header.clone())
+    }
+
+    fn set_header(&self, header: &[u8]) -> Result<()> {
+        self.inner.write().map_err(poisoned)?.header = Some(header.to_vec());
+        Ok(())
+    }
+
+    fn begin_batch<'a>(&'a self) -> Result<Box<dyn WriteBatch<N> + 'a>>
+    where
+        N: 'a,
+    {
+        Ok(Box::new(MemBatch {
+            store: self,
+            nodes: Vec::new(),
+            increments: Vec::new(),
+            version: None,
+        }))
+    }
+
+    fn meta(&self, ptr: &Ptr) -> Result<Option<NodeMeta>> {
+        let inner = self.inner.read().map_err(poisoned)?;
+        let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        if !inner.nodes.contains_key(&id) {
+            return Err(not_found(ptr));
+        }
+        Ok(Some(NodeMeta {
+            version: inner.versions.get(&id).copied(),
+            size: None,
+        }))
+    }
+
+    fn ref_count(&self, ptr: &Ptr) -> Result<Option<u64>> {
+        let inner = self.inner.read().map_err(poisoned)?;
+        let id = ptr.to_u64().ok_or_else(|| not_found(ptr))?;
+        let (_, count) = inner.nodes.get(&id).ok_or_else(|| not_found(ptr))?;
+        Ok(Some(*count))
+    }
+}
+
+struct MemBatch<'a, N> {
+    store: &'a MemNodeStore<N>,
+    nodes: Vec<(u64, N)>,
+    increments: Vec<u64>,
+    version: Option<u64>,
+}
+
+impl<N: Clone> WriteBatch<N> for MemBatch<'_, N> {
+    fn insert(&mut self, node: &N) -> Result<Ptr> {
+        // Reserve the id now so it stays unique among concurrent writers.
+        let mut inner = self.store.inner.write().map_err(poisoned)?;
+        let id = inner.next;
+        inner.next += 1;
+        self.nodes.push((id, node.clone()));
+        Ok(Ptr::from_u64(id))
+    }
+
+    fn inc_ref_count(&mut self, ptr: &Ptr) -> Result<()> {
+        self.increments
+            .push(ptr.to_u64().ok_or_else(|| not_found(ptr))?);
+        Ok(())
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.version = Some(version);
+    }
+
+    fn commit(self: Box<Self>) -> Result<()> {
+        let mut inner = self.store.inner.write().map_err(poisoned)?;
+        let ids: Vec<_> = self.nodes.iter().map(|(id, _)| *id).collect();
+        for (id, node) in self.nodes {
+            inner.nodes.insert(id, (node, 1));
+        }
+        if let Some(&missing) = self
+            .increments
+            .iter()
+            .find(|id| !inner.nodes.contains_key(id))
+        {
+            // Nobody saw the staged nodes while the lock was held.
+            for id in ids {
+                inner.nodes.remove(&id);
+            }
+            return Err(not_found(&Ptr::from_u64(missing)));
+        }
+        for id in &self.increments {
+            if let Some((_, count)) = inner.nodes.get_mut(id) {
+                *count += 1;
+            }
+        }
+        if let Some(version) = self.version {
+            inner
+                .versions
+                .extend(ids.into_iter().map(|id| (id, version)));
+        }
+        Ok(())
+    }
+}
+
+/// A store for memory-only trees which fails every operation.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct NullNodeStore;
+
+const NO_STORE: &str = "memory-only tree has no node store";
+
+impl<N> NodeStore<N> for NullNodeStore {
+    fn read(&self, _ptr: &Ptr) -> Result<N> {
+        Err(Error::invalid(NO_STORE))
+    }
+
+    fn insert(&self, _node: &N) -> Result<Ptr> {
+        Err(Error::invalid(NO_STORE))
+    }
+
+    fn inc_ref_count(&self, _ptr: &Ptr) -> Result<u64> {
+        Err(Error::invalid(NO_STORE))
+    }
+
+    fn dec_ref_count(&self, _ptr: &Ptr) -> Result<u64> {
+        Err(Error::invalid(NO_STORE))
+    }
+
+    fn delete(&self, _ptr: &Ptr) -> Result<()> {
+        Err(Error::invalid(NO_STORE))
+    }
+
+    fn header(&self) -> Result<Option<Vec<u8>>> {
+        Err(Error::invalid(NO_STORE))
+    }
+
+    fn set_header(&self, _header: &[u8]) -> Result<()> {
+        Err(Error::invalid(NO_STORE))
+    }
+}
+
+/// Shares a store, e.g. between the [`EncodedStore`](super::EncodedStore)s
+/// of several node types.
+impl<N, S: NodeStore<N> + ?Sized> NodeStore<N> for Arc<S> {
+    fn read(&self, ptr: &Ptr) -> Result<N> {
+        (**self).read(ptr)
+    }
+
+    fn read_many(&self, ptrs: &[Ptr]) -> Result<Vec<N>> {
+        (**self).read_many(ptrs)
+    }
+
+    fn insert(&self, node: &N) -> Result<Ptr> {
+        (**self).insert(node)
+    }
+
+    fn inc_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        (**self).inc_ref_count(ptr)
+    }
+
+    fn dec_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        (**self).dec_ref_count(ptr)
+    }
+
+    fn delete(&self, ptr: &Ptr) -> Result<()> {
+        (**self).delete(ptr)
+    }
+
+    fn try_update(&self, nodes: &[(Ptr, N)]) -> Result<bool> {
+        (**self).try_update(nodes)
+    }
+
+    fn begin_batch<'a>(&'a self) -> Result<Box<dyn WriteBatch<N> + 'a>>
+    where
+        N: 'a,
+    {
+        (**self).begin_batch()
+    }
+
+    fn header(&self) -> Result<Option<Vec<u8>>> {
+        (**self).header()
+    }
+
+    fn set_header(&self, header: &[u8]) -> Result<()> {
+        (**self).set_header(header)
+    }
+
+    fn health_check(&self, probe: &N) -> Result<HealthReport> {
+        (**self).health_check(probe)
+    }
+
+    fn meta(&self, ptr: &Ptr) -> Result<Option<NodeMeta>> {
+        (**self).meta(ptr)
+    }
+
+    fn ref_count(&self, ptr: &Ptr) -> Result<Option<u64>> {
+        (**self).ref_count(ptr)
+    }
+}
@@ -0,0 +1,607 @@
+//! A write-ahead log which makes saving a tree version crash safe.
+//!
+//! [`WalStore`] wraps another store and journals every [`WriteBatch`] to a
+//! log file before committing it. The journal holds the pointers of the
+//! nodes the batch inserts, the ones whose reference count it increments and
+//! the root of the version it saves, all synced to disk before the store sees
+//! any write. Once the batch is committed a completion record follows. Each
+//! record is laid out as
+//!
+//! ```text
+//! len: u32 LE | crc: u32 LE | payload: [u8; len]
+//! ```
+//!
+//! where `crc` is the CRC-32 of the payload. On open, a torn record at the
+//! end is truncated and every journaled batch without a completion record is
+//! rolled back in the store: if its writes reached the store, its nodes are
+//! deleted and its reference count increments undone. The root reported by
+//! [`WalStore::root`] is that of the last completed save, so it never points
+//! at nodes a crash lost. The journal is then compacted to a single record
+//! holding that root, as it is again whenever a completion record leaves no
+//! batch pending, so it doesn't grow with the number of saves.
+//!
+//! Releasing nodes outside of batches is journaled the same way, one
+//! decrement or deletion at a time: the pointer and the reference count it
+//! had before are synced first, a completion record follows. An interrupted
+//! release is finished on open, decrementing the count if it still has its
+//! earlier value and deleting the node if it is still there. In-place
+//! updates can't be undone or redone from a journal of pointers, so
+//! [`WalStore`] refuses them and trees save a new version instead.
+//!
+//! A damaged record before the end of the journal can't have been left by a
+//! crash, and the batches it hides may have completed, so it fails the open
+//! without touching the store or the journal. A write to the journal which
+//! fails is cut off again, so later records never follow a torn one; if even
+//! that fails, the journal refuses any further writes.
+//!
+//! Recovery tells whether a batch reached the store by reading the first
+//! node it inserted, so the wrapped store must give staged nodes pointers
+//! which don't resolve before the batch is committed, as [`MemNodeStore`]
+//! and [`FileNodeStore`] do. A batch which only increments reference counts
+//! can't be told apart this way and is left as is, which at worst keeps some
+//! nodes alive, as does a crash in the middle of releasing a version, which
+//! leaves the nodes below the last one released counted. Content-addressed
+//! stores, whose pointers may already resolve before a commit, can't be
+//! journaled.
+//!
+//! [`MemNodeStore`]: super::MemNodeStore
+//! [`FileNodeStore`]: super::FileNodeStore
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::file::Crc32;
+use super::store::{poisoned, HealthReport, NodeMeta, NodeStore, Ptr, WriteBatch};
+use crate::{Error, Result};
+
+const BEGIN: u8 = 0;
+const DONE: u8 = 1;
+const ABORTED: u8 = 2;
+const CHECKPOINT: u8 = 3;
+const DECREMENT: u8 = 4;
+const DELETE: u8 = 5;
+
+const HEADER_LEN: usize = 8;
+
+/// A store which journals its batches so that a crash during a save leaves
+/// the previous version intact, see the [module documentation](self).
+pub struct WalStore<S> {
+    inner: S,
+    journal: Mutex<Journal>,
+    rolled_back: usize,
+}
+
+struct Journal {
+    path: PathBuf,
+    file: File,
+    /// The length of the records written in full.
+    end: u64,
+    /// Set when a failed write couldn't be cut off.
+    broken: bool,
+    next_txn: u64,
+    /// The number of records begun in this process without completion.
+    open: usize,
+    root: Option<Ptr>,
+}
+
+/// A journaled batch.
+struct Txn {
+    inserted: Vec<Ptr>,
+    incremented: Vec<Ptr>,
+    /// The root the batch saves, if it recorded one.
+    root: Option<Option<Ptr>>,
+}
+
+/// A journaled record which hasn't completed.
+enum Pending {
+    Batch(Txn),
+    /// A decrement, with the reference count before it if the store tells.
+    Decrement(Ptr, Option<u64>),
+    Delete(Ptr),
+}
+
+impl<S> WalStore<S> {
+    /// Opens the journal at `path` for `inner`, creating it if it doesn't
+    /// exist, and rolls back the saves a crash interrupted.
+    pub fn open<N>(inner: S, path: impl AsRef<Path>) -> Result<Self>
+    where
+        S: NodeStore<N>,
+    {
+        let path = path.as_ref();
+        let mut bytes = Vec::new();
+        if path.exists() {
+            File::open(path)
+                .and_then(|mut file| file.read_to_end(&mut bytes))
+                .map_err(|err| Error::io(format!("reading journal {}", path.display()), err))?;
+        }
+        let (root, pending) = replay(&bytes)?;
+        let mut rolled_back = 0;
+        for pending in pending.into_values() {
+            match pending {
+                Pending::Batch(txn) => {
+                    roll_back(&inner, &txn)?;
+                    rolled_back += 1;
+                }
+                Pending::Decrement(ptr, before) => {
+                    let count = inner.ref_count(&ptr).ok().flatten();
+                    if count.is_some_and(|count| count > 0) && count == before {
+                        inner.dec_ref_count(&ptr)?;
+                    }
+                }
+                Pending::Delete(ptr) => {
+                    if inner.read(&ptr).is_ok() {
+                        inner.delete(&ptr)?;
+                    }
+                }
+            }
+        }
+        let (file, end) = compact(path, root)?;
+        Ok(WalStore {
+            inner,
+            journal: Mutex::new(Journal {
+                path: path.to_path_buf(),
+                file,
+                end,
+                broken: false,
+                next_txn: 0,
+                open: 0,
+                root,
+            }),
+            rolled_back,
+        })
+    }
+
+    /// Returns the root of the last completed save, `None` if it saved an
+    /// empty tree or nothing was saved yet.
+    pub fn root(&self) -> Result<Option<Ptr>> {
+        Ok(self.journal.lock().map_err(poisoned)?.root)
+    }
+
+    /// Returns the number of interrupted saves rolled back on open.
+    pub fn rolled_back(&self) -> usize {
+        self.rolled_back
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl Journal {
+    fn append(&mut self, payload: &[u8]) -> Result<()> {
+        if self.broken {
+            return Err(Error::Poisoned(
+                "journal refuses writes after a failed write it couldn't cut off".to_string(),
+            ));
+        }
+        let record = record(payload)?;
+        let written = self
+            .file
+            .write_all(&record)
+            .and_then(|()| self.file.sync_data());
+        if let Err(err) = written {
+            // Records appended after part of this one would be taken for
+            // corruption on open.
+            let cut = self
+                .file
+                .set_len(self.end)
+                .and_then(|()| self.file.sync_data());
+            self.broken = cut.is_err();
+            return Err(Error::io("appending to the journal", err));
+        }
+        self.end += record.len() as u64;
+        Ok(())
+    }
+
+    fn begin(&mut self, txn: &Txn) -> Result<u64> {
+        self.start(BEGIN, |payload| {
+            encode_root(payload, txn.root);
+            for ptrs in [&txn.inserted, &txn.incremented] {
+                payload.extend_from_slice(&(ptrs.len() as u32).to_le_bytes());
+                for ptr in ptrs {
+                    encode_ptr(payload, ptr);
+                }
+            }
+        })
+    }
+
+    /// Appends a record of kind `tag` under a new id, which `encode`
+    /// completes, returning the id.
+    fn start(&mut self, tag: u8, encode: impl FnOnce(&mut Vec<u8>)) -> Result<u64> {
+        let id = self.next_txn;
+        self.next_txn += 1;
+        let mut payload = vec![tag];
+        payload.extend_from_slice(&id.to_le_bytes());
+        encode(&mut payload);
+        self.append(&payload)?;
+        self.open += 1;
+        Ok(id)
+    }
+
+    fn end(&mut self, tag: u8, id: u64) -> Result<()> {
+        let mut payload = vec![tag];
+        payload.extend_from_slice(&id.to_le_bytes());
+        self.append(&payload)?;
+        self.open -= 1;
+        Ok(())
+    }
+
+    /// Compacts the journal to a checkpoint of the root unless a record is
+    /// still pending.
+    fn compact(&mut self) -> Result<()> {
+        if self.open > 0 {
+            return Ok(());
+        }
+        (self.file, self.end) = compact(&self.path, self.root)?;
+        Ok(())
+    }
+}
+
+impl<S> WalStore<S> {
+    /// Runs the single write `apply` between a record of kind `tag`, which
+    /// `encode` completes, and its completion record.
+    fn journaled<T>(
+        &self,
+        tag: u8,
+        encode: impl FnOnce(&mut Vec<u8>),
+        apply: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let id = self.journal.lock().map_err(poisoned)?.start(tag, encode)?;
+        // A failed write may still have reached the store, so it stays
+        // pending until open finishes it.
+        let out = apply()?;
+        let mut journal = self.journal.lock().map_err(poisoned)?;
+        journal.end(DONE, id)?;
+        journal.compact()?;
+        Ok(out)
+    }
+}
+
+impl<N, S: NodeStore<N>> NodeStore<N> for WalStore<S> {
+    fn read(&self, ptr: &Ptr) -> Result<N> {
+        self.inner.read(ptr)
+    }
+
+    fn read_many(&self, ptrs: &[Ptr]) -> Result<Vec<N>> {
+        self.inner.read_many(ptrs)
+    }
+
+    fn insert(&self, node: &N) -> Result<Ptr> {
+        self.inner.insert(node)
+    }
+
+    fn inc_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        self.inner.inc_ref_count(ptr)
+    }
+
+    fn dec_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        let before = self.inner.ref_count(ptr)?;
+        self.journaled(
+            DECREMENT,
+            |payload| {
+                encode_ptr(payload, ptr);
+                encode_count(payload, before);
+            },
+            || self.inner.dec_ref_count(ptr),
+        )
+    }
+
+    fn delete(&self, ptr: &Ptr) -> Result<()> {
+        self.journaled(
+            DELETE,
+            |payload| encode_ptr(payload, ptr),
+            || self.inner.delete(ptr),
+        )
+    }
+
+    /// Never updates, as the journal couldn't undo a rewrite a crash
+    /// interrupted.
+    fn try_update(&self, nodes: &[(Ptr, N)]) -> Result<bool> {
+        let _ = nodes;
+        Ok(false)
+    }
+
+    fn begin_batch<'a>(&'a self) -> Result<Box<dyn WriteBatch<N> + 'a>>
+    where
+        N: 'a,
+    {
+        Ok(Box::new(WalBatch {
+            journal: &self.journal,
+            inner: self.inner.begin_batch()?,
+            txn: Txn {
+                inserted: Vec::new(),
+                incremented: Vec::new(),
+                root: None,
+            },
+        }))
+    }
+
+    fn header(&self) -> Result<Option<Vec<u8>>> {
+        self.inner.header()
+    }
+
+    fn set_header(&self, header: &[u8]) -> Result<()> {
+        self.inner.set_header(header)
+    }
+
+    fn health_check(&self, probe: &N) -> Result<HealthReport> {
+        self.inner.health_check(probe)
+    }
+
+    fn meta(&self, ptr: &Ptr) -> Result<Option<NodeMeta>> {
+        self.inner.meta(ptr)
+    }
+
+    fn ref_count(&self, ptr: &Ptr) -> Result<Option<u64>> {
+        self.inner.ref_count(ptr)
+    }
+}
+
+/// Wraps the batch `B` of the inner store.
+struct WalBatch<'a, B: ?Sized> {
+    journal: &'a Mutex<Journal>,
+    inner: Box<B>,
+    txn: Txn,
+}
+
+impl<N, B: WriteBatch<N> + ?Sized> WriteBatch<N> for WalBatch<'_, B> {
+    fn insert(&mut self, node: &N) -> Result<Ptr> {
+        let ptr = self.inner.insert(node)?;
+        self.txn.inserted.push(ptr);
+        Ok(ptr)
+    }
+
+    fn inc_ref_count(&mut self, ptr: &Ptr) -> Result<()> {
+        self.inner.inc_ref_count(ptr)?;
+        self.txn.incremented.push(*ptr);
+        Ok(())
+    }
+
+    fn set_root(&mut self, root: Option<Ptr>) {
+        self.inner.set_root(root);
+        self.txn.root = Some(root);
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.inner.set_version(version);
+    }
+
+    fn commit(self: Box<Self>) -> Result<()> {
+        let id = self.journal.lock().map_err(poisoned)?.begin(&self.txn)?;
+        if let Err(err) = self.inner.commit() {
+            // Nothing reached the store, so there is nothing to roll back.
+            self.journal.lock().map_err(poisoned)?.end(ABORTED, id)?;
+            return Err(err);
+        }
+        let mut journal = self.journal.lock().map_err(poisoned)?;
+        journal.end(DONE, id)?;
+        if let Some(root) = self.txn.root {
+            journal.root = root;
+        }
+        journal.compact()
+    }
+}
+
+/// Undoes the writes of `txn` if they reached `store`.
+fn roll_back<N>(store: &impl NodeStore<N>, txn: &Txn) -> Result<()> {
+    let applied = match txn.inserted.first() {
+        Some(ptr) => store.read(ptr).is_ok(),
+        None => false,
+    };
+    if !applied {
+        return Ok(());
+    }
+    for ptr in &txn.incremented {
+        store.dec_ref_count(ptr)?;
+    }
+    for ptr in txn.inserted.iter().rev() {
+        store.delete(ptr)?;
+    }
+    Ok(())
+}
+
+/// Reads the journal, returning the root of the last completed save and the
+/// records which never completed, by id. A torn record at the end is
+/// ignored, while one failing its checksum before the end fails the replay.
+fn replay(bytes: &[u8]) -> Result<(Option<Ptr>, BTreeMap<u64, Pending>)> {
+    let mut root = None;
+    let mut pending = BTreeMap::new();
+    let mut pos = 0;
+    while let Some(header) = bytes.get(pos..pos + HEADER_LEN) {
+        let len = u32::from_le_bytes(header[..4].try_into().expect("4 bytes")) as usize;
+        let crc = u32::from_le_bytes(header[4..].try_into().expect("4 bytes"));
+        let Some(payload) = bytes.get(pos + HEADER_LEN..pos + HEADER_LEN + len) else {
+            break;
+        };
+        if checksum(payload) != crc {
+            if pos + HEADER_LEN + len == bytes.len() {
+                break;
+            }
+            return Err(Error::corruption(format!(
+                "journal record at offset {} fails its checksum",
+                pos
+            )));
+        }
+        let mut reader = Reader(payload);
+        match reader.u8()? {
+            BEGIN => {
+                let id = reader.u64()?;
+                let txn_root = reader.root()?;
+                let inserted = reader.ptrs()?;
+                let incremented = reader.ptrs()?;
+                pending.insert(
+                    id,
+                    Pending::Batch(Txn {
+                        inserted,
+                        incremented,
+                        root: txn_root,
+                    }),
+                );
+            }
+            DECREMENT => {
+                let id = reader.u64()?;
+                let ptr = reader.ptr()?;
+                pending.insert(id, Pending::Decrement(ptr, reader.count()?));
+            }
+            DELETE => {
+                let id = reader.u64()?;
+                pending.insert(id, Pending::Delete(reader.ptr()?));
+            }
+            DONE => {
+                let id = reader.u64()?;
+                let done = pending.remove(&id).ok_or_else(|| {
+                    Error::corruption(format!("journal completes unknown record {}", id))
+                })?;
+                if let Pending::Batch(Txn {
+                    root: Some(txn_root),
+                    ..
+                }) = done
+                {
+                    root = txn_root;
+                }
+            }
+            ABORTED => {
+                pending.remove(&reader.u64()?);
+            }
+            CHECKPOINT => root = reader.root()?.flatten(),
+            tag => {
+                return Err(Error::corruption(format!(
+                    "unknown journal record tag {} at offset {}",
+                    tag, pos
+                )))
+            }
+        }
+        pos += HEADER_LEN + len;
+    }
+    Ok((root, pending))
+}
+
+/// Replaces the journal at `path` with a single checkpoint of `root`,
+/// returning it opened for appending and its length.
+fn compact(path: &Path, root: Option<Ptr>) -> Result<(File, u64)> {
+    let mut payload = vec![CHECKPOINT];
+    encode_root(&mut payload, Some(root));
+    let mut tmp = PathBuf::from(path);
+    tmp.as_mut_os_string().push(".tmp");
+    // The handle stays valid across the rename, so a failure leaves either
+    // journal whole and open.
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&tmp)
+        .map_err(|err| Error::io(format!("creating journal {}", tmp.display()), err))?;
+    file.set_len(0)?;
+    let record = record(&payload)?;
+    file.write_all(&record)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+        .map_err(|err| Error::io(format!("replacing journal {}", path.display()), err))?;
+    Ok((file, record.len() as u64))
+}
+
+fn record(payload: &[u8]) -> Result<Vec<u8>> {
+    let len = u32::try_from(payload.len())
+        .map_err(|_| Error::invalid("batch too large for the journal"))?;
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+    record.extend_from_slice(&len.to_le_bytes());
+    record.extend_from_slice(&checksum(payload).to_le_bytes());
+    record.extend_from_slice(payload);
+    Ok(record)
+}
+
+fn checksum(payload: &[u8]) -> u32 {
+    let mut crc = Crc32::default();
+    crc.update(payload);
+    crc.finish()
+}
+
+fn encode_ptr(buf: &mut Vec<u8>, ptr: &Ptr) {
+    buf.push(ptr.as_bytes().len() as u8);
+    buf.extend_from_slice(ptr.as_bytes());
+}
+
+/// Encodes a reference count which the store may not have told.
+fn encode_count(buf: &mut Vec<u8>, count: Option<u64>) {
+    match count {
+        None => buf.push(0),
+        Some(count) => {
+            buf.push(1);
+            buf.extend_from_slice(&count.to_le_bytes());
+        }
+    }
+}
+
+/// Encodes a recorded root: no root, an empty tree, or a pointer.
+fn encode_root(buf: &mut Vec<u8>, root: Option<Option<Ptr>>) {
+    match root {
+        None => buf.push(0),
+        Some(None) => buf.push(1),
+        Some(Some(ptr)) => {
+            buf.push(2);
+            encode_ptr(buf, &ptr);
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes(&mut self, n: usize) -> Result<&[u8]> {
+        if self.0.len() < n {
+            return Err(Error::corruption("journal record ends early"));
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(
+            self.bytes(8)?.try_into().expect("8 bytes"),
+        ))
+    }
+
+    fn ptr(&mut self) -> Result<Ptr> {
+        let len = self.u8()? as usize;
+        Ptr::new(self.bytes(len)?)
+    }
+
+    fn ptrs(&mut self) -> Result<Vec<Ptr>> {
+        let n = u32::from_le_bytes(self.bytes(4)?.try_into().expect("4 bytes"));
+        (0..n).map(|_| self.ptr()).collect()
+    }
+
+    fn count(&mut self) -> Result<Option<u64>> {
+        Ok(match self.u8()? {
+            0 => None,
+            1 => Some(self.u64()?),
+            tag => {
+                return Err(Error::corruption(format!(
+                    "invalid journal count tag {}",
+                    tag
+                )))
+            }
+        })
+    }
+
+    fn root(&mut self) -> Result<Option<Option<Ptr>>> {
+        Ok(match self.u8()? {
+            0 => None,
+            1 => Some(None),
+            2 => Some(Some(self.ptr()?)),
+            tag => {
+                return Err(Error::corruption(format!(
+                    "invalid journal root tag {}",
+                    tag
+                )))
+            }
+        })
+    }
+}
@@ -0,0 +1,243 @@
+//! A spatial index of points over the ART.
+//!
+//! Points are keyed by their Z-order code: longitude and latitude are each
+//! quantized to 32 bits and their bits interleaved, longitude first, into
+//! 8 big-endian bytes. Nearby points share long key prefixes, and every
+//! prefix of a code identifies a rectangular cell, so a bounding box query
+//! decomposes into a set of cells which are scanned as key prefixes. The
+//! interleaving matches geohash, whose characters are 5-bit groups of the
+//! same code.
+
+use crate::tree::art;
+use crate::{Error, Result};
+
+/// A location in degrees.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Point {
+    lat: f64,
+    lon: f64,
+}
+
+impl Point {
+    pub fn new(lat: f64, lon: f64) -> Result<Self> {
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+            return Err(Error::invalid(format!(
+                "coordinates ({}, {}) are out of range",
+                lat, lon
+            )));
+        }
+        Ok(Point { lat, lon })
+    }
+
+    pub fn lat(&self) -> f64 {
+        self.lat
+    }
+
+    pub fn lon(&self) -> f64 {
+        self.lon
+    }
+
+    /// Returns the Z-order code of the cell of about 1 cm containing the
+    /// point.
+    pub fn z_order(&self) -> u64 {
+        interleave(quantize(self.lon, 180.0), quantize(self.lat, 90.0))
+    }
+
+    /// Returns the geohash of the point with `len` characters, at most 12.
+    pub fn geohash(&self, len: usize) -> String {
+        const ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+        let code = self.z_order();
+        (0..len.min(12))
+            .map(|i| ALPHABET[(code >> (59 - 5 * i) & 0x1f) as usize] as char)
+            .collect()
+    }
+}
+
+/// A latitude/longitude rectangle, including its edges. Boxes crossing the
+/// antimeridian have to be queried as two boxes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BoundingBox {
+    min: Point,
+    max: Point,
+}
+
+/// The number of bisections a bounding box query refines cells to before
+/// scanning the remaining partially covered cells and filtering the points.
+/// 10 levels leave cells of about 35 by 18 km.
+pub const DEFAULT_QUERY_LEVELS: u32 = 10;
+
+impl BoundingBox {
+    pub fn new(min: Point, max: Point) -> Result<Self> {
+        if min.lat > max.lat || min.lon > max.lon {
+            return Err(Error::invalid("bounding box minimum is above its maximum"));
+        }
+        Ok(BoundingBox { min, max })
+    }
+
+    pub fn contains(&self, point: &Point) -> bool {
+        (self.min.lat..=self.max.lat).contains(&point.lat)
+            && (self.min.lon..=self.max.lon).contains(&point.lon)
+    }
+
+    /// Returns key prefixes which together cover the box, by bisecting cells
+    /// overlapping the box up to `levels` times in each dimension. Cells
+    /// inside the box are returned as soon as they are found, so the prefixes
+    /// cover disjoint areas. Points under the prefixes may still lie outside
+    /// the box near its edges.
+    pub fn key_prefixes(&self, levels: u32) -> Vec<Vec<u8>> {
+        let x = (
+            quantize(self.min.lon, 180.0) as u64,
+            quantize(self.max.lon, 180.0) as u64,
+        );
+        let y = (
+            quantize(self.min.lat, 90.0) as u64,
+            quantize(self.max.lat, 90.0) as u64,
+        );
+        let mut prefixes = Vec::new();
+        let mut cells = vec![(0u64, 0u32)];
+        while let Some((code, bits)) = cells.pop() {
+            let (cx, cy) = cell_ranges(code, bits);
+            if cx.1 < x.0 || cx.0 > x.1 || cy.1 < y.0 || cy.0 > y.1 {
+                continue;
+            }
+            let inside = x.0 <= cx.0 && cx.1 <= x.1 && y.0 <= cy.0 && cy.1 <= y.1;
+            if inside || bits >= 2 * levels.min(32) {
+                push_byte_prefixes(code, bits, &mut prefixes);
+            } else {
+                for quadrant in (0..4).rev() {
+                    cells.push((code | quadrant << (62 - bits), bits + 2));
+                }
+            }
+        }
+        prefixes
+    }
+}
+
+/// Maps a coordinate in `[-bound, bound]` to `[0, 2^32)`.
+fn quantize(value: f64, bound: f64) -> u32 {
+    let scaled = (value + bound) / (2.0 * bound) * 4_294_967_296.0;
+    scaled.clamp(0.0, u32::MAX as f64) as u32
+}
+
+/// Interleaves the bits of `x` and `y`, starting with the top bit of `x`.
+fn interleave(x: u32, y: u32) -> u64 {
+    (spread(x) << 1) | spread(y)
+}
+
+/// Moves bit `i` of `v` to bit `2i`.
+fn spread(v: u32) -> u64 {
+    let mut v = v as u64;
+    v = (v | v << 16) & 0x0000_ffff_0000_ffff;
+    v = (v | v << 8) & 0x00ff_00ff_00ff_00ff;
+    v = (v | v << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+    v = (v | v << 2) & 0x3333_3333_3333_3333;
+    (v | v << 1) & 0x5555_5555_5555_5555
+}
+
+/// Inverse of [`spread`].
+fn compact(v: u64) -> u64 {
+    let mut v = v & 0x5555_5555_5555_5555;
+    v = (v | v >> 1) & 0x3333_3333_3333_3333;
+    v = (v | v >> 2) & 0x0f0f_0f0f_0f0f_0f0f;
+    v = (v | v >> 4) & 0x00ff_00ff_00ff_00ff;
+    v = (v | v >> 8) & 0x0000_ffff_0000_ffff;
+    (v | v >> 16) & 0x0000_0000_ffff_ffff
+}
+
+/// Returns the inclusive quantized longitude and latitude ranges of the cell
+/// whose code starts with the top `bits` bits of `code`.
+fn cell_ranges(code: u64, bits: u32) -> ((u64, u64), (u64, u64)) {
+    let (x_bits, y_bits) = (bits.div_ceil(2), bits / 2);
+    let (x, y) = (compact(code >> 1), compact(code));
+    let range = |low: u64, bits: u32| (low, low | ((1u64 << (32 - bits)) - 1));
+    (range(x, x_bits), range(y, y_bits))
+}
+
+/// Appends the byte prefixes covering exactly the keys whose code starts with
+/// the top `bits` bits of `code`.
+fn push_byte_prefixes(code: u64, bits: u32, prefixes: &mut Vec<Vec<u8>>) {
+    let len = bits.div_ceil(8) as usize;
+    let bytes = code.to_be_bytes();
+    let free = len as u32 * 8 - bits;
+    for low in 0..1u8 << free {
+        let mut prefix = bytes[..len].to_vec();
+        if let Some(last) = prefix.last_mut() {
+            *last |= low;
+        }
+        prefixes.push(prefix);
+    }
+}
+
+/// A persistent map from points, each with an id distinguishing points at
+/// the same location, to values.
+#[derive(Clone, Debug)]
+pub struct SpatialIndex<V> {
+    tree: art::Tree<(Point, V)>,
+}
+
+impl<V> Default for SpatialIndex<V> {
+    fn default() -> Self {
+        SpatialIndex {
+            tree: art::Tree::default(),
+        }
+    }
+}
+
+fn key(point: &Point, id: &[u8]) -> Vec<u8> {
+    let mut key = point.z_order().to_be_bytes().to_vec();
+    key.extend_from_slice(id);
+    key
+}
+
+impl<V> SpatialIndex<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Returns the underlying tree, keyed by the Z-order code followed by the
+    /// id.
+    pub fn tree(&self) -> &art::Tree<(Point, V)> {
+        &self.tree
+    }
+}
+
+impl<V: Clone> SpatialIndex<V> {
+    pub fn get(&self, point: &Point, id: &[u8]) -> Option<&V> {
+        self.tree.get(key(point, id)).map(|(_, value)| value)
+    }
+
+    pub fn insert(&self, point: Point, id: &[u8], value: V) -> Self {
+        SpatialIndex {
+            tree: self.tree.insert(key(&point, id), (point, value)),
+        }
+    }
+
+    pub fn remove(&self, point: &Point, id: &[u8]) -> Self {
+        SpatialIndex {
+            tree: self.tree.delete(key(point, id)),
+        }
+    }
+
+    /// Returns the points inside `bbox` with their ids and values, in Z-order.
+    pub fn query(&self, bbox: &BoundingBox) -> Vec<(&Point, &[u8], &V)> {
+        self.query_with_levels(bbox, DEFAULT_QUERY_LEVELS)
+    }
+
+    /// Like [`SpatialIndex::query`] with a configurable cell refinement, see
+    /// [`BoundingBox::key_prefixes`]. More levels scan fewer points outside
+    /// the box at the cost of more prefix scans.
+    pub fn query_with_levels(&self, bbox: &BoundingBox, levels: u32) -> Vec<(&Point, &[u8], &V)> {
+        let mut prefixes = bbox.key_prefixes(levels);
+        prefixes.sort();
+        prefixes
+            .iter()
+            .flat_map(|prefix| self.tree.scan_prefix(prefix))
+            .filter(|(_, (point, _))| bbox.contains(point))
+            .map(|(key, (point, value))| (point, &key[8..], value))
+            .collect()
+    }
+}
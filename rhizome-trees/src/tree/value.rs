@@ -0,0 +1,863 @@
+//! Key and value types with order-preserving, hashable encodings.
+//!
+//! The [`Ord`] implementation of each key type agrees with the lexicographic
+//! order of its byte encoding, so keys sort the same way in memory as they do
+//! once serialized to a byte-ordered store. [`KeyCodec`] and [`ValueCodec`]
+//! expose those encodings to the code which reads and writes trees as bytes,
+//! such as snapshots.
+//!
+//! There are wrappers for the integer types, booleans, strings, UUIDs and
+//! byte strings of fixed or any length. [`CompositeKey`] combines several
+//! keys into one, and [`KeyBuilder`] encodes keys of several fields one
+//! field at a time.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::ops::Bound::{self, Excluded, Included, Unbounded};
+
+use crate::tree::hash::{Hashable, Update};
+use crate::{Error, Result};
+
+/// A value which can be written to and read back from bytes.
+pub trait ValueCodec: Sized {
+    /// Appends the encoding of the value to `buf`.
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Reads a value from the whole of `bytes`.
+    fn decode(bytes: &[u8]) -> Result<Self>;
+
+    fn to_encoded(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        buf
+    }
+}
+
+/// A key which can be written to and read back from bytes, and whose
+/// encodings can be ordered without decoding them.
+pub trait KeyCodec: ValueCodec + Ord {
+    /// Compares two encoded keys in the order of the decoded keys. The
+    /// default compares the bytes, which is right for every key type whose
+    /// encoding preserves its order, as all the types of this module do.
+    fn compare(a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+
+    /// The length of every encoding, for key types whose encodings all have
+    /// the same length. [`CompositeKey`] writes the other ones escaped and
+    /// terminated unless they come last.
+    const FIXED_WIDTH: Option<usize> = None;
+}
+
+fn fixed<const N: usize>(bytes: &[u8], what: &str) -> Result<[u8; N]> {
+    match bytes.try_into() {
+        Ok(bytes) => Ok(bytes),
+        Err(_) => Err(Error::codec(format!(
+            "{} takes {} bytes, not {}",
+            what,
+            N,
+            bytes.len()
+        ))),
+    }
+}
+
+impl ValueCodec for Vec<u8> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl KeyCodec for Vec<u8> {}
+
+impl ValueCodec for () {
+    fn encode(&self, _buf: &mut Vec<u8>) {}
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if !bytes.is_empty() {
+            return Err(Error::codec(format!(
+                "unit value with {} bytes",
+                bytes.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// An arbitrary byte string, ordered lexicographically.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct BytesValue(pub Vec<u8>);
+
+impl BytesValue {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for BytesValue {
+    fn from(value: Vec<u8>) -> Self {
+        BytesValue(value)
+    }
+}
+
+impl From<&[u8]> for BytesValue {
+    fn from(value: &[u8]) -> Self {
+        BytesValue(value.to_vec())
+    }
+}
+
+impl Hashable for BytesValue {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(&self.0);
+    }
+}
+
+impl ValueCodec for BytesValue {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.0);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        Ok(BytesValue(bytes.to_vec()))
+    }
+}
+
+impl KeyCodec for BytesValue {}
+
+/// Defines a wrapper of an unsigned integer encoded as its big-endian bytes,
+/// which sort in numeric order.
+macro_rules! unsigned_key {
+    ($(#[$attr:meta])* $name:ident($int:ty), $width:literal, $what:literal) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        #[cfg_attr(
+            feature = "borsh",
+            derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+        )]
+        pub struct $name(pub $int);
+
+        impl $name {
+            pub fn to_bytes(&self) -> [u8; $width] {
+                self.0.to_be_bytes()
+            }
+
+            pub fn from_bytes(bytes: [u8; $width]) -> Self {
+                $name(<$int>::from_be_bytes(bytes))
+            }
+        }
+
+        impl From<$int> for $name {
+            fn from(value: $int) -> Self {
+                $name(value)
+            }
+        }
+
+        impl Hashable for $name {
+            fn update_hash<H: Update>(&self, hasher: &mut H) {
+                hasher.update(&self.to_bytes());
+            }
+        }
+
+        impl ValueCodec for $name {
+            fn encode(&self, buf: &mut Vec<u8>) {
+                buf.extend_from_slice(&self.to_bytes());
+            }
+
+            fn decode(bytes: &[u8]) -> Result<Self> {
+                Ok($name::from_bytes(fixed(bytes, $what)?))
+            }
+        }
+
+        impl KeyCodec for $name {
+            const FIXED_WIDTH: Option<usize> = Some($width);
+        }
+    };
+}
+
+/// Defines a wrapper of a signed integer encoded as its big-endian bytes
+/// with the sign bit flipped, so that negative numbers sort first.
+macro_rules! signed_key {
+    ($(#[$attr:meta])* $name:ident($int:ty, $unsigned:ty), $width:literal, $what:literal) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        #[cfg_attr(
+            feature = "borsh",
+            derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+        )]
+        pub struct $name(pub $int);
+
+        impl $name {
+            const SIGN: $unsigned = 1 << (<$unsigned>::BITS - 1);
+
+            pub fn to_bytes(&self) -> [u8; $width] {
+                ((self.0 as $unsigned) ^ $name::SIGN).to_be_bytes()
+            }
+
+            pub fn from_bytes(bytes: [u8; $width]) -> Self {
+                $name((<$unsigned>::from_be_bytes(bytes) ^ $name::SIGN) as $int)
+            }
+        }
+
+        impl From<$int> for $name {
+            fn from(value: $int) -> Self {
+                $name(value)
+            }
+        }
+
+        impl Hashable for $name {
+            fn update_hash<H: Update>(&self, hasher: &mut H) {
+                hasher.update(&self.to_bytes());
+            }
+        }
+
+        impl ValueCodec for $name {
+            fn encode(&self, buf: &mut Vec<u8>) {
+                buf.extend_from_slice(&self.to_bytes());
+            }
+
+            fn decode(bytes: &[u8]) -> Result<Self> {
+                Ok($name::from_bytes(fixed(bytes, $what)?))
+            }
+        }
+
+        impl KeyCodec for $name {
+            const FIXED_WIDTH: Option<usize> = Some($width);
+        }
+    };
+}
+
+unsigned_key!(
+    /// A `u8` encoded as a single byte.
+    U8BigEndian(u8), 1, "a u8"
+);
+unsigned_key!(
+    /// A `u16` encoded as 2 big-endian bytes.
+    U16BigEndian(u16), 2, "a u16"
+);
+unsigned_key!(
+    /// A `u32` encoded as 4 big-endian bytes.
+    U32BigEndian(u32), 4, "a u32"
+);
+unsigned_key!(
+    /// A `u64` encoded as 8 big-endian bytes, typically a version or a height.
+    U64BigEndian(u64), 8, "a u64"
+);
+unsigned_key!(
+    /// A `u128` encoded as 16 big-endian bytes.
+    U128BigEndian(u128), 16, "a u128"
+);
+signed_key!(
+    /// An `i8` encoded as a single byte with the sign bit flipped.
+    I8BigEndian(i8, u8), 1, "an i8"
+);
+signed_key!(
+    /// An `i16` encoded as 2 big-endian bytes with the sign bit flipped.
+    I16BigEndian(i16, u16), 2, "an i16"
+);
+signed_key!(
+    /// An `i32` encoded as 4 big-endian bytes with the sign bit flipped.
+    I32BigEndian(i32, u32), 4, "an i32"
+);
+signed_key!(
+    /// An `i64` encoded as 8 big-endian bytes with the sign bit flipped.
+    I64BigEndian(i64, u64), 8, "an i64"
+);
+signed_key!(
+    /// An `i128` encoded as 16 big-endian bytes with the sign bit flipped.
+    I128BigEndian(i128, u128), 16, "an i128"
+);
+
+/// A boolean encoded as a single byte, 0 for `false` and 1 for `true`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct BoolValue(pub bool);
+
+impl From<bool> for BoolValue {
+    fn from(value: bool) -> Self {
+        BoolValue(value)
+    }
+}
+
+impl Hashable for BoolValue {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(&[self.0 as u8]);
+    }
+}
+
+impl ValueCodec for BoolValue {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.0 as u8);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        match fixed(bytes, "a bool")? {
+            [0] => Ok(BoolValue(false)),
+            [1] => Ok(BoolValue(true)),
+            [byte] => Err(Error::codec(format!("invalid bool {}", byte))),
+        }
+    }
+}
+
+impl KeyCodec for BoolValue {
+    const FIXED_WIDTH: Option<usize> = Some(1);
+}
+
+/// A UTF-8 string, ordered like its bytes, which is the order of [`str`].
+/// Trees keyed by strings can be read with `&str` keys.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct StringValue(pub String);
+
+impl StringValue {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for StringValue {
+    fn from(value: String) -> Self {
+        StringValue(value)
+    }
+}
+
+impl From<&str> for StringValue {
+    fn from(value: &str) -> Self {
+        StringValue(value.to_owned())
+    }
+}
+
+impl Borrow<str> for StringValue {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Hashable for StringValue {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(self.0.as_bytes());
+    }
+}
+
+impl ValueCodec for StringValue {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.0.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        Ok(StringValue(
+            std::str::from_utf8(bytes).map_err(Error::codec)?.to_owned(),
+        ))
+    }
+}
+
+impl KeyCodec for StringValue {}
+
+/// A byte string of exactly `N` bytes, such as a hash or an address.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct FixedBytes<const N: usize>(pub [u8; N]);
+
+impl<const N: usize> FixedBytes<N> {
+    pub fn as_bytes(&self) -> &[u8; N] {
+        &self.0
+    }
+}
+
+impl<const N: usize> Default for FixedBytes<N> {
+    fn default() -> Self {
+        FixedBytes([0; N])
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for FixedBytes<N> {
+    fn from(value: [u8; N]) -> Self {
+        FixedBytes(value)
+    }
+}
+
+impl<const N: usize> Borrow<[u8; N]> for FixedBytes<N> {
+    fn borrow(&self) -> &[u8; N] {
+        &self.0
+    }
+}
+
+impl<const N: usize> Hashable for FixedBytes<N> {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(&self.0);
+    }
+}
+
+/// Serializes as a byte string, since serde only implements its traits for
+/// arrays of up to 32 elements.
+#[cfg(feature = "serde")]
+impl<const N: usize> serde::Serialize for FixedBytes<N> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const N: usize> serde::Deserialize<'de> for FixedBytes<N> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes: Vec<u8> = serde::Deserialize::deserialize(deserializer)?;
+        let len = bytes.len();
+        match bytes.try_into() {
+            Ok(bytes) => Ok(FixedBytes(bytes)),
+            Err(_) => Err(serde::de::Error::invalid_length(
+                len,
+                &format!("{} bytes", N).as_str(),
+            )),
+        }
+    }
+}
+
+impl<const N: usize> ValueCodec for FixedBytes<N> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.0);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        Ok(FixedBytes(fixed(bytes, "a fixed byte string")?))
+    }
+}
+
+impl<const N: usize> KeyCodec for FixedBytes<N> {
+    const FIXED_WIDTH: Option<usize> = Some(N);
+}
+
+/// A UUID encoded as its 16 bytes, which sort like its usual text form.
+/// With the `uuid` feature, it converts to and from [`uuid::Uuid`].
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct Uuid(pub [u8; 16]);
+
+impl Uuid {
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+impl From<[u8; 16]> for Uuid {
+    fn from(value: [u8; 16]) -> Self {
+        Uuid(value)
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl From<uuid::Uuid> for Uuid {
+    fn from(value: uuid::Uuid) -> Self {
+        Uuid(value.into_bytes())
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl From<Uuid> for uuid::Uuid {
+    fn from(value: Uuid) -> Self {
+        uuid::Uuid::from_bytes(value.0)
+    }
+}
+
+impl Hashable for Uuid {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(&self.0);
+    }
+}
+
+impl ValueCodec for Uuid {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.0);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        Ok(Uuid(fixed(bytes, "a uuid")?))
+    }
+}
+
+impl KeyCodec for Uuid {
+    const FIXED_WIDTH: Option<usize> = Some(16);
+}
+
+/// A point in time relative to the unix epoch with nanosecond precision.
+///
+/// Encoded as 12 bytes: the seconds as a big-endian `i64` with the sign bit
+/// flipped (so negative times sort first) followed by the nanoseconds as a
+/// big-endian `u32`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct Timestamp {
+    seconds: i64,
+    nanos: u32,
+}
+
+const NANOS_PER_SECOND: u32 = 1_000_000_000;
+
+impl Timestamp {
+    /// Creates a timestamp, carrying any whole seconds in `nanos` over into
+    /// `seconds`.
+    pub fn new(seconds: i64, nanos: u32) -> Self {
+        Timestamp {
+            seconds: seconds + (nanos / NANOS_PER_SECOND) as i64,
+            nanos: nanos % NANOS_PER_SECOND,
+        }
+    }
+
+    pub fn seconds(&self) -> i64 {
+        self.seconds
+    }
+
+    pub fn nanos(&self) -> u32 {
+        self.nanos
+    }
+
+    pub fn to_bytes(&self) -> [u8; 12] {
+        let mut bytes = [0u8; 12];
+        bytes[..8].copy_from_slice(&((self.seconds as u64) ^ (1 << 63)).to_be_bytes());
+        bytes[8..].copy_from_slice(&self.nanos.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: [u8; 12]) -> Self {
+        let mut seconds = [0u8; 8];
+        seconds.copy_from_slice(&bytes[..8]);
+        let mut nanos = [0u8; 4];
+        nanos.copy_from_slice(&bytes[8..]);
+        Timestamp::new(
+            (u64::from_be_bytes(seconds) ^ (1 << 63)) as i64,
+            u32::from_be_bytes(nanos),
+        )
+    }
+}
+
+impl From<std::time::SystemTime> for Timestamp {
+    fn from(value: std::time::SystemTime) -> Self {
+        match value.duration_since(std::time::UNIX_EPOCH) {
+            Ok(after) => Timestamp::new(after.as_secs() as i64, after.subsec_nanos()),
+            Err(before) => {
+                let before = before.duration();
+                match before.subsec_nanos() {
+                    0 => Timestamp::new(-(before.as_secs() as i64), 0),
+                    nanos => {
+                        Timestamp::new(-(before.as_secs() as i64) - 1, NANOS_PER_SECOND - nanos)
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Hashable for Timestamp {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(&self.to_bytes());
+    }
+}
+
+/// Serializes as the pair of seconds and nanoseconds.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Timestamp {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&(self.seconds, self.nanos), serializer)
+    }
+}
+
+/// Carries whole seconds in the nanoseconds over like [`Timestamp::new`].
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Timestamp {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (seconds, nanos) = serde::Deserialize::deserialize(deserializer)?;
+        Ok(Timestamp::new(seconds, nanos))
+    }
+}
+
+/// Written as the seconds then the nanoseconds, which must be below 10^9 to
+/// keep the encoding canonical.
+#[cfg(feature = "borsh")]
+impl borsh::BorshSerialize for Timestamp {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.seconds.serialize(writer)?;
+        self.nanos.serialize(writer)
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl borsh::BorshDeserialize for Timestamp {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let seconds = i64::deserialize_reader(reader)?;
+        let nanos = u32::deserialize_reader(reader)?;
+        if nanos >= NANOS_PER_SECOND {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "timestamp with out of range nanoseconds",
+            ));
+        }
+        Ok(Timestamp { seconds, nanos })
+    }
+}
+
+impl ValueCodec for Timestamp {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_bytes());
+    }
+
+    /// Fails on encodings with 10^9 nanoseconds or more, which
+    /// [`Timestamp::to_bytes`] never produces.
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let timestamp = Timestamp::from_bytes(fixed(bytes, "a timestamp")?);
+        if timestamp.to_bytes() != bytes {
+            return Err(Error::codec("timestamp with out of range nanoseconds"));
+        }
+        Ok(timestamp)
+    }
+}
+
+impl KeyCodec for Timestamp {
+    const FIXED_WIDTH: Option<usize> = Some(12);
+}
+
+/// A byte key prefixed by a version, ordered by version first.
+///
+/// Because the version has a fixed width, the concatenated encoding is
+/// unambiguous and sorts the same way as the tuple.
+pub type VersionedKey = (U64BigEndian, BytesValue);
+
+impl Hashable for VersionedKey {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        self.0.update_hash(hasher);
+        self.1.update_hash(hasher);
+    }
+}
+
+impl ValueCodec for VersionedKey {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.0.encode(buf);
+        self.1.encode(buf);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 8 {
+            return Err(Error::codec(format!(
+                "versioned key of {} bytes has no version",
+                bytes.len()
+            )));
+        }
+        let (version, key) = bytes.split_at(8);
+        Ok((U64BigEndian::decode(version)?, BytesValue::decode(key)?))
+    }
+}
+
+impl KeyCodec for VersionedKey {}
+
+/// A key made of the parts of a tuple of two to four keys, ordered by the
+/// first part, then the second, and so on.
+///
+/// The parts are encoded one after the other. A part of a type with a
+/// [`KeyCodec::FIXED_WIDTH`] is written as is, and so is the last part. Any
+/// other part is written with each zero byte doubled as `00 ff` and ends
+/// with `00 00`, which sorts before every byte a longer part could continue
+/// with. The encoding of the composite thus sorts like the tuple as long as
+/// the encodings of its parts sort like the parts, as the encodings of this
+/// module's types do. A pair of a fixed-width key and another key is
+/// encoded like [`VersionedKey`].
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct CompositeKey<T>(pub T);
+
+impl<T> From<T> for CompositeKey<T> {
+    fn from(value: T) -> Self {
+        CompositeKey(value)
+    }
+}
+
+/// Writes a part of a [`CompositeKey`] other than the last one.
+fn encode_part<T: KeyCodec>(part: &T, buf: &mut Vec<u8>) {
+    if T::FIXED_WIDTH.is_some() {
+        return part.encode(buf);
+    }
+    for byte in part.to_encoded() {
+        buf.push(byte);
+        if byte == 0 {
+            buf.push(0xff);
+        }
+    }
+    buf.extend_from_slice(&[0, 0]);
+}
+
+/// Reads a part of a [`CompositeKey`] other than the last one from the front
+/// of `bytes`.
+fn decode_part<T: KeyCodec>(bytes: &mut &[u8]) -> Result<T> {
+    if let Some(width) = T::FIXED_WIDTH {
+        if bytes.len() < width {
+            return Err(Error::codec(format!(
+                "composite key ends in a part of {} bytes",
+                width
+            )));
+        }
+        let (part, rest) = bytes.split_at(width);
+        *bytes = rest;
+        return T::decode(part);
+    }
+    let mut part = Vec::new();
+    let mut i = 0;
+    loop {
+        match bytes[i..] {
+            [0, 0, ..] => break,
+            [0, 0xff, ..] => {
+                part.push(0);
+                i += 2;
+            }
+            [byte, ..] if byte != 0 => {
+                part.push(byte);
+                i += 1;
+            }
+            _ => return Err(Error::codec("unterminated part of a composite key")),
+        }
+    }
+    *bytes = &bytes[i + 2..];
+    T::decode(&part)
+}
+
+/// Implements the codecs of a [`CompositeKey`] of a tuple, whose last part
+/// is listed apart from the others.
+macro_rules! composite_key {
+    ($($part:ident $index:tt),+; $last:ident $last_index:tt) => {
+        impl<$($part: KeyCodec,)+ $last: KeyCodec> Hashable for CompositeKey<($($part,)+ $last)> {
+            fn update_hash<H: Update>(&self, hasher: &mut H) {
+                hasher.update(&self.to_encoded());
+            }
+        }
+
+        impl<$($part: KeyCodec,)+ $last: KeyCodec> ValueCodec for CompositeKey<($($part,)+ $last)> {
+            fn encode(&self, buf: &mut Vec<u8>) {
+                $(encode_part(&(self.0).$index, buf);)+
+                (self.0).$last_index.encode(buf);
+            }
+
+            fn decode(mut bytes: &[u8]) -> Result<Self> {
+                let bytes = &mut bytes;
+                Ok(CompositeKey((
+                    $(decode_part::<$part>(bytes)?,)+
+                    $last::decode(bytes)?,
+                )))
+            }
+        }
+
+        impl<$($part: KeyCodec,)+ $last: KeyCodec> KeyCodec for CompositeKey<($($part,)+ $last)> {}
+    };
+}
+
+composite_key!(A 0; B 1);
+composite_key!(A 0, B 1; C 2);
+composite_key!(A 0, B 1, C 2; D 3);
+
+/// Starts building a key of several fields, see [`KeyBuilder`].
+pub fn key() -> KeyBuilder {
+    KeyBuilder::default()
+}
+
+/// Builds the encoding of a key one field at a time, e.g.
+/// `key().str(owner).u64(height).bytes(id).build()` for the entries of a
+/// secondary index of credits by owner.
+///
+/// Every field is encoded like a part of a [`CompositeKey`] other than the
+/// last one, so keys sort by their first field, then their second, and so
+/// on. Since every field is terminated, the keys whose first fields equal
+/// those of a shorter key are exactly the ones it is a prefix of, which
+/// [`KeyBuilder::prefix_range`] turns into a range to scan. A builder with
+/// the fields of a [`CompositeKey`] thus encodes it with its last field
+/// terminated too.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct KeyBuilder(Vec<u8>);
+
+impl KeyBuilder {
+    /// Appends a field of any key type.
+    pub fn field<T: KeyCodec>(mut self, field: &T) -> Self {
+        encode_part(field, &mut self.0);
+        self
+    }
+
+    pub fn bytes(self, field: &[u8]) -> Self {
+        self.field(&BytesValue::from(field))
+    }
+
+    pub fn str(self, field: &str) -> Self {
+        self.field(&StringValue::from(field))
+    }
+
+    pub fn bool(self, field: bool) -> Self {
+        self.field(&BoolValue(field))
+    }
+
+    pub fn u32(self, field: u32) -> Self {
+        self.field(&U32BigEndian(field))
+    }
+
+    pub fn u64(self, field: u64) -> Self {
+        self.field(&U64BigEndian(field))
+    }
+
+    pub fn u128(self, field: u128) -> Self {
+        self.field(&U128BigEndian(field))
+    }
+
+    pub fn i32(self, field: i32) -> Self {
+        self.field(&I32BigEndian(field))
+    }
+
+    pub fn i64(self, field: i64) -> Self {
+        self.field(&I64BigEndian(field))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn build(self) -> BytesValue {
+        BytesValue(self.0)
+    }
+
+    /// Returns the range of the keys whose first fields are the fields added
+    /// so far.
+    pub fn prefix_range(self) -> (Bound<BytesValue>, Bound<BytesValue>) {
+        let mut end = self.0.clone();
+        // The smallest byte string greater than every extension of the
+        // prefix, if there is one.
+        let upper = loop {
+            match end.pop() {
+                None => break Unbounded,
+                Some(0xff) => continue,
+                Some(byte) => {
+                    end.push(byte + 1);
+                    break Excluded(BytesValue(end));
+                }
+            }
+        };
+        (Included(BytesValue(self.0)), upper)
+    }
+}
@@ -0,0 +1,56 @@
+//! Checks that deleting an absent key from an ART or AVL tree copies nothing
+//! and returns a tree sharing the original root.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::art;
+use rhizome_trees::tree::avl;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::NodeManager;
+
+#[test]
+fn shares_art_roots() {
+    let tree = (0..300u32).fold(art::Tree::new(), |tree, i| {
+        tree.insert(format!("key{}", i * 3).into_bytes(), i)
+    });
+    // Missing leaves, inner nodes, prefixes and keys past the end.
+    for key in ["key1", "key", "ke", "zzz", "key30000", "key8970", ""] {
+        let deleted = tree.delete(key);
+        assert!(
+            Arc::ptr_eq(deleted.root().unwrap(), tree.root().unwrap()),
+            "{}",
+            key
+        );
+    }
+    let deleted = tree.delete("key3");
+    assert!(!Arc::ptr_eq(deleted.root().unwrap(), tree.root().unwrap()));
+    assert!(deleted.get("key3").is_none());
+    assert_eq!(deleted.get("key6"), Some(&2));
+
+    let empty = art::Tree::<u32>::new();
+    assert!(empty.delete("key").is_empty());
+}
+
+#[test]
+fn shares_avl_roots() {
+    let tree = (0..300u32).fold(avl::Tree::new(), |tree, i| {
+        tree.insert((i * 3).to_be_bytes().to_vec(), vec![1])
+            .unwrap()
+    });
+    let hash = tree.merkle_hash().unwrap();
+    for key in [1u32, 2, 898, 10_000] {
+        let deleted = tree.delete(&key.to_be_bytes().to_vec()).unwrap();
+        assert!(deleted.root().unwrap().same_node(tree.root().unwrap()));
+        assert_eq!(deleted.merkle_hash().unwrap(), hash);
+    }
+
+    // Saved trees keep their root pointer too.
+    let manager = Arc::new(NodeManager::in_memory());
+    let saved = avl::Tree::with_manager(manager)
+        .insert(vec![1], vec![1])
+        .unwrap()
+        .save()
+        .unwrap();
+    let deleted = saved.delete(&vec![2]).unwrap();
+    assert_eq!(deleted.root_ptr(), saved.root_ptr());
+}
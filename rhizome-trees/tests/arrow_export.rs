@@ -0,0 +1,177 @@
+//! Checks that exported record batches and Parquet files hold every entry
+//! of the exported version, in key order, with the hashes it commits to.
+#![cfg(feature = "arrow")]
+
+use std::sync::Arc;
+
+use arrow_array::{Array, BinaryArray, FixedSizeBinaryArray, RecordBatch};
+use rhizome_trees::tree::avl::arrow::ExportOptions;
+use rhizome_trees::tree::avl::node::EntryHash;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::{HashVersion, MerkleTree};
+use rhizome_trees::tree::node_manager::{MemNodeStore, NodeManager};
+use rhizome_trees::tree::value::{StringValue, ValueCodec};
+
+type Bytes = Vec<u8>;
+
+fn tree_of(len: u8) -> Tree<Bytes, Bytes> {
+    let mut tree = Tree::new();
+    // Inserted out of order, the export must sort them.
+    for key in (0..len).rev() {
+        tree = tree.insert(vec![key], vec![key; key as usize % 3]).unwrap();
+    }
+    tree
+}
+
+fn column<'a, A: 'static>(batch: &'a RecordBatch, name: &str) -> &'a A {
+    batch
+        .column_by_name(name)
+        .unwrap()
+        .as_any()
+        .downcast_ref::<A>()
+        .unwrap()
+}
+
+fn rows(batches: &[RecordBatch]) -> Vec<(Bytes, Bytes)> {
+    batches
+        .iter()
+        .flat_map(|batch| {
+            let keys: &BinaryArray = column(batch, "key");
+            let values: &BinaryArray = column(batch, "value");
+            (0..batch.num_rows())
+                .map(|i| (keys.value(i).to_vec(), values.value(i).to_vec()))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[test]
+fn batches_hold_every_entry_in_key_order() {
+    let tree = tree_of(10).save().unwrap();
+    let batches: Vec<_> = tree
+        .record_batches(ExportOptions::default().batch_rows(4))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    let sizes: Vec<_> = batches.iter().map(|batch| batch.num_rows()).collect();
+    assert_eq!(sizes, [4, 4, 2]);
+    let expected: Vec<_> = (0..10u8)
+        .map(|key| (vec![key], vec![key; key as usize % 3]))
+        .collect();
+    assert_eq!(rows(&batches), expected);
+    assert!(batches[0].column_by_name("entry_hash").is_none());
+}
+
+#[test]
+fn schema_records_the_version() {
+    let tree = tree_of(3);
+    let schema = tree.arrow_schema(&ExportOptions::default()).unwrap();
+    let root_hash: String = tree
+        .merkle_hash()
+        .unwrap()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    assert_eq!(schema.metadata()["rhizome.root_hash"], root_hash);
+    assert_eq!(schema.metadata()["rhizome.hash_version"], "1");
+    assert_eq!(schema.fields().len(), 2);
+}
+
+#[test]
+fn empty_trees_yield_no_batches() {
+    let tree = Tree::<Bytes, Bytes>::new();
+    let mut batches = tree.record_batches(ExportOptions::default()).unwrap();
+    assert_eq!(batches.schema().fields().len(), 2);
+    assert!(batches.next().is_none());
+}
+
+#[test]
+fn entry_hashes_match_the_nodes() {
+    for hash_version in [HashVersion::V0, HashVersion::V1] {
+        let manager = Arc::new(
+            NodeManager::builder(MemNodeStore::new())
+                .hash_version(hash_version)
+                .build(),
+        );
+        let mut tree = Tree::with_manager(manager);
+        for key in 0..5u8 {
+            tree = tree.insert(vec![key], vec![key, key]).unwrap();
+        }
+        let options = ExportOptions::default().entry_hashes(true);
+        let batches: Vec<_> = tree
+            .record_batches(options)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let hashes: &FixedSizeBinaryArray = column(&batches[0], "entry_hash");
+        assert_eq!(hashes.len(), 5);
+        for (i, node) in tree.iter().unwrap().enumerate() {
+            let expected = match node.unwrap().entry_hash() {
+                EntryHash::Leaf(leaf) => leaf.to_vec(),
+                EntryHash::Split { key, value } => [key, value].concat(),
+            };
+            assert_eq!(hashes.value(i), expected);
+        }
+    }
+}
+
+#[test]
+fn keys_and_values_use_their_codecs() {
+    let tree = Tree::new()
+        .insert(StringValue::from("b"), StringValue::from("beta"))
+        .unwrap()
+        .insert(StringValue::from("a"), StringValue::from("alpha"))
+        .unwrap();
+    let batch = tree
+        .record_batches(ExportOptions::default())
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let keys: &BinaryArray = column(&batch, "key");
+    let values: &BinaryArray = column(&batch, "value");
+    assert_eq!(keys.value(0), StringValue::from("a").to_encoded());
+    assert_eq!(values.value(1), StringValue::from("beta").to_encoded());
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn parquet_files_read_back() {
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let tree = tree_of(20).save().unwrap();
+    let path = std::env::temp_dir().join(format!("rhizome-export-{}.parquet", std::process::id()));
+    let options = ExportOptions::default().batch_rows(8).entry_hashes(true);
+    let written = tree
+        .export_parquet(std::fs::File::create(&path).unwrap(), options)
+        .unwrap();
+    assert_eq!(written, 20);
+
+    let reader =
+        ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(reader.metadata().num_row_groups(), 3);
+    let root_hash = tree.arrow_schema(&options).unwrap().metadata()["rhizome.root_hash"].clone();
+    assert_eq!(reader.schema().metadata()["rhizome.root_hash"], root_hash);
+    let file_metadata = reader
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .unwrap();
+    assert!(file_metadata
+        .iter()
+        .any(|entry| entry.key == "rhizome.root_hash" && entry.value == Some(root_hash.clone())));
+    let batches: Vec<_> = reader.build().unwrap().collect::<Result<_, _>>().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let expected: Vec<_> = tree
+        .iter()
+        .unwrap()
+        .map(|node| {
+            let node = node.unwrap();
+            (node.key().clone(), node.value().clone())
+        })
+        .collect();
+    assert_eq!(rows(&batches), expected);
+}
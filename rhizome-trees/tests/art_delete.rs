@@ -0,0 +1,97 @@
+//! Checks that deleting from a radix tree shrinks inner nodes through each
+//! size, collapses nodes left with a single entry, and keeps the tree a map
+//! of its remaining keys.
+
+mod art_shape;
+mod rng;
+
+use std::collections::BTreeMap;
+
+use art_shape::walk;
+use rhizome_trees::tree::art::Tree;
+use rng::Rng;
+
+#[test]
+fn shrinks_nodes_through_each_size() {
+    let mut tree = (0..=255u8).fold(Tree::new(), |tree, byte| tree.insert([byte], byte));
+    for byte in (0..=255u8).rev() {
+        tree = tree.delete([byte]);
+        let left = byte as usize;
+        let (entries, kinds) = walk(&tree);
+        assert_eq!(entries.len(), left);
+        let inner = [kinds.node4, kinds.node16, kinds.node48, kinds.node256];
+        // Nodes shrink well below the capacity of the smaller size.
+        let expected = match left {
+            0 | 1 => [0, 0, 0, 0],
+            2..=3 => [1, 0, 0, 0],
+            4..=12 => [0, 1, 0, 0],
+            13..=37 => [0, 0, 1, 0],
+            _ => [0, 0, 0, 1],
+        };
+        assert_eq!(inner, expected, "with {} keys left", left);
+    }
+    assert!(tree.is_empty());
+}
+
+#[test]
+fn collapses_single_entry_nodes() {
+    let tree = Tree::new()
+        .insert("rom", 1)
+        .insert("romane", 2)
+        .insert("romanus", 3)
+        .insert("romulus", 4);
+
+    // Without "romulus" the root holds one child, which merges into it.
+    let merged = tree.delete("romulus").delete("rom");
+    let root = merged.root().unwrap();
+    assert_eq!(root.header().unwrap().prefix(), b"roman");
+    assert_eq!(root.num_children(), 2);
+
+    // A node left with only its own leaf becomes that leaf.
+    let leaf = tree.delete("romane").delete("romanus").delete("romulus");
+    assert!(leaf.root().unwrap().header().is_none());
+    assert_eq!(leaf.get("rom"), Some(&1));
+
+    assert!(merged.delete("romane").delete("romanus").is_empty());
+    // Earlier versions are unchanged.
+    assert_eq!(tree.len(), 4);
+}
+
+#[test]
+fn matches_a_map_under_random_deletes() {
+    let mut rng = Rng(99);
+    let mut tree = Tree::new();
+    let mut model = BTreeMap::new();
+    for i in 0..40_000u32 {
+        let len = (rng.next() % 4) as usize;
+        let mut key: Vec<u8> = (0..len).map(|_| (rng.next() % 200) as u8).collect();
+        if rng.next().is_multiple_of(2) {
+            key.splice(0..0, *b"ap");
+        }
+        // Alternate between growing and shrinking phases.
+        let deletes = if (i / 10_000) % 2 == 1 { 7 } else { 3 };
+        if rng.next() % 10 < deletes {
+            tree = tree.delete(&key);
+            model.remove(&key);
+        } else {
+            tree = tree.insert(key.clone(), i);
+            model.insert(key, i);
+        }
+        if i % 500 == 0 {
+            let (entries, _) = walk(&tree);
+            assert_eq!(entries, model.clone().into_iter().collect::<Vec<_>>());
+        }
+    }
+
+    // Delete everything that's left.
+    let keys: Vec<_> = model.keys().cloned().collect();
+    for (i, key) in keys.iter().enumerate() {
+        tree = tree.delete(key);
+        model.remove(key);
+        if i % 300 == 0 {
+            let (entries, _) = walk(&tree);
+            assert_eq!(entries, model.clone().into_iter().collect::<Vec<_>>());
+        }
+    }
+    assert!(tree.is_empty());
+}
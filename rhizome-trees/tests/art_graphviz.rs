@@ -0,0 +1,65 @@
+//! Checks the Graphviz rendering of adaptive radix trees.
+
+use rhizome_trees::tree::art::Tree;
+use rhizome_trees::tree::graphviz::Format;
+
+#[test]
+fn renders_nodes_prefixes_and_edges() {
+    let tree = Tree::new()
+        .insert(b"ab".to_vec(), 1)
+        .insert(b"abc".to_vec(), 2)
+        .insert(b"abd\"\x01".to_vec(), 3)
+        .insert(b"x".to_vec(), 4);
+    let expected = r#"digraph art {
+    node [fontname=monospace];
+    n0 [label="Node4\nprefix \"\"\n2/4 children"];
+    n1 [label="Node4\nprefix b\n2/4 children"];
+    n2 [shape=box, label="Leaf\nab"];
+    n1 -> n2 [style=dashed];
+    n3 [shape=box, label="Leaf\nabc"];
+    n1 -> n3 [label="c"];
+    n4 [shape=box, label="Leaf\nabd\"\\x01"];
+    n1 -> n4 [label="d"];
+    n0 -> n1 [label="a"];
+    n5 [shape=box, label="Leaf\nx"];
+    n0 -> n5 [label="x"];
+}
+"#;
+    assert_eq!(tree.to_graphviz(), expected);
+}
+
+#[test]
+fn shows_occupancy_of_larger_nodes() {
+    let mut tree = Tree::new();
+    for byte in 0..20u8 {
+        tree = tree.insert(vec![byte], ());
+    }
+    let dot = tree.to_graphviz();
+    assert!(dot.contains("Node48\\nprefix \\\"\\\"\\n20/48 children"));
+    assert_eq!(dot.matches("shape=box").count(), 20);
+    assert!(dot.contains("[label=\"\\\\x13\"]"));
+}
+
+#[test]
+fn empty_trees_render_an_empty_graph() {
+    assert_eq!(
+        Tree::<()>::new().to_graphviz(),
+        "digraph art {\n    node [fontname=monospace];\n}\n"
+    );
+}
+
+#[test]
+fn renders_to_writers() {
+    let tree = Tree::new().insert(b"a".to_vec(), 1);
+    let mut out = Vec::new();
+    tree.render_graphviz(Format::Dot, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), tree.to_graphviz());
+
+    // Images need a Graphviz installation, without which rendering fails
+    // rather than panicking.
+    let mut out = Vec::new();
+    match tree.render_graphviz(Format::Svg, &mut out) {
+        Ok(()) => assert!(String::from_utf8_lossy(&out).contains("<svg")),
+        Err(err) => assert!(format!("{:#}", err).contains("dot"), "{:#}", err),
+    }
+}
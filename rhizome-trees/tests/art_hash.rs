@@ -0,0 +1,82 @@
+//! Checks that a radix tree's root hash commits to its entries alone,
+//! regardless of the order they were inserted in or the sizes its nodes
+//! passed through.
+
+use rhizome_trees::tree::art::Tree;
+use rhizome_trees::tree::hash::{MerkleTree, EMPTY_HASH};
+use rhizome_trees::tree::value::BytesValue;
+
+/// Keys of one to four bytes, many of them prefixes of others.
+fn keys() -> Vec<Vec<u8>> {
+    (0..3000u32)
+        .map(|i| {
+            let mut key = i.wrapping_mul(2_654_435_761).to_be_bytes().to_vec();
+            key.truncate((i % 4 + 1) as usize);
+            key
+        })
+        .collect()
+}
+
+fn filled<'a>(keys: impl IntoIterator<Item = &'a Vec<u8>>) -> Tree<BytesValue> {
+    keys.into_iter().fold(Tree::new(), |tree, key| {
+        tree.insert(key.clone(), BytesValue(key.clone()))
+    })
+}
+
+#[test]
+fn hashes_an_empty_tree() {
+    assert_eq!(Tree::<BytesValue>::new().merkle_hash().unwrap(), EMPTY_HASH);
+    let tree = Tree::new().insert("a", BytesValue(vec![]));
+    assert_eq!(tree.delete("a").merkle_hash().unwrap(), EMPTY_HASH);
+}
+
+#[test]
+fn ignores_insertion_order_and_node_sizes() {
+    let keys = keys();
+    let forward = filled(&keys);
+    let root = forward.merkle_hash().unwrap();
+    let mut backward = filled(keys.iter().rev());
+    assert_eq!(backward.merkle_hash().unwrap(), root);
+
+    // Grow a node to its largest size and shrink it back.
+    for byte in 0..=255u8 {
+        backward = backward.insert(vec![7, byte, 1, 2, 3], BytesValue(vec![]));
+    }
+    assert_ne!(backward.merkle_hash().unwrap(), root);
+    for byte in 0..=255u8 {
+        backward = backward.delete([7, byte, 1, 2, 3]);
+    }
+    assert_eq!(backward.merkle_hash().unwrap(), root);
+}
+
+#[test]
+fn commits_to_values() {
+    let keys = keys();
+    let tree = filled(&keys);
+    let root = tree.merkle_hash().unwrap();
+
+    let changed = tree.insert(keys[5].clone(), BytesValue(vec![9]));
+    assert_ne!(changed.merkle_hash().unwrap(), root);
+    // Cached hashes of the earlier version are unaffected.
+    assert_eq!(tree.merkle_hash().unwrap(), root);
+    let restored = changed.insert(keys[5].clone(), BytesValue(keys[5].clone()));
+    assert_eq!(restored.merkle_hash().unwrap(), root);
+}
+
+#[test]
+fn commits_to_keys() {
+    // The same values under keys which differ only in where a prefix ends.
+    let a = Tree::new()
+        .insert("ab", BytesValue(vec![1]))
+        .insert("abc", BytesValue(vec![2]));
+    let b = Tree::new()
+        .insert("ab", BytesValue(vec![1]))
+        .insert("abd", BytesValue(vec![2]));
+    let c = Tree::new()
+        .insert("a", BytesValue(vec![1]))
+        .insert("abc", BytesValue(vec![2]));
+    let roots = [a, b, c].map(|tree| tree.merkle_hash().unwrap());
+    assert_ne!(roots[0], roots[1]);
+    assert_ne!(roots[0], roots[2]);
+    assert_ne!(roots[1], roots[2]);
+}
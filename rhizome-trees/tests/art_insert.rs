@@ -0,0 +1,96 @@
+//! Checks that inserting into a radix tree grows inner nodes through each
+//! size, splits compressed prefixes, and keeps the tree a map of its keys.
+
+mod art_shape;
+mod rng;
+
+use std::collections::BTreeMap;
+
+use art_shape::walk;
+use rhizome_trees::tree::art::Tree;
+use rng::Rng;
+
+#[test]
+fn grows_nodes_through_each_size() {
+    let mut tree = Tree::new();
+    for byte in 0..=255u8 {
+        tree = tree.insert(vec![byte], byte);
+        let (_, kinds) = walk(&tree);
+        let inner = [kinds.node4, kinds.node16, kinds.node48, kinds.node256];
+        let expected = match byte as usize + 1 {
+            1 => [0, 0, 0, 0],
+            2..=4 => [1, 0, 0, 0],
+            5..=16 => [0, 1, 0, 0],
+            17..=48 => [0, 0, 1, 0],
+            _ => [0, 0, 0, 1],
+        };
+        assert_eq!(inner, expected, "after {} keys", byte as usize + 1);
+        assert_eq!(kinds.leaves, byte as usize + 1);
+    }
+    assert_eq!(tree.len(), 256);
+    for byte in 0..=255u8 {
+        assert_eq!(tree.get([byte]), Some(&byte));
+    }
+}
+
+#[test]
+fn splits_compressed_prefixes() {
+    let tree = Tree::new().insert("romane", 1).insert("romanus", 2);
+    let root = tree.root().unwrap();
+    assert_eq!(root.header().unwrap().prefix(), b"roman");
+
+    // A key diverging inside the prefix splits it, and a key ending inside
+    // it becomes the leaf of the new node.
+    let tree = tree.insert("romulus", 3).insert("rom", 4);
+    let root = tree.root().unwrap();
+    assert_eq!(root.header().unwrap().prefix(), b"rom");
+    assert!(root.header().unwrap().leaf().is_some());
+    let (entries, _) = walk(&tree);
+    assert_eq!(
+        entries,
+        vec![
+            (b"rom".to_vec(), 4),
+            (b"romane".to_vec(), 1),
+            (b"romanus".to_vec(), 2),
+            (b"romulus".to_vec(), 3),
+        ]
+    );
+}
+
+#[test]
+fn overwrites_existing_keys() {
+    let tree = Tree::new().insert("a", 1).insert("ab", 2);
+    let overwritten = tree.insert("ab", 3).insert("a", 4);
+    assert_eq!(overwritten.len(), 2);
+    assert_eq!(overwritten.get("a"), Some(&4));
+    assert_eq!(overwritten.get("ab"), Some(&3));
+    // Earlier versions are unchanged.
+    assert_eq!(tree.get("ab"), Some(&2));
+}
+
+#[test]
+fn matches_a_map_under_random_inserts() {
+    let mut rng = Rng(99);
+    let mut tree = Tree::new();
+    let mut model = BTreeMap::new();
+    for i in 0..20_000u32 {
+        // Short keys, half of them sharing a prefix, so that keys are often
+        // prefixes of each other.
+        let len = (rng.next() % 4) as usize;
+        let mut key: Vec<u8> = (0..len).map(|_| (rng.next() % 200) as u8).collect();
+        if rng.next().is_multiple_of(2) {
+            key.splice(0..0, *b"ap");
+        }
+        tree = tree.insert(key.clone(), i);
+        model.insert(key, i);
+        if i % 500 == 0 {
+            let (entries, _) = walk(&tree);
+            assert_eq!(entries, model.clone().into_iter().collect::<Vec<_>>());
+        }
+    }
+    assert_eq!(tree.len(), model.len() as u64);
+    for (key, value) in &model {
+        assert_eq!(tree.get(key), Some(value));
+    }
+    assert_eq!(tree.get(b"zzzzz"), None);
+}
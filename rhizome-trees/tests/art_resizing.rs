@@ -0,0 +1,62 @@
+//! Checks that radix tree nodes grow and shrink at the configured thresholds,
+//! and that every conversion is counted.
+
+mod art_shape;
+
+use art_shape::{walk, Kinds};
+use rhizome_trees::tree::art::node::{Resizing, Transition};
+use rhizome_trees::tree::art::Tree;
+
+#[test]
+fn rejects_invalid_thresholds() {
+    let grow = Resizing::default().grow_at(Transition::Node4To16, 5);
+    assert!(Tree::<u8>::with_resizing(grow).is_err());
+    let grow = Resizing::default().grow_at(Transition::Node4To16, 0);
+    assert!(Tree::<u8>::with_resizing(grow).is_err());
+    let shrink = Resizing::default().shrink_at(Transition::Node4To16, 4);
+    assert!(Tree::<u8>::with_resizing(shrink).is_err());
+}
+
+#[test]
+fn grows_early() {
+    let resizing = Resizing::default()
+        .grow_at(Transition::Node4To16, 2)
+        .shrink_at(Transition::Node4To16, 1);
+    let tree = (0..3u8).fold(Tree::with_resizing(resizing).unwrap(), |tree, b| {
+        tree.insert(vec![b], b)
+    });
+    let (entries, kinds) = walk(&tree);
+    assert_eq!(entries.len(), 3);
+    assert_eq!(
+        kinds,
+        Kinds {
+            leaves: 3,
+            node16: 1,
+            ..Kinds::default()
+        }
+    );
+    assert_eq!(tree.resize_stats().grown, [1, 0, 0]);
+
+    // Two children are above the shrink threshold.
+    let tree = tree.delete([0u8]);
+    assert_eq!(walk(&tree).1.node16, 1);
+    assert_eq!(tree.resize_stats().shrunk, [0, 0, 0]);
+}
+
+#[test]
+fn counts_default_conversions() {
+    let empty = Tree::<u8>::new();
+    let mut tree = (0..=255u8).fold(empty.clone(), |tree, b| tree.insert(vec![b], b));
+    assert_eq!(walk(&tree).1.node256, 1);
+    for b in 0..=255u8 {
+        assert_eq!(tree.get([b]), Some(&b));
+    }
+    // The counts are shared by every version of the tree.
+    assert_eq!(empty.resize_stats().grown, [1, 1, 1]);
+
+    for b in 0..253u8 {
+        tree = tree.delete([b]);
+    }
+    assert_eq!(walk(&tree).1.node4, 1);
+    assert_eq!(empty.resize_stats().shrunk, [1, 1, 1]);
+}
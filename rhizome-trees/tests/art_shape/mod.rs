@@ -0,0 +1,80 @@
+//! Walks the nodes of a radix tree, checking the invariants of its shape
+//! and counting the inner nodes of each size.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::art::node::Node;
+use rhizome_trees::tree::art::Tree;
+
+/// The number of nodes of each kind in a tree.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Kinds {
+    pub leaves: usize,
+    pub node4: usize,
+    pub node16: usize,
+    pub node48: usize,
+    pub node256: usize,
+}
+
+/// Returns the entries of `tree` in key order along with its node counts,
+/// panicking if a leaf isn't at the path of its key, if an inner node holds
+/// more children than its size allows, or if an inner node has fewer than
+/// two entries below it and so should have been collapsed.
+pub fn walk<V: Clone>(tree: &Tree<V>) -> (Vec<(Vec<u8>, V)>, Kinds) {
+    let mut entries = Vec::new();
+    let mut kinds = Kinds::default();
+    if let Some(root) = tree.root() {
+        walk_node(root, Vec::new(), &mut entries, &mut kinds);
+    }
+    (entries, kinds)
+}
+
+fn walk_node<V: Clone>(
+    node: &Arc<Node<V>>,
+    path: Vec<u8>,
+    entries: &mut Vec<(Vec<u8>, V)>,
+    kinds: &mut Kinds,
+) {
+    let Some(header) = node.header() else {
+        let Node::Leaf(leaf) = &**node else {
+            unreachable!()
+        };
+        assert!(leaf.key().starts_with(&path), "leaf off its path");
+        entries.push((leaf.key().to_vec(), leaf.value().clone()));
+        kinds.leaves += 1;
+        return;
+    };
+    let (count, capacity) = match &**node {
+        Node::Node4(_) => (&mut kinds.node4, 4),
+        Node::Node16(_) => (&mut kinds.node16, 16),
+        Node::Node48(_) => (&mut kinds.node48, 48),
+        _ => (&mut kinds.node256, 256),
+    };
+    *count += 1;
+    let children = node.num_children();
+    assert!(
+        children <= capacity,
+        "{} children in a node of {}",
+        children,
+        capacity
+    );
+    assert!(
+        children + header.leaf().iter().len() >= 2,
+        "inner node with a single entry"
+    );
+
+    let mut path = path;
+    path.extend_from_slice(header.prefix());
+    if let Some(leaf) = header.leaf() {
+        let Node::Leaf(entry) = &**leaf else {
+            panic!("inner node in a leaf slot")
+        };
+        assert_eq!(entry.key(), &path[..]);
+        walk_node(leaf, path.clone(), entries, kinds);
+    }
+    for (byte, child) in node.children() {
+        let mut child_path = path.clone();
+        child_path.push(byte);
+        walk_node(child, child_path, entries, kinds);
+    }
+}
@@ -0,0 +1,157 @@
+//! Checks that diffing two versions of a tree reports the creates, updates
+//! and deletes between them in key order, for in-memory and stored
+//! versions alike.
+
+mod rng;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::diff::{Change, OverwriteEvents};
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::NodeManager;
+use rhizome_trees::Result;
+use rng::Rng;
+
+type Bytes = Vec<u8>;
+
+/// The changes from `base` to `new`, computed entry by entry.
+fn expected(
+    base: &BTreeMap<Bytes, Bytes>,
+    new: &BTreeMap<Bytes, Bytes>,
+) -> Vec<Change<Bytes, Bytes>> {
+    let mut keys: Vec<_> = base.keys().chain(new.keys()).cloned().collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter_map(|key| match (base.get(&key), new.get(&key)) {
+            (None, Some(value)) => Some(Change::Create {
+                value: value.clone(),
+                key,
+            }),
+            (Some(old), None) => Some(Change::Delete {
+                old: old.clone(),
+                key,
+            }),
+            (Some(old), Some(new)) if old != new => Some(Change::Update {
+                old: old.clone(),
+                new: new.clone(),
+                key,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Applies random inserts and deletes to `tree`, comparing its diff against
+/// the previous version every few steps, and saving every `save_every`
+/// steps if it isn't zero.
+fn check_random_edits(mut tree: Tree<Bytes, Bytes>, save_every: usize) {
+    let mut rng = Rng(0x12345);
+    let mut model = BTreeMap::new();
+    let mut base = (tree.clone(), model.clone());
+    for step in 1..=2000usize {
+        let key = (rng.next() % 200).to_be_bytes().to_vec();
+        let value = vec![(rng.next() % 4) as u8];
+        if rng.next().is_multiple_of(3) {
+            tree = tree.delete(&key).unwrap();
+            model.remove(&key);
+        } else {
+            tree = tree.insert(key.clone(), value.clone()).unwrap();
+            model.insert(key, value);
+        }
+        if save_every > 0 && step.is_multiple_of(save_every) {
+            tree = tree.save().unwrap();
+        }
+        if step.is_multiple_of(25) {
+            let changes = tree.diff(&base.0).collect::<Result<Vec<_>>>().unwrap();
+            assert_eq!(changes, expected(&base.1, &model));
+            base = (tree.clone(), model.clone());
+        }
+    }
+}
+
+#[test]
+fn diffs_in_memory_versions() {
+    check_random_edits(Tree::new(), 0);
+}
+
+#[test]
+fn diffs_stored_versions() {
+    let manager = Arc::new(NodeManager::in_memory());
+    check_random_edits(Tree::with_manager(manager), 7);
+}
+
+#[test]
+fn diffs_a_version_against_itself() {
+    let tree = (0u8..50).fold(Tree::new(), |tree, i| {
+        tree.insert(vec![i], vec![i]).unwrap()
+    });
+    assert_eq!(tree.diff(&tree).count(), 0);
+    let saved = tree.save().unwrap();
+    assert_eq!(saved.diff(&tree).count(), 0);
+    assert_eq!(tree.diff(&saved).count(), 0);
+}
+
+#[test]
+fn reports_overwrites_as_delete_then_create() {
+    let base = Tree::new()
+        .insert(b"a".to_vec(), b"1".to_vec())
+        .unwrap()
+        .insert(b"b".to_vec(), b"1".to_vec())
+        .unwrap();
+    let new = base
+        .insert(b"b".to_vec(), b"2".to_vec())
+        .unwrap()
+        .insert(b"c".to_vec(), b"3".to_vec())
+        .unwrap()
+        .delete(&b"a".to_vec())
+        .unwrap();
+
+    let updates = new.diff(&base).collect::<Result<Vec<_>>>().unwrap();
+    assert_eq!(
+        updates,
+        vec![
+            Change::Delete {
+                key: b"a".to_vec(),
+                old: b"1".to_vec(),
+            },
+            Change::Update {
+                key: b"b".to_vec(),
+                old: b"1".to_vec(),
+                new: b"2".to_vec(),
+            },
+            Change::Create {
+                key: b"c".to_vec(),
+                value: b"3".to_vec(),
+            },
+        ]
+    );
+
+    let events = new
+        .diff_with(&base, OverwriteEvents::DeleteThenCreate)
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(
+        events,
+        vec![
+            Change::Delete {
+                key: b"a".to_vec(),
+                old: b"1".to_vec(),
+            },
+            Change::Delete {
+                key: b"b".to_vec(),
+                old: b"1".to_vec(),
+            },
+            Change::Create {
+                key: b"b".to_vec(),
+                value: b"2".to_vec(),
+            },
+            Change::Create {
+                key: b"c".to_vec(),
+                value: b"3".to_vec(),
+            },
+        ]
+    );
+    assert!(events.iter().map(Change::key).is_sorted());
+}
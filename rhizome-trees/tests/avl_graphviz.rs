@@ -0,0 +1,151 @@
+//! Checks that the Graphviz rendering of AVL trees tells apart unsaved,
+//! stored and uncached nodes, and shows the annotations it is asked for.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::graphviz::GraphvizOptions;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::graphviz::Format;
+use rhizome_trees::tree::node_manager::{CachePolicy, MemNodeStore, NodeManager};
+
+type Bytes = Vec<u8>;
+
+fn lines_with<'a>(dot: &'a str, pattern: &str) -> Vec<&'a str> {
+    dot.lines().filter(|line| line.contains(pattern)).collect()
+}
+
+#[test]
+fn marks_unsaved_and_stored_nodes() {
+    let tree: Tree<Bytes, Bytes> = Tree::new();
+    let tree = ["b", "a", "c"]
+        .into_iter()
+        .fold(tree, |tree, key| tree.insert(key.into(), vec![]).unwrap())
+        .save()
+        .unwrap();
+    // Inserting d rewrites the path to it, and a stays stored.
+    let tree = tree.insert(b"d".to_vec(), vec![]).unwrap();
+    let dot = tree.to_graphviz().unwrap();
+
+    assert!(dot.starts_with("digraph avl {\n"));
+    assert!(dot.contains("n0 [fillcolor=lightyellow, label=\"b\\nsize 4\"];"));
+    let stored = lines_with(&dot, "lightblue");
+    assert_eq!(stored.len(), 1);
+    assert!(stored[0].contains("label=\"a\\nsize 1\\nPtr("));
+    assert_eq!(lines_with(&dot, "lightyellow").len(), 3);
+    assert!(lines_with(&dot, "dashed").is_empty());
+    assert!(dot.contains("n0 -> n1 [label=\"L\"];"));
+    assert_eq!(lines_with(&dot, "->").len(), 3);
+}
+
+#[test]
+fn dashes_uncached_nodes() {
+    let manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .cache_policy(CachePolicy::Disabled)
+            .build(),
+    );
+    let mut tree: Tree<Bytes, Bytes> = Tree::with_manager(manager.clone());
+    for key in [b"b", b"a", b"c"] {
+        tree = tree.insert(key.to_vec(), key.to_vec()).unwrap();
+    }
+    let root = tree.save().unwrap().root_ptr().unwrap();
+    let dot = Tree::<Bytes, Bytes>::load(manager, root)
+        .to_graphviz()
+        .unwrap();
+    assert_eq!(lines_with(&dot, "style=\"filled,dashed\"").len(), 3);
+    assert_eq!(lines_with(&dot, "Ptr(").len(), 3);
+}
+
+#[test]
+fn labels_reference_counts() {
+    let manager = Arc::new(NodeManager::new(MemNodeStore::new()));
+    let tree = ["b", "a", "c"]
+        .into_iter()
+        .fold(Tree::with_manager(manager), |tree, key| {
+            tree.insert(key.into(), vec![]).unwrap()
+        })
+        .save()
+        .unwrap();
+    // The next version shares a, and rewrites b and c.
+    let next = tree.insert(b"d".to_vec(), vec![]).unwrap().save().unwrap();
+
+    let options = GraphvizOptions::default().ref_counts(true).sizes(false);
+    let dot = tree.to_graphviz_with(options).unwrap();
+    let shared = lines_with(&dot, "label=\"a\\n");
+    assert_eq!(shared.len(), 1);
+    assert!(shared[0].ends_with("\\nrefs 2\"];"), "{}", shared[0]);
+    assert_eq!(lines_with(&dot, "\\nrefs 1\"").len(), 2);
+    assert!(lines_with(&dot, "size").is_empty());
+    assert!(!dot.contains("cluster_legend"));
+
+    let dot = next.to_graphviz_with(options).unwrap();
+    assert_eq!(lines_with(&dot, "\\nrefs 2\"").len(), 1);
+    assert_eq!(lines_with(&dot, "\\nrefs 1\"").len(), 3);
+    // Without the option, counts aren't read.
+    assert!(lines_with(&next.to_graphviz().unwrap(), "refs").is_empty());
+}
+
+#[test]
+fn adds_a_legend() {
+    let tree: Tree<Bytes, Bytes> = Tree::new().insert(b"a".to_vec(), vec![]).unwrap();
+    let dot = tree
+        .to_graphviz_with(GraphvizOptions::default().ref_counts(true).legend(true))
+        .unwrap();
+    assert_eq!(
+        dot,
+        "digraph avl {\n    node [fontname=monospace, style=filled];\n    \
+         subgraph cluster_legend {\n        label=\"legend\";\n        \
+         legend_unsaved [fillcolor=lightyellow, label=\"unsaved\"];\n        \
+         legend_stored [fillcolor=lightblue, label=\"stored\"];\n        \
+         legend_uncached [fillcolor=lightblue, style=\"filled,dashed\", \
+         label=\"stored, not cached\"];\n        \
+         legend_label [fillcolor=white, shape=box, label=\"key\\nsize: entries in \
+         subtree\\npointer, if stored\\nrefs: reference count\"];\n    }\n    \
+         n0 [fillcolor=lightyellow, label=\"a\\nsize 1\"];\n}\n"
+    );
+
+    // The legend only explains the annotations shown.
+    let options = GraphvizOptions::default()
+        .sizes(false)
+        .cache_residency(false)
+        .legend(true);
+    let dot = tree.to_graphviz_with(options).unwrap();
+    assert!(dot.contains(
+        "legend_label [fillcolor=white, shape=box, label=\"key\\npointer, if stored\"];"
+    ));
+    assert!(!dot.contains("legend_uncached"));
+}
+
+#[test]
+fn hides_cache_residency() {
+    let manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .cache_policy(CachePolicy::Disabled)
+            .build(),
+    );
+    let tree: Tree<Bytes, Bytes> = Tree::with_manager(manager)
+        .insert(b"a".to_vec(), vec![])
+        .unwrap()
+        .save()
+        .unwrap();
+    assert_eq!(lines_with(&tree.to_graphviz().unwrap(), "dashed").len(), 1);
+    let options = GraphvizOptions::default().cache_residency(false);
+    assert!(lines_with(&tree.to_graphviz_with(options).unwrap(), "dashed").is_empty());
+}
+
+#[test]
+fn empty_trees_have_no_nodes() {
+    let dot = Tree::<Bytes, Bytes>::new().to_graphviz().unwrap();
+    assert_eq!(
+        dot,
+        "digraph avl {\n    node [fontname=monospace, style=filled];\n}\n"
+    );
+}
+
+#[test]
+fn renders_dot_to_writers() {
+    let tree: Tree<Bytes, Bytes> = Tree::new().insert(b"a".to_vec(), vec![]).unwrap();
+    let mut out = Vec::new();
+    tree.render_graphviz(Format::Dot, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), tree.to_graphviz().unwrap());
+}
@@ -0,0 +1,197 @@
+//! Exhaustively checks small AVL trees against every order of insertions
+//! and deletions, which reaches each single and double rotation case in
+//! every position, rather than the ones random orders happen to hit.
+//!
+//! The shape a sequence of operations produces depends only on the relative
+//! order of the keys involved, so the permutations of `0..n` stand for every
+//! sequence of `n` distinct keys, and the prefixes of a permutation are
+//! covered by the permutations of fewer keys.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::{Manager, Node};
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::{NodeManager, NodeRef};
+
+type Bytes = Vec<u8>;
+
+const MAX_KEYS: u8 = 8;
+
+/// Returns every permutation of `0..n`, in lexicographic order.
+fn permutations(n: u8) -> Vec<Vec<u8>> {
+    let mut order: Vec<u8> = (0..n).collect();
+    let mut all = vec![order.clone()];
+    loop {
+        let Some(i) = (1..order.len()).rev().find(|&i| order[i - 1] < order[i]) else {
+            return all;
+        };
+        let j = (i..order.len())
+            .rev()
+            .find(|&j| order[j] > order[i - 1])
+            .expect("order[i] is greater");
+        order.swap(i - 1, j);
+        order[i..].reverse();
+        all.push(order.clone());
+    }
+}
+
+fn key(k: u8) -> Bytes {
+    vec![k]
+}
+
+fn value(k: u8) -> Bytes {
+    vec![k, k]
+}
+
+fn insert_all(m: &Arc<Manager<Bytes, Bytes>>, order: &[u8]) -> Tree<Bytes, Bytes> {
+    order
+        .iter()
+        .fold(Tree::with_manager(m.clone()), |tree, &k| {
+            tree.insert(key(k), value(k)).unwrap()
+        })
+}
+
+/// Walks the subtree at `link`, checking the height, size and balance of
+/// every node and collecting the keys in order. Returns the height.
+fn walk(
+    tree: &Tree<Bytes, Bytes>,
+    link: Option<&NodeRef<Node<Bytes, Bytes>>>,
+    keys: &mut Vec<u8>,
+) -> Result<u8, String> {
+    let Some(link) = link else {
+        return Ok(0);
+    };
+    let node = tree.read(link).map_err(|err| err.to_string())?;
+    let before = keys.len();
+    let left = walk(tree, node.left(), keys)?;
+    keys.push(node.key()[0]);
+    if node.value() != &value(node.key()[0]) {
+        return Err(format!("key {:?} has value {:?}", node.key(), node.value()));
+    }
+    let right = walk(tree, node.right(), keys)?;
+    if left.abs_diff(right) > 1 {
+        return Err(format!(
+            "node {:?} has children of height {} and {}",
+            node.key(),
+            left,
+            right
+        ));
+    }
+    if node.height() != 1 + left.max(right) {
+        return Err(format!(
+            "node {:?} has height {} over children of height {} and {}",
+            node.key(),
+            node.height(),
+            left,
+            right
+        ));
+    }
+    if node.size() != (keys.len() - before) as u64 {
+        return Err(format!("node {:?} has size {}", node.key(), node.size()));
+    }
+    Ok(node.height())
+}
+
+/// Checks that `tree` is a balanced search tree holding exactly `keys`.
+fn check(tree: &Tree<Bytes, Bytes>, keys: &[u8], context: &dyn Fn() -> String) {
+    let mut found = Vec::new();
+    if let Err(err) = walk(tree, tree.root(), &mut found) {
+        panic!("{}: {}", context(), err);
+    }
+    let mut expected = keys.to_vec();
+    expected.sort_unstable();
+    assert_eq!(found, expected, "{}", context());
+}
+
+/// Checks that the cached hashes of `tree` match the ones recomputed from
+/// its entries, along with the invariants [`Tree::verify`] checks.
+fn check_hashes(tree: &Tree<Bytes, Bytes>, context: &dyn Fn() -> String) {
+    let recomputed = tree
+        .verify()
+        .unwrap_or_else(|err| panic!("{}: {:#}", context(), err));
+    assert_eq!(tree.merkle_hash().unwrap(), recomputed, "{}", context());
+}
+
+#[test]
+fn every_insertion_order() {
+    let m = Arc::new(NodeManager::in_memory());
+    for n in 1..=MAX_KEYS {
+        for order in permutations(n) {
+            let tree = insert_all(&m, &order);
+            let context = || format!("inserting {:?}", order);
+            check(&tree, &order, &context);
+            // Recomputing the hashes is slow enough to leave out the
+            // largest trees, whose shapes the check above covers.
+            if n < MAX_KEYS {
+                check_hashes(&tree, &context);
+            }
+        }
+    }
+}
+
+#[test]
+fn every_deletion_order() {
+    let m = Arc::new(NodeManager::in_memory());
+    for n in 1..=MAX_KEYS {
+        // Sorted insertions build the tree leaning furthest to the right,
+        // then every deletion order takes it apart.
+        let full = insert_all(&m, &(0..n).collect::<Vec<_>>());
+        for order in permutations(n) {
+            let mut tree = full.clone();
+            for (i, k) in order.iter().enumerate() {
+                tree = tree.delete(&key(*k)).unwrap();
+                check(&tree, &order[i + 1..], &|| {
+                    format!("deleting {:?} of {:?}", &order[..=i], order)
+                });
+            }
+            assert!(tree.is_empty());
+        }
+    }
+}
+
+#[test]
+fn every_insertion_and_deletion_order() {
+    const KEYS: u8 = 5;
+    let m = Arc::new(NodeManager::in_memory());
+    let orders = permutations(KEYS);
+    for inserted in &orders {
+        let full = insert_all(&m, inserted);
+        for deleted in &orders {
+            let mut tree = full.clone();
+            for (i, k) in deleted.iter().enumerate() {
+                tree = tree.delete(&key(*k)).unwrap();
+                let context = || {
+                    format!(
+                        "inserting {:?}, then deleting {:?}",
+                        inserted,
+                        &deleted[..=i]
+                    )
+                };
+                check(&tree, &deleted[i + 1..], &context);
+                check_hashes(&tree, &context);
+            }
+        }
+    }
+}
+
+#[test]
+fn hashes_are_stable() {
+    let m = Arc::new(NodeManager::in_memory());
+    for order in permutations(6) {
+        let tree = insert_all(&m, &order);
+        let hash = tree.merkle_hash().unwrap();
+        // The same operations produce the same tree.
+        assert_eq!(insert_all(&m, &order).merkle_hash().unwrap(), hash);
+        // Writes which change nothing keep the hash.
+        let rewritten = tree.insert(key(order[0]), value(order[0])).unwrap();
+        assert_eq!(rewritten.merkle_hash().unwrap(), hash, "{:?}", order);
+        let missing = tree.delete(&key(MAX_KEYS)).unwrap();
+        assert_eq!(missing.merkle_hash().unwrap(), hash, "{:?}", order);
+        // Saving and reloading keeps it.
+        let saved = tree.save().unwrap();
+        let loaded = Tree::load(m.clone(), saved.root_ptr().unwrap());
+        assert_eq!(loaded.verify().unwrap(), hash, "{:?}", order);
+        saved.free_version().unwrap();
+    }
+}
@@ -0,0 +1,49 @@
+//! Checks that inserting into and deleting from a stored tree reads each
+//! node near the written path at most once, rebalancing included.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{CachePolicy, MemNodeStore, NodeManager};
+
+type Bytes = Vec<u8>;
+type Manager = NodeManager<Node<Bytes, Bytes>>;
+
+fn key(i: u64) -> Bytes {
+    (i * 7919 % 10007).to_be_bytes().to_vec()
+}
+
+/// The number of store reads made by `f`.
+fn reads(manager: &Manager, f: impl FnOnce()) -> u64 {
+    let before = manager.stats().unwrap().cache_misses;
+    f();
+    manager.stats().unwrap().cache_misses - before
+}
+
+#[test]
+fn reads_each_child_once() {
+    let manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .cache_policy(CachePolicy::Disabled)
+            .metrics(true)
+            .build(),
+    );
+    let mut tree = Tree::with_manager(manager.clone());
+    for i in 0..2000 {
+        let height = tree
+            .root()
+            .map_or(0, |root| tree.read(root).unwrap().height()) as u64;
+        let read = reads(&manager, || tree = tree.insert(key(i), vec![1]).unwrap());
+        // The path down and at most one sibling per node on it, which
+        // rotations reuse rather than read again.
+        assert!(read <= 2 * height, "{} reads at height {}", read, height);
+        tree = tree.save().unwrap();
+    }
+    for i in 0..500 {
+        let height = tree.read(tree.root().unwrap()).unwrap().height() as u64;
+        let read = reads(&manager, || tree = tree.delete(&key(i)).unwrap());
+        assert!(read <= 2 * height, "{} reads at height {}", read, height);
+        tree = tree.save().unwrap();
+    }
+}
@@ -0,0 +1,72 @@
+//! Checks that proofs generated on a rayon pool are the proofs generated one
+//! by one, for in-memory and stored trees.
+#![cfg(feature = "rayon")]
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::NodeManager;
+
+type Bytes = Vec<u8>;
+
+fn key(i: u64) -> Bytes {
+    i.to_be_bytes().to_vec()
+}
+
+/// A tree of 2000 of the keys below 2003, in scattered order.
+fn filled(tree: Tree<Bytes, Bytes>) -> Tree<Bytes, Bytes> {
+    (0..2000u64).fold(tree, |tree, i| {
+        tree.insert(key(i * 7 % 2003), vec![i as u8]).unwrap()
+    })
+}
+
+fn pool() -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(4)
+        .build()
+        .unwrap()
+}
+
+/// Checks the proofs of `keys` in `tree` on a pool against those proved one
+/// by one, and that they prove the tree's values.
+fn check(tree: &Tree<Bytes, Bytes>, keys: &[Bytes]) {
+    let root = tree.merkle_hash().unwrap();
+    let proofs = tree.prove_batch_in(&pool(), keys).unwrap();
+    assert_eq!(proofs, tree.prove_batch(keys).unwrap());
+    for (key, proof) in keys.iter().zip(&proofs) {
+        match tree.get(key).unwrap() {
+            Some(value) => {
+                let proof = proof.as_ref().unwrap();
+                assert!(proof.verify(&root, key, &value));
+                assert!(!proof.verify(&root, key, &vec![1, 2]));
+            }
+            None => assert!(proof.is_none()),
+        }
+    }
+}
+
+#[test]
+fn proves_in_memory_trees_in_parallel() {
+    let keys: Vec<_> = (0..2100).map(key).collect();
+    check(&filled(Tree::new()), &keys);
+}
+
+#[test]
+fn proves_stored_trees_in_parallel() {
+    let manager = Arc::new(NodeManager::in_memory());
+    let tree = filled(Tree::with_manager(manager.clone())).save().unwrap();
+    // Reload the tree, so that the workers read its nodes through the manager.
+    let tree = Tree::load(manager, tree.root_ptr().unwrap());
+    let keys: Vec<_> = (0..2100).rev().map(key).collect();
+    check(&tree, &keys);
+}
+
+#[test]
+fn proves_an_empty_batch() {
+    let tree = filled(Tree::new());
+    assert!(tree
+        .prove_batch_in::<Bytes>(&pool(), &[])
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,96 @@
+//! Checks that batches of inserts and deletes leave a tree holding the same
+//! entries as applying them one at a time.
+
+mod rng;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::versioned::VersionedTree;
+use rhizome_trees::tree::avl::{BatchOp, Tree};
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::NodeManager;
+use rng::Rng;
+
+type Bytes = Vec<u8>;
+
+fn same_entries(tree: &Tree<Bytes, Bytes>, model: &BTreeMap<Bytes, Bytes>) -> bool {
+    let expected = model.iter().fold(Tree::new(), |tree, (key, value)| {
+        tree.insert(key.clone(), value.clone()).unwrap()
+    });
+    tree.diff(&expected).count() == 0
+}
+
+#[test]
+fn matches_a_model() {
+    let mut rng = Rng(7);
+    let mut tree = Tree::new();
+    let mut model = BTreeMap::new();
+    for round in 0..200u32 {
+        let mut ops = Vec::new();
+        for _ in 0..rng.next() % 50 {
+            let key = (rng.next() % 300).to_be_bytes().to_vec();
+            if rng.next().is_multiple_of(3) {
+                model.remove(&key);
+                ops.push(BatchOp::Delete(key));
+            } else {
+                let value = round.to_be_bytes().to_vec();
+                model.insert(key.clone(), value.clone());
+                ops.push(BatchOp::Insert(key, value));
+            }
+        }
+        let empty = ops.is_empty();
+        let before = tree.clone();
+        tree = tree.apply_batch(ops).unwrap();
+        assert_eq!(tree.verify().unwrap(), tree.merkle_hash().unwrap());
+        assert!(same_entries(&tree, &model), "round {}", round);
+        if empty {
+            assert_eq!(tree.root().is_some(), before.root().is_some());
+            if let (Some(root), Some(before)) = (tree.root(), before.root()) {
+                assert!(root.same_node(before));
+            }
+        }
+    }
+}
+
+#[test]
+fn applies_later_writes_last() {
+    let tree = Tree::new().insert(vec![1], vec![1]).unwrap();
+    let tree = tree
+        .apply_batch([
+            BatchOp::Delete(vec![1]),
+            BatchOp::Insert(vec![1], vec![2]),
+            BatchOp::Insert(vec![2], vec![2]),
+            BatchOp::Delete(vec![2]),
+            BatchOp::Delete(vec![3]),
+        ])
+        .unwrap();
+    assert_eq!(tree.get(&vec![1]).unwrap(), Some(vec![2]));
+    assert_eq!(tree.get(&vec![2]).unwrap(), None);
+    assert_eq!(tree.len().unwrap(), 1);
+}
+
+#[test]
+fn inserts_many_entries() {
+    let entries: Vec<_> = (0..20_000u64)
+        .map(|i| ((i * 7919 % 1_000_003).to_be_bytes().to_vec(), vec![1; 8]))
+        .collect();
+    let batched = Tree::new().insert_batch(entries.clone()).unwrap();
+    let one_by_one = entries.into_iter().fold(Tree::new(), |tree, (key, value)| {
+        tree.insert(key, value).unwrap()
+    });
+    assert_eq!(batched.verify().unwrap(), batched.merkle_hash().unwrap());
+    assert_eq!(batched.diff(&one_by_one).count(), 0);
+}
+
+#[test]
+fn batches_versioned_writes() {
+    let mut tree = VersionedTree::new(Arc::new(NodeManager::in_memory()));
+    tree.insert(vec![1], vec![1]).unwrap();
+    tree.save().unwrap();
+    tree.apply_batch([BatchOp::Delete(vec![1]), BatchOp::Insert(vec![2], vec![2])])
+        .unwrap();
+    tree.save().unwrap();
+    assert_eq!(tree.get(&vec![1]).unwrap(), None);
+    assert_eq!(tree.get(&vec![2]).unwrap(), Some(vec![2]));
+}
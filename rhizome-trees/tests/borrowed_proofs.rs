@@ -0,0 +1,57 @@
+//! Checks that proofs verified from borrowed hashes agree with owned proofs.
+
+use rhizome_trees::tree::avl::proof::{ProofRef, Side};
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+
+type Bytes = Vec<u8>;
+
+fn key(n: u32) -> Bytes {
+    n.to_be_bytes().to_vec()
+}
+
+#[test]
+fn verifies_borrowed_hashes() {
+    let tree = Tree::from_sorted_iter((0..1000).map(|i| (key(i), key(i)))).unwrap();
+    let root = tree.merkle_hash().unwrap();
+    for i in [0, 1, 333, 500, 999] {
+        let proof = tree.prove(&key(i)).unwrap().unwrap();
+        let borrowed = proof.as_ref();
+        assert_eq!(
+            borrowed.root_hash(&key(i)[..], &key(i)[..]),
+            proof.root_hash(&key(i)[..], &key(i)[..])
+        );
+        assert!(borrowed.verify(&root, &key(i)[..], &key(i)[..]));
+        assert!(!borrowed.verify(&root, &key(i)[..], &key(i + 1)[..]));
+        assert!(!borrowed.verify(&root, &key(i + 1)[..], &key(i)[..]));
+    }
+}
+
+#[test]
+fn borrows_from_separate_parts() {
+    let tree = Tree::from_sorted_iter((0..100).map(|i| (key(i), vec![1]))).unwrap();
+    let root = tree.merkle_hash().unwrap();
+    let proof = tree.prove(&key(42)).unwrap().unwrap();
+
+    // The hashes may be kept apart, as when decoded from a message.
+    let (left, right) = (proof.left, proof.right);
+    let mut path = proof.path.clone();
+    let borrowed = ProofRef {
+        hash_version: proof.hash_version,
+        left: &left,
+        right: &right,
+        path: &path,
+    };
+    assert!(borrowed.verify(&root, &key(42)[..], &[1][..]));
+
+    // A tampered step fails verification.
+    path[0].side = match path[0].side {
+        Side::Left => Side::Right,
+        Side::Right => Side::Left,
+    };
+    let tampered = ProofRef {
+        path: &path,
+        ..proof.as_ref()
+    };
+    assert!(!tampered.verify(&root, &key(42)[..], &[1][..]));
+}
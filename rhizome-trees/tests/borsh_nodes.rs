@@ -0,0 +1,106 @@
+//! Checks that AVL nodes persist through the Borsh codec in its documented
+//! layout, and that each node has exactly one encoding.
+
+#![cfg(feature = "borsh")]
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::borsh::BorshCodec;
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::{
+    CachePolicy, EncodedStore, MemNodeStore, NodeCodec, NodeManager, NodeStore,
+};
+use rhizome_trees::tree::value::{Timestamp, U64BigEndian};
+
+type Bytes = Vec<u8>;
+type Store<K, V> = EncodedStore<Arc<MemNodeStore<Bytes>>, BorshCodec<K, V>>;
+
+/// A tree whose nodes are encoded into `bytes`, and decoded on every read.
+fn borsh_tree<K, V>(bytes: &Arc<MemNodeStore<Bytes>>) -> Tree<K, V>
+where
+    K: Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+    Store<K, V>: NodeStore<Node<K, V>>,
+{
+    Tree::with_manager(Arc::new(
+        NodeManager::builder(EncodedStore::new(bytes.clone(), BorshCodec::new()))
+            .cache_policy(CachePolicy::Disabled)
+            .build(),
+    ))
+}
+
+#[test]
+fn round_trips_trees() {
+    let bytes = Arc::new(MemNodeStore::new());
+    let tree = (0..50u64).fold(borsh_tree(&bytes), |tree, i| {
+        tree.insert(U64BigEndian(i), Timestamp::new(i as i64, 3))
+            .unwrap()
+    });
+    let hash = tree.merkle_hash().unwrap();
+    let tree = tree.save().unwrap();
+    assert_eq!(tree.merkle_hash().unwrap(), hash);
+    assert_eq!(tree.verify().unwrap(), hash);
+    assert_eq!(
+        tree.get(&U64BigEndian(9)).unwrap(),
+        Some(Timestamp::new(9, 3))
+    );
+    let updated = tree
+        .insert(U64BigEndian(1000), Timestamp::new(0, 0))
+        .unwrap()
+        .save()
+        .unwrap();
+    assert_eq!(updated.len().unwrap(), 51);
+
+    // The root has two children, each written as an 8 byte pointer.
+    let raw = bytes.read(&tree.root_ptr().unwrap()).unwrap();
+    assert_eq!(raw.len(), 8 + 12 + 1 + 8 + 2 * (1 + 4 + 8) + 1 + 32);
+    let codec = BorshCodec::<U64BigEndian, Timestamp>::new();
+    assert_eq!(codec.to_bytes(&codec.decode(&raw).unwrap()).unwrap(), raw);
+}
+
+#[test]
+fn writes_documented_layout() {
+    let bytes = Arc::new(MemNodeStore::new());
+    let tree = borsh_tree(&bytes)
+        .insert(vec![1], vec![2, 3])
+        .unwrap()
+        .save()
+        .unwrap();
+    let raw = bytes.read(&tree.root_ptr().unwrap()).unwrap();
+    let height = tree.read(tree.root().unwrap()).unwrap().height();
+    let mut expected = vec![1, 0, 0, 0, 1, 2, 0, 0, 0, 2, 3, height];
+    expected.extend_from_slice(&1u64.to_le_bytes());
+    // Neither child is present.
+    expected.extend_from_slice(&[0, 0]);
+    assert_eq!(raw[..expected.len()], expected[..]);
+    assert_eq!(raw.len(), expected.len() + 1 + 32);
+    assert_eq!(raw[raw.len() - 32..], tree.merkle_hash().unwrap()[..]);
+}
+
+#[test]
+fn rejects_noncanonical_encodings() {
+    let bytes = Arc::new(MemNodeStore::new());
+    let tree = borsh_tree(&bytes)
+        .insert(vec![1], vec![2])
+        .unwrap()
+        .save()
+        .unwrap();
+    let raw = bytes.read(&tree.root_ptr().unwrap()).unwrap();
+    let codec = BorshCodec::<Bytes, Bytes>::new();
+    assert!(codec.decode(&raw).is_ok());
+
+    let mut trailing = raw.clone();
+    trailing.push(0);
+    assert!(codec.decode(&trailing).is_err());
+    assert!(codec.decode(&raw[..raw.len() - 1]).is_err());
+    // The tag of the left child.
+    let mut tag = raw.clone();
+    tag[5 + 5 + 1 + 8] = 2;
+    assert!(codec.decode(&tag).is_err());
+    // The hash version.
+    let mut version = raw.clone();
+    version[5 + 5 + 1 + 8 + 2] = 0xff;
+    assert!(codec.decode(&version).is_err());
+}
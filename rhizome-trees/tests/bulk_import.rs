@@ -0,0 +1,102 @@
+//! Checks that importing unsorted entries, through spilled runs and parallel
+//! chunks or entirely in memory, builds a valid balanced tree holding the
+//! last value of each key and leaves no run files behind.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::import::ImportOptions;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::NodeManager;
+
+type Bytes = Vec<u8>;
+
+/// An empty directory to spill runs to, unique to the test.
+fn spill_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rhizome-import-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// `n` entries with keys repeating about twice each, in a scattered order,
+/// and the map they describe.
+fn entries(n: u32) -> (Vec<(Bytes, Bytes)>, BTreeMap<Bytes, Bytes>) {
+    let mut state = 12345u64;
+    let entries: Vec<_> = (0..n)
+        .map(|i| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            let key = ((state >> 33) % (n as u64 / 2 + 1)).to_be_bytes().to_vec();
+            (key, i.to_be_bytes().to_vec())
+        })
+        .collect();
+    let model = entries.iter().cloned().collect();
+    (entries, model)
+}
+
+fn check_import(dir: &PathBuf, n: u32, run_bytes: usize, chunk_entries: usize) {
+    let (entries, model) = entries(n);
+    let options = ImportOptions {
+        run_bytes,
+        spill_dir: dir.clone(),
+        chunk_entries,
+        threads: 4,
+    };
+    let manager = Arc::new(NodeManager::in_memory());
+    let tree = Tree::import(manager, entries, &options).unwrap();
+
+    tree.verify().unwrap();
+    let imported: Vec<_> = tree
+        .iter()
+        .unwrap()
+        .map(|node| {
+            let node = node.unwrap();
+            (node.key().clone(), node.value().clone())
+        })
+        .collect();
+    assert_eq!(imported, model.into_iter().collect::<Vec<_>>());
+    if let Some(root) = tree.root() {
+        // The height of an AVL tree of n entries is below 1.45 log2(n + 2).
+        let height = tree.read(root).unwrap().height() as f64;
+        assert!(height <= 1.45 * ((imported.len() + 2) as f64).log2() + 1.0);
+    }
+    // The tree holds one node per entry, and nothing else is stored.
+    assert_eq!(
+        tree.free_version().unwrap(),
+        imported.len(),
+        "{} entries",
+        n
+    );
+    assert_eq!(fs::read_dir(dir).unwrap().count(), 0);
+}
+
+#[test]
+fn imports_through_spilled_runs() {
+    let dir = spill_dir("spilled");
+    check_import(&dir, 5000, 2000, 37);
+    check_import(&dir, 777, 500, 1);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn imports_in_memory() {
+    let dir = spill_dir("memory");
+    check_import(&dir, 5000, 1 << 30, 100);
+    check_import(&dir, 1, 100, 3);
+    check_import(&dir, 0, 100, 3);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn refuses_empty_chunks() {
+    let options = ImportOptions {
+        chunk_entries: 0,
+        ..ImportOptions::default()
+    };
+    let manager = Arc::new(NodeManager::in_memory());
+    assert!(Tree::import(manager, Vec::new(), &options).is_err());
+}
@@ -0,0 +1,48 @@
+//! Checks that trees built from sorted input hold every entry, are balanced to
+//! the minimum height and refuse unsorted or duplicate keys.
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+
+type Bytes = Vec<u8>;
+
+fn key(n: u32) -> Bytes {
+    n.to_be_bytes().to_vec()
+}
+
+#[test]
+fn builds_balanced_trees() {
+    for n in [0u32, 1, 2, 3, 7, 8, 100, 1000] {
+        let tree = Tree::from_sorted_iter((0..n).map(|i| (key(i), key(i)))).unwrap();
+        assert_eq!(tree.len().unwrap(), u64::from(n));
+        tree.verify().unwrap();
+        for i in 0..n {
+            assert_eq!(tree.get(&key(i)).unwrap(), Some(key(i)));
+        }
+        assert_eq!(tree.get(&key(n)).unwrap(), None);
+        tree.merkle_hash().unwrap();
+        if let Some(root) = tree.root() {
+            // A perfectly balanced tree of n nodes is ceil(log2(n + 1)) high.
+            let height = tree.read(root).unwrap().height();
+            assert_eq!(u32::from(height), 32 - n.leading_zeros(), "{}", n);
+        }
+    }
+}
+
+#[test]
+fn accepts_later_writes() {
+    let tree = Tree::from_sorted_iter((0..100).map(|i| (key(i * 2), vec![1]))).unwrap();
+    let tree = (0..100).fold(tree, |tree, i| {
+        tree.insert(key(i * 2 + 1), vec![2]).unwrap()
+    });
+    let tree = tree.delete(&key(0)).unwrap();
+    tree.verify().unwrap();
+    assert_eq!(tree.len().unwrap(), 199);
+    assert_eq!(tree.get(&key(7)).unwrap(), Some(vec![2]));
+}
+
+#[test]
+fn refuses_unsorted_keys() {
+    assert!(Tree::from_sorted_iter(vec![(key(2), key(2)), (key(1), key(1))]).is_err());
+    assert!(Tree::from_sorted_iter(vec![(key(1), key(2)), (key(1), key(1))]).is_err());
+}
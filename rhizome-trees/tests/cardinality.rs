@@ -0,0 +1,83 @@
+//! Checks that cardinality estimates are exact when the shape of a tree
+//! determines its size, and otherwise stay within their bounds and near the
+//! true count.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::art;
+use rhizome_trees::tree::avl;
+use rhizome_trees::tree::cardinality::Cardinality;
+use rhizome_trees::tree::node_manager::NodeManager;
+
+const ENTRIES: u32 = 20_000;
+
+/// Spreads `i` over the key space, so that the keys aren't inserted in
+/// order.
+fn scattered(i: u32) -> u32 {
+    i.wrapping_mul(2_654_435_761)
+}
+
+/// Checks `cardinality` is an estimate within its bounds and within a
+/// quarter of `len`.
+fn check_estimate(cardinality: Cardinality, len: u64) {
+    let Cardinality::Estimated {
+        estimate,
+        lower,
+        upper,
+    } = cardinality
+    else {
+        panic!("expected an estimate, got {:?}", cardinality);
+    };
+    assert!(lower <= len && len <= upper, "{:?}", cardinality);
+    assert!((lower..=upper).contains(&estimate));
+    assert!(estimate.abs_diff(len) < len / 4, "{:?}", cardinality);
+    assert_eq!(cardinality.value(), estimate);
+    assert!(!cardinality.is_exact());
+}
+
+#[test]
+fn counts_trivial_trees_exactly() {
+    let empty = avl::Tree::<u32, ()>::new();
+    assert_eq!(empty.cardinality().unwrap(), Cardinality::Exact(0));
+    let single = empty.insert(1, ()).unwrap();
+    assert_eq!(single.cardinality().unwrap(), Cardinality::Exact(1));
+
+    let empty = art::Tree::<()>::new();
+    assert_eq!(empty.cardinality().unwrap(), Cardinality::Exact(0));
+    let single = empty.insert("a", ());
+    assert_eq!(single.cardinality().unwrap(), Cardinality::Exact(1));
+    assert_eq!(single.cardinality().unwrap().value(), 1);
+}
+
+#[test]
+fn estimates_avl_trees() {
+    let tree = (0..ENTRIES).fold(avl::Tree::new(), |tree, i| {
+        tree.insert(scattered(i), ()).unwrap()
+    });
+    let cardinality = tree.cardinality().unwrap();
+    check_estimate(cardinality, ENTRIES as u64);
+    // Probes follow the same paths every time.
+    assert_eq!(tree.cardinality().unwrap(), cardinality);
+
+    // Stored trees are probed through their node manager.
+    let manager = Arc::new(NodeManager::in_memory());
+    let stored = (0..ENTRIES)
+        .fold(avl::Tree::with_manager(manager), |tree, i| {
+            tree.insert(scattered(i).to_be_bytes().to_vec(), vec![])
+                .unwrap()
+        })
+        .save()
+        .unwrap();
+    check_estimate(stored.cardinality_with_probes(128).unwrap(), ENTRIES as u64);
+}
+
+#[test]
+fn estimates_radix_trees() {
+    let tree = (0..ENTRIES).fold(art::Tree::new(), |tree, i| {
+        tree.insert(scattered(i).to_be_bytes(), ())
+    });
+    check_estimate(tree.cardinality().unwrap(), ENTRIES as u64);
+    // More probes don't leave the bounds.
+    check_estimate(tree.cardinality_with_probes(1000).unwrap(), ENTRIES as u64);
+    check_estimate(tree.cardinality_with_probes(0).unwrap(), ENTRIES as u64);
+}
@@ -0,0 +1,90 @@
+//! Checks that changesets are applied exactly once per id, across retries,
+//! reopening, rollbacks and pruning.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::diff::Change;
+use rhizome_trees::tree::avl::versioned::{ChangesetId, PruningPolicy, Version, VersionedTree};
+use rhizome_trees::tree::node_manager::NodeManager;
+
+type Bytes = Vec<u8>;
+
+fn create(key: u8) -> Vec<Change<Bytes, Bytes>> {
+    vec![Change::Create {
+        key: vec![key],
+        value: vec![key],
+    }]
+}
+
+fn versioned() -> VersionedTree<Bytes, Bytes> {
+    VersionedTree::new(Arc::new(NodeManager::in_memory()))
+}
+
+#[test]
+fn applies_each_id_once() {
+    let mut tree = versioned();
+    let id = ChangesetId([7; 32]);
+    let applied = tree.apply_changeset(id, create(1)).unwrap();
+    assert_eq!(applied.version, Version::new(1));
+    assert_eq!(applied.changeset, Some(id));
+
+    // Retrying returns the first application, even with other changes.
+    assert_eq!(tree.apply_changeset(id, create(1)).unwrap(), applied);
+    assert_eq!(tree.apply_changeset(id, create(2)).unwrap(), applied);
+    assert_eq!(tree.latest_version(), Version::new(1));
+    assert_eq!(tree.get(&vec![2]).unwrap(), None);
+
+    // Unsaved changes would be committed along with a changeset.
+    tree.insert(vec![2], vec![2]).unwrap();
+    assert!(tree
+        .apply_changeset(ChangesetId([8; 32]), create(3))
+        .is_err());
+    assert_eq!(tree.apply_changeset(id, create(1)).unwrap(), applied);
+    tree.save().unwrap();
+
+    let delete = vec![Change::Delete {
+        key: vec![1],
+        old: vec![1],
+    }];
+    let applied = tree.apply_changeset(ChangesetId([8; 32]), delete).unwrap();
+    assert_eq!(applied.version, Version::new(3));
+    assert_eq!(tree.get(&vec![1]).unwrap(), None);
+    assert_eq!(
+        tree.changeset_version(&id).unwrap().version,
+        Version::new(1)
+    );
+    assert!(tree.changeset_version(&ChangesetId([9; 32])).is_none());
+
+    // Reopened trees remember the ids from the version records.
+    let reopened = VersionedTree::<Bytes, Bytes>::open(
+        tree.working().manager().clone(),
+        tree.versions().to_vec(),
+    )
+    .unwrap();
+    assert_eq!(
+        reopened.changeset_version(&id).unwrap().version,
+        Version::new(1)
+    );
+}
+
+#[test]
+fn forgets_discarded_versions() {
+    let mut tree = versioned();
+    for i in 1..=4u8 {
+        tree.apply_changeset(ChangesetId([i; 32]), create(i))
+            .unwrap();
+    }
+
+    // A rolled back changeset can be applied again.
+    tree.rollback_to(Version::new(3)).unwrap();
+    assert!(tree.changeset_version(&ChangesetId([4; 32])).is_none());
+    let applied = tree
+        .apply_changeset(ChangesetId([4; 32]), create(4))
+        .unwrap();
+    assert_eq!(applied.version, Version::new(4));
+
+    tree.set_pruning(PruningPolicy::default().keep_recent(2));
+    tree.prune().unwrap();
+    assert!(tree.changeset_version(&ChangesetId([1; 32])).is_none());
+    assert!(tree.changeset_version(&ChangesetId([3; 32])).is_some());
+}
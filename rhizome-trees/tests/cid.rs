@@ -0,0 +1,86 @@
+//! Checks the encoding of CIDs and that roots, proofs and versions of trees
+//! in a content-addressed store identify their nodes by CID.
+#![cfg(feature = "cid")]
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::versioned::VersionedTree;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::cid::{Cid, RAW_CODEC};
+use rhizome_trees::tree::hash::{MerkleTree, EMPTY_HASH};
+use rhizome_trees::tree::node_manager::{ContentAddressedStore, NodeManager, NodeStore};
+
+type Bytes = Vec<u8>;
+type Store = ContentAddressedStore<Node<Bytes, Bytes>>;
+
+/// The SHA-256 of the empty string.
+const EMPTY_SHA256: [u8; 32] = [
+    0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f, 0xb9, 0x24,
+    0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b, 0x78, 0x52, 0xb8, 0x55,
+];
+
+fn saved() -> (Tree<Bytes, Bytes>, Arc<Store>) {
+    let store = Arc::new(ContentAddressedStore::new());
+    let manager = Arc::new(NodeManager::new(store.clone()));
+    let tree = (0..20u8)
+        .fold(Tree::with_manager(manager), |tree, i| {
+            tree.insert(vec![i], vec![i]).unwrap()
+        })
+        .save()
+        .unwrap();
+    (tree, store)
+}
+
+#[test]
+fn encodes_cids() {
+    let cid = Cid::new(EMPTY_SHA256);
+    assert_eq!(cid.codec(), RAW_CODEC);
+    assert_eq!(
+        cid.to_string(),
+        "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku"
+    );
+    assert_eq!(cid.to_string().parse::<Cid>().unwrap(), cid);
+    assert_eq!(Cid::from_bytes(&cid.to_bytes()).unwrap(), cid);
+    assert!("bafk".parse::<Cid>().is_err());
+    assert!("not a cid".parse::<Cid>().is_err());
+}
+
+#[test]
+fn identifies_roots_and_proofs() {
+    let (tree, _) = saved();
+    let cid = tree.root_cid().unwrap();
+    assert_eq!(*cid.digest(), tree.merkle_hash().unwrap());
+    assert_eq!(Cid::from_ptr(&tree.root_ptr().unwrap()).unwrap(), cid);
+
+    let proof = tree.prove(&vec![3]).unwrap().unwrap();
+    assert_eq!(proof.root_cid(&vec![3u8], &vec![3u8]), cid);
+    assert!(proof.verify_cid(&cid, &vec![3u8], &vec![3u8]));
+    assert!(!proof.verify_cid(&cid, &vec![3u8], &vec![4u8]));
+    let other_codec = Cid::with_codec(0x71, *cid.digest());
+    assert!(!proof.verify_cid(&other_codec, &vec![3u8], &vec![3u8]));
+}
+
+#[test]
+fn locates_proof_siblings_in_the_store() {
+    let (tree, store) = saved();
+    let proof = tree.prove(&vec![0]).unwrap().unwrap();
+    let siblings = proof.sibling_cids();
+    assert!(!siblings.is_empty());
+    for sibling in siblings.iter().filter(|cid| *cid.digest() != EMPTY_HASH) {
+        let ptr = sibling.to_ptr().unwrap();
+        assert!(store.read(&ptr).is_ok());
+        let subtree = Tree::load(tree.manager().clone(), ptr);
+        assert_eq!(subtree.root_cid().unwrap(), *sibling);
+    }
+}
+
+#[test]
+fn identifies_versions() {
+    let (tree, _) = saved();
+    let mut versioned = VersionedTree::new(tree.manager().clone());
+    versioned.insert(vec![1], vec![1]).unwrap();
+    versioned.save().unwrap();
+    let info = versioned.versions().last().unwrap();
+    assert_eq!(info.cid(), versioned.working().root_cid().unwrap());
+}
@@ -0,0 +1,116 @@
+//! Checks that vector clocks detect concurrency and that hybrid logical
+//! clocks never go backwards, whatever the wall clock does.
+
+use std::cmp::Ordering;
+
+use rhizome_trees::crdt::clock::{Hlc, HlcTimestamp, VectorClock};
+use rhizome_trees::tree::value::ValueCodec;
+
+#[test]
+fn vector_clocks_order_causally_related_events() {
+    let mut a = VectorClock::new();
+    a.increment(1).unwrap();
+    let mut b = a.clone();
+    assert_eq!(a.compare(&b), Some(Ordering::Equal));
+
+    b.increment(2).unwrap();
+    assert!(a.happened_before(&b));
+    assert!(a < b);
+
+    a.increment(1).unwrap();
+    assert!(a.is_concurrent(&b));
+    assert_eq!(a.partial_cmp(&b), None);
+
+    let mut merged = a.clone();
+    merged.merge(&b);
+    assert_eq!(merged.iter().collect::<Vec<_>>(), [(1, 2), (2, 1)]);
+    assert!(a < merged && b < merged);
+    // Merging is idempotent and commutative.
+    let mut other = b.clone();
+    other.merge(&a);
+    other.merge(&a);
+    assert_eq!(other, merged);
+
+    let encoded = merged.to_encoded();
+    assert_eq!(VectorClock::decode(&encoded).unwrap(), merged);
+    assert!(VectorClock::decode(&encoded[..20]).is_err());
+    let mut unsorted = encoded[16..].to_vec();
+    unsorted.extend_from_slice(&encoded[..16]);
+    assert!(VectorClock::decode(&unsorted).is_err());
+}
+
+#[test]
+fn hlc_timestamps_only_increase() {
+    let mut clock = Hlc::new(1);
+    let first = clock.now_at(1000).unwrap();
+    assert_eq!((first.millis, first.logical), (1000, 0));
+    // A wall clock standing still or going backwards bumps the counter.
+    let second = clock.now_at(1000).unwrap();
+    let third = clock.now_at(900).unwrap();
+    assert_eq!((third.millis, third.logical), (1000, 2));
+    assert!(first < second && second < third);
+    let fourth = clock.now_at(1200).unwrap();
+    assert_eq!((fourth.millis, fourth.logical), (1200, 0));
+
+    // Received timestamps ahead of the clock carry it forward.
+    let remote = HlcTimestamp {
+        millis: 1500,
+        logical: 7,
+        replica: 2,
+    };
+    let received = clock.update_at(remote, 1300).unwrap();
+    assert_eq!((received.millis, received.logical), (1500, 8));
+    assert!(remote < received);
+    assert!(received < clock.now_at(1300).unwrap());
+
+    let resumed = Hlc::new(1).resume(clock.last()).now_at(0).unwrap();
+    assert!(resumed > clock.last());
+}
+
+#[test]
+fn hlc_rejects_timestamps_too_far_ahead() {
+    let mut clock = Hlc::new(1).max_drift(100);
+    let remote = |millis| HlcTimestamp {
+        millis,
+        logical: 0,
+        replica: 2,
+    };
+    assert!(clock.update_at(remote(1100), 1000).is_ok());
+    assert!(clock.update_at(remote(1101), 1000).is_err());
+    assert_eq!(clock.last().millis, 1100);
+}
+
+#[test]
+fn hlc_timestamps_encode_in_order() {
+    let timestamps = [
+        HlcTimestamp {
+            millis: 1,
+            logical: u32::MAX,
+            replica: u64::MAX,
+        },
+        HlcTimestamp {
+            millis: 2,
+            logical: 0,
+            replica: 0,
+        },
+        HlcTimestamp {
+            millis: 2,
+            logical: 0,
+            replica: 1,
+        },
+        HlcTimestamp {
+            millis: 2,
+            logical: 1,
+            replica: 0,
+        },
+    ];
+    for pair in timestamps.windows(2) {
+        assert!(pair[0].to_encoded() < pair[1].to_encoded());
+    }
+    for timestamp in timestamps {
+        assert_eq!(
+            HlcTimestamp::decode(&timestamp.to_encoded()).unwrap(),
+            timestamp
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! Checks that key and value codecs round trip, that encoded keys order like
+//! decoded ones and that snapshots carry typed trees.
+
+mod rng;
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::NodeManager;
+use rhizome_trees::tree::value::{
+    BytesValue, KeyCodec, Timestamp, U64BigEndian, ValueCodec, VersionedKey,
+};
+use rng::Rng;
+
+/// Checks that `a` and `b` round trip and that their encodings compare as
+/// they do.
+fn check<K: KeyCodec + std::fmt::Debug>(a: K, b: K) {
+    let (x, y) = (a.to_encoded(), b.to_encoded());
+    assert_eq!(K::decode(&x).unwrap(), a);
+    assert_eq!(K::decode(&y).unwrap(), b);
+    assert_eq!(K::compare(&x, &y), a.cmp(&b), "{:?} {:?}", a, b);
+}
+
+#[test]
+fn preserves_key_order() {
+    let mut rng = Rng(7);
+    for _ in 0..1000 {
+        let (a, b) = (rng.next(), rng.next());
+        check(U64BigEndian(a), U64BigEndian(b));
+        check(U64BigEndian(a), U64BigEndian(a));
+        let nanos = |n: u64| (n % 1_000_000_000) as u32;
+        check(
+            Timestamp::new(a as i64, nanos(b)),
+            Timestamp::new(b as i64, nanos(a)),
+        );
+        check(
+            Timestamp::new(a as i64, nanos(a)),
+            Timestamp::new(a as i64, nanos(b)),
+        );
+        let key = |n: u64| BytesValue(n.to_le_bytes()[..(n % 9) as usize].to_vec());
+        check(key(a), key(b));
+        check((U64BigEndian(a % 3), key(a)), (U64BigEndian(b % 3), key(b)));
+    }
+    assert_eq!(
+        U64BigEndian::compare(
+            &U64BigEndian(1).to_encoded(),
+            &U64BigEndian(256).to_encoded()
+        ),
+        Ordering::Less
+    );
+}
+
+#[test]
+fn refuses_malformed_encodings() {
+    let key = VersionedKey::decode(&[0, 0, 0, 0, 0, 0, 0, 5, 1, 2]).unwrap();
+    assert_eq!(key, (U64BigEndian(5), BytesValue(vec![1, 2])));
+    assert_eq!(key.to_encoded(), [0, 0, 0, 0, 0, 0, 0, 5, 1, 2]);
+    assert!(VersionedKey::decode(&[1]).is_err());
+    assert!(U64BigEndian::decode(&[1; 9]).is_err());
+    assert!(<()>::decode(&[1]).is_err());
+
+    // Timestamps never encode 10^9 nanoseconds or more.
+    let mut bytes = [0u8; 12];
+    bytes[8..].copy_from_slice(&2_000_000_000u32.to_be_bytes());
+    assert!(Timestamp::decode(&bytes).is_err());
+    assert!(Timestamp::decode(&bytes[..11]).is_err());
+}
+
+#[test]
+fn snapshots_typed_trees() {
+    let tree = (0..500u64).fold(Tree::new(), |tree, i| {
+        tree.insert(U64BigEndian(i), Timestamp::new(i as i64 - 100, 7))
+            .unwrap()
+    });
+    let mut buf = Vec::new();
+    assert_eq!(tree.export_snapshot(&mut buf).unwrap(), 500);
+    let imported = Tree::<U64BigEndian, Timestamp>::import_snapshot(
+        Arc::new(NodeManager::in_memory()),
+        &buf[..],
+    )
+    .unwrap();
+    assert_eq!(imported.merkle_hash().unwrap(), tree.merkle_hash().unwrap());
+    assert_eq!(
+        imported.get(&U64BigEndian(3)).unwrap(),
+        Some(Timestamp::new(-97, 7))
+    );
+
+    // The format is the same as for byte trees.
+    let bytes =
+        Tree::<Vec<u8>, Vec<u8>>::import_snapshot(Arc::new(NodeManager::in_memory()), &buf[..])
+            .unwrap();
+    assert_eq!(bytes.merkle_hash().unwrap(), tree.merkle_hash().unwrap());
+    assert_eq!(
+        bytes.get(&U64BigEndian(3).to_encoded()).unwrap(),
+        Some(Timestamp::new(-97, 7).to_encoded())
+    );
+}
@@ -0,0 +1,96 @@
+//! Checks that commit validators see each save's changes and that a vetoed
+//! save writes nothing and leaves the working tree to be fixed.
+
+use std::sync::{Arc, Mutex};
+
+use rhizome_trees::tree::avl::diff::Change;
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::versioned::{PendingCommit, Version, VersionedTree};
+use rhizome_trees::tree::node_manager::{MemNodeStore, NodeManager};
+use rhizome_trees::{Error, Result};
+
+type Bytes = Vec<u8>;
+
+/// Rejects balances, stored as big-endian `i64`s, which go negative.
+fn non_negative(commit: &PendingCommit<'_, Bytes, Bytes>) -> Result<()> {
+    for change in commit.changes {
+        if let Change::Create { key, value }
+        | Change::Update {
+            key, new: value, ..
+        } = change
+        {
+            let balance = i64::from_be_bytes(value[..].try_into().unwrap());
+            if balance < 0 {
+                return Err(Error::Invalid(format!(
+                    "balance of {:?} is negative at version {}",
+                    key, commit.version
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn balance(amount: i64) -> Bytes {
+    amount.to_be_bytes().to_vec()
+}
+
+#[test]
+fn vetoes_invalid_saves() {
+    let store = Arc::new(MemNodeStore::<Node<Bytes, Bytes>>::new());
+    let mut tree = VersionedTree::new(Arc::new(NodeManager::new(store.clone())));
+    tree.add_validator(Arc::new(non_negative));
+
+    tree.insert(b"alice".to_vec(), balance(10)).unwrap();
+    assert_eq!(tree.save().unwrap(), Version::new(1));
+    let stored = store.len();
+
+    tree.insert(b"alice".to_vec(), balance(-5)).unwrap();
+    tree.insert(b"bob".to_vec(), balance(15)).unwrap();
+    assert!(tree.save().is_err());
+    assert_eq!(tree.latest_version(), Version::new(1));
+    assert_eq!(store.len(), stored);
+    // The rejected changes are still there to be fixed.
+    assert_eq!(tree.get(&b"bob".to_vec()).unwrap(), Some(balance(15)));
+
+    tree.insert(b"alice".to_vec(), balance(0)).unwrap();
+    assert_eq!(tree.save().unwrap(), Version::new(2));
+    // Deletes aren't balances, so they pass.
+    tree.delete(&b"alice".to_vec()).unwrap();
+    assert_eq!(tree.save().unwrap(), Version::new(3));
+}
+
+#[test]
+fn passes_each_save_to_every_validator() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut tree = VersionedTree::new(Arc::new(NodeManager::in_memory()));
+    for id in 0..2 {
+        let seen = seen.clone();
+        tree.add_validator(Arc::new(move |commit: &PendingCommit<'_, Bytes, Bytes>| {
+            let base = commit.base.len()?;
+            let working = commit.working.len()?;
+            seen.lock().unwrap().push((
+                id,
+                commit.version.get(),
+                base,
+                working,
+                commit.changes.len(),
+            ));
+            Ok(())
+        }));
+    }
+    tree.insert(vec![1], vec![1]).unwrap();
+    tree.insert(vec![2], vec![2]).unwrap();
+    tree.save().unwrap();
+    tree.delete(&vec![1]).unwrap();
+    tree.save().unwrap();
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![
+            (0, 1, 0, 2, 2),
+            (1, 1, 0, 2, 2),
+            (0, 2, 2, 1, 1),
+            (1, 2, 2, 1, 1),
+        ]
+    );
+}
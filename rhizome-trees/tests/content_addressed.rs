@@ -0,0 +1,71 @@
+//! Checks that a content-addressed store points at nodes by their merkle
+//! hashes, so identical subtrees of separately built versions are stored
+//! once and kept until no version uses them.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::{ContentAddressedStore, NodeManager};
+use rhizome_trees::tree::value::U64BigEndian as U;
+
+type Store = ContentAddressedStore<Node<U, U>>;
+
+fn manager() -> (Arc<NodeManager<Node<U, U>>>, Arc<Store>) {
+    let store = Arc::new(ContentAddressedStore::new());
+    (Arc::new(NodeManager::new(store.clone())), store)
+}
+
+fn filled(manager: &Arc<NodeManager<Node<U, U>>>, entries: u64) -> Tree<U, U> {
+    (0..entries).fold(Tree::with_manager(manager.clone()), |tree, i| {
+        tree.insert(U(i), U(i)).unwrap()
+    })
+}
+
+#[test]
+fn points_at_nodes_by_hash() {
+    let (manager, _) = manager();
+    let tree = filled(&manager, 200);
+    let root = tree.merkle_hash().unwrap();
+    let saved = tree.save().unwrap();
+    assert_eq!(saved.root_ptr().unwrap().as_bytes(), &root);
+
+    let changed = Tree::load(manager.clone(), saved.root_ptr().unwrap())
+        .insert(U(500), U(1))
+        .unwrap();
+    let root = changed.merkle_hash().unwrap();
+    let saved = changed.save().unwrap();
+    assert_eq!(saved.root_ptr().unwrap().as_bytes(), &root);
+    let loaded = Tree::load(manager, saved.root_ptr().unwrap());
+    assert_eq!(loaded.get(&U(500)).unwrap(), Some(U(1)));
+}
+
+#[test]
+fn stores_identical_subtrees_once() {
+    let (manager, store) = manager();
+    let first = filled(&manager, 200).save().unwrap();
+    let nodes = store.len();
+    assert_eq!(nodes, 200);
+
+    // The same entries inserted in the same order give the same nodes.
+    let second = filled(&manager, 200).save().unwrap();
+    assert_eq!(second.root_ptr(), first.root_ptr());
+    assert_eq!(store.len(), nodes);
+    let root = first.root_ptr().unwrap();
+    assert_eq!(manager.ref_count(&root).unwrap(), Some(2));
+
+    // A version sharing most of its nodes adds only the changed path.
+    let third = second.insert(U(0), U(1)).unwrap().save().unwrap();
+    let with_third = store.len();
+    assert!(with_third - nodes < 10, "{} new nodes", with_third - nodes);
+
+    // Releasing the duplicate keeps every node, and releasing the rest
+    // leaves only the third version's.
+    assert_eq!(manager.release(&root).unwrap(), 0);
+    assert_eq!(store.len(), with_third);
+    assert!(manager.release(&root).unwrap() > 0);
+    let remaining = Tree::load(manager.clone(), third.root_ptr().unwrap());
+    assert_eq!(remaining.iter().unwrap().count(), 200);
+    assert_eq!(store.len() as u64, remaining.stats().unwrap().nodes);
+}
@@ -0,0 +1,107 @@
+//! Checks that broken tree invariants go through the corruption hook and
+//! policy, while a missing root is returned like any other error.
+
+use std::sync::{Arc, Mutex};
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{
+    CorruptionPolicy, MemNodeStore, NodeManager, NodeStore, Ptr,
+};
+use rhizome_trees::Error;
+
+type Bytes = Vec<u8>;
+type Store = MemNodeStore<Node<Bytes, Bytes>>;
+type Reported = Arc<Mutex<Vec<String>>>;
+
+/// A manager over `store`, with an empty cache, recording the errors passed
+/// to its corruption hook.
+fn manager(
+    store: &Arc<Store>,
+    policy: CorruptionPolicy,
+) -> (Arc<NodeManager<Node<Bytes, Bytes>>>, Reported) {
+    let reported = Arc::new(Mutex::new(Vec::new()));
+    let hook = reported.clone();
+    let manager = NodeManager::builder(store.clone())
+        .corruption_policy(policy)
+        .on_corruption(move |err| hook.lock().unwrap().push(err.to_string()))
+        .build();
+    (Arc::new(manager), reported)
+}
+
+/// Saves a tree of three entries, returning its root.
+fn saved(store: &Arc<Store>) -> Ptr {
+    let (manager, _) = manager(store, CorruptionPolicy::Error);
+    let tree = [b"a", b"b", b"c"]
+        .into_iter()
+        .fold(Tree::with_manager(manager), |tree, key| {
+            tree.insert(key.to_vec(), key.to_vec()).unwrap()
+        });
+    tree.save().unwrap().root_ptr().unwrap()
+}
+
+#[test]
+fn returns_a_missing_root_without_aborting() {
+    let store = Arc::new(MemNodeStore::new());
+    let root = saved(&store);
+    store.delete(&root).unwrap();
+
+    // Aborting would end the test.
+    let (manager, reported) = manager(&store, CorruptionPolicy::Abort);
+    assert_eq!(manager.corruption_policy(), CorruptionPolicy::Abort);
+    let tree = Tree::<Bytes, Bytes>::load(manager, root);
+    let err = tree.get(&b"a".to_vec()).unwrap_err();
+    assert!(
+        matches!(err, Error::NotFound(ptr) if ptr == root),
+        "{}",
+        err
+    );
+    assert!(!err.is_corruption());
+    assert!(tree.verify().is_err());
+    assert!(reported.lock().unwrap().is_empty());
+}
+
+#[test]
+fn reports_a_missing_child() {
+    let store = Arc::new(MemNodeStore::new());
+    let root = saved(&store);
+    let root_node = store.read(&root).unwrap();
+    let left = root_node.left().unwrap().ptr().unwrap();
+    store.delete(&left).unwrap();
+
+    let (manager, reported) = manager(&store, CorruptionPolicy::Error);
+    let tree = Tree::<Bytes, Bytes>::load(manager, root);
+    assert_eq!(tree.get(&b"c".to_vec()).unwrap(), Some(b"c".to_vec()));
+    let err = tree.get(&b"a".to_vec()).unwrap_err();
+    assert!(matches!(err, Error::Corruption(_)), "{}", err);
+    assert!(err.is_corruption());
+    assert!(tree.insert(b"0".to_vec(), vec![]).is_err());
+    assert!(matches!(tree.verify(), Err(Error::Corruption(_))));
+    // Each failed operation reported the missing child once.
+    let reported = reported.lock().unwrap();
+    assert_eq!(reported.len(), 3);
+    assert!(
+        reported.iter().all(|err| err.contains("missing")),
+        "{:?}",
+        reported
+    );
+}
+
+#[test]
+fn reports_a_damaged_node() {
+    let store = Arc::new(MemNodeStore::new());
+    let root = saved(&store);
+    // Overwrite the left leaf with the right one, as if the store had
+    // returned the wrong bytes.
+    let root_node = store.read(&root).unwrap();
+    let left = root_node.left().unwrap().ptr().unwrap();
+    let right = root_node.right().unwrap().ptr().unwrap();
+    let right_node = store.read(&right).unwrap();
+    assert!(store.try_update(&[(left, right_node)]).unwrap());
+
+    let (manager, reported) = manager(&store, CorruptionPolicy::Error);
+    let tree = Tree::<Bytes, Bytes>::load(manager, root);
+    let err = tree.verify().unwrap_err();
+    assert!(matches!(err, Error::Corruption(_)), "{}", err);
+    assert_eq!(*reported.lock().unwrap(), vec![err.to_string()]);
+}
@@ -0,0 +1,161 @@
+//! Checks that anti-entropy syncs find exactly the entries two replicas
+//! disagree on, whatever the shapes of their trees, while exchanging far
+//! fewer entries than the replicas hold.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use rhizome_trees::crdt::sync::{apply_delta, sync, KeyRange, Message, SyncOptions, SyncSession};
+use rhizome_trees::crdt::ORSet;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::NodeManager;
+
+type Bytes = Vec<u8>;
+
+fn key(i: u32) -> Bytes {
+    i.to_be_bytes().to_vec()
+}
+
+fn tree_of(entries: impl IntoIterator<Item = (Bytes, Bytes)>) -> Tree<Bytes, Bytes> {
+    let mut tree = Tree::new();
+    for (key, value) in entries {
+        tree = tree.insert(key, value).unwrap();
+    }
+    tree
+}
+
+fn contents(tree: &Tree<Bytes, Bytes>) -> BTreeMap<Bytes, Bytes> {
+    tree.iter()
+        .unwrap()
+        .map(|node| {
+            let node = node.unwrap();
+            (node.key().clone(), node.value().clone())
+        })
+        .collect()
+}
+
+/// Counts the entries carried by `messages`.
+fn entries_sent(messages: &[Message<Bytes, Bytes>]) -> usize {
+    messages
+        .iter()
+        .map(|message| match message {
+            Message::Digest { .. } => 0,
+            Message::Entries { entries, .. } | Message::Missing { entries } => entries.len(),
+        })
+        .sum()
+}
+
+#[test]
+fn sync_finds_exactly_the_differences() {
+    let entries: Vec<_> = (0..2000).map(|i| (key(i), key(i))).collect();
+    // The replicas insert in opposite orders, so their trees differ in shape.
+    let mut a = tree_of(entries.iter().cloned());
+    let mut b = tree_of(entries.iter().rev().cloned());
+    a = a.insert(key(5000), b"only a".to_vec()).unwrap();
+    a = a.delete(&key(700)).unwrap();
+    b = b.insert(key(1234), b"changed".to_vec()).unwrap();
+    b = b.insert(key(9999), b"only b".to_vec()).unwrap();
+
+    let (mut session_a, mut session_b) = (
+        SyncSession::new(&a, SyncOptions::default()),
+        SyncSession::new(&b, SyncOptions::default()),
+    );
+    let mut messages = session_a.start().unwrap();
+    let (mut sent, mut rounds) = (0, 0);
+    while !messages.is_empty() {
+        sent += entries_sent(&messages);
+        rounds += 1;
+        messages = if rounds % 2 == 1 {
+            session_b.receive(messages).unwrap()
+        } else {
+            session_a.receive(messages).unwrap()
+        };
+    }
+    assert!(sent < 200, "sent {} entries", sent);
+
+    let mut delta_a = session_a.into_delta();
+    let mut delta_b = session_b.into_delta();
+    delta_a.sort();
+    delta_b.sort();
+    assert_eq!(
+        delta_a,
+        [
+            (key(700), key(700)),
+            (key(1234), b"changed".to_vec()),
+            (key(9999), b"only b".to_vec()),
+        ]
+    );
+    assert_eq!(
+        delta_b,
+        [(key(1234), key(1234)), (key(5000), b"only a".to_vec()),]
+    );
+
+    // Merging by keeping the larger value makes the replicas converge.
+    let merge = |_: &Bytes, a: Bytes, b: Bytes| a.max(b);
+    let a = apply_delta(&a, delta_a, merge).unwrap();
+    let b = apply_delta(&b, delta_b, merge).unwrap();
+    assert_eq!(contents(&a), contents(&b));
+    let (delta_a, delta_b) = sync(&a, &b, SyncOptions::default()).unwrap();
+    assert!(delta_a.is_empty() && delta_b.is_empty());
+}
+
+#[test]
+fn sync_handles_empty_and_disjoint_replicas() {
+    let empty = Tree::new();
+    let full = tree_of((0..100).map(|i| (key(i), key(i))));
+    let options = SyncOptions::default().fanout(2).leaf_len(1);
+    let (to_empty, to_full) = sync(&empty, &full, options).unwrap();
+    assert_eq!(to_empty.len(), 100);
+    assert!(to_full.is_empty());
+
+    let odd = tree_of((0..100).filter(|i| i % 2 == 1).map(|i| (key(i), key(i))));
+    let even = tree_of((0..100).filter(|i| i % 2 == 0).map(|i| (key(i), key(i))));
+    let (to_odd, to_even) = sync(&odd, &even, options).unwrap();
+    assert_eq!((to_odd.len(), to_even.len()), (50, 50));
+}
+
+#[test]
+fn sessions_reject_entries_outside_their_range() {
+    let tree = tree_of([(key(1), key(1))]);
+    let mut session = SyncSession::new(&tree, SyncOptions::default());
+    let range = KeyRange {
+        start: Some(key(10)),
+        end: None,
+    };
+    let message = Message::Entries {
+        range,
+        entries: vec![(key(1), key(1))],
+    };
+    assert!(session.receive(vec![message]).is_err());
+}
+
+#[test]
+fn synced_or_sets_converge_like_merged_ones() {
+    let manager = Arc::new(NodeManager::in_memory());
+    let mut a = ORSet::new(manager.clone(), 1);
+    let mut b = ORSet::new(manager.clone(), 2);
+    for i in 0..200u32 {
+        a.add(key(i)).unwrap();
+    }
+    b.merge(&a).unwrap();
+    a.remove(&key(3)).unwrap();
+    b.remove(&key(4)).unwrap();
+    b.add(key(3)).unwrap();
+    a.add(key(500)).unwrap();
+
+    let mut merged = ORSet::new(manager, 3);
+    merged.merge(&a).unwrap();
+    merged.merge(&b).unwrap();
+
+    let options = SyncOptions::default();
+    let (removed_a, removed_b) = sync(a.removed(), b.removed(), options).unwrap();
+    let (added_a, added_b) = sync(a.added(), b.added(), options).unwrap();
+    let tags = |delta: Vec<(_, ())>| delta.into_iter().map(|(tagged, ())| tagged);
+    a.merge_tags(tags(removed_a), tags(added_a)).unwrap();
+    b.merge_tags(tags(removed_b), tags(added_b)).unwrap();
+
+    let expected = merged.elements().unwrap();
+    assert!(expected.contains(&key(3)) && !expected.contains(&key(4)));
+    assert_eq!(a.elements().unwrap(), expected);
+    assert_eq!(b.elements().unwrap(), expected);
+}
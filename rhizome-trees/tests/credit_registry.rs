@@ -0,0 +1,86 @@
+//! Checks the credit registry demo: its account and balance rules, proving
+//! balances against each version and listing the changes of a version.
+
+#![cfg(feature = "demo")]
+
+use rhizome_trees::demo::{credit_key, CreditRegistry, ACCOUNTS, CREDITS, RETIREMENTS};
+use rhizome_trees::tree::avl::diff::Change;
+use rhizome_trees::tree::avl::versioned::Version;
+
+const BATCH: &str = "C01-2024";
+
+#[test]
+fn enforces_balances() {
+    let mut registry = CreditRegistry::default();
+    registry.open_account("issuer").unwrap();
+    registry.open_account("buyer").unwrap();
+    assert!(registry.open_account("buyer").is_err());
+    assert!(registry.open_account("").is_err());
+    assert!(registry.open_account("a\0b").is_err());
+    assert!(registry.issue("nobody", BATCH, 1).is_err());
+
+    registry.issue("issuer", BATCH, 100).unwrap();
+    assert!(registry.issue("issuer", BATCH, u64::MAX).is_err());
+    assert!(registry.transfer("issuer", "buyer", BATCH, 101).is_err());
+    assert!(registry.transfer("issuer", "nobody", BATCH, 1).is_err());
+    assert_eq!(registry.balance("issuer", BATCH).unwrap(), 100);
+
+    registry.transfer("issuer", "buyer", BATCH, 40).unwrap();
+    assert_eq!(registry.retire("buyer", BATCH, 10).unwrap(), 0);
+    assert_eq!(registry.retire("buyer", BATCH, 30).unwrap(), 1);
+    assert!(registry.retire("buyer", BATCH, 1).is_err());
+    assert_eq!(registry.balance("issuer", BATCH).unwrap(), 60);
+    assert_eq!(registry.balance("buyer", BATCH).unwrap(), 0);
+}
+
+#[test]
+fn proves_committed_balances() {
+    let mut registry = CreditRegistry::default();
+    registry.open_account("issuer").unwrap();
+    registry.issue("issuer", BATCH, 100).unwrap();
+    let first = registry.commit().unwrap();
+    assert_eq!(first.version, Version::new(1));
+
+    // Uncommitted changes aren't proven.
+    registry.retire("issuer", BATCH, 25).unwrap();
+    let proof = registry.prove_balance("issuer", BATCH).unwrap().unwrap();
+    assert_eq!(proof.balance, 100);
+    assert!(proof.verify(&first.hash));
+
+    let second = registry.commit().unwrap();
+    let proof = registry.prove_balance("issuer", BATCH).unwrap().unwrap();
+    assert_eq!((proof.version, proof.balance), (second.version, 75));
+    assert_eq!(proof.key, credit_key("issuer", BATCH));
+    assert!(proof.verify(&second.hash));
+    assert!(!proof.verify(&first.hash));
+    assert!(registry.prove_balance("issuer", "C02").unwrap().is_none());
+}
+
+#[test]
+fn lists_changes() {
+    let mut registry = CreditRegistry::default();
+    registry.open_account("issuer").unwrap();
+    registry.issue("issuer", BATCH, 100).unwrap();
+    let first = registry.commit().unwrap();
+    registry.retire("issuer", BATCH, 100).unwrap();
+    let second = registry.commit().unwrap();
+
+    let trees: Vec<_> = registry
+        .changes(first.version)
+        .unwrap()
+        .into_iter()
+        .map(|change| change.tree)
+        .collect();
+    assert_eq!(trees, [ACCOUNTS, CREDITS]);
+
+    let changes = registry.changes(second.version).unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0].tree, CREDITS);
+    assert!(matches!(&changes[0].change, Change::Delete { .. }));
+    assert_eq!(changes[1].tree, RETIREMENTS);
+    assert!(
+        matches!(&changes[1].change, Change::Create { key, .. } if key[..] == 0u64.to_be_bytes())
+    );
+
+    assert!(registry.changes(Version::new(0)).is_err());
+}
@@ -0,0 +1,42 @@
+//! Checks that trees created without a manager can be saved, loaded and
+//! versioned in memory, while memory-only managers still refuse to save.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::set::PersistentSet;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::NodeManager;
+
+type Bytes = Vec<u8>;
+
+#[test]
+fn saves_default_trees() {
+    let tree = Tree::new().insert(vec![1], vec![2]).unwrap();
+    let saved = tree.save().unwrap();
+    let root = saved.root_ptr().unwrap();
+    let loaded = Tree::load(saved.manager().clone(), root);
+    assert_eq!(loaded.get(&vec![1]).unwrap(), Some(vec![2]));
+
+    // Later versions share the store with the first.
+    let next = loaded.insert(vec![3], vec![4]).unwrap().save().unwrap();
+    let first = Tree::<Bytes, Bytes>::load(next.manager().clone(), root);
+    assert_eq!(first.get(&vec![3]).unwrap(), None);
+    assert_eq!(next.get(&vec![1]).unwrap(), Some(vec![2]));
+
+    let set = PersistentSet::new()
+        .insert(vec![5u8])
+        .unwrap()
+        .save()
+        .unwrap();
+    assert!(set.contains(&vec![5]).unwrap());
+}
+
+#[test]
+fn refuses_to_save_memory_only_trees() {
+    let manager = Arc::new(NodeManager::memory_only());
+    let tree = Tree::<Bytes, Bytes>::with_manager(manager)
+        .insert(vec![1], vec![2])
+        .unwrap();
+    assert!(tree.save().is_err());
+    assert_eq!(tree.get(&vec![1]).unwrap(), Some(vec![2]));
+}
@@ -0,0 +1,122 @@
+//! Checks that pruning into a deletion queue keeps the nodes of pruned
+//! versions until the queue is drained, and that draining deletes exactly
+//! the nodes no remaining version uses.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::versioned::{PruningPolicy, Version, VersionedTree};
+use rhizome_trees::tree::node_manager::{
+    DeletionQueue, DeletionStats, DrainOptions, MemNodeStore, NodeManager,
+};
+
+type Bytes = Vec<u8>;
+type Store = MemNodeStore<Node<Bytes, Bytes>>;
+
+fn key(i: u64) -> Bytes {
+    i.to_be_bytes().to_vec()
+}
+
+/// Saves `versions` versions, each adding a key and overwriting a counter.
+fn saved(versions: u64) -> (VersionedTree<Bytes, Bytes>, Arc<Store>) {
+    let store = Arc::new(MemNodeStore::new());
+    let manager = Arc::new(NodeManager::new(store.clone()));
+    let mut tree = VersionedTree::new(manager);
+    for i in 1..=versions {
+        tree.insert(key(i), key(i)).unwrap();
+        tree.insert(b"counter".to_vec(), key(i)).unwrap();
+        tree.save().unwrap();
+    }
+    (tree, store)
+}
+
+#[test]
+fn defers_deleting_pruned_versions() {
+    let (mut tree, store) = saved(10);
+    let queue = DeletionQueue::new(tree.working().manager().clone(), DrainOptions::default());
+    let before = store.len();
+
+    tree.set_pruning(PruningPolicy::default().keep_recent(1));
+    assert_eq!(tree.prune_deferred(&queue).unwrap(), 9);
+    assert_eq!(tree.versions().len(), 1);
+    // Nothing is deleted until the queue is drained.
+    assert_eq!(store.len(), before);
+    assert_eq!(queue.pending().unwrap().len(), 9);
+    assert_eq!(
+        queue.stats(),
+        DeletionStats {
+            queued: 9,
+            released: 0,
+            deleted: 0,
+        }
+    );
+
+    // Draining a few pointers at a time releases the versions gradually.
+    let deleted = queue.drain(3).unwrap();
+    assert_eq!(queue.stats().released, 3);
+    assert_eq!(queue.stats().deleted, deleted as u64);
+    assert_eq!(store.len(), before - deleted);
+
+    let deleted = deleted + queue.drain_all().unwrap();
+    assert!(queue.is_empty());
+    assert_eq!(queue.stats().deleted, deleted as u64);
+    assert_eq!(store.len(), before - deleted);
+
+    // The remaining version is intact, and nodes are stored only once per
+    // version sharing them.
+    let latest = tree.load_version(Version::new(10)).unwrap();
+    assert_eq!(latest.len().unwrap(), 11);
+    assert_eq!(latest.get(&b"counter".to_vec()).unwrap(), Some(key(10)));
+    assert_eq!(store.len() as u64, latest.stats().unwrap().nodes);
+}
+
+#[test]
+fn drains_on_a_background_thread() {
+    // The same history, pruned inline.
+    let (mut inline, inline_store) = saved(20);
+    inline.set_pruning(PruningPolicy::default().keep_recent(2));
+    inline.prune().unwrap();
+    inline.insert(key(100), key(100)).unwrap();
+    inline.save().unwrap();
+
+    let (mut tree, store) = saved(20);
+    let options = DrainOptions::default()
+        .nodes_per_second(10_000)
+        .chunk_nodes(4);
+    let queue = Arc::new(DeletionQueue::new(
+        tree.working().manager().clone(),
+        options,
+    ));
+    let handle = queue.spawn();
+
+    tree.set_pruning(PruningPolicy::default().keep_recent(2));
+    tree.prune_deferred(&queue).unwrap();
+    // Versions saved while the queue drains share nodes with pruned ones.
+    tree.insert(key(100), key(100)).unwrap();
+    tree.save().unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !queue.is_empty() {
+        assert!(Instant::now() < deadline, "queue not drained");
+        thread::sleep(Duration::from_millis(5));
+    }
+    handle.stop().unwrap();
+
+    assert_eq!(tree.versions().len(), 3);
+    for version in tree.versions() {
+        let loaded = tree.load_version(version.version).unwrap();
+        assert_eq!(loaded.iter().unwrap().count() as u64, loaded.len().unwrap());
+    }
+    assert_eq!(store.len(), inline_store.len());
+}
+
+#[test]
+fn refuses_a_queue_of_another_manager() {
+    let (mut tree, _) = saved(2);
+    let queue = DeletionQueue::new(Arc::new(NodeManager::in_memory()), DrainOptions::default());
+    tree.set_pruning(PruningPolicy::default().keep_recent(1));
+    assert!(tree.prune_deferred(&queue).is_err());
+    assert_eq!(tree.versions().len(), 2);
+}
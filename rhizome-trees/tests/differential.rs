@@ -0,0 +1,198 @@
+//! Runs randomized workloads against the trees and the reference model of
+//! [`reference`], checking after every step that roots, proofs and query
+//! results agree. The model hashes from the documented schemes, so any
+//! change to how the trees hash shows up here rather than as silently
+//! different roots.
+
+mod reference;
+mod rng;
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use reference::{Bytes, Model, Shape};
+use rhizome_trees::tree::art;
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::{NodeManager, NodeRef};
+use rng::Rng;
+
+const SEEDS: u64 = 10;
+const STEPS: usize = 200;
+
+/// Seeds a generator from a small seed, which must not be all zeros.
+fn seeded(seed: u64) -> Rng {
+    Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+}
+
+fn below(rng: &mut Rng, n: usize) -> usize {
+    (rng.next() % n as u64) as usize
+}
+
+/// Returns a short key over a few byte values, so that keys collide,
+/// share prefixes and are prefixes of one another.
+fn random_key(rng: &mut Rng) -> Bytes {
+    let len = below(rng, 4);
+    (0..len).map(|_| b"ab\x00\xff"[below(rng, 4)]).collect()
+}
+
+fn random_value(rng: &mut Rng) -> Bytes {
+    let len = below(rng, 3);
+    (0..len).map(|_| rng.next() as u8).collect()
+}
+
+fn random_bound(rng: &mut Rng) -> Bound<Bytes> {
+    match below(rng, 3) {
+        0 => Bound::Unbounded,
+        1 => Bound::Included(random_key(rng)),
+        _ => Bound::Excluded(random_key(rng)),
+    }
+}
+
+/// Returns the shape of the subtree at `link`.
+fn shape(tree: &Tree<Bytes, Bytes>, link: Option<&NodeRef<Node<Bytes, Bytes>>>) -> Shape {
+    let Some(link) = link else {
+        return Shape::Empty;
+    };
+    let node = tree.read(link).unwrap();
+    Shape::Node(
+        Box::new(shape(tree, node.left())),
+        Box::new(shape(tree, node.right())),
+    )
+}
+
+fn entry(
+    node: Option<impl std::ops::Deref<Target = Node<Bytes, Bytes>>>,
+) -> Option<(Bytes, Bytes)> {
+    node.map(|node| (node.key().clone(), node.value().clone()))
+}
+
+fn check_avl(tree: &Tree<Bytes, Bytes>, model: &Model, rng: &mut Rng) {
+    let shape = shape(tree, tree.root());
+    assert!(shape.is_balanced());
+    let root = model.avl_root(&shape);
+    assert_eq!(tree.merkle_hash().unwrap(), root);
+    assert_eq!(tree.len().unwrap(), model.len() as u64);
+
+    let all: Vec<_> = tree
+        .iter()
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (entry.key().clone(), entry.value().clone())
+        })
+        .collect();
+    assert_eq!(all, model.entries());
+
+    for _ in 0..4 {
+        let key = random_key(rng);
+        assert_eq!(tree.get(&key).unwrap().as_ref(), model.get(&key));
+        assert_eq!(tree.rank(&key).unwrap(), model.rank(&key) as u64);
+        assert_eq!(
+            entry(tree.successor(&key).unwrap()).as_ref(),
+            model.successor(&key)
+        );
+        assert_eq!(
+            entry(tree.predecessor(&key).unwrap()).as_ref(),
+            model.predecessor(&key)
+        );
+        let index = below(rng, model.len() + 1);
+        assert_eq!(
+            entry(tree.nth(index as u64).unwrap()).as_ref(),
+            model.nth(index)
+        );
+
+        let proof = tree.prove(&key).unwrap();
+        match (&proof, model.get(&key)) {
+            (Some(proof), Some(_)) => {
+                assert_eq!(model.avl_proof_root(&key, proof), Some(root));
+            }
+            (None, None) => {}
+            (proof, value) => panic!("proof {:?} for a key with value {:?}", proof, value),
+        }
+
+        let range = (random_bound(rng), random_bound(rng));
+        let found: Vec<_> = match tree.range(range.clone()) {
+            Ok(entries) => entries
+                .map(|entry| {
+                    let entry = entry.unwrap();
+                    (entry.key().clone(), entry.value().clone())
+                })
+                .collect(),
+            // Ranges whose start is after their end are rejected, like
+            // the ranges of a BTreeMap.
+            Err(_) => continue,
+        };
+        assert_eq!(found, model.range(range));
+    }
+}
+
+fn check_art(tree: &art::Tree<Bytes>, model: &Model, rng: &mut Rng) {
+    assert_eq!(tree.merkle_hash().unwrap(), model.art_root());
+    assert_eq!(tree.len(), model.len() as u64);
+    for _ in 0..4 {
+        let key = random_key(rng);
+        assert_eq!(tree.get(&key), model.get(&key));
+        let scanned: Vec<_> = tree
+            .scan_prefix(&key)
+            .into_iter()
+            .map(|(key, value)| (key.to_vec(), value.clone()))
+            .collect();
+        let expected: Vec<_> = model
+            .entries()
+            .iter()
+            .filter(|(k, _)| k.starts_with(&key))
+            .cloned()
+            .collect();
+        assert_eq!(scanned, expected);
+    }
+}
+
+#[test]
+fn avl_trees_match_the_model() {
+    for seed in 0..SEEDS {
+        let mut rng = seeded(seed);
+        let manager = Arc::new(NodeManager::in_memory());
+        let mut tree: Tree<Bytes, Bytes> = Tree::with_manager(manager);
+        let mut model = Model::default();
+        for _ in 0..STEPS {
+            match below(&mut rng, 10) {
+                0..=5 => {
+                    let (key, value) = (random_key(&mut rng), random_value(&mut rng));
+                    tree = tree.insert(key.clone(), value.clone()).unwrap();
+                    model.insert(key, value);
+                }
+                6..=8 => {
+                    let key = random_key(&mut rng);
+                    tree = tree.delete(&key).unwrap();
+                    model.delete(&key);
+                }
+                // Saving mixes stored nodes into the later versions.
+                _ => tree = tree.save().unwrap(),
+            }
+            check_avl(&tree, &model, &mut rng);
+        }
+    }
+}
+
+#[test]
+fn radix_trees_match_the_model() {
+    for seed in 0..SEEDS {
+        let mut rng = seeded(seed);
+        let mut tree = art::Tree::new();
+        let mut model = Model::default();
+        for _ in 0..STEPS {
+            let key = random_key(&mut rng);
+            if below(&mut rng, 3) < 2 {
+                let value = random_value(&mut rng);
+                tree = tree.insert(key.clone(), value.clone());
+                model.insert(key, value);
+            } else {
+                tree = tree.delete(&key);
+                model.delete(&key);
+            }
+            check_art(&tree, &model, &mut rng);
+        }
+    }
+}
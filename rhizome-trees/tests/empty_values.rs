@@ -0,0 +1,70 @@
+//! Checks that versioned trees allow, reject or delete on empty values as
+//! configured, for single writes, batches and changesets alike.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::diff::Change;
+use rhizome_trees::tree::avl::versioned::{ChangesetId, EmptyValues, VersionedTree};
+use rhizome_trees::tree::avl::BatchOp;
+use rhizome_trees::tree::node_manager::NodeManager;
+use rhizome_trees::Error;
+
+type Bytes = Vec<u8>;
+
+fn versioned(empty_values: EmptyValues) -> VersionedTree<Bytes, Bytes> {
+    let mut tree = VersionedTree::new(Arc::new(NodeManager::in_memory()));
+    assert_eq!(tree.empty_values(), EmptyValues::Allow);
+    tree.set_empty_values(empty_values);
+    tree
+}
+
+#[test]
+fn allows_empty_values() {
+    let mut tree = versioned(EmptyValues::Allow);
+    tree.insert(vec![1], vec![]).unwrap();
+    tree.save().unwrap();
+    assert_eq!(tree.get(&vec![1]).unwrap(), Some(vec![]));
+}
+
+#[test]
+fn rejects_empty_values() {
+    let mut tree = versioned(EmptyValues::Reject);
+    let error = tree.insert(vec![2], vec![]).unwrap_err();
+    assert!(matches!(error, Error::EmptyValue));
+
+    // A batch with an empty value is rejected as a whole.
+    let batch = vec![
+        BatchOp::Insert(vec![3], vec![3]),
+        BatchOp::Insert(vec![4], vec![]),
+    ];
+    assert!(matches!(tree.apply_batch(batch), Err(Error::EmptyValue)));
+    assert_eq!(tree.get(&vec![3]).unwrap(), None);
+
+    let changes = vec![Change::Create {
+        key: vec![5],
+        value: vec![],
+    }];
+    assert!(tree.apply_changeset(ChangesetId([1; 32]), changes).is_err());
+    assert!(tree.versions().is_empty());
+}
+
+#[test]
+fn deletes_on_empty_values() {
+    let mut tree = versioned(EmptyValues::Allow);
+    tree.insert(vec![1], vec![1]).unwrap();
+    tree.insert(vec![2], vec![2]).unwrap();
+    tree.save().unwrap();
+
+    tree.set_empty_values(EmptyValues::Delete);
+    tree.insert(vec![1], vec![]).unwrap();
+    assert_eq!(tree.get(&vec![1]).unwrap(), None);
+    tree.apply_batch(vec![
+        BatchOp::Insert(vec![2], vec![]),
+        BatchOp::Insert(vec![3], vec![]),
+    ])
+    .unwrap();
+    assert_eq!(tree.get(&vec![2]).unwrap(), None);
+    assert_eq!(tree.get(&vec![3]).unwrap(), None);
+    tree.save().unwrap();
+    assert!(tree.working().is_empty());
+}
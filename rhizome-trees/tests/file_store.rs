@@ -0,0 +1,102 @@
+//! Checks that the node log survives reopening, truncates a record torn by
+//! a crash and refuses to open over a damaged record.
+
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use rhizome_trees::tree::node_manager::{FileNodeStore, NodeStore, Ptr};
+use rhizome_trees::Error;
+
+fn log_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "rhizome-file-store-{}-{}",
+        name,
+        std::process::id()
+    ))
+}
+
+#[test]
+fn truncates_a_torn_tail() {
+    let path = log_path("torn");
+    let store = FileNodeStore::open(&path).unwrap();
+    let small = store.insert(&b"hello".to_vec()).unwrap();
+    let large = store.insert(&vec![7; 1000]).unwrap();
+    assert_eq!(store.inc_ref_count(&small).unwrap(), 2);
+    assert_eq!(store.dec_ref_count(&large).unwrap(), 0);
+    assert_eq!(store.read(&small).unwrap(), b"hello");
+    drop(store);
+
+    // A node record whose payload was cut short by a crash.
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[0, 10, 0, 0, 0, 1, 2]).unwrap();
+    drop(file);
+
+    let store = FileNodeStore::open(&path).unwrap();
+    assert_eq!(store.len(), 2);
+    assert_eq!(store.read(&large).unwrap(), vec![7; 1000]);
+    assert_eq!(store.inc_ref_count(&small).unwrap(), 3);
+    assert_eq!(store.inc_ref_count(&large).unwrap(), 1);
+    let appended = store.insert(&b"x".to_vec()).unwrap();
+    assert_eq!(store.read(&appended).unwrap(), b"x");
+    // Offsets inside a record aren't nodes.
+    assert!(store.read(&Ptr::from_u64(3)).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn refuses_a_damaged_record_before_the_end() {
+    let path = log_path("damaged");
+    let store = FileNodeStore::open(&path).unwrap();
+    let first = store.insert(&b"first".to_vec()).unwrap();
+    store.insert(&b"second".to_vec()).unwrap();
+    store.set_header(b"config").unwrap();
+    drop(store);
+    let len = fs::metadata(&path).unwrap().len();
+
+    // Flip a bit in the payload of the first record, after its 9 byte
+    // header.
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    file.seek(SeekFrom::Start(first.to_u64().unwrap() + 9))
+        .unwrap();
+    file.write_all(b"Girst").unwrap();
+    drop(file);
+
+    let err = FileNodeStore::open(&path).err().unwrap();
+    assert!(matches!(err, Error::Corruption(_)), "{}", err);
+    // The records after it are kept.
+    assert_eq!(fs::metadata(&path).unwrap().len(), len);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn truncates_a_damaged_last_record() {
+    let path = log_path("last");
+    let store = FileNodeStore::open(&path).unwrap();
+    let first = store.insert(&b"first".to_vec()).unwrap();
+    let last = store.insert(&b"last".to_vec()).unwrap();
+    drop(store);
+
+    // A crash may leave the last record with garbage instead of its payload.
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    file.seek(SeekFrom::Start(last.to_u64().unwrap() + 9))
+        .unwrap();
+    file.write_all(b"\0\0\0\0").unwrap();
+    drop(file);
+
+    let store = FileNodeStore::open(&path).unwrap();
+    assert_eq!(store.len(), 1);
+    assert_eq!(store.read(&first).unwrap(), b"first");
+    assert!(store.read(&last).is_err());
+    drop(store);
+    assert_eq!(fs::metadata(&path).unwrap().len(), last.to_u64().unwrap());
+    fs::remove_file(&path).unwrap();
+}
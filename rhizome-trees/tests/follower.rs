@@ -0,0 +1,143 @@
+//! Checks that a follower applying a primary's events ends up serving the
+//! primary's versions, and that it refuses nodes and commits it can't check.
+
+use std::sync::{Arc, Mutex};
+
+use rhizome_trees::tree::avl::follower::Follower;
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::versioned::{
+    PruningPolicy, Version, VersionEvent, VersionObserver, VersionedTree,
+};
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::{
+    ContentAddressedStore, MemNodeStore, NodeManager, NodeStore, StoreEvent, StoreObserver,
+};
+
+type Bytes = Vec<u8>;
+type N = Node<Bytes, Bytes>;
+
+enum Event {
+    Store(StoreEvent<N>),
+    Version(VersionEvent),
+}
+
+/// Queues the events of a primary for a follower.
+#[derive(Default)]
+struct Queue(Mutex<Vec<Event>>);
+
+impl Queue {
+    fn take(&self) -> Vec<Event> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+impl StoreObserver<N> for Queue {
+    fn on_store_event(&self, event: &StoreEvent<N>) {
+        self.0.lock().unwrap().push(Event::Store(event.clone()));
+    }
+}
+
+impl VersionObserver for Queue {
+    fn on_version_event(&self, event: &VersionEvent) {
+        self.0.lock().unwrap().push(Event::Version(event.clone()));
+    }
+}
+
+fn primary<S: NodeStore<N> + 'static>(store: S) -> (VersionedTree<Bytes, Bytes>, Arc<Queue>) {
+    let queue = Arc::new(Queue::default());
+    let manager = Arc::new(NodeManager::builder(store).observer(queue.clone()).build());
+    let mut tree = VersionedTree::new(manager);
+    tree.set_observer(queue.clone());
+    (tree, queue)
+}
+
+fn apply(follower: &Follower<Bytes, Bytes>, events: Vec<Event>) {
+    for event in events {
+        match event {
+            Event::Store(event) => follower.apply_store_event(&event).unwrap(),
+            Event::Version(event) => follower.apply_version_event(&event).unwrap(),
+        }
+    }
+}
+
+fn replicate<S: NodeStore<N> + 'static>(primary_store: S, follower_store: S) {
+    let (mut tree, queue) = primary(primary_store);
+    let follower = Follower::new(Arc::new(NodeManager::new(follower_store)));
+    for version in 0..10u8 {
+        for i in 0..20u8 {
+            tree.insert(vec![i], vec![version, i]).unwrap();
+        }
+        tree.delete(&vec![version]).unwrap();
+        tree.save().unwrap();
+        if version == 3 {
+            tree.insert(vec![1], vec![1]).unwrap();
+            tree.save().unwrap();
+            tree.rollback_to(Version::new(4)).unwrap();
+        }
+    }
+    tree.set_pruning(PruningPolicy::default().keep_recent(2));
+    tree.prune().unwrap();
+    // In-place updates of trees outside the history are replicated too.
+    let transient = tree
+        .working()
+        .insert(vec![99], vec![1])
+        .unwrap()
+        .save()
+        .unwrap();
+    let updated = transient.try_update(&vec![99], vec![2]).unwrap();
+    apply(&follower, queue.take());
+    let replicated = Tree::load(follower.manager().clone(), transient.root_ptr().unwrap());
+    let value = if updated { vec![2] } else { vec![1] };
+    assert_eq!(replicated.get(&vec![99]).unwrap(), Some(value));
+
+    assert_eq!(follower.versions().unwrap(), tree.versions().to_vec());
+    let latest = follower.latest().unwrap();
+    let hash = tree.working().merkle_hash().unwrap();
+    assert_eq!(latest.merkle_hash().unwrap(), hash);
+    assert_eq!(latest.verify().unwrap(), hash);
+    assert!(follower.load_version(Version::new(1)).is_err());
+}
+
+#[test]
+fn replicates_versions() {
+    replicate(ContentAddressedStore::new(), ContentAddressedStore::new());
+    replicate(MemNodeStore::new(), MemNodeStore::new());
+}
+
+#[test]
+fn refuses_unchecked_nodes_and_commits() {
+    let (mut tree, queue) = primary(ContentAddressedStore::new());
+    tree.insert(vec![1], vec![1]).unwrap();
+    tree.save().unwrap();
+    let follower = Follower::new(Arc::new(NodeManager::new(ContentAddressedStore::new())));
+
+    let events = queue.take();
+    let commit = events
+        .iter()
+        .find_map(|event| match event {
+            Event::Version(event) => Some(event.clone()),
+            Event::Store(_) => None,
+        })
+        .unwrap();
+    // The commit's root hasn't been replicated yet.
+    assert!(follower.apply_version_event(&commit).is_err());
+
+    // A node which never had its hash computed can't be checked.
+    let other = Tree::new().insert(vec![1], vec![2]).unwrap();
+    let unhashed = other.read(other.root().unwrap()).unwrap().clone_inner();
+    let ptr = match &events[0] {
+        Event::Store(StoreEvent::Inserted { ptr, .. }) => *ptr,
+        _ => panic!("the save didn't start by inserting its node"),
+    };
+    let forged = StoreEvent::Inserted {
+        ptr,
+        node: Arc::new(unhashed),
+    };
+    assert!(follower.apply_store_event(&forged).is_err());
+
+    apply(&follower, events);
+    assert_eq!(follower.latest_version().unwrap(), Version::INITIAL);
+    // The same version can't be committed twice.
+    assert!(follower.apply_version_event(&commit).is_err());
+}
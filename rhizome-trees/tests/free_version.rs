@@ -0,0 +1,83 @@
+//! Checks that freeing saved versions deletes exactly the nodes no other
+//! version shares, in stores which copy and which deduplicate nodes.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{ContentAddressedStore, MemNodeStore, NodeManager};
+use rhizome_trees::tree::value::U64BigEndian as U;
+
+/// Saves 20 versions, each overwriting 50 keys and some deleting one.
+fn versions(tree: Tree<U, U>) -> Vec<Tree<U, U>> {
+    let mut tree = tree;
+    let mut versions = Vec::new();
+    for version in 0..20u64 {
+        for i in 0..50u64 {
+            tree = tree
+                .insert(U((i * 7 + version * 13) % 300), U(version))
+                .unwrap();
+        }
+        if version % 3 == 0 {
+            tree = tree.delete(&U(version)).unwrap();
+        }
+        tree = tree.save().unwrap();
+        versions.push(tree.clone());
+    }
+    versions
+}
+
+#[test]
+fn keeps_the_nodes_of_remaining_versions() {
+    let store = Arc::new(MemNodeStore::new());
+    let manager = Arc::new(NodeManager::new(store.clone()));
+    let versions = versions(Tree::with_manager(manager.clone()));
+    let (last, older) = versions.split_last().unwrap();
+    let before = store.len();
+    let deleted: usize = older
+        .iter()
+        .map(|version| version.free_version().unwrap())
+        .sum();
+    assert_eq!(store.len(), before - deleted);
+
+    // Only the last version's nodes are left, one per entry, and it reads
+    // the same from the store.
+    let entries = (0..300u64)
+        .filter(|&i| last.get(&U(i)).unwrap().is_some())
+        .count();
+    assert_eq!(store.len(), entries);
+    let loaded = Tree::load(manager, last.root_ptr().unwrap());
+    for i in 0..300u64 {
+        assert_eq!(loaded.get(&U(i)).unwrap(), last.get(&U(i)).unwrap());
+    }
+
+    assert_eq!(last.free_version().unwrap(), entries);
+    assert!(store.is_empty());
+}
+
+#[test]
+fn keeps_deduplicated_nodes_until_the_last_reference() {
+    let store = Arc::new(ContentAddressedStore::new());
+    let manager = Arc::new(NodeManager::new(store.clone()));
+    let build = || {
+        Tree::with_manager(manager.clone())
+            .insert(U(1), U(1))
+            .unwrap()
+            .insert(U(2), U(2))
+            .unwrap()
+            .save()
+            .unwrap()
+    };
+    let (a, b) = (build(), build());
+    assert_eq!(store.len(), 2);
+    assert_eq!(a.free_version().unwrap(), 0);
+    assert_eq!(store.len(), 2);
+    assert_eq!(b.free_version().unwrap(), 2);
+    assert!(store.is_empty());
+}
+
+#[test]
+fn refuses_unsaved_versions() {
+    let tree = Tree::new().insert(U(1), U(1)).unwrap();
+    assert!(tree.free_version().is_err());
+    assert_eq!(Tree::<U, U>::new().free_version().unwrap(), 0);
+}
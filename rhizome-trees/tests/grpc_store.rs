@@ -0,0 +1,92 @@
+//! Checks that a remote node store forwards its calls to the store a gRPC
+//! server serves, and reports the served store's failures.
+#![cfg(feature = "grpc")]
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::node_manager::{
+    MemNodeStore, NodeStore, NodeStoreServer, RemoteNodeStore,
+};
+use rhizome_trees::Error;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+
+type Bytes = Vec<u8>;
+
+/// Serves `store` on a free local port, until the returned runtime is
+/// dropped.
+fn serve(store: Arc<MemNodeStore<Bytes>>) -> (Runtime, String) {
+    let runtime = Runtime::new().unwrap();
+    let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+    runtime.spawn(
+        Server::builder()
+            .add_service(NodeStoreServer::from_arc(store))
+            .serve_with_incoming(incoming),
+    );
+    (runtime, endpoint)
+}
+
+#[test]
+fn forwards_calls_and_failures() {
+    let local = Arc::new(MemNodeStore::new());
+    let (_server, endpoint) = serve(local.clone());
+    let remote = RemoteNodeStore::connect(endpoint).unwrap();
+
+    let ptr = remote.insert(&vec![1, 2, 3]).unwrap();
+    let other = remote.insert(&vec![4]).unwrap();
+    assert_eq!(local.read(&ptr).unwrap(), vec![1, 2, 3]);
+    assert_eq!(remote.read(&ptr).unwrap(), vec![1, 2, 3]);
+    assert_eq!(
+        remote.read_many(&[other, ptr]).unwrap(),
+        vec![vec![4], vec![1, 2, 3]]
+    );
+
+    assert_eq!(remote.inc_ref_count(&ptr).unwrap(), 2);
+    assert_eq!(remote.dec_ref_count(&ptr).unwrap(), 1);
+    assert_eq!(remote.dec_ref_count(&ptr).unwrap(), 0);
+    let err = remote.dec_ref_count(&ptr).unwrap_err();
+    assert!(matches!(err, Error::RefCountUnderflow(p) if p == ptr));
+
+    remote.delete(&ptr).unwrap();
+    assert!(local.read(&ptr).is_err());
+    let err = remote.read_many(&[other, ptr]).unwrap_err();
+    assert!(matches!(err, Error::NotFound(p) if p == ptr));
+
+    assert_eq!(remote.header().unwrap(), None);
+    remote.set_header(b"config").unwrap();
+    assert_eq!(local.header().unwrap(), Some(b"config".to_vec()));
+    assert_eq!(remote.header().unwrap(), Some(b"config".to_vec()));
+}
+
+#[cfg(feature = "borsh")]
+#[test]
+fn clients_share_saved_trees() {
+    use rhizome_trees::tree::avl::borsh::BorshCodec;
+    use rhizome_trees::tree::avl::Tree;
+    use rhizome_trees::tree::hash::MerkleTree;
+    use rhizome_trees::tree::node_manager::{EncodedStore, NodeManager};
+
+    let (_server, endpoint) = serve(Arc::new(MemNodeStore::new()));
+    let manager = |endpoint: &str| {
+        let remote = RemoteNodeStore::connect(endpoint).unwrap();
+        Arc::new(NodeManager::new(EncodedStore::new(
+            remote,
+            BorshCodec::new(),
+        )))
+    };
+
+    let writer = manager(&endpoint);
+    let tree = (0..20u8).fold(Tree::with_manager(writer), |tree, key| {
+        tree.insert(vec![key], vec![key; 2]).unwrap()
+    });
+    let saved = tree.save().unwrap();
+
+    let reader = Tree::<Bytes, Bytes>::load(manager(&endpoint), saved.root_ptr().unwrap());
+    assert_eq!(reader.merkle_hash().unwrap(), saved.merkle_hash().unwrap());
+    assert_eq!(reader.get(&vec![7]).unwrap(), Some(vec![7, 7]));
+    assert_eq!(reader.len().unwrap(), 20);
+}
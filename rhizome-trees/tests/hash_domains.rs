@@ -0,0 +1,102 @@
+//! Checks the node hash layouts of both hash versions, and that proofs,
+//! content addressing and snapshots follow the version of their manager.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::{entry_hash, node_hash, EntryHash, Node};
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::{hash_of, hash_parts, HashVersion, MerkleTree, EMPTY_HASH};
+use rhizome_trees::tree::node_manager::{ContentAddressedStore, MemNodeStore, NodeManager};
+
+type Bytes = Vec<u8>;
+
+fn value(i: u32) -> Bytes {
+    vec![i as u8; (i % 7) as usize]
+}
+
+fn filled(version: HashVersion) -> Tree<Bytes, Bytes> {
+    let manager = Arc::new(
+        NodeManager::builder(ContentAddressedStore::new())
+            .hash_version(version)
+            .build(),
+    );
+    (0..300u32).fold(Tree::with_manager(manager), |tree, i| {
+        tree.insert(i.to_be_bytes().to_vec(), value(i)).unwrap()
+    })
+}
+
+#[test]
+fn hashes_leaves_and_nodes_apart() {
+    let (key, value) = (b"k".to_vec(), b"v".to_vec());
+    let single = |version| {
+        let manager = Arc::new(
+            NodeManager::builder(MemNodeStore::new())
+                .hash_version(version)
+                .build(),
+        );
+        let tree = Tree::with_manager(manager);
+        tree.insert(key.clone(), value.clone())
+            .unwrap()
+            .merkle_hash()
+            .unwrap()
+    };
+
+    let legacy = hash_parts(&[&EMPTY_HASH, &hash_of(&key), &hash_of(&value), &EMPTY_HASH]);
+    assert_eq!(single(HashVersion::V0), legacy);
+
+    let leaf = hash_parts(&[&[0, 32], &hash_of(&key), &[32], &hash_of(&value)]);
+    assert_eq!(
+        entry_hash(HashVersion::V1, &key, &value),
+        EntryHash::Leaf(leaf)
+    );
+    let tagged = hash_parts(&[&[1], &EMPTY_HASH, &leaf, &EMPTY_HASH]);
+    assert_eq!(
+        node_hash(&EMPTY_HASH, &EntryHash::Leaf(leaf), &EMPTY_HASH),
+        tagged
+    );
+    assert_eq!(single(HashVersion::V1), tagged);
+    // New managers use the tagged layout.
+    let tree = Tree::new().insert(key.clone(), value.clone()).unwrap();
+    assert_eq!(tree.merkle_hash().unwrap(), tagged);
+}
+
+#[test]
+fn proves_and_addresses_with_either_version() {
+    for version in [HashVersion::V0, HashVersion::V1] {
+        let tree = filled(version);
+        let hash = tree.merkle_hash().unwrap();
+        let saved = tree.save().unwrap();
+        assert_eq!(saved.root_ptr().unwrap().as_bytes(), &hash);
+        assert_eq!(saved.verify().unwrap(), hash);
+        for i in [0u32, 5, 299] {
+            let key = i.to_be_bytes().to_vec();
+            let proof = saved.prove(&key).unwrap().unwrap();
+            assert!(proof.verify(&hash, &key, &value(i)));
+            assert!(!proof.verify(&hash, &key, &value(i + 1)));
+        }
+    }
+}
+
+#[test]
+fn snapshots_keep_their_version() {
+    for version in [HashVersion::V0, HashVersion::V1] {
+        let tree = filled(version);
+        let mut snapshot = Vec::new();
+        tree.export_snapshot(&mut snapshot).unwrap();
+
+        let manager = Arc::new(
+            NodeManager::builder(MemNodeStore::<Node<Bytes, Bytes>>::new())
+                .hash_version(version)
+                .build(),
+        );
+        let imported = Tree::import_snapshot(manager, &snapshot[..]).unwrap();
+        assert_eq!(imported.merkle_hash().unwrap(), tree.merkle_hash().unwrap());
+
+        // A default manager only imports snapshots of the tagged layout.
+        let manager = Arc::new(NodeManager::new(MemNodeStore::new()));
+        assert_eq!(
+            Tree::<Bytes, Bytes>::import_snapshot(manager, &snapshot[..]).is_ok(),
+            version == HashVersion::V1
+        );
+    }
+}
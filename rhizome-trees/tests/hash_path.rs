@@ -0,0 +1,62 @@
+//! Checks that the hash path of a key links each node's hash into its
+//! parent, up to the root hash of the tree.
+
+use rhizome_trees::tree::avl::proof::{PathNode, Side};
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::{MerkleTree, EMPTY_HASH};
+use rhizome_trees::tree::value::U64BigEndian as U;
+
+fn filled() -> Tree<U, U> {
+    (0..100u64).fold(Tree::new(), |tree, i| tree.insert(U(i * 2), U(i)).unwrap())
+}
+
+/// Checks that `path` runs from a node to the root of `tree`, each hash
+/// being the child hash its parent continues into.
+fn check_links(tree: &Tree<U, U>, path: &[PathNode]) {
+    let root = path.last().unwrap();
+    assert_eq!(root.hash, tree.merkle_hash().unwrap());
+    assert_eq!(root.depth, 0);
+    for pair in path.windows(2) {
+        let (child, parent) = (&pair[0], &pair[1]);
+        assert_eq!(child.depth, parent.depth + 1);
+        let expected = match parent.side {
+            Some(Side::Left) => parent.left,
+            Some(Side::Right) => parent.right,
+            None => panic!("the path continues past the key"),
+        };
+        assert_eq!(child.hash, expected);
+    }
+}
+
+#[test]
+fn leads_from_a_present_key_to_the_root() {
+    let tree = filled();
+    for key in [0, 74, 198] {
+        let path = tree.hash_path(&U(key)).unwrap();
+        assert!(path[0].side.is_none());
+        check_links(&tree, &path);
+        assert!(path[0].to_string().contains("(found)"), "{}", path[0]);
+    }
+}
+
+#[test]
+fn leads_from_where_an_absent_key_would_be() {
+    let tree = filled();
+    for key in [1, 75, 1000] {
+        let path = tree.hash_path(&U(key)).unwrap();
+        check_links(&tree, &path);
+        // The path ends at a node without a child on the key's side.
+        let last = &path[0];
+        let child = match last.side {
+            Some(Side::Left) => last.left,
+            Some(Side::Right) => last.right,
+            None => panic!("{} is absent", key),
+        };
+        assert_eq!(child, EMPTY_HASH);
+    }
+}
+
+#[test]
+fn is_empty_for_an_empty_tree() {
+    assert!(Tree::<U, U>::new().hash_path(&U(1)).unwrap().is_empty());
+}
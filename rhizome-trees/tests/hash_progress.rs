@@ -0,0 +1,90 @@
+//! Checks that hashing with progress reports every node hashed, can be
+//! cancelled, and resumes from the subtrees hashed before cancelling.
+
+use std::ops::ControlFlow;
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::{HashProgress, MerkleTree, EMPTY_HASH};
+use rhizome_trees::tree::node_manager::NodeManager;
+use rhizome_trees::tree::value::U64BigEndian as U;
+use rhizome_trees::Error;
+
+const ENTRIES: u64 = 5000;
+
+fn filled(tree: Tree<U, U>) -> Tree<U, U> {
+    (0..ENTRIES).fold(tree, |tree, i| tree.insert(U(i), U(i * 3)).unwrap())
+}
+
+#[test]
+fn reports_each_node_hashed() {
+    let tree = filled(Tree::new());
+    let mut reports = Vec::new();
+    let root = tree
+        .merkle_hash_with_progress(|progress| {
+            reports.push(progress);
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+    assert_eq!(root, filled(Tree::new()).merkle_hash().unwrap());
+    assert_eq!(reports.len() as u64, ENTRIES);
+    for (i, progress) in reports.iter().enumerate() {
+        assert_eq!(progress.hashed, i as u64 + 1);
+        assert!(progress.estimated_total >= ENTRIES / 2, "{:?}", progress);
+    }
+
+    // Nothing is left to hash the second time.
+    let mut calls = 0;
+    let again = tree.merkle_hash_with_progress(|_| {
+        calls += 1;
+        ControlFlow::Continue(())
+    });
+    assert_eq!(again.unwrap(), root);
+    assert_eq!(calls, 0);
+}
+
+#[test]
+fn resumes_after_cancelling() {
+    let tree = filled(Tree::new());
+    let mut last = None;
+    let cancelled = tree.merkle_hash_with_progress(|progress: HashProgress| {
+        last = Some(progress.hashed);
+        if progress.hashed == 2000 {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+    assert!(
+        matches!(cancelled, Err(Error::Cancelled(_))),
+        "{:?}",
+        cancelled
+    );
+    assert_eq!(last, Some(2000));
+
+    // The subtrees completed before cancelling keep their hashes.
+    let mut hashed = 0;
+    let root = tree
+        .merkle_hash_with_progress(|progress| {
+            hashed = progress.hashed;
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+    assert_eq!(hashed, ENTRIES - 2000);
+    assert_eq!(root, filled(Tree::new()).merkle_hash().unwrap());
+}
+
+#[test]
+fn hashes_stored_and_empty_trees() {
+    let expected = filled(Tree::new()).merkle_hash().unwrap();
+    let manager = Arc::new(NodeManager::in_memory());
+    let saved = filled(Tree::with_manager(manager.clone())).save().unwrap();
+    let loaded = Tree::<U, U>::load(manager, saved.root_ptr().unwrap());
+    let root = loaded
+        .merkle_hash_with_progress(|_| ControlFlow::Continue(()))
+        .unwrap();
+    assert_eq!(root, expected);
+
+    let empty = Tree::<U, U>::new().merkle_hash_with_progress(|_| ControlFlow::Break(()));
+    assert_eq!(empty.unwrap(), EMPTY_HASH);
+}
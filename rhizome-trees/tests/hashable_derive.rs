@@ -0,0 +1,91 @@
+//! Checks the encoding written by `#[derive(Hashable)]`: every field in order
+//! and prefixed with its length, after the variant index for enums.
+
+#![cfg(feature = "derive")]
+
+use rhizome_trees::tree::hash::{hash_of, Hashable, Update};
+use rhizome_trees::tree::value::{BytesValue, U64BigEndian};
+
+#[derive(Hashable)]
+struct Named {
+    a: Vec<u8>,
+    b: Vec<u8>,
+}
+
+#[derive(Hashable)]
+struct Tuple(Vec<u8>, U64BigEndian);
+
+#[derive(Hashable)]
+struct Unit;
+
+#[derive(Hashable)]
+struct Generic<T> {
+    t: T,
+    v: BytesValue,
+}
+
+#[derive(Hashable)]
+enum Enum {
+    A,
+    B(Vec<u8>),
+    C { x: Vec<u8>, y: Vec<u8> },
+}
+
+/// Collects the bytes written to a hasher.
+struct Written(Vec<u8>);
+
+impl Update for Written {
+    fn update(&mut self, data: &[u8]) {
+        self.0.extend_from_slice(data)
+    }
+}
+
+fn written<T: Hashable>(value: &T) -> Vec<u8> {
+    let mut written = Written(Vec::new());
+    value.update_hash(&mut written);
+    written.0
+}
+
+#[test]
+fn prefixes_struct_fields() {
+    assert_eq!(
+        written(&Named {
+            a: vec![1],
+            b: vec![],
+        }),
+        [0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0]
+    );
+    // Moving bytes between fields changes the hash.
+    assert_ne!(
+        hash_of(&Named {
+            a: b"ab".to_vec(),
+            b: b"c".to_vec(),
+        }),
+        hash_of(&Named {
+            a: b"a".to_vec(),
+            b: b"bc".to_vec(),
+        })
+    );
+    assert_eq!(written(&Tuple(vec![], U64BigEndian(2))).len(), 8 + 8 + 8);
+    assert!(written(&Unit).is_empty());
+    let generic = Generic {
+        t: vec![9u8],
+        v: BytesValue(vec![]),
+    };
+    assert_eq!(written(&generic).len(), 8 + 1 + 8);
+}
+
+#[test]
+fn prefixes_enum_variants() {
+    assert_eq!(written(&Enum::A), [0, 0, 0, 0]);
+    assert_eq!(
+        written(&Enum::B(vec![5])),
+        [0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 5]
+    );
+    let c = Enum::C {
+        x: vec![],
+        y: vec![],
+    };
+    assert_eq!(written(&c)[..4], [0, 0, 0, 2]);
+    assert_eq!(written(&c).len(), 4 + 16);
+}
@@ -0,0 +1,75 @@
+//! Checks that trees ordered by key hashes look keys up by their full key,
+//! refuse colliding keys and prove entries along with their full key.
+
+use rhizome_trees::tree::avl::hashed::{HashedKeyTree, KeyHash, KeyedValue};
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+
+type Bytes = Vec<u8>;
+
+fn entry(key: &[u8], value: &[u8]) -> KeyedValue<Bytes, Bytes> {
+    KeyedValue {
+        key: key.to_vec(),
+        value: value.to_vec(),
+    }
+}
+
+#[test]
+fn stores_long_keys() {
+    let long = vec![9; 4096];
+    let tree = HashedKeyTree::new()
+        .insert(long.clone(), vec![1])
+        .unwrap()
+        .insert(vec![2], vec![2])
+        .unwrap()
+        .save()
+        .unwrap();
+    assert_eq!(tree.get(&long[..]).unwrap(), Some(vec![1]));
+    assert_eq!(tree.get(&[2][..]).unwrap(), Some(vec![2]));
+    assert_eq!(tree.get(&[3][..]).unwrap(), None);
+    assert_eq!(tree.len().unwrap(), 2);
+    // Nodes hold the fixed-size hash as their key.
+    let root = tree.tree().root().unwrap();
+    assert_eq!(tree.tree().read(root).unwrap().key().0.len(), 32);
+
+    let hash = tree.merkle_hash().unwrap();
+    assert_eq!(tree.delete(&[3][..]).unwrap().merkle_hash().unwrap(), hash);
+    let deleted = tree.delete(&long[..]).unwrap();
+    assert_eq!(deleted.len().unwrap(), 1);
+    assert!(deleted.prove(&long[..]).unwrap().is_none());
+}
+
+#[test]
+fn proves_full_keys() {
+    let long = vec![9; 4096];
+    let tree = HashedKeyTree::new()
+        .insert(long.clone(), vec![1])
+        .unwrap()
+        .insert(vec![2], vec![2])
+        .unwrap();
+    let root = tree.merkle_hash().unwrap();
+    let proof = tree.prove(&long[..]).unwrap().unwrap();
+    let hash = KeyHash::of(&long);
+    assert!(proof.verify(&root, &hash, &entry(&long, &[1])));
+    assert!(!proof.verify(&root, &hash, &entry(&[8], &[1])));
+    assert!(!proof.verify(&root, &hash, &entry(&long, &[2])));
+}
+
+#[test]
+fn refuses_colliding_keys() {
+    // Store `b` under the hash of `a`, as a collision would.
+    let (a, b) = (b"a".to_vec(), b"b".to_vec());
+    let colliding = Tree::new()
+        .insert(KeyHash::of(&a), entry(&b, &[1]))
+        .unwrap();
+    let tree = HashedKeyTree::from_tree(colliding);
+    assert!(tree.get(&a[..]).is_err());
+    assert!(tree.insert(a.clone(), vec![2]).is_err());
+    // Deleting `a` leaves `b` alone.
+    let deleted = tree.delete(&a[..]).unwrap();
+    assert!(deleted
+        .tree()
+        .root()
+        .unwrap()
+        .same_node(tree.tree().root().unwrap()));
+}
@@ -0,0 +1,82 @@
+//! Checks that store health checks clean up their probe, and that verifying
+//! recent versions catches a stored node which doesn't match its version.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::versioned::VersionedTree;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::{
+    CachePolicy, ContentAddressedStore, MemNodeStore, NodeManager, NodeStore,
+};
+
+type Bytes = Vec<u8>;
+
+#[test]
+fn removes_the_probe() {
+    let store = MemNodeStore::<Bytes>::new();
+    let report = store.health_check(&vec![1, 2]).unwrap();
+    assert!(report.total() >= report.read);
+    assert!(store.is_empty());
+
+    // A content-addressed store keeps a stored node equal to the probe.
+    let store = ContentAddressedStore::<Node<Bytes, Bytes>>::new();
+    let manager = Arc::new(NodeManager::new(Arc::new(store)));
+    let mut tree = VersionedTree::new(manager.clone());
+    tree.insert(vec![1], vec![1]).unwrap();
+    tree.save().unwrap();
+    let root = tree.working().root().unwrap().clone();
+    let node = tree.working().read(&root).unwrap().clone_inner();
+    manager.store().health_check(&node).unwrap();
+    tree.verify_recent(1).unwrap();
+}
+
+#[test]
+fn verifies_recent_versions() {
+    let manager = Arc::new(NodeManager::in_memory());
+    let mut tree = VersionedTree::new(manager);
+    for i in 0..50u8 {
+        tree.insert(vec![i], vec![i]).unwrap();
+        if i % 10 == 0 {
+            tree.save().unwrap();
+        }
+    }
+    tree.save().unwrap();
+    tree.verify_recent(3).unwrap();
+    // Asking for more versions than there are verifies them all.
+    tree.verify_recent(10).unwrap();
+    assert_eq!(
+        tree.working().verify().unwrap(),
+        tree.working().merkle_hash().unwrap()
+    );
+}
+
+#[test]
+fn detects_a_replaced_root() {
+    let manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .cache_policy(CachePolicy::Disabled)
+            .build(),
+    );
+    let saved = |value: u8| {
+        let mut tree = VersionedTree::new(manager.clone());
+        for i in 0..5u8 {
+            tree.insert(vec![i], vec![i + value]).unwrap();
+        }
+        tree.save().unwrap();
+        tree.verify_recent(1).unwrap();
+        tree
+    };
+    let (original, other) = (saved(0), saved(1));
+
+    // Overwrite the original root with the other one's.
+    let original_root = original.working().root_ptr().unwrap();
+    let other_root = other.working().root_ptr().unwrap();
+    let node = manager.store().read(&other_root).unwrap();
+    assert!(manager
+        .store()
+        .try_update(&[(original_root, node)])
+        .unwrap());
+    assert!(original.verify_recent(1).is_err());
+    other.verify_recent(1).unwrap();
+}
@@ -0,0 +1,85 @@
+//! Checks that proofs round trip through the ICS-23 wire format, and that
+//! their ops compute the root hash the way an ICS-23 verifier would.
+#![cfg(feature = "ics23")]
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::proof::Proof;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::{encoding, hash_parts, HashVersion, MerkleTree, EMPTY_HASH};
+use rhizome_trees::tree::ics23::{proof_spec, HashOp, LengthOp};
+use rhizome_trees::tree::node_manager::{NodeManager, NullNodeStore};
+
+type Bytes = Vec<u8>;
+
+fn value(i: u32) -> Bytes {
+    vec![i as u8; (i % 5) as usize]
+}
+
+fn filled() -> Tree<Bytes, Bytes> {
+    (0..100u32).fold(Tree::new(), |tree, i| {
+        tree.insert(i.to_be_bytes().to_vec(), value(i)).unwrap()
+    })
+}
+
+/// Computes the root an ICS-23 verifier derives from an existence proof in
+/// the layout of [`proof_spec`].
+fn calculate_root(proof: &Proof, key: &[u8], value: &[u8]) -> [u8; 32] {
+    let spec = proof_spec();
+    let key = hash_parts(&[key]);
+    let value = hash_parts(&[value]);
+    let mut hash = hash_parts(&[&spec.leaf_spec.prefix, &[32], &key, &[32], &value]);
+    for op in proof.inner_ops().unwrap() {
+        hash = hash_parts(&[&op.prefix, &hash, &op.suffix]);
+    }
+    hash
+}
+
+#[test]
+fn round_trips_proofs() {
+    let tree = filled();
+    let root = tree.merkle_hash().unwrap();
+    for i in [0u32, 1, 37, 63, 99] {
+        let key = i.to_be_bytes().to_vec();
+        let proof = tree.prove(&key).unwrap().unwrap();
+        let bytes = proof.encode(&key, &value(i)).unwrap();
+        let (decoded, decoded_key, decoded_value) = Proof::decode(&bytes).unwrap();
+        assert_eq!(decoded, proof);
+        assert_eq!(decoded_key, encoding(&key));
+        assert_eq!(decoded_value, encoding(&value(i)));
+        assert_eq!(calculate_root(&decoded, &decoded_key, &decoded_value), root);
+        assert_ne!(calculate_root(&decoded, &decoded_key, b"other"), root);
+    }
+    assert!(Proof::decode(&[0xff, 0x01]).is_err());
+    assert!(Proof::decode(&[]).is_err());
+}
+
+#[test]
+fn specifies_the_tagged_layout() {
+    let spec = proof_spec();
+    assert_eq!(spec.leaf_spec.prefix, vec![0]);
+    assert_eq!(spec.leaf_spec.length, LengthOp::VarProto);
+    assert_eq!(spec.inner_spec.child_order, vec![0, 1, 2]);
+    assert_eq!(spec.inner_spec.child_size, 32);
+    assert_eq!(spec.inner_spec.empty_child, EMPTY_HASH.to_vec());
+    assert_eq!(spec.inner_spec.hash, HashOp::Sha256);
+    // The leaf spec comes first, as field 1 of the message.
+    let encoded = spec.encode();
+    assert_eq!(encoded[0], 0x0a);
+    assert_eq!(encoded, proof_spec().encode());
+}
+
+#[test]
+fn refuses_untagged_hashes() {
+    let manager = Arc::new(
+        NodeManager::builder(NullNodeStore)
+            .hash_version(HashVersion::V0)
+            .build(),
+    );
+    let tree = Tree::with_manager(manager)
+        .insert(vec![1], vec![2])
+        .unwrap();
+    let proof = tree.prove(&vec![1u8]).unwrap().unwrap();
+    assert!(proof.inner_ops().is_err());
+    assert!(proof.encode(&vec![1u8], &vec![2u8]).is_err());
+}
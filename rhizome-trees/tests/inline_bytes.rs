@@ -0,0 +1,49 @@
+//! Checks that inline byte strings stand in for `Vec<u8>` keys and values:
+//! same ordering, same hashes and same lookups, whether inline or not.
+#![cfg(feature = "inline")]
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::{hash_of, MerkleTree};
+use rhizome_trees::tree::inline::{InlineKey, InlineValue};
+
+#[test]
+fn inlines_short_contents() {
+    assert!(InlineKey::from(&b"abc"[..]).is_inline());
+    assert!(InlineKey::from(vec![1; 16]).is_inline());
+    assert!(!InlineKey::from(vec![1; 17]).is_inline());
+    assert!(InlineValue::from(vec![1; 32]).is_inline());
+    assert!(!InlineValue::from(vec![1; 33]).is_inline());
+    assert!(InlineKey::default().as_bytes().is_empty());
+
+    // Where the bytes are kept doesn't change their order or hash.
+    let long = vec![7; 20];
+    assert_eq!(InlineKey::from(long.clone()).as_bytes(), &long[..]);
+    assert!(InlineKey::from(&b"b"[..]) > InlineKey::from(long.clone()));
+    assert_eq!(hash_of(&InlineKey::from(long.clone())), hash_of(&long));
+    assert_eq!(
+        hash_of(&InlineValue::from(&b"ab"[..])),
+        hash_of(&b"ab".to_vec())
+    );
+}
+
+#[test]
+fn matches_a_tree_of_vectors() {
+    let mut vectors = Tree::<Vec<u8>, Vec<u8>>::new();
+    let mut inline = Tree::<InlineKey, InlineValue>::new();
+    for i in 0..500u32 {
+        let key = format!("key{}", i).into_bytes();
+        let value = vec![i as u8; (i % 50) as usize];
+        vectors = vectors.insert(key.clone(), value.clone()).unwrap();
+        inline = inline.insert(key.into(), value.into()).unwrap();
+    }
+    assert_eq!(
+        vectors.merkle_hash().unwrap(),
+        inline.merkle_hash().unwrap()
+    );
+    let value = inline.get(&b"key7"[..]).unwrap().unwrap();
+    assert_eq!(value.as_bytes(), &[7; 7]);
+    let value = inline.get(&b"key49"[..]).unwrap().unwrap();
+    assert!(!value.is_inline());
+    assert_eq!(value.as_bytes(), &[49; 49][..]);
+    assert_eq!(inline.get(&b"key500"[..]).unwrap(), None);
+}
@@ -0,0 +1,89 @@
+//! Checks stabbing and overlap queries of the interval tree against a list
+//! of intervals, along with the subtree maxima the queries prune by.
+
+mod rng;
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::interval::{Interval, IntervalTree, Node};
+use rhizome_trees::tree::value::U64BigEndian as U;
+use rng::Rng;
+
+/// Checks that each node records the largest end below it, returning it.
+fn check_max_end(node: Option<&Arc<Node<U, U>>>) -> Option<u64> {
+    let node = node?;
+    let max = [
+        Some(node.interval().end.0),
+        check_max_end(node.left()),
+        check_max_end(node.right()),
+    ]
+    .into_iter()
+    .flatten()
+    .max();
+    assert_eq!(Some(node.max_end().0), max);
+    max
+}
+
+fn bounds<'a>(found: impl IntoIterator<Item = (&'a Interval<U>, &'a U)>) -> Vec<(u64, u64)> {
+    found
+        .into_iter()
+        .map(|(interval, _)| (interval.start.0, interval.end.0))
+        .collect()
+}
+
+#[test]
+fn matches_a_list_of_intervals() {
+    let mut rng = Rng(7);
+    let mut tree = IntervalTree::new();
+    let mut intervals: Vec<(u64, u64)> = Vec::new();
+    for i in 0..3000 {
+        if i % 4 == 3 && !intervals.is_empty() {
+            let (start, end) = intervals.swap_remove(rng.next() as usize % intervals.len());
+            tree = tree.remove(&Interval::new(U(start), U(end)).unwrap());
+        } else {
+            let start = rng.next() % 1000;
+            let end = start + rng.next() % 50;
+            tree = tree.insert(Interval::new(U(start), U(end)).unwrap(), U(i));
+            if !intervals.contains(&(start, end)) {
+                intervals.push((start, end));
+            }
+        }
+        if i % 100 != 0 {
+            continue;
+        }
+        check_max_end(tree.root());
+        for point in [0, 5, 500, 999, 1040] {
+            let mut expected: Vec<_> = intervals
+                .iter()
+                .copied()
+                .filter(|&(start, end)| start <= point && point <= end)
+                .collect();
+            expected.sort();
+            assert_eq!(bounds(tree.intervals_containing(&U(point))), expected);
+        }
+        let query = Interval::new(U(300), U(320)).unwrap();
+        let mut expected: Vec<_> = intervals
+            .iter()
+            .copied()
+            .filter(|&(start, end)| start <= 320 && 300 <= end)
+            .collect();
+        expected.sort();
+        assert_eq!(bounds(tree.overlapping(&query)), expected);
+    }
+}
+
+#[test]
+fn maps_intervals_to_values() {
+    let interval = Interval::new(U(1), U(5)).unwrap();
+    let tree = IntervalTree::new().insert(interval, U(1));
+    assert_eq!(tree.get(&interval), Some(&U(1)));
+    let replaced = tree.insert(interval, U(2));
+    assert_eq!(replaced.get(&interval), Some(&U(2)));
+    assert_ne!(replaced.merkle_hash().unwrap(), tree.merkle_hash().unwrap());
+    assert!(replaced.remove(&interval).is_empty());
+    // Intervals are closed, and must not end before they start.
+    assert!(interval.contains(&U(5)));
+    assert!(interval.overlaps(&Interval::new(U(5), U(9)).unwrap()));
+    assert!(Interval::new(U(3), U(2)).is_err());
+}
@@ -0,0 +1,95 @@
+//! Checks reading keys as of past versions, and that pruning trims each key's
+//! history according to its own retention.
+
+use rhizome_trees::tree::avl::history::{KeyHistory, Retention};
+use rhizome_trees::tree::avl::versioned::Version;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+
+type Bytes = Vec<u8>;
+
+/// Changes the keys `hot`, `reg` and `since` at each of versions 1 to 10,
+/// then deletes `hot` at version 11.
+fn recorded() -> KeyHistory<Bytes, Bytes> {
+    let mut history = KeyHistory::new(Tree::new());
+    history.set_default_retention(Retention::KeepLast(2));
+    history.set_retention(b"reg".to_vec(), Retention::KeepAll);
+    history.set_retention(b"since".to_vec(), Retention::KeepSince(Version::new(5)));
+    for version in 1..=10u64 {
+        for key in [&b"hot"[..], b"reg", b"since"] {
+            history
+                .insert(Version::new(version), key.to_vec(), vec![version as u8])
+                .unwrap();
+        }
+    }
+    history.delete(Version::new(11), b"hot".to_vec()).unwrap();
+    history
+}
+
+#[test]
+fn reads_past_versions() {
+    let mut history = recorded();
+    assert_eq!(
+        history.get_at(&b"hot"[..], Version::new(3)).unwrap(),
+        Some(vec![3])
+    );
+    assert_eq!(
+        history.get_at(&b"hot"[..], Version::new(10)).unwrap(),
+        Some(vec![10])
+    );
+    assert_eq!(history.get(&b"hot"[..]).unwrap(), None);
+    assert_eq!(history.get(&b"reg"[..]).unwrap(), Some(vec![10]));
+    assert_eq!(
+        history.get_at(&b"absent"[..], Version::new(4)).unwrap(),
+        None
+    );
+    // Changes must come in version order.
+    assert!(history
+        .insert(Version::new(11), b"hot".to_vec(), vec![])
+        .is_err());
+    assert!(history
+        .insert(Version::new(2), b"reg".to_vec(), vec![])
+        .is_err());
+}
+
+#[test]
+fn prunes_by_retention() {
+    let mut history = recorded();
+    assert_eq!(history.retention(&b"hot"[..]), Retention::KeepLast(2));
+    let before = history.tree().merkle_hash().unwrap();
+    // `hot` keeps its last 2 of 11 changes and `since` the 6 from version 5.
+    assert_eq!(history.prune().unwrap(), 9 + 4);
+    assert_ne!(history.tree().merkle_hash().unwrap(), before);
+
+    assert!(history.get_at(&b"hot"[..], Version::new(3)).is_err());
+    assert_eq!(
+        history.get_at(&b"hot"[..], Version::new(10)).unwrap(),
+        Some(vec![10])
+    );
+    assert_eq!(
+        history.get_at(&b"reg"[..], Version::new(1)).unwrap(),
+        Some(vec![1])
+    );
+    assert_eq!(
+        history.get_at(&b"since"[..], Version::new(5)).unwrap(),
+        Some(vec![5])
+    );
+    assert!(history.get_at(&b"since"[..], Version::new(4)).is_err());
+    let since = history.history(&b"since"[..]).unwrap().unwrap();
+    assert_eq!(since.since(), Version::new(5));
+    assert_eq!(since.changes().len(), 6);
+
+    // Pruning again finds nothing to trim.
+    assert_eq!(history.prune().unwrap(), 0);
+}
+
+#[test]
+fn falls_back_to_the_default_retention() {
+    let mut history = recorded();
+    history.clear_retention(&b"reg"[..]);
+    assert_eq!(history.retention(&b"reg"[..]), Retention::KeepLast(2));
+    history.prune().unwrap();
+    let reg = history.history(&b"reg"[..]).unwrap().unwrap();
+    assert_eq!(reg.changes().len(), 2);
+    assert!(history.get_at(&b"reg"[..], Version::new(8)).is_err());
+}
@@ -0,0 +1,214 @@
+//! Checks that replicated lists keep their elements where they were
+//! inserted, converge however their states are merged, and survive a save
+//! and load.
+
+use std::sync::Arc;
+
+use rhizome_trees::crdt::lseq::{LSeqManager, Position};
+use rhizome_trees::crdt::LSeq;
+use rhizome_trees::tree::node_manager::NodeManager;
+use rhizome_trees::tree::value::ValueCodec;
+
+type Bytes = Vec<u8>;
+
+fn replicas(count: u64) -> (Arc<LSeqManager<Bytes>>, Vec<LSeq<Bytes>>) {
+    let manager = Arc::new(NodeManager::in_memory());
+    let lists = (1..=count)
+        .map(|replica| LSeq::new(manager.clone(), replica))
+        .collect();
+    (manager, lists)
+}
+
+fn text(list: &LSeq<Bytes>) -> String {
+    list.values()
+        .unwrap()
+        .into_iter()
+        .map(|value| String::from_utf8(value).unwrap())
+        .collect()
+}
+
+fn pair(lists: &mut [LSeq<Bytes>]) -> (&mut LSeq<Bytes>, &mut LSeq<Bytes>) {
+    let (a, b) = lists.split_at_mut(1);
+    (&mut a[0], &mut b[0])
+}
+
+#[test]
+fn inserts_land_at_their_index() {
+    let (_, mut lists) = replicas(1);
+    let list = &mut lists[0];
+    list.push(b"b".to_vec()).unwrap();
+    list.insert(0, b"a".to_vec()).unwrap();
+    list.push(b"d".to_vec()).unwrap();
+    list.insert(2, b"c".to_vec()).unwrap();
+    assert_eq!(text(list), "abcd");
+    assert_eq!(list.len().unwrap(), 4);
+    assert_eq!(list.get(2).unwrap(), Some(b"c".to_vec()));
+    assert_eq!(list.get(4).unwrap(), None);
+    assert!(list.insert(5, b"x".to_vec()).is_err());
+
+    assert_eq!(list.delete(1).unwrap(), Some(b"b".to_vec()));
+    assert_eq!(list.delete(3).unwrap(), None);
+    assert_eq!(text(list), "acd");
+}
+
+#[test]
+fn positions_are_stable_ids() {
+    let (_, mut lists) = replicas(1);
+    let list = &mut lists[0];
+    let c = list.push(b"c".to_vec()).unwrap();
+    list.insert(0, b"a".to_vec()).unwrap();
+    let b = list.insert(1, b"b".to_vec()).unwrap();
+    assert_eq!(list.index_of(&c).unwrap(), Some(2));
+    assert_eq!(list.position(1).unwrap(), Some(b.clone()));
+    assert_ne!(b.tag(), c.tag());
+
+    assert!(list.remove(&b).unwrap());
+    assert!(!list.remove(&b).unwrap());
+    assert_eq!(list.index_of(&b).unwrap(), None);
+    assert_eq!(list.index_of(&c).unwrap(), Some(1));
+}
+
+/// Checks edits against a `Vec`, with inserts crowding into a few gaps so
+/// that positions have to grow.
+#[test]
+fn edits_match_a_vec() {
+    let (_, mut lists) = replicas(1);
+    let list = &mut lists[0];
+    let mut model: Vec<Bytes> = Vec::new();
+    let mut seed = 7u64;
+    for i in 0..2000u32 {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let len = model.len() as u64;
+        let index = match seed >> 62 {
+            0 => 1.min(len),
+            1 => len,
+            2 => 0,
+            _ => (seed >> 20) % (len + 1),
+        };
+        if seed.is_multiple_of(5) && len > 0 {
+            let index = index.min(len - 1);
+            let removed = model.remove(index as usize);
+            assert_eq!(list.delete(index).unwrap(), Some(removed));
+        } else {
+            let value = i.to_be_bytes().to_vec();
+            model.insert(index as usize, value.clone());
+            list.insert(index, value).unwrap();
+        }
+    }
+    assert_eq!(list.values().unwrap(), model);
+    let positions: Vec<Position> = list
+        .entries()
+        .unwrap()
+        .into_iter()
+        .map(|(position, _)| position)
+        .collect();
+    assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn appends_keep_positions_short() {
+    let (_, mut lists) = replicas(1);
+    let list = &mut lists[0];
+    for i in 0..1000u32 {
+        let appended = list.push(i.to_be_bytes().to_vec()).unwrap();
+        let prepended = list.insert(0, i.to_be_bytes().to_vec()).unwrap();
+        assert_eq!(appended.atoms().len(), 1);
+        assert_eq!(prepended.atoms().len(), 1);
+    }
+}
+
+#[test]
+fn concurrent_edits_converge() {
+    let (_, mut lists) = replicas(3);
+    let (a, rest) = lists.split_first_mut().unwrap();
+    for value in ["a", "c"] {
+        a.push(value.as_bytes().to_vec()).unwrap();
+    }
+    for list in rest.iter_mut() {
+        list.merge(a).unwrap();
+    }
+
+    // Each replica inserts between a and c, and b deletes c as well.
+    a.insert(1, b"1".to_vec()).unwrap();
+    let (b, c) = pair(rest);
+    b.insert(1, b"2".to_vec()).unwrap();
+    b.delete(2).unwrap();
+    c.insert(1, b"3".to_vec()).unwrap();
+    c.push(b"d".to_vec()).unwrap();
+
+    // Merge in different orders, twice over to check idempotence.
+    for _ in 0..2 {
+        a.merge(b).unwrap();
+        a.merge(c).unwrap();
+        c.merge(b).unwrap();
+        c.merge(a).unwrap();
+        b.merge(c).unwrap();
+    }
+    assert_eq!(text(a), "a123d");
+    assert_eq!(text(b), text(a));
+    assert_eq!(text(c), text(a));
+}
+
+#[test]
+fn deleted_elements_stay_deleted() {
+    let (_, mut lists) = replicas(2);
+    let (a, b) = pair(&mut lists);
+    a.push(b"x".to_vec()).unwrap();
+    b.merge(a).unwrap();
+    b.delete(0).unwrap();
+
+    // a hasn't seen the delete and merges b's state: x is gone.
+    a.merge(b).unwrap();
+    assert!(a.is_empty());
+    // b merges a's state from before its delete: x stays gone.
+    b.merge(a).unwrap();
+    assert!(b.is_empty());
+    assert_eq!(a.removed().len().unwrap(), 1);
+}
+
+#[test]
+fn saved_lists_load() {
+    let (manager, mut lists) = replicas(1);
+    let list = &mut lists[0];
+    for value in ["a", "b", "c"] {
+        list.push(value.as_bytes().to_vec()).unwrap();
+    }
+    list.delete(1).unwrap();
+    let saved = list.save().unwrap();
+
+    let mut loaded = LSeq::load(manager.clone(), &saved);
+    assert_eq!(text(&loaded), "ac");
+    assert_eq!(loaded.removed().len().unwrap(), 1);
+    // The loaded replica never reuses the tags the saved one created.
+    let position = loaded.insert(1, b"b".to_vec()).unwrap();
+    assert_eq!(position.tag().counter, 3);
+
+    drop((lists, loaded));
+    assert!(LSeq::release(&manager, &saved).unwrap() > 0);
+}
+
+#[test]
+fn position_encodings_sort_as_positions() {
+    let (_, mut lists) = replicas(1);
+    let list = &mut lists[0];
+    for i in 0..50u32 {
+        list.insert((i as u64).min(1), i.to_be_bytes().to_vec())
+            .unwrap();
+    }
+    let positions: Vec<Position> = list
+        .entries()
+        .unwrap()
+        .into_iter()
+        .map(|(position, _)| position)
+        .collect();
+    assert!(positions.iter().any(|position| position.atoms().len() > 1));
+    let encoded: Vec<Bytes> = positions.iter().map(|p| p.to_encoded()).collect();
+    assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+    for (position, encoded) in positions.iter().zip(&encoded) {
+        assert_eq!(&Position::decode(encoded).unwrap(), position);
+    }
+    assert!(Position::decode(&[]).is_err());
+    assert!(Position::decode(&[0; 21]).is_err());
+}
@@ -0,0 +1,37 @@
+//! Checks that code generic over `Map` reads AVL and ART trees alike.
+
+use rhizome_trees::tree::map::Map;
+use rhizome_trees::tree::{art, avl};
+
+/// Counts how many of `keys` the map contains.
+fn count<M: Map + ?Sized>(map: &M, keys: &[&M::Key]) -> usize {
+    keys.iter()
+        .filter(|key| map.contains_key(key).unwrap())
+        .count()
+}
+
+#[test]
+fn reads_art_trees() {
+    let tree = art::Tree::new()
+        .insert(b"ab".to_vec(), 1)
+        .insert(b"a".to_vec(), 2)
+        .insert(b"b".to_vec(), 3);
+    assert_eq!(Map::len(&tree).unwrap(), 3);
+    assert_eq!(Map::get(&tree, &b"a"[..]).unwrap(), Some(2));
+    assert_eq!(Map::get(&tree, &b"c"[..]).unwrap(), None);
+    assert_eq!(count(&tree, &[&b"a"[..], &b"c"[..], &b"ab"[..]]), 2);
+    assert!(Map::is_empty(&art::Tree::<u32>::new()));
+}
+
+#[test]
+fn reads_avl_trees() {
+    let tree = avl::Tree::new()
+        .insert(vec![1], vec![1])
+        .unwrap()
+        .insert(vec![2], vec![2])
+        .unwrap();
+    assert_eq!(Map::len(&tree).unwrap(), 2);
+    assert_eq!(Map::get(&tree, &vec![2]).unwrap(), Some(vec![2]));
+    assert_eq!(count(&tree, &[&vec![1], &vec![3]]), 1);
+    assert!(!Map::is_empty(&tree));
+}
@@ -0,0 +1,138 @@
+//! Checks that a node manager reports its reads, cache lookups and writes
+//! to its metrics hooks.
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rhizome_trees::tree::avl::node::Manager;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{
+    CachePolicy, MemNodeStore, NodeManager, NodeManagerMetrics,
+};
+
+type Bytes = Vec<u8>;
+
+#[derive(Default)]
+struct Recorder {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    reads: AtomicU64,
+    nodes_read: AtomicU64,
+    nodes_written: AtomicU64,
+    bytes_written: AtomicU64,
+    increments: AtomicU64,
+    decrements: AtomicU64,
+    writes: AtomicU64,
+}
+
+impl NodeManagerMetrics for Recorder {
+    fn cache_hit(&self) {
+        self.hits.fetch_add(1, Relaxed);
+    }
+
+    fn cache_miss(&self) {
+        self.misses.fetch_add(1, Relaxed);
+    }
+
+    fn store_read(&self, nodes: usize, _: Duration) {
+        self.reads.fetch_add(1, Relaxed);
+        self.nodes_read.fetch_add(nodes as u64, Relaxed);
+    }
+
+    fn nodes_written(&self, nodes: usize, bytes: u64) {
+        self.nodes_written.fetch_add(nodes as u64, Relaxed);
+        self.bytes_written.fetch_add(bytes, Relaxed);
+    }
+
+    fn ref_count_updated(&self, incremented: bool) {
+        let counter = if incremented {
+            &self.increments
+        } else {
+            &self.decrements
+        };
+        counter.fetch_add(1, Relaxed);
+    }
+
+    fn store_write(&self, _: Duration) {
+        self.writes.fetch_add(1, Relaxed);
+    }
+}
+
+fn get(counter: &AtomicU64) -> u64 {
+    counter.load(Relaxed)
+}
+
+fn tree(manager: &Arc<Manager<Bytes, Bytes>>) -> Tree<Bytes, Bytes> {
+    (0..7u8).fold(Tree::with_manager(manager.clone()), |tree, key| {
+        tree.insert(vec![key], vec![key]).unwrap()
+    })
+}
+
+#[test]
+fn reports_saves_and_reads() {
+    let recorder = Arc::new(Recorder::default());
+    let manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .cache_policy(CachePolicy::Disabled)
+            .metrics_hooks(recorder.clone())
+            .build(),
+    );
+    let saved = tree(&manager).save().unwrap();
+    // A save is a single write of every new node.
+    assert_eq!(get(&recorder.writes), 1);
+    assert_eq!(get(&recorder.nodes_written), 7);
+    assert_eq!(get(&recorder.bytes_written), 0);
+
+    let root = saved.root_ptr().unwrap();
+    let loaded = Tree::<Bytes, Bytes>::load(manager.clone(), root);
+    assert_eq!(loaded.get(&vec![0]).unwrap(), Some(vec![0]));
+    // Without a cache, the path to the key is read node by node.
+    assert_eq!(get(&recorder.hits), 0);
+    assert_eq!(get(&recorder.misses), 3);
+    assert_eq!((get(&recorder.reads), get(&recorder.nodes_read)), (3, 3));
+
+    // Saving an update shares the untouched subtrees.
+    let updated = loaded.insert(vec![100], vec![]).unwrap().save().unwrap();
+    assert!(get(&recorder.increments) > 0);
+    assert_eq!(get(&recorder.decrements), 0);
+    manager.release(&updated.root_ptr().unwrap()).unwrap();
+    assert!(get(&recorder.decrements) > 0);
+}
+
+#[test]
+fn reports_cache_hits() {
+    let recorder = Arc::new(Recorder::default());
+    let manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .metrics_hooks(recorder.clone())
+            .build(),
+    );
+    let saved = tree(&manager).save().unwrap();
+    assert_eq!(saved.get(&vec![6]).unwrap(), Some(vec![6]));
+    // Saved nodes are cached.
+    assert_eq!((get(&recorder.hits), get(&recorder.misses)), (3, 0));
+    assert_eq!(get(&recorder.reads), 0);
+}
+
+#[cfg(feature = "borsh")]
+#[test]
+fn reports_encoded_bytes() {
+    use rhizome_trees::tree::avl::borsh::BorshCodec;
+    use rhizome_trees::tree::node_manager::{EncodedStore, FileNodeStore};
+
+    let path = std::env::temp_dir().join(format!("rhizome-metrics-hooks-{}", std::process::id()));
+    let store = FileNodeStore::open(&path).unwrap();
+    let recorder = Arc::new(Recorder::default());
+    let manager = Arc::new(
+        NodeManager::builder(EncodedStore::new(store, BorshCodec::new()))
+            .metrics_hooks(recorder.clone())
+            .build(),
+    );
+    let saved = tree(&manager).save().unwrap();
+    let stats = saved.stats().unwrap();
+    assert_eq!(get(&recorder.nodes_written), 7);
+    assert_eq!(get(&recorder.bytes_written), stats.encoded_size);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,68 @@
+//! Checks that multi-value registers keep concurrent writes until a write
+//! which saw them replaces them, however their states are merged.
+
+use rhizome_trees::crdt::clock::VectorClock;
+use rhizome_trees::crdt::MVReg;
+
+fn sorted(reg: &MVReg<&'static str>) -> Vec<&'static str> {
+    let mut values = reg.read();
+    values.sort();
+    values
+}
+
+#[test]
+fn concurrent_writes_are_all_kept() {
+    let mut a = MVReg::new(1);
+    let mut b = MVReg::new(2);
+    assert!(a.read().is_empty());
+
+    a.assign("a1").unwrap();
+    b.merge(&a);
+    assert_eq!(sorted(&b), ["a1"]);
+
+    // Both overwrite a1 without seeing each other's write.
+    a.assign("a2").unwrap();
+    b.assign("b1").unwrap();
+    let mut merged_ab = a.clone();
+    merged_ab.merge(&b);
+    let mut merged_ba = b.clone();
+    merged_ba.merge(&a);
+    assert_eq!(sorted(&merged_ab), ["a2", "b1"]);
+    assert_eq!(sorted(&merged_ba), ["a2", "b1"]);
+    assert_eq!(merged_ab.context(), merged_ba.context());
+
+    // Merging again changes nothing.
+    merged_ab.merge(&b);
+    merged_ab.merge(&merged_ba);
+    assert_eq!(sorted(&merged_ab), ["a2", "b1"]);
+
+    // A write based on the merged read resolves the conflict everywhere.
+    let context = merged_ab.context().clone();
+    merged_ab.write("resolved", &context).unwrap();
+    a.merge(&merged_ab);
+    b.merge(&merged_ab);
+    assert_eq!(sorted(&a), ["resolved"]);
+    assert_eq!(sorted(&b), ["resolved"]);
+}
+
+#[test]
+fn writes_replace_only_what_their_context_saw() {
+    let mut reg = MVReg::new(1);
+    reg.assign("first").unwrap();
+    let stale = reg.context().clone();
+    let mut other = MVReg::new(2);
+    other.merge(&reg);
+    other.assign("other").unwrap();
+    reg.merge(&other);
+    assert_eq!(sorted(&reg), ["other"]);
+
+    // A write derived from a read before the merge keeps the value it
+    // didn't see.
+    let dot = reg.write("late", &stale).unwrap();
+    assert_eq!((dot.replica, dot.counter), (1, 2));
+    assert_eq!(sorted(&reg), ["late", "other"]);
+
+    // An empty context replaces nothing.
+    reg.write("more", &VectorClock::new()).unwrap();
+    assert_eq!(sorted(&reg), ["late", "more", "other"]);
+}
@@ -0,0 +1,72 @@
+//! Checks that absent keys of saved versions are looked up once until the
+//! next write through the manager.
+
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{CachePolicy, MemNodeStore, NodeManager};
+
+type Bytes = Vec<u8>;
+
+/// The number of nodes read through the manager so far.
+fn reads(manager: &NodeManager<Node<Bytes, Bytes>>) -> u64 {
+    let stats = manager.stats().unwrap();
+    stats.cache_hits + stats.cache_misses
+}
+
+#[test]
+fn absent_keys_are_remembered_until_a_write() {
+    let manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .cache_policy(CachePolicy::Disabled)
+            .metrics(true)
+            .negative_lookup_cache(NonZeroUsize::new(16).unwrap())
+            .build(),
+    );
+    let mut tree = Tree::with_manager(manager.clone());
+    for i in 0..100u8 {
+        tree = tree.insert(vec![i * 2], vec![i]).unwrap();
+    }
+    let tree = tree.save().unwrap();
+
+    let before = reads(&manager);
+    assert!(!tree.contains_key_cached(&[1u8][..]).unwrap());
+    let first = reads(&manager) - before;
+    assert!(first > 0);
+    assert!(!tree.contains_key_cached(&[1u8][..]).unwrap());
+    assert_eq!(reads(&manager) - before, first);
+
+    // Present keys are always read.
+    assert_eq!(tree.get_cached(&[2u8][..]).unwrap(), Some(vec![1]));
+    let after_present = reads(&manager);
+    assert!(after_present > before + first);
+
+    // A save clears the cache, so the absent key is read again.
+    let other = tree.insert(vec![1], vec![1]).unwrap().save().unwrap();
+    let before = reads(&manager);
+    assert!(!tree.contains_key_cached(&[1u8][..]).unwrap());
+    assert!(reads(&manager) > before);
+    assert!(other.contains_key_cached(&[1u8][..]).unwrap());
+}
+
+#[test]
+fn lookups_without_the_cache_are_unchanged() {
+    let manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .cache_policy(CachePolicy::Disabled)
+            .metrics(true)
+            .build(),
+    );
+    let tree = Tree::with_manager(manager.clone())
+        .insert(vec![0], vec![0])
+        .unwrap()
+        .save()
+        .unwrap();
+    for _ in 0..2 {
+        let before = reads(&manager);
+        assert!(!tree.contains_key_cached(&[1u8][..]).unwrap());
+        assert!(reads(&manager) > before);
+    }
+}
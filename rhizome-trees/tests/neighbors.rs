@@ -0,0 +1,66 @@
+//! Checks first, last, successor and predecessor queries against a scan of
+//! the keys, for trees in memory and in the store.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{CachePolicy, MemNodeStore, NodeManager};
+
+#[test]
+fn finds_neighbors() {
+    let empty = Tree::<u32, u32>::new();
+    assert!(empty.first().unwrap().is_none());
+    assert!(empty.last().unwrap().is_none());
+    assert!(empty.successor(&3).unwrap().is_none());
+    assert!(empty.predecessor(&3).unwrap().is_none());
+
+    // Every third key from 0 to 897.
+    let tree = (0..300u32).fold(Tree::new(), |tree, i| tree.insert(i * 3, i).unwrap());
+    assert_eq!(*tree.first().unwrap().unwrap().key(), 0);
+    assert_eq!(*tree.last().unwrap().unwrap().key(), 897);
+    for key in 0..905u32 {
+        let successor = (key + 1..=897).find(|k| k.is_multiple_of(3));
+        let predecessor = (0..key.min(898)).rev().find(|k| k.is_multiple_of(3));
+        assert_eq!(
+            tree.successor(&key).unwrap().map(|node| *node.key()),
+            successor,
+            "successor of {}",
+            key
+        );
+        assert_eq!(
+            tree.predecessor(&key).unwrap().map(|node| *node.key()),
+            predecessor,
+            "predecessor of {}",
+            key
+        );
+    }
+}
+
+#[test]
+fn finds_neighbors_in_the_store() {
+    let manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .cache_policy(CachePolicy::Disabled)
+            .build(),
+    );
+    let tree = (0..100u8)
+        .fold(Tree::with_manager(manager.clone()), |tree, i| {
+            tree.insert(vec![i * 2], vec![i]).unwrap()
+        })
+        .save()
+        .unwrap();
+    let loaded = Tree::<Vec<u8>, Vec<u8>>::load(manager, tree.root_ptr().unwrap());
+    assert_eq!(loaded.first().unwrap().unwrap().key(), &vec![0]);
+    assert_eq!(loaded.last().unwrap().unwrap().key(), &vec![198]);
+    assert_eq!(loaded.successor(&vec![7]).unwrap().unwrap().key(), &vec![8]);
+    assert_eq!(
+        loaded.successor(&vec![8]).unwrap().unwrap().key(),
+        &vec![10]
+    );
+    assert_eq!(
+        loaded.predecessor(&vec![8]).unwrap().unwrap().key(),
+        &vec![6]
+    );
+    assert!(loaded.successor(&vec![198]).unwrap().is_none());
+    assert!(loaded.predecessor(&vec![0]).unwrap().is_none());
+}
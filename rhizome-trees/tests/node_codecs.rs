@@ -0,0 +1,83 @@
+//! Checks that trees of several node types persist through one byte store
+//! with the bincode codec, and that undecodable nodes are corruption.
+
+#![cfg(feature = "serde")]
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::{
+    BincodeCodec, CachePolicy, EncodedStore, FileNodeStore, MemNodeStore, NodeCodec, NodeManager,
+    NodeStore,
+};
+use rhizome_trees::tree::value::{Timestamp, U64BigEndian};
+
+type Bytes = Vec<u8>;
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "rhizome-node-codecs-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// A manager without a cache, so that every read decodes a node.
+fn uncached<N: Send + Sync + 'static>(
+    store: EncodedStore<Arc<FileNodeStore>, BincodeCodec>,
+) -> Arc<NodeManager<N>>
+where
+    BincodeCodec: NodeCodec<N>,
+{
+    Arc::new(
+        NodeManager::builder(store)
+            .cache_policy(CachePolicy::Disabled)
+            .build(),
+    )
+}
+
+#[test]
+fn shares_a_byte_store() {
+    let path = temp_path("shared");
+    let file = Arc::new(FileNodeStore::open(&path).unwrap());
+    let bytes = uncached(EncodedStore::new(file.clone(), BincodeCodec));
+    let times = uncached(EncodedStore::new(file.clone(), BincodeCodec));
+    let (mut a, mut b) = (Tree::with_manager(bytes), Tree::with_manager(times));
+    for i in 0..100u64 {
+        a = a.insert(i.to_be_bytes().to_vec(), vec![1; 3]).unwrap();
+        b = b
+            .insert(U64BigEndian(i), Timestamp::new(i as i64, 5))
+            .unwrap();
+    }
+    let (hash_a, hash_b) = (a.merkle_hash().unwrap(), b.merkle_hash().unwrap());
+    let (a, b) = (a.save().unwrap(), b.save().unwrap());
+    assert_eq!(file.len(), 200);
+
+    // Decoded nodes keep their hashes as well as their entries.
+    assert_eq!(a.merkle_hash().unwrap(), hash_a);
+    assert_eq!(b.merkle_hash().unwrap(), hash_b);
+    assert_eq!(a.verify().unwrap(), hash_a);
+    assert_eq!(
+        a.get(&7u64.to_be_bytes().to_vec()).unwrap(),
+        Some(vec![1; 3])
+    );
+    assert_eq!(
+        b.get(&U64BigEndian(42)).unwrap(),
+        Some(Timestamp::new(42, 5))
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn reports_undecodable_nodes() {
+    let inner = Arc::new(MemNodeStore::<Bytes>::new());
+    let ptr = inner.insert(&vec![0xff; 3]).unwrap();
+    let store = EncodedStore::new(inner, BincodeCodec);
+    let error = NodeStore::<Node<Bytes, Bytes>>::read(&store, &ptr).unwrap_err();
+    assert!(error.is_corruption(), "{}", error);
+}
@@ -0,0 +1,65 @@
+//! Checks that node handles own their nodes, so they can be sent between
+//! threads and outlive the cache entries they were read from.
+
+use std::sync::Arc;
+use std::thread;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{CachePolicy, MemNodeStore, NodeHandle, NodeManager};
+
+type Bytes = Vec<u8>;
+
+fn is_send_and_static<T: Send + 'static>(_: &T) {}
+
+/// A saved tree whose manager caches nothing, so every read loads a new
+/// copy of the node.
+fn uncached() -> Tree<Bytes, Bytes> {
+    let manager = NodeManager::builder(MemNodeStore::new())
+        .cache_policy(CachePolicy::Disabled)
+        .build();
+    (0u8..10)
+        .fold(Tree::with_manager(Arc::new(manager)), |tree, i| {
+            tree.insert(vec![i], vec![i; 3]).unwrap()
+        })
+        .save()
+        .unwrap()
+}
+
+#[test]
+fn sends_handles_between_threads() {
+    let tree = uncached();
+    let root: NodeHandle<Node<Bytes, Bytes>> = tree.read(tree.root().unwrap()).unwrap();
+    is_send_and_static(&root);
+    let key = root.key().clone();
+    let sent = thread::spawn(move || root.key().clone()).join().unwrap();
+    assert_eq!(sent, key);
+}
+
+#[test]
+fn maps_handles_to_parts_of_nodes() {
+    let tree = uncached();
+    let root = tree.read(tree.root().unwrap()).unwrap();
+    let value = root.clone().map(Node::value);
+    assert_eq!(*value, *root.value());
+    assert_eq!(value.len(), 3);
+    // The mapped handle keeps the node alive.
+    drop(root);
+    assert_eq!(value.node().value(), &*value);
+}
+
+#[test]
+fn converts_handles_to_owned_nodes() {
+    let tree = uncached();
+    let root = tree.read(tree.root().unwrap()).unwrap();
+    let arc = root.to_arc();
+    assert!(Arc::ptr_eq(&arc, &root.clone().into_arc()));
+
+    let cloned = root.clone_inner();
+    assert_eq!(cloned.key(), root.key());
+    drop(arc);
+    // The only reference left is moved out rather than cloned.
+    let key = root.key().clone();
+    let owned = root.into_owned();
+    assert_eq!(owned.key(), &key);
+}
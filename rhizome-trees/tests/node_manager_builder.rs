@@ -0,0 +1,79 @@
+//! Checks the options of the node manager builder: the cache policy, metrics
+//! and read-only mode.
+
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{CachePolicy, MemNodeStore, NodeManager};
+use rhizome_trees::tree::value::U64BigEndian as U;
+use rhizome_trees::Error;
+
+type Manager = Arc<NodeManager<Node<U, U>>>;
+
+/// Saves a tree of 100 entries through `manager` and loads it again.
+fn saved(manager: &Manager) -> Tree<U, U> {
+    let tree = (0..100u64)
+        .fold(Tree::with_manager(manager.clone()), |tree, i| {
+            tree.insert(U(i), U(i)).unwrap()
+        })
+        .save()
+        .unwrap();
+    Tree::load(manager.clone(), tree.root_ptr().unwrap())
+}
+
+#[test]
+fn counts_operations_with_metrics() {
+    let manager: Manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .metrics(true)
+            .cache_policy(CachePolicy::Disabled)
+            .build(),
+    );
+    let tree = saved(&manager);
+    assert_eq!(tree.get(&U(5)).unwrap(), Some(U(5)));
+    let stats = manager.stats().unwrap();
+    assert_eq!(stats.inserts, 100);
+    // Without a cache every read misses.
+    assert_eq!(stats.cache_hits, 0);
+    assert!(stats.cache_misses > 0);
+
+    assert!(NodeManager::<Node<U, U>>::new(MemNodeStore::new())
+        .stats()
+        .is_none());
+}
+
+#[test]
+fn caches_recently_read_nodes() {
+    let manager: Manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .metrics(true)
+            .cache_policy(CachePolicy::Lru {
+                capacity: NonZeroUsize::new(1000).unwrap(),
+            })
+            .build(),
+    );
+    let tree = saved(&manager);
+    tree.get(&U(5)).unwrap();
+    let first = manager.stats().unwrap();
+    tree.get(&U(5)).unwrap();
+    let second = manager.stats().unwrap();
+    assert_eq!(second.cache_misses, first.cache_misses);
+    assert!(second.cache_hits > first.cache_hits);
+}
+
+#[test]
+fn refuses_writes_when_read_only() {
+    let store = Arc::new(MemNodeStore::new());
+    let writable: Manager = Arc::new(NodeManager::new(store.clone()));
+    let root = saved(&writable).root_ptr().unwrap();
+
+    let read_only: Manager = Arc::new(NodeManager::builder(store).read_only(true).build());
+    assert!(read_only.is_read_only());
+    let tree = Tree::load(read_only, root);
+    assert_eq!(tree.get(&U(7)).unwrap(), Some(U(7)));
+    // Changes can be made in memory but not saved.
+    let changed = tree.insert(U(1000), U(1)).unwrap();
+    assert!(matches!(changed.save(), Err(Error::ReadOnly)));
+}
@@ -0,0 +1,89 @@
+//! Checks that trees report the version which wrote each of their nodes, and
+//! its size once encoded, as their store recorded them.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::versioned::VersionedTree;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{NodeManager, NodeMeta, NodeRef};
+
+type Bytes = Vec<u8>;
+type Link<'a> = Option<&'a NodeRef<Node<Bytes, Bytes>>>;
+
+/// Collects the key and metadata of every node below `link`.
+fn metas(tree: &Tree<Bytes, Bytes>, link: Link, out: &mut Vec<(Bytes, NodeMeta)>) {
+    let Some(link) = link else {
+        return;
+    };
+    let node = tree.read(link).unwrap();
+    out.push((node.key().clone(), tree.node_meta(link).unwrap().unwrap()));
+    metas(tree, node.left(), out);
+    metas(tree, node.right(), out);
+}
+
+#[test]
+fn records_versions() {
+    let mut versioned = VersionedTree::<Bytes, Bytes>::new(Arc::new(NodeManager::in_memory()));
+    for i in 1..=3u8 {
+        versioned.insert(vec![i], vec![i]).unwrap();
+        versioned.save().unwrap();
+    }
+    let tree = versioned.load_version(versioned.latest_version()).unwrap();
+    let mut out = Vec::new();
+    metas(&tree, tree.root(), &mut out);
+    out.sort_by(|a, b| a.0.cmp(&b.0));
+    // Memory stores don't encode their nodes.
+    let meta = |version| NodeMeta {
+        version: Some(version),
+        size: None,
+    };
+    // Inserting 3 rebalanced the tree, rewriting 1 and 2 as well.
+    assert_eq!(
+        out,
+        [(vec![1], meta(3)), (vec![2], meta(3)), (vec![3], meta(3))]
+    );
+
+    // Unsaved nodes have no metadata, nor nodes saved outside a version.
+    let unsaved = tree.insert(vec![9], vec![9]).unwrap();
+    assert_eq!(unsaved.node_meta(unsaved.root().unwrap()).unwrap(), None);
+    let saved = unsaved.save().unwrap();
+    assert_eq!(
+        saved.node_meta(saved.root().unwrap()).unwrap(),
+        Some(NodeMeta::default())
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn survives_reopening() {
+    use rhizome_trees::tree::node_manager::{BincodeCodec, EncodedStore, FileNodeStore};
+
+    let path = std::env::temp_dir().join(format!("rhizome-node-meta-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let open = || {
+        let store = EncodedStore::new(FileNodeStore::open(&path).unwrap(), BincodeCodec);
+        Arc::new(NodeManager::new(store))
+    };
+    let versions = {
+        let mut versioned = VersionedTree::new(open());
+        for i in 0..5u8 {
+            versioned.insert(vec![i], vec![i; 10]).unwrap();
+            versioned.save().unwrap();
+        }
+        versioned.versions().to_vec()
+    };
+
+    let versioned = VersionedTree::open(open(), versions).unwrap();
+    let tree = versioned.load_version(versioned.latest_version()).unwrap();
+    let mut out = Vec::new();
+    metas(&tree, tree.root(), &mut out);
+    assert_eq!(out.len(), 5);
+    assert!(out
+        .iter()
+        .all(|(_, meta)| meta.version.is_some() && meta.size.is_some()));
+    assert!(out
+        .iter()
+        .any(|(key, meta)| key == &vec![4] && meta.version == Some(5)));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,158 @@
+//! Checks that an object node store keeps nodes under their hashes, caches
+//! them locally and uploads the nodes of a batch on commit.
+#![cfg(feature = "object-store")]
+
+use std::sync::Arc;
+
+use object_store::memory::InMemory;
+use object_store::path::Path;
+use object_store::{ObjectStore, PutPayload};
+use rhizome_trees::tree::hash::Digest;
+use rhizome_trees::tree::node_manager::{NodeStore, ObjectNodeStore, Ptr};
+use rhizome_trees::Error;
+use sha2::{Digest as _, Sha256};
+
+type Bytes = Vec<u8>;
+
+fn node_path(prefix: &str, ptr: &Ptr) -> Path {
+    let hex: String = ptr
+        .as_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Path::from(format!("{}/nodes/{}", prefix, hex))
+}
+
+fn put(objects: &InMemory, path: &Path, bytes: &[u8]) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime
+        .block_on(objects.put(path, PutPayload::from(bytes.to_vec())))
+        .unwrap();
+}
+
+#[test]
+fn stores_nodes_under_their_hashes() {
+    let objects = Arc::new(InMemory::new());
+    let store = ObjectNodeStore::builder(objects.clone())
+        .prefix("trees")
+        .build()
+        .unwrap();
+
+    let ptr = store.insert(&vec![1, 2, 3]).unwrap();
+    let hash: Digest = Sha256::digest([1, 2, 3]).into();
+    assert_eq!(ptr.as_bytes(), &hash);
+    assert_eq!(store.read(&ptr).unwrap(), vec![1, 2, 3]);
+    assert_eq!(store.meta(&ptr).unwrap().unwrap().size, Some(3));
+
+    // Inserting the same bytes again takes another reference.
+    assert_eq!(store.insert(&vec![1, 2, 3]).unwrap(), ptr);
+    assert_eq!(store.inc_ref_count(&ptr).unwrap(), 3);
+    assert_eq!(store.dec_ref_count(&ptr).unwrap(), 2);
+    assert_eq!(store.dec_ref_count(&ptr).unwrap(), 1);
+    assert_eq!(store.dec_ref_count(&ptr).unwrap(), 0);
+    let err = store.dec_ref_count(&ptr).unwrap_err();
+    assert!(matches!(err, Error::RefCountUnderflow(p) if p == ptr));
+
+    // Bytes which don't hash to the pointer are corrupt.
+    put(&objects, &node_path("trees", &ptr), &[3, 2, 1]);
+    let err = store.read(&ptr).unwrap_err();
+    assert!(matches!(err, Error::Corrupt { ptr: p, .. } if p == ptr));
+
+    store.delete(&ptr).unwrap();
+    let err = store.read(&ptr).unwrap_err();
+    assert!(matches!(err, Error::NotFound(p) if p == ptr));
+    assert!(matches!(store.inc_ref_count(&ptr), Err(Error::NotFound(_))));
+
+    assert_eq!(store.header().unwrap(), None);
+    store.set_header(b"config").unwrap();
+    assert_eq!(store.header().unwrap(), Some(b"config".to_vec()));
+}
+
+#[test]
+fn reads_through_a_local_cache() {
+    let dir = std::env::temp_dir().join(format!("rhizome-object-cache-{}", std::process::id()));
+    let objects = Arc::new(InMemory::new());
+    let writer = ObjectNodeStore::new(objects.clone()).unwrap();
+    let ptr = writer.insert(&vec![7; 10]).unwrap();
+    let other = writer.insert(&vec![8]).unwrap();
+
+    let reader = ObjectNodeStore::builder(objects.clone())
+        .cache_dir(&dir)
+        .build()
+        .unwrap();
+    assert_eq!(reader.read(&ptr).unwrap(), vec![7; 10]);
+
+    // The cached node is still read once its object is corrupt, while the
+    // uncached one is fetched and checked.
+    put(&objects, &node_path("", &ptr), &[0]);
+    put(&objects, &node_path("", &other), &[0]);
+    assert_eq!(reader.read(&ptr).unwrap(), vec![7; 10]);
+    assert!(matches!(reader.read(&other), Err(Error::Corrupt { .. })));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn batches_upload_on_commit() {
+    let objects = Arc::new(InMemory::new());
+    let store = ObjectNodeStore::builder(objects)
+        .part_size(4)
+        .concurrency(2)
+        .build()
+        .unwrap();
+    let stored = store.insert(&vec![1]).unwrap();
+
+    let mut batch = store.begin_batch().unwrap();
+    batch.set_version(5);
+    // Larger than a part, so it is uploaded in parts.
+    let large = batch.insert(&(0..10).collect()).unwrap();
+    let small = batch.insert(&vec![2]).unwrap();
+    batch.inc_ref_count(&stored).unwrap();
+    batch.inc_ref_count(&small).unwrap();
+    assert!(matches!(store.read(&large), Err(Error::NotFound(_))));
+    batch.commit().unwrap();
+
+    assert_eq!(
+        store.read_many(&[large, small, stored]).unwrap(),
+        vec![(0..10).collect::<Bytes>(), vec![2], vec![1]]
+    );
+    assert_eq!(store.meta(&large).unwrap().unwrap().version, Some(5));
+    assert_eq!(store.meta(&stored).unwrap().unwrap().version, None);
+    assert_eq!(store.dec_ref_count(&small).unwrap(), 1);
+    assert_eq!(store.dec_ref_count(&stored).unwrap(), 1);
+
+    // A batch taking a reference on a missing node commits nothing.
+    let mut batch = store.begin_batch().unwrap();
+    let new = batch.insert(&vec![3]).unwrap();
+    batch.inc_ref_count(&new).unwrap();
+    batch.inc_ref_count(&Ptr::new(&[9; 32]).unwrap()).unwrap();
+    assert!(matches!(batch.commit(), Err(Error::NotFound(_))));
+    assert!(matches!(store.read(&new), Err(Error::NotFound(_))));
+}
+
+#[cfg(feature = "borsh")]
+#[test]
+fn saves_and_loads_trees() {
+    use rhizome_trees::tree::avl::borsh::BorshCodec;
+    use rhizome_trees::tree::avl::Tree;
+    use rhizome_trees::tree::hash::MerkleTree;
+    use rhizome_trees::tree::node_manager::{EncodedStore, NodeManager};
+
+    let objects = Arc::new(InMemory::new());
+    let manager = |objects: &Arc<InMemory>| {
+        let store = ObjectNodeStore::new(objects.clone()).unwrap();
+        Arc::new(NodeManager::new(EncodedStore::new(
+            store,
+            BorshCodec::new(),
+        )))
+    };
+
+    let tree = (0..20u8).fold(Tree::with_manager(manager(&objects)), |tree, key| {
+        tree.insert(vec![key], vec![key; 2]).unwrap()
+    });
+    let saved = tree.save().unwrap();
+
+    let loaded = Tree::<Bytes, Bytes>::load(manager(&objects), saved.root_ptr().unwrap());
+    assert_eq!(loaded.merkle_hash().unwrap(), saved.merkle_hash().unwrap());
+    assert_eq!(loaded.get(&vec![7]).unwrap(), Some(vec![7, 7]));
+    assert_eq!(loaded.len().unwrap(), 20);
+}
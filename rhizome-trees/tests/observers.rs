@@ -0,0 +1,120 @@
+//! Checks the order in which store and version observers see the writes of
+//! saving, rolling back and pruning versions.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::versioned::{
+    PruningPolicy, Version, VersionEvent, VersionObserver, VersionedTree,
+};
+use rhizome_trees::tree::node_manager::{MemNodeStore, NodeManager, StoreEvent, StoreObserver};
+
+type Bytes = Vec<u8>;
+type N = Node<Bytes, Bytes>;
+
+enum Event {
+    Store(StoreEvent<N>),
+    Version(VersionEvent),
+}
+
+#[derive(Default)]
+struct Log(Mutex<Vec<Event>>);
+
+impl Log {
+    fn take(&self) -> Vec<Event> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+impl StoreObserver<N> for Log {
+    fn on_store_event(&self, event: &StoreEvent<N>) {
+        self.0.lock().unwrap().push(Event::Store(event.clone()));
+    }
+}
+
+impl VersionObserver for Log {
+    fn on_version_event(&self, event: &VersionEvent) {
+        self.0.lock().unwrap().push(Event::Version(event.clone()));
+    }
+}
+
+fn observed() -> (VersionedTree<Bytes, Bytes>, Arc<Log>) {
+    let log = Arc::new(Log::default());
+    let manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .observer(log.clone())
+            .build(),
+    );
+    let mut tree = VersionedTree::new(manager);
+    tree.set_observer(log.clone());
+    (tree, log)
+}
+
+#[test]
+fn reports_nodes_before_their_commit() {
+    let (mut tree, log) = observed();
+    let mut inserted = HashSet::new();
+    for version in 1..=5u8 {
+        for i in 0..10u8 {
+            tree.insert(vec![i * version], vec![version]).unwrap();
+        }
+        tree.save().unwrap();
+
+        let events = log.take();
+        let (last, writes) = events.split_last().unwrap();
+        for event in writes {
+            match event {
+                Event::Store(StoreEvent::Inserted { ptr, node }) => {
+                    // Children are always reported before their parents.
+                    for child in [node.left(), node.right()].into_iter().flatten() {
+                        assert!(inserted.contains(&child.ptr().unwrap()));
+                    }
+                    inserted.insert(*ptr);
+                }
+                Event::Store(_) => {}
+                Event::Version(_) => panic!("a version event before the commit"),
+            }
+        }
+        match last {
+            Event::Version(VersionEvent::Committed(info)) => {
+                assert_eq!(info.version, Version::new(version as u64));
+                assert!(inserted.contains(&info.root.unwrap()));
+            }
+            _ => panic!("the save didn't end with its commit"),
+        }
+    }
+}
+
+#[test]
+fn reports_discarded_versions_before_their_nodes() {
+    let (mut tree, log) = observed();
+    for version in 1..=4u8 {
+        tree.insert(vec![version], vec![version]).unwrap();
+        tree.save().unwrap();
+    }
+    log.take();
+
+    tree.rollback_to(Version::new(3)).unwrap();
+    let events = log.take();
+    assert!(matches!(
+        events[0],
+        Event::Version(VersionEvent::RolledBack { to }) if to == Version::new(3)
+    ));
+    assert!(events[1..]
+        .iter()
+        .any(|event| matches!(event, Event::Store(StoreEvent::Deleted { .. }))));
+
+    tree.set_pruning(PruningPolicy::default().keep_recent(1));
+    tree.prune().unwrap();
+    let events = log.take();
+    match &events[0] {
+        Event::Version(VersionEvent::Pruned { versions }) => {
+            assert_eq!(versions, &[Version::new(1), Version::new(2)]);
+        }
+        _ => panic!("pruning didn't start with the pruned versions"),
+    }
+    assert!(events[1..]
+        .iter()
+        .all(|event| matches!(event, Event::Store(_))));
+}
@@ -0,0 +1,60 @@
+//! Checks selecting the i-th entry and ranking keys against a sorted list of
+//! the keys, for trees in memory and in the store.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{CachePolicy, MemNodeStore, NodeManager};
+
+/// 500 distinct even keys below 2000, inserted out of order.
+fn keys() -> Vec<u32> {
+    (0..500).map(|i| i * 7919 % 1000 * 2).collect()
+}
+
+#[test]
+fn selects_and_ranks() {
+    let keys = keys();
+    let tree = keys
+        .iter()
+        .fold(Tree::new(), |tree, &key| tree.insert(key, key + 1).unwrap());
+    let mut sorted = keys;
+    sorted.sort_unstable();
+
+    for (i, key) in sorted.iter().enumerate() {
+        let node = tree.nth(i as u64).unwrap().unwrap();
+        assert_eq!((*node.key(), *node.value()), (*key, key + 1));
+        assert_eq!(tree.rank(key).unwrap(), i as u64);
+        // Absent keys rank where they would be inserted.
+        assert_eq!(tree.rank(&(key + 1)).unwrap(), i as u64 + 1);
+    }
+    assert!(tree.nth(sorted.len() as u64).unwrap().is_none());
+    assert_eq!(tree.rank(&u32::MAX).unwrap(), sorted.len() as u64);
+
+    let empty = Tree::<u32, u32>::new();
+    assert!(empty.nth(0).unwrap().is_none());
+    assert_eq!(empty.rank(&1).unwrap(), 0);
+}
+
+#[test]
+fn selects_from_the_store() {
+    let manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .cache_policy(CachePolicy::Disabled)
+            .build(),
+    );
+    let tree = keys()
+        .iter()
+        .fold(Tree::with_manager(manager.clone()), |tree, key| {
+            tree.insert(key.to_be_bytes().to_vec(), vec![1]).unwrap()
+        })
+        .save()
+        .unwrap();
+    let loaded = Tree::<Vec<u8>, Vec<u8>>::load(manager, tree.root_ptr().unwrap());
+    let mut sorted = keys();
+    sorted.sort_unstable();
+    for i in [0, 1, 250, 499] {
+        let key = sorted[i].to_be_bytes().to_vec();
+        assert_eq!(loaded.nth(i as u64).unwrap().unwrap().key(), &key);
+        assert_eq!(loaded.rank(&key).unwrap(), i as u64);
+    }
+}
@@ -0,0 +1,107 @@
+//! Checks that observed-remove sets converge however their states are
+//! merged, and survive a save and load.
+
+use std::sync::Arc;
+
+use rhizome_trees::crdt::{ORSet, ORSetManager};
+use rhizome_trees::tree::node_manager::NodeManager;
+
+type Bytes = Vec<u8>;
+
+fn replicas(count: u64) -> (Arc<ORSetManager<Bytes>>, Vec<ORSet<Bytes>>) {
+    let manager = Arc::new(NodeManager::in_memory());
+    let sets = (1..=count)
+        .map(|replica| ORSet::new(manager.clone(), replica))
+        .collect();
+    (manager, sets)
+}
+
+fn elements(set: &ORSet<Bytes>) -> Vec<Bytes> {
+    set.elements().unwrap()
+}
+
+#[test]
+fn concurrent_add_wins_over_remove() {
+    let (_, mut sets) = replicas(2);
+    let (a, b) = sets.split_at_mut(1);
+    let (a, b) = (&mut a[0], &mut b[0]);
+
+    a.add(b"x".to_vec()).unwrap();
+    b.merge(a).unwrap();
+    assert!(b.contains(&b"x".to_vec()).unwrap());
+
+    // b removes the add it observed while a adds x again.
+    assert!(b.remove(&b"x".to_vec()).unwrap());
+    assert!(!b.remove(&b"x".to_vec()).unwrap());
+    a.add(b"x".to_vec()).unwrap();
+
+    a.merge(b).unwrap();
+    b.merge(a).unwrap();
+    assert_eq!(elements(a), [b"x".to_vec()]);
+    assert_eq!(elements(b), [b"x".to_vec()]);
+    assert_eq!(a.tags(&b"x".to_vec()).unwrap().len(), 1);
+
+    // A remove after observing both adds removes x everywhere.
+    b.remove(&b"x".to_vec()).unwrap();
+    a.merge(b).unwrap();
+    assert!(!a.contains(&b"x".to_vec()).unwrap());
+}
+
+#[test]
+fn merges_commute_and_are_idempotent() {
+    let (manager, mut sets) = replicas(3);
+    for (i, set) in sets.iter_mut().enumerate() {
+        for j in 0..10u8 {
+            set.add(vec![j % (i as u8 + 3)]).unwrap();
+        }
+        set.remove(&vec![i as u8]).unwrap();
+    }
+
+    // Merges the states in every order into fresh replicas.
+    let orders: [&[usize]; 4] = [&[0, 1, 2], &[2, 1, 0], &[1, 0, 2], &[1, 2, 0, 1, 2]];
+    let merged: Vec<Vec<Bytes>> = orders
+        .iter()
+        .map(|order| {
+            let mut set = ORSet::new(manager.clone(), 10);
+            for &i in *order {
+                set.merge(&sets[i]).unwrap();
+                set.merge(&sets[i]).unwrap();
+            }
+            elements(&set)
+        })
+        .collect();
+    for elements in &merged {
+        assert_eq!(elements, &merged[0]);
+    }
+    // Each removal only covers the adds of the replica which made it, which
+    // the other replicas' adds of the same element outlive.
+    assert_eq!(merged[0], (0..5u8).map(|i| vec![i]).collect::<Vec<_>>());
+}
+
+#[test]
+fn saved_sets_load_and_release() {
+    let (manager, mut sets) = replicas(1);
+    let set = &mut sets[0];
+    set.add(b"a".to_vec()).unwrap();
+    let tag = set.add(b"b".to_vec()).unwrap();
+    set.remove(&b"a".to_vec()).unwrap();
+    let saved = set.save().unwrap();
+    assert_eq!(saved.next_counter, 2);
+
+    let mut loaded = ORSet::load(manager.clone(), &saved);
+    assert_eq!(loaded.replica(), 1);
+    assert_eq!(elements(&loaded), [b"b".to_vec()]);
+    assert_eq!(loaded.tags(&b"b".to_vec()).unwrap(), [tag]);
+    // The loaded set keeps creating fresh tags and remembers the removal.
+    assert_ne!(loaded.add(b"b".to_vec()).unwrap(), tag);
+    let mut other = ORSet::new(manager.clone(), 2);
+    other.merge(set).unwrap();
+    loaded.merge(&other).unwrap();
+    assert!(!loaded.contains(&b"a".to_vec()).unwrap());
+
+    let empty = ORSet::<Bytes>::new(manager.clone(), 3).save().unwrap();
+    assert_eq!((empty.added, empty.removed), (None, None));
+    drop((loaded, other));
+    sets.clear();
+    assert!(ORSet::release(&manager, &saved).unwrap() > 0);
+}
@@ -0,0 +1,87 @@
+//! Checks that an overlay reads through to the tree beneath it, leaves that
+//! tree unchanged, and flattens into the version its writes describe.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::overlay::OverlayTree;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::NodeManager;
+use rhizome_trees::tree::value::U64BigEndian as U;
+
+/// A saved tree mapping each key below 100 to itself.
+fn saved() -> Tree<U, U> {
+    let manager = Arc::new(NodeManager::in_memory());
+    (0..100u64)
+        .fold(Tree::with_manager(manager), |tree, i| {
+            tree.insert(U(i), U(i)).unwrap()
+        })
+        .save()
+        .unwrap()
+}
+
+#[test]
+fn reads_through_to_the_base() {
+    let base = saved();
+    let mut overlay = OverlayTree::new(base.clone());
+    assert!(!overlay.is_modified());
+    overlay.insert(U(5), U(50));
+    overlay.delete(U(6));
+    overlay.insert(U(200), U(1));
+    assert!(overlay.is_modified());
+
+    assert_eq!(overlay.get(&U(5)).unwrap(), Some(U(50)));
+    assert_eq!(overlay.get(&U(6)).unwrap(), None);
+    assert_eq!(overlay.get(&U(7)).unwrap(), Some(U(7)));
+    assert_eq!(overlay.get(&U(200)).unwrap(), Some(U(1)));
+    assert!(!overlay.contains_key(&U(6)).unwrap());
+    assert!(overlay.contains_key(&U(7)).unwrap());
+
+    // The base tree doesn't see the writes.
+    assert_eq!(base.get(&U(5)).unwrap(), Some(U(5)));
+    assert_eq!(overlay.base_tree().get(&U(6)).unwrap(), Some(U(6)));
+}
+
+#[test]
+fn nests_overlays() {
+    let base = saved();
+    let mut outer = OverlayTree::new(base.clone());
+    outer.insert(U(5), U(50));
+    outer.delete(U(6));
+    outer.insert(U(200), U(1));
+    assert_eq!(outer.depth(), 0);
+
+    let mut inner = outer.begin();
+    assert_eq!(inner.depth(), 1);
+    assert!(!inner.is_modified());
+    assert_eq!(inner.get(&U(5)).unwrap(), Some(U(50)));
+    assert_eq!(inner.get(&U(6)).unwrap(), None);
+    inner.insert(U(6), U(66));
+    inner.delete(U(200));
+    inner.delete(U(9));
+    assert_eq!(inner.get(&U(6)).unwrap(), Some(U(66)));
+    assert_eq!(inner.get(&U(200)).unwrap(), None);
+
+    // Flattening applies every layer, innermost last.
+    let flattened = inner.flatten().unwrap();
+    assert!(flattened.root_ptr().is_some());
+    let expected = base
+        .insert(U(5), U(50))
+        .unwrap()
+        .insert(U(6), U(66))
+        .unwrap()
+        .delete(&U(9))
+        .unwrap();
+    assert_eq!(flattened.diff(&expected).count(), 0);
+}
+
+#[test]
+fn flattens_an_unmodified_overlay_to_its_base() {
+    let base = saved();
+    let flattened = OverlayTree::new(base.clone()).flatten().unwrap();
+    assert_eq!(flattened.root_ptr(), base.root_ptr());
+
+    // Deleting a key which doesn't exist changes nothing either.
+    let mut overlay = OverlayTree::new(base.clone());
+    overlay.delete(U(1000));
+    assert_eq!(overlay.flatten().unwrap().root_ptr(), base.root_ptr());
+}
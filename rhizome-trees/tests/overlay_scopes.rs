@@ -0,0 +1,96 @@
+//! Checks that committing a nested overlay scope moves exactly its writes
+//! into the enclosing one and that reverting it discards exactly its writes,
+//! against a stack of maps.
+
+mod rng;
+
+use std::collections::BTreeMap;
+
+use rhizome_trees::tree::avl::overlay::OverlayTree;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::value::U64BigEndian as U;
+use rng::Rng;
+
+fn base() -> Tree<U, U> {
+    (0..10u64).fold(Tree::new(), |tree, i| tree.insert(U(i), U(i)).unwrap())
+}
+
+#[test]
+fn commits_and_reverts_scopes() {
+    let mut block = OverlayTree::new(base());
+    block.insert(U(1), U(10));
+    let mut tx = block.begin();
+    tx.delete(U(2));
+
+    // A reverted message leaves the transaction as it was.
+    let mut msg = tx.begin();
+    msg.insert(U(1), U(11));
+    msg.insert(U(3), U(33));
+    assert_eq!(msg.get(&U(1)).unwrap(), Some(U(11)));
+    let tx = msg.revert().unwrap();
+    assert_eq!(tx.get(&U(1)).unwrap(), Some(U(10)));
+    assert_eq!(tx.get(&U(3)).unwrap(), Some(U(3)));
+    assert_eq!(tx.get(&U(2)).unwrap(), None);
+
+    // A committed one is part of it.
+    let mut msg = tx.begin();
+    msg.insert(U(4), U(44));
+    let tx = msg.commit().unwrap();
+    assert_eq!(tx.depth(), 1);
+    assert_eq!(tx.get(&U(4)).unwrap(), Some(U(44)));
+
+    let block = tx.commit().unwrap();
+    assert_eq!(block.depth(), 0);
+    assert_eq!(block.get(&U(2)).unwrap(), None);
+    assert_eq!(block.get(&U(4)).unwrap(), Some(U(44)));
+}
+
+#[test]
+fn refuses_to_close_the_outermost_overlay() {
+    let overlay = OverlayTree::new(base());
+    assert!(overlay.clone().commit().is_err());
+    assert!(overlay.revert().is_err());
+}
+
+#[test]
+fn matches_a_stack_of_maps() {
+    let mut rng = Rng(7);
+    let mut overlay = OverlayTree::new(base());
+    // The state seen by each open scope, outermost first.
+    let mut scopes = vec![(0..10u64).map(|i| (i, i)).collect::<BTreeMap<_, _>>()];
+    for _ in 0..5000 {
+        let key = rng.next() % 20;
+        match rng.next() % 10 {
+            0 | 1 if scopes.len() < 5 => {
+                overlay = overlay.begin();
+                scopes.push(scopes.last().unwrap().clone());
+            }
+            2 if scopes.len() > 1 => {
+                overlay = overlay.commit().unwrap();
+                let state = scopes.pop().unwrap();
+                *scopes.last_mut().unwrap() = state;
+            }
+            3 if scopes.len() > 1 => {
+                overlay = overlay.revert().unwrap();
+                scopes.pop();
+            }
+            4..=6 => {
+                overlay.delete(U(key));
+                scopes.last_mut().unwrap().remove(&key);
+            }
+            _ => {
+                let value = rng.next() % 100;
+                overlay.insert(U(key), U(value));
+                scopes.last_mut().unwrap().insert(key, value);
+            }
+        }
+        assert_eq!(overlay.depth(), scopes.len() - 1);
+        let state = scopes.last().unwrap();
+        for key in 0..20 {
+            assert_eq!(
+                overlay.get(&U(key)).unwrap(),
+                state.get(&key).map(|&v| U(v))
+            );
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Checks that backends chosen at runtime behind `dyn PersistentMap` apply
+//! the same writes and commit the same root hash.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::art;
+use rhizome_trees::tree::avl::versioned::VersionedTree;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::map::PersistentMap;
+use rhizome_trees::tree::node_manager::NodeManager;
+
+type Bytes = Vec<u8>;
+type DynMap = Box<dyn PersistentMap<Key = Bytes, Value = Bytes>>;
+
+fn check(maps: Vec<DynMap>) {
+    let mut hashes = Vec::new();
+    for mut map in maps {
+        assert!(map.is_empty());
+        for i in 0..20u8 {
+            map.insert(&vec![i], vec![i]).unwrap();
+        }
+        map.delete(&vec![3]).unwrap();
+        hashes.push(map.commit().unwrap());
+        assert_eq!(map.len().unwrap(), 19);
+        assert_eq!(map.get(&vec![4]).unwrap(), Some(vec![4]));
+        assert!(!map.contains_key(&vec![3]).unwrap());
+    }
+    assert!(hashes.windows(2).all(|pair| pair[0] == pair[1]));
+}
+
+#[test]
+fn commits_equal_hashes() {
+    check(vec![
+        Box::new(Tree::with_manager(Arc::new(NodeManager::in_memory()))),
+        Box::new(VersionedTree::new(Arc::new(NodeManager::in_memory()))),
+    ]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn commits_to_files() {
+    use rhizome_trees::tree::node_manager::{BincodeCodec, EncodedStore, FileNodeStore};
+
+    let path = std::env::temp_dir().join(format!("rhizome-dyn-map-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let store = EncodedStore::new(FileNodeStore::open(&path).unwrap(), BincodeCodec);
+    check(vec![
+        Box::new(Tree::with_manager(Arc::new(NodeManager::in_memory()))),
+        Box::new(Tree::with_manager(Arc::new(NodeManager::new(store)))),
+    ]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn holds_art_trees() {
+    let mut map: Box<dyn PersistentMap<Key = [u8], Value = Bytes>> = Box::new(art::Tree::new());
+    map.insert(b"a", vec![1]).unwrap();
+    map.insert(b"ab", vec![2]).unwrap();
+    map.delete(b"a").unwrap();
+    map.commit().unwrap();
+    assert_eq!(map.len().unwrap(), 1);
+    assert_eq!(map.get(b"ab").unwrap(), Some(vec![2]));
+}
+
+#[test]
+fn commits_without_changes_take_no_reference() {
+    let manager = Arc::new(NodeManager::in_memory());
+    let mut tree = Tree::with_manager(manager.clone());
+    let map: &mut dyn PersistentMap<Key = Bytes, Value = Bytes> = &mut tree;
+    map.insert(&vec![1], vec![1]).unwrap();
+    let hash = map.commit().unwrap();
+    assert_eq!(map.commit().unwrap(), hash);
+    let root = tree.root_ptr().unwrap();
+    assert_eq!(manager.ref_count(&root).unwrap(), Some(1));
+    assert!(tree.free_version().unwrap() > 0);
+}
@@ -0,0 +1,120 @@
+//! Checks set algebra on persistent sets against `BTreeSet`, including the
+//! balance of the resulting trees, for sets in memory and in different
+//! stores.
+
+mod rng;
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::set::PersistentSet;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::{MerkleTree, EMPTY_HASH};
+use rhizome_trees::tree::node_manager::{NodeManager, NodeRef};
+use rhizome_trees::tree::value::U64BigEndian as U;
+use rng::Rng;
+
+/// Appends the keys below `node` to `keys` in order, checking that the
+/// subtree is balanced, and returns its height.
+fn walk(tree: &Tree<U, ()>, node: Option<&NodeRef<Node<U, ()>>>, keys: &mut Vec<u64>) -> u8 {
+    let Some(node) = node else { return 0 };
+    let node = tree.read(node).unwrap();
+    let left = walk(tree, node.left(), keys);
+    keys.push(node.key().0);
+    let right = walk(tree, node.right(), keys);
+    assert!(left.abs_diff(right) <= 1, "unbalanced node");
+    assert_eq!(node.height(), 1 + left.max(right));
+    node.height()
+}
+
+fn contents(set: &PersistentSet<U>) -> Vec<u64> {
+    let mut keys = Vec::new();
+    walk(set.tree(), set.tree().root(), &mut keys);
+    assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    keys
+}
+
+#[test]
+fn matches_btree_sets() {
+    let mut rng = Rng(99);
+    let manager = Arc::new(NodeManager::in_memory());
+    for round in 0..100 {
+        let range = 1 + rng.next() % 500;
+        let mut a = if round % 2 == 0 {
+            PersistentSet::with_manager(manager.clone())
+        } else {
+            PersistentSet::new()
+        };
+        let mut b = PersistentSet::new();
+        let (mut expected_a, mut expected_b) = (BTreeSet::new(), BTreeSet::new());
+        for _ in 0..rng.next() % 300 {
+            let key = rng.next() % range;
+            a = a.insert(U(key)).unwrap();
+            expected_a.insert(key);
+        }
+        for _ in 0..rng.next() % 300 {
+            let key = rng.next() % range;
+            b = b.insert(U(key)).unwrap();
+            expected_b.insert(key);
+        }
+        if round % 4 == 0 {
+            a = a.save().unwrap();
+        }
+
+        assert_eq!(
+            contents(&a.union(&b).unwrap()),
+            expected_a.union(&expected_b).copied().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            contents(&a.intersection(&b).unwrap()),
+            expected_a
+                .intersection(&expected_b)
+                .copied()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            contents(&a.difference(&b).unwrap()),
+            expected_a
+                .difference(&expected_b)
+                .copied()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            contents(&b.difference(&a).unwrap()),
+            expected_b
+                .difference(&expected_a)
+                .copied()
+                .collect::<Vec<_>>()
+        );
+    }
+}
+
+#[test]
+fn handles_identical_and_empty_sets() {
+    let set = (0..50u64).fold(PersistentSet::new(), |set, i| set.insert(U(i)).unwrap());
+    let empty = PersistentSet::new();
+    assert_eq!(
+        set.union(&set).unwrap().merkle_hash().unwrap(),
+        set.merkle_hash().unwrap()
+    );
+    assert!(set.difference(&set).unwrap().is_empty());
+    assert_eq!(contents(&set.intersection(&set).unwrap()).len(), 50);
+    assert_eq!(contents(&set.union(&empty).unwrap()).len(), 50);
+    assert!(set.intersection(&empty).unwrap().is_empty());
+    assert_eq!(empty.merkle_hash().unwrap(), EMPTY_HASH);
+}
+
+#[test]
+fn inserts_and_removes_keys() {
+    let set = PersistentSet::new()
+        .insert(U(1))
+        .unwrap()
+        .insert(U(2))
+        .unwrap();
+    assert!(set.contains(&U(1)).unwrap());
+    let removed = set.remove(&U(1)).unwrap();
+    assert!(!removed.contains(&U(1)).unwrap());
+    assert!(set.contains(&U(1)).unwrap());
+    assert_eq!(contents(&removed), vec![2]);
+}
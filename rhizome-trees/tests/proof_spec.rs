@@ -0,0 +1,63 @@
+//! Checks that a verifier configured only from the proof spec descriptor
+//! reproduces the root hashes of trees.
+#![cfg(feature = "ics23")]
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::{hash_of, hash_parts, Digest, MerkleTree};
+use rhizome_trees::tree::ics23::{proof_spec, Child, HashOp, LengthOp, SpecDescriptor};
+
+type Bytes = Vec<u8>;
+
+/// Hashes a single-entry tree the way the descriptor says.
+fn root_of(spec: &SpecDescriptor, key: &[u8], value: &[u8]) -> Digest {
+    assert_eq!(
+        (spec.hash, spec.prehash_key, spec.prehash_value, spec.length),
+        (
+            HashOp::Sha256,
+            HashOp::Sha256,
+            HashOp::Sha256,
+            LengthOp::VarProto
+        )
+    );
+    let (key, value) = (hash_of(key), hash_of(value));
+    // A varint of a 32 byte length is the single byte 32.
+    let leaf = hash_parts(&[&spec.leaf_prefix, &[32], &key, &[32], &value]);
+    let mut parts: Vec<&[u8]> = vec![&spec.inner_prefix];
+    for child in &spec.child_order {
+        parts.push(match child {
+            Child::Entry => &leaf,
+            Child::Left | Child::Right => &spec.empty_child,
+        });
+    }
+    hash_parts(&parts)
+}
+
+#[test]
+fn descriptor_reproduces_root_hashes() {
+    let spec = proof_spec().describe().unwrap();
+    assert_eq!(spec.child_order, [Child::Left, Child::Entry, Child::Right]);
+    assert_eq!(spec.child_size as usize, spec.empty_child.len());
+
+    let tree: Tree<Bytes, Bytes> = Tree::new()
+        .insert(b"key".to_vec(), b"value".to_vec())
+        .unwrap();
+    assert_eq!(
+        root_of(&spec, b"key", b"value"),
+        tree.merkle_hash().unwrap()
+    );
+
+    let mut other = proof_spec();
+    other.max_depth = 10;
+    assert!(other.describe().is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn descriptor_serializes() {
+    let spec = proof_spec().describe().unwrap();
+    let bytes = bincode::serialize(&spec).unwrap();
+    assert_eq!(
+        bincode::deserialize::<SpecDescriptor>(&bytes).unwrap(),
+        spec
+    );
+}
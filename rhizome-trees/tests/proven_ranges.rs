@@ -0,0 +1,55 @@
+//! Checks that iterating a range with proofs yields the entries of the range,
+//! each with the proof `prove` would return for it.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::NodeManager;
+
+type Bytes = Vec<u8>;
+
+fn key(n: u32) -> Bytes {
+    n.to_be_bytes().to_vec()
+}
+
+#[test]
+fn proves_each_entry() {
+    let tree = (0..300).step_by(3).fold(
+        Tree::with_manager(Arc::new(NodeManager::in_memory())),
+        |tree, i| tree.insert(key(i), vec![i as u8]).unwrap(),
+    );
+    // Unsaved and saved trees alike.
+    for tree in [tree.clone(), tree.save().unwrap()] {
+        let root = tree.merkle_hash().unwrap();
+        for (lo, hi) in [(0, 300), (1, 2), (4, 100), (150, 151), (297, 400), (0, 0)] {
+            let proven: Vec<_> = tree
+                .range_with_proofs(key(lo)..key(hi))
+                .unwrap()
+                .map(|entry| entry.unwrap())
+                .collect();
+            let keys: Vec<Bytes> = tree
+                .range(key(lo)..key(hi))
+                .unwrap()
+                .map(|node| node.unwrap().key().clone())
+                .collect();
+            assert_eq!(
+                proven
+                    .iter()
+                    .map(|(node, _)| node.key().clone())
+                    .collect::<Vec<_>>(),
+                keys,
+                "{}..{}",
+                lo,
+                hi
+            );
+            for (node, proof) in &proven {
+                assert!(proof.verify(&root, node.key(), node.value()));
+                assert_eq!(tree.prove(node.key()).unwrap().as_ref(), Some(proof));
+            }
+        }
+        assert_eq!(tree.range_with_proofs(..).unwrap().count(), 100);
+    }
+    let empty: Tree<Bytes, Bytes> = Tree::new();
+    assert_eq!(empty.range_with_proofs(..).unwrap().count(), 0);
+}
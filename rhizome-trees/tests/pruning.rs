@@ -0,0 +1,81 @@
+//! Checks which versions pruning policies keep, and that pruning, inline or
+//! in the background, deletes only the nodes no kept version uses.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::versioned::{PruningPolicy, Version, VersionedTree};
+use rhizome_trees::tree::node_manager::{MemNodeStore, NodeManager};
+
+type Bytes = Vec<u8>;
+type Store = MemNodeStore<Node<Bytes, Bytes>>;
+
+/// Saves versions 1 to 20, version `v` setting the key `v % 3` to `v`.
+fn saved() -> (VersionedTree<Bytes, Bytes>, Arc<Store>) {
+    let store = Arc::new(MemNodeStore::new());
+    let mut tree = VersionedTree::new(Arc::new(NodeManager::new(store.clone())));
+    for version in 1..=20u8 {
+        tree.insert(vec![version % 3], vec![version]).unwrap();
+        tree.save().unwrap();
+    }
+    (tree, store)
+}
+
+fn versions(tree: &VersionedTree<Bytes, Bytes>) -> Vec<u64> {
+    tree.versions()
+        .iter()
+        .map(|info| info.version.get())
+        .collect()
+}
+
+#[test]
+fn decides_which_versions_to_keep() {
+    let latest = Version::new(20);
+    let policy = PruningPolicy::default();
+    assert!(policy.retains(Version::new(1), latest));
+
+    let policy = PruningPolicy::default().keep_recent(3).keep_every(5);
+    let kept: Vec<u64> = (1..=20)
+        .filter(|&v| policy.retains(Version::new(v), latest))
+        .collect();
+    assert_eq!(kept, vec![5, 10, 15, 18, 19, 20]);
+
+    // The latest version is kept regardless.
+    let policy = PruningPolicy::default().keep_recent(0).keep_every(0);
+    assert!(policy.retains(latest, latest));
+    assert!(!policy.retains(Version::new(19), latest));
+}
+
+#[test]
+fn prunes_versions_outside_the_policy() {
+    let (mut tree, store) = saved();
+    // The default policy keeps everything.
+    assert_eq!(tree.prune().unwrap(), 0);
+    assert_eq!(tree.versions().len(), 20);
+
+    tree.set_pruning(PruningPolicy::default().keep_recent(3).keep_every(5));
+    let before = store.len();
+    let deleted = tree.prune().unwrap();
+    assert!(deleted > 0);
+    assert_eq!(store.len(), before - deleted);
+    assert_eq!(versions(&tree), vec![5, 10, 15, 18, 19, 20]);
+    assert!(tree.load_version(Version::new(4)).is_err());
+    let fifth = tree.load_version(Version::new(5)).unwrap();
+    assert_eq!(fifth.get(&vec![2]).unwrap(), Some(vec![5]));
+    assert_eq!(fifth.get(&vec![1]).unwrap(), Some(vec![4]));
+}
+
+#[test]
+fn prunes_in_the_background() {
+    let (mut tree, store) = saved();
+    tree.set_pruning(PruningPolicy::default().keep_recent(1));
+    let handle = tree.prune_in_background();
+    // The pruned versions are gone before their nodes are.
+    assert_eq!(versions(&tree), vec![20]);
+    assert!(handle.join().unwrap().unwrap() > 0);
+
+    assert_eq!(tree.get(&vec![2]).unwrap(), Some(vec![20]));
+    // Only the nodes of the remaining version are left.
+    assert_eq!(store.len(), 3);
+    assert_eq!(tree.rollback_to(Version::new(0)).unwrap(), 3);
+}
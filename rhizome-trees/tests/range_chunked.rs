@@ -0,0 +1,142 @@
+//! Checks that chunked iteration yields the entries of a range in order,
+//! and reads the nodes of a chunk in a few batched round trips.
+
+use std::ops::Bound;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{CachePolicy, MemNodeStore, NodeManager, NodeStore, Ptr};
+
+type Bytes = Vec<u8>;
+type BytesNode = Node<Bytes, Bytes>;
+
+/// A store counting its round trips, as a network-backed store would pay
+/// for them.
+#[derive(Default)]
+struct RoundTrips {
+    inner: MemNodeStore<BytesNode>,
+    trips: AtomicUsize,
+}
+
+impl NodeStore<BytesNode> for RoundTrips {
+    fn read(&self, ptr: &Ptr) -> rhizome_trees::Result<BytesNode> {
+        self.trips.fetch_add(1, Relaxed);
+        self.inner.read(ptr)
+    }
+
+    fn read_many(&self, ptrs: &[Ptr]) -> rhizome_trees::Result<Vec<BytesNode>> {
+        self.trips.fetch_add(1, Relaxed);
+        self.inner.read_many(ptrs)
+    }
+
+    fn insert(&self, node: &BytesNode) -> rhizome_trees::Result<Ptr> {
+        self.inner.insert(node)
+    }
+
+    fn inc_ref_count(&self, ptr: &Ptr) -> rhizome_trees::Result<u64> {
+        self.inner.inc_ref_count(ptr)
+    }
+
+    fn dec_ref_count(&self, ptr: &Ptr) -> rhizome_trees::Result<u64> {
+        self.inner.dec_ref_count(ptr)
+    }
+
+    fn delete(&self, ptr: &Ptr) -> rhizome_trees::Result<()> {
+        self.inner.delete(ptr)
+    }
+}
+
+fn key(i: u32) -> Bytes {
+    i.to_be_bytes().to_vec()
+}
+
+/// Saves a tree of the keys `0..len` and reloads it with an uncached
+/// manager, so that every node is read from the store.
+fn stored_tree(len: u32) -> (Tree<Bytes, Bytes>, Arc<RoundTrips>) {
+    let store = Arc::new(RoundTrips::default());
+    let manager = Arc::new(
+        NodeManager::builder(store.clone())
+            .cache_policy(CachePolicy::Disabled)
+            .build(),
+    );
+    let mut tree = Tree::with_manager(manager.clone());
+    for i in 0..len {
+        tree = tree.insert(key(i), key(i * 2)).unwrap();
+    }
+    let root = tree.save().unwrap().root_ptr().unwrap();
+    (Tree::load(manager, root), store)
+}
+
+fn chunks(
+    tree: &Tree<Bytes, Bytes>,
+    range: (Bound<Bytes>, Bound<Bytes>),
+    size: usize,
+) -> Vec<Vec<Bytes>> {
+    tree.range_chunked(range, size)
+        .map(|chunk| {
+            chunk
+                .unwrap()
+                .iter()
+                .map(|node| node.key().clone())
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn chunks_match_the_range() {
+    let (tree, _) = stored_tree(100);
+    let bounds = [
+        (Bound::Unbounded, Bound::Unbounded),
+        (Bound::Included(key(10)), Bound::Excluded(key(60))),
+        (Bound::Excluded(key(10)), Bound::Included(key(60))),
+        (Bound::Included(key(99)), Bound::Unbounded),
+        (Bound::Unbounded, Bound::Excluded(key(0))),
+        (Bound::Included(key(50)), Bound::Included(key(50))),
+    ];
+    for range in bounds {
+        let expected: Vec<Bytes> = tree
+            .range(range.clone())
+            .unwrap()
+            .map(|node| node.unwrap().key().clone())
+            .collect();
+        for size in [0, 1, 3, 16, 1000] {
+            let chunks = chunks(&tree, range.clone(), size);
+            assert!(chunks.iter().all(|chunk| chunk.len() <= size.max(1)));
+            // Only the last chunk may be short.
+            if let Some((_, full)) = chunks.split_last() {
+                assert!(full.iter().all(|chunk| chunk.len() == size.max(1)));
+            }
+            assert_eq!(
+                chunks.concat(),
+                expected,
+                "{:?} in chunks of {}",
+                range,
+                size
+            );
+        }
+    }
+}
+
+#[test]
+fn chunks_batch_their_reads() {
+    let (tree, store) = stored_tree(1000);
+    let entries = tree.iter().unwrap().count();
+    let single_reads = store.trips.swap(0, Relaxed);
+    assert_eq!(single_reads, entries);
+
+    let chunks = tree.range_chunked(.., 100).count();
+    assert_eq!(chunks, 10);
+    let batched = store.trips.load(Relaxed);
+    // A chunk takes about one round trip per level of the tree.
+    assert!(batched < chunks * 15, "{} round trips", batched);
+}
+
+#[test]
+fn empty_trees_have_no_chunks() {
+    let tree = Tree::<Bytes, Bytes>::new();
+    assert!(tree.range_chunked(.., 10).next().is_none());
+}
@@ -0,0 +1,96 @@
+//! Checks that deleting a range of keys matches a `BTreeMap` model for every
+//! kind of bound, and leaves trees balanced and untouched where it can.
+
+mod rng;
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::versioned::VersionedTree;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::NodeManager;
+use rng::Rng;
+
+type Bytes = Vec<u8>;
+
+fn key(n: u64) -> Bytes {
+    (n as u32).to_be_bytes().to_vec()
+}
+
+fn random_bound(rng: &mut Rng) -> Bound<Bytes> {
+    let n = key(rng.next() % 1100);
+    match rng.next() % 3 {
+        0 => Bound::Included(n),
+        1 => Bound::Excluded(n),
+        _ => Bound::Unbounded,
+    }
+}
+
+/// Whether `BTreeMap::range` accepts the bounds rather than panicking.
+fn is_valid(lo: &Bound<Bytes>, hi: &Bound<Bytes>) -> bool {
+    match (lo, hi) {
+        (Bound::Excluded(a), Bound::Excluded(b)) => a < b,
+        (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) => {
+            a <= b
+        }
+        _ => true,
+    }
+}
+
+#[test]
+fn matches_model() {
+    let mut tree = Tree::new();
+    let mut model = BTreeMap::new();
+    for i in 0..500 {
+        let n = key(i * 7 % 1000);
+        tree = tree.insert(n.clone(), n.clone()).unwrap();
+        model.insert(n.clone(), n);
+    }
+    let mut rng = Rng(12345);
+    for round in 0..200u32 {
+        let (lo, hi) = (random_bound(&mut rng), random_bound(&mut rng));
+        let deleted = tree.delete_range((lo.clone(), hi.clone())).unwrap();
+        let mut expected = model.clone();
+        // Empty or inverted ranges delete nothing.
+        if is_valid(&lo, &hi) {
+            let keys: Vec<Bytes> = model.range((lo, hi)).map(|(k, _)| k.clone()).collect();
+            for k in keys {
+                expected.remove(&k);
+            }
+        }
+        let keys: Vec<Bytes> = deleted
+            .iter()
+            .unwrap()
+            .map(|node| node.unwrap().key().clone())
+            .collect();
+        assert_eq!(keys, expected.keys().cloned().collect::<Vec<_>>());
+        deleted.verify().unwrap();
+        assert_eq!(deleted.len().unwrap(), expected.len() as u64);
+        if round.is_multiple_of(5) {
+            tree = deleted;
+            model = expected;
+        }
+    }
+}
+
+#[test]
+fn shares_untouched_roots() {
+    let tree = (0..100).fold(Tree::new(), |tree, i| tree.insert(key(i), vec![1]).unwrap());
+    let deleted = tree.delete_range(key(2000)..).unwrap();
+    assert!(deleted.root().unwrap().same_node(tree.root().unwrap()));
+    assert!(tree.delete_range(..).unwrap().is_empty());
+}
+
+#[test]
+fn deletes_versioned_ranges() {
+    let mut tree: VersionedTree<Bytes, Bytes> =
+        VersionedTree::new(Arc::new(NodeManager::in_memory()));
+    for i in 0..10 {
+        tree.insert(key(i), key(i)).unwrap();
+    }
+    tree.delete_range(key(2)..key(8)).unwrap();
+    assert_eq!(tree.get(&key(1)).unwrap(), Some(key(1)));
+    assert_eq!(tree.get(&key(5)).unwrap(), None);
+    assert_eq!(tree.get(&key(8)).unwrap(), Some(key(8)));
+}
@@ -0,0 +1,128 @@
+//! Checks range iteration over every kind of bound, and streaming a range
+//! to an async consumer.
+
+use std::ops::Bound;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::NodeHandle;
+use rhizome_trees::Result;
+
+type Bytes = Vec<u8>;
+type Entry = Result<NodeHandle<Node<Bytes, Bytes>>>;
+
+fn key(n: u32) -> Bytes {
+    n.to_be_bytes().to_vec()
+}
+
+/// A saved tree of the even keys below 400.
+fn evens() -> Tree<Bytes, Bytes> {
+    (0..200)
+        .fold(Tree::new(), |tree, i| {
+            tree.insert(key(i * 2), vec![1]).unwrap()
+        })
+        .save()
+        .unwrap()
+}
+
+fn keys(entries: impl Iterator<Item = Entry>) -> Vec<u32> {
+    entries
+        .map(|node| u32::from_be_bytes(node.unwrap().key()[..].try_into().unwrap()))
+        .collect()
+}
+
+#[test]
+fn iterates_ranges() {
+    let tree = evens();
+    assert_eq!(
+        keys(tree.range(key(11)..key(21)).unwrap()),
+        [12, 14, 16, 18, 20]
+    );
+    assert_eq!(
+        keys(tree.range(key(12)..key(20)).unwrap()),
+        [12, 14, 16, 18]
+    );
+    assert_eq!(
+        keys(tree.range(key(12)..=key(20)).unwrap()),
+        [12, 14, 16, 18, 20]
+    );
+    assert_eq!(keys(tree.range(..key(5)).unwrap()), [0, 2, 4]);
+    assert_eq!(keys(tree.range(key(394)..).unwrap()), [394, 396, 398]);
+    assert_eq!(
+        keys(
+            tree.range((Bound::Excluded(key(12)), Bound::Included(key(16))))
+                .unwrap()
+        ),
+        [14, 16]
+    );
+    assert_eq!(tree.range(..).unwrap().count(), 200);
+    assert_eq!(tree.iter().unwrap().count(), 200);
+    assert_eq!(tree.range(key(1000)..).unwrap().count(), 0);
+    assert_eq!(tree.range(key(13)..key(14)).unwrap().count(), 0);
+    assert_eq!(Tree::<Bytes, Bytes>::new().range(..).unwrap().count(), 0);
+}
+
+#[cfg(feature = "stream")]
+mod stream {
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake};
+    use std::thread::{self, Thread};
+    use std::time::{Duration, Instant};
+
+    use futures_core::Stream;
+
+    use super::*;
+
+    /// Wakes the test thread, which parks while the stream is pending.
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Polls `stream` to its end, blocking the thread while it is pending.
+    fn collect<S: Stream + Unpin>(mut stream: S) -> Vec<S::Item> {
+        let waker = Arc::new(Unpark(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        let mut items = Vec::new();
+        loop {
+            match Pin::new(&mut stream).poll_next(&mut cx) {
+                Poll::Ready(Some(item)) => items.push(item),
+                Poll::Ready(None) => return items,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn streams_ranges() {
+        let tree = (0..1000).fold(Tree::new(), |tree, i| tree.insert(key(i), vec![1]).unwrap());
+        for prefetch in [1, 4, 2000] {
+            let streamed = collect(tree.range_stream(.., prefetch));
+            assert_eq!(keys(streamed.into_iter()), (0..1000).collect::<Vec<_>>());
+        }
+        let streamed = collect(evens().range_stream(key(11)..=key(20), 2));
+        assert_eq!(keys(streamed.into_iter()), [12, 14, 16, 18, 20]);
+        assert!(collect(evens().range_stream(key(500).., 2)).is_empty());
+    }
+
+    #[test]
+    fn stops_when_dropped() {
+        let tree = (0..1000).fold(Tree::new(), |tree, i| tree.insert(key(i), vec![1]).unwrap());
+        let managers = Arc::strong_count(tree.manager());
+        let stream = tree.range_stream(.., 2);
+        // The worker holds the tree while it is blocked on the full queue,
+        // and lets go of it once the stream is dropped.
+        thread::sleep(Duration::from_millis(10));
+        assert!(Arc::strong_count(tree.manager()) > managers);
+        drop(stream);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Arc::strong_count(tree.manager()) > managers {
+            assert!(Instant::now() < deadline, "the worker didn't stop");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! A reference model of the trees, written for clarity rather than speed:
+//! a sorted `Vec` of entries whose hashes are recomputed from scratch, with
+//! SHA-256 applied directly, from the hashing schemes the trees document.
+//!
+//! An AVL root depends on the shape the operations left the tree in, which
+//! the model doesn't track, so [`Model::avl_root`] hashes the entries laid
+//! out in a given [`Shape`]. A radix tree's shape follows from its keys, so
+//! [`Model::art_root`] derives it.
+
+use std::ops::RangeBounds;
+
+use rhizome_trees::tree::avl::node::EntryHash;
+use rhizome_trees::tree::avl::proof::{Proof, Side};
+use rhizome_trees::tree::hash::{Digest, HashVersion};
+use sha2::{Digest as _, Sha256};
+
+pub type Bytes = Vec<u8>;
+
+const EMPTY: Digest = [0; 32];
+
+fn sha256(parts: &[&[u8]]) -> Digest {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// The shape of a binary tree without its entries, which take the nodes in
+/// key order.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Shape {
+    Empty,
+    Node(Box<Shape>, Box<Shape>),
+}
+
+impl Shape {
+    pub fn size(&self) -> usize {
+        match self {
+            Shape::Empty => 0,
+            Shape::Node(left, right) => left.size() + 1 + right.size(),
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match self {
+            Shape::Empty => 0,
+            Shape::Node(left, right) => 1 + left.height().max(right.height()),
+        }
+    }
+
+    /// Whether the heights of the children of every node differ by at most
+    /// one.
+    pub fn is_balanced(&self) -> bool {
+        match self {
+            Shape::Empty => true,
+            Shape::Node(left, right) => {
+                left.height().abs_diff(right.height()) <= 1
+                    && left.is_balanced()
+                    && right.is_balanced()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Model {
+    entries: Vec<(Bytes, Bytes)>,
+}
+
+impl Model {
+    pub fn entries(&self) -> &[(Bytes, Bytes)] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn insert(&mut self, key: Bytes, value: Bytes) {
+        match self.entries.binary_search_by(|(k, _)| k.cmp(&key)) {
+            Ok(i) => self.entries[i].1 = value,
+            Err(i) => self.entries.insert(i, (key, value)),
+        }
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.entries.retain(|(k, _)| k != key);
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&Bytes> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn range(&self, range: impl RangeBounds<Bytes>) -> Vec<(Bytes, Bytes)> {
+        self.entries
+            .iter()
+            .filter(|(k, _)| range.contains(k))
+            .cloned()
+            .collect()
+    }
+
+    /// Counts the keys less than `key`.
+    pub fn rank(&self, key: &[u8]) -> usize {
+        self.entries
+            .iter()
+            .filter(|(k, _)| k.as_slice() < key)
+            .count()
+    }
+
+    pub fn nth(&self, index: usize) -> Option<&(Bytes, Bytes)> {
+        self.entries.get(index)
+    }
+
+    /// Returns the entry with the smallest key greater than `key`.
+    pub fn successor(&self, key: &[u8]) -> Option<&(Bytes, Bytes)> {
+        self.entries.iter().find(|(k, _)| k.as_slice() > key)
+    }
+
+    /// Returns the entry with the largest key less than `key`.
+    pub fn predecessor(&self, key: &[u8]) -> Option<&(Bytes, Bytes)> {
+        self.entries.iter().rev().find(|(k, _)| k.as_slice() < key)
+    }
+
+    /// Hashes the entries laid out in `shape`, which must have a node per
+    /// entry, with [`HashVersion::V1`]:
+    ///
+    /// - an entry hashes as `SHA-256(0x00 || 0x20 || SHA-256(key) || 0x20 ||
+    ///   SHA-256(value))`,
+    /// - a node as `SHA-256(0x01 || left || entry || right)`,
+    /// - a missing child as 32 zero bytes.
+    pub fn avl_root(&self, shape: &Shape) -> Digest {
+        assert_eq!(
+            shape.size(),
+            self.len(),
+            "the shape doesn't fit the entries"
+        );
+        avl_hash(shape, &self.entries)
+    }
+
+    /// Recomputes the root hash a [`Proof`] of `key` commits to with the
+    /// hashing of [`Model::avl_root`], or `None` if the model doesn't hold
+    /// `key`.
+    pub fn avl_proof_root(&self, key: &[u8], proof: &Proof) -> Option<Digest> {
+        assert_eq!(proof.hash_version, HashVersion::V1);
+        let value = self.get(key)?;
+        let node = avl_node(&proof.left, &avl_entry(key, value), &proof.right);
+        Some(proof.path.iter().fold(node, |child, step| {
+            let EntryHash::Leaf(entry) = step.entry else {
+                panic!("a V1 proof has a split entry hash");
+            };
+            match step.side {
+                Side::Left => avl_node(&child, &entry, &step.sibling),
+                Side::Right => avl_node(&step.sibling, &entry, &child),
+            }
+        }))
+    }
+
+    /// Hashes the entries as the radix tree holding them:
+    ///
+    /// - a single entry is a leaf, hashing as `SHA-256(0x00 || SHA-256(key)
+    ///   || SHA-256(value))`,
+    /// - several entries sharing the key bytes below their parent's branch
+    ///   are an inner node with those bytes as its prefix, hashing as
+    ///   `SHA-256(0x01 || len(prefix) as a big-endian u32 || prefix || leaf
+    ///   || children)`, where `leaf` is the hash of the entry whose key ends
+    ///   at the node or 32 zero bytes, and `children` is the next key byte of
+    ///   each group of the other entries followed by the group's hash, in
+    ///   byte order,
+    /// - no entries as 32 zero bytes.
+    pub fn art_root(&self) -> Digest {
+        if self.entries.is_empty() {
+            return EMPTY;
+        }
+        art_hash(&self.entries, 0)
+    }
+}
+
+fn avl_entry(key: &[u8], value: &[u8]) -> Digest {
+    sha256(&[&[0x00, 0x20], &sha256(&[key]), &[0x20], &sha256(&[value])])
+}
+
+fn avl_node(left: &Digest, entry: &Digest, right: &Digest) -> Digest {
+    sha256(&[&[0x01], left, entry, right])
+}
+
+fn avl_hash(shape: &Shape, entries: &[(Bytes, Bytes)]) -> Digest {
+    let Shape::Node(left, right) = shape else {
+        return EMPTY;
+    };
+    let (before, rest) = entries.split_at(left.size());
+    let ((key, value), after) = rest.split_first().expect("the shape fits the entries");
+    avl_node(
+        &avl_hash(left, before),
+        &avl_entry(key, value),
+        &avl_hash(right, after),
+    )
+}
+
+/// Hashes `entries`, which are sorted and share their first `depth` bytes.
+fn art_hash(entries: &[(Bytes, Bytes)], depth: usize) -> Digest {
+    if let [(key, value)] = entries {
+        return sha256(&[&[0x00], &sha256(&[key]), &sha256(&[value])]);
+    }
+    let (first, last) = (&entries[0].0, &entries[entries.len() - 1].0);
+    let shared = first[depth..]
+        .iter()
+        .zip(&last[depth..])
+        .take_while(|(a, b)| a == b)
+        .count();
+    let end = depth + shared;
+    let mut buf = vec![0x01];
+    buf.extend_from_slice(&(shared as u32).to_be_bytes());
+    buf.extend_from_slice(&first[depth..end]);
+    // Only the smallest key can end at the node.
+    let (leaf, children) = match entries.split_first() {
+        Some((entry, rest)) if entry.0.len() == end => {
+            (art_hash(std::slice::from_ref(entry), end), rest)
+        }
+        _ => (EMPTY, entries),
+    };
+    buf.extend_from_slice(&leaf);
+    for group in children.chunk_by(|(a, _), (b, _)| a[end] == b[end]) {
+        buf.push(group[0].0[end]);
+        buf.extend_from_slice(&art_hash(group, end + 1));
+    }
+    sha256(&[&buf])
+}
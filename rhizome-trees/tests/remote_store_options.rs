@@ -0,0 +1,199 @@
+//! Checks that a remote node store retries transient failures, keeps to its
+//! deadline and answers stale reads only within the allowed staleness.
+#![cfg(feature = "grpc")]
+
+use std::io;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rhizome_trees::tree::node_manager::{
+    MemNodeStore, NodeStore, NodeStoreServer, Ptr, ReadConsistency, RemoteNodeStore, RetryPolicy,
+};
+use rhizome_trees::{Error, Result};
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+
+type Bytes = Vec<u8>;
+
+/// A store whose reads and inserts fail with an I/O error while `failures`
+/// lasts, and whose reads take `delay`.
+#[derive(Default)]
+struct Flaky {
+    nodes: MemNodeStore<Bytes>,
+    failures: AtomicU32,
+    calls: AtomicU32,
+    delay: Duration,
+}
+
+impl Flaky {
+    fn call(&self) -> Result<()> {
+        self.calls.fetch_add(1, Relaxed);
+        match self
+            .failures
+            .fetch_update(Relaxed, Relaxed, |left| left.checked_sub(1))
+        {
+            Ok(_) => Err(io::Error::other("disk unavailable").into()),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+impl NodeStore<Bytes> for Flaky {
+    fn read(&self, ptr: &Ptr) -> Result<Bytes> {
+        self.call()?;
+        thread::sleep(self.delay);
+        self.nodes.read(ptr)
+    }
+
+    fn insert(&self, node: &Bytes) -> Result<Ptr> {
+        self.call()?;
+        self.nodes.insert(node)
+    }
+
+    fn inc_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        self.nodes.inc_ref_count(ptr)
+    }
+
+    fn dec_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        self.nodes.dec_ref_count(ptr)
+    }
+
+    fn delete(&self, ptr: &Ptr) -> Result<()> {
+        self.nodes.delete(ptr)
+    }
+}
+
+/// Serves `store` on a free local port, until the returned runtime is
+/// dropped.
+fn serve<S: NodeStore<Bytes> + 'static>(store: Arc<S>) -> (Runtime, String) {
+    let runtime = Runtime::new().unwrap();
+    let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+    runtime.spawn(
+        Server::builder()
+            .add_service(NodeStoreServer::from_arc(store))
+            .serve_with_incoming(incoming),
+    );
+    (runtime, endpoint)
+}
+
+fn quick_retries() -> RetryPolicy {
+    RetryPolicy::default()
+        .max_attempts(3)
+        .backoff(Duration::from_millis(1), Duration::from_millis(5))
+}
+
+#[test]
+fn retries_transient_failures() {
+    let store = Arc::new(Flaky::default());
+    let ptr = store.nodes.insert(&vec![1]).unwrap();
+    let (_server, endpoint) = serve(store.clone());
+    let remote = RemoteNodeStore::builder(&endpoint)
+        .retry(quick_retries())
+        .connect()
+        .unwrap();
+
+    store.failures.store(2, Relaxed);
+    assert_eq!(remote.read(&ptr).unwrap(), vec![1]);
+    assert_eq!(store.calls.swap(0, Relaxed), 3);
+
+    // The attempts run out.
+    store.failures.store(3, Relaxed);
+    let err = remote.read(&ptr).unwrap_err();
+    assert!(matches!(err, Error::Storage { .. }), "{}", err);
+    assert_eq!(store.calls.swap(0, Relaxed), 3);
+
+    let once = RemoteNodeStore::builder(&endpoint)
+        .retry(RetryPolicy::never())
+        .connect()
+        .unwrap();
+    store.failures.store(1, Relaxed);
+    assert!(once.read(&ptr).is_err());
+    assert_eq!(store.calls.swap(0, Relaxed), 1);
+}
+
+#[test]
+fn does_not_retry_sent_writes() {
+    let store = Arc::new(Flaky::default());
+    let (_server, endpoint) = serve(store.clone());
+    let remote = RemoteNodeStore::builder(endpoint)
+        .retry(quick_retries())
+        .connect()
+        .unwrap();
+
+    // The server may have applied a failed write, so it isn't repeated.
+    store.failures.store(1, Relaxed);
+    assert!(remote.insert(&vec![1]).is_err());
+    assert_eq!(store.calls.load(Relaxed), 1);
+    assert_eq!(store.nodes.len(), 0);
+}
+
+#[test]
+fn gives_up_at_the_deadline() {
+    let store = Arc::new(Flaky {
+        delay: Duration::from_secs(1),
+        ..Flaky::default()
+    });
+    let ptr = store.nodes.insert(&vec![1]).unwrap();
+    let (_server, endpoint) = serve(store.clone());
+    let remote = RemoteNodeStore::builder(endpoint)
+        .retry(quick_retries())
+        .timeout(Duration::from_millis(100))
+        .connect()
+        .unwrap();
+
+    let start = Instant::now();
+    let err = remote.read(&ptr).unwrap_err();
+    assert!(start.elapsed() < Duration::from_millis(900));
+    assert!(matches!(err, Error::Storage { .. }), "{}", err);
+    // A timed out attempt isn't retried past the deadline.
+    assert_eq!(store.calls.load(Relaxed), 1);
+}
+
+#[test]
+fn answers_stale_reads_within_the_staleness() {
+    let local = Arc::new(MemNodeStore::new());
+    let (_server, endpoint) = serve(local.clone());
+    let strong = RemoteNodeStore::connect(&endpoint).unwrap();
+    let stale = RemoteNodeStore::builder(&endpoint)
+        .read_consistency(ReadConsistency::MaxStaleness(Duration::from_secs(3600)))
+        .connect()
+        .unwrap();
+
+    // Nodes deleted by another client are still read, along with the
+    // client's own writes.
+    let read = strong.insert(&vec![1]).unwrap();
+    assert_eq!(stale.read(&read).unwrap(), vec![1]);
+    let written = stale.insert(&vec![2]).unwrap();
+    strong.delete(&read).unwrap();
+    strong.delete(&written).unwrap();
+    assert!(matches!(strong.read(&read), Err(Error::NotFound(_))));
+    assert_eq!(
+        stale.read_many(&[written, read]).unwrap(),
+        vec![vec![2], vec![1]]
+    );
+
+    strong.set_header(b"first").unwrap();
+    assert_eq!(stale.header().unwrap(), Some(b"first".to_vec()));
+    strong.set_header(b"second").unwrap();
+    assert_eq!(stale.header().unwrap(), Some(b"first".to_vec()));
+    stale.set_header(b"third").unwrap();
+    assert_eq!(stale.header().unwrap(), Some(b"third".to_vec()));
+
+    // Past the staleness, reads go to the server again.
+    let brief = RemoteNodeStore::builder(&endpoint)
+        .read_consistency(ReadConsistency::MaxStaleness(Duration::from_millis(20)))
+        .connect()
+        .unwrap();
+    let ptr = strong.insert(&vec![3]).unwrap();
+    assert_eq!(brief.read(&ptr).unwrap(), vec![3]);
+    strong.delete(&ptr).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    assert!(matches!(brief.read(&ptr), Err(Error::NotFound(_))));
+}
@@ -0,0 +1,112 @@
+//! Checks that AVL nodes persist through the rkyv codec, and that views read
+//! stored nodes in place as the tree reads them.
+
+#![cfg(feature = "rkyv")]
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::rkyv::{NodeView, RkyvCodec, ViewReader};
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::{
+    CachePolicy, EncodedStore, MemNodeStore, NodeCodec, NodeManager, NodeStore,
+};
+use rhizome_trees::Error;
+
+type Bytes = Vec<u8>;
+
+/// A tree whose nodes are archived into `bytes`, and decoded on every read.
+fn rkyv_tree(bytes: &Arc<MemNodeStore<Bytes>>) -> Tree<Bytes, Bytes> {
+    Tree::with_manager(Arc::new(
+        NodeManager::builder(EncodedStore::new(bytes.clone(), RkyvCodec::new()))
+            .cache_policy(CachePolicy::Disabled)
+            .build(),
+    ))
+}
+
+fn key(i: u32) -> Bytes {
+    i.to_be_bytes().to_vec()
+}
+
+#[test]
+fn round_trips_trees() {
+    let bytes = Arc::new(MemNodeStore::new());
+    let tree = (0..50).fold(rkyv_tree(&bytes), |tree, i| {
+        tree.insert(key(i), vec![i as u8; 3]).unwrap()
+    });
+    let hash = tree.merkle_hash().unwrap();
+    let tree = tree.save().unwrap();
+    assert_eq!(tree.merkle_hash().unwrap(), hash);
+    assert_eq!(tree.verify().unwrap(), hash);
+    assert_eq!(tree.get(&key(9)).unwrap(), Some(vec![9; 3]));
+    let updated = tree.insert(key(1000), vec![]).unwrap().save().unwrap();
+    assert_eq!(updated.len().unwrap(), 51);
+
+    let raw = bytes.read(&tree.root_ptr().unwrap()).unwrap();
+    let codec = RkyvCodec::<Bytes, Bytes>::new();
+    assert_eq!(codec.to_bytes(&codec.decode(&raw).unwrap()).unwrap(), raw);
+    // Bytes which aren't aligned are decoded all the same.
+    let mut shifted = vec![0];
+    shifted.extend_from_slice(&raw);
+    assert_eq!(
+        codec
+            .to_bytes(&codec.decode(&shifted[1..]).unwrap())
+            .unwrap(),
+        raw
+    );
+}
+
+#[test]
+fn reads_views_in_place() {
+    let bytes = Arc::new(MemNodeStore::new());
+    let tree = (0..100)
+        .step_by(2)
+        .fold(rkyv_tree(&bytes), |tree, i| {
+            tree.insert(key(i), vec![i as u8]).unwrap()
+        })
+        .save()
+        .unwrap();
+    let root = tree.root_ptr().unwrap();
+    let mut reader = ViewReader::new(&*bytes);
+
+    let view = reader.read::<Bytes, Bytes>(&root).unwrap();
+    let node = tree.read(tree.root().unwrap()).unwrap();
+    assert_eq!(view.key().as_slice(), &node.key()[..]);
+    assert_eq!(view.to_value().unwrap(), *node.value());
+    assert_eq!(view.size(), 50);
+    assert_eq!(view.height(), node.height());
+    assert_eq!(*view.hash(), tree.merkle_hash().unwrap());
+    assert!(view.left().unwrap().is_some() && view.right().unwrap().is_some());
+
+    for i in 0..100 {
+        let found = reader.get::<Bytes, Bytes, [u8]>(&root, &key(i)).unwrap();
+        assert_eq!(found, tree.get(&key(i)).unwrap(), "{}", i);
+    }
+}
+
+#[test]
+fn rejects_damaged_archives() {
+    let bytes = Arc::new(MemNodeStore::new());
+    let tree = rkyv_tree(&bytes)
+        .insert(vec![1], vec![2])
+        .unwrap()
+        .save()
+        .unwrap();
+    let ptr = tree.root_ptr().unwrap();
+    let raw = bytes.read(&ptr).unwrap();
+    let codec = RkyvCodec::<Bytes, Bytes>::new();
+    assert!(codec.decode(&raw).is_ok());
+    assert!(codec.decode(&raw[..raw.len() - 1]).is_err());
+    assert!(NodeView::<Bytes, Bytes>::new(&[]).is_err());
+
+    // Relative pointers reaching past the archive.
+    let damaged = vec![0xff; raw.len()];
+    assert!(codec.decode(&damaged).is_err());
+    let bytes = Arc::new(MemNodeStore::new());
+    let ptr = bytes.insert(&damaged).unwrap();
+    let err = ViewReader::new(&*bytes)
+        .read::<Bytes, Bytes>(&ptr)
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::Corrupt { .. }), "{}", err);
+}
@@ -0,0 +1,14 @@
+//! A small deterministic xorshift generator for randomized tests, so that
+//! failures reproduce from the seed.
+
+pub struct Rng(pub u64);
+
+impl Rng {
+    /// Returns the next number, from a seed which must not be zero.
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
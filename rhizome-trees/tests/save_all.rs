@@ -0,0 +1,73 @@
+//! Checks that trees saved together are written in a single batch, which
+//! stores all of them or none.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::{MemNodeStore, NodeManager};
+
+type Bytes = Vec<u8>;
+
+fn tree_of(manager: &Arc<NodeManager<Node<Bytes, Bytes>>>, keys: &[u8]) -> Tree<Bytes, Bytes> {
+    let mut tree = Tree::with_manager(manager.clone());
+    for &key in keys {
+        tree = tree.insert(vec![key], vec![key]).unwrap();
+    }
+    tree
+}
+
+#[test]
+fn save_all_matches_separate_saves() {
+    let store = Arc::new(MemNodeStore::new());
+    let manager = Arc::new(NodeManager::new(store.clone()));
+    let base = tree_of(&manager, &[1, 2, 3]).save().unwrap();
+    let trees = [
+        base.insert(vec![4], vec![4]).unwrap(),
+        base.clone(),
+        tree_of(&manager, &[]),
+        tree_of(&manager, &[5, 6]),
+    ];
+
+    let saved = Tree::save_all(&trees).unwrap();
+    assert_eq!(saved.len(), trees.len());
+    for (tree, saved) in trees.iter().zip(&saved) {
+        assert_eq!(saved.merkle_hash().unwrap(), tree.merkle_hash().unwrap());
+        let reloaded = match saved.root_ptr() {
+            Some(root) => Tree::load(manager.clone(), root),
+            None => Tree::with_manager(manager.clone()),
+        };
+        assert_eq!(reloaded.merkle_hash().unwrap(), tree.merkle_hash().unwrap());
+    }
+    // Each saved tree holds its own reference on its root.
+    for saved in &saved {
+        saved.free_version().unwrap();
+    }
+    base.free_version().unwrap();
+    assert_eq!(store.len(), 0);
+    assert!(Tree::<Bytes, Bytes>::save_all([]).unwrap().is_empty());
+}
+
+#[test]
+fn save_all_stores_nothing_if_any_tree_fails() {
+    let store = Arc::new(MemNodeStore::new());
+    let manager = Arc::new(NodeManager::new(store.clone()));
+    let released = tree_of(&manager, &[1]).save().unwrap();
+    released.free_version().unwrap();
+    assert_eq!(store.len(), 0);
+
+    // The released root is gone, so taking a reference on it fails the batch.
+    let trees = [tree_of(&manager, &[2, 3]), released];
+    assert!(Tree::save_all(&trees).is_err());
+    assert_eq!(store.len(), 0);
+}
+
+#[test]
+fn save_all_requires_one_manager() {
+    let trees = [
+        tree_of(&Arc::new(NodeManager::in_memory()), &[1]),
+        tree_of(&Arc::new(NodeManager::in_memory()), &[2]),
+    ];
+    assert!(Tree::save_all(&trees).is_err());
+}
@@ -0,0 +1,152 @@
+//! Checks that the scrubber follows the history of a versioned tree,
+//! verifies intact versions and reports nodes damaged in the store.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::scrub::{ScrubEvent, ScrubObserver, ScrubOptions, Scrubber};
+use rhizome_trees::tree::avl::versioned::{PruningPolicy, Version, VersionedTree};
+use rhizome_trees::tree::node_manager::NodeManager;
+
+type Bytes = Vec<u8>;
+
+#[derive(Default)]
+struct Recorder(Mutex<Vec<ScrubEvent>>);
+
+impl ScrubObserver for Recorder {
+    fn on_scrub_event(&self, event: &ScrubEvent) {
+        self.0.lock().unwrap().push(event.clone());
+    }
+}
+
+fn observed(options: ScrubOptions) -> (VersionedTree<Bytes, Bytes>, Arc<Scrubber<Bytes, Bytes>>) {
+    let manager = Arc::new(NodeManager::in_memory());
+    let scrubber = Arc::new(Scrubber::new(manager.clone(), options));
+    let mut tree = VersionedTree::new(manager);
+    tree.set_observer(scrubber.clone());
+    (tree, scrubber)
+}
+
+fn versions(numbers: impl IntoIterator<Item = u64>) -> Vec<Version> {
+    numbers.into_iter().map(Version::new).collect()
+}
+
+#[test]
+fn tracks_saved_versions() {
+    let (mut tree, scrubber) = observed(ScrubOptions::default());
+    assert_eq!(scrubber.scrub_once().unwrap(), None);
+    for i in 0..5u8 {
+        tree.insert(vec![i], vec![i]).unwrap();
+        tree.save().unwrap();
+    }
+    assert_eq!(scrubber.tracked().unwrap(), versions(1..=5));
+
+    tree.rollback_to(Version::new(4)).unwrap();
+    assert_eq!(scrubber.tracked().unwrap(), versions(1..=4));
+
+    tree.set_pruning(PruningPolicy::default().keep_recent(2));
+    tree.prune().unwrap();
+    assert_eq!(scrubber.tracked().unwrap(), versions(3..=4));
+}
+
+#[test]
+fn verifies_intact_versions() {
+    let (mut tree, scrubber) = observed(ScrubOptions::default().subtree_nodes(8));
+    for i in 0..100u8 {
+        tree.insert(vec![i], vec![i; 3]).unwrap();
+        if i % 10 == 9 {
+            tree.save().unwrap();
+        }
+    }
+    for _ in 0..50 {
+        match scrubber.scrub_once().unwrap() {
+            Some(ScrubEvent::Verified { version, nodes, .. }) => {
+                assert!(scrubber.tracked().unwrap().contains(&version));
+                assert!(nodes > 0);
+            }
+            other => panic!("unexpected outcome {:?}", other),
+        }
+    }
+    let stats = scrubber.stats();
+    assert_eq!(stats.subtrees, 50);
+    assert_eq!(stats.mismatches, 0);
+    // Every subtree is reached by a descent from the root, which reads
+    // more nodes than the subtree holds.
+    assert!(stats.nodes > 50 * 8);
+}
+
+#[test]
+fn reports_damaged_nodes() {
+    let manager = Arc::new(NodeManager::in_memory());
+    let recorder = Arc::new(Recorder::default());
+    let scrubber =
+        Scrubber::new(manager.clone(), ScrubOptions::default()).observer(recorder.clone());
+    let mut tree = VersionedTree::new(manager.clone());
+    for key in [b"a", b"b", b"c"] {
+        tree.insert(key.to_vec(), key.to_vec()).unwrap();
+    }
+    tree.save().unwrap();
+    scrubber.track(tree.versions().iter().copied()).unwrap();
+    let first = scrubber.scrub_once().unwrap();
+    assert!(
+        matches!(first, Some(ScrubEvent::Verified { nodes: 3, .. })),
+        "{:?}",
+        first
+    );
+
+    // Overwrite the left leaf with the right one, as if the store had
+    // returned the wrong bytes. Cached reads still see the right nodes.
+    let root = tree.versions()[0].root.unwrap();
+    let root_node: Node<Bytes, Bytes> = manager.store().read(&root).unwrap();
+    let left = root_node.left().unwrap().ptr().unwrap();
+    let right = root_node.right().unwrap().ptr().unwrap();
+    let right_node = manager.store().read(&right).unwrap();
+    assert!(manager.store().try_update(&[(left, right_node)]).unwrap());
+    assert_eq!(tree.get(b"a".as_slice()).unwrap(), Some(b"a".to_vec()));
+
+    let event = scrubber.scrub_once().unwrap().unwrap();
+    match &event {
+        ScrubEvent::Mismatch {
+            version,
+            root: subtree,
+            error,
+        } => {
+            assert_eq!(*version, Version::new(1));
+            assert_eq!(*subtree, root);
+            assert!(error.contains("stored hash"), "{}", error);
+        }
+        other => panic!("unexpected outcome {:?}", other),
+    }
+    assert_eq!(scrubber.stats().mismatches, 1);
+    assert_eq!(recorder.0.lock().unwrap().last(), Some(&event));
+}
+
+#[test]
+fn background_thread_scrubs_until_stopped() {
+    let (mut tree, scrubber) = observed(ScrubOptions::default().nodes_per_second(100_000));
+    for i in 0..20u8 {
+        tree.insert(vec![i], vec![i]).unwrap();
+    }
+    tree.save().unwrap();
+
+    let handle = scrubber.spawn();
+    let started = Instant::now();
+    while scrubber.stats().subtrees < 3 {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "scrubber stalled"
+        );
+        thread::sleep(Duration::from_millis(5));
+    }
+    // Saving and pruning wait for the subtree being scrubbed.
+    for i in 20..40u8 {
+        tree.insert(vec![i], vec![i]).unwrap();
+        tree.save().unwrap();
+    }
+    tree.set_pruning(PruningPolicy::default().keep_recent(1));
+    tree.prune().unwrap();
+    handle.stop().unwrap();
+    assert_eq!(scrubber.stats().mismatches, 0);
+}
@@ -0,0 +1,72 @@
+//! Checks that managers of different node types can share one cache, within
+//! its capacity, without confusing nodes of different stores.
+
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{MemNodeStore, NodeManager, SharedCache};
+use rhizome_trees::tree::value::BytesValue;
+
+type Bytes = Vec<u8>;
+
+fn key(n: u64) -> Bytes {
+    n.to_be_bytes().to_vec()
+}
+
+fn shared<N: Clone + Send + Sync + 'static>(cache: &SharedCache) -> Arc<NodeManager<N>> {
+    Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .shared_cache(cache.clone())
+            .metrics(true)
+            .build(),
+    )
+}
+
+#[test]
+fn shares_one_budget() {
+    let cache = SharedCache::new(NonZeroUsize::new(50).unwrap());
+    assert_eq!(cache.capacity(), 50);
+    assert!(cache.is_empty());
+    let bytes = shared(&cache);
+    let set = shared(&cache);
+    let (mut a, mut b) = (Tree::with_manager(bytes.clone()), Tree::with_manager(set));
+    for i in 0..40 {
+        a = a.insert(key(i), vec![1]).unwrap();
+        b = b.insert(BytesValue(key(i)), ()).unwrap();
+    }
+    let (a, b) = (a.save().unwrap(), b.save().unwrap());
+    // 80 nodes were saved between the two trees.
+    assert_eq!(cache.len(), 50);
+
+    for i in 0..40 {
+        assert_eq!(a.get(&key(i)).unwrap(), Some(vec![1]));
+        assert_eq!(b.get(&BytesValue(key(i))).unwrap(), Some(()));
+    }
+    let stats = bytes.stats().unwrap();
+    assert!(stats.cache_hits > 0);
+    assert!(stats.cache_misses > 0);
+    assert_eq!(cache.len(), 50);
+}
+
+#[test]
+fn keeps_stores_apart() {
+    let cache = SharedCache::new(NonZeroUsize::new(1000).unwrap());
+    let trees: Vec<Tree<Bytes, Bytes>> = (0..2u8)
+        .map(|value| {
+            (0..20)
+                .fold(Tree::with_manager(shared(&cache)), |tree, i| {
+                    tree.insert(key(i), vec![value]).unwrap()
+                })
+                .save()
+                .unwrap()
+        })
+        .collect();
+    // Both stores hand out the same pointers.
+    assert_eq!(trees[0].root_ptr(), trees[1].root_ptr());
+    for (value, tree) in trees.iter().enumerate() {
+        for i in 0..20 {
+            assert_eq!(tree.get(&key(i)).unwrap(), Some(vec![value as u8]));
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Checks that snapshots are deterministic, import into the exact version
+//! they were exported from, and are rejected without leaving nodes behind
+//! when damaged or hashed differently.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::{HashVersion, MerkleTree};
+use rhizome_trees::tree::node_manager::{MemNodeStore, NodeManager};
+
+type Bytes = Vec<u8>;
+type Store = MemNodeStore<Node<Bytes, Bytes>>;
+
+fn filled(entries: u32) -> Tree<Bytes, Bytes> {
+    let manager = Arc::new(NodeManager::in_memory());
+    (0..entries).fold(Tree::with_manager(manager), |tree, i| {
+        let key = (i * 7919 % 100_003).to_be_bytes().to_vec();
+        tree.insert(key, vec![i as u8; (i % 50) as usize]).unwrap()
+    })
+}
+
+fn export(tree: &Tree<Bytes, Bytes>) -> Vec<u8> {
+    let mut snapshot = Vec::new();
+    tree.export_snapshot(&mut snapshot).unwrap();
+    snapshot
+}
+
+/// Imports `snapshot` into an empty store, returning the store along with
+/// the result.
+fn import(snapshot: &[u8]) -> (rhizome_trees::Result<Tree<Bytes, Bytes>>, Arc<Store>) {
+    let store = Arc::new(MemNodeStore::new());
+    let manager = Arc::new(NodeManager::new(store.clone()));
+    (Tree::import_snapshot(manager, snapshot), store)
+}
+
+#[test]
+fn round_trips_versions() {
+    for entries in [0, 1, 2, 100, 20_000] {
+        let tree = filled(entries);
+        let tree = if entries == 100 {
+            tree.save().unwrap()
+        } else {
+            tree
+        };
+        let mut snapshot = Vec::new();
+        assert_eq!(tree.export_snapshot(&mut snapshot).unwrap(), entries as u64);
+        assert!(snapshot.starts_with(b"RHZSNAP"));
+        assert_eq!(export(&tree), snapshot);
+
+        let (imported, store) = import(&snapshot);
+        let imported = imported.unwrap();
+        let root = tree.merkle_hash().unwrap();
+        assert_eq!(imported.verify().unwrap(), root);
+        assert_eq!(imported.merkle_hash().unwrap(), root);
+        assert_eq!(store.len(), entries as usize);
+        // The shape is rebuilt too, so the import exports the same bytes.
+        assert_eq!(export(&imported), snapshot);
+    }
+}
+
+#[test]
+fn rejects_damaged_snapshots() {
+    let snapshot = export(&filled(100));
+    let len = snapshot.len();
+
+    let mut flipped = snapshot.clone();
+    flipped[len - 10] ^= 1;
+    let (imported, store) = import(&flipped);
+    assert!(imported.is_err());
+    assert!(store.is_empty());
+
+    let (imported, store) = import(&snapshot[..len - 3]);
+    assert!(imported.is_err());
+    assert!(store.is_empty());
+
+    let (imported, store) = import(&snapshot[..5]);
+    assert!(imported.is_err());
+    assert!(store.is_empty());
+}
+
+#[test]
+fn rejects_other_hash_versions() {
+    let snapshot = export(&filled(10));
+    let manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .hash_version(HashVersion::V0)
+            .build(),
+    );
+    assert!(Tree::<Bytes, Bytes>::import_snapshot(manager, &snapshot[..]).is_err());
+}
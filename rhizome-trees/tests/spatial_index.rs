@@ -0,0 +1,93 @@
+//! Checks that bounding box queries of the spatial index find exactly the
+//! points inside the box, whatever the number of prefix scans they are
+//! decomposed into, and the geohashes of points.
+
+mod rng;
+
+use rhizome_trees::tree::art;
+use rhizome_trees::tree::spatial::{BoundingBox, Point, SpatialIndex};
+use rng::Rng;
+
+/// Returns a number in `[low, high)`.
+fn between(rng: &mut Rng, low: f64, high: f64) -> f64 {
+    low + (rng.next() % 1_000_000) as f64 / 1_000_000.0 * (high - low)
+}
+
+fn random_point(rng: &mut Rng) -> Point {
+    Point::new(between(rng, -90.0, 90.0), between(rng, -180.0, 180.0)).unwrap()
+}
+
+#[test]
+fn encodes_geohashes() {
+    let point = Point::new(57.64911, 10.40744).unwrap();
+    assert_eq!(point.geohash(11), "u4pruydqqvj");
+    assert_eq!(point.geohash(3), "u4p");
+    assert!(Point::new(91.0, 0.0).is_err());
+    assert!(Point::new(0.0, -181.0).is_err());
+}
+
+#[test]
+fn finds_the_points_in_a_box() {
+    let mut rng = Rng(3);
+    let mut index = SpatialIndex::new();
+    let mut points = Vec::new();
+    for i in 0..5000u32 {
+        let point = random_point(&mut rng);
+        index = index.insert(point, &i.to_be_bytes(), i);
+        points.push((point, i));
+    }
+    for _ in 0..50 {
+        let (a, b) = (random_point(&mut rng), random_point(&mut rng));
+        let bbox = BoundingBox::new(
+            Point::new(a.lat().min(b.lat()), a.lon().min(b.lon())).unwrap(),
+            Point::new(a.lat().max(b.lat()), a.lon().max(b.lon())).unwrap(),
+        )
+        .unwrap();
+        let mut expected: Vec<u32> = points
+            .iter()
+            .filter(|(point, _)| bbox.contains(point))
+            .map(|&(_, i)| i)
+            .collect();
+        expected.sort();
+        for levels in [0, 3, 10] {
+            let mut found: Vec<u32> = index
+                .query_with_levels(&bbox, levels)
+                .into_iter()
+                .map(|(_, _, &i)| i)
+                .collect();
+            found.sort();
+            assert_eq!(found, expected, "with {} levels", levels);
+        }
+    }
+}
+
+#[test]
+fn removes_points_by_id() {
+    let point = Point::new(1.0, 2.0).unwrap();
+    let index = SpatialIndex::new()
+        .insert(point, b"a", 1)
+        .insert(point, b"b", 2);
+    assert_eq!(index.get(&point, b"a"), Some(&1));
+    let removed = index.remove(&point, b"a");
+    assert_eq!(removed.get(&point, b"a"), None);
+    assert_eq!(removed.get(&point, b"b"), Some(&2));
+    assert!(removed.remove(&point, b"b").is_empty());
+}
+
+#[test]
+fn scans_radix_tree_prefixes() {
+    let tree = art::Tree::new()
+        .insert("ab", 1)
+        .insert("abc", 2)
+        .insert("abd", 3)
+        .insert("b", 4)
+        .insert("a", 5);
+    let values: Vec<_> = tree
+        .scan_prefix("ab")
+        .into_iter()
+        .map(|(_, &value)| value)
+        .collect();
+    assert_eq!(values, vec![1, 2, 3]);
+    assert_eq!(tree.scan_prefix("").len(), 5);
+    assert!(tree.scan_prefix("abcd").is_empty());
+}
@@ -0,0 +1,211 @@
+//! Checks that every node store implements the reference counting contract
+//! of `NodeStore` the same way, so garbage collection can rely on it.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use rhizome_trees::tree::hash::{Hashable, Update};
+use rhizome_trees::tree::node_manager::{
+    ContentAddressedStore, EncodedStore, FileNodeStore, MemNodeStore, NodeCodec, NodeMeta,
+    NodeStore, Ptr, TreeNode,
+};
+use rhizome_trees::{Error, Result};
+
+/// A childless node for stores which need to hash their nodes.
+#[derive(Clone, PartialEq, Debug)]
+struct Leaf(Vec<u8>);
+
+impl Hashable for Leaf {
+    fn update_hash<H: Update>(&self, hasher: &mut H) {
+        hasher.update(&self.0);
+    }
+}
+
+impl TreeNode for Leaf {
+    fn stored_children(&self) -> Vec<Ptr> {
+        Vec::new()
+    }
+}
+
+/// Encodes a node of a single byte as itself.
+struct ByteCodec;
+
+impl NodeCodec<u8> for ByteCodec {
+    fn encode(&self, node: &u8, buf: &mut Vec<u8>) -> Result<()> {
+        buf.push(*node);
+        Ok(())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<u8> {
+        match bytes {
+            [byte] => Ok(*byte),
+            _ => Err(Error::Codec(format!("{} bytes aren't a node", bytes.len()))),
+        }
+    }
+}
+
+fn store_error(result: Result<impl Debug>) -> Error {
+    result.expect_err("operation should fail")
+}
+
+/// Runs the contract checks against `store`, creating distinct nodes with
+/// `node`.
+fn check_contract<N: PartialEq + Debug>(store: &dyn NodeStore<N>, node: impl Fn(u8) -> N) {
+    let ptr = store.insert(&node(1)).unwrap();
+    assert_eq!(store.read(&ptr).unwrap(), node(1));
+
+    // A new node starts with a single reference.
+    assert_eq!(store.inc_ref_count(&ptr).unwrap(), 2);
+    assert_eq!(store.dec_ref_count(&ptr).unwrap(), 1);
+    assert_eq!(store.dec_ref_count(&ptr).unwrap(), 0);
+
+    // Releasing a reference twice fails and leaves the count at 0.
+    assert!(matches!(
+        store_error(store.dec_ref_count(&ptr)),
+        Error::RefCountUnderflow(p) if p == ptr
+    ));
+    assert_eq!(store.inc_ref_count(&ptr).unwrap(), 1);
+    // Stores which can tell a count without changing it report the same.
+    if let Some(count) = store.ref_count(&ptr).unwrap() {
+        assert_eq!(count, 1);
+    }
+    assert_eq!(store.dec_ref_count(&ptr).unwrap(), 0);
+
+    // Deleted nodes are gone for every operation.
+    store.delete(&ptr).unwrap();
+    assert!(matches!(
+        store_error(store.read(&ptr)),
+        Error::NotFound(p) if p == ptr
+    ));
+    assert!(matches!(
+        store_error(store.inc_ref_count(&ptr)),
+        Error::NotFound(p) if p == ptr
+    ));
+    assert!(matches!(
+        store_error(store.dec_ref_count(&ptr)),
+        Error::NotFound(p) if p == ptr
+    ));
+    assert!(matches!(
+        store_error(store.delete(&ptr)),
+        Error::NotFound(p) if p == ptr
+    ));
+    assert!(!matches!(store.ref_count(&ptr), Ok(Some(_))));
+
+    // A batch which fails to commit applies none of its writes.
+    let kept = store.insert(&node(2)).unwrap();
+    let mut batch = store.begin_batch().unwrap();
+    let staged = batch.insert(&node(3)).unwrap();
+    batch.inc_ref_count(&kept).unwrap();
+    batch.inc_ref_count(&ptr).unwrap();
+    assert!(matches!(
+        store_error(batch.commit()),
+        Error::NotFound(p) if p == ptr
+    ));
+    assert!(store.read(&staged).is_err());
+    assert_eq!(store.dec_ref_count(&kept).unwrap(), 0);
+
+    // A committed batch applies all of them.
+    let mut batch = store.begin_batch().unwrap();
+    let staged = batch.insert(&node(3)).unwrap();
+    batch.inc_ref_count(&kept).unwrap();
+    batch.inc_ref_count(&staged).unwrap();
+    batch.set_version(7);
+    batch.commit().unwrap();
+    assert_eq!(store.read(&staged).unwrap(), node(3));
+
+    // Stores which record metadata give nodes the version of their batch.
+    if let Some(meta) = store.meta(&staged).unwrap() {
+        assert_eq!(meta.version, Some(7));
+        assert_eq!(store.meta(&kept).unwrap().unwrap().version, None);
+    }
+    assert_eq!(store.dec_ref_count(&staged).unwrap(), 1);
+    assert_eq!(store.dec_ref_count(&kept).unwrap(), 0);
+}
+
+#[test]
+fn mem_store() {
+    check_contract(&MemNodeStore::new(), |n| vec![n]);
+}
+
+#[test]
+fn content_addressed_store() {
+    check_contract(&ContentAddressedStore::new(), |n| Leaf(vec![n]));
+}
+
+#[test]
+fn encoded_store_reports_undecodable_nodes_as_corrupt() {
+    let bytes = Arc::new(MemNodeStore::new());
+    let store = EncodedStore::new(bytes.clone(), ByteCodec);
+    check_contract(&store, |n| n);
+
+    let ptr = bytes.insert(&vec![1, 2]).unwrap();
+    let err = store_error(store.read(&ptr));
+    assert!(err.is_corruption());
+    let Error::Corrupt {
+        ptr: corrupt,
+        source: Some(source),
+    } = err
+    else {
+        panic!("not a corrupt node: {}", err);
+    };
+    assert_eq!(corrupt, ptr);
+    assert!(matches!(*source, Error::Codec(_)));
+    // A missing node isn't mistaken for a corrupt one.
+    bytes.delete(&ptr).unwrap();
+    assert!(matches!(store_error(store.read(&ptr)), Error::NotFound(p) if p == ptr));
+}
+
+#[test]
+fn file_store() {
+    let path =
+        std::env::temp_dir().join(format!("rhizome-store-conformance-{}", std::process::id()));
+    let store = FileNodeStore::open(&path).unwrap();
+    check_contract(&store, |n| vec![n]);
+    let mut batch = store.begin_batch().unwrap();
+    let ptr = batch.insert(&vec![1, 2, 3]).unwrap();
+    batch.set_version(9);
+    batch.commit().unwrap();
+    drop(store);
+
+    // The metadata survives reopening the log.
+    let store = FileNodeStore::open(&path).unwrap();
+    let meta = NodeMeta {
+        version: Some(9),
+        size: Some(3),
+    };
+    assert_eq!(store.meta(&ptr).unwrap(), Some(meta));
+    drop(store);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "redb")]
+#[test]
+fn redb_store() {
+    use rhizome_trees::tree::node_manager::RedbNodeStore;
+
+    let path = std::env::temp_dir().join(format!("rhizome-store-redb-{}", std::process::id()));
+    let store = RedbNodeStore::open(&path).unwrap();
+    check_contract(&store, |n| vec![n]);
+    let mut batch = store.begin_batch().unwrap();
+    let ptr = batch.insert(&vec![1, 2, 3]).unwrap();
+    batch.set_version(9);
+    batch.commit().unwrap();
+    store.set_header(b"config").unwrap();
+    assert!(store.try_update(&[(ptr, vec![4, 5, 6])]).unwrap());
+    let len = store.len();
+    drop(store);
+
+    // Everything survives reopening the database, and ids aren't reused.
+    let store = RedbNodeStore::open(&path).unwrap();
+    let meta = NodeMeta {
+        version: Some(9),
+        size: Some(3),
+    };
+    assert_eq!(store.meta(&ptr).unwrap(), Some(meta));
+    assert_eq!(store.read(&ptr).unwrap(), vec![4, 5, 6]);
+    assert_eq!(store.header().unwrap(), Some(b"config".to_vec()));
+    assert_eq!(store.len(), len);
+    assert!(store.insert(&vec![7]).unwrap().to_u64() > ptr.to_u64());
+    drop(store);
+    std::fs::remove_file(&path).unwrap();
+}
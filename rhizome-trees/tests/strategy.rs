@@ -0,0 +1,62 @@
+//! Checks that the proptest strategies generate trees holding the entries
+//! they report, stored as requested.
+#![cfg(feature = "proptest")]
+
+use proptest::prelude::*;
+
+use rhizome_trees::tree::avl::strategy::{trees, trees_with_entries, Persistence, TreeOptions};
+use rhizome_trees::tree::node_manager::NodeRef;
+
+type Bytes = Vec<u8>;
+
+fn keys() -> impl Strategy<Value = Bytes> {
+    prop::collection::vec(0..8u8, 1..3)
+}
+
+fn values() -> impl Strategy<Value = Bytes> {
+    prop::collection::vec(any::<u8>(), 0..4)
+}
+
+proptest! {
+    #[test]
+    fn generated_trees_hold_their_entries(
+        (tree, entries) in trees_with_entries(keys(), values(), TreeOptions::default())
+    ) {
+        let stored: Vec<_> = tree
+            .iter()
+            .unwrap()
+            .map(|node| {
+                let node = node.unwrap();
+                (node.key().clone(), node.value().clone())
+            })
+            .collect();
+        prop_assert_eq!(stored, entries.into_iter().collect::<Vec<_>>());
+        prop_assert!(tree.verify().is_ok());
+    }
+
+    #[test]
+    fn reloaded_trees_are_stored(
+        tree in trees(
+            keys(),
+            values(),
+            TreeOptions::default().persistence(Persistence::Reloaded),
+        )
+    ) {
+        match tree.root() {
+            None => prop_assert_eq!(tree.len().unwrap(), 0),
+            Some(root) => prop_assert!(matches!(root, NodeRef::Stored(_))),
+        }
+    }
+
+    #[test]
+    fn unsaved_trees_are_in_memory(
+        tree in trees(
+            keys(),
+            values(),
+            TreeOptions::default().ops(1..8).persistence(Persistence::InMemory),
+        )
+    ) {
+        prop_assert!(!matches!(tree.root(), Some(NodeRef::Stored(_))));
+        prop_assert!(tree.save().is_ok());
+    }
+}
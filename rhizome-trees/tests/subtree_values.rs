@@ -0,0 +1,75 @@
+//! Checks that trees nested as root values open lazily and that chained
+//! proofs prove their entries against the outermost root.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::subtree::{ChainedProof, RootValue};
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::NodeManager;
+use rhizome_trees::tree::value::BytesValue as B;
+
+type Storage = Arc<NodeManager<Node<B, B>>>;
+
+/// Accounts 0 to 19, each with a storage tree mapping slots 0 to 9 to the
+/// account and slot.
+fn accounts() -> (Tree<B, RootValue>, Storage) {
+    let storage = Arc::new(NodeManager::in_memory());
+    let accounts = (0..20u8).fold(
+        Tree::with_manager(Arc::new(NodeManager::in_memory())),
+        |accounts, account| {
+            let slots = (0..10u8)
+                .fold(Tree::with_manager(storage.clone()), |slots, slot| {
+                    slots.insert(B(vec![slot]), B(vec![account, slot])).unwrap()
+                })
+                .save()
+                .unwrap();
+            let value = RootValue::of(&slots).unwrap();
+            accounts.insert(B(vec![account]), value).unwrap()
+        },
+    );
+    (accounts.save().unwrap(), storage)
+}
+
+#[test]
+fn opens_subtrees() {
+    let (accounts, storage) = accounts();
+    let slots = accounts
+        .subtree(&B(vec![9]), storage.clone())
+        .unwrap()
+        .unwrap();
+    assert_eq!(slots.get(&B(vec![2])).unwrap(), Some(B(vec![9, 2])));
+    assert!(accounts.subtree(&B(vec![99]), storage).unwrap().is_none());
+}
+
+#[test]
+fn refers_to_saved_trees_only() {
+    let unsaved = Tree::new().insert(B(vec![1]), B(vec![1])).unwrap();
+    assert!(RootValue::of(&unsaved).is_err());
+
+    let empty = RootValue::of(&Tree::<B, B>::new()).unwrap();
+    assert_eq!(empty, RootValue::EMPTY);
+    let opened = empty.open(Arc::new(NodeManager::<Node<B, B>>::in_memory()));
+    assert!(opened.is_empty());
+}
+
+#[test]
+fn chains_proofs_through_subtrees() {
+    let (accounts, storage) = accounts();
+    let root = accounts.merkle_hash().unwrap();
+    let (outer, slots) = accounts
+        .prove_subtree(&B(vec![7]), storage)
+        .unwrap()
+        .unwrap();
+    let inner = slots.prove(&B(vec![3])).unwrap().unwrap();
+    let proof = ChainedProof::new(outer).then(inner);
+    let keys = [&B(vec![7]), &B(vec![3])];
+
+    assert!(proof.verify(&root, &keys, &B(vec![7, 3])));
+    assert!(!proof.verify(&root, &keys, &B(vec![7, 4])));
+    assert!(!proof.verify(&root, &[&B(vec![7]), &B(vec![4])], &B(vec![7, 3])));
+    // There must be one key per level.
+    assert!(!proof.verify(&root, &keys[..1], &B(vec![7, 3])));
+    assert_eq!(proof.root_hash(&keys[..1], &B(vec![7, 3])), None);
+}
@@ -0,0 +1,81 @@
+//! Checks that tree configs validate their settings, round trip through
+//! their encoding, and are persisted in and enforced by store headers.
+
+use std::fs;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use rhizome_trees::tree::hash::HashVersion;
+use rhizome_trees::tree::node_manager::{
+    CachePolicy, FileNodeStore, MemNodeStore, NodeManager, NodeStore, ProofFormat, TreeConfig,
+};
+
+type Bytes = Vec<u8>;
+
+fn config() -> TreeConfig {
+    TreeConfig::default()
+        .hash_version(HashVersion::V0)
+        .cache_policy(CachePolicy::Disabled)
+        .memoize_value_hashes(NonZeroUsize::new(5).unwrap(), 64)
+        .metrics(true)
+}
+
+#[test]
+fn validates_and_encodes() {
+    let config = config();
+    config.validate().unwrap();
+    let encoded = config.encode();
+    assert!(encoded.starts_with(b"RZTC"));
+    assert_eq!(TreeConfig::decode(&encoded).unwrap(), config);
+    assert_eq!(
+        TreeConfig::decode(&TreeConfig::default().encode()).unwrap(),
+        TreeConfig::default()
+    );
+    assert!(TreeConfig::decode(&encoded[..10]).is_err());
+    assert!(TreeConfig::decode(b"RZTX").is_err());
+
+    // ICS-23 proofs can't express the untagged hash layout.
+    assert!(config.proof_format(ProofFormat::Ics23).validate().is_err());
+    TreeConfig::default()
+        .proof_format(ProofFormat::Ics23)
+        .validate()
+        .unwrap();
+}
+
+#[test]
+fn persists_in_the_store_header() {
+    let path = std::env::temp_dir().join(format!("rhizome-config-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let manager = NodeManager::<Bytes>::builder(FileNodeStore::open(&path).unwrap())
+        .config(config())
+        .open()
+        .unwrap();
+    assert_eq!(manager.hash_version(), HashVersion::V0);
+    assert!(manager.stats().is_some());
+    manager.insert(b"x".to_vec()).unwrap();
+    drop(manager);
+
+    // Opening with other settings fails; reopening takes the stored ones.
+    let store = FileNodeStore::open(&path).unwrap();
+    assert_eq!(store.len(), 1);
+    assert!(NodeManager::<Bytes>::builder(store).open().is_err());
+    let store = FileNodeStore::open(&path).unwrap();
+    let manager = NodeManager::<Bytes>::reopen(store).unwrap().build();
+    assert_eq!(*manager.config(), config());
+    drop(manager);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn reopens_only_configured_stores() {
+    assert!(NodeManager::reopen(MemNodeStore::<Bytes>::new()).is_err());
+    let store = MemNodeStore::<Bytes>::new();
+    store.set_header(&TreeConfig::default().encode()).unwrap();
+    NodeManager::builder(store).open().unwrap();
+
+    // A store opened without settings gets the defaults.
+    let store = Arc::new(MemNodeStore::<Bytes>::new());
+    NodeManager::builder(store.clone()).open().unwrap();
+    let manager = NodeManager::<Bytes>::reopen(store).unwrap().build();
+    assert_eq!(*manager.config(), TreeConfig::default());
+}
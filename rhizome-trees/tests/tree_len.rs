@@ -0,0 +1,78 @@
+//! Checks that the subtree sizes kept in nodes stay exact through inserts,
+//! deletes, batches, rotations and saves.
+
+mod rng;
+
+use std::collections::BTreeMap;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::{BatchOp, Tree};
+use rhizome_trees::tree::node_manager::NodeRef;
+use rng::Rng;
+
+type Bytes = Vec<u8>;
+
+/// Counts the entries under `node`, checking each node's size on the way.
+fn count(tree: &Tree<Bytes, Bytes>, node: Option<&NodeRef<Node<Bytes, Bytes>>>) -> u64 {
+    let Some(node) = node else {
+        return 0;
+    };
+    let node = tree.read(node).unwrap();
+    let size = 1 + count(tree, node.left()) + count(tree, node.right());
+    assert_eq!(node.size(), size);
+    size
+}
+
+#[test]
+fn tracks_sizes() {
+    let mut rng = Rng(7);
+    let mut tree = Tree::new();
+    let mut model = BTreeMap::new();
+    assert!(tree.is_empty());
+    assert_eq!(tree.len().unwrap(), 0);
+    for i in 0..3000u64 {
+        let key = (rng.next() % 500).to_be_bytes().to_vec();
+        match rng.next() % 4 {
+            0 => {
+                tree = tree.delete(&key).unwrap();
+                model.remove(&key);
+            }
+            1 => {
+                let ops: Vec<_> = (0..10)
+                    .map(|j| {
+                        let key = (rng.next() % 500 + j).to_be_bytes().to_vec();
+                        if rng.next().is_multiple_of(2) {
+                            model.remove(&key);
+                            BatchOp::Delete(key)
+                        } else {
+                            model.insert(key.clone(), vec![1]);
+                            BatchOp::Insert(key, vec![1])
+                        }
+                    })
+                    .collect();
+                tree = tree.apply_batch(ops).unwrap();
+            }
+            _ => {
+                tree = tree.insert(key.clone(), vec![0]).unwrap();
+                model.insert(key, vec![0]);
+            }
+        }
+        assert_eq!(tree.len().unwrap(), model.len() as u64);
+        assert_eq!(tree.is_empty(), model.is_empty());
+        if i.is_multiple_of(100) {
+            tree = tree.save().unwrap();
+            assert_eq!(count(&tree, tree.root()), model.len() as u64);
+        }
+    }
+}
+
+#[test]
+fn keeps_sizes_when_replacing() {
+    let tree = (0..100u8).fold(Tree::new(), |tree, i| {
+        tree.insert(vec![i], vec![i]).unwrap()
+    });
+    let tree = tree.insert(vec![50], vec![0]).unwrap();
+    let tree = tree.delete(&vec![200]).unwrap();
+    assert_eq!(tree.len().unwrap(), 100);
+    assert_eq!(count(&tree, tree.root()), 100);
+}
@@ -0,0 +1,87 @@
+//! Checks the shape reports of AVL and radix trees.
+
+use rhizome_trees::tree::art;
+use rhizome_trees::tree::avl::Tree;
+
+type Bytes = Vec<u8>;
+
+fn avl(keys: impl IntoIterator<Item = u8>) -> Tree<Bytes, Bytes> {
+    keys.into_iter().fold(Tree::new(), |tree, key| {
+        tree.insert(vec![key], vec![key; 3]).unwrap()
+    })
+}
+
+#[test]
+fn avl_stats_count_levels_and_locations() {
+    let empty = Tree::<Bytes, Bytes>::new().stats().unwrap();
+    assert_eq!(
+        (empty.nodes, empty.height, empty.average_depth),
+        (0, 0, 0.0)
+    );
+
+    // Fifteen ascending keys fill four levels.
+    let tree = avl(0..15);
+    let stats = tree.stats().unwrap();
+    assert_eq!((stats.nodes, stats.height), (15, 4));
+    assert_eq!(stats.average_depth, (2 + 4 * 2 + 8 * 3) as f64 / 15.0);
+    assert_eq!((stats.in_memory, stats.stored), (15, 0));
+
+    let saved = tree.save().unwrap();
+    let updated = saved.insert(vec![200], vec![]).unwrap();
+    let stats = updated.stats().unwrap();
+    assert_eq!(stats.nodes, 16);
+    // The path to the new key is rewritten.
+    assert_eq!((stats.in_memory, stats.stored), (5, 11));
+    assert_eq!(stats.height, 5);
+}
+
+#[cfg(feature = "borsh")]
+#[test]
+fn avl_stats_measure_stored_nodes() {
+    use std::sync::Arc;
+
+    use rhizome_trees::tree::avl::borsh::BorshCodec;
+    use rhizome_trees::tree::node_manager::{EncodedStore, FileNodeStore, NodeManager};
+
+    let path = std::env::temp_dir().join(format!("rhizome-tree-stats-{}", std::process::id()));
+    let store = FileNodeStore::open(&path).unwrap();
+    let manager = Arc::new(NodeManager::new(EncodedStore::new(
+        store,
+        BorshCodec::new(),
+    )));
+    let tree = (0..20u8).fold(Tree::with_manager(manager), |tree, key| {
+        tree.insert(vec![key], vec![key; 3]).unwrap()
+    });
+    let estimated = tree.stats().unwrap();
+    let saved = tree.save().unwrap().stats().unwrap();
+    assert_eq!((saved.in_memory, saved.stored), (0, 20));
+    // The file store reports the Borsh encodings, which prefix the key and
+    // value with their lengths where the estimate doesn't.
+    assert_eq!(saved.encoded_size, estimated.encoded_size + 20 * 8);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn art_stats_report_fill_per_node_type() {
+    let empty = art::Tree::<u8>::new().stats();
+    assert_eq!((empty.entries, empty.nodes, empty.height), (0, 0, 0));
+    assert_eq!(empty.node4.fill_factor(), 0.0);
+
+    // A root with 20 children under distinct first bytes, the first of which
+    // splits again into two leaves.
+    let mut tree = art::Tree::new();
+    for byte in 0..20u8 {
+        tree = tree.insert(vec![byte, 1], byte);
+    }
+    tree = tree.insert(vec![0, 2], 0);
+    let stats = tree.stats();
+    assert_eq!(stats.entries, 21);
+    assert_eq!(stats.nodes, 23);
+    assert_eq!(stats.height, 3);
+    assert_eq!(stats.average_depth, (19 + 2 * 2) as f64 / 21.0);
+    assert_eq!((stats.node48.nodes, stats.node48.children), (1, 20));
+    assert_eq!(stats.node48.fill_factor(), 20.0 / 48.0);
+    assert_eq!((stats.node4.nodes, stats.node4.children), (1, 2));
+    assert_eq!(stats.node4.fill_factor(), 0.5);
+    assert_eq!((stats.node16.nodes, stats.node256.nodes), (0, 0));
+}
@@ -0,0 +1,89 @@
+//! Checks that `try_update` rewrites the stored path to a key in place when
+//! no other version shares it, and otherwise leaves the tree untouched.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::versioned::VersionedTree;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::MerkleTree;
+use rhizome_trees::tree::node_manager::{ContentAddressedStore, NodeManager};
+use rhizome_trees::tree::value::U64BigEndian as U;
+
+fn filled(tree: Tree<U, U>, value: impl Fn(u64) -> u64) -> Tree<U, U> {
+    (0..100u64).fold(tree, |tree, i| tree.insert(U(i), U(value(i))).unwrap())
+}
+
+#[test]
+fn updates_unshared_stored_paths_in_place() {
+    let manager = Arc::new(NodeManager::in_memory());
+    let tree = filled(Tree::with_manager(manager.clone()), |i| i)
+        .save()
+        .unwrap();
+    let root = tree.root_ptr().unwrap();
+
+    assert!(tree.try_update(&U(42), U(4200)).unwrap());
+    assert_eq!(tree.root_ptr(), Some(root));
+    assert_eq!(tree.get(&U(42)).unwrap(), Some(U(4200)));
+
+    // The stored nodes changed, and hash like a tree built with the value.
+    let loaded = Tree::load(manager, root);
+    assert_eq!(loaded.get(&U(42)).unwrap(), Some(U(4200)));
+    let expected = filled(Tree::new(), |i| if i == 42 { 4200 } else { i });
+    assert_eq!(
+        loaded.merkle_hash().unwrap(),
+        expected.merkle_hash().unwrap()
+    );
+}
+
+#[test]
+fn refuses_absent_keys_and_shared_or_unsaved_paths() {
+    let manager = Arc::new(NodeManager::in_memory());
+    let tree = filled(Tree::with_manager(manager), |i| i).save().unwrap();
+    assert!(!tree.try_update(&U(1000), U(1)).unwrap());
+    assert_eq!(tree.get(&U(1000)).unwrap(), None);
+
+    // Saving the version again shares its nodes with the copy.
+    let shared = tree.save().unwrap();
+    assert!(!shared.try_update(&U(1), U(2)).unwrap());
+    assert_eq!(shared.get(&U(1)).unwrap(), Some(U(1)));
+
+    let unsaved = tree.insert(U(1), U(5)).unwrap();
+    assert!(!unsaved.try_update(&U(1), U(6)).unwrap());
+    assert_eq!(unsaved.get(&U(1)).unwrap(), Some(U(5)));
+}
+
+#[test]
+fn keeps_recorded_versions() {
+    let manager = Arc::new(NodeManager::in_memory());
+    let mut versioned = VersionedTree::new(manager.clone());
+    versioned
+        .set_working(filled(Tree::with_manager(manager), |i| i))
+        .unwrap();
+    versioned.save().unwrap();
+    let recorded = versioned.versions()[0];
+    let root = recorded.root.unwrap();
+    assert_eq!(versioned.working().root_ptr(), Some(root));
+
+    // The version holds the only reference to its root, yet its nodes stay.
+    assert!(!versioned.working().try_update(&U(42), U(4200)).unwrap());
+    let loaded = versioned.load_version(recorded.version).unwrap();
+    assert_eq!(loaded.get(&U(42)).unwrap(), Some(U(42)));
+    assert_eq!(loaded.merkle_hash().unwrap(), recorded.hash);
+    assert_eq!(loaded.verify().unwrap(), recorded.hash);
+    let pin = versioned.pin(recorded.version).unwrap();
+    assert!(!pin.tree().try_update(&U(42), U(4200)).unwrap());
+    assert_eq!(pin.tree().merkle_hash().unwrap(), recorded.hash);
+}
+
+#[test]
+fn refuses_stores_addressed_by_content() {
+    // A node's pointer is its hash, so it can't change in place.
+    let manager = Arc::new(NodeManager::new(ContentAddressedStore::new()));
+    let tree = Tree::with_manager(manager)
+        .insert(U(1), U(1))
+        .unwrap()
+        .save()
+        .unwrap();
+    assert!(!tree.try_update(&U(1), U(2)).unwrap());
+    assert_eq!(tree.get(&U(1)).unwrap(), Some(U(1)));
+}
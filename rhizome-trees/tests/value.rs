@@ -0,0 +1,276 @@
+use rhizome_trees::tree::hash::{hash_of, Hashable, Update};
+use std::fmt::Debug;
+use std::ops::Bound;
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::value::{
+    key, BoolValue, BytesValue, CompositeKey, FixedBytes, I128BigEndian, I16BigEndian,
+    I32BigEndian, I64BigEndian, I8BigEndian, KeyBuilder, KeyCodec, StringValue, Timestamp,
+    U128BigEndian, U16BigEndian, U32BigEndian, U64BigEndian, U8BigEndian, Uuid, ValueCodec,
+    VersionedKey,
+};
+use rhizome_trees::Error;
+
+#[derive(Default)]
+struct Encoding(Vec<u8>);
+
+impl Update for Encoding {
+    fn update(&mut self, data: &[u8]) {
+        self.0.extend_from_slice(data);
+    }
+}
+
+fn encode<T: Hashable>(value: &T) -> Vec<u8> {
+    let mut encoding = Encoding::default();
+    value.update_hash(&mut encoding);
+    encoding.0
+}
+
+fn assert_order_matches_encoding<T: Hashable + Ord + Debug>(values: &[T]) {
+    for a in values {
+        for b in values {
+            assert_eq!(a.cmp(b), encode(a).cmp(&encode(b)), "{:?} vs {:?}", a, b);
+        }
+    }
+}
+
+#[test]
+fn u64_big_endian_order() {
+    let values: Vec<U64BigEndian> = [0, 1, 255, 256, 65_535, 1 << 32, u64::MAX - 1, u64::MAX]
+        .into_iter()
+        .map(U64BigEndian)
+        .collect();
+    assert_order_matches_encoding(&values);
+    assert!(values.windows(2).all(|w| w[0] < w[1]));
+    for v in &values {
+        assert_eq!(U64BigEndian::from_bytes(v.to_bytes()), *v);
+    }
+}
+
+#[test]
+fn timestamp_order() {
+    let values = vec![
+        Timestamp::new(i64::MIN, 0),
+        Timestamp::new(-1, 0),
+        Timestamp::new(-1, 999_999_999),
+        Timestamp::new(0, 0),
+        Timestamp::new(0, 1),
+        Timestamp::new(1, 0),
+        Timestamp::new(1_700_000_000, 500),
+        Timestamp::new(i64::MAX, 999_999_999),
+    ];
+    assert_order_matches_encoding(&values);
+    assert!(values.windows(2).all(|w| w[0] < w[1]));
+    for v in &values {
+        assert_eq!(Timestamp::from_bytes(v.to_bytes()), *v);
+    }
+}
+
+#[test]
+fn timestamp_normalizes_nanos() {
+    assert_eq!(
+        Timestamp::new(1, 1_500_000_000),
+        Timestamp::new(2, 500_000_000)
+    );
+    let before_epoch = std::time::UNIX_EPOCH - std::time::Duration::from_millis(1500);
+    assert_eq!(
+        Timestamp::from(before_epoch),
+        Timestamp::new(-2, 500_000_000)
+    );
+}
+
+#[test]
+fn versioned_key_order() {
+    let key = |version: u64, bytes: &[u8]| -> VersionedKey {
+        (U64BigEndian(version), BytesValue::from(bytes))
+    };
+    let values = vec![
+        key(0, b""),
+        key(0, b"a"),
+        key(0, b"ab"),
+        key(0, b"b"),
+        key(1, b""),
+        key(1, b"\xff\xff"),
+        key(256, b"a"),
+    ];
+    assert_order_matches_encoding(&values);
+    assert!(values.windows(2).all(|w| w[0] < w[1]));
+    assert_ne!(hash_of(&key(1, b"a")), hash_of(&key(256, b"a")));
+}
+
+/// Checks that `values` are in increasing order, that their encodings sort
+/// the same way and that each one decodes back from its encoding.
+fn assert_key_codec<T: KeyCodec + Hashable + Debug>(values: &[T]) {
+    assert_order_matches_encoding(values);
+    assert!(values.windows(2).all(|w| w[0] < w[1]), "{:?}", values);
+    for v in values {
+        let encoded = v.to_encoded();
+        assert_eq!(encoded, encode(v));
+        if let Some(width) = T::FIXED_WIDTH {
+            assert_eq!(encoded.len(), width);
+        }
+        assert_eq!(&T::decode(&encoded).unwrap(), v);
+    }
+}
+
+#[test]
+fn integer_order() {
+    assert_key_codec(&[0, 1, 127, 128, u8::MAX].map(U8BigEndian));
+    assert_key_codec(&[0, 1, 255, 256, u16::MAX].map(U16BigEndian));
+    assert_key_codec(&[0, 1, 1 << 16, u32::MAX].map(U32BigEndian));
+    assert_key_codec(&[0, 1, 1 << 64, u128::MAX].map(U128BigEndian));
+    assert_key_codec(&[i8::MIN, -1, 0, 1, i8::MAX].map(I8BigEndian));
+    assert_key_codec(&[i16::MIN, -256, -1, 0, 255, i16::MAX].map(I16BigEndian));
+    assert_key_codec(&[i32::MIN, -1, 0, 1, i32::MAX].map(I32BigEndian));
+    assert_key_codec(&[i64::MIN, -(1 << 32), -1, 0, 1 << 32, i64::MAX].map(I64BigEndian));
+    assert_key_codec(&[i128::MIN, -1, 0, 1, i128::MAX].map(I128BigEndian));
+    assert!(U16BigEndian::decode(&[1]).is_err());
+}
+
+#[test]
+fn bool_string_and_byte_order() {
+    assert_key_codec(&[false, true].map(BoolValue));
+    assert!(BoolValue::decode(&[2]).is_err());
+    assert_key_codec(&["", "a", "a\0", "ab", "b", "\u{e9}", "\u{1f980}"].map(StringValue::from));
+    assert!(StringValue::decode(&[0xff]).is_err());
+    assert_key_codec(&[[0, 0], [0, 1], [1, 0], [255, 255]].map(FixedBytes));
+    assert!(FixedBytes::<2>::decode(&[1, 2, 3]).is_err());
+    assert_key_codec(&[[0; 16], [0x12; 16], [0xff; 16]].map(Uuid));
+}
+
+#[test]
+fn composite_key_order() {
+    type Key = CompositeKey<(StringValue, I32BigEndian, BytesValue)>;
+    let key =
+        |s: &str, n: i32, b: &[u8]| -> Key { CompositeKey((s.into(), I32BigEndian(n), b.into())) };
+    assert_key_codec(&[
+        key("", -1, b""),
+        key("", 0, b""),
+        key("", 0, b"\0"),
+        key("\0", i32::MIN, b""),
+        key("\0\0", 0, b""),
+        key("\0a", 0, b""),
+        key("a", i32::MIN, b""),
+        key("a", 7, b"\0\0"),
+        key("a", 7, b"\x01"),
+        key("a\0", i32::MIN, b""),
+        key("ab", 0, b""),
+    ]);
+    assert!(Key::decode(b"a\0").is_err());
+    assert!(Key::decode(b"a\0\x01").is_err());
+
+    // A pair with a fixed-width first part is encoded like a versioned key.
+    let versioned: VersionedKey = (U64BigEndian(3), BytesValue::from(&b"k"[..]));
+    let pair = CompositeKey(versioned.clone());
+    assert_eq!(pair.to_encoded(), versioned.to_encoded());
+    assert_key_codec(&[
+        CompositeKey((BoolValue(false), Uuid([9; 16]), StringValue::from("z"))),
+        CompositeKey((BoolValue(true), Uuid([0; 16]), StringValue::from(""))),
+    ]);
+}
+
+#[test]
+fn string_keys_are_read_with_str() {
+    let tree = Tree::<StringValue, BytesValue>::new()
+        .insert("apple".into(), BytesValue::from(&b"1"[..]))
+        .unwrap()
+        .insert("pear".into(), BytesValue::from(&b"2"[..]))
+        .unwrap();
+    assert_eq!(tree.get("pear").unwrap(), Some(BytesValue::from(&b"2"[..])));
+    assert_eq!(tree.get("plum").unwrap(), None);
+}
+
+#[test]
+fn key_builder_order() {
+    let fields = [
+        ("", 0, &b""[..]),
+        ("", 0, b"\0"),
+        ("", 1, b""),
+        ("\0", 0, b""),
+        ("a", 0, b"\xff"),
+        ("a", u64::MAX, b""),
+        ("a\0", 0, b""),
+        ("ab", 0, b""),
+    ];
+    let keys: Vec<BytesValue> = fields
+        .iter()
+        .map(|(s, n, b)| key().str(s).u64(*n).bytes(b).build())
+        .collect();
+    assert!(keys.windows(2).all(|w| w[0] < w[1]), "{:?}", keys);
+    // Fixed-width fields are written as is, like in a composite key.
+    let composite = CompositeKey((U64BigEndian(5), StringValue::from("x")));
+    assert_eq!(
+        key().u64(5).str("x").as_bytes(),
+        &b"\0\0\0\0\0\0\0\x05x\0\0"[..]
+    );
+    assert!(key()
+        .u64(5)
+        .str("x")
+        .as_bytes()
+        .starts_with(&composite.to_encoded()));
+}
+
+#[test]
+fn key_builder_prefix_range() {
+    let mut tree = Tree::<BytesValue, BytesValue>::new();
+    for owner in ["al", "alice", "alice\0", "bob"] {
+        for n in [0, 1, u64::MAX] {
+            let k = key().str(owner).u64(n).build();
+            tree = tree.insert(k, BytesValue::from(owner.as_bytes())).unwrap();
+        }
+    }
+    let owners: Vec<BytesValue> = tree
+        .range(key().str("alice").prefix_range())
+        .unwrap()
+        .map(|node| node.unwrap().value().clone())
+        .collect();
+    assert_eq!(owners, vec![BytesValue::from(&b"alice"[..]); 3]);
+    // The terminator of the last field is what gets incremented, unless
+    // every byte is 0xff.
+    let (_, upper) = key().bytes(b"\xff").prefix_range();
+    assert_eq!(upper, Bound::Excluded(BytesValue::from(&b"\xff\0\x01"[..])));
+    let (_, upper) = key().u64(u64::MAX).prefix_range();
+    assert_eq!(upper, Bound::Unbounded);
+    assert_eq!(KeyBuilder::default().prefix_range().1, Bound::Unbounded);
+}
+
+/// A `u64` written little-endian, which doesn't sort like the numbers.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct U64LittleEndian(u64);
+
+impl ValueCodec for U64LittleEndian {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.0.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> rhizome_trees::Result<Self> {
+        let bytes = bytes
+            .try_into()
+            .map_err(|_| Error::Codec(format!("a u64 takes 8 bytes, not {}", bytes.len())))?;
+        Ok(U64LittleEndian(u64::from_le_bytes(bytes)))
+    }
+}
+
+impl KeyCodec for U64LittleEndian {}
+
+#[test]
+fn audit_finds_misordered_encodings() {
+    let tree = (0..300).fold(Tree::new(), |tree, n| {
+        tree.insert(U64LittleEndian(n), ()).unwrap()
+    });
+    let report = tree.audit_key_order(u64::MAX).unwrap();
+    assert_eq!(report.pairs, 299);
+    // Only the carries into the second byte are out of order.
+    let misordered: Vec<_> = report
+        .misordered
+        .iter()
+        .map(|pair| (pair.lower.0, pair.higher.0))
+        .collect();
+    assert_eq!(misordered, [(255, 256)]);
+    assert!(tree.audit_key_order(10).unwrap().pairs == 10);
+
+    let tree = (0..300).fold(Tree::new(), |tree, n| {
+        tree.insert(U64BigEndian(n), ()).unwrap()
+    });
+    assert!(tree.audit_key_order(u64::MAX).unwrap().is_consistent());
+}
@@ -0,0 +1,57 @@
+//! Checks that memoizing the hashes of large values never changes a hash,
+//! whether values are shared, distinct or evicted from the memo.
+
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::hash::{hash_of, MerkleTree, ValueHashMemo};
+use rhizome_trees::tree::node_manager::{MemNodeStore, NodeManager};
+
+type Bytes = Vec<u8>;
+
+fn filled(manager: NodeManager<Node<Bytes, Bytes>>) -> Tree<Bytes, Bytes> {
+    let shared = vec![7; 64 * 1024];
+    (0..500u32).fold(Tree::with_manager(Arc::new(manager)), |tree, i| {
+        // Most entries share one value, every tenth has its own.
+        let value = if i.is_multiple_of(10) {
+            vec![i as u8; 2048 + i as usize]
+        } else {
+            shared.clone()
+        };
+        tree.insert(i.to_be_bytes().to_vec(), value).unwrap()
+    })
+}
+
+#[test]
+fn remembers_hashes() {
+    let memo = ValueHashMemo::new(NonZeroUsize::new(2).unwrap(), 8);
+    for value in [vec![], vec![1; 7], vec![1; 8], vec![2; 100], vec![1; 8]] {
+        assert_eq!(memo.hash_of(&value), hash_of(&value));
+    }
+    // Values of other types with the same encoding share a hash.
+    assert_eq!(
+        memo.hash_of(&b"abcdefgh"[..]),
+        hash_of(&b"abcdefgh".to_vec())
+    );
+}
+
+#[test]
+fn hashes_trees_the_same() {
+    let plain = filled(NodeManager::new(MemNodeStore::new()));
+    let hash = plain.merkle_hash().unwrap();
+    for capacity in [1, 16] {
+        let memoized = filled(
+            NodeManager::builder(MemNodeStore::new())
+                .memoize_value_hashes(NonZeroUsize::new(capacity).unwrap(), 1024)
+                .build(),
+        );
+        assert_eq!(memoized.merkle_hash().unwrap(), hash);
+        let saved = memoized.save().unwrap();
+        assert_eq!(saved.verify().unwrap(), hash);
+        let key = 5u32.to_be_bytes().to_vec();
+        let proof = saved.prove(&key).unwrap().unwrap();
+        assert!(proof.verify(&hash, &key, &vec![7; 64 * 1024]));
+    }
+}
@@ -0,0 +1,58 @@
+//! Checks that values can be read by reference, from memory or the store,
+//! without being cloned.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{CachePolicy, MemNodeStore, NodeManager};
+
+static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+/// A value which counts how often it is cloned.
+#[derive(PartialEq, Debug)]
+struct Counted(Vec<u8>);
+
+impl Clone for Counted {
+    fn clone(&self) -> Self {
+        CLONES.fetch_add(1, Ordering::SeqCst);
+        Counted(self.0.clone())
+    }
+}
+
+#[test]
+fn reads_without_cloning() {
+    let tree = Tree::new().insert(1u32, Counted(vec![9; 100])).unwrap();
+    let before = CLONES.load(Ordering::SeqCst);
+    let value = tree.get_ref(&1).unwrap().unwrap();
+    assert_eq!(value.0.len(), 100);
+    assert_eq!(tree.get_with(&1, |value| value.0[0]).unwrap(), Some(9));
+    assert_eq!(tree.get_with(&2, |value| value.0[0]).unwrap(), None);
+    assert!(tree.get_ref(&2).unwrap().is_none());
+    assert_eq!(CLONES.load(Ordering::SeqCst), before);
+
+    // The handle keeps the value alive after the tree is gone.
+    drop(tree);
+    assert_eq!(value.0[0], 9);
+}
+
+#[test]
+fn reads_stored_values() {
+    let manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .cache_policy(CachePolicy::Disabled)
+            .build(),
+    );
+    let tree = (0..100u8).fold(Tree::with_manager(manager.clone()), |tree, i| {
+        tree.insert(vec![i], vec![i; 100]).unwrap()
+    });
+    let saved = tree.save().unwrap();
+    let loaded = Tree::<Vec<u8>, Vec<u8>>::load(manager, saved.root_ptr().unwrap());
+    for i in 0..100u8 {
+        let value = loaded.get_ref(&[i][..]).unwrap().unwrap();
+        assert_eq!(*value, vec![i; 100]);
+        let len = loaded.get_with(&[i][..], |value| value.len()).unwrap();
+        assert_eq!(len, Some(100));
+    }
+    assert!(loaded.get_ref(&[100][..]).unwrap().is_none());
+}
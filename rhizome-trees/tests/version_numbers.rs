@@ -0,0 +1,66 @@
+//! Checks that versions start at the configured initial version and only
+//! ever advance by one, including after rollbacks and when reopening.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::versioned::{Version, VersionedTree};
+use rhizome_trees::tree::node_manager::NodeManager;
+
+type Bytes = Vec<u8>;
+type Manager = Arc<NodeManager<Node<Bytes, Bytes>>>;
+
+fn manager() -> Manager {
+    Arc::new(NodeManager::in_memory())
+}
+
+#[test]
+fn numbers_versions() {
+    assert_eq!(Version::new(7).get(), 7);
+    assert_eq!(Version::new(7).next().unwrap(), Version::new(8));
+    assert!(Version::new(u64::MAX).next().is_err());
+    assert_eq!(u64::from(Version::INITIAL), 1);
+    assert_eq!(Version::new(42).to_string(), "42");
+    assert!(Version::ZERO < Version::INITIAL);
+}
+
+#[test]
+fn starts_at_the_initial_version() {
+    assert!(VersionedTree::<Bytes, Bytes>::with_initial_version(manager(), Version::ZERO).is_err());
+
+    let mut tree = VersionedTree::with_initial_version(manager(), Version::new(100)).unwrap();
+    assert_eq!(tree.latest_version(), Version::ZERO);
+    assert_eq!(tree.next_version().unwrap(), Version::new(100));
+    tree.insert(vec![1], vec![1]).unwrap();
+    assert!(tree.save_version(Version::new(1)).is_err());
+    assert_eq!(tree.save().unwrap(), Version::new(100));
+    assert_eq!(tree.save().unwrap(), Version::new(101));
+
+    // Rolling back everything starts over at the initial version.
+    tree.rollback_to(Version::ZERO).unwrap();
+    assert_eq!(tree.next_version().unwrap(), Version::new(100));
+}
+
+#[test]
+fn refuses_gaps_and_regressions() {
+    let mut tree = VersionedTree::new(manager());
+    assert!(tree.save_version(Version::new(2)).is_err());
+    tree.save_version(Version::INITIAL).unwrap();
+    assert!(tree.save_version(Version::INITIAL).is_err());
+    assert!(tree.save_version(Version::new(3)).is_err());
+    assert_eq!(tree.save().unwrap(), Version::new(2));
+    assert_eq!(tree.versions().len(), 2);
+}
+
+#[test]
+fn continues_numbering_when_reopened() {
+    let mut tree = VersionedTree::with_initial_version(manager(), Version::new(10)).unwrap();
+    for i in 0..3u8 {
+        tree.insert(vec![i], vec![i]).unwrap();
+        tree.save().unwrap();
+    }
+    let mut reopened =
+        VersionedTree::open(tree.working().manager().clone(), tree.versions().to_vec()).unwrap();
+    assert_eq!(reopened.latest_version(), Version::new(12));
+    assert_eq!(reopened.save().unwrap(), Version::new(13));
+}
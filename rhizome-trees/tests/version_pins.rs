@@ -0,0 +1,118 @@
+//! Checks that readers holding pinned versions of a `VersionedTree` can
+//! traverse them while other versions are pruned as aggressively as
+//! possible.
+
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::versioned::{PruningPolicy, Version, VersionPin, VersionedTree};
+use rhizome_trees::tree::node_manager::{MemNodeStore, NodeManager};
+
+type Bytes = Vec<u8>;
+
+const VERSIONS: u64 = 200;
+const READERS: usize = 4;
+
+fn key(i: u64) -> Bytes {
+    i.to_be_bytes().to_vec()
+}
+
+/// Reads every entry of a pinned version, which holds the keys `1..=v`
+/// along with a counter overwritten by every save.
+fn read_all(pin: &VersionPin<Bytes, Bytes>) {
+    let v = pin.version().get();
+    let mut entries = 0;
+    for entry in pin.tree().iter().unwrap() {
+        let node = entry.unwrap_or_else(|err| panic!("reading version {}: {:#}", v, err));
+        entries += 1;
+        if node.key().as_slice() == b"counter" {
+            assert_eq!(node.value(), &key(v));
+        } else {
+            assert_eq!(node.key(), node.value());
+        }
+    }
+    assert_eq!(entries, v + 1, "version {}", v);
+}
+
+#[test]
+fn pruning_never_frees_pinned_versions() {
+    let store = Arc::new(MemNodeStore::<Node<Bytes, Bytes>>::new());
+    let manager = Arc::new(NodeManager::new(store.clone()));
+    let mut tree = VersionedTree::new(manager);
+    tree.set_pruning(PruningPolicy::default().keep_recent(1));
+
+    let (senders, readers): (Vec<_>, Vec<_>) = (0..READERS)
+        .map(|_| {
+            let (sender, receiver) = mpsc::channel::<VersionPin<Bytes, Bytes>>();
+            let reader = thread::spawn(move || {
+                for pin in receiver {
+                    read_all(&pin);
+                    // Reads it again after later versions were pruned.
+                    read_all(&pin);
+                }
+            });
+            (sender, reader)
+        })
+        .unzip();
+
+    for i in 1..=VERSIONS {
+        tree.insert(key(i), key(i)).unwrap();
+        tree.insert(b"counter".to_vec(), key(i)).unwrap();
+        let version = tree.save().unwrap();
+        let pin = tree.pin_current().unwrap();
+        assert_eq!(pin.version(), version);
+        for sender in &senders {
+            sender.send(pin.clone()).unwrap();
+        }
+        drop(pin);
+        if i % 2 == 0 {
+            tree.prune().unwrap();
+        } else {
+            tree.prune_in_background().join().unwrap().unwrap();
+        }
+    }
+    drop(senders);
+    for reader in readers {
+        reader.join().unwrap();
+    }
+
+    // With every pin dropped, the next prune frees the versions they kept.
+    tree.prune().unwrap();
+    assert_eq!(tree.versions().len(), 1);
+    let latest = tree.load_version(tree.latest_version()).unwrap();
+    assert_eq!(store.len() as u64, latest.len().unwrap());
+}
+
+#[test]
+fn pinned_versions_survive_pruning_and_block_rollbacks() {
+    let manager = Arc::new(NodeManager::in_memory());
+    let mut tree = VersionedTree::new(manager);
+    tree.set_pruning(PruningPolicy::default().keep_recent(1));
+    for i in 1..=3 {
+        tree.insert(key(i), key(i)).unwrap();
+        tree.save().unwrap();
+    }
+    let pin = tree.pin(Version::new(2)).unwrap();
+    assert!(tree.is_pinned(Version::new(2)));
+
+    tree.prune().unwrap();
+    let kept: Vec<_> = tree
+        .versions()
+        .iter()
+        .map(|info| info.version.get())
+        .collect();
+    assert_eq!(kept, [2, 3]);
+    assert!(tree.rollback_to(Version::new(1)).is_err());
+    assert_eq!(pin.tree().len().unwrap(), 2);
+
+    // Rolling back to the pinned version itself discards only later ones.
+    tree.rollback_to(Version::new(2)).unwrap();
+    drop(pin);
+    assert!(!tree.is_pinned(Version::new(2)));
+    tree.insert(key(3), key(3)).unwrap();
+    tree.save().unwrap();
+    tree.prune().unwrap();
+    assert_eq!(tree.versions().len(), 1);
+}
@@ -0,0 +1,73 @@
+//! Checks that a versioned tree numbers the versions it saves, loads any
+//! saved version, and rolls back by releasing the versions after the target.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::node::Node;
+use rhizome_trees::tree::avl::versioned::{Version, VersionedTree};
+use rhizome_trees::tree::node_manager::{MemNodeStore, NodeManager};
+
+type Bytes = Vec<u8>;
+type Store = MemNodeStore<Node<Bytes, Bytes>>;
+
+/// Saves versions 1 to 5, version `v` adding the keys `[v, 0]` to `[v, 9]`.
+fn saved() -> (VersionedTree<Bytes, Bytes>, Arc<Store>) {
+    let store = Arc::new(MemNodeStore::new());
+    let mut tree = VersionedTree::new(Arc::new(NodeManager::new(store.clone())));
+    for version in 1..=5u8 {
+        for i in 0..10u8 {
+            tree.insert(vec![version, i], vec![version]).unwrap();
+        }
+        assert_eq!(tree.save().unwrap(), Version::new(version as u64));
+    }
+    (tree, store)
+}
+
+#[test]
+fn loads_saved_versions() {
+    let (tree, _) = saved();
+    assert_eq!(tree.latest_version(), Version::new(5));
+    assert_eq!(tree.versions().len(), 5);
+    let third = tree.load_version(Version::new(3)).unwrap();
+    assert_eq!(third.get(&vec![3, 0]).unwrap(), Some(vec![3]));
+    assert_eq!(third.get(&vec![4, 0]).unwrap(), None);
+    assert!(tree.load_version(Version::new(6)).is_err());
+
+    // Reopening from the recorded versions gives the same state.
+    let reopened =
+        VersionedTree::open(tree.working().manager().clone(), tree.versions().to_vec()).unwrap();
+    assert_eq!(reopened.latest_version(), Version::new(5));
+    assert_eq!(reopened.get(&vec![2, 1]).unwrap(), Some(vec![2]));
+}
+
+#[test]
+fn rolls_back_to_a_saved_version() {
+    let (mut tree, store) = saved();
+    tree.insert(vec![9], vec![9]).unwrap();
+    let before = store.len();
+    let deleted = tree.rollback_to(Version::new(3)).unwrap();
+    assert!(deleted > 0);
+    assert_eq!(store.len(), before - deleted);
+
+    // Unsaved changes and later versions are gone.
+    assert_eq!(tree.latest_version(), Version::new(3));
+    assert_eq!(tree.get(&vec![9]).unwrap(), None);
+    assert_eq!(tree.get(&vec![4, 0]).unwrap(), None);
+    assert_eq!(tree.get(&vec![3, 1]).unwrap(), Some(vec![3]));
+    assert!(tree.load_version(Version::new(4)).is_err());
+    // Their numbers are reused.
+    assert_eq!(tree.save().unwrap(), Version::new(4));
+
+    assert!(tree.rollback_to(Version::new(0)).unwrap() > 0);
+    assert!(tree.working().is_empty());
+    assert!(store.is_empty());
+}
+
+#[test]
+fn saves_only_the_next_version() {
+    let (mut tree, _) = saved();
+    assert!(tree.save_version(Version::new(5)).is_err());
+    assert!(tree.save_version(Version::new(7)).is_err());
+    tree.save_version(Version::new(6)).unwrap();
+    assert_eq!(tree.latest_version(), Version::new(6));
+}
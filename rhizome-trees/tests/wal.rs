@@ -0,0 +1,295 @@
+//! Checks that the write-ahead journal rolls back saves a crash interrupted,
+//! finishes interrupted releases, stays compact and refuses to recover over
+//! a damaged journal.
+
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{
+    FileNodeStore, MemNodeStore, NodeManager, NodeStore, Ptr, WalStore, WriteBatch,
+};
+use rhizome_trees::{Error, Result};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rhizome-wal-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A file store which crashes, by panicking, around its writes once told to.
+#[derive(Default)]
+struct Crashing {
+    inner: Option<FileNodeStore>,
+    /// Crashes before the next decrement reaches the store.
+    before: AtomicBool,
+    /// Crashes after the next batch or decrement reached the store.
+    after: AtomicBool,
+}
+
+impl Crashing {
+    fn open(path: &Path) -> Self {
+        Crashing {
+            inner: Some(FileNodeStore::open(path).unwrap()),
+            ..Crashing::default()
+        }
+    }
+
+    fn inner(&self) -> &FileNodeStore {
+        self.inner.as_ref().unwrap()
+    }
+
+    fn crash(flag: &AtomicBool) {
+        if flag.swap(false, Ordering::SeqCst) {
+            panic!("crash");
+        }
+    }
+}
+
+impl NodeStore<Vec<u8>> for Crashing {
+    fn read(&self, ptr: &Ptr) -> Result<Vec<u8>> {
+        self.inner().read(ptr)
+    }
+
+    fn insert(&self, node: &Vec<u8>) -> Result<Ptr> {
+        self.inner().insert(node)
+    }
+
+    fn inc_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        self.inner().inc_ref_count(ptr)
+    }
+
+    fn dec_ref_count(&self, ptr: &Ptr) -> Result<u64> {
+        Crashing::crash(&self.before);
+        let count = self.inner().dec_ref_count(ptr)?;
+        Crashing::crash(&self.after);
+        Ok(count)
+    }
+
+    fn delete(&self, ptr: &Ptr) -> Result<()> {
+        self.inner().delete(ptr)
+    }
+
+    fn begin_batch<'a>(&'a self) -> Result<Box<dyn WriteBatch<Vec<u8>> + 'a>>
+    where
+        Vec<u8>: 'a,
+    {
+        Ok(Box::new(CrashingBatch {
+            inner: self.inner().begin_batch()?,
+            after: &self.after,
+        }))
+    }
+
+    fn ref_count(&self, ptr: &Ptr) -> Result<Option<u64>> {
+        self.inner().ref_count(ptr)
+    }
+}
+
+struct CrashingBatch<'a> {
+    inner: Box<dyn WriteBatch<Vec<u8>> + 'a>,
+    after: &'a AtomicBool,
+}
+
+impl WriteBatch<Vec<u8>> for CrashingBatch<'_> {
+    fn insert(&mut self, node: &Vec<u8>) -> Result<Ptr> {
+        self.inner.insert(node)
+    }
+
+    fn inc_ref_count(&mut self, ptr: &Ptr) -> Result<()> {
+        self.inner.inc_ref_count(ptr)
+    }
+
+    fn set_root(&mut self, root: Option<Ptr>) {
+        self.inner.set_root(root)
+    }
+
+    fn commit(self: Box<Self>) -> Result<()> {
+        self.inner.commit()?;
+        Crashing::crash(self.after);
+        Ok(())
+    }
+}
+
+/// Commits a batch inserting `node` as the new root, taking a reference on
+/// `shared`.
+fn save<S: NodeStore<Vec<u8>>>(store: &WalStore<S>, node: &[u8], shared: Option<Ptr>) -> Ptr {
+    let mut batch = store.begin_batch().unwrap();
+    let root = batch.insert(&node.to_vec()).unwrap();
+    if let Some(shared) = shared {
+        batch.inc_ref_count(&shared).unwrap();
+    }
+    batch.set_root(Some(root));
+    batch.commit().unwrap();
+    root
+}
+
+/// Runs `write`, checking that it crashed.
+fn crashed(write: impl FnOnce()) {
+    assert!(panic::catch_unwind(AssertUnwindSafe(write)).is_err());
+}
+
+#[test]
+fn rolls_back_an_interrupted_save() {
+    let dir = temp_dir("interrupted");
+    let (log, journal) = (dir.join("nodes"), dir.join("wal"));
+    let store = WalStore::open(Crashing::open(&log), &journal).unwrap();
+    assert_eq!(store.root().unwrap(), None);
+    let first = save(&store, b"first", None);
+    let mut batch = store.begin_batch().unwrap();
+    let second = batch.insert(&b"second".to_vec()).unwrap();
+    batch.inc_ref_count(&first).unwrap();
+    batch.set_root(Some(second));
+    // A crash once the save reached the store, before it completed.
+    store.inner().after.store(true, Ordering::SeqCst);
+    crashed(|| batch.commit().unwrap());
+    assert_eq!(store.inner().read(&second).unwrap(), b"second");
+    assert_eq!(store.root().unwrap(), Some(first));
+    drop(store);
+
+    let store = WalStore::open(FileNodeStore::open(&log).unwrap(), &journal).unwrap();
+    assert_eq!(store.rolled_back(), 1);
+    assert_eq!(store.root().unwrap(), Some(first));
+    assert!(store.read(&second).is_err());
+    assert_eq!(store.dec_ref_count(&first).unwrap(), 0);
+    drop(store);
+
+    let store = WalStore::open(FileNodeStore::open(&log).unwrap(), &journal).unwrap();
+    assert_eq!(store.rolled_back(), 0);
+    assert_eq!(store.root().unwrap(), Some(first));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn refuses_a_damaged_record_before_the_end() {
+    let dir = temp_dir("damaged");
+    let (log, journal) = (dir.join("nodes"), dir.join("wal"));
+    let store = WalStore::open(Crashing::open(&log), &journal).unwrap();
+    let first = save(&store, b"first", None);
+    let begin = fs::metadata(&journal).unwrap().len();
+    store.inner().after.store(true, Ordering::SeqCst);
+    crashed(|| {
+        save(&store, b"second", Some(first));
+    });
+    // The interrupted save stays pending, so this one isn't compacted.
+    let third = save(&store, b"third", None);
+    drop(store);
+    let bytes = fs::read(&journal).unwrap();
+
+    // Damage the batch id in the record beginning the interrupted save,
+    // which the third save follows.
+    let mut file = OpenOptions::new().write(true).open(&journal).unwrap();
+    file.seek(SeekFrom::Start(begin + 8 + 1)).unwrap();
+    file.write_all(&[0xff]).unwrap();
+    drop(file);
+    let damaged = fs::read(&journal).unwrap();
+
+    let err = WalStore::open(FileNodeStore::open(&log).unwrap(), &journal)
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::Corruption(_)), "{}", err);
+    // Nothing is rolled back nor compacted away.
+    assert_eq!(fs::read(&journal).unwrap(), damaged);
+    assert_eq!(damaged.len(), bytes.len());
+    let store = FileNodeStore::open(&log).unwrap();
+    assert_eq!(store.read(&third).unwrap(), b"third");
+    assert_eq!(store.inc_ref_count(&first).unwrap(), 3);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn finishes_an_interrupted_release() {
+    let dir = temp_dir("release");
+    let (log, journal) = (dir.join("nodes"), dir.join("wal"));
+    let store = WalStore::open(Crashing::open(&log), &journal).unwrap();
+    let root = save(&store, b"root", None);
+    store.inc_ref_count(&root).unwrap();
+    store.inc_ref_count(&root).unwrap();
+    drop(store);
+
+    // Crashes before and after the decrement reached the store both end up
+    // decrementing once.
+    for (after, count) in [(false, 2), (true, 1)] {
+        let store = WalStore::open(Crashing::open(&log), &journal).unwrap();
+        let crashing = store.inner();
+        let flag = if after {
+            &crashing.after
+        } else {
+            &crashing.before
+        };
+        flag.store(true, Ordering::SeqCst);
+        crashed(|| drop(store.dec_ref_count(&root)));
+        drop(store);
+        let store = WalStore::open(FileNodeStore::open(&log).unwrap(), &journal).unwrap();
+        assert_eq!(store.ref_count(&root).unwrap(), Some(count));
+    }
+    let store = WalStore::open(FileNodeStore::open(&log).unwrap(), &journal).unwrap();
+    assert_eq!(store.dec_ref_count(&root).unwrap(), 0);
+    store.delete(&root).unwrap();
+    assert!(store.read(&root).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn compacts_completed_writes() {
+    let dir = temp_dir("compact");
+    let (log, journal) = (dir.join("nodes"), dir.join("wal"));
+    let store = WalStore::open(FileNodeStore::open(&log).unwrap(), &journal).unwrap();
+    let compacted = fs::metadata(&journal).unwrap().len();
+    let mut root = save(&store, b"0", None);
+    let len = fs::metadata(&journal).unwrap().len();
+    for i in 1..20u8 {
+        let next = save(&store, &[i], None);
+        assert_eq!(store.dec_ref_count(&root).unwrap(), 0);
+        store.delete(&root).unwrap();
+        root = next;
+        assert_eq!(fs::metadata(&journal).unwrap().len(), len);
+    }
+    assert!(len > compacted);
+    assert_eq!(store.root().unwrap(), Some(root));
+    // In-place updates aren't journaled, so they are refused.
+    assert!(!store.try_update(&[(root, vec![9])]).unwrap());
+    assert_eq!(store.read(&root).unwrap(), [19]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn ignores_a_torn_last_record() {
+    let dir = temp_dir("torn");
+    let (log, journal) = (dir.join("nodes"), dir.join("wal"));
+    let store = WalStore::open(FileNodeStore::open(&log).unwrap(), &journal).unwrap();
+    let first = save(&store, b"first", None);
+    drop(store);
+
+    // The header and part of the payload of a record beginning a save.
+    let mut file = OpenOptions::new().append(true).open(&journal).unwrap();
+    file.write_all(&[40, 0, 0, 0, 1, 2, 3, 4, 0, 7]).unwrap();
+    drop(file);
+
+    let store = WalStore::open(FileNodeStore::open(&log).unwrap(), &journal).unwrap();
+    assert_eq!(store.rolled_back(), 0);
+    assert_eq!(store.root().unwrap(), Some(first));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn journals_tree_saves() {
+    let dir = temp_dir("tree");
+    let journal = dir.join("wal");
+    let store = WalStore::open(MemNodeStore::new(), &journal).unwrap();
+    let manager = Arc::new(NodeManager::new(store));
+    let tree = (0..100u32).fold(Tree::with_manager(manager.clone()), |tree, i| {
+        tree.insert(i.to_be_bytes().to_vec(), vec![1]).unwrap()
+    });
+    let saved = tree.save().unwrap();
+    assert_eq!(
+        saved.get(&7u32.to_be_bytes().to_vec()).unwrap(),
+        Some(vec![1])
+    );
+    assert_eq!(saved.get(&1000u32.to_be_bytes().to_vec()).unwrap(), None);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,100 @@
+//! Checks that write batches apply all of their writes or none, and that a
+//! file store recovers from a batch torn by a crash.
+
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+
+use rhizome_trees::tree::node_manager::{FileNodeStore, MemNodeStore, NodeStore, Ptr};
+
+fn store_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rhizome-batch-{}-{}", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn commits_all_or_nothing() {
+    let store = MemNodeStore::<u32>::new();
+    let a = store.insert(&1).unwrap();
+
+    let mut batch = store.begin_batch().unwrap();
+    let b = batch.insert(&2).unwrap();
+    batch.inc_ref_count(&a).unwrap();
+    batch.inc_ref_count(&Ptr::from_u64(77)).unwrap();
+    assert!(batch.commit().is_err());
+    assert!(store.read(&b).is_err());
+    assert_eq!(store.dec_ref_count(&a).unwrap(), 0);
+
+    let a = store.insert(&1).unwrap();
+    let mut batch = store.begin_batch().unwrap();
+    let b = batch.insert(&2).unwrap();
+    batch.inc_ref_count(&a).unwrap();
+    // Nothing is visible before the commit.
+    assert!(store.read(&b).is_err());
+    batch.commit().unwrap();
+    assert_eq!(store.read(&b).unwrap(), 2);
+    assert_eq!(store.inc_ref_count(&a).unwrap(), 3);
+}
+
+#[test]
+fn persists_committed_batches() {
+    let path = store_path("commit");
+    let store = FileNodeStore::open(&path).unwrap();
+    let a = store.insert(&b"a".to_vec()).unwrap();
+    let mut batch = store.begin_batch().unwrap();
+    let b = batch.insert(&b"bb".to_vec()).unwrap();
+    let c = batch.insert(&b"ccc".to_vec()).unwrap();
+    batch.inc_ref_count(&a).unwrap();
+    batch.inc_ref_count(&b).unwrap();
+    assert!(store.read(&b).is_err());
+    batch.commit().unwrap();
+    assert_eq!(store.read(&c).unwrap(), b"ccc");
+    assert_eq!(store.dec_ref_count(&a).unwrap(), 1);
+
+    // A failed batch writes nothing to the file either.
+    let mut batch = store.begin_batch().unwrap();
+    let d = batch.insert(&b"d".to_vec()).unwrap();
+    batch.inc_ref_count(&Ptr::from_u64(99_999)).unwrap();
+    assert!(batch.commit().is_err());
+    assert!(store.read(&d).is_err());
+    drop(store);
+
+    let store = FileNodeStore::open(&path).unwrap();
+    assert_eq!(store.len(), 3);
+    assert_eq!(store.read(&b).unwrap(), b"bb");
+    assert_eq!(store.dec_ref_count(&b).unwrap(), 1);
+    assert_eq!(store.dec_ref_count(&a).unwrap(), 0);
+    drop(store);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn discards_torn_batches() {
+    let path = store_path("torn");
+    let store = FileNodeStore::open(&path).unwrap();
+    let a = store.insert(&b"a".to_vec()).unwrap();
+    let mut batch = store.begin_batch().unwrap();
+    let b = batch.insert(&b"bb".to_vec()).unwrap();
+    let c = batch.insert(&b"ccc".to_vec()).unwrap();
+    batch.commit().unwrap();
+    drop(store);
+
+    // Cut the end of the batch off, as a crash while writing it would.
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    let len = file.metadata().unwrap().len();
+    file.set_len(len - 2).unwrap();
+    drop(file);
+
+    let store = FileNodeStore::open(&path).unwrap();
+    assert_eq!(store.len(), 1);
+    assert_eq!(store.read(&a).unwrap(), b"a");
+    assert!(store.read(&b).is_err());
+    assert!(store.read(&c).is_err());
+    // The store keeps working after the torn batch.
+    let d = store.insert(&b"d".to_vec()).unwrap();
+    drop(store);
+    let store = FileNodeStore::open(&path).unwrap();
+    assert_eq!(store.read(&d).unwrap(), b"d");
+    drop(store);
+    fs::remove_file(&path).unwrap();
+}
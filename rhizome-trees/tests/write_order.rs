@@ -0,0 +1,87 @@
+//! Checks that the write order of a save changes where new nodes are laid
+//! out in the store, but not the saved tree.
+
+use std::sync::Arc;
+
+use rhizome_trees::tree::avl::Tree;
+use rhizome_trees::tree::node_manager::{MemNodeStore, NodeManager, NodeRef, Ptr, WriteOrder};
+
+type Bytes = Vec<u8>;
+
+fn saved(order: WriteOrder, keys: impl IntoIterator<Item = u8>) -> Tree<Bytes, Bytes> {
+    let manager = Arc::new(
+        NodeManager::builder(MemNodeStore::new())
+            .write_order(order)
+            .build(),
+    );
+    let mut tree: Tree<Bytes, Bytes> = Tree::with_manager(manager);
+    for key in keys {
+        tree = tree.insert(vec![key], vec![key; 2]).unwrap();
+    }
+    tree.save().unwrap()
+}
+
+/// Returns the pointer and depth of every stored node, ordered by pointer.
+fn layout(tree: &Tree<Bytes, Bytes>) -> Vec<(u64, usize)> {
+    fn walk(tree: &Tree<Bytes, Bytes>, ptr: Ptr, depth: usize, out: &mut Vec<(u64, usize)>) {
+        out.push((ptr.to_u64().unwrap(), depth));
+        let node = tree.read(&NodeRef::Stored(ptr)).unwrap();
+        for child in [node.left(), node.right()].into_iter().flatten() {
+            walk(tree, child.ptr().unwrap(), depth + 1, out);
+        }
+    }
+    let mut out = Vec::new();
+    walk(tree, tree.root_ptr().unwrap(), 0, &mut out);
+    out.sort();
+    out
+}
+
+fn entries(tree: &Tree<Bytes, Bytes>) -> Vec<(Bytes, Bytes)> {
+    tree.iter()
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (entry.key().clone(), entry.value().clone())
+        })
+        .collect()
+}
+
+#[test]
+fn orders_save_the_same_tree() {
+    let post = saved(WriteOrder::PostOrder, 0..100);
+    let level = saved(WriteOrder::LevelOrder, 0..100);
+    assert_eq!(post.verify().unwrap(), level.verify().unwrap());
+    assert_eq!(entries(&post), entries(&level));
+    assert_eq!(entries(&level).len(), 100);
+}
+
+#[test]
+fn level_order_writes_the_root_last_and_leaves_first() {
+    let tree = saved(WriteOrder::LevelOrder, 0..15);
+    let layout = layout(&tree);
+    assert_eq!(layout.len(), 15);
+    assert_eq!(layout.last(), Some(&(14, 0)));
+    assert!(layout.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+}
+
+#[test]
+fn post_order_keeps_subtrees_contiguous() {
+    let tree = saved(WriteOrder::PostOrder, 0..15);
+    let layout = layout(&tree);
+    assert_eq!(layout.last(), Some(&(14, 0)));
+    // The left subtree of the root, with 7 nodes, is written first.
+    assert_eq!(layout[6], (6, 1));
+    assert!(layout[..7].iter().all(|&(_, depth)| depth >= 1));
+}
+
+#[test]
+fn level_order_leaves_stored_children_in_place() {
+    let tree = saved(WriteOrder::LevelOrder, 0..15);
+    let before = tree.root_ptr().unwrap();
+    let tree = tree.insert(vec![200], vec![]).unwrap().save().unwrap();
+    assert!(tree.root_ptr().unwrap().to_u64() > before.to_u64());
+    // Only the path to the new key was rewritten.
+    let layout = layout(&tree);
+    assert_eq!(layout.iter().filter(|&&(ptr, _)| ptr >= 15).count(), 5);
+    assert!(tree.verify().is_ok());
+}